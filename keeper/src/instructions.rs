@@ -0,0 +1,134 @@
+//! Instructions the keeper sends. Params are borsh encoded field by field in
+//! the order of the processor params structs, which are only built with the
+//! program feature.
+
+use std::mem::size_of;
+
+use borsh::BorshSerialize;
+use nix::{client::NixInstruction, state::MarketLoansFixed};
+use solana_sdk::{
+    compute_budget::ComputeBudgetInstruction,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_instruction, system_program,
+};
+
+fn instruction_data<T: BorshSerialize>(instruction: NixInstruction, params: T) -> Vec<u8> {
+    let mut data: Vec<u8> = vec![instruction as u8];
+    data.extend(params.try_to_vec().unwrap());
+    data
+}
+
+/// Priority fee and compute limit, prepended to every keeper transaction.
+pub fn compute_budget_instructions(
+    priority_fee_micro_lamports: u64,
+    compute_unit_limit: u32,
+) -> [Instruction; 2] {
+    [
+        ComputeBudgetInstruction::set_compute_unit_limit(compute_unit_limit),
+        ComputeBudgetInstruction::set_compute_unit_price(priority_fee_micro_lamports),
+    ]
+}
+
+/// CleanExpiredOrdersParams is use_a_tree, is_bid, max_orders.
+pub fn clean_expired_orders_instruction(
+    payer: &Pubkey,
+    market: &Pubkey,
+    market_loans: &Pubkey,
+    base_global: &Pubkey,
+    use_a_tree: bool,
+    is_bid: bool,
+    max_orders: u32,
+) -> Instruction {
+    Instruction {
+        program_id: nix::ID,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(*market_loans, false),
+            AccountMeta::new(*market, false),
+            AccountMeta::new(*base_global, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: instruction_data(
+            NixInstruction::CleanExpiredOrders,
+            (use_a_tree, is_bid, max_orders),
+        ),
+    }
+}
+
+/// Allocates a loans page for the market and links it after the previous
+/// last page. The new account has to sign the create.
+pub fn create_market_loan_account_instructions(
+    admin: &Pubkey,
+    market: &Pubkey,
+    market_loan_account: &Pubkey,
+    previous_market_loans: &Pubkey,
+    rent_lamports: u64,
+) -> [Instruction; 2] {
+    [
+        system_instruction::create_account(
+            admin,
+            market_loan_account,
+            rent_lamports,
+            size_of::<MarketLoansFixed>() as u64,
+            &nix::ID,
+        ),
+        Instruction {
+            program_id: nix::ID,
+            accounts: vec![
+                AccountMeta::new(*admin, true),
+                AccountMeta::new(*market_loan_account, false),
+                AccountMeta::new_readonly(*market, false),
+                AccountMeta::new(*previous_market_loans, false),
+            ],
+            data: vec![NixInstruction::CreateMarketLoanAccount as u8],
+        },
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_clean_expired_orders_instruction() {
+        let payer: Pubkey = Pubkey::new_unique();
+        let market: Pubkey = Pubkey::new_unique();
+        let market_loans: Pubkey = Pubkey::new_unique();
+        let base_global: Pubkey = Pubkey::new_unique();
+        let instruction: Instruction = clean_expired_orders_instruction(
+            &payer,
+            &market,
+            &market_loans,
+            &base_global,
+            true,
+            false,
+            300,
+        );
+
+        // Tag, the two flags, then max_orders little endian.
+        assert_eq!(
+            instruction.data,
+            [
+                vec![NixInstruction::CleanExpiredOrders as u8, 1, 0],
+                300_u32.to_le_bytes().to_vec(),
+            ]
+            .concat()
+        );
+        assert_eq!(
+            instruction
+                .accounts
+                .iter()
+                .map(|account: &AccountMeta| account.pubkey)
+                .collect::<Vec<Pubkey>>(),
+            vec![
+                payer,
+                market_loans,
+                market,
+                base_global,
+                system_program::id()
+            ]
+        );
+        assert!(instruction.accounts[0].is_signer);
+    }
+}
@@ -0,0 +1,278 @@
+//! Turns what scan finds on a market into transactions.
+
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use nix::{client::*, validation::get_global_address};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{
+    account::Account,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::Signer,
+    transaction::Transaction,
+};
+
+use crate::{
+    instructions::{
+        clean_expired_orders_instruction, compute_budget_instructions,
+        create_market_loan_account_instructions,
+    },
+    scan::{find_expired_sides, find_underwater_loans, get_market, get_market_loans},
+};
+
+pub struct KeeperConfig {
+    pub priority_fee_micro_lamports: u64,
+    pub compute_unit_limit: u32,
+    /// Orders removed per CleanExpiredOrders. The program caps it at
+    /// MAX_MATCHED_LOANS.
+    pub max_orders: u32,
+    /// Price per whole token by mint, only used to flag underwater loans.
+    pub prices: HashMap<Pubkey, f64>,
+}
+
+pub struct Keeper {
+    pub rpc_client: RpcClient,
+    pub payer: Keypair,
+    pub config: KeeperConfig,
+}
+
+struct LoansPage {
+    key: Pubkey,
+    data: Vec<u8>,
+}
+
+impl Keeper {
+    /// Runs every check on one state of a market and sends what is needed.
+    /// Errors on one action are logged so the others still go out.
+    pub async fn crank_market(
+        &self,
+        market_key: &Pubkey,
+        market_data: &[u8],
+        slot: u64,
+    ) -> Result<()> {
+        let market: MarketRef =
+            get_market(market_data).ok_or_else(|| anyhow!("{market_key} is not a market"))?;
+        let now_slot: u32 = slot.min(u32::MAX as u64) as u32;
+        // Good till time orders are keyed to the cluster clock, which tracks
+        // wall time closely enough to pick which sides to clean.
+        let now_unix_timestamp: i64 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs() as i64);
+        let loans_pages: Vec<LoansPage> = self.get_loans_pages(market_key).await?;
+
+        // Pages are chained, only the last one takes new loans.
+        let last_page: Option<&LoansPage> = loans_pages.iter().find(|page| {
+            get_market_loans(&page.data)
+                .is_some_and(|market_loans| !market_loans.fixed.has_next_page())
+        });
+        let open_page: Option<&LoansPage> = last_page.filter(|page| {
+            get_market_loans(&page.data).is_some_and(|market_loans| !market_loans.fixed.is_full())
+        });
+
+        match (last_page, open_page) {
+            (Some(last_page), None) => {
+                if let Err(err) = self
+                    .add_loans_page(market_key, &market, &last_page.key)
+                    .await
+                {
+                    warn!("{market_key}: could not add a loans page: {err:#}");
+                }
+            }
+            (None, _) => warn!("{market_key}: no loans page found"),
+            _ => {}
+        }
+
+        if let Some(open_page) = open_page {
+            for expired_side in find_expired_sides(&market, now_slot, now_unix_timestamp) {
+                if let Err(err) = self
+                    .clean_expired_orders(
+                        market_key,
+                        &market,
+                        &open_page.key,
+                        expired_side.use_a_tree,
+                        expired_side.is_bid,
+                    )
+                    .await
+                {
+                    warn!(
+                        "{market_key}: could not clean {} expired orders: {err:#}",
+                        expired_side.num_expired
+                    );
+                }
+            }
+        }
+
+        // There is no liquidation instruction yet, so these are only reported.
+        for page in loans_pages.iter() {
+            let Some(market_loans) = get_market_loans(&page.data) else {
+                continue;
+            };
+            for loan in find_underwater_loans(market.fixed, &market_loans, &self.config.prices) {
+                warn!(
+                    "{market_key}: loan {} on {} is underwater, liability {:.2} collateral {:.2}",
+                    loan.sequence_number, page.key, loan.liability_value, loan.collateral_value
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn clean_expired_orders(
+        &self,
+        market_key: &Pubkey,
+        market: &MarketRef<'_>,
+        market_loans_key: &Pubkey,
+        use_a_tree: bool,
+        is_bid: bool,
+    ) -> Result<()> {
+        let base_mint: &Pubkey = if use_a_tree {
+            market.fixed.get_base_a_mint()
+        } else {
+            market.fixed.get_base_b_mint()
+        };
+        // The loader wants the base global even when no order on the side
+        // is global, so markets whose base mint has none cannot be cleaned.
+        let (base_global_key, _) = get_global_address(base_mint);
+        if self
+            .rpc_client
+            .get_account_with_commitment(&base_global_key, self.rpc_client.commitment())
+            .await?
+            .value
+            .is_none()
+        {
+            return Err(anyhow!("no global for base mint {base_mint}"));
+        }
+
+        let signature: Signature = self
+            .send(
+                &[clean_expired_orders_instruction(
+                    &self.payer.pubkey(),
+                    market_key,
+                    market_loans_key,
+                    &base_global_key,
+                    use_a_tree,
+                    is_bid,
+                    self.config.max_orders,
+                )],
+                &[],
+            )
+            .await?;
+        info!(
+            "{market_key}: cleaned expired orders use_a_tree={} is_bid={} {signature}",
+            use_a_tree, is_bid
+        );
+        Ok(())
+    }
+
+    /// Only the market admin can add loans pages. Other keepers can only
+    /// report that the market is stuck.
+    async fn add_loans_page(
+        &self,
+        market_key: &Pubkey,
+        market: &MarketRef<'_>,
+        previous_market_loans_key: &Pubkey,
+    ) -> Result<()> {
+        if market.fixed.get_admin() != &self.payer.pubkey() {
+            return Err(anyhow!(
+                "loans ledger is full and admin {} has to add a page",
+                market.fixed.get_admin()
+            ));
+        }
+        let market_loan_account: Keypair = Keypair::new();
+        let rent_lamports: u64 = self
+            .rpc_client
+            .get_minimum_balance_for_rent_exemption(std::mem::size_of::<MarketLoansFixed>())
+            .await?;
+        let signature: Signature = self
+            .send(
+                &create_market_loan_account_instructions(
+                    &self.payer.pubkey(),
+                    market_key,
+                    &market_loan_account.pubkey(),
+                    previous_market_loans_key,
+                    rent_lamports,
+                ),
+                &[&market_loan_account],
+            )
+            .await?;
+        info!(
+            "{market_key}: added loans page {} {signature}",
+            market_loan_account.pubkey()
+        );
+        Ok(())
+    }
+
+    /// Loans pages are not linked from the market, so they are found by the
+    /// market key stored right after their discriminant.
+    async fn get_loans_pages(&self, market_key: &Pubkey) -> Result<Vec<LoansPage>> {
+        let discriminant: u64 = get_discriminant::<MarketLoansFixed>()?;
+        let accounts: Vec<(Pubkey, Account)> = self
+            .rpc_client
+            .get_program_accounts_with_config(
+                &nix::ID,
+                RpcProgramAccountsConfig {
+                    filters: Some(vec![
+                        RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+                            0,
+                            discriminant.to_le_bytes().to_vec(),
+                        )),
+                        RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+                            8,
+                            market_key.to_bytes().to_vec(),
+                        )),
+                    ]),
+                    account_config: RpcAccountInfoConfig {
+                        encoding: Some(UiAccountEncoding::Base64),
+                        ..RpcAccountInfoConfig::default()
+                    },
+                    ..RpcProgramAccountsConfig::default()
+                },
+            )
+            .await?;
+        Ok(accounts
+            .into_iter()
+            .filter(|(_, account)| get_market_loans(&account.data).is_some())
+            .map(|(key, account)| LoansPage {
+                key,
+                data: account.data,
+            })
+            .collect())
+    }
+
+    async fn send(
+        &self,
+        instructions: &[Instruction],
+        extra_signers: &[&Keypair],
+    ) -> Result<Signature> {
+        let mut all_instructions: Vec<Instruction> = compute_budget_instructions(
+            self.config.priority_fee_micro_lamports,
+            self.config.compute_unit_limit,
+        )
+        .to_vec();
+        all_instructions.extend_from_slice(instructions);
+
+        let mut signers: Vec<&Keypair> = vec![&self.payer];
+        signers.extend_from_slice(extra_signers);
+        let transaction: Transaction = Transaction::new_signed_with_payer(
+            &all_instructions,
+            Some(&self.payer.pubkey()),
+            &signers,
+            self.rpc_client.get_latest_blockhash().await?,
+        );
+        Ok(self
+            .rpc_client
+            .send_and_confirm_transaction(&transaction)
+            .await?)
+    }
+}
@@ -0,0 +1,131 @@
+//! Keeper for nix markets. Subscribes to the given markets and, on every
+//! change, cleans expired orders, adds a loans page when the ledger is full
+//! and the keeper is the market admin, and reports underwater loans.
+
+mod instructions;
+mod keeper;
+mod scan;
+
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use futures::{stream::select_all, StreamExt};
+use log::{info, warn};
+use nix::state::MAX_MATCHED_LOANS;
+use solana_account_decoder::{UiAccount, UiAccountEncoding};
+use solana_client::{
+    nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient},
+    rpc_config::RpcAccountInfoConfig,
+    rpc_response::Response,
+};
+use solana_sdk::{
+    account::Account, commitment_config::CommitmentConfig, pubkey::Pubkey,
+    signature::read_keypair_file,
+};
+
+use crate::keeper::{Keeper, KeeperConfig};
+
+#[derive(Parser)]
+#[command(name = "nix-keeper")]
+struct Args {
+    #[arg(long, default_value = "http://127.0.0.1:8899")]
+    rpc_url: String,
+    #[arg(long, default_value = "ws://127.0.0.1:8900")]
+    ws_url: String,
+    /// Pays for the cranks. Has to be the market admin to add loans pages.
+    #[arg(long)]
+    keypair: PathBuf,
+    /// Market to watch, can be repeated.
+    #[arg(long = "market", required = true)]
+    markets: Vec<Pubkey>,
+    #[arg(long, default_value_t = 1_000)]
+    priority_fee_micro_lamports: u64,
+    #[arg(long, default_value_t = 400_000)]
+    compute_unit_limit: u32,
+    #[arg(long, default_value_t = MAX_MATCHED_LOANS as u32)]
+    max_orders: u32,
+    /// Price of a whole token as MINT=PRICE, can be repeated. Loans are only
+    /// checked when both mints of the market have a price.
+    #[arg(long = "price", value_parser = parse_price)]
+    prices: Vec<(Pubkey, f64)>,
+}
+
+fn parse_price(arg: &str) -> Result<(Pubkey, f64)> {
+    let (mint, price) = arg
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected MINT=PRICE, got {arg}"))?;
+    Ok((mint.parse()?, price.parse()?))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let args: Args = Args::parse();
+
+    let keeper: Keeper = Keeper {
+        rpc_client: RpcClient::new_with_commitment(args.rpc_url, CommitmentConfig::confirmed()),
+        payer: read_keypair_file(&args.keypair)
+            .map_err(|err| anyhow!("could not read {}: {err}", args.keypair.display()))?,
+        config: KeeperConfig {
+            priority_fee_micro_lamports: args.priority_fee_micro_lamports,
+            compute_unit_limit: args.compute_unit_limit,
+            max_orders: args.max_orders,
+            prices: args.prices.into_iter().collect::<HashMap<Pubkey, f64>>(),
+        },
+    };
+
+    // Subscriptions only fire on changes, so crank the current state first.
+    for market_key in args.markets.iter() {
+        let response: Response<Option<Account>> = keeper
+            .rpc_client
+            .get_account_with_commitment(market_key, keeper.rpc_client.commitment())
+            .await?;
+        match response.value {
+            Some(account) => {
+                if let Err(err) = keeper
+                    .crank_market(market_key, &account.data, response.context.slot)
+                    .await
+                {
+                    warn!("{market_key}: {err:#}");
+                }
+            }
+            None => warn!("{market_key}: account not found"),
+        }
+    }
+
+    let pubsub_client: PubsubClient = PubsubClient::new(&args.ws_url).await?;
+    let mut subscriptions = Vec::new();
+    for market_key in args.markets.iter() {
+        let (stream, _unsubscribe) = pubsub_client
+            .account_subscribe(
+                market_key,
+                Some(RpcAccountInfoConfig {
+                    encoding: Some(UiAccountEncoding::Base64),
+                    commitment: Some(keeper.rpc_client.commitment()),
+                    ..RpcAccountInfoConfig::default()
+                }),
+            )
+            .await?;
+        let market_key: Pubkey = *market_key;
+        subscriptions.push(stream.map(move |response: Response<UiAccount>| (market_key, response)));
+    }
+    info!("Watching {} markets", subscriptions.len());
+
+    // Updates are handled one at a time so a crank never races the one
+    // before it.
+    let mut updates = select_all(subscriptions);
+    while let Some((market_key, response)) = updates.next().await {
+        let Some(account) = response.value.decode::<Account>() else {
+            warn!("{market_key}: could not decode account update");
+            continue;
+        };
+        if let Err(err) = keeper
+            .crank_market(&market_key, &account.data, response.context.slot)
+            .await
+        {
+            warn!("{market_key}: {err:#}");
+        }
+    }
+    Err(anyhow!("Market subscriptions closed"))
+}
@@ -0,0 +1,195 @@
+//! Finds work for the keeper in raw account data. Nothing here talks to the
+//! cluster, so the checks run the same on a subscription update or on a
+//! snapshot of the accounts.
+
+use std::{collections::HashMap, mem::size_of};
+
+use fixed::types::I80F48;
+use nix::{client::*, validation::NixAccount};
+use solana_sdk::pubkey::Pubkey;
+
+/// One book side with orders that CleanExpiredOrders would remove.
+pub struct ExpiredSide {
+    pub use_a_tree: bool,
+    pub is_bid: bool,
+    pub num_expired: u32,
+}
+
+/// Active loan whose liability is worth more than its collateral.
+pub struct UnderwaterLoan {
+    pub sequence_number: u64,
+    pub collateral_value: f64,
+    pub liability_value: f64,
+}
+
+/// Market view over account data, None when the account is not a market on
+/// the current layout.
+pub fn get_market(data: &[u8]) -> Option<MarketRef> {
+    if data.len() < size_of::<MarketFixed>() {
+        return None;
+    }
+    let (fixed_data, dynamic) = data.split_at(size_of::<MarketFixed>());
+    let fixed: &MarketFixed = get_helper::<MarketFixed>(fixed_data, 0_u32);
+    fixed.verify_discriminant().ok()?;
+    fixed.verify_version().ok()?;
+    Some(MarketRef { fixed, dynamic })
+}
+
+/// Loans page view over account data, None when the account is not a loans
+/// page.
+pub fn get_market_loans(data: &[u8]) -> Option<MarketLoansRef> {
+    if data.len() < size_of::<MarketLoansFixed>() {
+        return None;
+    }
+    let (fixed_data, dynamic) = data.split_at(size_of::<MarketLoansFixed>());
+    let fixed: &MarketLoansFixed = get_helper::<MarketLoansFixed>(fixed_data, 0_u32);
+    fixed.verify_discriminant().ok()?;
+    Some(MarketLoansRef { fixed, dynamic })
+}
+
+/// Book sides of both trees that have expired orders.
+pub fn find_expired_sides(
+    market: &MarketRef,
+    now_slot: u32,
+    now_unix_timestamp: i64,
+) -> Vec<ExpiredSide> {
+    let mut expired_sides: Vec<ExpiredSide> = Vec::new();
+    for use_a_tree in [true, false] {
+        for is_bid in [true, false] {
+            let num_expired: u32 =
+                market.count_expired_orders(use_a_tree, is_bid, now_slot, now_unix_timestamp);
+            if num_expired > 0 {
+                expired_sides.push(ExpiredSide {
+                    use_a_tree,
+                    is_bid,
+                    num_expired,
+                });
+            }
+        }
+    }
+    expired_sides
+}
+
+/// Active loans on a page whose liability is worth more than their
+/// collateral at the given prices, in the same unit as the prices. Shares are
+/// valued at one token atom each, which ignores the interest marginfi has
+/// accrued on them. Good enough to flag a loan, not to liquidate it. Loans
+/// with an unpriced mint are skipped.
+pub fn find_underwater_loans(
+    market: &MarketFixed,
+    market_loans: &MarketLoansRef,
+    prices: &HashMap<Pubkey, f64>,
+) -> Vec<UnderwaterLoan> {
+    let (Some(base_a_price), Some(base_b_price)) = (
+        prices.get(market.get_base_a_mint()),
+        prices.get(market.get_base_b_mint()),
+    ) else {
+        return Vec::new();
+    };
+    let base_a_atom_value: f64 = base_a_price / 10_f64.powi(market.get_base_a_decimals() as i32);
+    let base_b_atom_value: f64 = base_b_price / 10_f64.powi(market.get_base_b_decimals() as i32);
+
+    let loans_tree: ActiveLoanTreeReadOnly = ActiveLoanTreeReadOnly::new(
+        market_loans.dynamic,
+        market_loans.fixed.active_loans_root_index,
+        NIL,
+    );
+    loans_tree
+        .iter::<ActiveLoan>()
+        .map(|(_, loan)| loan)
+        .filter(|loan| loan.status == LoanStatus::Active)
+        .filter_map(|loan| {
+            let (liability_atom_value, collateral_atom_value) = if loan.is_liability_base_a.0 == 1 {
+                (base_a_atom_value, base_b_atom_value)
+            } else {
+                (base_b_atom_value, base_a_atom_value)
+            };
+            let collateral_value: f64 =
+                I80F48::from(loan.collateral_shares).to_num::<f64>() * collateral_atom_value;
+            let liability_value: f64 =
+                I80F48::from(loan.liability_shares).to_num::<f64>() * liability_atom_value;
+            (liability_value > collateral_value).then_some(UnderwaterLoan {
+                sequence_number: loan.sequence_number,
+                collateral_value,
+                liability_value,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use hypertree::get_mut_helper;
+
+    use super::*;
+
+    /// Loans page holding `loans` with sequence numbers from 1, with a
+    /// block for each.
+    fn new_market_loans(loans: &[ActiveLoan]) -> MarketLoansValue {
+        let mut market_loans: MarketLoansValue = MarketLoansValue {
+            fixed: MarketLoansFixed::new_empty(Pubkey::new_unique()),
+            dynamic: vec![0; MARKET_LOAN_BLOCK_SIZE * loans.len()],
+        };
+        market_loans
+            .expand_loan_account(loans.len() as u32)
+            .unwrap();
+        for (sequence_number, loan) in (1..).zip(loans) {
+            let mut loan: ActiveLoan = *loan;
+            loan.set_sequence_number(sequence_number);
+            market_loans.add_loan(loan).unwrap();
+        }
+        market_loans
+    }
+
+    fn new_loan(collateral_shares: u64, liability_shares: u64) -> ActiveLoan {
+        ActiveLoan::new_empty(
+            true,
+            0,
+            1,
+            false,
+            WrappedI80F48::from(I80F48::from_num(collateral_shares)),
+            WrappedI80F48::from(I80F48::from_num(liability_shares)),
+            500,
+            0,
+            0,
+        )
+    }
+
+    #[test]
+    fn test_get_market_rejects_other_accounts() {
+        assert!(get_market(&[]).is_none());
+        assert!(get_market_loans(&[]).is_none());
+        // Zeroed data has the size of a market but not its discriminant.
+        assert!(get_market(&vec![0; size_of::<MarketFixed>()]).is_none());
+
+        let mut data: Vec<u8> = vec![0; size_of::<MarketLoansFixed>()];
+        *get_mut_helper::<MarketLoansFixed>(&mut data, 0_u32) =
+            MarketLoansFixed::new_empty(Pubkey::new_unique());
+        let market_loans: MarketLoansRef = get_market_loans(&data).unwrap();
+        assert_eq!(market_loans.fixed.num_active_loans, 0);
+    }
+
+    #[test]
+    fn test_find_underwater_loans() {
+        // Both mints of a zeroed market are the default key, with no
+        // decimals, so one share is worth the one price.
+        let market: MarketFixed = MarketFixed::default();
+        let market_loans: MarketLoansValue =
+            new_market_loans(&[new_loan(100, 50), new_loan(100, 150)]);
+        let market_loans_ref: MarketLoansRef = MarketLoansRef {
+            fixed: &market_loans.fixed,
+            dynamic: &market_loans.dynamic,
+        };
+
+        let prices: HashMap<Pubkey, f64> = HashMap::from([(Pubkey::default(), 2.0)]);
+        let underwater_loans: Vec<UnderwaterLoan> =
+            find_underwater_loans(&market, &market_loans_ref, &prices);
+        assert_eq!(underwater_loans.len(), 1);
+        assert_eq!(underwater_loans[0].sequence_number, 2);
+        assert_eq!(underwater_loans[0].collateral_value, 200.0);
+        assert_eq!(underwater_loans[0].liability_value, 300.0);
+
+        // Without prices nothing is flagged.
+        assert!(find_underwater_loans(&market, &market_loans_ref, &HashMap::new()).is_empty());
+    }
+}
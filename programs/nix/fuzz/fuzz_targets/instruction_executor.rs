@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nix_fuzz::executor::{FuzzInstruction, NixFuzzer};
+
+fuzz_target!(|fuzz_instructions: Vec<FuzzInstruction>| {
+    let mut fuzzer: NixFuzzer = NixFuzzer::new();
+    for fuzz_instruction in fuzz_instructions {
+        fuzzer.run(fuzz_instruction);
+    }
+});
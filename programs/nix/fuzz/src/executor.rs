@@ -0,0 +1,604 @@
+use std::mem::size_of;
+
+use arbitrary::Arbitrary;
+use borsh::BorshSerialize;
+use bytemuck::Zeroable;
+use fixed::types::I80F48;
+use marginfi::{
+    state::{
+        marginfi_group::{Bank, MarginfiGroup},
+        price::OracleSetup,
+    },
+    ID as MARGINFI_PROGRAM_ID,
+};
+use nix::{
+    marginfi_utils::{
+        compute_anchor_account_discriminator, MARGINFI_BANK_DISCRIMINATOR,
+        MARGINFI_GROUP_DISCRIMINATOR,
+    },
+    program::{
+        cancel_order::CancelOrderParams, create_market::CreateMarketParams, deposit::DepositParams,
+        get_dynamic_account, place_order::PlaceOrderParams, NixInstruction,
+    },
+    state::{
+        verify::{get_total_claimed_asset_shares, verify_market},
+        ExpiryPolicy, MarketFixed, MarketLoansFixed, MarketRef, OrderType,
+    },
+    validation::{
+        get_global_address, get_global_vault_address, get_marginfi_liquidity_vault_authority,
+        get_market_fee_receiver_address, get_market_signer_address,
+        get_nix_marginfi_account_address, get_vault_address,
+    },
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_option::COption,
+    program_pack::Pack,
+    pubkey,
+    pubkey::Pubkey,
+    system_program,
+};
+use spl_token::state::{Account, AccountState, Mint};
+
+use crate::{
+    store::{AccountStore, StoredAccount},
+    stubs::{get_marginfi_balance, install_syscall_stubs, FUZZ_SLOT, FUZZ_UNIX_TIMESTAMP},
+};
+
+pub const NUM_TRADERS: usize = 3;
+const TRADER_LAMPORTS: u64 = 1_000_000_000_000;
+const TRADER_TOKEN_BALANCE: u64 = 1_000_000_000_000;
+const LIQUIDITY_VAULT_BALANCE: u64 = 1_000_000_000_000;
+const PYTH_RECEIVER_PROGRAM_ID: Pubkey = pubkey!("rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ");
+
+/// One step of a fuzz run. Fields are narrowed to ranges where instructions
+/// have a chance of succeeding, the program still validates all of them.
+#[derive(Arbitrary, Debug, Clone, Copy)]
+pub enum FuzzInstruction {
+    CreateMarket {
+        protocol_fee_rate_bps: u16,
+        referral_bps: u16,
+        maker_rebate_bps: u16,
+        max_open_orders_per_seat: u8,
+    },
+    ClaimSeat {
+        trader: u8,
+    },
+    Deposit {
+        trader: u8,
+        is_base_a: bool,
+        amount: u32,
+    },
+    PlaceOrder {
+        trader: u8,
+        num_base_atoms: u32,
+        rate_bps: u16,
+        reverse_spread_bps: u16,
+        is_bid: bool,
+        use_a_tree: bool,
+        order_type: u8,
+        match_limit: Option<u8>,
+    },
+    CancelOrder {
+        trader: u8,
+        order_sequence_number: u8,
+        use_a_tree: bool,
+    },
+}
+
+/// Accounts of one of the two bases of the market.
+struct BaseAccounts {
+    mint: Pubkey,
+    marginfi_group: Pubkey,
+    marginfi_bank: Pubkey,
+    marginfi_liquidity_vault: Pubkey,
+    marginfi_liquidity_vault_authority: Pubkey,
+    oracle: Pubkey,
+    global: Pubkey,
+    global_vault: Pubkey,
+    vault: Pubkey,
+    fee_receiver: Pubkey,
+    marginfi_account: Pubkey,
+    trader_token_accounts: [Pubkey; NUM_TRADERS],
+}
+
+pub struct NixFuzzer {
+    pub store: AccountStore,
+    admin: Pubkey,
+    traders: [Pubkey; NUM_TRADERS],
+    market: Pubkey,
+    market_loans: Pubkey,
+    market_signer: Pubkey,
+    base_a: BaseAccounts,
+    base_b: BaseAccounts,
+    is_market_created: bool,
+}
+
+/// Keys are fixed so that a crash reproduces from its input alone.
+fn fuzz_key(seed: u8) -> Pubkey {
+    Pubkey::new_from_array([seed; 32])
+}
+
+fn pack<T: Pack>(state: T) -> Vec<u8> {
+    let mut data: Vec<u8> = vec![0; T::LEN];
+    T::pack(state, &mut data).unwrap();
+    data
+}
+
+fn token_account_data(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Vec<u8> {
+    pack(Account {
+        mint: *mint,
+        owner: *owner,
+        amount,
+        state: AccountState::Initialized,
+        ..Account::default()
+    })
+}
+
+/// PriceUpdateV2 of the pyth receiver, borsh encoded by hand to not depend on
+/// the anchor version of the sdk.
+fn price_update_data(feed_id: &[u8; 32], price: i64, exponent: i32) -> Vec<u8> {
+    let mut data: Vec<u8> = compute_anchor_account_discriminator("PriceUpdateV2").to_vec();
+    data.extend_from_slice(Pubkey::default().as_ref()); // write_authority
+    data.push(1); // VerificationLevel::Full
+    data.extend_from_slice(feed_id);
+    data.extend_from_slice(&price.to_le_bytes());
+    data.extend_from_slice(&0_u64.to_le_bytes()); // conf
+    data.extend_from_slice(&exponent.to_le_bytes());
+    data.extend_from_slice(&FUZZ_UNIX_TIMESTAMP.to_le_bytes()); // publish_time
+    data.extend_from_slice(&FUZZ_UNIX_TIMESTAMP.to_le_bytes()); // prev_publish_time
+    data.extend_from_slice(&price.to_le_bytes()); // ema_price
+    data.extend_from_slice(&0_u64.to_le_bytes()); // ema_conf
+    data.extend_from_slice(&FUZZ_SLOT.to_le_bytes()); // posted_slot
+    data
+}
+
+fn nix_instruction(
+    instruction: NixInstruction,
+    accounts: Vec<AccountMeta>,
+    params_data: Vec<u8>,
+) -> Instruction {
+    let mut data: Vec<u8> = vec![instruction as u8];
+    data.extend(params_data);
+    Instruction {
+        program_id: nix::ID,
+        accounts,
+        data,
+    }
+}
+
+impl BaseAccounts {
+    fn new(
+        store: &mut AccountStore,
+        seed: u8,
+        decimals: u8,
+        price: i64,
+        market: &Pubkey,
+        traders: &[Pubkey; NUM_TRADERS],
+    ) -> Self {
+        let mint: Pubkey = fuzz_key(seed);
+        let marginfi_group: Pubkey = fuzz_key(seed + 1);
+        let marginfi_bank: Pubkey = fuzz_key(seed + 2);
+        let marginfi_liquidity_vault: Pubkey = fuzz_key(seed + 3);
+        let oracle: Pubkey = fuzz_key(seed + 4);
+        let (marginfi_liquidity_vault_authority, _) =
+            get_marginfi_liquidity_vault_authority(&marginfi_bank);
+        let trader_token_accounts: [Pubkey; NUM_TRADERS] =
+            std::array::from_fn(|trader| fuzz_key(seed + 5 + trader as u8));
+
+        store.set_rent_exempt(
+            mint,
+            spl_token::id(),
+            pack(Mint {
+                mint_authority: COption::None,
+                supply: LIQUIDITY_VAULT_BALANCE + TRADER_TOKEN_BALANCE * NUM_TRADERS as u64,
+                decimals,
+                is_initialized: true,
+                freeze_authority: COption::None,
+            }),
+        );
+
+        let mut marginfi_group_data: Vec<u8> = MARGINFI_GROUP_DISCRIMINATOR.to_vec();
+        marginfi_group_data.extend_from_slice(bytemuck::bytes_of(&MarginfiGroup::zeroed()));
+        store.set_rent_exempt(marginfi_group, MARGINFI_PROGRAM_ID, marginfi_group_data);
+
+        // Share values of one keep shares and tokens the same, which is what
+        // the marginfi stub assumes.
+        let feed_id: [u8; 32] = [seed + 4; 32];
+        let mut bank: Bank = Bank::zeroed();
+        bank.mint = mint;
+        bank.mint_decimals = decimals;
+        bank.group = marginfi_group;
+        bank.asset_share_value = I80F48::ONE.into();
+        bank.liability_share_value = I80F48::ONE.into();
+        bank.liquidity_vault = marginfi_liquidity_vault;
+        bank.config.asset_weight_init = I80F48::from_num(0.8).into();
+        bank.config.liability_weight_init = I80F48::from_num(1.25).into();
+        bank.config.oracle_setup = OracleSetup::PythPushOracle;
+        bank.config.oracle_keys[0] = Pubkey::new_from_array(feed_id);
+        bank.config.oracle_max_age = u16::MAX;
+        let mut marginfi_bank_data: Vec<u8> = MARGINFI_BANK_DISCRIMINATOR.to_vec();
+        marginfi_bank_data.extend_from_slice(bytemuck::bytes_of(&bank));
+        store.set_rent_exempt(marginfi_bank, MARGINFI_PROGRAM_ID, marginfi_bank_data);
+
+        store.set_rent_exempt(
+            marginfi_liquidity_vault,
+            spl_token::id(),
+            token_account_data(
+                &mint,
+                &marginfi_liquidity_vault_authority,
+                LIQUIDITY_VAULT_BALANCE,
+            ),
+        );
+        store.set_rent_exempt(
+            oracle,
+            PYTH_RECEIVER_PROGRAM_ID,
+            price_update_data(&feed_id, price, -8),
+        );
+        for (trader, trader_token_account) in traders.iter().zip(trader_token_accounts.iter()) {
+            store.set_rent_exempt(
+                *trader_token_account,
+                spl_token::id(),
+                token_account_data(&mint, trader, TRADER_TOKEN_BALANCE),
+            );
+        }
+
+        BaseAccounts {
+            mint,
+            marginfi_group,
+            marginfi_bank,
+            marginfi_liquidity_vault,
+            marginfi_liquidity_vault_authority,
+            oracle,
+            global: get_global_address(&mint).0,
+            global_vault: get_global_vault_address(&mint).0,
+            vault: get_vault_address(market, &mint).0,
+            fee_receiver: get_market_fee_receiver_address(market, &mint).0,
+            marginfi_account: get_nix_marginfi_account_address(market, &mint).0,
+            trader_token_accounts,
+        }
+    }
+
+    fn marginfi_cpi_account_metas(&self) -> Vec<AccountMeta> {
+        vec![
+            AccountMeta::new_readonly(self.marginfi_group, false),
+            AccountMeta::new(self.marginfi_bank, false),
+            AccountMeta::new(self.marginfi_account, false),
+            AccountMeta::new(self.marginfi_liquidity_vault, false),
+            AccountMeta::new_readonly(self.marginfi_liquidity_vault_authority, false),
+        ]
+    }
+}
+
+impl NixFuzzer {
+    /// Sets up two mints with their marginfi banks, oracles and globals, and
+    /// funded traders. The market itself is left to a CreateMarket step.
+    pub fn new() -> Self {
+        install_syscall_stubs();
+
+        let mut store: AccountStore = AccountStore::default();
+        for program_id in [
+            system_program::id(),
+            spl_token::id(),
+            MARGINFI_PROGRAM_ID,
+            nix::ID,
+        ] {
+            store.set(
+                program_id,
+                StoredAccount {
+                    lamports: 1,
+                    executable: true,
+                    ..StoredAccount::default()
+                },
+            );
+        }
+
+        let admin: Pubkey = fuzz_key(1);
+        let traders: [Pubkey; NUM_TRADERS] =
+            std::array::from_fn(|trader| fuzz_key(2 + trader as u8));
+        for payer in std::iter::once(&admin).chain(traders.iter()) {
+            store.set(
+                *payer,
+                StoredAccount {
+                    lamports: TRADER_LAMPORTS,
+                    ..StoredAccount::default()
+                },
+            );
+        }
+
+        // Allocated by the client ahead of CreateMarket.
+        let market: Pubkey = fuzz_key(10);
+        let market_loans: Pubkey = fuzz_key(11);
+        store.set_rent_exempt(market, nix::ID, vec![0; size_of::<MarketFixed>()]);
+        store.set_rent_exempt(
+            market_loans,
+            nix::ID,
+            vec![0; size_of::<MarketLoansFixed>()],
+        );
+
+        let base_a: BaseAccounts =
+            BaseAccounts::new(&mut store, 20, 9, 15_000_000_000, &market, &traders);
+        let base_b: BaseAccounts =
+            BaseAccounts::new(&mut store, 40, 6, 100_000_000, &market, &traders);
+
+        let mut fuzzer: NixFuzzer = NixFuzzer {
+            store,
+            admin,
+            traders,
+            market,
+            market_loans,
+            market_signer: get_market_signer_address(&market).0,
+            base_a,
+            base_b,
+            is_market_created: false,
+        };
+        for base in [&fuzzer.base_a, &fuzzer.base_b] {
+            let global_create: Instruction = nix_instruction(
+                NixInstruction::GlobalCreate,
+                vec![
+                    AccountMeta::new(fuzzer.admin, true),
+                    AccountMeta::new(base.global, false),
+                    AccountMeta::new_readonly(system_program::id(), false),
+                    AccountMeta::new_readonly(base.mint, false),
+                    AccountMeta::new(base.global_vault, false),
+                    AccountMeta::new_readonly(spl_token::id(), false),
+                ],
+                Vec::new(),
+            );
+            fuzzer.store.execute(&global_create).unwrap();
+        }
+        fuzzer
+    }
+
+    /// Runs one step and checks the market afterwards. Failing instructions
+    /// are expected and dropped, only broken invariants panic.
+    pub fn run(&mut self, fuzz_instruction: FuzzInstruction) {
+        let instruction: Instruction = match fuzz_instruction {
+            FuzzInstruction::CreateMarket {
+                protocol_fee_rate_bps,
+                referral_bps,
+                maker_rebate_bps,
+                max_open_orders_per_seat,
+            } => self.create_market(CreateMarketParams {
+                protocol_fee_rate_bps: (protocol_fee_rate_bps % 1_000) as u64,
+                marginfi_market_buffer_bps: 0,
+                referral_bps: (referral_bps % 5_001) as u64,
+                maker_rebate_bps: (maker_rebate_bps % 5_001) as u64,
+                min_reverse_spread_bps: 0,
+                max_reverse_spread_bps: 1_000,
+                max_open_orders_per_seat: max_open_orders_per_seat as u16,
+                allowlist_authority: None,
+                fee_on_interest: false,
+                max_borrow_utilization_bps: 0,
+            }),
+            _ if !self.is_market_created => return,
+            FuzzInstruction::ClaimSeat { trader } => self.claim_seat(trader),
+            FuzzInstruction::Deposit {
+                trader,
+                is_base_a,
+                amount,
+            } => self.deposit(trader, is_base_a, amount as u64),
+            FuzzInstruction::PlaceOrder {
+                trader,
+                num_base_atoms,
+                rate_bps,
+                reverse_spread_bps,
+                is_bid,
+                use_a_tree,
+                order_type,
+                match_limit,
+            } => self.place_order(
+                trader,
+                PlaceOrderParams {
+                    trader_index_hint: None,
+                    num_base_atoms: num_base_atoms as u64,
+                    rate_bps,
+                    reverse_spread_bps,
+                    is_bid,
+                    use_a_tree,
+                    last_valid_slot: 0,
+                    order_type: OrderType::try_from(order_type % 6).unwrap(),
+                    expiry_policy: ExpiryPolicy::default(),
+                    referrer: None,
+                    match_limit: match_limit.map(u32::from),
+                    auto_claim_seat: false,
+                    size_in_quote: false,
+                    defer_remainder: false,
+                    max_reverse_cycles: 0,
+                    max_expired_orders_to_sweep: 0,
+                    max_new_loans: None,
+                    reverse_bid_spread_bps: None,
+                    last_valid_unix_timestamp: 0,
+                },
+            ),
+            FuzzInstruction::CancelOrder {
+                trader,
+                order_sequence_number,
+                use_a_tree,
+            } => self.cancel_order(
+                trader,
+                CancelOrderParams {
+                    trader_index_hint: None,
+                    order_sequence_number: order_sequence_number as u64,
+                    order_index_hint: None,
+                    use_a_tree,
+                    max_expired_orders_to_sweep: 0,
+                },
+            ),
+        };
+
+        let result = self.store.execute(&instruction);
+        if result.is_ok() && matches!(fuzz_instruction, FuzzInstruction::CreateMarket { .. }) {
+            self.is_market_created = true;
+        }
+        if self.is_market_created {
+            self.verify();
+        }
+    }
+
+    fn trader(&self, trader: u8) -> (usize, Pubkey) {
+        let trader_index: usize = trader as usize % NUM_TRADERS;
+        (trader_index, self.traders[trader_index])
+    }
+
+    fn create_market(&self, params: CreateMarketParams) -> Instruction {
+        let mut accounts: Vec<AccountMeta> = vec![
+            AccountMeta::new(self.admin, true),
+            AccountMeta::new(self.market, false),
+            AccountMeta::new(self.market_loans, false),
+            AccountMeta::new_readonly(self.market_signer, false),
+            AccountMeta::new_readonly(self.base_a.mint, false),
+            AccountMeta::new_readonly(self.base_b.mint, false),
+            AccountMeta::new(self.base_a.fee_receiver, false),
+            AccountMeta::new(self.base_b.fee_receiver, false),
+            AccountMeta::new(self.base_a.vault, false),
+            AccountMeta::new(self.base_b.vault, false),
+        ];
+        for base in [&self.base_a, &self.base_b] {
+            accounts.extend([
+                AccountMeta::new_readonly(base.marginfi_group, false),
+                AccountMeta::new_readonly(base.marginfi_bank, false),
+                AccountMeta::new(base.marginfi_account, false),
+            ]);
+        }
+        accounts.extend([
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(spl_token_2022::id(), false),
+        ]);
+        nix_instruction(
+            NixInstruction::CreateMarket,
+            accounts,
+            params.try_to_vec().unwrap(),
+        )
+    }
+
+    fn claim_seat(&self, trader: u8) -> Instruction {
+        let (_, trader) = self.trader(trader);
+        nix_instruction(
+            NixInstruction::ClaimSeat,
+            vec![
+                AccountMeta::new(trader, true),
+                AccountMeta::new(self.market, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+            Vec::new(),
+        )
+    }
+
+    fn deposit(&self, trader: u8, is_base_a: bool, amount: u64) -> Instruction {
+        let (trader_index, trader) = self.trader(trader);
+        let base: &BaseAccounts = if is_base_a {
+            &self.base_a
+        } else {
+            &self.base_b
+        };
+        nix_instruction(
+            NixInstruction::Deposit,
+            vec![
+                AccountMeta::new(trader, true),
+                AccountMeta::new(self.market, false),
+                AccountMeta::new_readonly(self.market_signer, false),
+                AccountMeta::new(base.trader_token_accounts[trader_index], false),
+                AccountMeta::new(base.vault, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(base.mint, false),
+                AccountMeta::new_readonly(base.marginfi_group, false),
+                AccountMeta::new(base.marginfi_bank, false),
+                AccountMeta::new(base.marginfi_account, false),
+                AccountMeta::new(base.marginfi_liquidity_vault, false),
+            ],
+            DepositParams::new(amount, None, false).try_to_vec().unwrap(),
+        )
+    }
+
+    fn place_order(&self, trader: u8, params: PlaceOrderParams) -> Instruction {
+        let (_, trader) = self.trader(trader);
+        let (base, quote) = if params.use_a_tree {
+            (&self.base_a, &self.base_b)
+        } else {
+            (&self.base_b, &self.base_a)
+        };
+        let mut accounts: Vec<AccountMeta> = vec![
+            AccountMeta::new(trader, true),
+            AccountMeta::new(self.market, false),
+            AccountMeta::new(self.market_loans, false),
+            AccountMeta::new_readonly(self.market_signer, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(base.mint, false),
+            AccountMeta::new_readonly(quote.mint, false),
+        ];
+        // Both global slots left empty. The loader skips slots that do not
+        // hold a global account.
+        accounts.extend(vec![
+            AccountMeta::new_readonly(system_program::id(), false);
+            8
+        ]);
+        accounts.extend(base.marginfi_cpi_account_metas());
+        accounts.extend(quote.marginfi_cpi_account_metas());
+        accounts.extend([
+            AccountMeta::new_readonly(base.oracle, false),
+            AccountMeta::new_readonly(quote.oracle, false),
+        ]);
+        nix_instruction(
+            NixInstruction::PlaceOrder,
+            accounts,
+            params.try_to_versioned_vec().unwrap(),
+        )
+    }
+
+    fn cancel_order(&self, trader: u8, params: CancelOrderParams) -> Instruction {
+        let (_, trader) = self.trader(trader);
+        let base_global: Pubkey = if params.use_a_tree {
+            self.base_a.global
+        } else {
+            self.base_b.global
+        };
+        nix_instruction(
+            NixInstruction::CancelOrder,
+            vec![
+                AccountMeta::new(trader, true),
+                AccountMeta::new(self.market_loans, false),
+                AccountMeta::new(self.market, false),
+                AccountMeta::new(base_global, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+            params.try_to_vec().unwrap(),
+        )
+    }
+
+    /// Runs the state::verify checks on the market, and checks that the
+    /// market never owes traders more shares than it holds on marginfi.
+    pub fn verify(&self) {
+        let market_data: &[u8] = &self.store.get(&self.market).unwrap().data;
+        let market: MarketRef = get_dynamic_account(market_data);
+        verify_market(&market);
+
+        let (base_a_claimed_shares, base_b_claimed_shares) =
+            get_total_claimed_asset_shares(&market);
+        for (claimed_shares, base) in [
+            (base_a_claimed_shares, &self.base_a),
+            (base_b_claimed_shares, &self.base_b),
+        ] {
+            let (held_shares, _) = get_marginfi_balance(
+                &self.store.get(&base.marginfi_account).unwrap().data,
+                &base.marginfi_bank,
+            );
+            // Conversions round in favor of the market, one share of slack
+            // covers the last rounding.
+            assert!(
+                claimed_shares <= held_shares + I80F48::ONE,
+                "Market owes {} shares of {} but holds {}",
+                claimed_shares,
+                base.mint,
+                held_shares,
+            );
+        }
+    }
+}
+
+impl Default for NixFuzzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,12 @@
+//! Fuzzing for nix without a validator. Instructions run through
+//! `nix::process_instruction` against an in-memory [`store::AccountStore`],
+//! with CPIs and sysvars served by [`stubs::FuzzSyscallStubs`]. The
+//! [`executor::NixFuzzer`] turns fuzzer input into sequences of CreateMarket,
+//! ClaimSeat, Deposit, PlaceOrder and CancelOrder and checks the market after
+//! every step.
+//!
+//! From programs/nix: `cargo +nightly fuzz run instruction_executor`
+
+pub mod executor;
+pub mod store;
+pub mod stubs;
@@ -0,0 +1,162 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction,
+    program_error::ProgramError, pubkey::Pubkey, rent::Rent,
+};
+
+#[derive(Clone, Default)]
+pub struct StoredAccount {
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub owner: Pubkey,
+    pub executable: bool,
+}
+
+thread_local! {
+    /// Buffers of accounts that were resized by a stubbed CPI. They have to
+    /// outlive the AccountInfos of the instruction that is running.
+    static RESIZED_BUFFERS: RefCell<Vec<Box<[u8]>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Stands in for the runtime growing or shrinking an account. Points the data
+/// of the account at a new zeroed buffer that keeps the old contents.
+pub fn resize_account_data(account_info: &AccountInfo, new_len: usize) -> ProgramResult {
+    let mut buffer: Box<[u8]> = vec![0; new_len].into_boxed_slice();
+    {
+        let old_data = account_info.try_borrow_data()?;
+        let copy_len: usize = old_data.len().min(new_len);
+        buffer[..copy_len].copy_from_slice(&old_data[..copy_len]);
+    }
+    let buffer_ptr: *mut u8 = buffer.as_mut_ptr();
+    RESIZED_BUFFERS.with(|buffers| buffers.borrow_mut().push(buffer));
+
+    // The buffer stays in RESIZED_BUFFERS until AccountStore::execute has
+    // copied the account back, which is after the last use of account_info.
+    *account_info.try_borrow_mut_data()? =
+        unsafe { std::slice::from_raw_parts_mut(buffer_ptr, new_len) };
+    Ok(())
+}
+
+/// All accounts the fuzzer knows about. Accounts that were never set read as
+/// empty system accounts, like on chain.
+#[derive(Default)]
+pub struct AccountStore {
+    accounts: HashMap<Pubkey, StoredAccount>,
+}
+
+impl AccountStore {
+    pub fn get(&self, key: &Pubkey) -> Option<&StoredAccount> {
+        self.accounts.get(key)
+    }
+
+    pub fn set(&mut self, key: Pubkey, account: StoredAccount) {
+        self.accounts.insert(key, account);
+    }
+
+    /// Sets an account funded to be rent exempt for its data.
+    pub fn set_rent_exempt(&mut self, key: Pubkey, owner: Pubkey, data: Vec<u8>) {
+        self.set(
+            key,
+            StoredAccount {
+                lamports: Rent::default().minimum_balance(data.len()),
+                data,
+                owner,
+                executable: false,
+            },
+        );
+    }
+
+    /// Runs a nix instruction. Accounts are only written back when it
+    /// succeeds, so a failed instruction leaves no trace, same as a failed
+    /// transaction.
+    pub fn execute(&mut self, instruction: &Instruction) -> ProgramResult {
+        if instruction.program_id != nix::ID {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        // The runtime hands out one account per key, even when an instruction
+        // lists it more than once.
+        let mut keys: Vec<Pubkey> = Vec::new();
+        let mut is_signer: Vec<bool> = Vec::new();
+        let mut is_writable: Vec<bool> = Vec::new();
+        for account_meta in instruction.accounts.iter() {
+            match keys.iter().position(|key| *key == account_meta.pubkey) {
+                Some(index) => {
+                    is_signer[index] |= account_meta.is_signer;
+                    is_writable[index] |= account_meta.is_writable;
+                }
+                None => {
+                    keys.push(account_meta.pubkey);
+                    is_signer.push(account_meta.is_signer);
+                    is_writable.push(account_meta.is_writable);
+                }
+            }
+        }
+        let mut accounts: Vec<StoredAccount> = keys
+            .iter()
+            .map(|key| self.accounts.get(key).cloned().unwrap_or_default())
+            .collect();
+
+        let (result, updated_accounts) = {
+            let account_infos: Vec<AccountInfo> = keys
+                .iter()
+                .zip(accounts.iter_mut())
+                .enumerate()
+                .map(|(index, (key, account))| {
+                    let StoredAccount {
+                        lamports,
+                        data,
+                        owner,
+                        executable,
+                    } = account;
+                    AccountInfo::new(
+                        key,
+                        is_signer[index],
+                        is_writable[index],
+                        lamports,
+                        data,
+                        owner,
+                        *executable,
+                        0,
+                    )
+                })
+                .collect();
+            let instruction_account_infos: Vec<AccountInfo> = instruction
+                .accounts
+                .iter()
+                .map(|account_meta| {
+                    let index: usize = keys
+                        .iter()
+                        .position(|key| *key == account_meta.pubkey)
+                        .unwrap();
+                    account_infos[index].clone()
+                })
+                .collect();
+
+            let result: ProgramResult = nix::process_instruction(
+                &instruction.program_id,
+                &instruction_account_infos,
+                &instruction.data,
+            );
+            let updated_accounts: Vec<StoredAccount> = account_infos
+                .iter()
+                .map(|account_info| StoredAccount {
+                    lamports: account_info.lamports(),
+                    data: account_info.data.borrow().to_vec(),
+                    owner: *account_info.owner,
+                    executable: account_info.executable,
+                })
+                .collect();
+            (result, updated_accounts)
+        };
+        RESIZED_BUFFERS.with(|buffers| buffers.borrow_mut().clear());
+
+        if result.is_ok() {
+            for (key, account) in keys.into_iter().zip(updated_accounts) {
+                self.accounts.insert(key, account);
+            }
+        }
+        result
+    }
+}
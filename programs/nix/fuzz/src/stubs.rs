@@ -0,0 +1,404 @@
+use std::{mem::size_of, sync::Once};
+
+use fixed::types::I80F48;
+use marginfi::{
+    state::{marginfi_account::MarginfiAccount, marginfi_group::Bank},
+    ID as MARGINFI_PROGRAM_ID,
+};
+use nix::marginfi_utils::{
+    MARGINFI_ACCOUNT_CLOSE_DISCRIMINATOR, MARGINFI_ACCOUNT_DISCRIMINATOR,
+    MARGINFI_ACCOUNT_INITIALIZE_DISCRIMINATOR, MARGINFI_LENDING_ACCOUNT_BORROW_DISCRIMINATOR,
+    MARGINFI_LENDING_ACCOUNT_DEPOSIT_DISCRIMINATOR, MARGINFI_LENDING_ACCOUNT_REPAY_DISCRIMINATOR,
+    MARGINFI_LENDING_ACCOUNT_WITHDRAW_DISCRIMINATOR,
+};
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::{ProgramResult, SUCCESS},
+    instruction::Instruction,
+    program_error::ProgramError,
+    program_pack::Pack,
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction::SystemInstruction,
+    system_program,
+};
+use spl_token::{
+    instruction::TokenInstruction,
+    state::{Account, AccountState},
+};
+
+use crate::store::resize_account_data;
+
+pub const FUZZ_SLOT: u64 = 1_000;
+pub const FUZZ_UNIX_TIMESTAMP: i64 = 1_700_000_000;
+
+/// Runs the CPIs nix makes without a validator. System and token instructions
+/// behave like the real programs. Marginfi is reduced to a ledger of shares at
+/// a share value of one, without interest, oracles or health checks.
+pub struct FuzzSyscallStubs;
+
+pub fn install_syscall_stubs() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        set_syscall_stubs(Box::new(FuzzSyscallStubs));
+    });
+}
+
+impl SyscallStubs for FuzzSyscallStubs {
+    // Logs are the bulk of the time spent per instruction otherwise.
+    fn sol_log(&self, _message: &str) {}
+    fn sol_log_data(&self, _data: &[&[u8]]) {}
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe {
+            *(var_addr as *mut Clock) = Clock {
+                slot: FUZZ_SLOT,
+                epoch_start_timestamp: FUZZ_UNIX_TIMESTAMP,
+                epoch: 0,
+                leader_schedule_epoch: 0,
+                unix_timestamp: FUZZ_UNIX_TIMESTAMP,
+            };
+        }
+        SUCCESS
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe {
+            *(var_addr as *mut Rent) = Rent::default();
+        }
+        SUCCESS
+    }
+
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let pda_signers: Vec<Pubkey> = signers_seeds
+            .iter()
+            .map(|seeds| Pubkey::create_program_address(seeds, &nix::ID))
+            .collect::<Result<Vec<Pubkey>, _>>()
+            .map_err(|_| ProgramError::InvalidSeeds)?;
+        let cpi_accounts = CpiAccounts {
+            instruction,
+            account_infos,
+            pda_signers: &pda_signers,
+        };
+
+        if instruction.program_id == system_program::id() {
+            process_system_instruction(&cpi_accounts)
+        } else if instruction.program_id == spl_token::id() {
+            process_token_instruction(&cpi_accounts)
+        } else if instruction.program_id == MARGINFI_PROGRAM_ID {
+            process_marginfi_instruction(&cpi_accounts)
+        } else {
+            Err(ProgramError::IncorrectProgramId)
+        }
+    }
+}
+
+struct CpiAccounts<'a, 'b, 'info> {
+    instruction: &'a Instruction,
+    account_infos: &'a [AccountInfo<'info>],
+    pda_signers: &'b [Pubkey],
+}
+
+impl<'a, 'b, 'info> CpiAccounts<'a, 'b, 'info> {
+    /// Account at `index` in the instruction. Fails like the runtime when it
+    /// is missing or should sign and does not.
+    fn get(&self, index: usize) -> Result<&'a AccountInfo<'info>, ProgramError> {
+        let account_meta = self
+            .instruction
+            .accounts
+            .get(index)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let account_info: &AccountInfo = self
+            .account_infos
+            .iter()
+            .find(|account_info| *account_info.key == account_meta.pubkey)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        if account_meta.is_signer
+            && !account_info.is_signer
+            && !self.pda_signers.contains(account_info.key)
+        {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        Ok(account_info)
+    }
+}
+
+fn transfer_lamports(from: &AccountInfo, to: &AccountInfo, lamports: u64) -> ProgramResult {
+    let from_lamports: u64 = from
+        .lamports()
+        .checked_sub(lamports)
+        .ok_or(ProgramError::InsufficientFunds)?;
+    **from.try_borrow_mut_lamports()? = from_lamports;
+    **to.try_borrow_mut_lamports()? += lamports;
+    Ok(())
+}
+
+fn process_system_instruction(cpi_accounts: &CpiAccounts) -> ProgramResult {
+    let system_instruction: SystemInstruction =
+        bincode::deserialize(&cpi_accounts.instruction.data)
+            .map_err(|_| ProgramError::InvalidInstructionData)?;
+    match system_instruction {
+        SystemInstruction::CreateAccount {
+            lamports,
+            space,
+            owner,
+        } => {
+            let from: &AccountInfo = cpi_accounts.get(0)?;
+            let to: &AccountInfo = cpi_accounts.get(1)?;
+            if to.lamports() != 0 || !to.data_is_empty() {
+                return Err(ProgramError::AccountAlreadyInitialized);
+            }
+            transfer_lamports(from, to, lamports)?;
+            resize_account_data(to, space as usize)?;
+            to.assign(&owner);
+            Ok(())
+        }
+        SystemInstruction::Transfer { lamports } => {
+            transfer_lamports(cpi_accounts.get(0)?, cpi_accounts.get(1)?, lamports)
+        }
+        // Only used by expand_dynamic under the fuzz feature, in place of a
+        // realloc by the owning program. So unlike the real system program
+        // this resizes accounts that are already allocated.
+        SystemInstruction::Allocate { space } => {
+            resize_account_data(cpi_accounts.get(0)?, space as usize)
+        }
+        _ => Err(ProgramError::InvalidInstructionData),
+    }
+}
+
+fn transfer_tokens(
+    source: &AccountInfo,
+    destination: &AccountInfo,
+    authority: &AccountInfo,
+    amount: u64,
+) -> ProgramResult {
+    let mut source_account: Account = Account::unpack(&source.try_borrow_data()?)?;
+    if source_account.owner != *authority.key {
+        return Err(ProgramError::IllegalOwner);
+    }
+    source_account.amount = source_account
+        .amount
+        .checked_sub(amount)
+        .ok_or(ProgramError::InsufficientFunds)?;
+    if source.key == destination.key {
+        return Ok(());
+    }
+    let mut destination_account: Account = Account::unpack(&destination.try_borrow_data()?)?;
+    if source_account.mint != destination_account.mint {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    destination_account.amount += amount;
+    Account::pack(source_account, &mut source.try_borrow_mut_data()?)?;
+    Account::pack(destination_account, &mut destination.try_borrow_mut_data()?)
+}
+
+fn process_token_instruction(cpi_accounts: &CpiAccounts) -> ProgramResult {
+    match TokenInstruction::unpack(&cpi_accounts.instruction.data)? {
+        TokenInstruction::InitializeAccount3 { owner } => {
+            let token_account: &AccountInfo = cpi_accounts.get(0)?;
+            let mint: &AccountInfo = cpi_accounts.get(1)?;
+            let account = Account {
+                mint: *mint.key,
+                owner,
+                state: AccountState::Initialized,
+                ..Account::default()
+            };
+            Account::pack(account, &mut token_account.try_borrow_mut_data()?)
+        }
+        TokenInstruction::Transfer { amount } => transfer_tokens(
+            cpi_accounts.get(0)?,
+            cpi_accounts.get(1)?,
+            cpi_accounts.get(2)?,
+            amount,
+        ),
+        TokenInstruction::TransferChecked { amount, .. } => transfer_tokens(
+            cpi_accounts.get(0)?,
+            cpi_accounts.get(2)?,
+            cpi_accounts.get(3)?,
+            amount,
+        ),
+        _ => Err(ProgramError::InvalidInstructionData),
+    }
+}
+
+/// Asset and liability shares of a marginfi account on a bank.
+pub fn get_marginfi_balance(marginfi_account_data: &[u8], bank: &Pubkey) -> (I80F48, I80F48) {
+    let marginfi_account: &MarginfiAccount =
+        bytemuck::from_bytes(&marginfi_account_data[8..8 + size_of::<MarginfiAccount>()]);
+    marginfi_account
+        .lending_account
+        .balances
+        .iter()
+        .find(|balance| balance.active != 0 && balance.bank_pk == *bank)
+        .map(|balance| {
+            (
+                I80F48::from(balance.asset_shares),
+                I80F48::from(balance.liability_shares),
+            )
+        })
+        .unwrap_or_default()
+}
+
+fn change_marginfi_balance(
+    marginfi_account: &AccountInfo,
+    bank: &AccountInfo,
+    asset_shares_delta: I80F48,
+    liability_shares_delta: I80F48,
+) -> ProgramResult {
+    let mut marginfi_account_data = marginfi_account.try_borrow_mut_data()?;
+    let marginfi_account_fixed: &mut MarginfiAccount =
+        bytemuck::from_bytes_mut(&mut marginfi_account_data[8..8 + size_of::<MarginfiAccount>()]);
+    let balances = &mut marginfi_account_fixed.lending_account.balances;
+    let balance_index: usize = balances
+        .iter()
+        .position(|balance| balance.active != 0 && balance.bank_pk == *bank.key)
+        .or_else(|| balances.iter().position(|balance| balance.active == 0))
+        .ok_or(ProgramError::AccountDataTooSmall)?;
+    let balance = &mut balances[balance_index];
+
+    let asset_shares: I80F48 = I80F48::from(balance.asset_shares) + asset_shares_delta;
+    let liability_shares: I80F48 = I80F48::from(balance.liability_shares) + liability_shares_delta;
+    if asset_shares < I80F48::ZERO || liability_shares < I80F48::ZERO {
+        return Err(ProgramError::InsufficientFunds);
+    }
+    balance.active = 1;
+    balance.bank_pk = *bank.key;
+    balance.asset_shares = asset_shares.into();
+    balance.liability_shares = liability_shares.into();
+
+    let mut bank_data = bank.try_borrow_mut_data()?;
+    let bank_fixed: &mut Bank = bytemuck::from_bytes_mut(&mut bank_data[8..8 + size_of::<Bank>()]);
+    bank_fixed.total_asset_shares =
+        (I80F48::from(bank_fixed.total_asset_shares) + asset_shares_delta).into();
+    bank_fixed.total_liability_shares =
+        (I80F48::from(bank_fixed.total_liability_shares) + liability_shares_delta).into();
+    Ok(())
+}
+
+fn process_marginfi_instruction(cpi_accounts: &CpiAccounts) -> ProgramResult {
+    let instruction_data: &[u8] = &cpi_accounts.instruction.data;
+    if instruction_data.len() < 8 {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    let (discriminator, data) = instruction_data.split_at(8);
+    let amount: u64 = data
+        .get(0..8)
+        .map(|amount_bytes| u64::from_le_bytes(amount_bytes.try_into().unwrap()))
+        .unwrap_or_default();
+    // Option<bool> after the amount for withdraw_all and repay_all.
+    let is_all: bool = data.get(8..10) == Some(&[1u8, 1u8][..]);
+
+    if discriminator == MARGINFI_ACCOUNT_INITIALIZE_DISCRIMINATOR {
+        // group, marginfi_account, authority, fee_payer, system_program
+        let group: &AccountInfo = cpi_accounts.get(0)?;
+        let marginfi_account: &AccountInfo = cpi_accounts.get(1)?;
+        let authority: &AccountInfo = cpi_accounts.get(2)?;
+        let fee_payer: &AccountInfo = cpi_accounts.get(3)?;
+        if !marginfi_account.data_is_empty() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+        let space: usize = 8 + size_of::<MarginfiAccount>();
+        transfer_lamports(
+            fee_payer,
+            marginfi_account,
+            Rent::default().minimum_balance(space),
+        )?;
+        resize_account_data(marginfi_account, space)?;
+        marginfi_account.assign(&MARGINFI_PROGRAM_ID);
+
+        let mut marginfi_account_data = marginfi_account.try_borrow_mut_data()?;
+        marginfi_account_data[..8].copy_from_slice(&MARGINFI_ACCOUNT_DISCRIMINATOR);
+        let marginfi_account_fixed: &mut MarginfiAccount =
+            bytemuck::from_bytes_mut(&mut marginfi_account_data[8..]);
+        marginfi_account_fixed.group = *group.key;
+        marginfi_account_fixed.authority = *authority.key;
+        Ok(())
+    } else if discriminator == MARGINFI_ACCOUNT_CLOSE_DISCRIMINATOR {
+        // marginfi_account, authority, fee_payer
+        let marginfi_account: &AccountInfo = cpi_accounts.get(0)?;
+        cpi_accounts.get(1)?;
+        let fee_payer: &AccountInfo = cpi_accounts.get(2)?;
+        {
+            let marginfi_account_data = marginfi_account.try_borrow_data()?;
+            let marginfi_account_fixed: &MarginfiAccount =
+                bytemuck::from_bytes(&marginfi_account_data[8..]);
+            if marginfi_account_fixed
+                .lending_account
+                .balances
+                .iter()
+                .any(|balance| balance.active != 0)
+            {
+                return Err(ProgramError::InvalidAccountData);
+            }
+        }
+        transfer_lamports(marginfi_account, fee_payer, marginfi_account.lamports())?;
+        resize_account_data(marginfi_account, 0)?;
+        marginfi_account.assign(&system_program::id());
+        Ok(())
+    } else if discriminator == MARGINFI_LENDING_ACCOUNT_DEPOSIT_DISCRIMINATOR
+        || discriminator == MARGINFI_LENDING_ACCOUNT_REPAY_DISCRIMINATOR
+    {
+        // group, marginfi_account, authority, bank, source, liquidity_vault,
+        // token_program
+        let marginfi_account: &AccountInfo = cpi_accounts.get(1)?;
+        let authority: &AccountInfo = cpi_accounts.get(2)?;
+        let bank: &AccountInfo = cpi_accounts.get(3)?;
+        let source: &AccountInfo = cpi_accounts.get(4)?;
+        let liquidity_vault: &AccountInfo = cpi_accounts.get(5)?;
+
+        let is_deposit: bool = discriminator == MARGINFI_LENDING_ACCOUNT_DEPOSIT_DISCRIMINATOR;
+        let amount: u64 = if !is_deposit && is_all {
+            let (_, liability_shares) =
+                get_marginfi_balance(&marginfi_account.try_borrow_data()?, bank.key);
+            liability_shares.ceil().to_num()
+        } else {
+            amount
+        };
+        transfer_tokens(source, liquidity_vault, authority, amount)?;
+        if is_deposit {
+            change_marginfi_balance(marginfi_account, bank, I80F48::from(amount), I80F48::ZERO)
+        } else {
+            change_marginfi_balance(marginfi_account, bank, I80F48::ZERO, -I80F48::from(amount))
+        }
+    } else if discriminator == MARGINFI_LENDING_ACCOUNT_WITHDRAW_DISCRIMINATOR
+        || discriminator == MARGINFI_LENDING_ACCOUNT_BORROW_DISCRIMINATOR
+    {
+        // group, marginfi_account, authority, bank, destination,
+        // liquidity_vault_authority, liquidity_vault, token_program
+        let marginfi_account: &AccountInfo = cpi_accounts.get(1)?;
+        cpi_accounts.get(2)?;
+        let bank: &AccountInfo = cpi_accounts.get(3)?;
+        let destination: &AccountInfo = cpi_accounts.get(4)?;
+        let liquidity_vault_authority: &AccountInfo = cpi_accounts.get(5)?;
+        let liquidity_vault: &AccountInfo = cpi_accounts.get(6)?;
+
+        let is_withdraw: bool = discriminator == MARGINFI_LENDING_ACCOUNT_WITHDRAW_DISCRIMINATOR;
+        let amount: u64 = if is_withdraw && is_all {
+            let (asset_shares, _) =
+                get_marginfi_balance(&marginfi_account.try_borrow_data()?, bank.key);
+            asset_shares.floor().to_num()
+        } else {
+            amount
+        };
+        if is_withdraw {
+            change_marginfi_balance(marginfi_account, bank, -I80F48::from(amount), I80F48::ZERO)?;
+        } else {
+            change_marginfi_balance(marginfi_account, bank, I80F48::ZERO, I80F48::from(amount))?;
+        }
+        transfer_tokens(
+            liquidity_vault,
+            destination,
+            liquidity_vault_authority,
+            amount,
+        )
+    } else {
+        Err(ProgramError::InvalidInstructionData)
+    }
+}
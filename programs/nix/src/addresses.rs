@@ -0,0 +1,292 @@
+//! Every address the program derives, and the sizes of the accounts behind
+//! them, in one place. Integrators building transactions off chain should
+//! derive from here rather than copying seeds, so a seed change on chain
+//! cannot silently drift from the client.
+
+use solana_program::pubkey::Pubkey;
+
+use crate::state::sort_mints;
+
+pub use crate::state::{
+    get_event_queue_account_size, get_market_account_size, get_market_loans_account_size,
+    ACTIVE_LOAN_SIZE, CLAIMED_SEAT_SIZE, CROSS_MARGIN_SEAT_SIZE, EVENT_QUEUE_FIXED_SIZE,
+    GLOBAL_BLOCK_SIZE, GLOBAL_DEPOSIT_SIZE, GLOBAL_FIXED_SIZE, GLOBAL_TRADER_SIZE,
+    MARKET_AUCTION_SIZE, MARKET_BLOCK_SIZE, MARKET_EVENT_SIZE, MARKET_FIXED_SIZE,
+    MARKET_INSURANCE_SIZE, MARKET_LOANS_FIXED_SIZE, MARKET_LOAN_BLOCK_SIZE,
+    MARKET_REGISTRY_FIXED_SIZE, MARKET_STATS_SIZE, PENDING_ORDER_SIZE, RESTING_ORDER_SIZE,
+};
+
+#[macro_export]
+macro_rules! market_vault_seeds {
+    ( $market:expr, $mint:expr ) => {
+        &[b"vault", $market.as_ref(), $mint.as_ref()]
+    };
+}
+
+#[macro_export]
+macro_rules! market_vault_seeds_with_bump {
+    ( $market:expr, $mint:expr, $bump:expr ) => {
+        &[&[b"vault", $market.as_ref(), $mint.as_ref(), &[$bump]]]
+    };
+}
+
+#[macro_export]
+macro_rules! global_vault_seeds {
+    ( $mint:expr ) => {
+        &[b"global-vault", $mint.as_ref()]
+    };
+}
+
+#[macro_export]
+macro_rules! global_vault_seeds_with_bump {
+    ( $mint:expr, $bump:expr ) => {
+        &[&[b"global-vault", $mint.as_ref(), &[$bump]]]
+    };
+}
+
+#[macro_export]
+macro_rules! market_fee_receiver_seeds {
+    ( $market:expr, $mint:expr ) => {
+        &[b"fee-receiver", $market.as_ref(), $mint.as_ref()]
+    };
+}
+
+#[macro_export]
+macro_rules! market_fee_receiver_seeds_with_bump {
+    ( $market:expr, $mint:expr, $bump:expr ) => {
+        &[&[b"fee-receiver", $market.as_ref(), $mint.as_ref(), &[$bump]]]
+    };
+}
+
+#[macro_export]
+macro_rules! market_signer_seeds {
+    ( $market:expr ) => {
+        &[b"market-signer", $market.as_ref()]
+    };
+}
+
+#[macro_export]
+macro_rules! market_signer_seeds_with_bump {
+    ( $market:expr, $bump:expr ) => {
+        &[&[b"market-signer", $market.as_ref(), &[$bump]]]
+    };
+}
+
+pub fn get_market_signer_address(market: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(market_signer_seeds!(market), &crate::ID)
+}
+
+pub fn get_market_fee_receiver_address(market: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(market_fee_receiver_seeds!(market, mint), &crate::ID)
+}
+
+pub fn get_vault_address(market: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(market_vault_seeds!(market, mint), &crate::ID)
+}
+
+pub fn get_global_vault_address(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(global_vault_seeds!(mint), &crate::ID)
+}
+
+#[macro_export]
+macro_rules! global_seeds {
+    ( $mint:expr ) => {
+        &[b"global", $mint.as_ref()]
+    };
+}
+
+#[macro_export]
+macro_rules! global_seeds_with_bump {
+    ( $mint:expr, $bump:expr ) => {
+        &[&[b"global", $mint.as_ref(), &[$bump]]]
+    };
+}
+
+#[macro_export]
+macro_rules! nix_marginfi_account_seeds {
+    ($market:expr, $mint:expr) => {
+        &[b"nix_marginfi_account", $market.as_ref(), $mint.as_ref()]
+    };
+}
+
+#[macro_export]
+macro_rules! nix_marginfi_account_seeds_with_bump {
+    ( $market:expr, $mint:expr, $bump:expr ) => {
+        &[&[
+            b"nix_marginfi_account",
+            $market.as_ref(),
+            $mint.as_ref(),
+            &[$bump],
+        ]]
+    };
+}
+
+pub fn get_nix_marginfi_account_address(market: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(nix_marginfi_account_seeds!(market, mint), &crate::ID)
+}
+
+pub fn get_global_address(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(global_seeds!(mint), &crate::ID)
+}
+
+#[macro_export]
+macro_rules! cross_margin_seat_seeds {
+    ( $market:expr, $trader:expr ) => {
+        &[b"cross-margin-seat", $market.as_ref(), $trader.as_ref()]
+    };
+}
+
+pub fn get_cross_margin_seat_address(market: &Pubkey, trader: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(cross_margin_seat_seeds!(market, trader), &crate::ID)
+}
+
+#[macro_export]
+macro_rules! market_stats_seeds {
+    ( $market:expr ) => {
+        &[b"market-stats", $market.as_ref()]
+    };
+}
+
+pub fn get_market_stats_address(market: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(market_stats_seeds!(market), &crate::ID)
+}
+
+#[macro_export]
+macro_rules! market_auction_seeds {
+    ( $market:expr ) => {
+        &[b"market-auction", $market.as_ref()]
+    };
+}
+
+pub fn get_market_auction_address(market: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(market_auction_seeds!(market), &crate::ID)
+}
+
+#[macro_export]
+macro_rules! market_insurance_seeds {
+    ( $market:expr ) => {
+        &[b"market-insurance", $market.as_ref()]
+    };
+}
+
+pub fn get_market_insurance_address(market: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(market_insurance_seeds!(market), &crate::ID)
+}
+
+#[macro_export]
+macro_rules! market_registry_seeds {
+    ( $mint_low:expr, $mint_high:expr ) => {
+        &[b"market-registry", $mint_low.as_ref(), $mint_high.as_ref()]
+    };
+}
+
+#[macro_export]
+macro_rules! pending_order_seeds {
+    ( $market:expr, $trader:expr ) => {
+        &[b"pending-order", $market.as_ref(), $trader.as_ref()]
+    };
+}
+
+pub fn get_pending_order_address(market: &Pubkey, trader: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(pending_order_seeds!(market, trader), &crate::ID)
+}
+
+/// Same address whichever order the mints are given in.
+pub fn get_market_registry_address(mint: &Pubkey, other_mint: &Pubkey) -> (Pubkey, u8) {
+    let (mint_low, mint_high) = sort_mints(mint, other_mint);
+    Pubkey::find_program_address(market_registry_seeds!(mint_low, mint_high), &crate::ID)
+}
+
+#[macro_export]
+macro_rules! market_seeds {
+    ( $base_a_mint:expr, $base_b_mint:expr, $nonce:expr ) => {
+        &[
+            b"market",
+            $base_a_mint.as_ref(),
+            $base_b_mint.as_ref(),
+            &$nonce.to_le_bytes(),
+        ]
+    };
+}
+
+/// Address of a canonical market for a mint pair. The nonce lets more than
+/// one canonical market exist per pair, routers start looking from zero.
+pub fn get_market_address(base_a_mint: &Pubkey, base_b_mint: &Pubkey, nonce: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(market_seeds!(base_a_mint, base_b_mint, nonce), &crate::ID)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use solana_program::pubkey;
+
+    const MARKET: Pubkey = Pubkey::new_from_array([1; 32]);
+    const MINT: Pubkey = Pubkey::new_from_array([2; 32]);
+    const OTHER_MINT: Pubkey = Pubkey::new_from_array([3; 32]);
+    const TRADER: Pubkey = Pubkey::new_from_array([4; 32]);
+
+    // Pinned so that a change to any seed, or to the program id, shows up
+    // here before it breaks clients deriving the old addresses.
+    #[test]
+    fn test_market_scoped_addresses() {
+        assert_eq!(
+            get_market_signer_address(&MARKET),
+            (pubkey!("HCgLX9WJh3yk2zZ3MQLnXJ2UmrPFssJhgkGWbyiKKGFE"), 254)
+        );
+        assert_eq!(
+            get_vault_address(&MARKET, &MINT),
+            (pubkey!("89NssCWS5M6guYRKyZkLeaXYbbepMNwKz6Hcf2iLAMZX"), 255)
+        );
+        assert_eq!(
+            get_market_fee_receiver_address(&MARKET, &MINT),
+            (pubkey!("7AgpR4UdZZLcXWWxNoszgoXWm7StW9mEhN8Ruzg1Ngzj"), 254)
+        );
+        assert_eq!(
+            get_nix_marginfi_account_address(&MARKET, &MINT),
+            (pubkey!("59HB7hkjQ2EJZ7mP3yPPKPinVEHEPdPdDt6557So6wNr"), 252)
+        );
+        assert_eq!(
+            get_market_stats_address(&MARKET),
+            (pubkey!("DfrsDdpN1zUYwtTAQbMLdErrFg7byBeag6hDdCt9BWcm"), 253)
+        );
+        assert_eq!(
+            get_market_auction_address(&MARKET),
+            (pubkey!("5XGEhQjwcPZa6Wg2zpDwgdku2KKH6QzNYm3DdF4C8ue1"), 254)
+        );
+        assert_eq!(
+            get_market_insurance_address(&MARKET),
+            (pubkey!("CoP1urd7ysGtuGHxUQQp8mpchPEUPKNdWUv6aZp2CeGS"), 255)
+        );
+    }
+
+    #[test]
+    fn test_trader_scoped_addresses() {
+        assert_eq!(
+            get_cross_margin_seat_address(&MARKET, &TRADER),
+            (pubkey!("443bnEp26wnevMpxvdt69maqMp94QTEzaWRKARnhj8Pg"), 255)
+        );
+        assert_eq!(
+            get_pending_order_address(&MARKET, &TRADER),
+            (pubkey!("FgEJSgJ41tUJc4GaSpdhgjvkujeWMbcLfKTs7jCf8uDq"), 255)
+        );
+    }
+
+    #[test]
+    fn test_mint_scoped_addresses() {
+        assert_eq!(
+            get_global_address(&MINT),
+            (pubkey!("2A1h3NnGkaKPEQ8tLNWayDq1xgjkUApCeQZoZR2jSUhM"), 253)
+        );
+        assert_eq!(
+            get_global_vault_address(&MINT),
+            (pubkey!("GafEX82JcnhXb3bqMLC7RaDcU7bBccgiJcNXxBj2j7qR"), 254)
+        );
+        assert_eq!(
+            get_market_address(&MINT, &OTHER_MINT, 0),
+            (pubkey!("Cqdv68PFqKFXnxiVEeTccJF7Rgo6KCTc2EaGCXFGfMsM"), 255)
+        );
+        let registry: (Pubkey, u8) = (pubkey!("Y6U9yoDzBnJ2AiVz6GgPLGSwrKq61nVtj7hWEeiUJ9A"), 254);
+        assert_eq!(get_market_registry_address(&MINT, &OTHER_MINT), registry);
+        assert_eq!(get_market_registry_address(&OTHER_MINT, &MINT), registry);
+    }
+}
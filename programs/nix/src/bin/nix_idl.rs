@@ -0,0 +1,12 @@
+//! Prints the nix IDL as JSON. Run with
+//! `cargo run -p nix --features idl --bin nix-idl > nix.json`.
+
+fn main() {
+    match nix::idl::generate_idl() {
+        Ok(idl) => println!("{}", serde_json::to_string_pretty(&idl).unwrap()),
+        Err(err) => {
+            eprintln!("Could not generate IDL: {err}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,20 @@
+//! Everything needed to read nix accounts and events off chain. Available
+//! without the `program` feature, so indexers and bots can depend on the
+//! crate with `default-features = false, features = ["client"]` and skip the
+//! marginfi and CPI dependencies.
+
+pub use hypertree::{
+    get_helper, DataIndex, Get, HyperTreeReadOperations, HyperTreeValueIteratorTrait, PodBool,
+    RBNode, RedBlackTreeReadOnly, NIL,
+};
+
+pub use crate::{
+    addresses::*,
+    logs::*,
+    math::*,
+    program::{error::describe, NixError, NixInstruction},
+    quantities::*,
+    state::*,
+    utils::get_discriminant,
+    ID,
+};
@@ -0,0 +1,182 @@
+//! IDL generation straight from the program source, so clients do not need
+//! the anchor or shank CLI. NixInstruction is a plain u8 enum, so shank sees
+//! no args on it. The params structs are attached here by name, an
+//! instruction X takes XParams when that type exists, or the params listed
+//! for it in SHARED_PARAMS.
+
+use serde_json::{json, Value};
+use shank_idl::{extract_idl, ParseIdlOpts};
+
+/// Instructions that take the params of another instruction.
+const SHARED_PARAMS: &[(&str, &str)] = &[
+    ("PlaceOrderBaseA", "PlaceOrderParams"),
+    ("PlaceOrderBaseB", "PlaceOrderParams"),
+];
+
+pub fn generate_idl() -> Result<Value, String> {
+    let idl = extract_idl(
+        concat!(env!("CARGO_MANIFEST_DIR"), "/src/lib.rs"),
+        ParseIdlOpts::default(),
+    )
+    .map_err(|err| err.to_string())?
+    .ok_or("No IDL found in src/lib.rs")?;
+    let mut idl: Value = serde_json::from_str(&idl.try_into_json().map_err(|err| err.to_string())?)
+        .map_err(|err| err.to_string())?;
+
+    let type_names: Vec<String> = idl["types"]
+        .as_array()
+        .map(|types| {
+            types
+                .iter()
+                .filter_map(|ty| ty["name"].as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Some(instructions) = idl["instructions"].as_array_mut() {
+        for instruction in instructions.iter_mut() {
+            let Some(name) = instruction["name"].as_str() else {
+                continue;
+            };
+            let expected_params_name: String = SHARED_PARAMS
+                .iter()
+                .find(|(instruction_name, _)| instruction_name.eq_ignore_ascii_case(name))
+                .map_or_else(|| format!("{name}Params"), |(_, params)| params.to_string());
+            let params_name: Option<&String> = type_names
+                .iter()
+                .find(|type_name| type_name.eq_ignore_ascii_case(&expected_params_name));
+            if let Some(params_name) = params_name {
+                instruction["args"] = json!([{
+                    "name": "params",
+                    "type": { "defined": params_name },
+                }]);
+            }
+        }
+    }
+    Ok(idl)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::program::NixInstruction;
+
+    fn names(idl: &Value, section: &str) -> Vec<String> {
+        idl[section]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["name"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_idl_has_every_instruction() {
+        let idl: Value = generate_idl().unwrap();
+        let instructions: &Vec<Value> = idl["instructions"].as_array().unwrap();
+
+        let mut num_instructions: usize = 0;
+        while let Ok(instruction) = NixInstruction::try_from(num_instructions as u8) {
+            let name: String = format!("{:?}", instruction);
+            let idl_instruction: &Value = instructions
+                .iter()
+                .find(|idl_instruction| {
+                    idl_instruction["name"]
+                        .as_str()
+                        .unwrap()
+                        .eq_ignore_ascii_case(&name)
+                })
+                .unwrap_or_else(|| panic!("{name} missing from IDL"));
+            assert_eq!(
+                idl_instruction["discriminant"]["value"].as_u64(),
+                Some(instruction as u64),
+                "{name} discriminant"
+            );
+            assert!(
+                !idl_instruction["accounts"].as_array().unwrap().is_empty(),
+                "{name} has no accounts"
+            );
+            num_instructions += 1;
+        }
+        assert_eq!(instructions.len(), num_instructions);
+
+        let num_with_params: usize = instructions
+            .iter()
+            .filter(|instruction| !instruction["args"].as_array().unwrap().is_empty())
+            .count();
+        assert_eq!(num_with_params, 38);
+    }
+
+    /// A client built from the IDL has to pass the slot checks of the loader,
+    /// so every fixed slot is listed with its signer flag and is writable
+    /// wherever the loader needs it to be.
+    #[cfg(feature = "program")]
+    #[test]
+    fn test_idl_accounts_match_loader_slots() {
+        use crate::validation::loaders::{get_account_slots, AccountSlot};
+
+        let idl: Value = generate_idl().unwrap();
+        let instructions: &Vec<Value> = idl["instructions"].as_array().unwrap();
+
+        let mut discriminant: u8 = 0;
+        while let Ok(instruction) = NixInstruction::try_from(discriminant) {
+            let name: String = format!("{:?}", instruction);
+            let idl_accounts: &Vec<Value> = instructions
+                .iter()
+                .find(|idl_instruction| {
+                    idl_instruction["name"]
+                        .as_str()
+                        .unwrap()
+                        .eq_ignore_ascii_case(&name)
+                })
+                .unwrap_or_else(|| panic!("{name} missing from IDL"))["accounts"]
+                .as_array()
+                .unwrap();
+            let slots: &[AccountSlot] = get_account_slots(instruction);
+            assert!(
+                idl_accounts.len() >= slots.len(),
+                "{name} lists {} accounts, the loader takes {}",
+                idl_accounts.len(),
+                slots.len()
+            );
+            for (index, (idl_account, slot)) in idl_accounts.iter().zip(slots).enumerate() {
+                let account_name: &str = idl_account["name"].as_str().unwrap();
+                assert_eq!(
+                    idl_account["isSigner"].as_bool(),
+                    Some(slot.signer),
+                    "{name} account {index} {account_name} signer"
+                );
+                assert!(
+                    idl_account["isMut"].as_bool().unwrap() || !slot.writable,
+                    "{name} account {index} {account_name} has to be writable"
+                );
+            }
+            discriminant += 1;
+        }
+    }
+
+    #[test]
+    fn test_idl_has_state_accounts() {
+        let idl: Value = generate_idl().unwrap();
+        let mut defined: Vec<String> = names(&idl, "accounts");
+        defined.extend(names(&idl, "types"));
+
+        for name in [
+            "MarketFixed",
+            "GlobalFixed",
+            "MarketLoansFixed",
+            "ActiveLoan",
+            "ClaimedSeat",
+            "CrossMarginSeat",
+            "MarketStats",
+            "MarketAuction",
+            "MarketInsurance",
+            "MarketRegistryFixed",
+            "PendingOrder",
+            "RestingOrder",
+            "EventQueueFixed",
+            "MarketEvent",
+        ] {
+            assert!(defined.iter().any(|d| d == name), "{name} missing from IDL");
+        }
+    }
+}
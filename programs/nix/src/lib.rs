@@ -1,13 +1,22 @@
 #![allow(unexpected_cfgs)]
 
+#[cfg(feature = "program")]
 use hypertree::trace;
+#[cfg(feature = "program")]
 use solana_program::{
     account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
     pubkey::Pubkey,
 };
+pub mod addresses;
+pub mod client;
+#[cfg(feature = "idl")]
+pub mod idl;
 pub mod logs;
 pub mod macros;
+#[cfg(feature = "program")]
 pub mod marginfi_utils;
+pub mod matching;
+pub mod math;
 pub mod program;
 pub mod quantities;
 pub mod state;
@@ -15,10 +24,12 @@ pub mod utils;
 pub mod validation;
 solana_program::declare_id!("Nixjf1STQfCHXdpapnADG41pirqoy4QaUdQoUu8cL5i");
 
+#[cfg(feature = "program")]
 use program::{
-    claim_seat::process_claim_seat, create_market::process_create_market, create_market_loan_account::process_create_market_loan_account, deposit::process_deposit, global_add_trader::process_global_add_trader, global_create::process_global_create, global_deposit::process_global_deposit, place_order::process_place_order, NixInstruction
+    claim_seat::process_claim_seat, create_market::process_create_market, create_market_loan_account::process_create_market_loan_account, deposit::process_deposit, global_add_trader::process_global_add_trader, global_create::process_global_create, global_deposit::process_global_deposit, global_evict::process_global_evict, place_order::{process_place_order, process_place_order_base_a, process_place_order_base_b}, referrer_claim::process_referrer_claim, claim_maker_rebate::process_claim_maker_rebate, close_market::process_close_market, emit_book_snapshot::process_emit_book_snapshot, quote_order::process_quote_order, create_event_queue::process_create_event_queue, consume_events::process_consume_events, migrate_market::process_migrate_market, create_market_pda::process_create_market_pda, clean_expired_orders::process_clean_expired_orders, place_order_smart::process_place_order_smart, sweep_stranded_gas::process_sweep_stranded_gas, reduce_order::process_reduce_order, create_cross_margin_seat::process_create_cross_margin_seat, place_loan_sale::process_place_loan_sale, create_market_stats::process_create_market_stats, continue_order::process_continue_order, modify_order::process_modify_order, set_circuit_breaker::process_set_circuit_breaker, create_market_auction::process_create_market_auction, run_auction::process_run_auction, top_up_collateral::process_top_up_collateral, release_collateral::process_release_collateral, migrate_global::process_migrate_global, settle_loan_proceeds::process_settle_loan_proceeds, check_health::process_check_health, mark_default::process_mark_default, create_market_insurance::process_create_market_insurance, set_insurance_fee::process_set_insurance_fee, top_up_insurance::process_top_up_insurance, draw_insurance::process_draw_insurance, set_order_rate_limit::process_set_order_rate_limit, harvest_emissions::process_harvest_emissions, global_link_marginfi::process_global_link_marginfi, set_allowed_caller::process_set_allowed_caller, set_debug_logs::process_set_debug_logs, route_orders::process_route_orders, acknowledge_bank_config_change::process_acknowledge_bank_config_change, NixInstruction
 };
 
+#[cfg(feature = "program")]
 pub fn process_instruction<'a>(
     program_id: &Pubkey,
     accounts: &'a [AccountInfo<'a>],
@@ -61,19 +72,143 @@ pub fn process_instruction<'a>(
         NixInstruction::CancelOrder => {
             process_cancel_order(program_id, accounts, data)?;
         }
+        NixInstruction::ReferrerClaim => {
+            process_referrer_claim(program_id, accounts, data)?;
+        }
+        NixInstruction::ClaimMakerRebate => {
+            process_claim_maker_rebate(program_id, accounts, data)?;
+        }
+        NixInstruction::CloseMarket => {
+            process_close_market(program_id, accounts, data)?;
+        }
+        NixInstruction::EmitBookSnapshot => {
+            process_emit_book_snapshot(program_id, accounts, data)?;
+        }
+        NixInstruction::QuoteOrder => {
+            process_quote_order(program_id, accounts, data)?;
+        }
+        NixInstruction::CreateEventQueue => {
+            process_create_event_queue(program_id, accounts, data)?;
+        }
+        NixInstruction::ConsumeEvents => {
+            process_consume_events(program_id, accounts, data)?;
+        }
+        NixInstruction::GlobalEvict => {
+            process_global_evict(program_id, accounts, data)?;
+        }
+        NixInstruction::MigrateMarket => {
+            process_migrate_market(program_id, accounts, data)?;
+        }
+        NixInstruction::CreateMarketPda => {
+            process_create_market_pda(program_id, accounts, data)?;
+        }
+        NixInstruction::CleanExpiredOrders => {
+            process_clean_expired_orders(program_id, accounts, data)?;
+        }
+        NixInstruction::PlaceOrderSmart => {
+            process_place_order_smart(program_id, accounts, data)?;
+        }
+        NixInstruction::SweepStrandedGas => {
+            process_sweep_stranded_gas(program_id, accounts, data)?;
+        }
+        NixInstruction::ReduceOrder => {
+            process_reduce_order(program_id, accounts, data)?;
+        }
+        NixInstruction::CreateCrossMarginSeat => {
+            process_create_cross_margin_seat(program_id, accounts, data)?;
+        }
+        NixInstruction::PlaceLoanSale => {
+            process_place_loan_sale(program_id, accounts, data)?;
+        }
+        NixInstruction::CreateMarketStats => {
+            process_create_market_stats(program_id, accounts, data)?;
+        }
+        NixInstruction::ContinueOrder => {
+            process_continue_order(program_id, accounts, data)?;
+        }
+        NixInstruction::ModifyOrder => {
+            process_modify_order(program_id, accounts, data)?;
+        }
+        NixInstruction::SetCircuitBreaker => {
+            process_set_circuit_breaker(program_id, accounts, data)?;
+        }
+        NixInstruction::CreateMarketAuction => {
+            process_create_market_auction(program_id, accounts, data)?;
+        }
+        NixInstruction::RunAuction => {
+            process_run_auction(program_id, accounts, data)?;
+        }
+        NixInstruction::TopUpCollateral => {
+            process_top_up_collateral(program_id, accounts, data)?;
+        }
+        NixInstruction::ReleaseCollateral => {
+            process_release_collateral(program_id, accounts, data)?;
+        }
+        NixInstruction::MigrateGlobal => {
+            process_migrate_global(program_id, accounts, data)?;
+        }
+        NixInstruction::SettleLoanProceeds => {
+            process_settle_loan_proceeds(program_id, accounts, data)?;
+        }
+        NixInstruction::CheckHealth => {
+            process_check_health(program_id, accounts, data)?;
+        }
+        NixInstruction::MarkDefault => {
+            process_mark_default(program_id, accounts, data)?;
+        }
+        NixInstruction::CreateMarketInsurance => {
+            process_create_market_insurance(program_id, accounts, data)?;
+        }
+        NixInstruction::SetInsuranceFee => {
+            process_set_insurance_fee(program_id, accounts, data)?;
+        }
+        NixInstruction::TopUpInsurance => {
+            process_top_up_insurance(program_id, accounts, data)?;
+        }
+        NixInstruction::DrawInsurance => {
+            process_draw_insurance(program_id, accounts, data)?;
+        }
+        NixInstruction::SetOrderRateLimit => {
+            process_set_order_rate_limit(program_id, accounts, data)?;
+        }
+        NixInstruction::HarvestEmissions => {
+            process_harvest_emissions(program_id, accounts, data)?;
+        }
+        NixInstruction::GlobalLinkMarginfi => {
+            process_global_link_marginfi(program_id, accounts, data)?;
+        }
+        NixInstruction::PlaceOrderBaseA => {
+            process_place_order_base_a(program_id, accounts, data)?;
+        }
+        NixInstruction::PlaceOrderBaseB => {
+            process_place_order_base_b(program_id, accounts, data)?;
+        }
+        NixInstruction::SetAllowedCaller => {
+            process_set_allowed_caller(program_id, accounts, data)?;
+        }
+        NixInstruction::SetDebugLogs => {
+            process_set_debug_logs(program_id, accounts, data)?;
+        }
+        NixInstruction::RouteOrders => {
+            process_route_orders(program_id, accounts, data)?;
+        }
+        NixInstruction::AcknowledgeBankConfigChange => {
+            process_acknowledge_bank_config_change(program_id, accounts, data)?;
+        }
     }
     Ok(()) 
 }
 
-#[cfg(not(feature = "no-entrypoint"))]
+#[cfg(all(feature = "program", not(feature = "no-entrypoint")))]
 solana_program::entrypoint!(process_instruction);
 
-#[cfg(not(feature = "no-entrypoint"))]
+#[cfg(all(feature = "program", not(feature = "no-entrypoint")))]
 use solana_security_txt::security_txt;
 
+#[cfg(feature = "program")]
 use crate::program::cancel_order::process_cancel_order;
 
-#[cfg(not(feature = "no-entrypoint"))]
+#[cfg(all(feature = "program", not(feature = "no-entrypoint")))]
 security_txt! {
     name: "nix",
     project_url: "",
@@ -1,9 +1,16 @@
+use borsh::{BorshDeserialize, BorshSerialize};
 use bytemuck::{Pod, Zeroable};
 use hypertree::PodBool;
-use shank::ShankAccount;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use shank::{ShankAccount, ShankType};
 use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+use static_assertions::const_assert_eq;
 
-use crate::state::OrderType;
+use crate::{
+    program::NixInstruction,
+    quantities::WrappedI80F48,
+    state::{OrderType, MAX_BOOK_SNAPSHOT_LEVELS},
+};
 
 /// Serialize and log an event
 ///
@@ -33,7 +40,15 @@ macro_rules! discriminant {
     ($type_name:ident, $value:ident) => {
         impl Discriminant for $type_name {
             fn discriminant() -> [u8; 8] {
-                u64::to_le_bytes(crate::utils::get_discriminant::<$type_name>().unwrap())
+                // Logs keep hashing the path they were first emitted under so
+                // indexers keep decoding them.
+                u64::to_le_bytes(
+                    crate::utils::get_discriminant_of_name(concat!(
+                        "nix::logs::",
+                        stringify!($type_name)
+                    ))
+                    .unwrap(),
+                )
             }
         }
     };
@@ -48,10 +63,60 @@ discriminant!(GlobalAddTraderLog, test_global_add_trader_log);
 
 discriminant!(GlobalDepositLog, test_global_deposit_log);
 discriminant!(GlobalCleanupLog, test_global_cleanup_log);
+discriminant!(GlobalEvictLog, test_global_evict_log);
 
 discriminant!(FillLog, test_fill_log);
 discriminant!(PlaceOrderLog, test_fill_log);
 discriminant!(CancelOrderLog, test_cancel_order_log);
+discriminant!(ReferrerClaimLog, test_referrer_claim_log);
+discriminant!(ClaimMakerRebateLog, test_claim_maker_rebate_log);
+discriminant!(CloseMarketLog, test_close_market_log);
+discriminant!(BookSnapshotLog, test_book_snapshot_log);
+discriminant!(QuoteOrderLog, test_quote_order_log);
+discriminant!(CreateEventQueueLog, test_create_event_queue_log);
+discriminant!(ConsumeEventsLog, test_consume_events_log);
+discriminant!(MigrateMarketLog, test_migrate_market_log);
+discriminant!(CleanExpiredOrdersLog, test_clean_expired_orders_log);
+discriminant!(PlaceOrderSmartLog, test_place_order_smart_log);
+discriminant!(SweepStrandedGasLog, test_sweep_stranded_gas_log);
+discriminant!(ReduceOrderLog, test_reduce_order_log);
+discriminant!(CreateCrossMarginSeatLog, test_create_cross_margin_seat_log);
+discriminant!(PlaceLoanSaleLog, test_place_loan_sale_log);
+discriminant!(LoanSaleLog, test_loan_sale_log);
+discriminant!(CreateMarketStatsLog, test_create_market_stats_log);
+discriminant!(MarketStatsLog, test_market_stats_log);
+discriminant!(RegisterMarketLog, test_register_market_log);
+discriminant!(PendingOrderLog, test_pending_order_log);
+discriminant!(SeatUpdatedLog, test_seat_updated_log);
+discriminant!(OrderConvertedToPoolLoanLog, test_order_converted_to_pool_loan_log);
+discriminant!(ModifyOrderLog, test_modify_order_log);
+discriminant!(SetCircuitBreakerLog, test_set_circuit_breaker_log);
+discriminant!(CircuitBreakerTrippedLog, test_circuit_breaker_tripped_log);
+discriminant!(CreateMarketAuctionLog, test_create_market_auction_log);
+discriminant!(RunAuctionLog, test_run_auction_log);
+discriminant!(TopUpCollateralLog, test_top_up_collateral_log);
+discriminant!(ReleaseCollateralLog, test_release_collateral_log);
+discriminant!(MigrateGlobalLog, test_migrate_global_log);
+discriminant!(SettleLoanProceedsLog, test_settle_loan_proceeds_log);
+discriminant!(CheckHealthLog, test_check_health_log);
+discriminant!(LoanDefaultedLog, test_loan_defaulted_log);
+discriminant!(ExpiredOrdersSweptLog, test_expired_orders_swept_log);
+discriminant!(CreateMarketInsuranceLog, test_create_market_insurance_log);
+discriminant!(SetInsuranceFeeLog, test_set_insurance_fee_log);
+discriminant!(TopUpInsuranceLog, test_top_up_insurance_log);
+discriminant!(DrawInsuranceLog, test_draw_insurance_log);
+discriminant!(SetOrderRateLimitLog, test_set_order_rate_limit_log);
+discriminant!(EmissionsHarvestedLog, test_emissions_harvested_log);
+discriminant!(GlobalLinkMarginfiLog, test_global_link_marginfi_log);
+discriminant!(SetAllowedCallerLog, test_set_allowed_caller_log);
+discriminant!(SetDebugLogsLog, test_set_debug_logs_log);
+discriminant!(RouteOrdersLog, test_route_orders_log);
+discriminant!(
+    AcknowledgeBankConfigChangeLog,
+    test_acknowledge_bank_config_change_log
+);
+discriminant!(ErrorLog, test_error_log);
+discriminant!(SourceErrorLog, test_source_error_log);
 
 #[repr(C)]
 #[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
@@ -100,6 +165,14 @@ pub struct GlobalDepositLog {
 }
 #[repr(C)]
 #[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct GlobalEvictLog {
+    pub evictor: Pubkey,
+    pub evictee: Pubkey,
+    pub evictor_atoms: u64,
+    pub evictee_atoms: u64,
+}
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
 pub struct GlobalCleanupLog {
     pub cleaner: Pubkey,
     pub maker: Pubkey,
@@ -124,7 +197,13 @@ pub struct FillLog {
     pub taker_sequence_number: u64,
     pub taker_is_buy: PodBool,
     pub is_maker_global: PodBool,
-    pub _padding1: [u8; 14],
+    pub _padding1: [u8; 6],
+    /// Time on book of the maker seat on this tree after the fill, in base
+    /// atom slots.
+    pub maker_time_on_book: u64,
+    /// Fee charged to the taker on the matched base atoms, in base asset
+    /// shares.
+    pub protocol_fee_shares: WrappedI80F48,
 }
 
 #[repr(C)]
@@ -148,4 +227,585 @@ pub struct CancelOrderLog {
     pub market: Pubkey,
     pub trader: Pubkey,
     pub order_sequence_number: u64,
-}
\ No newline at end of file
+    /// Time on book of the seat on the tree of the order, in base atom slots.
+    pub time_on_book: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct ReferrerClaimLog {
+    pub market: Pubkey,
+    pub referrer: Pubkey,
+    pub base_a_referral_fee_shares: WrappedI80F48,
+    pub base_b_referral_fee_shares: WrappedI80F48,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct ClaimMakerRebateLog {
+    pub market: Pubkey,
+    pub trader: Pubkey,
+    pub base_a_maker_rebate_shares: WrappedI80F48,
+    pub base_b_maker_rebate_shares: WrappedI80F48,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct CloseMarketLog {
+    pub market: Pubkey,
+    pub admin: Pubkey,
+}
+
+/// Aggregated resting size at one rate, in shares of the base bank: asset
+/// shares of the collateral for asks, liability shares for bids.
+#[repr(C)]
+#[derive(Default, Clone, Copy, Zeroable, Pod, BorshDeserialize, BorshSerialize, ShankAccount)]
+pub struct BookLevel {
+    pub rate_bps: u16,
+    pub num_orders: u16,
+    pub _padding: [u8; 4],
+    pub total_shares: WrappedI80F48,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct BookSnapshotLog {
+    pub market: Pubkey,
+    pub slot: u64,
+    pub is_a_tree: PodBool,
+    pub num_bid_levels: u8,
+    pub num_ask_levels: u8,
+    pub _padding: [u8; 5],
+    // Shank reads array lengths as literals, the assert keeps them at
+    // MAX_BOOK_SNAPSHOT_LEVELS.
+    pub bids: [BookLevel; 16],
+    pub asks: [BookLevel; 16],
+}
+const_assert_eq!(MAX_BOOK_SNAPSHOT_LEVELS, 16);
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct QuoteOrderLog {
+    pub market: Pubkey,
+    pub base_atoms: u64,
+    pub quote_atoms: u64,
+    pub num_maker_orders: u32,
+    pub weighted_rate_bps: u16,
+    pub is_bid: PodBool,
+    pub is_a_tree: PodBool,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct CreateEventQueueLog {
+    pub market: Pubkey,
+    pub event_queue: Pubkey,
+    pub admin: Pubkey,
+    pub capacity: u32,
+    pub _padding: [u8; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct ConsumeEventsLog {
+    pub market: Pubkey,
+    pub event_queue: Pubkey,
+    pub up_to_event_id: u64,
+    pub num_consumed: u32,
+    pub _padding: [u8; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct MigrateMarketLog {
+    pub market: Pubkey,
+    pub from_version: u8,
+    pub to_version: u8,
+    pub _padding: [u8; 6],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct CleanExpiredOrdersLog {
+    pub market: Pubkey,
+    pub cranker: Pubkey,
+    pub num_removed: u32,
+    pub use_a_tree: PodBool,
+    pub is_bid: PodBool,
+    pub _padding: [u8; 2],
+    /// Gas deposits of the good till time orders removed, paid to the
+    /// cranker.
+    pub gas_deposit_lamports: u64,
+}
+
+/// Emitted before the PlaceOrderLog of a PlaceOrderSmart, with the best
+/// opposing rate seen on each tree and the tree the order went to. Rates are
+/// zero for a tree with nothing to match.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct PlaceOrderSmartLog {
+    pub market: Pubkey,
+    pub trader: Pubkey,
+    pub base_a_best_rate_bps: u16,
+    pub base_b_best_rate_bps: u16,
+    pub is_bid: PodBool,
+    pub is_a_tree: PodBool,
+    pub _padding: [u8; 2],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct SweepStrandedGasLog {
+    pub market: Pubkey,
+    pub global: Pubkey,
+    pub trader: Pubkey,
+    pub lamports: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct ReduceOrderLog {
+    pub market: Pubkey,
+    pub trader: Pubkey,
+    pub order_sequence_number: u64,
+    pub collateral_shares_removed: WrappedI80F48,
+    pub collateral_shares_remaining: WrappedI80F48,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct CreateCrossMarginSeatLog {
+    pub market: Pubkey,
+    pub trader: Pubkey,
+    pub cross_margin_seat: Pubkey,
+    pub marginfi_account: Pubkey,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct PlaceLoanSaleLog {
+    pub market: Pubkey,
+    pub trader: Pubkey,
+    pub market_loans: Pubkey,
+    pub loan_sequence_number: u64,
+    pub order_sequence_number: u64,
+    pub rate_bps: u16,
+    pub _padding: [u8; 6],
+}
+
+/// Emitted when a taker ask buys a loan sale and becomes the lender.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct LoanSaleLog {
+    pub market: Pubkey,
+    pub market_loans: Pubkey,
+    pub seller: Pubkey,
+    pub buyer: Pubkey,
+    pub loan_sequence_number: u64,
+    pub base_atoms: u64,
+    pub rate_bps: u16,
+    pub _padding: [u8; 6],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct CreateMarketStatsLog {
+    pub market: Pubkey,
+    pub market_stats: Pubkey,
+    pub payer: Pubkey,
+}
+
+/// Emitted by PlaceOrder when it matched and the market stats account was
+/// passed in. Volume covers the last MARKET_STATS_NUM_BUCKETS buckets.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct MarketStatsLog {
+    pub market: Pubkey,
+    pub rolling_volume_atoms: u64,
+    pub open_interest_atoms: u64,
+    pub is_base_a_tree: PodBool,
+    pub _padding: [u8; 7],
+}
+
+/// Emitted by CreateMarket when the market is added to the registry of its
+/// mint pair.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct RegisterMarketLog {
+    pub market: Pubkey,
+    pub market_registry: Pubkey,
+    pub num_markets: u32,
+    pub _padding: [u8; 4],
+}
+
+/// Emitted when an order defers its remainder to the PendingOrder of the
+/// trader, and again by each ContinueOrder. Zero base atoms means the
+/// pending order is done and closed.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct PendingOrderLog {
+    pub market: Pubkey,
+    pub trader: Pubkey,
+    pub num_base_atoms: u64,
+    pub order_sequence_number: u64,
+}
+
+/// Emitted when a deposit changes the withdrawable balance of a seat. Holds
+/// the balances after the change.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct SeatUpdatedLog {
+    pub market: Pubkey,
+    pub trader: Pubkey,
+    pub base_a_withdrawable_asset_share: WrappedI80F48,
+    pub base_b_withdrawable_asset_share: WrappedI80F48,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct ModifyOrderLog {
+    pub market: Pubkey,
+    pub trader: Pubkey,
+    pub order_sequence_number: u64,
+    pub reverse_spread_bps: u16,
+    pub reverse_bid_spread_bps: u16,
+    pub reverse_cycles_left: u16,
+    pub _padding: [u8; 2],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct SetCircuitBreakerLog {
+    pub market: Pubkey,
+    pub admin: Pubkey,
+    pub circuit_breaker_slots: u32,
+    pub circuit_breaker_bps: u16,
+    pub _padding: [u8; 2],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct CircuitBreakerTrippedLog {
+    pub market: Pubkey,
+    /// Move of the base over quote oracle price since the last order.
+    pub price_move_bps: u64,
+    pub post_only_until_slot: u32,
+    pub _padding: [u8; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct CreateMarketAuctionLog {
+    pub market: Pubkey,
+    pub market_auction: Pubkey,
+    pub admin: Pubkey,
+    pub window_slots: u32,
+    pub window_end_slot: u32,
+}
+
+/// Emitted by RunAuction. Zero cleared base atoms means the tree did not
+/// cross and nothing settles on it.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct RunAuctionLog {
+    pub market: Pubkey,
+    pub base_a_cleared_base_atoms: u64,
+    pub base_b_cleared_base_atoms: u64,
+    pub base_a_clearing_rate_bps: u16,
+    pub base_b_clearing_rate_bps: u16,
+    pub settle_end_slot: u32,
+}
+
+/// Emitted by TopUpCollateral. is_loan tells whether sequence_number is a
+/// loan or a resting bid.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct TopUpCollateralLog {
+    pub market: Pubkey,
+    pub trader: Pubkey,
+    pub sequence_number: u64,
+    pub collateral_shares: WrappedI80F48,
+    pub is_loan: PodBool,
+    pub _padding: [u8; 7],
+}
+
+/// Emitted by ReleaseCollateral. amount is what reached the vault from
+/// marginfi and was sent on to the borrower.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct ReleaseCollateralLog {
+    pub market: Pubkey,
+    pub trader: Pubkey,
+    pub market_loans: Pubkey,
+    pub loan_sequence_number: u64,
+    pub released_collateral_shares: WrappedI80F48,
+    pub remaining_collateral_shares: WrappedI80F48,
+    pub amount: u64,
+    pub is_base_a: PodBool,
+    pub _padding: [u8; 7],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct MigrateGlobalLog {
+    pub global: Pubkey,
+    pub gas_deposit_lamports: u64,
+}
+
+/// Emitted by SettleLoanProceeds. lender_shares are in the lent mint,
+/// borrower_shares in the collateral mint.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct SettleLoanProceedsLog {
+    pub market: Pubkey,
+    pub market_loans: Pubkey,
+    pub lender: Pubkey,
+    pub borrower: Pubkey,
+    pub loan_sequence_number: u64,
+    pub lender_shares: WrappedI80F48,
+    pub borrower_shares: WrappedI80F48,
+    pub is_liability_base_a: PodBool,
+    pub _padding: [u8; 7],
+}
+
+/// Emitted by CheckHealth, values are in USD of the oracles.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct CheckHealthLog {
+    pub market: Pubkey,
+    pub market_loans: Pubkey,
+    pub trader: Pubkey,
+    pub num_loans: u64,
+    pub collateral_value_usd: WrappedI80F48,
+    pub liability_value_usd: WrappedI80F48,
+    /// Liability over collateral value, zero without collateral.
+    pub ltv: WrappedI80F48,
+    /// Weighted collateral less weighted liability at the LTV buffer of the
+    /// market. Negative once the loans are no longer backed.
+    pub distance_to_liquidation_usd: WrappedI80F48,
+}
+
+/// Emitted by MarkDefault. collateral_shares went to the lender in the
+/// collateral mint, liability_shares in the lent mint are what it was owed.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct LoanDefaultedLog {
+    pub market: Pubkey,
+    pub market_loans: Pubkey,
+    pub lender: Pubkey,
+    pub borrower: Pubkey,
+    pub loan_sequence_number: u64,
+    pub collateral_shares: WrappedI80F48,
+    pub liability_shares: WrappedI80F48,
+    /// Liability less collateral value in USD of the oracles.
+    pub shortfall_usd: WrappedI80F48,
+    /// Shares of the lent mint the insurance fund of the market paid the
+    /// lender towards the shortfall. The rest is written off against its
+    /// claim.
+    pub insurance_shares: WrappedI80F48,
+    pub is_liability_base_a: PodBool,
+    pub _padding: [u8; 7],
+}
+
+/// Emitted when Deposit, CancelOrder or PlaceOrder removed expired orders
+/// of the trader and returned their collateral to the seat.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct ExpiredOrdersSweptLog {
+    pub market: Pubkey,
+    pub trader: Pubkey,
+    pub num_removed: u32,
+    pub _padding: [u8; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct CreateMarketInsuranceLog {
+    pub market: Pubkey,
+    pub market_insurance: Pubkey,
+    pub admin: Pubkey,
+    pub insurance_fee_bps: u16,
+    pub _padding: [u8; 6],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct SetInsuranceFeeLog {
+    pub market: Pubkey,
+    pub admin: Pubkey,
+    pub insurance_fee_bps: u16,
+    pub _padding: [u8; 6],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct TopUpInsuranceLog {
+    pub market: Pubkey,
+    pub payer: Pubkey,
+    pub shares: WrappedI80F48,
+    pub is_base_a: PodBool,
+    pub _padding: [u8; 7],
+}
+
+/// Emitted by DrawInsurance. amount is what reached the vault from marginfi
+/// and was sent on to the admin.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct DrawInsuranceLog {
+    pub market: Pubkey,
+    pub admin: Pubkey,
+    pub shares: WrappedI80F48,
+    pub amount: u64,
+    pub is_base_a: PodBool,
+    pub _padding: [u8; 7],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct SetOrderRateLimitLog {
+    pub market: Pubkey,
+    pub admin: Pubkey,
+    pub order_window_slots: u32,
+    pub max_orders_per_window: u16,
+    pub _padding: [u8; 2],
+}
+
+/// Emissions withdrawn from one marginfi bank of the market. When they went
+/// to seats, seat_shares is what the seats were credited, the rest went to
+/// the protocol fees.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct EmissionsHarvestedLog {
+    pub market: Pubkey,
+    pub emissions_mint: Pubkey,
+    pub seat_shares: WrappedI80F48,
+    pub amount: u64,
+    pub is_base_a: PodBool,
+    pub to_seats: PodBool,
+    pub _padding: [u8; 6],
+}
+
+/// Emitted by GlobalLinkMarginfi. vault_atoms were deposited for
+/// minted_shares, which the balances of the global are now counted in.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct GlobalLinkMarginfiLog {
+    pub global: Pubkey,
+    pub marginfi_bank: Pubkey,
+    pub minted_shares: WrappedI80F48,
+    pub vault_atoms: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct SetAllowedCallerLog {
+    pub market: Pubkey,
+    pub admin: Pubkey,
+    pub allowed_caller: Pubkey,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct SetDebugLogsLog {
+    pub market: Pubkey,
+    pub admin: Pubkey,
+    pub debug_log_flags: u8,
+    pub _padding: [u8; 7],
+}
+
+/// Ties together the PlaceOrderLog of each leg of a RouteOrders.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct RouteOrdersLog {
+    pub first_market: Pubkey,
+    pub second_market: Pubkey,
+    pub trader: Pubkey,
+    pub first_order_sequence_number: u64,
+    pub second_order_sequence_number: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct AcknowledgeBankConfigChangeLog {
+    pub market: Pubkey,
+    pub admin: Pubkey,
+    pub marginfi_bank: Pubkey,
+    /// Zero when the market had not recorded one yet.
+    pub old_bank_config_hash: u64,
+    pub new_bank_config_hash: u64,
+}
+
+/// Why a bid was turned into a loan on the underlying protocol.
+#[derive(
+    Debug,
+    BorshDeserialize,
+    BorshSerialize,
+    PartialEq,
+    Clone,
+    Copy,
+    ShankType,
+    IntoPrimitive,
+    TryFromPrimitive,
+)]
+#[repr(u8)]
+pub enum PoolLoanConversionReason {
+    // Expired with the ConvertToPool policy when a taker reached it.
+    Expired = 0,
+    // A taker reached it with no collateral shares left.
+    NoCollateral = 1,
+    // Canceled by the trader.
+    Canceled = 2,
+}
+unsafe impl bytemuck::Zeroable for PoolLoanConversionReason {}
+unsafe impl bytemuck::Pod for PoolLoanConversionReason {}
+
+/// Emitted when a resting bid is removed from the book and its collateral and
+/// liability shares become a loan on the underlying protocol at rate 0.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct OrderConvertedToPoolLoanLog {
+    pub market: Pubkey,
+    pub trader: Pubkey,
+    pub order_sequence_number: u64,
+    pub collateral_shares: WrappedI80F48,
+    pub liability_shares: WrappedI80F48,
+    pub reason: PoolLoanConversionReason,
+    pub _padding: [u8; 7],
+}
+
+/// Emitted by require_account! right before a check fails. The error code is
+/// the same number the transaction fails with.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct ErrorLog {
+    pub error_code: u64,
+    pub instruction_tag: u8,
+    /// Index of the offending account in the instruction accounts.
+    pub account_index: u8,
+    pub _padding: [u8; 6],
+}
+
+/// Emitted when an error from marginfi, from a CPI or its oracle adapters,
+/// is returned as a NixError. The source code is what marginfi failed with.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod, ShankAccount)]
+pub struct SourceErrorLog {
+    pub error_code: u64,
+    pub source_error_code: u64,
+}
+
+/// Emits an ErrorLog for the error and hands it back for returning.
+pub fn with_error_log(
+    error: ProgramError,
+    instruction: NixInstruction,
+    account_index: usize,
+) -> ProgramError {
+    // Logging never fails, and a failed log should not mask the real error.
+    let _ = emit_stack(ErrorLog {
+        error_code: u64::from(error.clone()),
+        instruction_tag: instruction as u8,
+        account_index: account_index as u8,
+        _padding: [0; 6],
+    });
+    error
+}
@@ -1,16 +1,16 @@
 use crate::{
-    market_signer_seeds_with_bump,  program::NixError, require, state::MarketFixed, validation::{
-         loaders::{GlobalTradeAccounts, MarginfiCpiAccounts},  MarginfiAccountInfo, MarketSigner, MintAccountInfo, NixAccountInfo, Program, Signer, TokenAccountInfo, TokenProgram
+    program::{MarginfiCpiError, NixError, OracleError}, require, utils::get_ui_amount_multiplier, validation::{
+         loaders::{GlobalTradeAccounts, MarginfiCpiAccounts},  validate_pyth_push_owner, MarginfiAccountInfo, MarketSigner, MintAccountInfo, Program, Signer, TokenAccountInfo, TokenProgram
     }
 };
+use arrayvec::ArrayVec;
 use borsh::BorshSerialize;
 use fixed::{ types::I80F48};
 use hypertree::trace;
 use marginfi::{
-    constants::EXP_10_I80F48,
     prelude::MarginfiGroup,
     state::{
-        marginfi_account::MarginfiAccount,
+        marginfi_account::{MarginfiAccount, MAX_LENDING_ACCOUNT_BALANCES},
         marginfi_group::{Bank, BankConfig},
         price::{OraclePriceFeedAdapter, OraclePriceType, OracleSetup, PriceAdapter, PriceBias},
     },
@@ -18,14 +18,30 @@ use marginfi::{
 };
 use sha2::{Digest, Sha256};
 use solana_program::{
-    account_info::{next_account_info, AccountInfo},
+    account_info::AccountInfo,
     clock::Clock,
     entrypoint::ProgramResult,
     instruction::{AccountMeta, Instruction},
     program::invoke_signed,
     program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use spl_token_2022::{
+    extension::{transfer_hook, StateWithExtensions},
+    state::Mint,
+};
+use spl_transfer_hook_interface::onchain::add_extra_accounts_for_execute_cpi;
+use std::cell::Ref;
+
+pub use crate::{
+    math::{
+        convert_asset_shares_to_tokens, convert_tokens_to_asset_shares,
+        convert_tokens_to_liability_shares, get_base_atoms_backed_by_quote_collateral,
+        get_required_quote_collateral_to_back_loan, get_token_amount_to_repay_liability_shares,
+        get_weighted_value_usd,
+    },
+    quantities::BankShareValues,
 };
-use std::slice::Iter;
 
 // https://github.com/mrgnlabs/mrgn-ts/blob/6fb11c9ed0547feb1048855cc960880b1d66f965/packages/marginfi-client-v2/src/idl/marginfi-types_0.1.0.ts#L108
 pub const MARGINFI_GROUP_DISCRIMINATOR: [u8; 8] = [182, 23, 173, 240, 151, 206, 182, 67];
@@ -44,6 +60,7 @@ pub const MARGINFI_LENDING_ACCOUNT_WITHDRAW_EMISSION: [u8; 8] =
 pub const MARGINFI_LENDING_ACCOUNT_SETTLE_EMISSION: [u8; 8] =
     [161, 58, 136, 174, 242, 223, 156, 176];
 pub const MARGINFI_ACCOUNT_INITIALIZE_DISCRIMINATOR: [u8; 8] = [43, 78, 61, 255, 148, 52, 249, 154];
+pub const MARGINFI_ACCOUNT_CLOSE_DISCRIMINATOR: [u8; 8] = [186, 221, 93, 34, 50, 97, 194, 241];
 
 #[derive(BorshSerialize)]
 pub struct MfiInitializeAccountData {}
@@ -66,6 +83,8 @@ pub struct MfiLendingAccountRepayData {
     pub amount: u64,
     pub repay_all: Option<bool>,
 }
+#[derive(BorshSerialize)]
+pub struct MfiLendingAccountWithdrawEmissionsData {}
 
 /// A minimal tool to convert a hex string like "22f123639" into the byte equivalent.
 pub fn hex_to_bytes(hex: &str) -> Vec<u8> {
@@ -105,6 +124,10 @@ fn test_marginfi_discriminant() {
         compute_anchor_fn_discriminator("marginfi_account_initialize").to_vec(),
         MARGINFI_ACCOUNT_INITIALIZE_DISCRIMINATOR
     );
+    assert_eq!(
+        compute_anchor_fn_discriminator("marginfi_account_close").to_vec(),
+        MARGINFI_ACCOUNT_CLOSE_DISCRIMINATOR
+    );
 
     assert_eq!(
         compute_anchor_account_discriminator("Bank").to_vec(),
@@ -140,14 +163,14 @@ fn test_marginfi_discriminant() {
     );
 }
 
+/// `authority` is the market signer of a market, or the vault of a global.
 pub fn initialize_marginfi_account<'a, 'info>(
     marginfi_group: &'a MarginfiAccountInfo<'a, 'info, MarginfiGroup>,
     marginfi_account: &'a MarginfiAccountInfo<'a, 'info, MarginfiAccount>,
     admin: &'a Signer<'a, 'info>,
     system_program: &'a Program<'a, 'info>,
-    market: &'a NixAccountInfo<'a, 'info, MarketFixed>,
     authority: &'a AccountInfo<'info>,
-    authority_bump: u8,
+    authority_pda_seeds: &[&[&[u8]]],
 ) -> ProgramResult {
 
 
@@ -170,9 +193,9 @@ pub fn initialize_marginfi_account<'a, 'info>(
             admin.as_ref().clone(),
             system_program.as_ref().clone(),
         ],
-        market_signer_seeds_with_bump!(market.key, authority_bump),
+        authority_pda_seeds,
     )
-    .map_err(|_| NixError::MarginfiCpiFailed)?;
+    .map_err(|err| NixError::from(MarginfiCpiError(err)))?;
 
     //account is expected to have been initialized in the marginfi program
     let marginfi_account_data = marginfi_account.try_borrow_data()?;
@@ -185,142 +208,344 @@ pub fn initialize_marginfi_account<'a, 'info>(
         initialized_marginfi_account
             .group
             .eq(marginfi_group.as_ref().key)
-            && initialized_marginfi_account
-                .authority
-                .eq(marginfi_account.as_ref().key),
+            && initialized_marginfi_account.authority.eq(authority.key),
         NixError::MarginfiAccountInitializationFailed,
         "Marginfi account not initialized correctly",
     )?;
     Ok(())
 }
 
+// CPI to MarginFi: Close account. Fails if the account still has balances.
+pub fn cpi_marginfi_account_close<'a, 'info>(
+    marginfi_account: &MarginfiAccountInfo<'a, 'info, MarginfiAccount>,
+    authority: &MarketSigner<'a, 'info>,
+    fee_payer: &Signer<'a, 'info>,
+    authority_pda_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    trace!("CPI: MarginFi Close account {:?}", marginfi_account.key);
+    invoke_signed(
+        &Instruction {
+            program_id: MARGINFI_PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new(*marginfi_account.key, false),
+                AccountMeta::new_readonly(*authority.as_ref().key, true),
+                AccountMeta::new(*fee_payer.key, true),
+            ],
+            data: MARGINFI_ACCOUNT_CLOSE_DISCRIMINATOR.to_vec(),
+        },
+        &[
+            marginfi_account.as_ref().clone(),
+            authority.as_ref().clone(),
+            fee_payer.as_ref().clone(),
+        ],
+        authority_pda_seeds,
+    )
+    .map_err(|err| {
+        trace!("MarginFi Close account CPI failed: {:?}", err);
+        NixError::from(MarginfiCpiError(err)).into()
+    })
+}
+
+/// Most accounts a marginfi CPI takes: the fixed accounts of the lending
+/// instruction, transfer hook accounts, and a bank and up to three oracles for
+/// each balance of the health check.
+const MAX_MARGINFI_CPI_ACCOUNTS: usize = 16 + 4 * MAX_LENDING_ACCOUNT_BALANCES;
+/// Discriminator and args of the largest lending instruction.
+const MAX_MARGINFI_CPI_DATA_LEN: usize = 32;
+/// A bank and up to three oracles for each balance.
+const MAX_HEALTH_CHECK_ACCOUNTS: usize = 4 * MAX_LENDING_ACCOUNT_BALANCES;
+
+/// Buffers marginfi CPIs are built in. The program heap is a bump allocator
+/// that never frees, so instructions that make several CPIs, like a
+/// PlaceOrder that borrows and then deposits, build them all in one scratch
+/// sized up front instead of growing new Vecs each time. The metas and infos
+/// stay Vecs on the heap, the instruction owns its metas and an ArrayVec of
+/// AccountInfos this size does not fit in a stack frame.
+pub struct MarginfiCpiScratch<'info> {
+    account_metas: Vec<AccountMeta>,
+    account_infos: Vec<AccountInfo<'info>>,
+    data: Vec<u8>,
+}
+
+impl<'info> Default for MarginfiCpiScratch<'info> {
+    fn default() -> Self {
+        MarginfiCpiScratch {
+            account_metas: Vec::with_capacity(MAX_MARGINFI_CPI_ACCOUNTS),
+            account_infos: Vec::with_capacity(MAX_MARGINFI_CPI_ACCOUNTS),
+            data: Vec::with_capacity(MAX_MARGINFI_CPI_DATA_LEN),
+        }
+    }
+}
+
+/// Lending instruction to marginfi, signed by the market signer. Accounts are
+/// added in the order marginfi expects them, which keeps the metas and the
+/// infos of the CPI in step.
+pub struct MarginfiCpiBuilder<'s, 'info> {
+    name: &'static str,
+    scratch: &'s mut MarginfiCpiScratch<'info>,
+}
+
+impl<'s, 'info> MarginfiCpiBuilder<'s, 'info> {
+    pub fn new<T: BorshSerialize>(
+        name: &'static str,
+        discriminator: [u8; 8],
+        args: &T,
+        scratch: &'s mut MarginfiCpiScratch<'info>,
+    ) -> Result<Self, ProgramError> {
+        scratch.account_metas.clear();
+        scratch.account_infos.clear();
+        scratch.data.clear();
+        scratch.data.extend_from_slice(&discriminator);
+        args.serialize(&mut scratch.data)
+            .map_err(|_| ProgramError::InvalidInstructionData)?;
+        Ok(MarginfiCpiBuilder { name, scratch })
+    }
+
+    fn push(self, account_meta: AccountMeta, account_info: AccountInfo<'info>) -> Self {
+        self.scratch.account_metas.push(account_meta);
+        self.scratch.account_infos.push(account_info);
+        self
+    }
+
+    pub fn writable(self, account: &AccountInfo<'info>) -> Self {
+        self.push(AccountMeta::new(*account.key, false), account.clone())
+    }
+
+    pub fn readonly(self, account: &AccountInfo<'info>) -> Self {
+        self.push(
+            AccountMeta::new_readonly(*account.key, false),
+            account.clone(),
+        )
+    }
+
+    /// Authority of the marginfi account, the market signer or a global vault.
+    pub fn authority(self, authority: &impl AsRef<AccountInfo<'info>>) -> Self {
+        self.push(
+            AccountMeta::new(*authority.as_ref().key, true),
+            authority.as_ref().clone(),
+        )
+    }
+
+    /// Group, account, authority and bank, which every lending instruction
+    /// starts with.
+    pub fn lending_accounts(
+        self,
+        marginfi_group: &AccountInfo<'info>,
+        marginfi_account: &AccountInfo<'info>,
+        authority: &impl AsRef<AccountInfo<'info>>,
+        marginfi_bank: &AccountInfo<'info>,
+    ) -> Self {
+        self.writable(marginfi_group)
+            .writable(marginfi_account)
+            .authority(authority)
+            .writable(marginfi_bank)
+    }
+
+    /// Token 22 banks take the mint after the token program.
+    pub fn mint(self, mint: Option<&MintAccountInfo<'_, 'info>>) -> Self {
+        match mint {
+            Some(mint) => self.readonly(mint.as_ref()),
+            None => self,
+        }
+    }
+
+    /// Accounts the transfer hook of `mint` needs for the transfer marginfi
+    /// makes from `source` to `destination`, looked up by key among
+    /// `accounts`. marginfi forwards its remaining accounts to the hook, after
+    /// it has read any health check accounts, so these go last. Nothing is
+    /// added for mints without a hook.
+    pub fn transfer_hook_accounts(
+        mut self,
+        mint: Option<&MintAccountInfo<'_, 'info>>,
+        source: &AccountInfo<'info>,
+        destination: &AccountInfo<'info>,
+        authority: &AccountInfo<'info>,
+        amount: u64,
+        accounts: &[AccountInfo<'info>],
+    ) -> Result<Self, ProgramError> {
+        let mint: &MintAccountInfo = match mint {
+            Some(mint) => mint,
+            None => return Ok(self),
+        };
+        for hook_account in
+            get_transfer_hook_account_infos(mint, source, destination, authority, amount, accounts)?
+        {
+            self = if hook_account.is_writable {
+                self.writable(&hook_account)
+            } else {
+                self.readonly(&hook_account)
+            };
+        }
+        Ok(self)
+    }
+
+    /// Banks and oracles marginfi health checks `marginfi_account` against
+    /// once `target_bank` has a balance, see get_health_check_account_infos.
+    pub fn health_check_accounts<'a>(
+        mut self,
+        marginfi_account: &MarginfiAccountInfo<'_, '_, MarginfiAccount>,
+        target_bank: &Pubkey,
+        accounts: &'a [AccountInfo<'a>],
+    ) -> Result<Self, ProgramError>
+    where
+        'a: 'info,
+    {
+        let health_check_account_infos: ArrayVec<&'a AccountInfo<'a>, MAX_HEALTH_CHECK_ACCOUNTS> =
+            get_health_check_account_infos(marginfi_account, target_bank, accounts)?;
+        for account_info in health_check_account_infos {
+            self = self.readonly(account_info);
+        }
+        Ok(self)
+    }
+
+    pub fn invoke_signed(self, authority_pda_seeds: &[&[&[u8]]]) -> ProgramResult {
+        trace!("CPI: MarginFi {}", self.name);
+        let MarginfiCpiScratch {
+            account_metas,
+            account_infos,
+            data,
+        } = self.scratch;
+        // The instruction takes the buffers for the CPI and hands them back
+        // after, so the next CPI reuses them.
+        let instruction: Instruction = Instruction {
+            program_id: MARGINFI_PROGRAM_ID,
+            accounts: std::mem::take(account_metas),
+            data: std::mem::take(data),
+        };
+        let result: ProgramResult = invoke_signed(&instruction, account_infos, authority_pda_seeds);
+        *account_metas = instruction.accounts;
+        *data = instruction.data;
+        result.map_err(|err| {
+            trace!("MarginFi {} CPI failed: {:?}", self.name, err);
+            NixError::from(MarginfiCpiError(err)).into()
+        })
+    }
+}
+
+/// Extra accounts of the transfer hook of a token22 mint for one transfer,
+/// followed by the hook program and its validation account, as
+/// spl_transfer_hook_interface resolves them from `accounts`. Empty when the
+/// mint has no hook.
+fn get_transfer_hook_account_infos<'info>(
+    mint: &MintAccountInfo<'_, 'info>,
+    source: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+    authority: &AccountInfo<'info>,
+    amount: u64,
+    accounts: &[AccountInfo<'info>],
+) -> Result<Vec<AccountInfo<'info>>, ProgramError> {
+    let hook_program_id_opt: Option<Pubkey> = transfer_hook::get_program_id(
+        &StateWithExtensions::<Mint>::unpack(&mint.info.data.borrow())?,
+    );
+    let mut hook_account_infos: Vec<AccountInfo<'info>> = Vec::new();
+    if let Some(hook_program_id) = hook_program_id_opt {
+        // Only the accounts the hook adds are kept, the instruction is a
+        // throwaway.
+        add_extra_accounts_for_execute_cpi(
+            &mut Instruction::new_with_bytes(hook_program_id, &[], Vec::new()),
+            &mut hook_account_infos,
+            &hook_program_id,
+            source.clone(),
+            mint.info.clone(),
+            destination.clone(),
+            authority.clone(),
+            amount,
+            accounts,
+        )?;
+    }
+    Ok(hook_account_infos)
+}
+
 // CPI to MarginFi: Deposit
 pub fn cpi_marginfi_deposit<'a, 'info>(
     marginfi_group: &MarginfiAccountInfo<'a, 'info, MarginfiGroup>,
     marginfi_account: &MarginfiAccountInfo<'a, 'info, MarginfiAccount>,
     marginfi_bank: &MarginfiAccountInfo<'a, 'info, Bank>,
     marginfi_liquidity_vault: &TokenAccountInfo<'a, 'info>,
-    authority: MarketSigner<'a,'info>,
+    authority: impl AsRef<AccountInfo<'info>>,
     vault: &TokenAccountInfo<'a, 'info>,
     token_program: &TokenProgram<'a, 'info>,
     amount: u64,
     deposit_up_to_limit: Option<bool>,
     mint: &Option<MintAccountInfo<'a, 'info>>,
+    transfer_hook_accounts: &[AccountInfo<'info>],
     authority_pda_seeds: &[&[&[u8]]],
 ) -> ProgramResult {
     trace!("CPI: MarginFi Deposit amount {}", amount);
-    let ix_data_args = MfiLendingAccountDepositData {
+    MarginfiCpiBuilder::new(
+        "Deposit",
+        MARGINFI_LENDING_ACCOUNT_DEPOSIT_DISCRIMINATOR,
+        &MfiLendingAccountDepositData {
+            amount,
+            deposit_up_to_limit,
+        },
+        &mut MarginfiCpiScratch::default(),
+    )?
+    .lending_accounts(
+        marginfi_group.as_ref(),
+        marginfi_account.as_ref(),
+        &authority,
+        marginfi_bank.as_ref(),
+    )
+    .writable(vault.as_ref())
+    .writable(marginfi_liquidity_vault.as_ref())
+    .readonly(token_program.as_ref())
+    .mint(mint.as_ref())
+    .transfer_hook_accounts(
+        mint.as_ref(),
+        vault.as_ref(),
+        marginfi_liquidity_vault.as_ref(),
+        authority.as_ref(),
         amount,
-        deposit_up_to_limit,
-    };
-    let mut data_vec = MARGINFI_LENDING_ACCOUNT_DEPOSIT_DISCRIMINATOR.to_vec();
-    data_vec.extend_from_slice(
-        &ix_data_args
-            .try_to_vec()
-            .map_err(|_| ProgramError::InvalidInstructionData)?,
-    );
-
-    let mut cpi_account_metas = vec![
-        AccountMeta::new(*marginfi_group.key, false),
-        AccountMeta::new(*marginfi_account.key, false),
-        AccountMeta::new(*authority.as_ref().key, true),
-        AccountMeta::new(*marginfi_bank.key, false),
-        AccountMeta::new(*vault.key, false),
-        AccountMeta::new(*marginfi_liquidity_vault.key, false),
-        AccountMeta::new_readonly(*token_program.key, false),
-    ];
-    if let Some(mint_ai) = &mint {
-        cpi_account_metas.push(AccountMeta::new_readonly(*mint_ai.as_ref().key, false));
-        //add mint account for token 22 accounts
-    }
-
-    let instruction = Instruction {
-        program_id: MARGINFI_PROGRAM_ID,
-        accounts: cpi_account_metas,
-        data: data_vec,
-    };
-
-    let mut cpi_account_infos = vec![
-        marginfi_group.as_ref().clone(),
-        marginfi_account.as_ref().clone(),
-        authority.as_ref().clone(),
-        marginfi_bank.as_ref().clone(),
-        vault.as_ref().clone(),
-        marginfi_liquidity_vault.as_ref().clone(),
-        token_program.as_ref().clone(),
-    ];
-
-    // Add mint account info if provided
-    if let Some(mint_ai) = &mint {
-        cpi_account_infos.push(mint_ai.as_ref().clone());
-    }
-
-    invoke_signed(&instruction, &cpi_account_infos, authority_pda_seeds).map_err(|_e| {
-        trace!("MarginFi Deposit CPI failed: {:?}", _e);
-        NixError::MarginfiCpiFailed.into()
-    })
+        transfer_hook_accounts,
+    )?
+    .invoke_signed(authority_pda_seeds)
 }
 
-// CPI to MarginFi: Deposit
+// CPI to MarginFi: Deposit everything in source
 pub fn cpi_marginfi_deposit_place_order<'a, 'info>(
     marginfi_cpi_accts: &MarginfiCpiAccounts<'a, 'info>,
-    authority: MarketSigner<'a,'info>,
+    authority: MarketSigner<'a, 'info>,
     source: &TokenAccountInfo<'a, 'info>,
     token_program: &TokenProgram<'a, 'info>,
     mint: Option<&MintAccountInfo<'a, 'info>>,
+    scratch: &mut MarginfiCpiScratch<'info>,
     authority_pda_seeds: &[&[&[u8]]],
-) -> ProgramResult {
+    accounts: &'a [AccountInfo<'a>],
+) -> ProgramResult
+where
+    'a: 'info,
+{
+    let amount: u64 = source.get_balance();
     trace!("CPI: MarginFi Deposit amount {}", amount);
-    let ix_data_args = MfiLendingAccountDepositData {
-        amount: source.get_balance(), //incoming vault
-        deposit_up_to_limit: None,
-    };
-    let mut data_vec = MARGINFI_LENDING_ACCOUNT_DEPOSIT_DISCRIMINATOR.to_vec();
-    data_vec.extend_from_slice(
-        &ix_data_args
-            .try_to_vec()
-            .map_err(|_| ProgramError::InvalidInstructionData)?,
-    );
-
-    let mut cpi_account_metas = vec![
-        AccountMeta::new(*marginfi_cpi_accts.marginfi_group.key, false),
-        AccountMeta::new(*marginfi_cpi_accts.marginfi_account.key, false),
-        AccountMeta::new(*authority.as_ref().key, true), 
-        AccountMeta::new(*marginfi_cpi_accts.marginfi_bank.key, false),
-        AccountMeta::new(*source.key, false),
-        AccountMeta::new(*marginfi_cpi_accts.marginfi_liquidity_vault.key, false),
-        AccountMeta::new_readonly(*token_program.key, false),
-    ];
-    if let Some(mint_ai) = &mint {
-        cpi_account_metas.push(AccountMeta::new_readonly(*mint_ai.as_ref().key, false));
-        //add mint account for token 22 accounts
-    }
-
-    let instruction = Instruction {
-        program_id: MARGINFI_PROGRAM_ID,
-        accounts: cpi_account_metas,
-        data: data_vec,
-    };
-
-    let mut cpi_account_infos = vec![
-        marginfi_cpi_accts.marginfi_group.as_ref().clone(),
-        marginfi_cpi_accts.marginfi_account.as_ref().clone(),
-        authority.as_ref().clone(),
-        marginfi_cpi_accts.marginfi_bank.as_ref().clone(),
-        source.as_ref().clone(),
-        marginfi_cpi_accts.marginfi_liquidity_vault.as_ref().clone(),
-        token_program.as_ref().clone(),
-    ];
-
-    // Add mint account info if provided
-    if let Some(mint_ai) = &mint {
-        cpi_account_infos.push(mint_ai.as_ref().clone());
-    }
-
-    invoke_signed(&instruction, &cpi_account_infos, authority_pda_seeds).map_err(|_e| {
-        trace!("MarginFi Deposit CPI failed: {:?}", _e);
-        NixError::MarginfiCpiFailed.into()
-    })
+    MarginfiCpiBuilder::new(
+        "Deposit",
+        MARGINFI_LENDING_ACCOUNT_DEPOSIT_DISCRIMINATOR,
+        &MfiLendingAccountDepositData {
+            amount,
+            deposit_up_to_limit: None,
+        },
+        scratch,
+    )?
+    .lending_accounts(
+        marginfi_cpi_accts.marginfi_group.as_ref(),
+        marginfi_cpi_accts.marginfi_account.as_ref(),
+        &authority,
+        marginfi_cpi_accts.marginfi_bank.as_ref(),
+    )
+    .writable(source.as_ref())
+    .writable(marginfi_cpi_accts.marginfi_liquidity_vault.as_ref())
+    .readonly(token_program.as_ref())
+    .mint(mint)
+    .transfer_hook_accounts(
+        mint,
+        source.as_ref(),
+        marginfi_cpi_accts.marginfi_liquidity_vault.as_ref(),
+        authority.as_ref(),
+        amount,
+        accounts,
+    )?
+    .invoke_signed(authority_pda_seeds)
 }
 
 // CPI to MarginFi: Borrow
@@ -329,16 +554,16 @@ pub fn cpi_marginfi_borrow<'a, 'info>(
     global_trade_accounts_opts: &[Option<GlobalTradeAccounts<'a, 'info>>; 2],
     amount: u64,
     mint: Option<&MintAccountInfo<'a, 'info>>,
-    authority: MarketSigner<'a,'info>,
+    authority: MarketSigner<'a, 'info>,
+    scratch: &mut MarginfiCpiScratch<'info>,
     authority_pda_seeds: &[&[&[u8]]],
     accounts: &'a [AccountInfo<'a>],
 ) -> ProgramResult
 where
     'a: 'info,
 {
-    //we're borrowing base tokens from quote account (with quote vault as authority)
-    // let base_global_trade_accounts_opts = global_trade_accounts_opts[0];
-    // let quote_global_trade_accounts_opts = global_trade_accounts_opts[1];
+    // Borrows base from the marginfi account of the quote side into the base
+    // vault.
     let destination = &global_trade_accounts_opts[0]
         .clone()
         .unwrap()
@@ -351,118 +576,100 @@ where
         .token_program_opt
         .unwrap();
 
-    let account_iter: &mut Iter<AccountInfo<'a>> = &mut accounts.iter();
-
-    // borrow from marginfi quote account into base vault
     let base_marginfi_cpi_accts = marginfi_cpi_accounts_opts[0].as_ref().unwrap();
     let quote_marginfi_cpi_accts = marginfi_cpi_accounts_opts[1].as_ref().unwrap();
 
-    trace!("CPI: MarginFi Deposit amount {}", amount);
-    let ix_data_args = MfiLendingAccountBorrowData { amount };
-    let mut data_vec = MARGINFI_LENDING_ACCOUNT_BORROW_DISCRIMINATOR.to_vec();
-    data_vec.extend_from_slice(
-        &ix_data_args
-            .try_to_vec()
-            .map_err(|_| ProgramError::InvalidInstructionData)?,
-    );
-
-    let mut cpi_account_metas = vec![
-        AccountMeta::new(*base_marginfi_cpi_accts.marginfi_group.key, false),
-        AccountMeta::new(*quote_marginfi_cpi_accts.marginfi_account.key, false),
-        AccountMeta::new(*authority.as_ref().key, true), //authority
-        AccountMeta::new(*base_marginfi_cpi_accts.marginfi_bank.key, false), //debt bank
-        AccountMeta::new(*destination.key, false), //destination for borrowed tokens
-        AccountMeta::new(
-            *base_marginfi_cpi_accts
-                .marginfi_liquidity_vault_authority
-                .key,
-            false,
-        ),
-        AccountMeta::new(*base_marginfi_cpi_accts.marginfi_liquidity_vault.key, false),
-        AccountMeta::new_readonly(*token_program.key, false),
-    ];
-    if let Some(mint_ai) = &mint {
-        cpi_account_metas.push(AccountMeta::new_readonly(*mint_ai.as_ref().key, false));
-        //add mint account for token 22 accounts
-    }
-
-    let instruction = Instruction {
-        program_id: MARGINFI_PROGRAM_ID,
-        accounts: cpi_account_metas,
-        data: data_vec,
-    };
-
-    let mut cpi_account_infos = vec![
-        base_marginfi_cpi_accts.marginfi_group.as_ref().clone(),
-        quote_marginfi_cpi_accts.marginfi_account.as_ref().clone(),
-        authority.as_ref().clone(),
-        base_marginfi_cpi_accts.marginfi_bank.as_ref().clone(),
-        destination.as_ref().clone(),
-        base_marginfi_cpi_accts
-            .marginfi_liquidity_vault_authority
-            .clone(),
-        base_marginfi_cpi_accts
-            .marginfi_liquidity_vault
-            .as_ref()
-            .clone(),
-        token_program.as_ref().clone(),
-    ];
-
-    // Add mint account info if provided
-    if let Some(mint_ai) = &mint {
-        cpi_account_infos.push(mint_ai.as_ref().clone());
-    }
-
-    let base_bank_fixed = base_marginfi_cpi_accts.marginfi_bank.get_fixed()?;
-    let base_num_oracle_ais = match base_bank_fixed.config.oracle_setup {
-        OracleSetup::StakedWithPythPush => 3,
-        _ => 1,
-    };
-    let base_expected_oracle_keys = base_bank_fixed.config.oracle_keys;
-
-    let quote_bank_fixed = quote_marginfi_cpi_accts.marginfi_bank.get_fixed()?;
-    let quote_num_oracle_ais = match quote_bank_fixed.config.oracle_setup {
-        OracleSetup::StakedWithPythPush => 3,
-        _ => 1,
-    };
-    let quote_expected_oracle_keys = quote_bank_fixed.config.oracle_keys;
-
-    let mut base_oracle_accounts = Vec::with_capacity(base_num_oracle_ais);
-    for i in 0..base_num_oracle_ais {
-        let account = next_account_info(account_iter)?;
+    trace!("CPI: MarginFi Borrow amount {}", amount);
+    MarginfiCpiBuilder::new(
+        "Borrow",
+        MARGINFI_LENDING_ACCOUNT_BORROW_DISCRIMINATOR,
+        &MfiLendingAccountBorrowData { amount },
+        scratch,
+    )?
+    .lending_accounts(
+        base_marginfi_cpi_accts.marginfi_group.as_ref(),
+        quote_marginfi_cpi_accts.marginfi_account.as_ref(),
+        &authority,
+        base_marginfi_cpi_accts.marginfi_bank.as_ref(),
+    )
+    .writable(destination.as_ref())
+    .writable(base_marginfi_cpi_accts.marginfi_liquidity_vault_authority)
+    .writable(base_marginfi_cpi_accts.marginfi_liquidity_vault.as_ref())
+    .readonly(token_program.as_ref())
+    .mint(mint)
+    .health_check_accounts(
+        &quote_marginfi_cpi_accts.marginfi_account,
+        base_marginfi_cpi_accts.marginfi_bank.key,
+        accounts,
+    )?
+    .transfer_hook_accounts(
+        mint,
+        base_marginfi_cpi_accts.marginfi_liquidity_vault.as_ref(),
+        destination.as_ref(),
+        base_marginfi_cpi_accts.marginfi_liquidity_vault_authority,
+        amount,
+        accounts,
+    )?
+    .invoke_signed(authority_pda_seeds)
+}
 
+/// Assembles the health check accounts marginfi expects after the fixed
+/// accounts of a borrow or withdraw: the bank of every balance that is active
+/// once the instruction lands, in balance order, each followed by its oracle
+/// accounts. `target_bank` takes the first free balance slot if the account
+/// has no balance there yet, like marginfi does when it opens one. Banks and
+/// oracles are looked up by key among `accounts`.
+fn get_health_check_account_infos<'a>(
+    marginfi_account: &MarginfiAccountInfo<'_, '_, MarginfiAccount>,
+    target_bank: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+) -> Result<ArrayVec<&'a AccountInfo<'a>, MAX_HEALTH_CHECK_ACCOUNTS>, ProgramError> {
+    let find_account = |key: &Pubkey| -> Result<&'a AccountInfo<'a>, ProgramError> {
+        let account_opt: Option<&'a AccountInfo<'a>> =
+            accounts.iter().find(|account| account.key == key);
         require!(
-            base_expected_oracle_keys[i] == *account.key,
+            account_opt.is_some(),
             NixError::InvalidOracleAccount,
-            "Invalid Oracle Account >> expected: {:?}, actual: {:?}",
-            base_expected_oracle_keys[i],
-            account.key
+            "Missing health check account {}",
+            key,
         )?;
-        base_oracle_accounts.push(account.clone());
-    }
+        Ok(account_opt.unwrap())
+    };
 
-    let mut quote_oracle_accounts = Vec::with_capacity(quote_num_oracle_ais);
-    for i in 0..quote_num_oracle_ais {
-        let account = next_account_info(account_iter)?;
+    let bank_keys: ArrayVec<Pubkey, MAX_LENDING_ACCOUNT_BALANCES> = {
+        let marginfi_account_fixed: Ref<MarginfiAccount> = marginfi_account.get_fixed()?;
+        let balances = &marginfi_account_fixed.lending_account.balances;
+        let mut is_target_pending: bool = !balances
+            .iter()
+            .any(|balance| balance.active != 0 && balance.bank_pk == *target_bank);
+        let mut bank_keys: ArrayVec<Pubkey, MAX_LENDING_ACCOUNT_BALANCES> = ArrayVec::new();
+        for balance in balances.iter() {
+            if balance.active != 0 {
+                bank_keys.push(balance.bank_pk);
+            } else if is_target_pending {
+                bank_keys.push(*target_bank);
+                is_target_pending = false;
+            }
+        }
+        bank_keys
+    };
 
-        require!(
-            quote_expected_oracle_keys[i] == *account.key,
-            NixError::InvalidOracleAccount,
-            "Invalid Oracle Account >> expected: {:?}, actual: {:?}",
-            quote_expected_oracle_keys[i],
-            account.key
-        )?;
-        quote_oracle_accounts.push(account.clone());
+    let mut account_infos: ArrayVec<&'a AccountInfo<'a>, MAX_HEALTH_CHECK_ACCOUNTS> =
+        ArrayVec::new();
+    for bank_key in bank_keys.iter() {
+        let bank_info: &'a AccountInfo<'a> = find_account(bank_key)?;
+        let bank: MarginfiAccountInfo<Bank> = MarginfiAccountInfo::new_bank(bank_info)?;
+        let bank_fixed: Ref<Bank> = bank.get_fixed()?;
+        let num_oracle_ais: usize = match bank_fixed.config.oracle_setup {
+            OracleSetup::StakedWithPythPush => 3,
+            _ => 1,
+        };
+        account_infos.push(bank_info);
+        for oracle_key in bank_fixed.config.oracle_keys[..num_oracle_ais].iter() {
+            account_infos.push(find_account(oracle_key)?);
+        }
     }
-    cpi_account_infos.push(base_marginfi_cpi_accts.marginfi_bank.as_ref().clone());
-    cpi_account_infos.extend_from_slice(&base_oracle_accounts);
-    cpi_account_infos.push(quote_marginfi_cpi_accts.marginfi_bank.as_ref().clone());
-    cpi_account_infos.extend_from_slice(&quote_oracle_accounts);
-
-    invoke_signed(&instruction, &cpi_account_infos, authority_pda_seeds).map_err(|_e| {
-        trace!("MarginFi Deposit CPI failed: {:?}", _e);
-        NixError::MarginfiCpiFailed.into()
-    })
+    Ok(account_infos)
 }
 
 // CPI to MarginFi: withdraw
@@ -471,7 +678,8 @@ pub fn cpi_marginfi_withdraw<'a, 'info>(
     global_trade_accounts_opts: &[Option<GlobalTradeAccounts<'a, 'info>>; 2],
     amount: u64,
     mint: Option<&MintAccountInfo<'a, 'info>>,
-    authority: MarketSigner<'a,'info>,
+    authority: MarketSigner<'a, 'info>,
+    scratch: &mut MarginfiCpiScratch<'info>,
     authority_pda_seeds: &[&[&[u8]]],
     accounts: &'a [AccountInfo<'a>],
 ) -> ProgramResult
@@ -491,188 +699,168 @@ where
         .token_program_opt
         .unwrap();
 
-    let account_iter: &mut Iter<AccountInfo<'a>> = &mut accounts.iter();
-
-    let base_marginfi_cpi_accts = marginfi_cpi_accounts_opts[0].as_ref().unwrap();
-    let quote_marginfi_cpi_accts = marginfi_cpi_accounts_opts[1].as_ref().unwrap();
-    trace!("CPI: MarginFi Deposit amount {}", amount);
-    let ix_data_args = MfiLendingAccountWithdrawData {
+    cpi_marginfi_withdraw_to(
+        marginfi_cpi_accounts_opts[0].as_ref().unwrap(),
+        destination,
+        &token_program,
         amount,
-        withdraw_all: None,
-    };
-
-    // CRITICAL BUG FIX: Use correct withdraw discriminator instead of borrow
-    // OLD: let mut data_vec = MARGINFI_LENDING_ACCOUNT_BORROW_DISCRIMINATOR.to_vec();
-    let mut data_vec = MARGINFI_LENDING_ACCOUNT_WITHDRAW_DISCRIMINATOR.to_vec();
-    data_vec.extend_from_slice(
-        &ix_data_args
-            .try_to_vec()
-            .map_err(|_| ProgramError::InvalidInstructionData)?,
-    );
-
-    let mut cpi_account_metas = vec![
-        AccountMeta::new(*base_marginfi_cpi_accts.marginfi_group.key, false),
-        AccountMeta::new(*base_marginfi_cpi_accts.marginfi_account.key, false),
-        AccountMeta::new(*authority.as_ref().key, true), //authority
-        AccountMeta::new(*base_marginfi_cpi_accts.marginfi_bank.key, false), //debt bank
-        AccountMeta::new(*destination.key, false), //destination for borrowed tokens
-        AccountMeta::new(
-            *base_marginfi_cpi_accts
-                .marginfi_liquidity_vault_authority
-                .key,
-            false,
-        ),
-        AccountMeta::new(*base_marginfi_cpi_accts.marginfi_liquidity_vault.key, false),
-        AccountMeta::new_readonly(*token_program.key, false),
-    ];
-    if let Some(mint_ai) = &mint {
-        cpi_account_metas.push(AccountMeta::new_readonly(*mint_ai.as_ref().key, false));
-        //add mint account for token 22 accounts
-    }
-
-    let instruction = Instruction {
-        program_id: MARGINFI_PROGRAM_ID,
-        accounts: cpi_account_metas,
-        data: data_vec,
-    };
-
-    let mut cpi_account_infos = vec![
-        base_marginfi_cpi_accts.marginfi_group.as_ref().clone(),
-        quote_marginfi_cpi_accts.marginfi_account.as_ref().clone(),
-        authority.as_ref().clone(),
-        base_marginfi_cpi_accts.marginfi_bank.as_ref().clone(),
-        destination.as_ref().clone(),
-        base_marginfi_cpi_accts
-            .marginfi_liquidity_vault_authority
-            .clone(),
-        base_marginfi_cpi_accts
-            .marginfi_liquidity_vault
-            .as_ref()
-            .clone(),
-        token_program.as_ref().clone(),
-    ];
-
-    // Add mint account info if provided
-    if let Some(mint_ai) = &mint {
-        cpi_account_infos.push(mint_ai.as_ref().clone());
-    }
-    let base_bank_fixed = base_marginfi_cpi_accts.marginfi_bank.get_fixed()?;
-    let base_num_oracle_ais = match base_bank_fixed.config.oracle_setup {
-        OracleSetup::StakedWithPythPush => 3,
-        _ => 1,
-    };
-    let base_expected_oracle_keys = base_bank_fixed.config.oracle_keys;
-
-    let quote_bank_fixed = quote_marginfi_cpi_accts.marginfi_bank.get_fixed()?;
-    let quote_num_oracle_ais = match quote_bank_fixed.config.oracle_setup {
-        OracleSetup::StakedWithPythPush => 3,
-        _ => 1,
-    };
-    let quote_expected_oracle_keys = quote_bank_fixed.config.oracle_keys;
-
-    let mut base_oracle_accounts = Vec::with_capacity(base_num_oracle_ais);
-    for i in 0..base_num_oracle_ais {
-        let account = next_account_info(account_iter)?;
-
-        require!(
-            base_expected_oracle_keys[i] == *account.key,
-            NixError::InvalidOracleAccount,
-            "Invalid Oracle Account >> expected: {:?}, actual: {:?}",
-            base_expected_oracle_keys[i],
-            account.key
-        )?;
-        base_oracle_accounts.push(account.clone());
-    }
-
-    let mut quote_oracle_accounts = Vec::with_capacity(quote_num_oracle_ais);
-    for i in 0..quote_num_oracle_ais {
-        let account = next_account_info(account_iter)?;
+        mint,
+        authority,
+        scratch,
+        authority_pda_seeds,
+        accounts,
+    )
+}
 
-        require!(
-            quote_expected_oracle_keys[i] == *account.key,
-            NixError::InvalidOracleAccount,
-            "Invalid Oracle Account >> expected: {:?}, actual: {:?}",
-            quote_expected_oracle_keys[i],
-            account.key
-        )?;
-        quote_oracle_accounts.push(account.clone());
-    }
-    cpi_account_infos.push(base_marginfi_cpi_accts.marginfi_bank.as_ref().clone());
-    cpi_account_infos.extend_from_slice(&base_oracle_accounts);
-    cpi_account_infos.push(quote_marginfi_cpi_accts.marginfi_bank.as_ref().clone());
-    cpi_account_infos.extend_from_slice(&quote_oracle_accounts);
-
-    invoke_signed(&instruction, &cpi_account_infos, authority_pda_seeds).map_err(|_e| {
-        trace!("MarginFi Deposit CPI failed: {:?}", _e);
-        NixError::MarginfiCpiFailed.into()
-    })
+// CPI to MarginFi: withdraw into a market vault, or the token account of a
+// trader leaving a global
+pub fn cpi_marginfi_withdraw_to<'a, 'info>(
+    base_marginfi_cpi_accts: &MarginfiCpiAccounts<'a, 'info>,
+    destination: &TokenAccountInfo<'a, 'info>,
+    token_program: &TokenProgram<'a, 'info>,
+    amount: u64,
+    mint: Option<&MintAccountInfo<'a, 'info>>,
+    authority: impl AsRef<AccountInfo<'info>>,
+    scratch: &mut MarginfiCpiScratch<'info>,
+    authority_pda_seeds: &[&[&[u8]]],
+    accounts: &'a [AccountInfo<'a>],
+) -> ProgramResult
+where
+    'a: 'info,
+{
+    trace!("CPI: MarginFi Withdraw amount {}", amount);
+    MarginfiCpiBuilder::new(
+        "Withdraw",
+        MARGINFI_LENDING_ACCOUNT_WITHDRAW_DISCRIMINATOR,
+        &MfiLendingAccountWithdrawData {
+            amount,
+            withdraw_all: None,
+        },
+        scratch,
+    )?
+    .lending_accounts(
+        base_marginfi_cpi_accts.marginfi_group.as_ref(),
+        base_marginfi_cpi_accts.marginfi_account.as_ref(),
+        &authority,
+        base_marginfi_cpi_accts.marginfi_bank.as_ref(),
+    )
+    .writable(destination.as_ref())
+    .writable(base_marginfi_cpi_accts.marginfi_liquidity_vault_authority)
+    .writable(base_marginfi_cpi_accts.marginfi_liquidity_vault.as_ref())
+    .readonly(token_program.as_ref())
+    .mint(mint)
+    .health_check_accounts(
+        &base_marginfi_cpi_accts.marginfi_account,
+        base_marginfi_cpi_accts.marginfi_bank.key,
+        accounts,
+    )?
+    .transfer_hook_accounts(
+        mint,
+        base_marginfi_cpi_accts.marginfi_liquidity_vault.as_ref(),
+        destination.as_ref(),
+        base_marginfi_cpi_accts.marginfi_liquidity_vault_authority,
+        amount,
+        accounts,
+    )?
+    .invoke_signed(authority_pda_seeds)
 }
 
-// CPI to MarginFi: Repay
+// CPI to MarginFi: Repay with everything in source
 pub fn cpi_marginfi_repay<'a, 'info>(
     marginfi_cpi_accts: &MarginfiCpiAccounts<'a, 'info>,
-    authority: MarketSigner<'a,'info>,
+    authority: MarketSigner<'a, 'info>,
     source: &'a TokenAccountInfo<'a, 'info>,
     token_program: &TokenProgram<'a, 'info>,
     mint: Option<&MintAccountInfo<'a, 'info>>,
+    scratch: &mut MarginfiCpiScratch<'info>,
+    authority_pda_seeds: &[&[&[u8]]],
+    accounts: &'a [AccountInfo<'a>],
+) -> ProgramResult
+where
+    'a: 'info,
+{
+    let amount: u64 = source.get_balance();
+    trace!("CPI: MarginFi Repay amount {}", amount);
+    MarginfiCpiBuilder::new(
+        "Repay",
+        MARGINFI_LENDING_ACCOUNT_REPAY_DISCRIMINATOR,
+        &MfiLendingAccountRepayData {
+            amount,
+            repay_all: None,
+        },
+        scratch,
+    )?
+    .lending_accounts(
+        marginfi_cpi_accts.marginfi_group.as_ref(),
+        marginfi_cpi_accts.marginfi_account.as_ref(),
+        &authority,
+        marginfi_cpi_accts.marginfi_bank.as_ref(),
+    )
+    .writable(source.as_ref())
+    .writable(marginfi_cpi_accts.marginfi_liquidity_vault.as_ref())
+    .readonly(token_program.as_ref())
+    .mint(mint)
+    .transfer_hook_accounts(
+        mint,
+        source.as_ref(),
+        marginfi_cpi_accts.marginfi_liquidity_vault.as_ref(),
+        authority.as_ref(),
+        amount,
+        accounts,
+    )?
+    .invoke_signed(authority_pda_seeds)
+}
+
+// CPI to MarginFi: Withdraw the emissions the marginfi account earned on a
+// bank. Marginfi checks the emissions auth and vault against the bank.
+pub fn cpi_marginfi_withdraw_emissions<'a, 'info>(
+    marginfi_cpi_accts: &MarginfiCpiAccounts<'a, 'info>,
+    authority: MarketSigner<'a, 'info>,
+    emissions_mint: &MintAccountInfo<'a, 'info>,
+    emissions_auth: &AccountInfo<'info>,
+    emissions_vault: &AccountInfo<'info>,
+    destination: &TokenAccountInfo<'a, 'info>,
+    token_program: &TokenProgram<'a, 'info>,
     authority_pda_seeds: &[&[&[u8]]],
 ) -> ProgramResult {
-    trace!("CPI: MarginFi Deposit amount {}", amount);
-    // CRITICAL BUG FIX: Use correct repay data structure and discriminator
-    // OLD: let ix_data_args = MfiLendingAccountBorrowData {
-    //     amount: source.get_balance(), //incoming vault
-    // };
-    let ix_data_args = MfiLendingAccountRepayData {
-        amount: source.get_balance(), //incoming vault
-        repay_all: None,
-    };
-    // OLD: let mut data_vec = MARGINFI_LENDING_ACCOUNT_BORROW_DISCRIMINATOR.to_vec();
-    let mut data_vec = MARGINFI_LENDING_ACCOUNT_REPAY_DISCRIMINATOR.to_vec();
-    data_vec.extend_from_slice(
-        &ix_data_args
-            .try_to_vec()
-            .map_err(|_| ProgramError::InvalidInstructionData)?,
+    trace!(
+        "CPI: MarginFi Withdraw emissions {:?}",
+        emissions_mint.info.key
     );
+    MarginfiCpiBuilder::new(
+        "WithdrawEmissions",
+        MARGINFI_LENDING_ACCOUNT_WITHDRAW_EMISSION,
+        &MfiLendingAccountWithdrawEmissionsData {},
+        &mut MarginfiCpiScratch::default(),
+    )?
+    .lending_accounts(
+        marginfi_cpi_accts.marginfi_group.as_ref(),
+        marginfi_cpi_accts.marginfi_account.as_ref(),
+        &authority,
+        marginfi_cpi_accts.marginfi_bank.as_ref(),
+    )
+    .writable(emissions_mint.as_ref())
+    .readonly(emissions_auth)
+    .writable(emissions_vault)
+    .writable(destination.as_ref())
+    .readonly(token_program.as_ref())
+    .invoke_signed(authority_pda_seeds)
+}
 
-    let mut cpi_account_metas = vec![
-        AccountMeta::new(*marginfi_cpi_accts.marginfi_group.key, false),
-        AccountMeta::new(*marginfi_cpi_accts.marginfi_account.key, false),
-        AccountMeta::new(*authority.as_ref().key, true), //authority
-        AccountMeta::new(*marginfi_cpi_accts.marginfi_bank.key, false),
-        AccountMeta::new(*source.key, false), //source token account
-        AccountMeta::new(*marginfi_cpi_accts.marginfi_liquidity_vault.key, false),
-        AccountMeta::new_readonly(*token_program.key, false),
-    ];
-    if let Some(mint_ai) = &mint {
-        cpi_account_metas.push(AccountMeta::new_readonly(*mint_ai.as_ref().key, false));
-        //add mint account for token 22 accounts
-    }
-
-    let instruction = Instruction {
-        program_id: MARGINFI_PROGRAM_ID,
-        accounts: cpi_account_metas,
-        data: data_vec,
-    };
-
-    let mut cpi_account_infos = vec![
-        marginfi_cpi_accts.marginfi_group.as_ref().clone(),
-        marginfi_cpi_accts.marginfi_account.as_ref().clone(),
-        authority.as_ref().clone(),
-        marginfi_cpi_accts.marginfi_bank.as_ref().clone(),
-        source.as_ref().clone(),
-        marginfi_cpi_accts.marginfi_liquidity_vault.as_ref().clone(),
-        token_program.as_ref().clone(),
-    ];
-
-    // Add mint account info if provided
-    if let Some(mint_ai) = &mint {
-        cpi_account_infos.push(mint_ai.as_ref().clone());
-    }
-
-    invoke_signed(&instruction, &cpi_account_infos, authority_pda_seeds).map_err(|_e| {
-        trace!("MarginFi Deposit CPI failed: {:?}", _e);
-        NixError::MarginfiCpiFailed.into()
-    })
+/// Asset shares the marginfi account holds in the bank, zero without an
+/// active balance there. Read around a CPI to see what it minted or burned.
+pub fn get_marginfi_asset_shares(
+    marginfi_cpi_accts: &MarginfiCpiAccounts<'_, '_>,
+) -> Result<I80F48, ProgramError> {
+    Ok(marginfi_cpi_accts
+        .marginfi_account
+        .get_fixed()?
+        .lending_account
+        .balances
+        .iter()
+        .find(|b| b.active != 0 && b.bank_pk == *marginfi_cpi_accts.marginfi_bank.key)
+        .map(|b| I80F48::from(b.asset_shares))
+        .unwrap_or_default())
 }
 
 pub fn get_oracle_price<'a>(
@@ -682,113 +870,371 @@ pub fn get_oracle_price<'a>(
     price_bias: Option<PriceBias>,
     oracle_price_type: OraclePriceType,
 ) -> Result<I80F48, ProgramError> {
-    let adapter =
-        OraclePriceFeedAdapter::try_from_bank_config(bank_config, oracle_accounts, clock)?;
+    let adapter = OraclePriceFeedAdapter::try_from_bank_config(bank_config, oracle_accounts, clock)
+        .map_err(|err| NixError::from(OracleError(err.into())))?;
 
-    let price = adapter.get_price_of_type(oracle_price_type, price_bias, bank_config.oracle_max_confidence)?;
+    let price = adapter
+        .get_price_of_type(oracle_price_type, price_bias, bank_config.oracle_max_confidence)
+        .map_err(|err| NixError::from(OracleError(err.into())))?;
     Ok(price)
 }
 
-/// Converts token amount to asset shares
-pub fn convert_tokens_to_asset_shares(
-    token_amount: u64,
-    bank: &Bank,
-) -> Result<I80F48, ProgramError> {
-    I80F48::from_num(token_amount)
-        .checked_div(I80F48::from(bank.asset_share_value))
-        .ok_or(NixError::NumericalOverflow.into())
+/// Oracle adapters built so far in one instruction, by bank. Building an
+/// adapter deserializes the Pyth or Switchboard account, so a bank that is
+/// priced more than once, like the base bank of a bid that is also in the
+/// health check, only pays for it the first time.
+pub struct OraclePrices<'a> {
+    oracle_accounts: &'a [AccountInfo<'a>],
+    clock: Clock,
+    adapters: Vec<(Pubkey, OraclePriceFeedAdapter)>,
 }
 
-pub fn convert_asset_shares_to_tokens(
-    asset_shares: I80F48,
-    bank: &Bank,
-) -> Result<u64, ProgramError> {
-    Ok(asset_shares
-        .checked_mul(I80F48::from(bank.asset_share_value))
-        .ok_or(NixError::NumericalOverflow)?
-        .checked_floor()
-        .ok_or(NixError::NumericalOverflow)?
-        .to_num::<u64>())
+impl<'a> OraclePrices<'a> {
+    pub fn new(oracle_accounts: &'a [AccountInfo<'a>], clock: Clock) -> Self {
+        OraclePrices {
+            oracle_accounts,
+            clock,
+            adapters: Vec::new(),
+        }
+    }
+
+    /// Same as get_oracle_price, with the adapter reused across calls for
+    /// the same bank.
+    pub fn get_price(
+        &mut self,
+        bank_key: &Pubkey,
+        bank_config: &BankConfig,
+        price_bias: Option<PriceBias>,
+        oracle_price_type: OraclePriceType,
+    ) -> Result<I80F48, ProgramError> {
+        let index: usize = match self.adapters.iter().position(|(key, _)| key == bank_key) {
+            Some(index) => index,
+            None => {
+                // The adapter only matches push accounts to the oracle keys
+                // of the bank, so a misconfigured bank could point anywhere.
+                if matches!(
+                    bank_config.oracle_setup,
+                    OracleSetup::PythPushOracle | OracleSetup::StakedWithPythPush
+                ) {
+                    let oracle_key: &Pubkey = &bank_config.oracle_keys[0];
+                    let oracle_account_opt: Option<&AccountInfo> = self
+                        .oracle_accounts
+                        .iter()
+                        .find(|account| account.key == oracle_key);
+                    require!(
+                        oracle_account_opt.is_some(),
+                        NixError::InvalidOracleAccount,
+                        "Missing Pyth price account {} of bank {}",
+                        oracle_key,
+                        bank_key,
+                    )?;
+                    validate_pyth_push_owner(oracle_account_opt.unwrap())?;
+                }
+                let adapter: OraclePriceFeedAdapter = OraclePriceFeedAdapter::try_from_bank_config(
+                    bank_config,
+                    self.oracle_accounts,
+                    &self.clock,
+                )
+                .map_err(|err| NixError::from(OracleError(err.into())))?;
+                self.adapters.push((*bank_key, adapter));
+                self.adapters.len() - 1
+            }
+        };
+        let price: I80F48 = self.adapters[index]
+            .1
+            .get_price_of_type(
+                oracle_price_type,
+                price_bias,
+                bank_config.oracle_max_confidence,
+            )
+            .map_err(|err| NixError::from(OracleError(err.into())))?;
+        Ok(price)
+    }
+
+    /// Same as get_price, for 10^decimals atoms of the mint instead of a UI
+    /// token. They differ for interest-bearing and scaled-ui mints.
+    pub fn get_price_of_atoms(
+        &mut self,
+        bank_key: &Pubkey,
+        bank_config: &BankConfig,
+        mint: &MintAccountInfo,
+        price_bias: Option<PriceBias>,
+        oracle_price_type: OraclePriceType,
+    ) -> Result<I80F48, ProgramError> {
+        let price: I80F48 = self.get_price(bank_key, bank_config, price_bias, oracle_price_type)?;
+        price
+            .checked_mul(get_ui_amount_multiplier(mint, self.clock.unix_timestamp)?)
+            .ok_or_else(|| NixError::NumericalOverflow.into())
+    }
 }
 
-pub fn get_required_quote_collateral_to_back_loan<'a, 'info>(
-    base_marginfi_bank: &'a Bank,
-    quote_marginfi_bank: &'a Bank,
-    base_oracle_price_usd: I80F48,
-    quote_oracle_price_usd: I80F48,
-    buffer_f: I80F48,
-    num_base_atoms: u64,
-) -> Result<u64, ProgramError> {
-    // Calculate effective collateral weight by applying buffer
-    let effective_quote_collateral_weight =
-        I80F48::from(quote_marginfi_bank.config.asset_weight_init)
-            .checked_mul(buffer_f)
-            .ok_or(NixError::NumericalOverflow)?;
-
-    // Convert base tokens to USD value == loan value usd
-    let base_value_usd = I80F48::from_num(num_base_atoms)
-        .checked_mul(base_oracle_price_usd)
-        .ok_or(NixError::NumericalOverflow)?
-        .checked_div(EXP_10_I80F48[base_marginfi_bank.mint_decimals as usize])
-        .ok_or(NixError::NumericalOverflow)?;
-
-    // Calculate required collateral value in USD
-    // Formula: (base_value_usd * liability_weight) / effective_collateral_weight
-    let required_quote_collateral_value_usd = base_value_usd
-        .checked_mul(I80F48::from(
-            base_marginfi_bank.config.liability_weight_init,
-        ))
-        .ok_or(NixError::NumericalOverflow)?
-        .checked_div(effective_quote_collateral_weight)
-        .ok_or(NixError::NumericalOverflow)?;
-
-    // Convert USD value to quote token amount
-    let required_collateral_tokens_i80f48 = required_quote_collateral_value_usd
-        .checked_mul(EXP_10_I80F48[quote_marginfi_bank.mint_decimals as usize])
-        .ok_or(NixError::NumericalOverflow)?
-        .checked_div(quote_oracle_price_usd)
-        .ok_or(NixError::NumericalOverflow)?;
-
-    // Convert to u64 and round up to ensure sufficient collateral
-    let required_collateral_tokens = required_collateral_tokens_i80f48
-        .checked_ceil()
-        .ok_or(NixError::NumericalOverflow)?
-        .to_num::<u64>();
-    Ok(required_collateral_tokens)
+/// Hash of the bank config fields the collateral math and oracle reads of
+/// nix depend on: the weights, the oracle setup, keys, max age and max
+/// confidence. Marginfi can change them under a live market, see
+/// MarketFixed::get_bank_config_hash. Never zero, markets read zero as not
+/// recorded yet.
+pub fn get_bank_config_hash(bank_config: &BankConfig) -> u64 {
+    let mut hasher = Sha256::new();
+    for weight in [
+        bank_config.asset_weight_init,
+        bank_config.asset_weight_maint,
+        bank_config.liability_weight_init,
+        bank_config.liability_weight_maint,
+    ] {
+        hasher.update(I80F48::from(weight).to_le_bytes());
+    }
+    hasher.update([bank_config.oracle_setup as u8]);
+    for oracle_key in bank_config.oracle_keys.iter() {
+        hasher.update(oracle_key.as_ref());
+    }
+    hasher.update(bank_config.oracle_max_age.to_le_bytes());
+    hasher.update(bank_config.oracle_max_confidence.to_le_bytes());
+    let hash = hasher.finalize();
+    u64::from_le_bytes(hash[0..8].try_into().unwrap()).max(1)
 }
 
-/// Returns the amount of tokens required to repay a given amount of liability shares.
-pub fn get_token_amount_to_repay_liability_shares(
-    liability_shares: I80F48,
-    bank: &Bank,
-) -> Result<u64, ProgramError> {
-    let liability_share_value: I80F48 = bank.liability_share_value.into();
-
-    // Calculate the liability amount (the actual token value of the debt)
-    let liability_amount_i80f48 = liability_shares
-        .checked_mul(liability_share_value)
-        .ok_or(NixError::NumericalOverflow)?; // Assuming NixError is your custom error type
-
-    // Round up to the nearest whole token unit and convert to u64 
-    //checked_ceil here would round up in favour of the maker to ensure at least the actual repay amount is obtained  
-    let repay_amount = liability_amount_i80f48
-        .checked_ceil()
-        .ok_or(NixError::NumericalOverflow)?
-        .to_num::<u64>();
-
-    Ok(repay_amount)
+impl From<&Bank> for BankShareValues {
+    fn from(bank: &Bank) -> Self {
+        BankShareValues {
+            asset_share_value: bank.asset_share_value.into(),
+            liability_share_value: bank.liability_share_value.into(),
+            asset_weight_init: bank.config.asset_weight_init.into(),
+            liability_weight_init: bank.config.liability_weight_init.into(),
+            total_asset_shares: bank.total_asset_shares.into(),
+            mint_decimals: bank.mint_decimals,
+        }
+    }
 }
-/// Converts a token amount to liability shares for a given bank.
-/// This is the inverse of `get_token_amount_to_repay_base_liability_shares`.
-pub fn convert_tokens_to_liability_shares(
-    token_amount: u64,
-    bank: &Bank,
+
+/// Initial health of a marginfi account in USD, the way marginfi weighs it
+/// when opening a borrow: assets at asset_weight_init and the low price, less
+/// liabilities at liability_weight_init and the high price. Banks and oracles
+/// of every active balance are looked up by key among `accounts`, and priced
+/// through `oracle_prices` so banks the instruction already priced are not
+/// read again.
+pub fn get_marginfi_account_health_usd<'a>(
+    marginfi_account: &AccountInfo,
+    accounts: &'a [AccountInfo<'a>],
+    oracle_prices: &mut OraclePrices<'a>,
 ) -> Result<I80F48, ProgramError> {
-    let liability_share_value: I80F48 = bank.liability_share_value.into();
+    let balances: Vec<(Pubkey, I80F48, I80F48)> = {
+        let data: Ref<&mut [u8]> = marginfi_account.try_borrow_data()?;
+        let marginfi_account_fixed: &MarginfiAccount = bytemuck::from_bytes(&data[8..]);
+        marginfi_account_fixed
+            .lending_account
+            .balances
+            .iter()
+            .filter(|balance| balance.active != 0)
+            .map(|balance| {
+                (
+                    balance.bank_pk,
+                    balance.asset_shares.into(),
+                    balance.liability_shares.into(),
+                )
+            })
+            .collect()
+    };
 
-    // Convert token amount to asset shares
-    let liability_shares = I80F48::from_num(token_amount)
-        .checked_div(liability_share_value)
-        .ok_or(NixError::NumericalOverflow)?;
-    Ok(liability_shares)
+    let mut health_usd: I80F48 = I80F48::ZERO;
+    for (bank_key, asset_shares, liability_shares) in balances.iter() {
+        let bank_info_opt: Option<&AccountInfo> =
+            accounts.iter().find(|account| account.key == bank_key);
+        require!(
+            bank_info_opt.is_some(),
+            NixError::InvalidOracleAccount,
+            "Missing health check account {}",
+            bank_key,
+        )?;
+        let bank: MarginfiAccountInfo<Bank> =
+            MarginfiAccountInfo::new_bank(bank_info_opt.unwrap())?;
+        let bank_fixed: Ref<Bank> = bank.get_fixed()?;
+        let bank_share_values: BankShareValues = BankShareValues::from(&*bank_fixed);
+
+        if asset_shares.is_positive() {
+            let price_usd: I80F48 = oracle_prices.get_price(
+                bank_key,
+                &bank_fixed.config,
+                Some(PriceBias::Low),
+                OraclePriceType::TimeWeighted,
+            )?;
+            let asset_value_usd: I80F48 = get_weighted_value_usd(
+                asset_shares
+                    .checked_mul(bank_share_values.asset_share_value)
+                    .ok_or(NixError::NumericalOverflow)?,
+                bank_share_values.asset_weight_init,
+                bank_share_values.mint_decimals,
+                price_usd,
+            )?;
+            health_usd = health_usd
+                .checked_add(asset_value_usd)
+                .ok_or(NixError::NumericalOverflow)?;
+        }
+        if liability_shares.is_positive() {
+            let price_usd: I80F48 = oracle_prices.get_price(
+                bank_key,
+                &bank_fixed.config,
+                Some(PriceBias::High),
+                OraclePriceType::TimeWeighted,
+            )?;
+            let liability_value_usd: I80F48 = get_weighted_value_usd(
+                liability_shares
+                    .checked_mul(bank_share_values.liability_share_value)
+                    .ok_or(NixError::NumericalOverflow)?,
+                bank_share_values.liability_weight_init,
+                bank_share_values.mint_decimals,
+                price_usd,
+            )?;
+            health_usd = health_usd
+                .checked_sub(liability_value_usd)
+                .ok_or(NixError::NumericalOverflow)?;
+        }
+    }
+    Ok(health_usd)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const NUM_ACCOUNTS: usize = 6;
+
+    fn new_keys() -> [Pubkey; NUM_ACCOUNTS] {
+        let mut keys: [Pubkey; NUM_ACCOUNTS] = [Pubkey::default(); NUM_ACCOUNTS];
+        for key in keys.iter_mut() {
+            *key = Pubkey::new_unique();
+        }
+        keys
+    }
+
+    #[test]
+    fn test_marginfi_cpi_builder() {
+        let keys: [Pubkey; NUM_ACCOUNTS] = new_keys();
+        let owner: Pubkey = Pubkey::new_unique();
+        let mut lamports: [u64; NUM_ACCOUNTS] = [0; NUM_ACCOUNTS];
+        let mut data: [[u8; 0]; NUM_ACCOUNTS] = [[]; NUM_ACCOUNTS];
+        let accounts: Vec<AccountInfo> = keys
+            .iter()
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .map(|((key, lamports), data)| {
+                AccountInfo::new(key, false, false, lamports, data, &owner, false, 0)
+            })
+            .collect();
+
+        let mut scratch: MarginfiCpiScratch = MarginfiCpiScratch::default();
+        MarginfiCpiBuilder::new(
+            "Deposit",
+            MARGINFI_LENDING_ACCOUNT_DEPOSIT_DISCRIMINATOR,
+            &MfiLendingAccountDepositData {
+                amount: 7,
+                deposit_up_to_limit: Some(true),
+            },
+            &mut scratch,
+        )
+        .unwrap()
+        .lending_accounts(&accounts[0], &accounts[1], &accounts[2], &accounts[3])
+        .writable(&accounts[4])
+        .mint(None)
+        .readonly(&accounts[5]);
+
+        // Only the authority signs, and the infos follow the metas.
+        assert_eq!(
+            scratch.account_metas,
+            vec![
+                AccountMeta::new(keys[0], false),
+                AccountMeta::new(keys[1], false),
+                AccountMeta::new(keys[2], true),
+                AccountMeta::new(keys[3], false),
+                AccountMeta::new(keys[4], false),
+                AccountMeta::new_readonly(keys[5], false),
+            ]
+        );
+        let info_keys: Vec<Pubkey> = scratch
+            .account_infos
+            .iter()
+            .map(|account_info: &AccountInfo| *account_info.key)
+            .collect();
+        assert_eq!(info_keys, keys.to_vec());
+        let mut expected_data: Vec<u8> = MARGINFI_LENDING_ACCOUNT_DEPOSIT_DISCRIMINATOR.to_vec();
+        expected_data.extend_from_slice(&7_u64.to_le_bytes());
+        expected_data.extend_from_slice(&[1, 1]);
+        assert_eq!(scratch.data, expected_data);
+    }
+
+    #[test]
+    fn test_marginfi_cpi_scratch_reuse() {
+        let keys: [Pubkey; NUM_ACCOUNTS] = new_keys();
+        let owner: Pubkey = Pubkey::new_unique();
+        let mut lamports: [u64; NUM_ACCOUNTS] = [0; NUM_ACCOUNTS];
+        let mut data: [[u8; 0]; NUM_ACCOUNTS] = [[]; NUM_ACCOUNTS];
+        let accounts: Vec<AccountInfo> = keys
+            .iter()
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .map(|((key, lamports), data)| {
+                AccountInfo::new(key, false, false, lamports, data, &owner, false, 0)
+            })
+            .collect();
+
+        let mut scratch: MarginfiCpiScratch = MarginfiCpiScratch::default();
+        let buffers: [*const u8; 3] = [
+            scratch.account_metas.as_ptr() as *const u8,
+            scratch.account_infos.as_ptr() as *const u8,
+            scratch.data.as_ptr(),
+        ];
+        // Off chain the CPI is a stub that succeeds, which still hands the
+        // buffers to the instruction and back.
+        MarginfiCpiBuilder::new(
+            "Deposit",
+            MARGINFI_LENDING_ACCOUNT_DEPOSIT_DISCRIMINATOR,
+            &MfiLendingAccountDepositData {
+                amount: 7,
+                deposit_up_to_limit: Some(true),
+            },
+            &mut scratch,
+        )
+        .unwrap()
+        .lending_accounts(&accounts[0], &accounts[1], &accounts[2], &accounts[3])
+        .writable(&accounts[4])
+        .readonly(&accounts[5])
+        .invoke_signed(&[])
+        .unwrap();
+        assert_eq!(scratch.account_metas.len(), NUM_ACCOUNTS);
+        assert_eq!(scratch.account_infos.len(), NUM_ACCOUNTS);
+
+        // The next CPI starts from empty buffers.
+        MarginfiCpiBuilder::new(
+            "Borrow",
+            MARGINFI_LENDING_ACCOUNT_BORROW_DISCRIMINATOR,
+            &MfiLendingAccountBorrowData { amount: 9 },
+            &mut scratch,
+        )
+        .unwrap()
+        .lending_accounts(&accounts[0], &accounts[1], &accounts[2], &accounts[3]);
+        assert_eq!(
+            scratch.account_metas,
+            vec![
+                AccountMeta::new(keys[0], false),
+                AccountMeta::new(keys[1], false),
+                AccountMeta::new(keys[2], true),
+                AccountMeta::new(keys[3], false),
+            ]
+        );
+        assert_eq!(scratch.account_infos.len(), 4);
+        let mut expected_data: Vec<u8> = MARGINFI_LENDING_ACCOUNT_BORROW_DISCRIMINATOR.to_vec();
+        expected_data.extend_from_slice(&9_u64.to_le_bytes());
+        assert_eq!(scratch.data, expected_data);
+
+        // Both CPIs were built in the buffers sized up front.
+        assert_eq!(
+            [
+                scratch.account_metas.as_ptr() as *const u8,
+                scratch.account_infos.as_ptr() as *const u8,
+                scratch.data.as_ptr(),
+            ],
+            buffers
+        );
+        assert!(scratch.account_metas.capacity() >= MAX_MARGINFI_CPI_ACCOUNTS);
+        assert!(scratch.data.capacity() >= MAX_MARGINFI_CPI_DATA_LEN);
+    }
 }
@@ -0,0 +1,450 @@
+use fixed::types::I80F48;
+use hypertree::DataIndex;
+use solana_program::program_error::ProgramError;
+
+use crate::{
+    math::{get_buffer_f, get_required_quote_collateral_to_back_loan},
+    program::NixError,
+    quantities::BankShareValues,
+    state::{ExpiryPolicy, RestingOrder},
+};
+
+/// Banks and oracle prices a match is sized with, read once per
+/// instruction.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MatchBankParams {
+    pub base_bank: BankShareValues,
+    pub quote_bank: BankShareValues,
+    pub base_oracle_price_usd: I80F48,
+    pub quote_oracle_price_usd: I80F48,
+    /// Share of the quote collateral weight that is used, 1 minus the ltv
+    /// buffer of the market.
+    pub buffer_f: I80F48,
+}
+
+impl MatchBankParams {
+    pub fn new(
+        base_bank: BankShareValues,
+        quote_bank: BankShareValues,
+        base_oracle_price_usd: I80F48,
+        quote_oracle_price_usd: I80F48,
+        ltv_buffer_bps: u64,
+    ) -> Result<Self, ProgramError> {
+        Ok(MatchBankParams {
+            base_bank,
+            quote_bank,
+            base_oracle_price_usd,
+            quote_oracle_price_usd,
+            buffer_f: get_buffer_f(ltv_buffer_bps)?,
+        })
+    }
+}
+
+/// What a taker does with the next maker order on the other side.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum MakerStep {
+    /// Expired bid that stays on the book until it is cleaned.
+    Skip,
+    /// Expired, or nothing left to trade. Taken off the book.
+    Remove,
+    /// Past the limit rate of the taker, so matching stops.
+    Stop,
+    /// Crosses the taker.
+    Match,
+}
+
+/// Decides what a taker at `rate_bps` does with `maker_order`. Loan sales
+/// hold no collateral, only expiring removes them.
+pub fn get_maker_step(
+    maker_order: &RestingOrder,
+    is_bid: bool,
+    rate_bps: u16,
+    now_slot: u32,
+    now_unix_timestamp: i64,
+) -> MakerStep {
+    let maker_is_expired: bool = maker_order.is_expired(now_slot, now_unix_timestamp);
+    if maker_is_expired
+        && maker_order.get_is_bid()
+        && maker_order.get_expiry_policy() == ExpiryPolicy::KeepUntilCleaned
+    {
+        return MakerStep::Skip;
+    }
+    if maker_is_expired
+        || (!maker_order.is_loan_sale() && I80F48::from(maker_order.get_collateral_shares()) == 0)
+    {
+        return MakerStep::Remove;
+    }
+    if (is_bid && maker_order.get_rate_bps() > rate_bps)
+        || (!is_bid && maker_order.get_rate_bps() < rate_bps)
+    {
+        return MakerStep::Stop;
+    }
+    MakerStep::Match
+}
+
+/// Size of one fill against a maker order.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct MatchedFill {
+    pub base_atoms: u64,
+    /// Quote collateral that backs the loan of the fill.
+    pub quote_atoms: u64,
+    pub rate_bps: u16,
+    /// The maker order has nothing left after the fill.
+    pub did_fully_match_resting_order: bool,
+}
+
+/// Fills up to `remaining_base_atoms` of a maker order worth
+/// `maker_base_atoms`.
+pub fn get_matched_fill(
+    maker_base_atoms: u64,
+    remaining_base_atoms: u64,
+    rate_bps: u16,
+    params: &MatchBankParams,
+) -> Result<MatchedFill, ProgramError> {
+    let did_fully_match_resting_order: bool = remaining_base_atoms >= maker_base_atoms;
+    let base_atoms: u64 = remaining_base_atoms.min(maker_base_atoms);
+    let quote_atoms: u64 = get_required_quote_collateral_to_back_loan(
+        &params.base_bank,
+        &params.quote_bank,
+        params.base_oracle_price_usd,
+        params.quote_oracle_price_usd,
+        params.buffer_f,
+        base_atoms,
+    )?;
+    Ok(MatchedFill {
+        base_atoms,
+        quote_atoms,
+        rate_bps,
+        did_fully_match_resting_order,
+    })
+}
+
+/// Uniform rate a batch of bids and asks clears at, with the base atoms that
+/// trade there. Orders are (rate_bps, base_atoms) in any order. The rate is
+/// the one that trades the most, ties going to the smallest imbalance between
+/// the two sides and then to the lowest rate. None when the book does not
+/// cross.
+pub fn get_clearing_rate(bids: &[(u16, u64)], asks: &[(u16, u64)]) -> Option<(u16, u64)> {
+    let mut bids: Vec<(u16, u64)> = bids.to_vec();
+    let mut asks: Vec<(u16, u64)> = asks.to_vec();
+    bids.sort_unstable_by_key(|(rate_bps, _)| *rate_bps);
+    asks.sort_unstable_by_key(|(rate_bps, _)| *rate_bps);
+    let mut rates: Vec<u16> = bids
+        .iter()
+        .chain(asks.iter())
+        .map(|(rate_bps, _)| *rate_bps)
+        .collect();
+    rates.sort_unstable();
+    rates.dedup();
+
+    // Walking the rates up, bids below the rate drop out of the demand and
+    // asks at or below it join the supply.
+    let mut demand_base_atoms: u64 = bids.iter().fold(0_u64, |total, (_, base_atoms)| {
+        total.saturating_add(*base_atoms)
+    });
+    let mut supply_base_atoms: u64 = 0;
+    let mut next_bid: usize = 0;
+    let mut next_ask: usize = 0;
+    // Rate, base atoms and imbalance of the best rate so far.
+    let mut best: Option<(u16, u64, u64)> = None;
+    for rate_bps in rates {
+        while next_bid < bids.len() && bids[next_bid].0 < rate_bps {
+            demand_base_atoms = demand_base_atoms.saturating_sub(bids[next_bid].1);
+            next_bid += 1;
+        }
+        while next_ask < asks.len() && asks[next_ask].0 <= rate_bps {
+            supply_base_atoms = supply_base_atoms.saturating_add(asks[next_ask].1);
+            next_ask += 1;
+        }
+        let base_atoms: u64 = demand_base_atoms.min(supply_base_atoms);
+        let imbalance: u64 = demand_base_atoms.abs_diff(supply_base_atoms);
+        let is_better: bool = match best {
+            None => base_atoms > 0,
+            Some((_, best_base_atoms, best_imbalance)) => {
+                base_atoms > best_base_atoms
+                    || (base_atoms == best_base_atoms && imbalance < best_imbalance)
+            }
+        };
+        if is_better {
+            best = Some((rate_bps, base_atoms, imbalance));
+        }
+    }
+    best.map(|(rate_bps, base_atoms, _)| (rate_bps, base_atoms))
+}
+
+/// One step of a planned match, with the index of the maker order.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum PlannedStep {
+    Skip(DataIndex),
+    Remove(DataIndex),
+    Fill(DataIndex, MatchedFill),
+}
+
+/// Result of running a taker against a book side without changing it. The
+/// steps are what place_order would do to each maker order, in order.
+#[derive(Debug, Default, Clone)]
+pub struct MatchPlan {
+    pub steps: Vec<PlannedStep>,
+    pub base_atoms: u64,
+    pub quote_atoms: u64,
+    pub remaining_base_atoms: u64,
+    pub stopped_at_match_limit: bool,
+}
+
+impl MatchPlan {
+    /// Rate of the fills weighted by their base atoms, zero without fills.
+    pub fn get_weighted_rate_bps(&self) -> u16 {
+        if self.base_atoms == 0 {
+            return 0;
+        }
+        let rate_weighted_base_atoms: u128 = self
+            .steps
+            .iter()
+            .map(|step| match step {
+                PlannedStep::Fill(_, fill) => fill.rate_bps as u128 * fill.base_atoms as u128,
+                _ => 0,
+            })
+            .sum();
+        (rate_weighted_base_atoms / self.base_atoms as u128) as u16
+    }
+
+    pub fn get_num_fills(&self) -> u32 {
+        self.steps
+            .iter()
+            .filter(|step| matches!(step, PlannedStep::Fill(..)))
+            .count() as u32
+    }
+}
+
+/// Plans a taker against `maker_orders`, best first. Every maker order
+/// crossed counts towards `match_limit`, skipped and removed ones included.
+/// Loan sales are bought whole by PlaceOrder and are skipped here.
+pub fn plan_matches<'a>(
+    maker_orders: impl IntoIterator<Item = (DataIndex, &'a RestingOrder)>,
+    is_bid: bool,
+    rate_bps: u16,
+    num_base_atoms: u64,
+    now_slot: u32,
+    now_unix_timestamp: i64,
+    match_limit: u32,
+    params: &MatchBankParams,
+) -> Result<MatchPlan, ProgramError> {
+    let mut plan: MatchPlan = MatchPlan {
+        remaining_base_atoms: num_base_atoms,
+        ..Default::default()
+    };
+    let mut num_maker_orders_crossed: u32 = 0;
+    for (maker_order_index, maker_order) in maker_orders {
+        if plan.remaining_base_atoms == 0 {
+            break;
+        }
+        if num_maker_orders_crossed >= match_limit {
+            plan.stopped_at_match_limit = true;
+            break;
+        }
+        num_maker_orders_crossed += 1;
+
+        match get_maker_step(maker_order, is_bid, rate_bps, now_slot, now_unix_timestamp) {
+            MakerStep::Skip => plan.steps.push(PlannedStep::Skip(maker_order_index)),
+            MakerStep::Remove => plan.steps.push(PlannedStep::Remove(maker_order_index)),
+            MakerStep::Stop => break,
+            MakerStep::Match if maker_order.is_loan_sale() => {
+                plan.steps.push(PlannedStep::Skip(maker_order_index))
+            }
+            MakerStep::Match => {
+                let fill: MatchedFill = get_matched_fill(
+                    maker_order.get_num_base_atoms(&params.base_bank)?,
+                    plan.remaining_base_atoms,
+                    maker_order.get_rate_bps(),
+                    params,
+                )?;
+                plan.remaining_base_atoms -= fill.base_atoms;
+                plan.base_atoms = plan
+                    .base_atoms
+                    .checked_add(fill.base_atoms)
+                    .ok_or(NixError::NumericalOverflow)?;
+                plan.quote_atoms = plan
+                    .quote_atoms
+                    .checked_add(fill.quote_atoms)
+                    .ok_or(NixError::NumericalOverflow)?;
+                plan.steps.push(PlannedStep::Fill(maker_order_index, fill));
+            }
+        }
+    }
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        quantities::WrappedI80F48,
+        state::{OrderType, NO_EXPIRATION_LAST_VALID_SLOT},
+    };
+
+    fn new_ask(rate_bps: u16, collateral_shares: u64, last_valid_slot: u32) -> RestingOrder {
+        RestingOrder::new(
+            rate_bps,
+            0,
+            WrappedI80F48::from(collateral_shares),
+            WrappedI80F48::ZERO,
+            true,
+            0,
+            last_valid_slot,
+            OrderType::Limit,
+            false,
+            0,
+            ExpiryPolicy::ReturnCollateral,
+        )
+        .unwrap()
+    }
+
+    fn new_bid(rate_bps: u16, last_valid_slot: u32, expiry_policy: ExpiryPolicy) -> RestingOrder {
+        RestingOrder::new(
+            rate_bps,
+            0,
+            WrappedI80F48::from(1_u64),
+            WrappedI80F48::from(1_u64),
+            true,
+            0,
+            last_valid_slot,
+            OrderType::Limit,
+            true,
+            0,
+            expiry_policy,
+        )
+        .unwrap()
+    }
+
+    fn new_params() -> MatchBankParams {
+        let bank: BankShareValues = BankShareValues {
+            asset_share_value: I80F48::ONE,
+            liability_share_value: I80F48::ONE,
+            asset_weight_init: I80F48::ONE,
+            liability_weight_init: I80F48::ONE,
+            mint_decimals: 6,
+            ..Default::default()
+        };
+        MatchBankParams::new(bank, bank, I80F48::ONE, I80F48::ONE, 0).unwrap()
+    }
+
+    #[test]
+    fn test_expired_makers() {
+        let expired_ask: RestingOrder = new_ask(500, 1_000, 10);
+        assert_eq!(
+            get_maker_step(&expired_ask, true, 500, 11, 0),
+            MakerStep::Remove
+        );
+        assert_eq!(
+            get_maker_step(&expired_ask, true, 500, 10, 0),
+            MakerStep::Match
+        );
+
+        let kept_bid: RestingOrder = new_bid(500, 10, ExpiryPolicy::KeepUntilCleaned);
+        assert_eq!(
+            get_maker_step(&kept_bid, false, 500, 11, 0),
+            MakerStep::Skip
+        );
+        let converted_bid: RestingOrder = new_bid(500, 10, ExpiryPolicy::ConvertToPool);
+        assert_eq!(
+            get_maker_step(&converted_bid, false, 500, 11, 0),
+            MakerStep::Remove
+        );
+    }
+
+    #[test]
+    fn test_good_till_time_makers() {
+        let mut ask: RestingOrder = new_ask(500, 1_000, NO_EXPIRATION_LAST_VALID_SLOT);
+        ask.set_good_till_time(1_000, 5_000);
+        assert_eq!(get_maker_step(&ask, true, 500, 0, 1_000), MakerStep::Match);
+        assert_eq!(get_maker_step(&ask, true, 500, 0, 1_001), MakerStep::Remove);
+
+        // Whichever of the slot and the time comes first expires it.
+        let mut bid: RestingOrder = new_bid(500, 10, ExpiryPolicy::KeepUntilCleaned);
+        bid.set_good_till_time(1_000, 5_000);
+        assert_eq!(get_maker_step(&bid, false, 500, 11, 0), MakerStep::Skip);
+        assert_eq!(get_maker_step(&bid, false, 500, 0, 1_001), MakerStep::Skip);
+        assert_eq!(
+            get_maker_step(&bid, false, 500, 10, 1_000),
+            MakerStep::Match
+        );
+    }
+
+    #[test]
+    fn test_zero_share_makers() {
+        let empty_ask: RestingOrder = new_ask(500, 0, NO_EXPIRATION_LAST_VALID_SLOT);
+        assert_eq!(
+            get_maker_step(&empty_ask, true, 500, 0, 0),
+            MakerStep::Remove
+        );
+    }
+
+    #[test]
+    fn test_crossing_own_limit() {
+        let ask: RestingOrder = new_ask(500, 1_000, NO_EXPIRATION_LAST_VALID_SLOT);
+        assert_eq!(get_maker_step(&ask, true, 499, 0, 0), MakerStep::Stop);
+        assert_eq!(get_maker_step(&ask, true, 500, 0, 0), MakerStep::Match);
+
+        let bid: RestingOrder =
+            new_bid(500, NO_EXPIRATION_LAST_VALID_SLOT, ExpiryPolicy::default());
+        assert_eq!(get_maker_step(&bid, false, 501, 0, 0), MakerStep::Stop);
+        assert_eq!(get_maker_step(&bid, false, 500, 0, 0), MakerStep::Match);
+    }
+
+    #[test]
+    fn test_plan_matches() {
+        let asks: [RestingOrder; 4] = [
+            new_ask(400, 0, NO_EXPIRATION_LAST_VALID_SLOT),
+            new_ask(450, 600, NO_EXPIRATION_LAST_VALID_SLOT),
+            new_ask(500, 600, NO_EXPIRATION_LAST_VALID_SLOT),
+            new_ask(600, 600, NO_EXPIRATION_LAST_VALID_SLOT),
+        ];
+        let maker_orders = || {
+            asks.iter()
+                .enumerate()
+                .map(|(index, ask)| (index as DataIndex, ask))
+        };
+
+        let plan: MatchPlan =
+            plan_matches(maker_orders(), true, 500, 1_000, 0, 0, 10, &new_params()).unwrap();
+        assert_eq!(plan.steps.len(), 3);
+        assert_eq!(plan.steps[0], PlannedStep::Remove(0));
+        assert_eq!(plan.base_atoms, 1_000);
+        assert_eq!(plan.remaining_base_atoms, 0);
+        assert_eq!(plan.get_num_fills(), 2);
+        assert_eq!(plan.get_weighted_rate_bps(), 470);
+        assert!(!plan.stopped_at_match_limit);
+
+        // The removed maker counts towards the limit.
+        let plan: MatchPlan =
+            plan_matches(maker_orders(), true, 600, 1_000, 0, 0, 2, &new_params()).unwrap();
+        assert_eq!(plan.base_atoms, 600);
+        assert_eq!(plan.remaining_base_atoms, 400);
+        assert!(plan.stopped_at_match_limit);
+    }
+
+    #[test]
+    fn test_clearing_rate() {
+        // Does not cross.
+        assert_eq!(get_clearing_rate(&[(400, 100)], &[(500, 100)]), None);
+        assert_eq!(get_clearing_rate(&[], &[(500, 100)]), None);
+
+        // 500 trades 200, every other rate trades 100.
+        assert_eq!(
+            get_clearing_rate(&[(600, 100), (500, 100)], &[(400, 100), (500, 150)]),
+            Some((500, 200))
+        );
+
+        // Both rates trade 100, 500 leaves nothing over.
+        assert_eq!(
+            get_clearing_rate(&[(500, 100), (400, 50)], &[(400, 100)]),
+            Some((500, 100))
+        );
+
+        // Same volume and imbalance, so the lowest rate.
+        assert_eq!(
+            get_clearing_rate(&[(600, 100)], &[(400, 100)]),
+            Some((400, 100))
+        );
+    }
+}
@@ -0,0 +1,422 @@
+//! Pure conversions between token amounts, marginfi shares and collateral.
+//! Shared by the program and off chain clients, see the `client` module.
+
+use fixed::types::I80F48;
+use solana_program::program_error::ProgramError;
+
+use crate::{program::NixError, quantities::BankShareValues, require};
+
+/// Share of the collateral weight a market lets traders use, 1 minus its ltv
+/// buffer.
+pub fn get_buffer_f(ltv_buffer_bps: u64) -> Result<I80F48, ProgramError> {
+    I80F48::from_num(10_000i64 - ltv_buffer_bps as i64)
+        .checked_div(I80F48::from_num(10_000))
+        .ok_or(NixError::NumericalOverflow.into())
+}
+
+/// 10^decimals, the number of atoms in one whole token.
+fn get_decimals_multiplier(decimals: u8) -> Result<I80F48, ProgramError> {
+    10u128
+        .checked_pow(decimals as u32)
+        .and_then(I80F48::checked_from_num)
+        .ok_or(NixError::NumericalOverflow.into())
+}
+
+/// Converts token amount to asset shares
+pub fn convert_tokens_to_asset_shares(
+    token_amount: u64,
+    bank: &BankShareValues,
+) -> Result<I80F48, ProgramError> {
+    I80F48::from_num(token_amount)
+        .checked_div(bank.asset_share_value)
+        .ok_or(NixError::NumericalOverflow.into())
+}
+
+pub fn convert_asset_shares_to_tokens(
+    asset_shares: I80F48,
+    bank: &BankShareValues,
+) -> Result<u64, ProgramError> {
+    Ok(asset_shares
+        .checked_mul(bank.asset_share_value)
+        .ok_or(NixError::NumericalOverflow)?
+        .checked_floor()
+        .ok_or(NixError::NumericalOverflow)?
+        .to_num::<u64>())
+}
+
+pub fn get_required_quote_collateral_to_back_loan(
+    base_marginfi_bank: &BankShareValues,
+    quote_marginfi_bank: &BankShareValues,
+    base_oracle_price_usd: I80F48,
+    quote_oracle_price_usd: I80F48,
+    buffer_f: I80F48,
+    num_base_atoms: u64,
+) -> Result<u64, ProgramError> {
+    // Calculate effective collateral weight by applying buffer
+    let effective_quote_collateral_weight = quote_marginfi_bank
+        .asset_weight_init
+        .checked_mul(buffer_f)
+        .ok_or(NixError::NumericalOverflow)?;
+
+    // Convert base tokens to USD value == loan value usd
+    let base_value_usd = I80F48::from_num(num_base_atoms)
+        .checked_mul(base_oracle_price_usd)
+        .ok_or(NixError::NumericalOverflow)?
+        .checked_div(get_decimals_multiplier(base_marginfi_bank.mint_decimals)?)
+        .ok_or(NixError::NumericalOverflow)?;
+
+    // Calculate required collateral value in USD
+    // Formula: (base_value_usd * liability_weight) / effective_collateral_weight
+    let required_quote_collateral_value_usd = base_value_usd
+        .checked_mul(base_marginfi_bank.liability_weight_init)
+        .ok_or(NixError::NumericalOverflow)?
+        .checked_div(effective_quote_collateral_weight)
+        .ok_or(NixError::NumericalOverflow)?;
+
+    // Convert USD value to quote token amount
+    let required_collateral_tokens_i80f48 = required_quote_collateral_value_usd
+        .checked_mul(get_decimals_multiplier(quote_marginfi_bank.mint_decimals)?)
+        .ok_or(NixError::NumericalOverflow)?
+        .checked_div(quote_oracle_price_usd)
+        .ok_or(NixError::NumericalOverflow)?;
+
+    // Convert to u64 and round up to ensure sufficient collateral
+    let required_collateral_tokens = required_collateral_tokens_i80f48
+        .checked_ceil()
+        .ok_or(NixError::NumericalOverflow)?
+        .to_num::<u64>();
+    Ok(required_collateral_tokens)
+}
+
+/// Inverse of get_required_quote_collateral_to_back_loan, the most base atoms
+/// that num_quote_atoms of collateral can back. Rounded down so the
+/// collateral required for the result never exceeds what was committed by
+/// more than the rounding up of the forward conversion.
+pub fn get_base_atoms_backed_by_quote_collateral(
+    base_marginfi_bank: &BankShareValues,
+    quote_marginfi_bank: &BankShareValues,
+    base_oracle_price_usd: I80F48,
+    quote_oracle_price_usd: I80F48,
+    buffer_f: I80F48,
+    num_quote_atoms: u64,
+) -> Result<u64, ProgramError> {
+    let effective_quote_collateral_weight = quote_marginfi_bank
+        .asset_weight_init
+        .checked_mul(buffer_f)
+        .ok_or(NixError::NumericalOverflow)?;
+
+    // Convert quote tokens to USD value == collateral value usd
+    let quote_value_usd = I80F48::from_num(num_quote_atoms)
+        .checked_mul(quote_oracle_price_usd)
+        .ok_or(NixError::NumericalOverflow)?
+        .checked_div(get_decimals_multiplier(quote_marginfi_bank.mint_decimals)?)
+        .ok_or(NixError::NumericalOverflow)?;
+
+    // Formula: (quote_value_usd * effective_collateral_weight) / liability_weight
+    let base_value_usd = quote_value_usd
+        .checked_mul(effective_quote_collateral_weight)
+        .ok_or(NixError::NumericalOverflow)?
+        .checked_div(base_marginfi_bank.liability_weight_init)
+        .ok_or(NixError::NumericalOverflow)?;
+
+    let base_atoms_i80f48 = base_value_usd
+        .checked_mul(get_decimals_multiplier(base_marginfi_bank.mint_decimals)?)
+        .ok_or(NixError::NumericalOverflow)?
+        .checked_div(base_oracle_price_usd)
+        .ok_or(NixError::NumericalOverflow)?;
+
+    Ok(base_atoms_i80f48
+        .checked_floor()
+        .ok_or(NixError::NumericalOverflow)?
+        .to_num::<u64>())
+}
+
+/// USD value of an amount of atoms times a marginfi risk weight.
+pub fn get_weighted_value_usd(
+    num_atoms: I80F48,
+    weight: I80F48,
+    mint_decimals: u8,
+    price_usd: I80F48,
+) -> Result<I80F48, ProgramError> {
+    num_atoms
+        .checked_mul(price_usd)
+        .and_then(|value| value.checked_mul(weight))
+        .and_then(|value| value.checked_div(get_decimals_multiplier(mint_decimals).ok()?))
+        .ok_or(NixError::NumericalOverflow.into())
+}
+
+/// How far the liability of a loan is above its collateral, unweighted. A
+/// loan is in default while this is Some, its collateral no longer covers
+/// what is owed.
+pub fn get_default_shortfall_usd(
+    collateral_value_usd: I80F48,
+    liability_value_usd: I80F48,
+) -> Option<I80F48> {
+    liability_value_usd
+        .checked_sub(collateral_value_usd)
+        .filter(|shortfall_usd| shortfall_usd.is_positive())
+}
+
+/// Price in base atoms of a loan sold at `sale_rate_bps`. The buyer pays the
+/// amount owed, less a discount or plus a premium of the difference between
+/// the sale rate and the rate the loan pays, so a higher sale rate is a
+/// cheaper loan. Rounded down, in favour of the buyer.
+pub fn get_loan_sale_price_atoms(
+    owed_atoms: u64,
+    loan_rate_bps: u16,
+    sale_rate_bps: u16,
+) -> Result<u64, ProgramError> {
+    let price_bps: i64 = 10_000 + loan_rate_bps as i64 - sale_rate_bps as i64;
+    require!(
+        price_bps > 0,
+        NixError::InvalidLoanSale,
+        "Sale rate {} leaves nothing to pay for a loan at {}",
+        sale_rate_bps,
+        loan_rate_bps,
+    )?;
+    let price_atoms: u128 = (owed_atoms as u128)
+        .checked_mul(price_bps as u128)
+        .ok_or(NixError::NumericalOverflow)?
+        / 10_000;
+    u64::try_from(price_atoms).map_err(|_| NixError::NumericalOverflow.into())
+}
+
+/// Returns the amount of tokens required to repay a given amount of liability shares.
+pub fn get_token_amount_to_repay_liability_shares(
+    liability_shares: I80F48,
+    bank: &BankShareValues,
+) -> Result<u64, ProgramError> {
+    let liability_share_value: I80F48 = bank.liability_share_value;
+
+    // Calculate the liability amount (the actual token value of the debt)
+    let liability_amount_i80f48 = liability_shares
+        .checked_mul(liability_share_value)
+        .ok_or(NixError::NumericalOverflow)?; // Assuming NixError is your custom error type
+
+    // Round up to the nearest whole token unit and convert to u64
+    //checked_ceil here would round up in favour of the maker to ensure at least the actual repay amount is obtained
+    let repay_amount = liability_amount_i80f48
+        .checked_ceil()
+        .ok_or(NixError::NumericalOverflow)?
+        .to_num::<u64>();
+
+    Ok(repay_amount)
+}
+/// Converts a token amount to liability shares for a given bank.
+/// This is the inverse of `get_token_amount_to_repay_base_liability_shares`.
+pub fn convert_tokens_to_liability_shares(
+    token_amount: u64,
+    bank: &BankShareValues,
+) -> Result<I80F48, ProgramError> {
+    let liability_share_value: I80F48 = bank.liability_share_value;
+
+    // Convert token amount to asset shares
+    let liability_shares = I80F48::from_num(token_amount)
+        .checked_div(liability_share_value)
+        .ok_or(NixError::NumericalOverflow)?;
+    Ok(liability_shares)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_loan_sale_price() {
+        assert_eq!(
+            get_loan_sale_price_atoms(1_000_000, 500, 500).unwrap(),
+            1_000_000
+        );
+        // Selling above the loan rate is a discount, below it a premium.
+        assert_eq!(
+            get_loan_sale_price_atoms(1_000_000, 500, 700).unwrap(),
+            980_000
+        );
+        assert_eq!(
+            get_loan_sale_price_atoms(1_000_000, 500, 200).unwrap(),
+            1_030_000
+        );
+        assert!(get_loan_sale_price_atoms(1_000_000, 0, 10_000).is_err());
+    }
+
+    #[test]
+    fn test_base_atoms_backed_by_quote_collateral() {
+        let base_bank: BankShareValues = BankShareValues {
+            liability_weight_init: I80F48::from_num(1.25),
+            mint_decimals: 9,
+            ..Default::default()
+        };
+        let quote_bank: BankShareValues = BankShareValues {
+            asset_weight_init: I80F48::from_num(0.75),
+            mint_decimals: 6,
+            ..Default::default()
+        };
+        let base_price_usd: I80F48 = I80F48::from_num(150);
+        let quote_price_usd: I80F48 = I80F48::ONE;
+        let buffer_f: I80F48 = I80F48::from_num(0.75);
+
+        // 1_000 usdc backs 1_000 * 0.75 * 0.75 / 1.25 = 450 usd of sol.
+        let num_quote_atoms: u64 = 1_000_000_000;
+        let num_base_atoms: u64 = get_base_atoms_backed_by_quote_collateral(
+            &base_bank,
+            &quote_bank,
+            base_price_usd,
+            quote_price_usd,
+            buffer_f,
+            num_quote_atoms,
+        )
+        .unwrap();
+        assert_eq!(num_base_atoms, 3_000_000_000);
+
+        let required_quote_atoms: u64 = get_required_quote_collateral_to_back_loan(
+            &base_bank,
+            &quote_bank,
+            base_price_usd,
+            quote_price_usd,
+            buffer_f,
+            num_base_atoms,
+        )
+        .unwrap();
+        assert!(required_quote_atoms.abs_diff(num_quote_atoms) <= 1);
+    }
+
+    const TOKEN_AMOUNTS: [u64; 8] = [
+        0,
+        1,
+        2,
+        999,
+        1_000_000,
+        123_456_789,
+        10u64.pow(12),
+        10u64.pow(15),
+    ];
+    const SHARE_VALUES: [f64; 5] = [0.5, 1.0, 1.000123, 1.5, 3.75];
+
+    fn new_bank(share_value: f64) -> BankShareValues {
+        BankShareValues {
+            asset_share_value: I80F48::from_num(share_value),
+            liability_share_value: I80F48::from_num(share_value),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_asset_shares_round_trip() {
+        for share_value in SHARE_VALUES {
+            let bank: BankShareValues = new_bank(share_value);
+            for token_amount in TOKEN_AMOUNTS {
+                let asset_shares: I80F48 =
+                    convert_tokens_to_asset_shares(token_amount, &bank).unwrap();
+                let round_trip: u64 = convert_asset_shares_to_tokens(asset_shares, &bank).unwrap();
+                // Rounded down, never more than went in.
+                assert!(round_trip <= token_amount);
+                assert!(round_trip + 1 >= token_amount);
+            }
+        }
+    }
+
+    #[test]
+    fn test_liability_shares_round_trip() {
+        for share_value in SHARE_VALUES {
+            let bank: BankShareValues = new_bank(share_value);
+            for token_amount in TOKEN_AMOUNTS {
+                let liability_shares: I80F48 =
+                    convert_tokens_to_liability_shares(token_amount, &bank).unwrap();
+                let round_trip: u64 =
+                    get_token_amount_to_repay_liability_shares(liability_shares, &bank).unwrap();
+                assert!(round_trip.abs_diff(token_amount) <= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_conversions_are_monotonic() {
+        let base_bank: BankShareValues = BankShareValues {
+            liability_weight_init: I80F48::from_num(1.25),
+            mint_decimals: 9,
+            ..new_bank(1.5)
+        };
+        let quote_bank: BankShareValues = BankShareValues {
+            asset_weight_init: I80F48::from_num(0.75),
+            mint_decimals: 6,
+            ..new_bank(1.000123)
+        };
+        let base_price_usd: I80F48 = I80F48::from_num(150);
+        let quote_price_usd: I80F48 = I80F48::ONE;
+        for ltv_buffer_bps in [0, 2_500, 9_000] {
+            let buffer_f: I80F48 = get_buffer_f(ltv_buffer_bps).unwrap();
+            let mut last_required_quote_atoms: u64 = 0;
+            let mut last_backed_base_atoms: u64 = 0;
+            let mut last_asset_shares: I80F48 = I80F48::ZERO;
+            let mut last_liability_shares: I80F48 = I80F48::ZERO;
+            for token_amount in TOKEN_AMOUNTS {
+                let required_quote_atoms: u64 = get_required_quote_collateral_to_back_loan(
+                    &base_bank,
+                    &quote_bank,
+                    base_price_usd,
+                    quote_price_usd,
+                    buffer_f,
+                    token_amount,
+                )
+                .unwrap();
+                let backed_base_atoms: u64 = get_base_atoms_backed_by_quote_collateral(
+                    &base_bank,
+                    &quote_bank,
+                    base_price_usd,
+                    quote_price_usd,
+                    buffer_f,
+                    token_amount,
+                )
+                .unwrap();
+                let asset_shares: I80F48 =
+                    convert_tokens_to_asset_shares(token_amount, &quote_bank).unwrap();
+                let liability_shares: I80F48 =
+                    convert_tokens_to_liability_shares(token_amount, &base_bank).unwrap();
+                assert!(required_quote_atoms >= last_required_quote_atoms);
+                assert!(backed_base_atoms >= last_backed_base_atoms);
+                assert!(asset_shares >= last_asset_shares);
+                assert!(liability_shares >= last_liability_shares);
+                last_required_quote_atoms = required_quote_atoms;
+                last_backed_base_atoms = backed_base_atoms;
+                last_asset_shares = asset_shares;
+                last_liability_shares = liability_shares;
+
+                // Collateral committed for a loan always backs the loan.
+                let round_trip: u64 = get_base_atoms_backed_by_quote_collateral(
+                    &base_bank,
+                    &quote_bank,
+                    base_price_usd,
+                    quote_price_usd,
+                    buffer_f,
+                    required_quote_atoms,
+                )
+                .unwrap();
+                assert!(round_trip + 1 >= token_amount);
+            }
+        }
+    }
+
+    #[test]
+    fn test_default_shortfall() {
+        assert_eq!(
+            get_default_shortfall_usd(I80F48::from_num(90), I80F48::from_num(100)),
+            Some(I80F48::from_num(10))
+        );
+        // Fully covered, even at exactly the liability.
+        assert_eq!(
+            get_default_shortfall_usd(I80F48::from_num(100), I80F48::from_num(100)),
+            None
+        );
+        assert_eq!(
+            get_default_shortfall_usd(I80F48::from_num(120), I80F48::from_num(100)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_buffer_f() {
+        assert_eq!(get_buffer_f(0).unwrap(), I80F48::ONE);
+        assert_eq!(get_buffer_f(2_500).unwrap(), I80F48::from_num(0.75));
+        assert_eq!(get_buffer_f(10_000).unwrap(), I80F48::ZERO);
+    }
+}
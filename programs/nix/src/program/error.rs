@@ -1,9 +1,13 @@
+use num_enum::TryFromPrimitive;
 use solana_program::program_error::ProgramError;
+use static_assertions::const_assert_eq;
 use thiserror::Error;
 
+use crate::logs::{emit_stack, SourceErrorLog};
+
 // use crate::program::error;
 
-#[derive(Debug, Error)]
+#[derive(Debug, Clone, Copy, Error, TryFromPrimitive)]
 #[repr(u32)]
 pub enum NixError {
     #[error("Invalid market parameters error")]
@@ -96,6 +100,299 @@ pub enum NixError {
     InvalidAdminKey = 43,
     #[error("Invalid Global Mint")]
     InvalidGlobalMint = 44,
+    #[error("Reverse spread outside of market bounds")]
+    InvalidReverseSpread = 45,
+    #[error("Seat has reached the max number of open orders")]
+    TooManyOpenOrders = 46,
+    #[error("Invalid referrer")]
+    InvalidReferrer = 47,
+    #[error("Market still has orders, loans or balances")]
+    MarketNotEmpty = 48,
+    #[error("Market loans account is full, use the next page")]
+    MarketLoansPageFull = 49,
+    #[error("Book snapshot already emitted this slot")]
+    BookSnapshotRateLimited = 50,
+    #[error("Market is permissioned and the allowlist authority did not sign")]
+    NotAllowlisted = 51,
+    #[error("Event queue is full, consume events first")]
+    EventQueueFull = 52,
+    #[error("Market has an event queue that was not passed in")]
+    MissingEventQueue = 53,
+    #[error("Global still has free seats, add a trader instead of evicting")]
+    GlobalNotFull = 54,
+    #[error("Seat liability would exceed the borrow limit of the market")]
+    BorrowLimitExceeded = 55,
+    #[error("Market layout version does not match the program, run MigrateMarket")]
+    MarketVersionMismatch = 56,
+    #[error("Not enough accounts for the instruction")]
+    MissingAccounts = 57,
+    #[error("Account has to sign the instruction")]
+    AccountNotSigner = 58,
+    #[error("Account has to be writable")]
+    AccountNotWritable = 59,
+    #[error("Writable account is passed more than once")]
+    DuplicateAccount = 60,
+    #[error("Order needs the marginfi accounts of a side that was not passed")]
+    MissingMarginfiAccounts = 61,
+    #[error("Only asks can be reduced, to a smaller size above zero")]
+    InvalidReduce = 62,
+    #[error("Cross margin account health does not cover the order")]
+    CrossMarginUnhealthy = 63,
+    #[error("Loan cannot be sold or bought this way")]
+    InvalidLoanSale = 64,
+    #[error("Market registry of the pair is full")]
+    MarketRegistryFull = 65,
+    #[error("Order cannot be deferred or continued this way")]
+    InvalidPendingOrder = 66,
+    #[error("Marginfi could not read the oracle price")]
+    OracleFailed = 67,
+    #[error("Market has no marginfi bank for a side this order needs")]
+    MissingMarginfiBank = 68,
+    #[error("Order cannot be modified this way")]
+    InvalidModifyOrder = 69,
+    #[error("Place order params are invalid or of an unknown version")]
+    InvalidPlaceOrderParams = 70,
+    #[error("Market is post only after a large oracle price move")]
+    MarketPostOnly = 71,
+    #[error("Auction window of the market is still open")]
+    AuctionNotDue = 72,
+    #[error("Collateral can only be topped up on a borrow order or loan of the trader")]
+    InvalidTopUp = 73,
+    #[error("Collateral release is not from a loan of the trader or leaves it under the buffer")]
+    InvalidCollateralRelease = 74,
+    #[error("Oracle feed has different decimals than expected")]
+    OracleFeedDecimalsMismatch = 75,
+    #[error("Global layout does not match the program, run MigrateGlobal")]
+    GlobalLayoutMismatch = 76,
+    #[error("Gas deposit is outside of the program bounds")]
+    InvalidGasDeposit = 77,
+    #[error("Loan has not been repaid")]
+    LoanNotRepaid = 78,
+    #[error("Quantity is negative, not finite or does not fit in atoms")]
+    InvalidQuantity = 79,
+    #[error("Loan is still backed by its collateral")]
+    LoanNotInDefault = 80,
+    #[error("Insurance draw is more than the fund holds")]
+    InvalidInsuranceDraw = 81,
+    #[error("Seat placed too many new orders in the order window of the market")]
+    OrderRateLimited = 82,
+    #[error("Global is already linked to marginfi")]
+    GlobalAlreadyLinked = 83,
+    #[error("Orders on this market can only be placed by its allowed caller program")]
+    CallerNotAllowed = 84,
+    #[error("Mint freezes new token accounts and the market signer is not its freeze authority")]
+    MintFrozenByDefault = 85,
+    #[error("Token account is frozen")]
+    TokenAccountFrozen = 86,
+    #[error("Token account has the CPI guard on, so it cannot be moved from through nix")]
+    CpiGuardEnabled = 87,
+    #[error("Marginfi bank config changed since the market recorded it")]
+    BankConfigChanged = 88,
+    #[error("Marginfi account health does not cover the borrow of the order")]
+    InsufficientCollateral = 89,
+    #[error("Market blocks are on a layout MigrateMarket cannot convert")]
+    MarketNotMigratable = 90,
+}
+
+// Clients match on these codes, so a variant keeps its code once released.
+// New variants go at the end with the next free code.
+const_assert_eq!(NixError::InvalidMarketParameters as u32, 0);
+const_assert_eq!(NixError::InvalidDepositAccounts as u32, 1);
+const_assert_eq!(NixError::InvalidWithdrawAccounts as u32, 2);
+const_assert_eq!(NixError::InvalidCancel as u32, 3);
+const_assert_eq!(NixError::InvalidFreeList as u32, 4);
+const_assert_eq!(NixError::AlreadyClaimedSeat as u32, 5);
+const_assert_eq!(NixError::PostOnlyCrosses as u32, 6);
+const_assert_eq!(NixError::AlreadyExpired as u32, 7);
+const_assert_eq!(NixError::InsufficientOut as u32, 8);
+const_assert_eq!(NixError::InvalidPlaceOrderFromWalletParams as u32, 9);
+const_assert_eq!(NixError::WrongIndexHintParams as u32, 10);
+const_assert_eq!(NixError::PriceNotPositive as u32, 11);
+const_assert_eq!(NixError::OrderWouldOverflow as u32, 12);
+const_assert_eq!(NixError::OrderTooSmall as u32, 13);
+const_assert_eq!(NixError::NumericalOverflow as u32, 14);
+const_assert_eq!(NixError::MissingGlobal as u32, 15);
+const_assert_eq!(NixError::GlobalInsufficient as u32, 16);
+const_assert_eq!(NixError::IncorrectAccount as u32, 17);
+const_assert_eq!(NixError::InvalidMint as u32, 18);
+const_assert_eq!(NixError::TooManyGlobalSeats as u32, 19);
+const_assert_eq!(NixError::InvalidGlobalBidOrder as u32, 20);
+const_assert_eq!(NixError::InvalidEvict as u32, 21);
+const_assert_eq!(NixError::InvalidClean as u32, 22);
+const_assert_eq!(NixError::InvalidMarginfiAccount as u32, 23);
+const_assert_eq!(NixError::OracleNotSetup as u32, 24);
+const_assert_eq!(NixError::IncorrectOracleAccount as u32, 25);
+const_assert_eq!(NixError::MarginfiAccountInitializationFailed as u32, 26);
+const_assert_eq!(NixError::InvalidOracleAccount as u32, 27);
+const_assert_eq!(NixError::PriceOracleMathError as u32, 28);
+const_assert_eq!(NixError::StaleOracle as u32, 29);
+const_assert_eq!(NixError::InvalidPrice as u32, 30);
+const_assert_eq!(NixError::InvalidSwitchboardDecimalConversion as u32, 31);
+const_assert_eq!(NixError::PythPushWrongAccountOwner as u32, 32);
+const_assert_eq!(NixError::InvalidFeeReceiver as u32, 33);
+const_assert_eq!(NixError::InvalidVault as u32, 34);
+const_assert_eq!(NixError::InvalidMarginfiGroup as u32, 35);
+const_assert_eq!(NixError::InvalidMarginfiBank as u32, 36);
+const_assert_eq!(NixError::InvalidMarginfiLiquidityVault as u32, 37);
+const_assert_eq!(NixError::MarginfiCpiFailed as u32, 38);
+const_assert_eq!(NixError::InvalidMarginfiState as u32, 39);
+const_assert_eq!(NixError::MaxActiveLoansExceeded as u32, 40);
+const_assert_eq!(NixError::InvalidActiveLoan as u32, 41);
+const_assert_eq!(NixError::InvalidAskReverseOrder as u32, 42);
+const_assert_eq!(NixError::InvalidAdminKey as u32, 43);
+const_assert_eq!(NixError::InvalidGlobalMint as u32, 44);
+const_assert_eq!(NixError::InvalidReverseSpread as u32, 45);
+const_assert_eq!(NixError::TooManyOpenOrders as u32, 46);
+const_assert_eq!(NixError::InvalidReferrer as u32, 47);
+const_assert_eq!(NixError::MarketNotEmpty as u32, 48);
+const_assert_eq!(NixError::MarketLoansPageFull as u32, 49);
+const_assert_eq!(NixError::BookSnapshotRateLimited as u32, 50);
+const_assert_eq!(NixError::NotAllowlisted as u32, 51);
+const_assert_eq!(NixError::EventQueueFull as u32, 52);
+const_assert_eq!(NixError::MissingEventQueue as u32, 53);
+const_assert_eq!(NixError::GlobalNotFull as u32, 54);
+const_assert_eq!(NixError::BorrowLimitExceeded as u32, 55);
+const_assert_eq!(NixError::MarketVersionMismatch as u32, 56);
+const_assert_eq!(NixError::MissingAccounts as u32, 57);
+const_assert_eq!(NixError::AccountNotSigner as u32, 58);
+const_assert_eq!(NixError::AccountNotWritable as u32, 59);
+const_assert_eq!(NixError::DuplicateAccount as u32, 60);
+const_assert_eq!(NixError::MissingMarginfiAccounts as u32, 61);
+const_assert_eq!(NixError::InvalidReduce as u32, 62);
+const_assert_eq!(NixError::CrossMarginUnhealthy as u32, 63);
+const_assert_eq!(NixError::InvalidLoanSale as u32, 64);
+const_assert_eq!(NixError::MarketRegistryFull as u32, 65);
+const_assert_eq!(NixError::InvalidPendingOrder as u32, 66);
+const_assert_eq!(NixError::OracleFailed as u32, 67);
+const_assert_eq!(NixError::MissingMarginfiBank as u32, 68);
+const_assert_eq!(NixError::InvalidModifyOrder as u32, 69);
+const_assert_eq!(NixError::InvalidPlaceOrderParams as u32, 70);
+const_assert_eq!(NixError::MarketPostOnly as u32, 71);
+const_assert_eq!(NixError::AuctionNotDue as u32, 72);
+const_assert_eq!(NixError::InvalidTopUp as u32, 73);
+const_assert_eq!(NixError::InvalidCollateralRelease as u32, 74);
+const_assert_eq!(NixError::OracleFeedDecimalsMismatch as u32, 75);
+const_assert_eq!(NixError::GlobalLayoutMismatch as u32, 76);
+const_assert_eq!(NixError::InvalidGasDeposit as u32, 77);
+const_assert_eq!(NixError::LoanNotRepaid as u32, 78);
+const_assert_eq!(NixError::InvalidQuantity as u32, 79);
+const_assert_eq!(NixError::LoanNotInDefault as u32, 80);
+const_assert_eq!(NixError::InvalidInsuranceDraw as u32, 81);
+const_assert_eq!(NixError::OrderRateLimited as u32, 82);
+const_assert_eq!(NixError::GlobalAlreadyLinked as u32, 83);
+const_assert_eq!(NixError::CallerNotAllowed as u32, 84);
+const_assert_eq!(NixError::MintFrozenByDefault as u32, 85);
+const_assert_eq!(NixError::TokenAccountFrozen as u32, 86);
+const_assert_eq!(NixError::CpiGuardEnabled as u32, 87);
+const_assert_eq!(NixError::BankConfigChanged as u32, 88);
+const_assert_eq!(NixError::InsufficientCollateral as u32, 89);
+const_assert_eq!(NixError::MarketNotMigratable as u32, 90);
+
+impl NixError {
+    /// What went wrong and what to do about it, for wallets and explorers to
+    /// show instead of the custom error code. Longer than the error string,
+    /// which stays short for the program logs.
+    pub fn user_message(self) -> &'static str {
+        match self {
+            NixError::InvalidMarketParameters => "Market parameters are invalid, check the mints, marginfi banks and bounds passed to CreateMarket",
+            NixError::InvalidDepositAccounts => "Deposit accounts do not match the market, check the trader token account, vault and mint",
+            NixError::InvalidWithdrawAccounts => "Withdraw accounts do not match the market, check the trader token account, vault and mint",
+            NixError::InvalidCancel => "Order cannot be cancelled, it is not on the book or belongs to another trader",
+            NixError::InvalidFreeList => "Free list of the market account is corrupted",
+            NixError::AlreadyClaimedSeat => "Trader already has a seat on this market, skip ClaimSeat",
+            NixError::PostOnlyCrosses => "Post only order would match a resting order, move its rate away from the other side of the book",
+            NixError::AlreadyExpired => "Order expires before the current slot, set a later last valid slot",
+            NixError::InsufficientOut => "Order filled less than the minimum out amount, loosen the rate limit or the minimum",
+            NixError::InvalidPlaceOrderFromWalletParams => "Place order from wallet params do not fit the order type",
+            NixError::WrongIndexHintParams => "Index hint does not point at the order, refetch the market and retry",
+            NixError::PriceNotPositive => "Order rate has to be above zero",
+            NixError::OrderWouldOverflow => "Order size and rate do not fit in atoms, place a smaller order",
+            NixError::OrderTooSmall => "Order is too small to settle any atoms, place a larger order",
+            NixError::NumericalOverflow => "Amount overflowed in token math, use a smaller amount",
+            NixError::MissingGlobal => "Missing global accounts when adding a global order, pass the global and its vault",
+            NixError::GlobalInsufficient => "Global does not hold enough to back the order, deposit to the global first",
+            NixError::IncorrectAccount => "An account does not match the one the instruction expects, check the account order",
+            NixError::InvalidMint => "Mint is not a mint of this market",
+            NixError::TooManyGlobalSeats => "Global has no free seats, evict the smallest depositor with GlobalEvict",
+            NixError::InvalidGlobalBidOrder => "Global orders can only be asks, place bids from a market seat",
+            NixError::InvalidEvict => "Only the smallest depositor of a full global can be evicted",
+            NixError::InvalidClean => "Order is neither expired nor unbacked, so it cannot be cleaned",
+            NixError::InvalidMarginfiAccount => "Marginfi account does not belong to the market or is not owned by marginfi",
+            NixError::OracleNotSetup => "Marginfi bank has no oracle configured, so it cannot price collateral",
+            NixError::IncorrectOracleAccount => "Oracle account does not match the oracle of the marginfi bank",
+            NixError::MarginfiAccountInitializationFailed => "Marginfi did not set up the account for the market signer, retry the instruction",
+            NixError::InvalidOracleAccount => "Oracle account cannot be read as a price feed",
+            NixError::PriceOracleMathError => "Oracle price math overflowed",
+            NixError::StaleOracle => "Oracle price is too old, update the oracle and retry",
+            NixError::InvalidPrice => "Oracle price is zero, negative or too uncertain to use",
+            NixError::InvalidSwitchboardDecimalConversion => "Switchboard price cannot be converted to a decimal",
+            NixError::PythPushWrongAccountOwner => "Pyth push oracle is not owned by the pyth receiver program",
+            NixError::InvalidFeeReceiver => "Fee receiver is not the fee receiver PDA of the market",
+            NixError::InvalidVault => "Vault is not the vault PDA of the market or global for this mint",
+            NixError::InvalidMarginfiGroup => "Marginfi group does not match the group of the market",
+            NixError::InvalidMarginfiBank => "Marginfi bank does not match the bank of the market for this mint",
+            NixError::InvalidMarginfiLiquidityVault => "Marginfi liquidity vault does not match the bank",
+            NixError::MarginfiCpiFailed => "Marginfi rejected the CPI, see the source error code logged before this error",
+            NixError::InvalidMarginfiState => "Marginfi account or bank is not in the state nix expects",
+            NixError::MaxActiveLoansExceeded => "Maximum number of active loans reached, close loans before opening more",
+            NixError::InvalidActiveLoan => "Loan does not exist or is no longer active",
+            NixError::InvalidAskReverseOrder => "Reverse order params are invalid, check the spreads and cycles",
+            NixError::InvalidAdminKey => "Signer is not the admin of the market or global",
+            NixError::InvalidGlobalMint => "Global does not belong to this mint",
+            NixError::InvalidReverseSpread => "Reverse spread is outside the bounds of the market",
+            NixError::TooManyOpenOrders => "Seat has the max number of open orders, cancel some before placing more",
+            NixError::InvalidReferrer => "Referrer is not valid for this market",
+            NixError::MarketNotEmpty => "Market still has orders, loans or balances, cancel, repay and withdraw before closing it",
+            NixError::MarketLoansPageFull => "Market loans account is full, pass the next loans page or create one with CreateMarketLoanAccount",
+            NixError::BookSnapshotRateLimited => "Book snapshot was already emitted this slot, retry in the next slot",
+            NixError::NotAllowlisted => "Market is permissioned, have the allowlist authority sign the instruction",
+            NixError::EventQueueFull => "Event queue is full, run ConsumeEvents first",
+            NixError::MissingEventQueue => "Market has an event queue, pass it to the instruction",
+            NixError::GlobalNotFull => "Global still has free seats, add the trader with GlobalAddTrader instead of evicting",
+            NixError::BorrowLimitExceeded => "Seat liability would exceed the borrow limit of the market, borrow less",
+            NixError::MarketVersionMismatch => "Market account is on an older layout, run MigrateMarket first",
+            NixError::MissingAccounts => "Not enough accounts were passed for the instruction",
+            NixError::AccountNotSigner => "An account that has to sign the instruction did not sign",
+            NixError::AccountNotWritable => "An account that has to be writable was passed read only",
+            NixError::DuplicateAccount => "A writable account was passed more than once",
+            NixError::MissingMarginfiAccounts => "Order needs the marginfi accounts of a side that were not passed",
+            NixError::InvalidReduce => "Only asks can be reduced, and only to a smaller size above zero",
+            NixError::CrossMarginUnhealthy => "Cross margin account health does not cover the order, add collateral or place a smaller order",
+            NixError::InvalidLoanSale => "Loan cannot be sold or bought this way",
+            NixError::MarketRegistryFull => "Market registry of the pair is full",
+            NixError::InvalidPendingOrder => "Order cannot be deferred or continued this way",
+            NixError::OracleFailed => "Marginfi could not read the oracle price, see the source error code logged before this error",
+            NixError::MissingMarginfiBank => "Market has no marginfi bank for a side this order needs",
+            NixError::InvalidModifyOrder => "Order cannot be modified this way",
+            NixError::InvalidPlaceOrderParams => "Place order params are invalid or of an unknown version, update the client",
+            NixError::MarketPostOnly => "Market is post only after a large oracle price move, only post only orders are accepted for now",
+            NixError::AuctionNotDue => "Auction window of the market is still open, run the auction once it closes",
+            NixError::InvalidTopUp => "Collateral can only be topped up on a borrow order or loan of the trader",
+            NixError::InvalidCollateralRelease => "Collateral release is not from a loan of the trader or leaves it under the buffer",
+            NixError::OracleFeedDecimalsMismatch => "Oracle feed has different decimals than the market expects",
+            NixError::GlobalLayoutMismatch => "Global account is on an older layout, run MigrateGlobal first",
+            NixError::InvalidGasDeposit => "Gas deposit is outside of the program bounds",
+            NixError::LoanNotRepaid => "Loan has not been repaid yet",
+            NixError::InvalidQuantity => "Amount is negative, not finite or does not fit in atoms",
+            NixError::LoanNotInDefault => "Loan is still backed by its collateral, so it cannot be marked in default",
+            NixError::InvalidInsuranceDraw => "Insurance draw is more than the fund holds",
+            NixError::OrderRateLimited => "Seat placed too many new orders in the order window of the market, retry in a later slot",
+            NixError::GlobalAlreadyLinked => "Global is already linked to marginfi",
+            NixError::CallerNotAllowed => "Orders on this market can only be placed through its allowed caller program",
+            NixError::MintFrozenByDefault => "Mint freezes new token accounts and the market signer is not its freeze authority, so the market vaults would be frozen",
+            NixError::TokenAccountFrozen => "Token account is frozen, have the freeze authority thaw it first",
+            NixError::CpiGuardEnabled => "Token account has the CPI guard on, turn it off to move tokens through nix",
+            NixError::BankConfigChanged => "Weights or oracles of a marginfi bank changed since the market recorded them, the market admin has to run AcknowledgeBankConfigChange",
+            NixError::InsufficientCollateral => "Marginfi would refuse to borrow the resting part of the bid, deposit more collateral or place a smaller bid",
+            NixError::MarketNotMigratable => "Seats and orders of the market are on an older block layout that cannot be migrated, close the market and create a new one",
+        }
+    }
+}
+
+/// User message of the nix error with this custom error code, for clients
+/// that only have the code of a failed transaction.
+pub fn describe(code: u32) -> &'static str {
+    NixError::try_from(code).map_or("Unknown nix error code", NixError::user_message)
 }
 
 impl From<NixError> for ProgramError {
@@ -104,6 +401,35 @@ impl From<NixError> for ProgramError {
     }
 }
 
+/// Error returned by a CPI into marginfi.
+pub struct MarginfiCpiError(pub ProgramError);
+
+/// Error returned by the marginfi oracle adapters when pricing a bank.
+pub struct OracleError(pub ProgramError);
+
+impl From<MarginfiCpiError> for NixError {
+    fn from(e: MarginfiCpiError) -> Self {
+        with_source_error_log(NixError::MarginfiCpiFailed, e.0)
+    }
+}
+
+impl From<OracleError> for NixError {
+    fn from(e: OracleError) -> Self {
+        with_source_error_log(NixError::OracleFailed, e.0)
+    }
+}
+
+/// Logs the code the source failed with next to the NixError returned for
+/// it, so the cause is not lost behind the stable nix code.
+fn with_source_error_log(error: NixError, source: ProgramError) -> NixError {
+    // Logging never fails, and a failed log should not mask the real error.
+    let _ = emit_stack(SourceErrorLog {
+        error_code: error as u64,
+        source_error_code: u64::from(source),
+    });
+    error
+}
+
 #[macro_export]
 macro_rules! require {
   ($test:expr, $err:expr, $($arg:tt)*) => {
@@ -118,3 +444,74 @@ macro_rules! require {
     }
   };
 }
+
+/// Same as require!, but also emits an ErrorLog with the instruction and the
+/// index of the offending account, so failures in instructions with many
+/// accounts can be pinned down from the logs without a trace build.
+#[macro_export]
+macro_rules! require_account {
+  ($test:expr, $err:expr, $instruction:expr, $account_index:expr, $($arg:tt)*) => {
+    if $test {
+        Ok(())
+    } else {
+        let error: solana_program::program_error::ProgramError =
+            $crate::logs::with_error_log(($err).into(), $instruction, $account_index);
+        $crate::require!(false, error, $($arg)*)
+    }
+  };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn all_errors() -> Vec<NixError> {
+        (0..)
+            .map_while(|code: u32| NixError::try_from(code).ok())
+            .collect()
+    }
+
+    #[test]
+    fn test_describe() {
+        let errors: Vec<NixError> = all_errors();
+        assert_eq!(errors.len(), NixError::MarketNotMigratable as usize + 1);
+        for error in errors.iter() {
+            assert!(!error.user_message().is_empty());
+            assert_eq!(describe(*error as u32), error.user_message());
+        }
+        assert_eq!(describe(errors.len() as u32), "Unknown nix error code");
+    }
+
+    #[test]
+    fn test_source_errors_keep_the_nix_code() {
+        assert_eq!(
+            NixError::from(MarginfiCpiError(ProgramError::Custom(6_009))) as u32,
+            NixError::MarginfiCpiFailed as u32
+        );
+        assert_eq!(
+            NixError::from(OracleError(ProgramError::Custom(6_009))) as u32,
+            NixError::OracleFailed as u32
+        );
+    }
+
+    /// Writes errors.json next to the manifest, the code, name and user
+    /// message of every error for the ts client. Commit it with new errors.
+    #[test]
+    fn test_write_errors_json() {
+        let errors: Vec<NixError> = all_errors();
+        let mut json: String = "[\n".to_string();
+        for (index, error) in errors.iter().enumerate() {
+            let message: &str = error.user_message();
+            assert!(!message.contains(['"', '\\']), "{message} needs escaping");
+            json += &format!(
+                "  {{ \"code\": {}, \"name\": \"{:?}\", \"msg\": \"{}\" }}{}\n",
+                *error as u32,
+                error,
+                message,
+                if index + 1 < errors.len() { "," } else { "" },
+            );
+        }
+        json += "]\n";
+        std::fs::write(concat!(env!("CARGO_MANIFEST_DIR"), "/errors.json"), json).unwrap();
+    }
+}
@@ -8,48 +8,60 @@ pub enum NixInstruction {
     /// Create a market
     #[account(0, writable, signer, name = "admin", desc = "Admin account")]
     #[account(1, writable, name = "market", desc = "Market state account")]
-    #[account(2, name = "system_program", desc = "System program")]
-    #[account(3, name = "token_program", desc = "Token program")]
-    #[account(4, name = "token_program_22", desc = "Token Program 2022")]
-    #[account(5, name = "base_a_mint", desc = "Base A mint")]
-    #[account(6, name = "base_b_mint", desc = "Base B mint")]
-    // Base A accounts
-    #[account(7, writable, name = "base_a_fee_receiver", desc = "Base A fee receiver PDA")]
+    #[account(2, writable, name = "market_loans", desc = "Market loans account, allocated but uninitialized")]
+    #[account(3, name = "market_signer", desc = "Market signer PDA")]
+    #[account(4, name = "base_a_mint", desc = "Base A mint")]
+    #[account(5, name = "base_b_mint", desc = "Base B mint")]
+    #[account(6, writable, name = "base_a_fee_receiver", desc = "Base A fee receiver PDA")]
+    #[account(7, writable, name = "base_b_fee_receiver", desc = "Base B fee receiver PDA")]
     #[account(8, writable, name = "base_a_vault", desc = "Base A vault PDA")]
-    #[account(9, name = "base_a_marginfi_group", desc = "Base A Marginfi group")]
-    #[account(10, name = "base_a_marginfi_bank", desc = "Base A Marginfi bank")]
-    #[account(11, name = "base_a_marginfi_account", desc = "Base A Marginfi account PDA")]
-    // Base B accounts
-    #[account(12, writable, name = "base_b_fee_receiver", desc = "Base B fee receiver PDA")]
-    #[account(13, writable, name = "base_b_vault", desc = "Base B vault PDA")]
-    #[account(14, name = "base_b_marginfi_group", desc = "Base B Marginfi group")]
-    #[account(15, name = "base_b_marginfi_bank", desc = "Base B Marginfi bank")]
-    #[account(16, name = "base_b_marginfi_account", desc = "Base B Marginfi account PDA")]
+    #[account(9, writable, name = "base_b_vault", desc = "Base B vault PDA")]
+    #[account(10, name = "base_a_marginfi_group", desc = "Base A Marginfi group")]
+    #[account(11, name = "base_a_marginfi_bank", desc = "Base A Marginfi bank")]
+    #[account(12, writable, name = "base_a_marginfi_account", desc = "Base A Marginfi account PDA")]
+    #[account(13, name = "base_b_marginfi_group", desc = "Base B Marginfi group")]
+    #[account(14, name = "base_b_marginfi_bank", desc = "Base B Marginfi bank")]
+    #[account(15, writable, name = "base_b_marginfi_account", desc = "Base B Marginfi account PDA")]
+    #[account(16, name = "system_program", desc = "System program")]
+    #[account(17, name = "token_program", desc = "Token program")]
+    #[account(18, name = "token_program_22", desc = "Token Program 2022")]
+    // The market registry PDA of the mint pair can be appended, writable, to
+    // list the market there. The admin pays for the registry.
+    // For a one sided market, pass the system program as the group, bank and
+    // marginfi account of the mint marginfi does not support. Nothing can be
+    // deposited, lent or borrowed on that side.
     CreateMarket = 0,
 
     /// Create a market loan account
     #[account(0, writable, signer, name = "admin", desc = "Admin account")]
     #[account(1, writable, name = "market_loan_account", desc = "Market loan state account")]
-    #[account(2, name = "system_program", desc = "System program")]
+    #[account(2, name = "market", desc = "Market state account")]
+    #[account(3, writable, optional, name = "previous_market_loans", desc = "Last loans page of the market, linked to the new account")]
     CreateMarketLoanAccount = 1,
 
     /// Allocate a seat
     #[account(0, writable, signer, name = "payer", desc = "Payer")]
     #[account(1, writable, name = "market", desc = "Account holding all market state")]
     #[account(2, name = "system_program", desc = "System program")]
+    #[account(3, signer, optional, name = "allowlist_authority", desc = "Required on permissioned markets")]
     ClaimSeat = 2,
 
     /// Deposit
     #[account(0, writable, signer, name = "payer", desc = "Payer")]
     #[account(1, writable, name = "market", desc = "Account holding all market state")]
-    #[account(2, name = "mint", desc = "Required for token22 transfer_checked")]
+    #[account(2, name = "market_signer", desc = "Market signer PDA")]
     #[account(3, writable, name = "trader_token", desc = "Trader token account")]
-    #[account(4, name = "token_program", desc = "Token program(22), should be the version that aligns with the token being used")]
-    #[account(5, writable, name = "vault", desc = "vault PDA, seeds are [b'vault', market, mint]")]
-    #[account(6, name = "marginfi_group", desc = "Marginfi group")]
-    #[account(7, name = "marginfi_bank", desc = "Marginfi bank")]
-    #[account(8, name = "marginfi_account", desc = "Marginfi account PDA")]
-    #[account(9, name = "marginfi_liquidity_vault", desc = "Marginfi liquidity vault. constraint => bank.liquidity_vault == liquidity_vault")]
+    #[account(4, writable, name = "vault", desc = "vault PDA, seeds are [b'vault', market, mint]")]
+    #[account(5, name = "token_program", desc = "Token program(22), should be the version that aligns with the token being used")]
+    #[account(6, name = "mint", desc = "Required for token22 transfer_checked")]
+    #[account(7, name = "marginfi_group", desc = "Marginfi group")]
+    #[account(8, writable, name = "marginfi_bank", desc = "Marginfi bank")]
+    #[account(9, writable, name = "marginfi_account", desc = "Marginfi account PDA")]
+    #[account(10, writable, name = "marginfi_liquidity_vault", desc = "Marginfi liquidity vault. constraint => bank.liquidity_vault == liquidity_vault")]
+    // Token22 mints with a transfer hook also need the hook program, its
+    // extra account metas account and the accounts it lists, appended in order.
+    // With auto_claim_seat the payer also funds growing the market, which
+    // needs the system program in the transaction.
     Deposit = 3,
     
     /// Create global account for a given token.
@@ -59,6 +71,7 @@ pub enum NixInstruction {
     #[account(3, name = "mint", desc = "Mint for this global account")]
     #[account(4, writable, name = "global_vault", desc = "Global vault")]
     #[account(5, name = "token_program", desc = "Token program(22)")]
+    // Without params the global uses GAS_DEPOSIT_LAMPORTS.
     GlobalCreate = 4,
 
 
@@ -76,6 +89,10 @@ pub enum NixInstruction {
     #[account(3, writable, name = "global_vault", desc = "Global vault")]
     #[account(4, writable, name = "trader_token", desc = "Trader token account")]
     #[account(5, name = "token_program", desc = "Token program(22)")]
+    // A global linked to marginfi also needs its group, bank, marginfi
+    // account, liquidity vault and vault authority, appended in any order.
+    // Token22 mints with a transfer hook also need the hook program, its
+    // extra account metas account and the accounts it lists, in any order.
     GlobalDeposit = 6,
     
     /// Place an order on the market
@@ -95,7 +112,8 @@ pub enum NixInstruction {
     #[account(12, writable, name = "global_vault_2", desc = "Global vault 2 (optional)")]
     #[account(13, writable, name = "market_vault_2", desc = "Market vault 2 (optional)")]
     #[account(14, name = "token_program_2", desc = "Token program 2 (optional)")]
-    // Marginfi CPI accounts (2 required sets of 5 accounts each)
+    // Marginfi CPI accounts (2 sets of 5 accounts each). Post only asks only
+    // need the base set and global asks neither, the rest follows directly.
     #[account(15, name = "marginfi_group_1", desc = "Marginfi group 1")]
     #[account(16, name = "marginfi_bank_1", desc = "Marginfi bank 1")]
     #[account(17, name = "marginfi_account_1", desc = "Marginfi account 1")]
@@ -106,16 +124,559 @@ pub enum NixInstruction {
     #[account(22, name = "marginfi_account_2", desc = "Marginfi account 2")]
     #[account(23, writable, name = "marginfi_liquidity_vault_2", desc = "Marginfi liquidity vault 2")]
     #[account(24, name = "marginfi_liquidity_vault_authority_2", desc = "Marginfi vault authority 2")]
+    // Markets with an event queue also need it appended, writable.
+    // Borrows and withdraws also need the bank and oracle accounts of every
+    // active balance on the marginfi account, appended in any order.
+    // A global linked to marginfi also needs its group, bank, marginfi
+    // account, liquidity vault and vault authority, appended in any order.
+    // Bids from a cross margin seat append the CrossMarginSeat, its delegated
+    // marginfi account and the banks and oracles of that account's balances.
+    // Asks that can buy a loan sale also need its loans page, writable.
+    // The market stats PDA, writable, can be appended to keep the stats.
+    // Markets in auction mode also need their MarketAuction PDA appended.
+    // Markets with an allowed caller also need the instructions sysvar.
+    // Orders that defer their remainder append their PendingOrder, writable.
+    // Token22 mints with a transfer hook also need the hook program, its
+    // extra account metas account and the accounts it lists, in any order.
+    // Data is PlaceOrderParams behind a tag and version byte. Untagged data
+    // from older clients is still read as v1.
+    // Sets PlaceOrderReturnData as the return data.
     PlaceOrder = 7,
     
     /// Cancel an existing order
     #[account(0, writable, signer, name = "payer", desc = "Order owner/signer")]
     #[account(1, writable, name = "market_loans", desc = "Market loans account")]
     #[account(2, writable, name = "market", desc = "Market state account")]
-    #[account(3, writable, name = "base_global", desc = "Global account for base mint")]
+    #[account(3, writable, name = "base_global", desc = "Global account for base mint, or the system program when no global order is removed")]
     #[account(4, name = "system_program", desc = "System program")]
+    // Markets with an event queue also need it appended, writable.
+    // Sets CancelOrderReturnData as the return data.
     CancelOrder = 8,
 
+    /// Move accrued referral fees into the referrer's withdrawable balance
+    #[account(0, signer, name = "referrer", desc = "Referrer with a seat on the market")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    ReferrerClaim = 9,
+
+    /// Move accrued maker rebates into the trader's withdrawable balance
+    #[account(0, signer, name = "trader", desc = "Trader with a seat on the market")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    ClaimMakerRebate = 10,
+
+    /// Close an empty market and return all rent to the admin
+    #[account(0, writable, signer, name = "admin", desc = "Market admin, receives the rent")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    #[account(2, writable, name = "market_loans", desc = "Market loans account")]
+    #[account(3, name = "market_signer", desc = "Market signer PDA")]
+    #[account(4, writable, name = "base_a_vault", desc = "Base A vault PDA")]
+    #[account(5, writable, name = "base_b_vault", desc = "Base B vault PDA")]
+    #[account(6, writable, name = "base_a_fee_receiver", desc = "Base A fee receiver PDA")]
+    #[account(7, writable, name = "base_b_fee_receiver", desc = "Base B fee receiver PDA")]
+    #[account(8, writable, name = "base_a_marginfi_account", desc = "Base A Marginfi account PDA")]
+    #[account(9, writable, name = "base_b_marginfi_account", desc = "Base B Marginfi account PDA")]
+    #[account(10, name = "token_program", desc = "Token program")]
+    #[account(11, name = "token_program_22", desc = "Token Program 2022")]
+    #[account(12, name = "marginfi_program", desc = "Marginfi program")]
+    #[account(13, writable, name = "market_stats", desc = "Market stats PDA, closed if created")]
+    #[account(14, writable, name = "market_auction", desc = "Market auction PDA, closed if created")]
+    #[account(15, writable, name = "market_insurance", desc = "Market insurance PDA, closed if created and empty")]
+    #[account(16, writable, name = "event_queue", desc = "Event queue of the market, closed once consumed")]
+    #[account(17, writable, name = "market_registry", desc = "Registry of the mint pair, the market is taken out of it")]
+    // The system program stands in for the marginfi account of the side of
+    // a one sided market without a bank, and for the event queue of a market
+    // without one. Pages chained after market_loans follow, in order.
+    CloseMarket = 11,
+
+    /// Log aggregated bid and ask levels of one tree. Permissionless, at most once per slot per tree
+    #[account(0, writable, name = "market", desc = "Market state account")]
+    EmitBookSnapshot = 12,
+
+    /// Simulate a taker order against the book without changing state, log the expected fill and
+    /// return it with the best levels of the side it would take from
+    #[account(0, name = "market", desc = "Market state account")]
+    #[account(1, name = "base_marginfi_bank", desc = "Marginfi bank of the base mint")]
+    #[account(2, name = "quote_marginfi_bank", desc = "Marginfi bank of the quote mint")]
+    QuoteOrder = 13,
+
+    /// Attach an event queue to the market. Fills, cancels and loans are recorded on it from then on
+    #[account(0, writable, signer, name = "admin", desc = "Market admin, pays for the queue space")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    #[account(2, writable, name = "event_queue", desc = "Event queue account, allocated but uninitialized")]
+    CreateEventQueue = 14,
+
+    /// Drop processed events from the market event queue
+    #[account(0, signer, name = "admin", desc = "Market admin")]
+    #[account(1, name = "market", desc = "Market state account")]
+    #[account(2, writable, name = "event_queue", desc = "Event queue account")]
+    ConsumeEvents = 15,
+
+    /// Take the seat of the global trader with the smallest deposit when the global is full. The evictee is refunded in full
+    #[account(0, writable, signer, name = "payer", desc = "Payer, takes the seat")]
+    #[account(1, writable, name = "global", desc = "Global account")]
+    #[account(2, name = "mint", desc = "Mint for this global account")]
+    #[account(3, writable, name = "global_vault", desc = "Global vault")]
+    #[account(4, writable, name = "trader_token", desc = "Payer token account, funds the new deposit")]
+    #[account(5, writable, name = "evictee_token", desc = "Token account of the evicted trader, receives the refund")]
+    #[account(6, name = "token_program", desc = "Token program(22)")]
+    // A global linked to marginfi also needs its group, bank, marginfi
+    // account, liquidity vault and vault authority and the oracle of the
+    // bank, appended in any order. So are the transfer hook accounts of a
+    // token22 mint with a hook.
+    GlobalEvict = 16,
+
+    /// Bring a market created by an older program up to the current account layout. Markets from before MARKET_BLOCK_LAYOUT_VERSION are rejected. Permissionless
+    #[account(0, writable, signer, name = "payer", desc = "Payer, funds the rent of the larger header")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    #[account(2, name = "system_program", desc = "System program")]
+    MigrateMarket = 17,
+
+    /// Create a market at the canonical PDA of its mint pair and a nonce. base_a_mint has to sort before base_b_mint. Same accounts as CreateMarket. The nonce 0 market has to be permissionless with a protocol fee of at most MAX_CANONICAL_PROTOCOL_FEE_RATE_BPS
+    #[account(0, writable, signer, name = "admin", desc = "Admin account")]
+    #[account(1, writable, name = "market", desc = "Market PDA, seeds are [b'market', base_a_mint, base_b_mint, nonce]")]
+    #[account(2, writable, name = "market_loans", desc = "Market loans account, allocated but uninitialized")]
+    #[account(3, name = "market_signer", desc = "Market signer PDA")]
+    #[account(4, name = "base_a_mint", desc = "Base A mint")]
+    #[account(5, name = "base_b_mint", desc = "Base B mint")]
+    #[account(6, writable, name = "base_a_fee_receiver", desc = "Base A fee receiver PDA")]
+    #[account(7, writable, name = "base_b_fee_receiver", desc = "Base B fee receiver PDA")]
+    #[account(8, writable, name = "base_a_vault", desc = "Base A vault PDA")]
+    #[account(9, writable, name = "base_b_vault", desc = "Base B vault PDA")]
+    #[account(10, name = "base_a_marginfi_group", desc = "Base A Marginfi group")]
+    #[account(11, name = "base_a_marginfi_bank", desc = "Base A Marginfi bank")]
+    #[account(12, writable, name = "base_a_marginfi_account", desc = "Base A Marginfi account PDA")]
+    #[account(13, name = "base_b_marginfi_group", desc = "Base B Marginfi group")]
+    #[account(14, name = "base_b_marginfi_bank", desc = "Base B Marginfi bank")]
+    #[account(15, writable, name = "base_b_marginfi_account", desc = "Base B Marginfi account PDA")]
+    #[account(16, name = "system_program", desc = "System program")]
+    #[account(17, name = "token_program", desc = "Token program")]
+    #[account(18, name = "token_program_22", desc = "Token Program 2022")]
+    // The market registry PDA of the mint pair can be appended, writable, to
+    // list the market there. The admin pays for the registry.
+    CreateMarketPda = 18,
+
+    /// Remove expired orders from one side of a book. Permissionless, the cranker collects the gas deposits of expired global and good till time orders
+    #[account(0, writable, signer, name = "payer", desc = "Cranker, pays for new loan records")]
+    #[account(1, writable, name = "market_loans", desc = "Market loans account")]
+    #[account(2, writable, name = "market", desc = "Market state account")]
+    #[account(3, writable, name = "base_global", desc = "Global account for base mint, or the system program when no global order is removed")]
+    #[account(4, name = "system_program", desc = "System program")]
+    CleanExpiredOrders = 19,
+
+    /// Place an order on whichever tree gives the better rate. Same accounts as PlaceOrder
+    #[account(0, writable, signer, name = "payer", desc = "Trader placing the order")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    #[account(2, writable, name = "market_loans", desc = "Market loans account")]
+    #[account(3, name = "market_signer", desc = "Market signer PDA")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "base_mint", desc = "Base token mint")]
+    #[account(6, name = "quote_mint", desc = "Quote token mint")]
+    // Optional global trading accounts (up to 2 sets of 4 accounts each)
+    #[account(7, writable, name = "global_1", desc = "Global account 1 (optional)")]
+    #[account(8, writable, name = "global_vault_1", desc = "Global vault 1 (optional)")]
+    #[account(9, writable, name = "market_vault_1", desc = "Market vault 1 (optional)")]
+    #[account(10, name = "token_program_1", desc = "Token program 1 (optional)")]
+    #[account(11, writable, name = "global_2", desc = "Global account 2 (optional)")]
+    #[account(12, writable, name = "global_vault_2", desc = "Global vault 2 (optional)")]
+    #[account(13, writable, name = "market_vault_2", desc = "Market vault 2 (optional)")]
+    #[account(14, name = "token_program_2", desc = "Token program 2 (optional)")]
+    // Marginfi CPI accounts (2 sets of 5 accounts each). Post only asks only
+    // need the base set and global asks neither, the rest follows directly.
+    #[account(15, name = "marginfi_group_1", desc = "Marginfi group 1")]
+    #[account(16, name = "marginfi_bank_1", desc = "Marginfi bank 1")]
+    #[account(17, name = "marginfi_account_1", desc = "Marginfi account 1")]
+    #[account(18, writable, name = "marginfi_liquidity_vault_1", desc = "Marginfi liquidity vault 1")]
+    #[account(19, name = "marginfi_liquidity_vault_authority_1", desc = "Marginfi vault authority 1")]
+    #[account(20, name = "marginfi_group_2", desc = "Marginfi group 2")]
+    #[account(21, name = "marginfi_bank_2", desc = "Marginfi bank 2")]
+    #[account(22, name = "marginfi_account_2", desc = "Marginfi account 2")]
+    #[account(23, writable, name = "marginfi_liquidity_vault_2", desc = "Marginfi liquidity vault 2")]
+    #[account(24, name = "marginfi_liquidity_vault_authority_2", desc = "Marginfi vault authority 2")]
+    // Markets with an event queue also need it appended, writable.
+    // Borrows and withdraws also need the bank and oracle accounts of every
+    // active balance on the marginfi account, appended in any order.
+    // A global linked to marginfi also needs its group, bank, marginfi
+    // account, liquidity vault and vault authority, appended in any order.
+    // The market stats PDA, writable, can be appended to keep the stats.
+    // Markets in auction mode also need their MarketAuction PDA appended.
+    // Markets with an allowed caller also need the instructions sysvar.
+    PlaceOrderSmart = 20,
+
+    /// Return gas prepayments stranded on a global account to the trader who paid them. Permissionless
+    #[account(0, writable, name = "trader", desc = "Trader with a seat on the market, receives the lamports")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    #[account(2, writable, name = "global", desc = "Global account for base A or base B of the market")]
+    SweepStrandedGas = 21,
+
+    /// Shrink a resting ask in place. It keeps its sequence number and its place in the queue
+    #[account(0, signer, name = "payer", desc = "Order owner")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    // Markets with an event queue also need it appended, writable.
+    ReduceOrder = 22,
+
+    /// Opt a seat into cross margin with the trader's own marginfi account, still under the trader's authority
+    #[account(0, writable, signer, name = "trader", desc = "Trader with a seat on the market, pays the rent")]
+    #[account(1, name = "market", desc = "Market state account")]
+    #[account(2, writable, name = "cross_margin_seat", desc = "Cross margin seat PDA of the market and trader")]
+    #[account(3, name = "marginfi_account", desc = "Trader owned marginfi account in a group of the market")]
+    #[account(4, name = "system_program", desc = "System program")]
+    CreateCrossMarginSeat = 23,
+
+    /// Offer the lender side of a loan on the book. Taker asks buy it whole through PlaceOrder
+    #[account(0, writable, signer, name = "payer", desc = "Lender of the loan, pays for market expansion")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    #[account(2, name = "market_loans", desc = "Loans page holding the loan")]
+    #[account(3, name = "system_program", desc = "System program")]
+    PlaceLoanSale = 24,
+
+    /// Create the account keeping rolling volume and open interest of a market. Permissionless
+    #[account(0, writable, signer, name = "payer", desc = "Pays the rent")]
+    #[account(1, name = "market", desc = "Market state account")]
+    #[account(2, writable, name = "market_stats", desc = "Market stats PDA of the market")]
+    #[account(3, name = "system_program", desc = "System program")]
+    CreateMarketStats = 25,
+
+    /// Resume matching an order that deferred its remainder at its match limit. Same accounts as PlaceOrder
+    #[account(0, writable, signer, name = "payer", desc = "Trader of the pending order")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    #[account(2, writable, name = "market_loans", desc = "Market loans account")]
+    #[account(3, name = "market_signer", desc = "Market signer PDA")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "base_mint", desc = "Base token mint")]
+    #[account(6, name = "quote_mint", desc = "Quote token mint")]
+    // Optional global trading accounts (up to 2 sets of 4 accounts each)
+    #[account(7, writable, name = "global_1", desc = "Global account 1 (optional)")]
+    #[account(8, writable, name = "global_vault_1", desc = "Global vault 1 (optional)")]
+    #[account(9, writable, name = "market_vault_1", desc = "Market vault 1 (optional)")]
+    #[account(10, name = "token_program_1", desc = "Token program 1 (optional)")]
+    #[account(11, writable, name = "global_2", desc = "Global account 2 (optional)")]
+    #[account(12, writable, name = "global_vault_2", desc = "Global vault 2 (optional)")]
+    #[account(13, writable, name = "market_vault_2", desc = "Market vault 2 (optional)")]
+    #[account(14, name = "token_program_2", desc = "Token program 2 (optional)")]
+    // Marginfi CPI accounts (2 sets of 5 accounts each). Post only asks only
+    // need the base set and global asks neither, the rest follows directly.
+    #[account(15, name = "marginfi_group_1", desc = "Marginfi group 1")]
+    #[account(16, name = "marginfi_bank_1", desc = "Marginfi bank 1")]
+    #[account(17, name = "marginfi_account_1", desc = "Marginfi account 1")]
+    #[account(18, writable, name = "marginfi_liquidity_vault_1", desc = "Marginfi liquidity vault 1")]
+    #[account(19, name = "marginfi_liquidity_vault_authority_1", desc = "Marginfi vault authority 1")]
+    #[account(20, name = "marginfi_group_2", desc = "Marginfi group 2")]
+    #[account(21, name = "marginfi_bank_2", desc = "Marginfi bank 2")]
+    #[account(22, name = "marginfi_account_2", desc = "Marginfi account 2")]
+    #[account(23, writable, name = "marginfi_liquidity_vault_2", desc = "Marginfi liquidity vault 2")]
+    #[account(24, name = "marginfi_liquidity_vault_authority_2", desc = "Marginfi vault authority 2")]
+    // Markets with an event queue also need it appended, writable.
+    // Borrows and withdraws also need the bank and oracle accounts of every
+    // active balance on the marginfi account, appended in any order.
+    // A global linked to marginfi also needs its group, bank, marginfi
+    // account, liquidity vault and vault authority, appended in any order.
+    // The market stats PDA, writable, can be appended to keep the stats.
+    // Markets in auction mode also need their MarketAuction PDA appended.
+    // Markets with an allowed caller also need the instructions sysvar.
+    // The PendingOrder PDA of the market and payer, writable, is appended.
+    // It is closed to the payer once nothing is deferred again.
+    ContinueOrder = 26,
+
+    /// Change the spread and remaining cycles of a resting reverse order. It keeps its rate and its place in the queue
+    #[account(0, signer, name = "payer", desc = "Order owner")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    ModifyOrder = 27,
+
+    /// Set or turn off the oracle circuit breaker of a market. Also lifts a trip in progress
+    #[account(0, signer, name = "admin", desc = "Market admin")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    SetCircuitBreaker = 28,
+
+    /// Put a market in auction mode: orders collect over a window and match in a batch at a single clearing rate
+    #[account(0, writable, signer, name = "admin", desc = "Market admin, pays the rent")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    #[account(2, writable, name = "market_auction", desc = "Market auction PDA of the market")]
+    #[account(3, name = "system_program", desc = "System program")]
+    CreateMarketAuction = 29,
+
+    /// Find the clearing rate of each tree once the auction window of a market closes and start settling at it. Permissionless
+    #[account(0, name = "market", desc = "Market state account")]
+    #[account(1, writable, name = "market_auction", desc = "Market auction PDA of the market")]
+    #[account(2, name = "base_a_marginfi_bank", desc = "Marginfi bank of base A")]
+    #[account(3, name = "base_b_marginfi_bank", desc = "Marginfi bank of base B")]
+    RunAuction = 30,
+
+    /// Deposit into the collateral of a resting bid or an active loan of the payer
+    #[account(0, writable, signer, name = "payer", desc = "Payer")]
+    #[account(1, writable, name = "market", desc = "Account holding all market state")]
+    #[account(2, name = "market_signer", desc = "Market signer PDA")]
+    #[account(3, writable, name = "trader_token", desc = "Trader token account")]
+    #[account(4, writable, name = "vault", desc = "vault PDA, seeds are [b'vault', market, mint]")]
+    #[account(5, name = "token_program", desc = "Token program(22), should be the version that aligns with the token being used")]
+    #[account(6, name = "mint", desc = "Required for token22 transfer_checked")]
+    #[account(7, name = "marginfi_group", desc = "Marginfi group")]
+    #[account(8, writable, name = "marginfi_bank", desc = "Marginfi bank")]
+    #[account(9, writable, name = "marginfi_account", desc = "Marginfi account PDA")]
+    #[account(10, writable, name = "marginfi_liquidity_vault", desc = "Marginfi liquidity vault. constraint => bank.liquidity_vault == liquidity_vault")]
+    // Same accounts as Deposit, including any transfer hook accounts. Topping
+    // up a loan also needs its MarketLoans page, writable, appended last.
+    TopUpCollateral = 31,
+
+    /// Withdraw collateral an active loan of the payer does not need to stay within the LTV buffer
+    #[account(0, writable, signer, name = "payer", desc = "Borrower of the loan")]
+    #[account(1, name = "market", desc = "Account holding all market state")]
+    #[account(2, writable, name = "market_loans", desc = "MarketLoans page holding the loan")]
+    #[account(3, name = "market_signer", desc = "Market signer PDA, authority of the vault and the marginfi account")]
+    #[account(4, name = "mint", desc = "Collateral mint")]
+    #[account(5, writable, name = "trader_token", desc = "Trader token account of the collateral mint")]
+    #[account(6, name = "token_program", desc = "Token program(22), should be the version that aligns with the token being used")]
+    #[account(7, writable, name = "vault", desc = "vault PDA, seeds are [b'vault', market, mint]")]
+    #[account(8, name = "marginfi_group", desc = "Marginfi group")]
+    #[account(9, writable, name = "marginfi_bank", desc = "Marginfi bank of the collateral mint")]
+    #[account(10, writable, name = "marginfi_account", desc = "Marginfi account PDA of the collateral mint")]
+    #[account(11, writable, name = "marginfi_liquidity_vault", desc = "Marginfi liquidity vault. constraint => bank.liquidity_vault == liquidity_vault")]
+    #[account(12, name = "marginfi_liquidity_vault_authority", desc = "Marginfi liquidity vault authority")]
+    #[account(13, name = "liability_marginfi_bank", desc = "Marginfi bank of the mint the loan lent")]
+    #[account(14, name = "liability_mint", desc = "Mint the loan lent")]
+    // Oracles of both banks and the banks and oracles marginfi health checks
+    // the withdraw against are appended after, then the transfer hook
+    // accounts of a token22 collateral mint with a hook.
+    ReleaseCollateral = 32,
+
+    /// Move a global account created before the gas deposit or the marginfi link were stored on it to the current layout. Permissionless
+    #[account(0, writable, signer, name = "payer", desc = "Payer, funds the rent of the larger header")]
+    #[account(1, writable, name = "global", desc = "Global account")]
+    #[account(2, name = "system_program", desc = "System program")]
+    MigrateGlobal = 33,
+
+    /// Move what a repaid loan owes into the withdrawable balances of its lender and borrower and close it. Permissionless
+    #[account(0, signer, name = "payer", desc = "Payer")]
+    #[account(1, writable, name = "market", desc = "Account holding all market state")]
+    #[account(2, writable, name = "market_loans", desc = "MarketLoans page holding the loan")]
+    SettleLoanProceeds = 34,
+
+    /// Log the collateral value, liability value, LTV and distance to liquidation of the loans of a seat. Read only
+    #[account(0, name = "market", desc = "Account holding all market state")]
+    #[account(1, name = "market_loans", desc = "MarketLoans page holding the loans")]
+    #[account(2, name = "base_a_marginfi_bank", desc = "Marginfi bank of base a")]
+    #[account(3, name = "base_b_marginfi_bank", desc = "Marginfi bank of base b")]
+    #[account(4, name = "base_a_mint", desc = "Base a mint")]
+    #[account(5, name = "base_b_mint", desc = "Base b mint")]
+    // Oracles of both banks are appended after.
+    CheckHealth = 35,
+
+    /// Mark an active loan whose collateral no longer covers its liability as defaulted and write it off against the lender. Permissionless
+    #[account(0, signer, name = "payer", desc = "Payer")]
+    #[account(1, writable, name = "market", desc = "Account holding all market state")]
+    #[account(2, writable, name = "market_loans", desc = "MarketLoans page holding the loan")]
+    #[account(3, name = "base_a_marginfi_bank", desc = "Marginfi bank of base a")]
+    #[account(4, name = "base_b_marginfi_bank", desc = "Marginfi bank of base b")]
+    #[account(5, name = "base_a_mint", desc = "Base a mint")]
+    #[account(6, name = "base_b_mint", desc = "Base b mint")]
+    // Oracles of both banks are appended after. The MarketInsurance PDA of
+    // the market, when it has one, is appended last and covers what it can
+    // of the shortfall.
+    MarkDefault = 36,
+
+    /// Create the insurance fund of a market, which takes a share of its protocol fees
+    #[account(0, writable, signer, name = "admin", desc = "Market admin, pays the rent")]
+    #[account(1, name = "market", desc = "Market state account")]
+    #[account(2, writable, name = "market_insurance", desc = "Market insurance PDA of the market")]
+    #[account(3, name = "system_program", desc = "System program")]
+    CreateMarketInsurance = 37,
+
+    /// Change the share of the protocol fees of a market that goes to its insurance fund
+    #[account(0, signer, name = "admin", desc = "Market admin")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    #[account(2, writable, name = "market_insurance", desc = "Market insurance PDA of the market")]
+    SetInsuranceFee = 38,
+
+    /// Deposit into the insurance fund of a market. Permissionless
+    #[account(0, writable, signer, name = "payer", desc = "Payer")]
+    #[account(1, writable, name = "market", desc = "Account holding all market state")]
+    #[account(2, name = "market_signer", desc = "Market signer PDA")]
+    #[account(3, writable, name = "trader_token", desc = "Trader token account")]
+    #[account(4, writable, name = "vault", desc = "vault PDA, seeds are [b'vault', market, mint]")]
+    #[account(5, name = "token_program", desc = "Token program(22), should be the version that aligns with the token being used")]
+    #[account(6, name = "mint", desc = "Required for token22 transfer_checked")]
+    #[account(7, name = "marginfi_group", desc = "Marginfi group")]
+    #[account(8, writable, name = "marginfi_bank", desc = "Marginfi bank")]
+    #[account(9, writable, name = "marginfi_account", desc = "Marginfi account PDA")]
+    #[account(10, writable, name = "marginfi_liquidity_vault", desc = "Marginfi liquidity vault. constraint => bank.liquidity_vault == liquidity_vault")]
+    // Same accounts as Deposit, including any transfer hook accounts. The
+    // MarketInsurance PDA of the market is appended last, writable.
+    TopUpInsurance = 39,
+
+    /// Withdraw from the insurance fund of a market to the admin
+    #[account(0, writable, signer, name = "admin", desc = "Market admin")]
+    #[account(1, writable, name = "market", desc = "Account holding all market state")]
+    #[account(2, writable, name = "market_insurance", desc = "Market insurance PDA of the market")]
+    #[account(3, name = "market_signer", desc = "Market signer PDA, authority of the vault and the marginfi account")]
+    #[account(4, name = "mint", desc = "Mint to withdraw")]
+    #[account(5, writable, name = "admin_token", desc = "Admin token account of the mint")]
+    #[account(6, name = "token_program", desc = "Token program(22), should be the version that aligns with the token being used")]
+    #[account(7, writable, name = "vault", desc = "vault PDA, seeds are [b'vault', market, mint]")]
+    #[account(8, name = "marginfi_group", desc = "Marginfi group")]
+    #[account(9, writable, name = "marginfi_bank", desc = "Marginfi bank of the mint")]
+    #[account(10, writable, name = "marginfi_account", desc = "Marginfi account PDA of the mint")]
+    #[account(11, writable, name = "marginfi_liquidity_vault", desc = "Marginfi liquidity vault. constraint => bank.liquidity_vault == liquidity_vault")]
+    #[account(12, name = "marginfi_liquidity_vault_authority", desc = "Marginfi liquidity vault authority")]
+    // The banks and oracles marginfi health checks the withdraw against are
+    // appended after, then the transfer hook accounts of a token22 mint with
+    // a hook.
+    DrawInsurance = 40,
+
+    /// Set or turn off the limit on new resting orders a seat can place per window of slots
+    #[account(0, signer, name = "admin", desc = "Market admin")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    SetOrderRateLimit = 41,
+
+    /// Withdraw the marginfi emissions a bank of the market earned, to the fee receiver of the emissions mint or pro rata to the seats
+    #[account(0, writable, signer, name = "admin", desc = "Market admin, pays the rent of a new fee receiver")]
+    #[account(1, writable, name = "market", desc = "Account holding all market state")]
+    #[account(2, name = "market_signer", desc = "Market signer PDA, authority of the marginfi account")]
+    #[account(3, writable, name = "emissions_mint", desc = "Mint the bank pays emissions in")]
+    #[account(4, name = "emissions_auth", desc = "Marginfi emissions auth PDA of the bank and mint")]
+    #[account(5, writable, name = "emissions_vault", desc = "Marginfi emissions vault PDA of the bank and mint")]
+    #[account(6, writable, name = "destination", desc = "Fee receiver PDA of the emissions mint, created if missing, or the market vault of the bank when paying seats")]
+    #[account(7, name = "token_program", desc = "Token program(22) of the emissions mint")]
+    #[account(8, name = "system_program", desc = "System program")]
+    #[account(9, name = "marginfi_group", desc = "Marginfi group")]
+    #[account(10, writable, name = "marginfi_bank", desc = "Marginfi bank")]
+    #[account(11, writable, name = "marginfi_account", desc = "Marginfi account PDA of the bank mint")]
+    #[account(12, writable, name = "marginfi_liquidity_vault", desc = "Marginfi liquidity vault. constraint => bank.liquidity_vault == liquidity_vault")]
+    #[account(13, name = "marginfi_liquidity_vault_authority", desc = "Marginfi liquidity vault authority")]
+    HarvestEmissions = 42,
+
+    /// Lend the deposits of a global to the marginfi bank a market uses for its mint. Balances become asset shares of the bank. Permissionless
+    #[account(0, writable, signer, name = "payer", desc = "Payer, funds the rent of the marginfi account")]
+    #[account(1, writable, name = "global", desc = "Global account")]
+    #[account(2, name = "mint", desc = "Mint for this global account")]
+    #[account(3, writable, name = "global_vault", desc = "Global vault, authority of the marginfi account")]
+    #[account(4, name = "market", desc = "Market that lends the mint to the bank")]
+    #[account(5, writable, name = "marginfi_group", desc = "Marginfi group of the bank")]
+    #[account(6, writable, name = "marginfi_bank", desc = "Marginfi bank of the market for the mint")]
+    #[account(7, writable, name = "marginfi_account", desc = "Uninitialized marginfi account PDA of the global and mint")]
+    #[account(8, writable, name = "marginfi_liquidity_vault", desc = "Marginfi liquidity vault. constraint => bank.liquidity_vault == liquidity_vault")]
+    #[account(9, name = "token_program", desc = "Token program(22)")]
+    #[account(10, name = "system_program", desc = "System program")]
+    GlobalLinkMarginfi = 43,
+
+    /// Place an order on the base A tree of the market. Same accounts and data as PlaceOrder
+    #[account(0, writable, signer, name = "payer", desc = "Trader placing the order")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    #[account(2, writable, name = "market_loans", desc = "Market loans account")]
+    #[account(3, name = "market_signer", desc = "Market signer PDA")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "base_mint", desc = "Base token mint")]
+    #[account(6, name = "quote_mint", desc = "Quote token mint")]
+    // Optional global trading accounts (up to 2 sets of 4 accounts each)
+    #[account(7, writable, name = "global_1", desc = "Global account 1 (optional)")]
+    #[account(8, writable, name = "global_vault_1", desc = "Global vault 1 (optional)")]
+    #[account(9, writable, name = "market_vault_1", desc = "Market vault 1 (optional)")]
+    #[account(10, name = "token_program_1", desc = "Token program 1 (optional)")]
+    #[account(11, writable, name = "global_2", desc = "Global account 2 (optional)")]
+    #[account(12, writable, name = "global_vault_2", desc = "Global vault 2 (optional)")]
+    #[account(13, writable, name = "market_vault_2", desc = "Market vault 2 (optional)")]
+    #[account(14, name = "token_program_2", desc = "Token program 2 (optional)")]
+    // Marginfi CPI accounts (2 sets of 5 accounts each). Post only asks only
+    // need the base set and global asks neither, the rest follows directly.
+    #[account(15, name = "marginfi_group_1", desc = "Marginfi group 1")]
+    #[account(16, name = "marginfi_bank_1", desc = "Marginfi bank 1")]
+    #[account(17, name = "marginfi_account_1", desc = "Marginfi account 1")]
+    #[account(18, writable, name = "marginfi_liquidity_vault_1", desc = "Marginfi liquidity vault 1")]
+    #[account(19, name = "marginfi_liquidity_vault_authority_1", desc = "Marginfi vault authority 1")]
+    #[account(20, name = "marginfi_group_2", desc = "Marginfi group 2")]
+    #[account(21, name = "marginfi_bank_2", desc = "Marginfi bank 2")]
+    #[account(22, name = "marginfi_account_2", desc = "Marginfi account 2")]
+    #[account(23, writable, name = "marginfi_liquidity_vault_2", desc = "Marginfi liquidity vault 2")]
+    #[account(24, name = "marginfi_liquidity_vault_authority_2", desc = "Marginfi vault authority 2")]
+    // Markets with an event queue also need it appended, writable.
+    // Borrows and withdraws also need the bank and oracle accounts of every
+    // active balance on the marginfi account, appended in any order.
+    // A global linked to marginfi also needs its group, bank, marginfi
+    // account, liquidity vault and vault authority, appended in any order.
+    // Bids from a cross margin seat append the CrossMarginSeat, its delegated
+    // marginfi account and the banks and oracles of that account's balances.
+    // Asks that can buy a loan sale also need its loans page, writable.
+    // The market stats PDA, writable, can be appended to keep the stats.
+    // Markets in auction mode also need their MarketAuction PDA appended.
+    // Markets with an allowed caller also need the instructions sysvar.
+    // Orders that defer their remainder append their PendingOrder, writable.
+    // Token22 mints with a transfer hook also need the hook program, its
+    // extra account metas account and the accounts it lists, in any order.
+    // Data is PlaceOrderParams behind a tag and version byte, like PlaceOrder.
+    // Its use_a_tree has to match the tree of the instruction.
+    PlaceOrderBaseA = 44,
+
+    /// Place an order on the base B tree of the market. Same accounts and data as PlaceOrder
+    #[account(0, writable, signer, name = "payer", desc = "Trader placing the order")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    #[account(2, writable, name = "market_loans", desc = "Market loans account")]
+    #[account(3, name = "market_signer", desc = "Market signer PDA")]
+    #[account(4, name = "system_program", desc = "System program")]
+    #[account(5, name = "base_mint", desc = "Base token mint")]
+    #[account(6, name = "quote_mint", desc = "Quote token mint")]
+    // Optional global trading accounts (up to 2 sets of 4 accounts each)
+    #[account(7, writable, name = "global_1", desc = "Global account 1 (optional)")]
+    #[account(8, writable, name = "global_vault_1", desc = "Global vault 1 (optional)")]
+    #[account(9, writable, name = "market_vault_1", desc = "Market vault 1 (optional)")]
+    #[account(10, name = "token_program_1", desc = "Token program 1 (optional)")]
+    #[account(11, writable, name = "global_2", desc = "Global account 2 (optional)")]
+    #[account(12, writable, name = "global_vault_2", desc = "Global vault 2 (optional)")]
+    #[account(13, writable, name = "market_vault_2", desc = "Market vault 2 (optional)")]
+    #[account(14, name = "token_program_2", desc = "Token program 2 (optional)")]
+    // Marginfi CPI accounts (2 sets of 5 accounts each). Post only asks only
+    // need the base set and global asks neither, the rest follows directly.
+    #[account(15, name = "marginfi_group_1", desc = "Marginfi group 1")]
+    #[account(16, name = "marginfi_bank_1", desc = "Marginfi bank 1")]
+    #[account(17, name = "marginfi_account_1", desc = "Marginfi account 1")]
+    #[account(18, writable, name = "marginfi_liquidity_vault_1", desc = "Marginfi liquidity vault 1")]
+    #[account(19, name = "marginfi_liquidity_vault_authority_1", desc = "Marginfi vault authority 1")]
+    #[account(20, name = "marginfi_group_2", desc = "Marginfi group 2")]
+    #[account(21, name = "marginfi_bank_2", desc = "Marginfi bank 2")]
+    #[account(22, name = "marginfi_account_2", desc = "Marginfi account 2")]
+    #[account(23, writable, name = "marginfi_liquidity_vault_2", desc = "Marginfi liquidity vault 2")]
+    #[account(24, name = "marginfi_liquidity_vault_authority_2", desc = "Marginfi vault authority 2")]
+    // Markets with an event queue also need it appended, writable.
+    // Borrows and withdraws also need the bank and oracle accounts of every
+    // active balance on the marginfi account, appended in any order.
+    // A global linked to marginfi also needs its group, bank, marginfi
+    // account, liquidity vault and vault authority, appended in any order.
+    // Bids from a cross margin seat append the CrossMarginSeat, its delegated
+    // marginfi account and the banks and oracles of that account's balances.
+    // Asks that can buy a loan sale also need its loans page, writable.
+    // The market stats PDA, writable, can be appended to keep the stats.
+    // Markets in auction mode also need their MarketAuction PDA appended.
+    // Markets with an allowed caller also need the instructions sysvar.
+    // Orders that defer their remainder append their PendingOrder, writable.
+    // Token22 mints with a transfer hook also need the hook program, its
+    // extra account metas account and the accounts it lists, in any order.
+    // Data is PlaceOrderParams behind a tag and version byte, like PlaceOrder.
+    // Its use_a_tree has to match the tree of the instruction.
+    PlaceOrderBaseB = 45,
+
+    /// Restrict placing orders on the market to CPIs from one program, or lift the restriction
+    #[account(0, signer, name = "admin", desc = "Market admin")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    SetAllowedCaller = 46,
+
+    /// Turn the extra diagnostic logs of a market on or off
+    #[account(0, signer, name = "admin", desc = "Market admin")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    SetDebugLogs = 47,
+
+    /// Place two orders, usually on different markets, that both go through or both fail
+    #[account(0, writable, signer, name = "payer", desc = "Trader placing the orders")]
+    #[account(1, writable, name = "market", desc = "Market of the first leg")]
+    // The accounts of the first leg, as for PlaceOrder with whatever it
+    // appends, then the accounts of the second leg the same way. The payer
+    // starts both. first_leg_num_accounts in the data splits them.
+    // Data is RouteOrdersParams. Its PlaceOrderParams are the current layout,
+    // without the tag and version byte of PlaceOrder.
+    // Sets RouteOrdersReturnData as the return data.
+    RouteOrders = 48,
+
+    /// Record the current config of a marginfi bank of the market after marginfi changed it
+    #[account(0, signer, name = "admin", desc = "Market admin")]
+    #[account(1, writable, name = "market", desc = "Market state account")]
+    #[account(2, name = "marginfi_bank", desc = "Marginfi bank of either base of the market")]
+    AcknowledgeBankConfigChange = 49,
+
 }
 
 impl NixInstruction {
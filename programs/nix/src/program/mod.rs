@@ -1,7 +1,9 @@
 pub mod error;
+#[cfg(feature = "program")]
 pub mod processor;
 pub mod instruction;
 
 pub use error::*;
+#[cfg(feature = "program")]
 pub use processor::*;
 pub use instruction::*;
\ No newline at end of file
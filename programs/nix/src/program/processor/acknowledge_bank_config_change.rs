@@ -0,0 +1,42 @@
+use std::cell::RefMut;
+
+use hypertree::{get_mut_helper, trace};
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, AcknowledgeBankConfigChangeLog},
+    marginfi_utils::get_bank_config_hash,
+    state::MarketFixed,
+    validation::loaders::AcknowledgeBankConfigChangeContext,
+};
+
+pub(crate) fn process_acknowledge_bank_config_change(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    _data: &[u8],
+) -> ProgramResult {
+    trace!("process_acknowledge_bank_config_change accts={accounts:?}");
+    let AcknowledgeBankConfigChangeContext {
+        admin,
+        market,
+        marginfi_bank,
+        is_base_a,
+    } = AcknowledgeBankConfigChangeContext::load(accounts)?;
+
+    // The admin signs off on the new weights and oracles as they are now.
+    // Orders already resting keep the collateral they were backed with.
+    let new_bank_config_hash: u64 = get_bank_config_hash(&marginfi_bank.get_fixed()?.config);
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    let market_fixed: &mut MarketFixed = get_mut_helper::<MarketFixed>(market_data, 0_u32);
+    let old_bank_config_hash: u64 = market_fixed.get_bank_config_hash(is_base_a);
+    market_fixed.set_bank_config_hash(is_base_a, new_bank_config_hash);
+
+    emit_stack(AcknowledgeBankConfigChangeLog {
+        market: *market.key,
+        admin: *admin.key,
+        marginfi_bank: *marginfi_bank.key,
+        old_bank_config_hash,
+        new_bank_config_hash,
+    })
+}
@@ -2,23 +2,49 @@ use std::cell::RefMut;
 
 use borsh::{BorshDeserialize, BorshSerialize};
 use hypertree::{get_helper, DataIndex, RBNode};
-use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+use shank::ShankType;
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, program::set_return_data, pubkey::Pubkey,
+};
 
 use crate::{
     logs::{emit_stack, CancelOrderLog},
-    program::{get_mut_dynamic_account, get_trader_index_with_hint},
+    program::{
+        get_mut_dynamic_account, get_trader_index_with_hint, pay_gas_deposits, push_market_events,
+        sweep_expired_orders_of_trader,
+    },
+    quantities::WrappedI80F48,
     require,
-    state::{MarketDataTreeNodeType, MarketRefMut, RestingOrder, MARKET_BLOCK_SIZE},
+    state::{
+        get_helper_seat, MarketDataTreeNodeType, MarketEvent, MarketEventType, MarketRefMut,
+        RestingOrder, MARKET_BLOCK_SIZE,
+    },
+    utils::get_now_slot,
     validation::loaders::CancelOrderContext,
 };
 
-#[derive(BorshDeserialize, BorshSerialize)]
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
 pub struct CancelOrderParams {
     pub trader_index_hint: Option<DataIndex>,
     pub order_sequence_number: u64,
     pub order_index_hint: Option<DataIndex>,
+    /// Tree the order rests on. Orders created by reverse fills rest on the
+    /// other tree from the order that filled, and are cancelled on that tree.
     pub use_a_tree: bool,
+    /// Up to this many of the payer's other expired orders are removed too,
+    /// and their collateral returned to the seat. Capped at
+    /// MAX_SWEPT_EXPIRED_ORDERS. Zero skips the scan.
+    pub max_expired_orders_to_sweep: u8,
+}
+
+/// Return data of CancelOrder, for programs that CPI into nix.
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct CancelOrderReturnData {
+    pub order_sequence_number: u64,
+    /// Tree the order was on, the one given by use_a_tree.
+    pub cancelled_on_a_tree: bool,
 }
+
 pub fn process_cancel_order<'a>(
     program_id: &Pubkey,
     accounts: &'a [AccountInfo<'a>],
@@ -38,6 +64,7 @@ pub fn process_cancel_order_core<'a>(
         order_sequence_number,
         order_index_hint,
         use_a_tree,
+        max_expired_orders_to_sweep,
     } = params;
     let cancel_order_context: CancelOrderContext = CancelOrderContext::load(accounts, use_a_tree)?;
 
@@ -45,7 +72,7 @@ pub fn process_cancel_order_core<'a>(
         payer,
         market,
         market_loans,
-        base_global,
+        base_global_opt,
         system_program,
         ..
     } = cancel_order_context;
@@ -56,18 +83,16 @@ pub fn process_cancel_order_core<'a>(
     let trader_index: DataIndex =
         get_trader_index_with_hint(trader_index_hint, &dynamic_account, &payer)?;
 
-    match order_index_hint {
-        None => {
-            dynamic_account.cancel_order(
-                use_a_tree,
-                trader_index,
-                order_sequence_number,
-                &base_global,
-                payer.clone(),
-                system_program,
-                &market_loans,
-            )?;
-        }
+    let gas_deposit_lamports: u64 = match order_index_hint {
+        None => dynamic_account.cancel_order(
+            use_a_tree,
+            trader_index,
+            order_sequence_number,
+            &base_global_opt,
+            payer.clone(),
+            system_program,
+            &market_loans,
+        )?,
         Some(hinted_cancel_index) => {
             // Simple sanity check on the hint given. Make sure that it
             // aligns with block boundaries. We do a check that it is an
@@ -104,17 +129,53 @@ pub fn process_cancel_order_core<'a>(
             dynamic_account.cancel_order_by_index(
                 use_a_tree,
                 hinted_cancel_index,
-                &base_global,
+                &base_global_opt,
                 &Some(payer.clone()),
                 &Some(system_program),
                 &market_loans,
-            )?;
+            )?
         }
     };
+    pay_gas_deposits(&market, &payer, gas_deposit_lamports)?;
+    push_market_events(
+        dynamic_account.fixed,
+        market.key,
+        accounts,
+        &[MarketEvent::new(
+            MarketEventType::Cancel,
+            get_now_slot() as u64,
+            *payer.key,
+            Pubkey::default(),
+            order_sequence_number,
+            WrappedI80F48::default(),
+            WrappedI80F48::default(),
+            0,
+            use_a_tree,
+        )],
+    )?;
+    let time_on_book: u64 = get_helper_seat(&dynamic_account.dynamic, trader_index)
+        .get_value()
+        .get_time_on_book(use_a_tree);
     emit_stack(CancelOrderLog {
         market: *market.key,
         trader: *payer.key,
         order_sequence_number,
+        time_on_book,
     })?;
+    sweep_expired_orders_of_trader(
+        &mut dynamic_account,
+        &market,
+        &payer,
+        trader_index,
+        max_expired_orders_to_sweep,
+    )?;
+
+    set_return_data(
+        &CancelOrderReturnData {
+            order_sequence_number,
+            cancelled_on_a_tree: use_a_tree,
+        }
+        .try_to_vec()?,
+    );
     Ok(())
 }
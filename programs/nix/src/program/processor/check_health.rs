@@ -0,0 +1,211 @@
+use std::cell::Ref;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use fixed::types::I80F48;
+use hypertree::{trace, DataIndex};
+use marginfi::state::{
+    marginfi_group::Bank,
+    price::{OraclePriceType, PriceBias},
+};
+use shank::ShankType;
+use solana_program::{
+    account_info::AccountInfo, clock::Clock, entrypoint::ProgramResult, pubkey::Pubkey,
+    sysvar::Sysvar,
+};
+
+use crate::{
+    logs::{emit_stack, CheckHealthLog},
+    marginfi_utils::{get_weighted_value_usd, BankShareValues, OraclePrices},
+    math::get_buffer_f,
+    program::NixError,
+    require,
+    state::{ActiveLoan, MarketLoansRef, MarketRef},
+    utils::assert_already_has_seat,
+    validation::loaders::CheckHealthContext,
+};
+
+use super::get_dynamic_account;
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct CheckHealthParams {
+    /// Borrower whose loans are checked.
+    pub trader: Pubkey,
+    /// Only check this loan of the trader. None for all its active loans on
+    /// the page.
+    pub loan_sequence_number: Option<u64>,
+}
+
+pub fn process_check_health<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+) -> ProgramResult {
+    let params: CheckHealthParams = CheckHealthParams::try_from_slice(data)?;
+    process_check_health_core(program_id, accounts, params)
+}
+
+/// Logs how well the loans of a seat are backed, priced the way
+/// ReleaseCollateral prices them. Writes nothing, so front ends and keepers
+/// can simulate it instead of redoing the margin math. Banks are read as
+/// marginfi last accrued them.
+pub fn process_check_health_core<'a>(
+    _program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    params: CheckHealthParams,
+) -> ProgramResult {
+    trace!("process_check_health accts={accounts:?}");
+    let CheckHealthParams {
+        trader,
+        loan_sequence_number,
+    } = params;
+    let CheckHealthContext {
+        market,
+        market_loans,
+        base_a_marginfi_bank,
+        base_b_marginfi_bank,
+        base_a_mint,
+        base_b_mint,
+    } = CheckHealthContext::load(accounts)?;
+
+    let (trader_index, ltv_buffer_bps) = {
+        let market_data: Ref<&mut [u8]> = market.try_borrow_data()?;
+        let dynamic_account: MarketRef = get_dynamic_account(&market_data);
+        let trader_index: DataIndex = dynamic_account.get_trader_index(&trader);
+        (trader_index, dynamic_account.fixed.get_ltv_buffer_bps())
+    };
+    assert_already_has_seat(trader_index)?;
+
+    let loans: Vec<ActiveLoan> = {
+        let market_loans_data: Ref<&mut [u8]> = market_loans.try_borrow_data()?;
+        let market_loans_dynamic_account: MarketLoansRef = get_dynamic_account(&market_loans_data);
+        market_loans_dynamic_account
+            .get_borrowed_loans(trader_index)
+            .into_iter()
+            .filter(|loan| {
+                loan_sequence_number.is_none() || loan_sequence_number == Some(loan.sequence_number)
+            })
+            .collect()
+    };
+    require!(
+        loan_sequence_number.is_none() || !loans.is_empty(),
+        NixError::InvalidActiveLoan,
+        "Loan {:?} is not an active loan of {}",
+        loan_sequence_number,
+        trader,
+    )?;
+
+    let base_a_bank: Ref<Bank> = base_a_marginfi_bank.get_fixed()?;
+    let base_b_bank: Ref<Bank> = base_b_marginfi_bank.get_fixed()?;
+    let bank_share_values: [BankShareValues; 2] = [
+        BankShareValues::from(&*base_a_bank),
+        BankShareValues::from(&*base_b_bank),
+    ];
+    // Collateral is priced low and liabilities high, by side.
+    let mut oracle_prices: OraclePrices = OraclePrices::new(accounts, Clock::get()?);
+    let mut collateral_prices_usd: [I80F48; 2] = [I80F48::ZERO; 2];
+    let mut liability_prices_usd: [I80F48; 2] = [I80F48::ZERO; 2];
+    for (index, (bank, bank_fixed, mint)) in [
+        (&base_a_marginfi_bank, &base_a_bank, &base_a_mint),
+        (&base_b_marginfi_bank, &base_b_bank, &base_b_mint),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        collateral_prices_usd[index] = oracle_prices.get_price_of_atoms(
+            bank.key,
+            &bank_fixed.config,
+            mint,
+            Some(PriceBias::Low),
+            OraclePriceType::TimeWeighted,
+        )?;
+        liability_prices_usd[index] = oracle_prices.get_price_of_atoms(
+            bank.key,
+            &bank_fixed.config,
+            mint,
+            Some(PriceBias::High),
+            OraclePriceType::TimeWeighted,
+        )?;
+    }
+
+    let buffer_f: I80F48 = get_buffer_f(ltv_buffer_bps)?;
+    let mut collateral_value_usd: I80F48 = I80F48::ZERO;
+    let mut liability_value_usd: I80F48 = I80F48::ZERO;
+    let mut distance_to_liquidation_usd: I80F48 = I80F48::ZERO;
+    for loan in loans.iter() {
+        let liability_index: usize = if loan.is_liability_base_a.0 == 1 {
+            0
+        } else {
+            1
+        };
+        let collateral_index: usize = 1 - liability_index;
+        let collateral_bank: &BankShareValues = &bank_share_values[collateral_index];
+        let liability_bank: &BankShareValues = &bank_share_values[liability_index];
+
+        let collateral_atoms: I80F48 = I80F48::from(loan.collateral_shares)
+            .checked_mul(collateral_bank.asset_share_value)
+            .ok_or(NixError::NumericalOverflow)?;
+        let liability_atoms: I80F48 = I80F48::from(loan.liability_shares)
+            .checked_mul(liability_bank.liability_share_value)
+            .ok_or(NixError::NumericalOverflow)?;
+        let collateral_price_usd: I80F48 = collateral_prices_usd[collateral_index];
+        let liability_price_usd: I80F48 = liability_prices_usd[liability_index];
+
+        collateral_value_usd = collateral_value_usd
+            .checked_add(get_weighted_value_usd(
+                collateral_atoms,
+                I80F48::ONE,
+                collateral_bank.mint_decimals,
+                collateral_price_usd,
+            )?)
+            .ok_or(NixError::NumericalOverflow)?;
+        liability_value_usd = liability_value_usd
+            .checked_add(get_weighted_value_usd(
+                liability_atoms,
+                I80F48::ONE,
+                liability_bank.mint_decimals,
+                liability_price_usd,
+            )?)
+            .ok_or(NixError::NumericalOverflow)?;
+
+        // Same weights as get_required_quote_collateral_to_back_loan, so the
+        // distance is zero exactly where ReleaseCollateral stops.
+        let collateral_weight: I80F48 = collateral_bank
+            .asset_weight_init
+            .checked_mul(buffer_f)
+            .ok_or(NixError::NumericalOverflow)?;
+        let weighted_collateral_usd: I80F48 = get_weighted_value_usd(
+            collateral_atoms,
+            collateral_weight,
+            collateral_bank.mint_decimals,
+            collateral_price_usd,
+        )?;
+        let weighted_liability_usd: I80F48 = get_weighted_value_usd(
+            liability_atoms,
+            liability_bank.liability_weight_init,
+            liability_bank.mint_decimals,
+            liability_price_usd,
+        )?;
+        distance_to_liquidation_usd = distance_to_liquidation_usd
+            .checked_add(weighted_collateral_usd)
+            .and_then(|distance| distance.checked_sub(weighted_liability_usd))
+            .ok_or(NixError::NumericalOverflow)?;
+    }
+    let ltv: I80F48 = if collateral_value_usd.is_positive() {
+        liability_value_usd
+            .checked_div(collateral_value_usd)
+            .ok_or(NixError::NumericalOverflow)?
+    } else {
+        I80F48::ZERO
+    };
+
+    emit_stack(CheckHealthLog {
+        market: *market.key,
+        market_loans: *market_loans.key,
+        trader,
+        num_loans: loans.len() as u64,
+        collateral_value_usd: collateral_value_usd.into(),
+        liability_value_usd: liability_value_usd.into(),
+        ltv: ltv.into(),
+        distance_to_liquidation_usd: distance_to_liquidation_usd.into(),
+    })
+}
@@ -0,0 +1,61 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::DataIndex;
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, ClaimMakerRebateLog},
+    state::MarketRefMut,
+    validation::loaders::ClaimMakerRebateContext,
+};
+
+use super::{get_mut_dynamic_account, get_trader_index_with_hint};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct ClaimMakerRebateParams {
+    pub trader_index_hint: Option<DataIndex>,
+}
+
+impl ClaimMakerRebateParams {
+    pub fn new(trader_index_hint: Option<DataIndex>) -> Self {
+        ClaimMakerRebateParams { trader_index_hint }
+    }
+}
+
+pub(crate) fn process_claim_maker_rebate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let params: ClaimMakerRebateParams = ClaimMakerRebateParams::try_from_slice(data)?;
+    process_claim_maker_rebate_core(program_id, accounts, params)
+}
+
+pub(crate) fn process_claim_maker_rebate_core(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    params: ClaimMakerRebateParams,
+) -> ProgramResult {
+    let claim_maker_rebate_context: ClaimMakerRebateContext =
+        ClaimMakerRebateContext::load(accounts)?;
+    let ClaimMakerRebateContext { trader, market } = claim_maker_rebate_context;
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    let mut dynamic_account: MarketRefMut = get_mut_dynamic_account(market_data);
+    let trader_index: DataIndex =
+        get_trader_index_with_hint(params.trader_index_hint, &dynamic_account, &trader)?;
+
+    let (base_a_maker_rebate_shares, base_b_maker_rebate_shares) =
+        dynamic_account.claim_maker_rebates(trader_index)?;
+
+    emit_stack(ClaimMakerRebateLog {
+        market: *market.key,
+        trader: *trader.key,
+        base_a_maker_rebate_shares,
+        base_b_maker_rebate_shares,
+    })?;
+
+    Ok(())
+}
@@ -1,13 +1,16 @@
-use std::cell::RefMut;
+use std::cell::{Ref, RefMut};
 
 use crate::{
     logs::{emit_stack, ClaimSeatLog},
-    state::{MarketFixed, MarketRefMut},
+    program::NixError,
+    require,
+    state::{MarketFixed, MarketRef, MarketRefMut},
     validation::{loaders::ClaimSeatContext, NixAccountInfo, Signer},
 };
+use hypertree::is_not_nil;
 use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
 
-use super::shared::{expand_market_if_needed, get_mut_dynamic_account};
+use super::shared::{expand_market_if_needed, get_dynamic_account, get_mut_dynamic_account};
 
 
 pub(crate) fn process_claim_seat(
@@ -26,6 +29,32 @@ pub(crate) fn process_claim_seat(
     Ok(())
 }
 
+/// Claims a seat for the payer if it does not have one yet, for instructions
+/// with an auto_claim_seat param. Permissioned markets need the allowlist
+/// authority to co-sign, so there the seat has to come from ClaimSeat. The
+/// payer funds the block that replaces the one the seat took.
+pub(crate) fn claim_seat_if_needed<'a, 'info>(
+    market: &NixAccountInfo<'a, 'info, MarketFixed>,
+    payer: &Signer<'a, 'info>,
+) -> ProgramResult {
+    {
+        let market_data: Ref<&mut [u8]> = market.try_borrow_data()?;
+        let dynamic_account: MarketRef = get_dynamic_account(&market_data);
+        if is_not_nil!(dynamic_account.get_trader_index(payer.key)) {
+            return Ok(());
+        }
+        require!(
+            !dynamic_account.fixed.is_permissioned(),
+            NixError::NotAllowlisted,
+            "Seats on permissioned market {} have to be claimed with ClaimSeat",
+            market.key,
+        )?;
+    }
+
+    process_claim_seat_internal(market, payer)?;
+    expand_market_if_needed(payer, market)
+}
+
 fn process_claim_seat_internal<'a, 'info>(
     market: &NixAccountInfo<'a, 'info, MarketFixed>,
     payer: &Signer<'a, 'info>,
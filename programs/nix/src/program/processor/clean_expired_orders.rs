@@ -0,0 +1,99 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::{trace, PodBool};
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, CleanExpiredOrdersLog},
+    program::{expand_market_loans, get_mut_dynamic_account, pay_gas_deposits, NixInstruction},
+    state::{MarketLoansFixed, MarketRefMut, MatchedLoans},
+    utils::{get_now_slot, try_to_add_new_loans},
+    validation::loaders::CleanExpiredOrdersContext,
+};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct CleanExpiredOrdersParams {
+    pub use_a_tree: bool,
+    pub is_bid: bool,
+    /// Max number of expired orders to remove. Capped at MAX_MATCHED_LOANS.
+    pub max_orders: u32,
+}
+
+impl CleanExpiredOrdersParams {
+    pub fn new(use_a_tree: bool, is_bid: bool, max_orders: u32) -> Self {
+        CleanExpiredOrdersParams {
+            use_a_tree,
+            is_bid,
+            max_orders,
+        }
+    }
+}
+
+pub(crate) fn process_clean_expired_orders<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+) -> ProgramResult {
+    let params: CleanExpiredOrdersParams = CleanExpiredOrdersParams::try_from_slice(data)?;
+    process_clean_expired_orders_core(program_id, accounts, params)
+}
+
+pub(crate) fn process_clean_expired_orders_core<'a>(
+    _program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    params: CleanExpiredOrdersParams,
+) -> ProgramResult {
+    trace!("process_clean_expired_orders accts={accounts:?}");
+    let CleanExpiredOrdersParams {
+        use_a_tree,
+        is_bid,
+        max_orders,
+    } = params;
+    let clean_expired_orders_context: CleanExpiredOrdersContext =
+        CleanExpiredOrdersContext::load_for(
+            accounts,
+            use_a_tree,
+            NixInstruction::CleanExpiredOrders,
+        )?;
+    let CleanExpiredOrdersContext {
+        payer,
+        market,
+        market_loans,
+        base_global_opt,
+        system_program,
+    } = clean_expired_orders_context;
+
+    let (num_removed, expired_loans, gas_deposit_lamports): (u32, MatchedLoans, u64) = {
+        let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+        let mut dynamic_account: MarketRefMut = get_mut_dynamic_account(market_data);
+        dynamic_account.clean_expired_orders(
+            use_a_tree,
+            is_bid,
+            max_orders,
+            get_now_slot(),
+            &base_global_opt,
+            &payer,
+            &system_program,
+        )?
+    };
+
+    pay_gas_deposits(&market, &payer, gas_deposit_lamports)?;
+
+    if !expired_loans.is_empty() {
+        expand_market_loans::<MarketLoansFixed>(&payer, &market_loans, expired_loans.len() as u32)?;
+        try_to_add_new_loans(&market_loans, &expired_loans)?;
+    }
+
+    emit_stack(CleanExpiredOrdersLog {
+        market: *market.key,
+        cranker: *payer.key,
+        num_removed,
+        use_a_tree: PodBool::from(use_a_tree),
+        is_bid: PodBool::from(is_bid),
+        _padding: [0; 2],
+        gas_deposit_lamports,
+    })?;
+    Ok(())
+}
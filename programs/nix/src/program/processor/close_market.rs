@@ -0,0 +1,193 @@
+use std::cell::{Ref, RefMut};
+
+use fixed::types::I80F48;
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, program::invoke_signed,
+    pubkey::Pubkey,
+};
+
+use crate::{
+    logs::{emit_stack, CloseMarketLog},
+    marginfi_utils::cpi_marginfi_account_close,
+    market_signer_seeds_with_bump,
+    program::NixError,
+    require,
+    state::{MarketFixed, MarketInsurance, MarketRefMut, MarketRegistryRefMut},
+    utils::close_nix_account,
+    validation::{loaders::CloseMarketContext, MarketSigner, Signer, TokenAccountInfo, TokenProgram},
+};
+
+use super::get_mut_dynamic_account;
+
+pub(crate) fn process_close_market(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    _data: &[u8],
+) -> ProgramResult {
+    process_close_market_core(program_id, accounts)
+}
+
+pub(crate) fn process_close_market_core(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let close_market_context: CloseMarketContext = CloseMarketContext::load(accounts)?;
+    let CloseMarketContext {
+        admin,
+        market,
+        market_loans,
+        market_signer,
+        base_a_vault,
+        base_b_vault,
+        base_a_fee_receiver,
+        base_b_fee_receiver,
+        base_a_marginfi_account,
+        base_b_marginfi_account,
+        token_program,
+        token_program_22,
+        market_stats,
+        market_auction,
+        market_insurance,
+        event_queue,
+        market_registry,
+        next_market_loans_pages,
+        ..
+    } = &close_market_context;
+
+    for page in std::iter::once(market_loans).chain(next_market_loans_pages.iter()) {
+        require!(
+            page.get_fixed()?.num_active_loans == 0,
+            NixError::MarketNotEmpty,
+            "Market loans page {} still has active loans",
+            page.key,
+        )?;
+    }
+    if let Some(market_insurance) = market_insurance {
+        let market_insurance_fixed: Ref<MarketInsurance> = market_insurance.get_fixed()?;
+        require!(
+            I80F48::from(market_insurance_fixed.get_shares(true)) == 0
+                && I80F48::from(market_insurance_fixed.get_shares(false)) == 0,
+            NixError::MarketNotEmpty,
+            "Market insurance fund still holds shares",
+        )?;
+    }
+    if let Some(event_queue) = event_queue {
+        require!(
+            event_queue.get_fixed()?.get_count() == 0,
+            NixError::MarketNotEmpty,
+            "Event queue still has unconsumed events",
+        )?;
+    }
+    {
+        let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+        let dynamic_account: MarketRefMut = get_mut_dynamic_account::<MarketFixed>(market_data);
+        dynamic_account.verify_empty_for_close()?;
+    }
+
+    // Token accounts can only be closed once empty, so the token program
+    // enforces that nothing is left in the vaults or fee receivers.
+    for token_account in [
+        base_a_vault,
+        base_b_vault,
+        base_a_fee_receiver,
+        base_b_fee_receiver,
+    ] {
+        close_token_account(
+            token_account,
+            admin,
+            market_signer,
+            market.key,
+            token_program,
+            token_program_22,
+        )?;
+    }
+
+    // Marginfi refuses to close accounts that still have balances. The side
+    // of a one sided market without a bank has no account to close.
+    for marginfi_account in [base_a_marginfi_account, base_b_marginfi_account]
+        .into_iter()
+        .flatten()
+    {
+        cpi_marginfi_account_close(
+            marginfi_account,
+            market_signer,
+            admin,
+            market_signer_seeds_with_bump!(market.key, market_signer.bump),
+        )?;
+    }
+
+    if let Some(market_registry) = market_registry {
+        let market_registry_data: &mut RefMut<&mut [u8]> =
+            &mut market_registry.try_borrow_mut_data()?;
+        let mut dynamic_account: MarketRegistryRefMut =
+            get_mut_dynamic_account(market_registry_data);
+        dynamic_account.unregister_market(market.key)?;
+    }
+
+    emit_stack(CloseMarketLog {
+        market: *market.key,
+        admin: *admin.key,
+    })?;
+
+    for nix_account in [
+        market_stats.as_ref().map(|account| account.info),
+        market_auction.as_ref().map(|account| account.info),
+        market_insurance.as_ref().map(|account| account.info),
+        event_queue.as_ref().map(|account| account.info),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        close_nix_account(nix_account, admin.info)?;
+    }
+    for page in next_market_loans_pages {
+        close_nix_account(page.info, admin.info)?;
+    }
+    close_nix_account(market_loans.info, admin.info)?;
+    close_nix_account(market.info, admin.info)?;
+
+    Ok(())
+}
+
+fn close_token_account<'a, 'info>(
+    token_account: &TokenAccountInfo<'a, 'info>,
+    admin: &Signer<'a, 'info>,
+    market_signer: &MarketSigner<'a, 'info>,
+    market: &Pubkey,
+    token_program: &TokenProgram<'a, 'info>,
+    token_program_22: &TokenProgram<'a, 'info>,
+) -> ProgramResult {
+    let is_22: bool = *token_account.owner == spl_token_2022::id();
+    let close_instruction = if is_22 {
+        spl_token_2022::instruction::close_account(
+            &spl_token_2022::id(),
+            token_account.key,
+            admin.key,
+            market_signer.info.key,
+            &[],
+        )?
+    } else {
+        spl_token::instruction::close_account(
+            &spl_token::id(),
+            token_account.key,
+            admin.key,
+            market_signer.info.key,
+            &[],
+        )?
+    };
+    invoke_signed(
+        &close_instruction,
+        &[
+            token_account.as_ref().clone(),
+            admin.as_ref().clone(),
+            market_signer.as_ref().clone(),
+            if is_22 {
+                token_program_22.as_ref()
+            } else {
+                token_program.as_ref()
+            }
+            .clone(),
+        ],
+        market_signer_seeds_with_bump!(market, market_signer.bump),
+    )
+}
@@ -0,0 +1,62 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::trace;
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, ConsumeEventsLog},
+    program::get_mut_dynamic_account,
+    state::EventQueueRefMut,
+    validation::loaders::ConsumeEventsContext,
+};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct ConsumeEventsParams {
+    /// Id of the last event the consumer has processed. Events after it stay
+    /// on the queue.
+    pub up_to_event_id: u64,
+}
+
+impl ConsumeEventsParams {
+    pub fn new(up_to_event_id: u64) -> Self {
+        ConsumeEventsParams { up_to_event_id }
+    }
+}
+
+pub(crate) fn process_consume_events(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let params: ConsumeEventsParams = ConsumeEventsParams::try_from_slice(data)?;
+    process_consume_events_core(program_id, accounts, params)
+}
+
+pub(crate) fn process_consume_events_core(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    params: ConsumeEventsParams,
+) -> ProgramResult {
+    trace!("process_consume_events accts={accounts:?}");
+    let consume_events_context: ConsumeEventsContext = ConsumeEventsContext::load(accounts)?;
+    let ConsumeEventsContext {
+        market,
+        event_queue,
+        ..
+    } = consume_events_context;
+
+    let event_queue_data: &mut RefMut<&mut [u8]> = &mut event_queue.try_borrow_mut_data()?;
+    let mut dynamic_account: EventQueueRefMut = get_mut_dynamic_account(event_queue_data);
+    let num_consumed: u32 = dynamic_account.consume_events(params.up_to_event_id);
+
+    emit_stack(ConsumeEventsLog {
+        market: *market.key,
+        event_queue: *event_queue.key,
+        up_to_event_id: params.up_to_event_id,
+        num_consumed,
+        _padding: [0; 4],
+    })?;
+    Ok(())
+}
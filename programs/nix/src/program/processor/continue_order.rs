@@ -0,0 +1,93 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::{trace, DataIndex};
+use shank::ShankType;
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+use crate::{
+    logs::{emit_stack, PendingOrderLog},
+    program::NixError,
+    require,
+    state::{PendingOrder, NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP},
+    utils::{close_nix_account, get_now_slot},
+    validation::{get_pending_order_address, NixAccountInfo, Signer},
+};
+
+use super::place_order::{process_place_order_resumable, PlaceOrderParams};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct ContinueOrderParams {
+    pub trader_index_hint: Option<DataIndex>,
+}
+
+impl ContinueOrderParams {
+    pub fn new(trader_index_hint: Option<DataIndex>) -> Self {
+        ContinueOrderParams { trader_index_hint }
+    }
+}
+
+pub(crate) fn process_continue_order<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+) -> ProgramResult {
+    let params: ContinueOrderParams = ContinueOrderParams::try_from_slice(data)?;
+    trace!("process_continue_order accs={accounts:?}");
+
+    let payer: Signer = Signer::new(accounts.first().ok_or(ProgramError::NotEnoughAccountKeys)?)?;
+    let market_info: &AccountInfo = accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let (pending_order_key, _pending_order_bump) =
+        get_pending_order_address(market_info.key, payer.key);
+    let pending_order_info_opt: Option<&AccountInfo> = accounts
+        .iter()
+        .find(|account| *account.key == pending_order_key);
+    require!(
+        pending_order_info_opt.is_some(),
+        NixError::InvalidPendingOrder,
+        "Missing pending order {}",
+        pending_order_key,
+    )?;
+    let pending_order_info: &AccountInfo = pending_order_info_opt.unwrap();
+    let pending_order: PendingOrder =
+        *NixAccountInfo::<PendingOrder>::new(pending_order_info)?.get_fixed()?;
+
+    // Nothing can rest or match past the expiry, so the remainder is dropped.
+    if pending_order.is_expired(get_now_slot()) {
+        close_nix_account(pending_order_info, payer.info)?;
+        return emit_stack(PendingOrderLog {
+            market: *market_info.key,
+            trader: *payer.key,
+            num_base_atoms: 0,
+            order_sequence_number: pending_order.order_sequence_number,
+        });
+    }
+
+    process_place_order_resumable(
+        program_id,
+        accounts,
+        PlaceOrderParams {
+            trader_index_hint: params.trader_index_hint,
+            num_base_atoms: pending_order.num_base_atoms,
+            rate_bps: pending_order.rate_bps,
+            reverse_spread_bps: 0,
+            is_bid: pending_order.get_is_bid(),
+            use_a_tree: pending_order.get_use_a_tree(),
+            last_valid_slot: pending_order.last_valid_slot,
+            order_type: pending_order.order_type,
+            expiry_policy: pending_order.expiry_policy,
+            referrer: pending_order.get_referrer(),
+            match_limit: Some(pending_order.match_limit),
+            auto_claim_seat: false,
+            size_in_quote: false,
+            defer_remainder: true,
+            max_reverse_cycles: 0,
+            max_expired_orders_to_sweep: 0,
+            max_new_loans: None,
+            reverse_bid_spread_bps: None,
+            last_valid_unix_timestamp: NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP,
+        },
+        Some(pending_order.order_sequence_number),
+    )
+}
@@ -0,0 +1,65 @@
+use std::cell::Ref;
+
+use hypertree::{trace, DataIndex};
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, CreateCrossMarginSeatLog},
+    program::get_dynamic_account,
+    state::{CrossMarginSeat, MarketRef},
+    utils::assert_already_has_seat,
+    validation::{
+        get_cross_margin_seat_address, loaders::CreateCrossMarginSeatContext, NixAccountInfo,
+    },
+};
+
+pub(crate) fn process_create_cross_margin_seat(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    _data: &[u8],
+) -> ProgramResult {
+    trace!("process_create_cross_margin_seat accs={accounts:?}");
+    let CreateCrossMarginSeatContext {
+        trader,
+        market,
+        cross_margin_seat,
+        marginfi_account,
+        system_program,
+    } = CreateCrossMarginSeatContext::load(accounts)?;
+
+    {
+        let market_data: Ref<&mut [u8]> = market.try_borrow_data()?;
+        let dynamic_account: MarketRef = get_dynamic_account(&market_data);
+        let trader_index: DataIndex = dynamic_account.get_trader_index(trader.key);
+        assert_already_has_seat(trader_index)?;
+    }
+
+    let (_cross_margin_seat_key, cross_margin_seat_bump) =
+        get_cross_margin_seat_address(market.key, trader.key);
+    let cross_margin_seat_seeds: Vec<Vec<u8>> = vec![
+        b"cross-margin-seat".to_vec(),
+        market.key.as_ref().to_vec(),
+        trader.key.as_ref().to_vec(),
+        vec![cross_margin_seat_bump],
+    ];
+    let cross_margin_seat: NixAccountInfo<CrossMarginSeat> =
+        NixAccountInfo::<CrossMarginSeat>::new_init_pda(
+            cross_margin_seat.info,
+            &trader,
+            &system_program,
+            cross_margin_seat_seeds,
+        )?;
+    cross_margin_seat.init_fixed(CrossMarginSeat::new(
+        *market.key,
+        *trader.key,
+        *marginfi_account.key,
+    ))?;
+
+    emit_stack(CreateCrossMarginSeatLog {
+        market: *market.key,
+        trader: *trader.key,
+        cross_margin_seat: *cross_margin_seat.key,
+        marginfi_account: *marginfi_account.key,
+    })?;
+    Ok(())
+}
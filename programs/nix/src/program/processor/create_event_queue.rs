@@ -0,0 +1,84 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::{get_mut_helper, trace};
+use shank::ShankType;
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult,
+    entrypoint::MAX_PERMITTED_DATA_INCREASE, pubkey::Pubkey,
+};
+
+use crate::{
+    logs::{emit_stack, CreateEventQueueLog},
+    program::{expand_event_queue, get_mut_dynamic_account, NixError},
+    require,
+    state::{EventQueueFixed, MarketRefMut, MARKET_EVENT_SIZE},
+    validation::loaders::CreateEventQueueContext,
+};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct CreateEventQueueParams {
+    /// Number of events the queue holds before ConsumeEvents has to run.
+    pub capacity: u32,
+}
+
+impl CreateEventQueueParams {
+    pub fn new(capacity: u32) -> Self {
+        CreateEventQueueParams { capacity }
+    }
+}
+
+pub(crate) fn process_create_event_queue(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let params: CreateEventQueueParams = CreateEventQueueParams::try_from_slice(data)?;
+    process_create_event_queue_core(program_id, accounts, params)
+}
+
+pub(crate) fn process_create_event_queue_core(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    params: CreateEventQueueParams,
+) -> ProgramResult {
+    trace!("process_create_event_queue accts={accounts:?}");
+    // The queue is grown with a single realloc, which is capped per
+    // instruction.
+    require!(
+        params.capacity > 0
+            && params.capacity as usize * MARKET_EVENT_SIZE <= MAX_PERMITTED_DATA_INCREASE,
+        NixError::InvalidMarketParameters,
+        "Invalid event queue capacity {}",
+        params.capacity,
+    )?;
+    let create_event_queue_context: CreateEventQueueContext =
+        CreateEventQueueContext::load(accounts)?;
+    let CreateEventQueueContext {
+        admin,
+        market,
+        event_queue,
+    } = create_event_queue_context;
+
+    {
+        let event_queue_bytes: &mut [u8] = &mut event_queue.try_borrow_mut_data()?[..];
+        *get_mut_helper::<EventQueueFixed>(event_queue_bytes, 0_u32) =
+            EventQueueFixed::new_empty(*market.key);
+    }
+    expand_event_queue(&admin, &event_queue, params.capacity)?;
+
+    {
+        let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+        let dynamic_account: MarketRefMut = get_mut_dynamic_account(market_data);
+        dynamic_account.fixed.set_event_queue(*event_queue.key);
+    }
+
+    emit_stack(CreateEventQueueLog {
+        market: *market.key,
+        event_queue: *event_queue.key,
+        admin: *admin.key,
+        capacity: params.capacity,
+        _padding: [0; 4],
+    })?;
+    Ok(())
+}
@@ -1,21 +1,35 @@
 use crate::{
-    logs::{emit_stack, CreateMarketLog},
-    marginfi_utils::initialize_marginfi_account,
-    program::expand_market_if_needed,
-    state::MarketFixed,
-    utils::create_account,
+    logs::{emit_stack, CreateMarketLog, RegisterMarketLog},
+    marginfi_utils::{get_bank_config_hash, initialize_marginfi_account},
+    market_signer_seeds_with_bump,
+    program::{
+        create_market_loan_account::initialize_market_loans, expand_market_if_needed,
+        expand_market_registry, get_mut_dynamic_account, NixError,
+    },
+    require,
+    state::{sort_mints, MarketFixed, MarketRegistryFixed, MarketRegistryRefMut},
+    utils::{create_account, get_ui_amount_multiplier},
     validation::{
-        get_market_fee_receiver_address, get_market_signer_address, get_vault_address,
-        loaders::CreateMarketContext, EmptyAccount, MarginfiAccountInfo, MintAccountInfo,
-        NixAccountInfo, Program, Signer, TokenProgram,
+        get_market_fee_receiver_address, get_market_registry_address, get_market_signer_address,
+        get_vault_address, loaders::CreateMarketContext, EmptyAccount, MarginfiAccountInfo,
+        MintAccountInfo, NixAccountInfo, Program, Signer, TokenProgram,
     },
 };
 use borsh::{BorshDeserialize, BorshSerialize};
+use fixed::types::I80F48;
 use hypertree::{get_mut_helper, trace};
 use marginfi::state::{marginfi_account::MarginfiAccount, marginfi_group::MarginfiGroup};
+use shank::ShankType;
 use solana_program::{
-    account_info::AccountInfo, entrypoint::ProgramResult, program::invoke, program_pack::Pack,
-    pubkey::Pubkey, rent::Rent, sysvar::Sysvar,
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::ProgramResult,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    sysvar::Sysvar,
 };
 use spl_token_2022::{
     extension::{
@@ -27,11 +41,25 @@ use spl_token_2022::{
 };
 use std::mem::size_of;
 
-use std::cell::Ref;
-#[derive(BorshDeserialize, BorshSerialize)]
+use std::cell::{Ref, RefMut};
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
 pub struct CreateMarketParams {
-    protocol_fee_rate_bps: u64,
-    marginfi_market_buffer_bps: u64,
+    pub protocol_fee_rate_bps: u64,
+    pub marginfi_market_buffer_bps: u64,
+    pub referral_bps: u64,
+    pub maker_rebate_bps: u64,
+    pub min_reverse_spread_bps: u16,
+    pub max_reverse_spread_bps: u16,
+    pub max_open_orders_per_seat: u16,
+    /// Key that has to co-sign every ClaimSeat. None for a permissionless
+    /// market.
+    pub allowlist_authority: Option<Pubkey>,
+    /// Charge the protocol fee on the interest of a fill instead of its
+    /// notional.
+    pub fee_on_interest: bool,
+    /// Max liability of one seat on a base, in bps of the deposits in the
+    /// marginfi bank of that base. Zero for no limit.
+    pub max_borrow_utilization_bps: u16,
 }
 
 pub(crate) fn process_create_market(
@@ -49,11 +77,33 @@ pub(crate) fn process_create_market_core(
     params: CreateMarketParams,
 ) -> ProgramResult {
     trace!("process_create_market accts={accounts:?}");
+    require!(
+        params.max_reverse_spread_bps < 10_000
+            && params.min_reverse_spread_bps <= params.max_reverse_spread_bps,
+        NixError::InvalidMarketParameters,
+        "Invalid reverse spread bounds. min: {} max: {}",
+        params.min_reverse_spread_bps,
+        params.max_reverse_spread_bps,
+    )?;
+    require!(
+        params.max_borrow_utilization_bps <= 10_000,
+        NixError::InvalidMarketParameters,
+        "Invalid max borrow utilization bps: {}",
+        params.max_borrow_utilization_bps,
+    )?;
+    require!(
+        params.referral_bps + params.maker_rebate_bps <= 10_000,
+        NixError::InvalidMarketParameters,
+        "Invalid protocol fee split. referral bps: {} maker rebate bps: {}",
+        params.referral_bps,
+        params.maker_rebate_bps,
+    )?;
     let create_market_context: CreateMarketContext = CreateMarketContext::load(accounts)?;
 
     let CreateMarketContext {
         admin,
         market,
+        market_loans,
         market_signer,
         system_program,
         token_program,
@@ -66,6 +116,8 @@ pub(crate) fn process_create_market_core(
         base_b_vault,
         base_a_marginfi_group,
         base_b_marginfi_group,
+        base_a_marginfi_bank,
+        base_b_marginfi_bank,
         base_a_marginfi_account,
         base_b_marginfi_account,
         ..
@@ -105,18 +157,40 @@ pub(crate) fn process_create_market_core(
         )?;
     }
     // Do not need to initialize with the system program because it is
-    // assumed that it is done already and loaded with rent. That is not at
-    // a PDA because we do not want to be restricted to a single market for
-    // a pair. If there is lock contention and hotspotting for one market,
-    // it could be useful to have a second where it is easier to land
+    // assumed that it is done already and loaded with rent, either by the
+    // client or by CreateMarketPda. Markets are not required to be at a PDA
+    // because we do not want to be restricted to a single market for a
+    // pair. If there is lock contention and hotspotting for one market, it
+    // could be useful to have a second where it is easier to land
     // transactions. That protection is worth the possibility that users
     // would use an inactive market when multiple exist.
 
+    // Orders check the banks against these, so a change to their weights or
+    // oracles under the market needs the admin to acknowledge it.
+    let mut bank_config_hashes: [u64; 2] = [0; 2];
+    for (bank_config_hash, marginfi_bank) in bank_config_hashes
+        .iter_mut()
+        .zip([base_a_marginfi_bank, base_b_marginfi_bank])
+    {
+        if let Some(marginfi_bank) = marginfi_bank {
+            *bank_config_hash = get_bank_config_hash(&marginfi_bank.get_fixed()?.config);
+        }
+    }
+
     // Setup the empty market
     let empty_market_fixed: MarketFixed = MarketFixed::new_empty(
         &create_market_context,
         params.protocol_fee_rate_bps,
         params.marginfi_market_buffer_bps,
+        params.referral_bps,
+        params.maker_rebate_bps,
+        params.min_reverse_spread_bps,
+        params.max_reverse_spread_bps,
+        params.max_open_orders_per_seat,
+        params.allowlist_authority.unwrap_or_default(),
+        params.fee_on_interest,
+        params.max_borrow_utilization_bps,
+        bank_config_hashes,
     );
     assert_eq!(market.data_len(), size_of::<MarketFixed>());
 
@@ -130,9 +204,85 @@ pub(crate) fn process_create_market_core(
         admin: *admin.key,
     })?;
     expand_market_if_needed(&admin, &market)?;
+
+    // The loans ledger is created in the same instruction so that a market
+    // can never exist without somewhere to record its loans.
+    initialize_market_loans(admin, market_loans, market)?;
+
+    // Listing is opt in so the account list of CreateMarket stays the same
+    // for callers that do not append the registry.
+    let (market_registry_key, _bump) =
+        get_market_registry_address(base_a_mint.as_ref().key, base_b_mint.as_ref().key);
+    if let Some(market_registry) = accounts
+        .iter()
+        .find(|account| *account.key == market_registry_key)
+    {
+        register_market(
+            admin,
+            system_program,
+            market_registry,
+            base_a_mint.as_ref().key,
+            base_b_mint.as_ref().key,
+            market.key,
+        )?;
+    }
     Ok(())
 }
 
+/// Appends the market to the registry of its mint pair, creating the
+/// registry for the first market of the pair.
+fn register_market<'a, 'info>(
+    admin: &'a Signer<'a, 'info>,
+    system_program: &'a Program<'a, 'info>,
+    market_registry: &'a AccountInfo<'info>,
+    base_a_mint: &Pubkey,
+    base_b_mint: &Pubkey,
+    market: &Pubkey,
+) -> ProgramResult {
+    require!(
+        market_registry.is_writable,
+        NixError::AccountNotWritable,
+        "Market registry {} has to be writable",
+        market_registry.key,
+    )?;
+    if market_registry.data_is_empty() {
+        let (mint_low, mint_high) = sort_mints(base_a_mint, base_b_mint);
+        let (_market_registry_key, market_registry_bump) =
+            get_market_registry_address(base_a_mint, base_b_mint);
+        let market_registry_seeds: Vec<Vec<u8>> = vec![
+            b"market-registry".to_vec(),
+            mint_low.as_ref().to_vec(),
+            mint_high.as_ref().to_vec(),
+            vec![market_registry_bump],
+        ];
+        NixAccountInfo::<MarketRegistryFixed>::new_init_pda(
+            market_registry,
+            admin,
+            system_program,
+            market_registry_seeds,
+        )?
+        .init_fixed(MarketRegistryFixed::new_empty(base_a_mint, base_b_mint))?;
+    }
+    let market_registry: NixAccountInfo<MarketRegistryFixed> =
+        NixAccountInfo::<MarketRegistryFixed>::new(market_registry)?;
+    expand_market_registry(admin, &market_registry)?;
+
+    let num_markets: u32 = {
+        let market_registry_data: &mut RefMut<&mut [u8]> =
+            &mut market_registry.try_borrow_mut_data()?;
+        let mut dynamic_account: MarketRegistryRefMut =
+            get_mut_dynamic_account(market_registry_data);
+        dynamic_account.register_market(market)?;
+        dynamic_account.fixed.get_num_markets()
+    };
+    emit_stack(RegisterMarketLog {
+        market: *market,
+        market_registry: *market_registry.key,
+        num_markets,
+        _padding: [0; 4],
+    })
+}
+
 fn process_token_type<'a, 'info>(
     admin: &'a Signer<'a, 'info>,
     market: &'a NixAccountInfo<'a, 'info, MarketFixed>,
@@ -141,8 +291,8 @@ fn process_token_type<'a, 'info>(
     mint: &'a MintAccountInfo<'a, 'info>,
     vault: &'a EmptyAccount<'a, 'info>,
     fee_receiver: &'a EmptyAccount<'a, 'info>,
-    marginfi_group: &'a MarginfiAccountInfo<'a, 'info, MarginfiGroup>,
-    marginfi_account: &'a MarginfiAccountInfo<'a, 'info, MarginfiAccount>,
+    marginfi_group: &'a Option<MarginfiAccountInfo<'a, 'info, MarginfiGroup>>,
+    marginfi_account: &'a Option<MarginfiAccountInfo<'a, 'info, MarginfiAccount>>,
     system_program: &'a Program<'a, 'info>,
     token_program: &'a TokenProgram<'a, 'info>,
     token_program_22: &'a TokenProgram<'a, 'info>,
@@ -160,16 +310,18 @@ fn process_token_type<'a, 'info>(
         market_signer,
     )?;
 
-    // 2. Initialize Marginfi account
-    initialize_marginfi_account(
-        marginfi_group,
-        marginfi_account,
-        admin,
-        system_program, // system_program
-        market,
-        market_signer,
-        market_signer_bump,
-    )?;
+    // 2. Initialize Marginfi account, unless this side of a one sided market
+    // has no bank. Its tokens then only sit in the vault.
+    if let (Some(marginfi_group), Some(marginfi_account)) = (marginfi_group, marginfi_account) {
+        initialize_marginfi_account(
+            marginfi_group,
+            marginfi_account,
+            admin,
+            system_program, // system_program
+            market_signer,
+            market_signer_seeds_with_bump!(market.key, market_signer_bump),
+        )?;
+    }
 
     Ok(())
 }
@@ -213,6 +365,18 @@ fn create_vault_and_fee_receiver<'a, 'info>(
                 );
             }
         }
+        // Interest-bearing and scaled-ui mints show atoms at a multiple that
+        // changes over time. Collateral is priced with it, so it has to be
+        // readable now.
+        let ui_amount_multiplier: I80F48 =
+            get_ui_amount_multiplier(mint, Clock::get()?.unix_timestamp)?;
+        if ui_amount_multiplier != I80F48::ONE {
+            solana_program::msg!(
+                "Mint {} is priced at {} times its atoms",
+                mint_info.key,
+                ui_amount_multiplier
+            );
+        }
     }
 
     // We don't have to deserialize the mint, just check the owner.
@@ -231,26 +395,7 @@ fn create_vault_and_fee_receiver<'a, 'info>(
         vec![vault_bump],
     ];
 
-    let (_fee_receiver_key, fee_receiver_bump) =
-        get_market_fee_receiver_address(market.key, mint_info.key);
-
-    let fee_receiver_seeds: Vec<Vec<u8>> = vec![
-        b"fee-receiver".to_vec(),
-        market.key.as_ref().to_vec(),
-        mint_info.key.as_ref().to_vec(),
-        vec![fee_receiver_bump],
-    ];
-
-    let space = if is_mint_22 {
-        let mint_data: Ref<'_, &mut [u8]> = mint_info.data.borrow();
-        let mint_with_extension = PodStateWithExtensions::<PodMint>::unpack(&mint_data).unwrap();
-        let mint_extensions = mint_with_extension.get_extension_types()?;
-        let required_extensions =
-            ExtensionType::get_required_init_account_extensions(&mint_extensions);
-        ExtensionType::try_calculate_account_len::<Account>(&required_extensions)?
-    } else {
-        spl_token::state::Account::LEN
-    };
+    let space: usize = get_token_account_space(mint_info)?;
 
     // Create vault
     create_account(
@@ -291,15 +436,80 @@ fn create_vault_and_fee_receiver<'a, 'info>(
             .clone(),
         ],
     )?;
+    thaw_if_frozen_by_default(
+        mint,
+        vault_info,
+        token_program_22,
+        market.key,
+        market_signer,
+    )?;
 
-    // Create fee receiver
-    create_account(
+    create_fee_receiver(
         admin,
+        mint,
+        fee_receiver.as_ref(),
+        system_program,
+        if is_mint_22 {
+            token_program_22
+        } else {
+            token_program
+        },
+        market.key,
+        market_signer,
+    )
+}
+
+/// Space of a token account of the mint, with room for the extensions the
+/// mint requires its accounts to have.
+fn get_token_account_space(mint_info: &AccountInfo) -> Result<usize, ProgramError> {
+    if *mint_info.owner != spl_token_2022::id() {
+        return Ok(spl_token::state::Account::LEN);
+    }
+    let mint_data: Ref<'_, &mut [u8]> = mint_info.data.borrow();
+    let mint_with_extension = PodStateWithExtensions::<PodMint>::unpack(&mint_data)?;
+    let mint_extensions = mint_with_extension.get_extension_types()?;
+    let required_extensions = ExtensionType::get_required_init_account_extensions(&mint_extensions);
+    Ok(ExtensionType::try_calculate_account_len::<Account>(
+        &required_extensions,
+    )?)
+}
+
+/// Creates the fee receiver of a mint on a market, a token account at its
+/// PDA owned by the market signer. Mints of the market get theirs with the
+/// market, other mints when something is first paid out in them.
+pub(crate) fn create_fee_receiver<'a, 'info>(
+    payer: &'a Signer<'a, 'info>,
+    mint: &'a MintAccountInfo<'a, 'info>,
+    fee_receiver_info: &'a AccountInfo<'info>,
+    system_program: &'a Program<'a, 'info>,
+    token_program: &'a TokenProgram<'a, 'info>,
+    market: &Pubkey,
+    market_signer: &'a AccountInfo<'info>,
+) -> ProgramResult {
+    let mint_info = mint.as_ref();
+    let is_mint_22: bool = *mint_info.owner == spl_token_2022::id();
+    let token_program_for_mint: Pubkey = if is_mint_22 {
+        spl_token_2022::id()
+    } else {
+        spl_token::id()
+    };
+
+    let (_fee_receiver_key, fee_receiver_bump) =
+        get_market_fee_receiver_address(market, mint_info.key);
+    let fee_receiver_seeds: Vec<Vec<u8>> = vec![
+        b"fee-receiver".to_vec(),
+        market.as_ref().to_vec(),
+        mint_info.key.as_ref().to_vec(),
+        vec![fee_receiver_bump],
+    ];
+
+    create_account(
+        payer,
         fee_receiver_info,
         system_program.as_ref(),
         &token_program_for_mint,
-        &rent,
-        space as u64,
+        &Rent::get()?,
+        get_token_account_space(mint_info)? as u64,
         fee_receiver_seeds,
     )?;
     let fee_receiver_instruction = if is_mint_22 {
@@ -320,17 +530,58 @@ fn create_vault_and_fee_receiver<'a, 'info>(
     invoke(
         &fee_receiver_instruction,
         &[
-            admin.as_ref().clone(),
+            payer.as_ref().clone(),
             fee_receiver_info.clone(),
             mint_info.clone(),
-            if is_mint_22 {
-                token_program_22.as_ref()
-            } else {
-                token_program.as_ref()
-            }
-            .clone(),
+            token_program.as_ref().clone(),
         ],
     )?;
+    thaw_if_frozen_by_default(
+        mint,
+        fee_receiver_info,
+        token_program,
+        market,
+        market_signer,
+    )
+}
 
-    Ok(())
+/// Token22 mints with a frozen DefaultAccountState create the accounts of
+/// the market frozen. The market signer thaws them, so it has to be the
+/// freeze authority of the mint, otherwise deposits would fail later in
+/// the transfer.
+fn thaw_if_frozen_by_default<'a, 'info>(
+    mint: &MintAccountInfo<'a, 'info>,
+    token_account_info: &AccountInfo<'info>,
+    token_program: &TokenProgram<'a, 'info>,
+    market: &Pubkey,
+    market_signer: &AccountInfo<'info>,
+) -> ProgramResult {
+    if !mint.is_frozen_by_default()? {
+        return Ok(());
+    }
+    let freeze_authority: Option<Pubkey> = mint.mint.freeze_authority.into();
+    require!(
+        freeze_authority == Some(*market_signer.key),
+        NixError::MintFrozenByDefault,
+        "Mint {} freezes new token accounts and {:?} is its freeze authority, not the market signer",
+        mint.info.key,
+        freeze_authority,
+    )?;
+    let (_market_signer_key, market_signer_bump) = get_market_signer_address(market);
+    invoke_signed(
+        &spl_token_2022::instruction::thaw_account(
+            token_program.as_ref().key,
+            token_account_info.key,
+            mint.info.key,
+            market_signer.key,
+            &[],
+        )?,
+        &[
+            token_account_info.clone(),
+            mint.info.clone(),
+            market_signer.clone(),
+            token_program.as_ref().clone(),
+        ],
+        market_signer_seeds_with_bump!(market, market_signer_bump),
+    )
 }
@@ -0,0 +1,84 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::{get_mut_helper, trace};
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, CreateMarketAuctionLog},
+    program::NixError,
+    require,
+    state::{MarketAuction, MarketFixed},
+    utils::get_now_slot,
+    validation::{get_market_auction_address, loaders::CreateMarketAuctionContext, NixAccountInfo},
+};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct CreateMarketAuctionParams {
+    /// Slots orders collect for before an auction, and that the market then
+    /// settles at the clearing rate for.
+    pub window_slots: u32,
+}
+
+pub(crate) fn process_create_market_auction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let params: CreateMarketAuctionParams = CreateMarketAuctionParams::try_from_slice(data)?;
+    process_create_market_auction_core(program_id, accounts, params)
+}
+
+/// Puts the market in auction mode. There is no way back, continuous
+/// matching stays frozen outside of settlements from here on.
+pub(crate) fn process_create_market_auction_core(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    params: CreateMarketAuctionParams,
+) -> ProgramResult {
+    trace!("process_create_market_auction accs={accounts:?}");
+    let CreateMarketAuctionContext {
+        admin,
+        market,
+        market_auction,
+        system_program,
+    } = CreateMarketAuctionContext::load(accounts)?;
+    require!(
+        params.window_slots > 0,
+        NixError::InvalidMarketParameters,
+        "Auction window needs at least one slot",
+    )?;
+
+    let (_market_auction_key, market_auction_bump) = get_market_auction_address(market.key);
+    let market_auction_seeds: Vec<Vec<u8>> = vec![
+        b"market-auction".to_vec(),
+        market.key.as_ref().to_vec(),
+        vec![market_auction_bump],
+    ];
+    let market_auction: NixAccountInfo<MarketAuction> =
+        NixAccountInfo::<MarketAuction>::new_init_pda(
+            market_auction.info,
+            &admin,
+            &system_program,
+            market_auction_seeds,
+        )?;
+    let now_slot: u32 = get_now_slot();
+    market_auction.init_fixed(MarketAuction::new(
+        *market.key,
+        params.window_slots,
+        now_slot,
+    ))?;
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    let market_fixed: &mut MarketFixed = get_mut_helper::<MarketFixed>(market_data, 0_u32);
+    market_fixed.set_auction_mode(true);
+
+    emit_stack(CreateMarketAuctionLog {
+        market: *market.key,
+        market_auction: *market_auction.key,
+        admin: *admin.key,
+        window_slots: params.window_slots,
+        window_end_slot: now_slot.saturating_add(params.window_slots),
+    })
+}
@@ -0,0 +1,82 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::trace;
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+use std::cell::Ref;
+
+use crate::{
+    logs::{emit_stack, CreateMarketInsuranceLog},
+    program::NixError,
+    require,
+    state::{MarketFixed, MarketInsurance},
+    validation::{
+        get_market_insurance_address, loaders::CreateMarketInsuranceContext, NixAccountInfo,
+    },
+};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct CreateMarketInsuranceParams {
+    /// Share of the protocol fees of the market that goes to the fund, in
+    /// bps.
+    pub insurance_fee_bps: u16,
+}
+
+pub(crate) fn process_create_market_insurance(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let params: CreateMarketInsuranceParams = CreateMarketInsuranceParams::try_from_slice(data)?;
+    process_create_market_insurance_core(program_id, accounts, params)
+}
+
+/// Opens the insurance fund of a market. Only protocol fees charged from
+/// here on are split into it.
+pub(crate) fn process_create_market_insurance_core(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    params: CreateMarketInsuranceParams,
+) -> ProgramResult {
+    trace!("process_create_market_insurance accs={accounts:?}");
+    let CreateMarketInsuranceContext {
+        admin,
+        market,
+        market_insurance,
+        system_program,
+    } = CreateMarketInsuranceContext::load(accounts)?;
+    require!(
+        params.insurance_fee_bps <= 10_000,
+        NixError::InvalidMarketParameters,
+        "Insurance fee {} bps is more than the whole protocol fee",
+        params.insurance_fee_bps,
+    )?;
+
+    let (_market_insurance_key, market_insurance_bump) = get_market_insurance_address(market.key);
+    let market_insurance_seeds: Vec<Vec<u8>> = vec![
+        b"market-insurance".to_vec(),
+        market.key.as_ref().to_vec(),
+        vec![market_insurance_bump],
+    ];
+    let market_insurance: NixAccountInfo<MarketInsurance> =
+        NixAccountInfo::<MarketInsurance>::new_init_pda(
+            market_insurance.info,
+            &admin,
+            &system_program,
+            market_insurance_seeds,
+        )?;
+    let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+    market_insurance.init_fixed(MarketInsurance::new(
+        *market.key,
+        params.insurance_fee_bps,
+        market_fixed.get_base_a_protocol_fee_shares(),
+        market_fixed.get_base_b_protocol_fee_shares(),
+    ))?;
+
+    emit_stack(CreateMarketInsuranceLog {
+        market: *market.key,
+        market_insurance: *market_insurance.key,
+        admin: *admin.key,
+        insurance_fee_bps: params.insurance_fee_bps,
+        _padding: [0; 6],
+    })
+}
@@ -1,8 +1,8 @@
 use crate::{
     logs::{emit_stack, CreateMarketLoanAccountLog},
     program::expand_market_loans_if_needed,
-    state::MarketLoansFixed,
-    validation::loaders::CreateMarketLoanAccountContext,
+    state::{MarketFixed, MarketLoansFixed},
+    validation::{loaders::CreateMarketLoanAccountContext, NixAccountInfo, Signer},
 };
 use hypertree::{get_mut_helper, trace};
 use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
@@ -29,7 +29,7 @@ pub(crate) fn process_create_market_loan_account_core(
         admin,
         market_loan_account,
         market,
-        ..
+        previous_market_loans_opt,
     } = &create_context;
 
     // Do not need to initialize with the system program because it is
@@ -39,22 +39,41 @@ pub(crate) fn process_create_market_loan_account_core(
     // one market loan account or the account is getting close to its full capacity,
     // it could be useful to have a second where it
     // is easier to land transactions and record active loans.
+    initialize_market_loans(admin, market_loan_account, market)?;
 
-    // Setup the empty market loan account
+    if let Some(previous_market_loans) = previous_market_loans_opt {
+        let previous_market_loans_bytes: &mut [u8] =
+            &mut previous_market_loans.try_borrow_mut_data()?[..];
+        get_mut_helper::<MarketLoansFixed>(previous_market_loans_bytes, 0_u32).next_page =
+            *market_loan_account.key;
+    }
+    Ok(())
+}
+
+/// Writes an empty loan ledger for the market into an allocated account and
+/// leaves a free block on it. Shared with CreateMarket so that a market never
+/// exists without its first loans account.
+pub(crate) fn initialize_market_loans<'a, 'info>(
+    admin: &Signer<'a, 'info>,
+    market_loan_account: &NixAccountInfo<'a, 'info, MarketLoansFixed>,
+    market: &NixAccountInfo<'a, 'info, MarketFixed>,
+) -> ProgramResult {
     let empty_market_loans_fixed: MarketLoansFixed = MarketLoansFixed::new_empty(*market.key);
     assert_eq!(
         market_loan_account.data_len(),
         size_of::<MarketLoansFixed>()
     );
 
-    let market_loan_bytes: &mut [u8] = &mut market_loan_account.try_borrow_mut_data()?[..];
-    *get_mut_helper::<MarketLoansFixed>(market_loan_bytes, 0_u32) = empty_market_loans_fixed;
+    {
+        let market_loan_bytes: &mut [u8] = &mut market_loan_account.try_borrow_mut_data()?[..];
+        *get_mut_helper::<MarketLoansFixed>(market_loan_bytes, 0_u32) = empty_market_loans_fixed;
+    }
 
     emit_stack(CreateMarketLoanAccountLog {
         market: *market.key,
         market_loan_account_key: *market_loan_account.key,
         admin: *admin.key,
     })?;
-    expand_market_loans_if_needed(&admin, &market_loan_account, 1)?;
+    expand_market_loans_if_needed(admin, market_loan_account, 1)?;
     Ok(())
 }
@@ -0,0 +1,79 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::trace;
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    program::{
+        create_market::{process_create_market_core, CreateMarketParams},
+        NixError,
+    },
+    require,
+    state::MarketFixed,
+    validation::{get_market_address, loaders::CreateMarketPdaContext, NixAccountInfo},
+};
+
+/// Nonce of the market routers derive for a pair.
+const CANONICAL_MARKET_NONCE: u64 = 0;
+/// Highest protocol fee the canonical market of a pair can be created with.
+/// Whoever creates it first picks its terms, so they are kept to ones every
+/// trader can live with.
+pub const MAX_CANONICAL_PROTOCOL_FEE_RATE_BPS: u64 = 50;
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct CreateMarketPdaParams {
+    /// Picks one of the canonical markets of the pair.
+    pub nonce: u64,
+    pub market_params: CreateMarketParams,
+}
+
+pub(crate) fn process_create_market_pda(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    trace!("process_create_market_pda accs={accounts:?}");
+    let CreateMarketPdaParams {
+        nonce,
+        market_params,
+    } = CreateMarketPdaParams::try_from_slice(data)?;
+    require!(
+        nonce != CANONICAL_MARKET_NONCE
+            || (market_params.allowlist_authority.is_none()
+                && market_params.protocol_fee_rate_bps <= MAX_CANONICAL_PROTOCOL_FEE_RATE_BPS),
+        NixError::InvalidMarketParameters,
+        "The canonical market has to be permissionless with a protocol fee of at most {} bps, got {} bps",
+        MAX_CANONICAL_PROTOCOL_FEE_RATE_BPS,
+        market_params.protocol_fee_rate_bps,
+    )?;
+    let create_market_pda_context: CreateMarketPdaContext =
+        CreateMarketPdaContext::load(accounts, nonce)?;
+
+    let CreateMarketPdaContext {
+        admin,
+        market,
+        system_program,
+        base_a_mint,
+        base_b_mint,
+    } = create_market_pda_context;
+
+    // Allocate the market at its PDA, after which it looks the same as a
+    // client allocated market to the rest of CreateMarket.
+    let (_market_key, market_bump) =
+        get_market_address(base_a_mint.info.key, base_b_mint.info.key, nonce);
+    let market_seeds: Vec<Vec<u8>> = vec![
+        b"market".to_vec(),
+        base_a_mint.info.key.as_ref().to_vec(),
+        base_b_mint.info.key.as_ref().to_vec(),
+        nonce.to_le_bytes().to_vec(),
+        vec![market_bump],
+    ];
+    NixAccountInfo::<MarketFixed>::new_init_pda(
+        market.info,
+        &admin,
+        &system_program,
+        market_seeds,
+    )?;
+
+    process_create_market_core(program_id, accounts, market_params)
+}
@@ -0,0 +1,44 @@
+use hypertree::trace;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, CreateMarketStatsLog},
+    state::MarketStats,
+    validation::{get_market_stats_address, loaders::CreateMarketStatsContext, NixAccountInfo},
+};
+
+/// Stats are only kept once this account exists and PlaceOrder is given it.
+pub(crate) fn process_create_market_stats(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    _data: &[u8],
+) -> ProgramResult {
+    trace!("process_create_market_stats accs={accounts:?}");
+    let CreateMarketStatsContext {
+        payer,
+        market,
+        market_stats,
+        system_program,
+    } = CreateMarketStatsContext::load(accounts)?;
+
+    let (_market_stats_key, market_stats_bump) = get_market_stats_address(market.key);
+    let market_stats_seeds: Vec<Vec<u8>> = vec![
+        b"market-stats".to_vec(),
+        market.key.as_ref().to_vec(),
+        vec![market_stats_bump],
+    ];
+    let market_stats: NixAccountInfo<MarketStats> = NixAccountInfo::<MarketStats>::new_init_pda(
+        market_stats.info,
+        &payer,
+        &system_program,
+        market_stats_seeds,
+    )?;
+    market_stats.init_fixed(MarketStats::new(*market.key))?;
+
+    emit_stack(CreateMarketStatsLog {
+        market: *market.key,
+        market_stats: *market_stats.key,
+        payer: *payer.key,
+    })?;
+    Ok(())
+}
@@ -3,27 +3,43 @@ use std::cell::RefMut;
 use borsh::{BorshDeserialize, BorshSerialize};
 use fixed::types::I80F48;
 use hypertree::DataIndex;
-use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+use shank::ShankType;
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
+    pubkey::Pubkey,
+};
 
 use crate::{
-    marginfi_utils::cpi_marginfi_deposit, market_signer_seeds_with_bump,  program::NixError, state::MarketRefMut, validation::{
-        loaders::DepositContext, MintAccountInfo, Signer, TokenAccountInfo, TokenProgram,
+    logs::{emit_stack, SeatUpdatedLog}, marginfi_utils::cpi_marginfi_deposit, market_signer_seeds_with_bump,  program::NixError, state::{MarketFixed, MarketRefMut, SeatSnapshot}, validation::{
+        loaders::DepositContext, MintAccountInfo, NixAccountInfo, Signer, TokenAccountInfo, TokenProgram,
     }
 };
 
-use super::{get_mut_dynamic_account, get_trader_index_with_hint};
+use super::{
+    claim_seat::claim_seat_if_needed, get_mut_dynamic_account, get_trader_index_with_hint,
+    sweep_expired_orders_of_trader,
+};
 
-#[derive(BorshDeserialize, BorshSerialize)]
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
 pub struct DepositParams {
     pub amount: u64,
     pub trader_index_hint: Option<DataIndex>,
+    /// Claim a seat first if the payer has none. Not allowed on permissioned
+    /// markets.
+    pub auto_claim_seat: bool,
+    /// Up to this many of the payer's own expired orders are removed and
+    /// their collateral returned to the seat, capped at
+    /// MAX_SWEPT_EXPIRED_ORDERS. Zero skips the scan.
+    pub max_expired_orders_to_sweep: u8,
 }
 
 impl DepositParams {
-    pub fn new(amount: u64, trader_index_hint: Option<DataIndex>) -> Self {
+    pub fn new(amount: u64, trader_index_hint: Option<DataIndex>, auto_claim_seat: bool) -> Self {
         DepositParams {
             amount,
             trader_index_hint,
+            auto_claim_seat,
+            max_expired_orders_to_sweep: 0,
         }
     }
 }
@@ -45,11 +61,55 @@ pub(crate) fn process_deposit_core(
     let DepositParams {
         amount,
         trader_index_hint,
+        auto_claim_seat,
+        max_expired_orders_to_sweep,
     } = params;
-    // Due to transfer fees, this might not be what you expect.
-    let mut deposited_amount: u64 = amount;
 
     let deposit_context: DepositContext = DepositContext::load(accounts)?;
+    let payer: &Signer = &deposit_context.payer;
+    let market: &NixAccountInfo<MarketFixed> = &deposit_context.market;
+
+    if auto_claim_seat {
+        claim_seat_if_needed(market, payer)?;
+    }
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    let mut dynamic_account: MarketRefMut = get_mut_dynamic_account(market_data);
+
+    let (mfi_asset_shares_gained, is_base_a) =
+        deposit_to_marginfi(&deposit_context, dynamic_account.fixed, amount)?;
+
+    let trader_index: DataIndex =
+        get_trader_index_with_hint(trader_index_hint, &dynamic_account, payer)?;
+
+    dynamic_account.deposit(trader_index, mfi_asset_shares_gained.into(), is_base_a)?;
+    sweep_expired_orders_of_trader(
+        &mut dynamic_account,
+        &market,
+        &payer,
+        trader_index,
+        max_expired_orders_to_sweep,
+    )?;
+
+    let seat: SeatSnapshot = dynamic_account.get_seat_snapshot(payer.key).unwrap();
+    emit_stack(SeatUpdatedLog {
+        market: *market.key,
+        trader: *payer.key,
+        base_a_withdrawable_asset_share: seat.base_a_withdrawable_asset_share,
+        base_b_withdrawable_asset_share: seat.base_b_withdrawable_asset_share,
+    })?;
+    Ok(())
+}
+
+/// Moves amount from the trader token account through the vault into the
+/// marginfi account of the market. Returns the asset shares that gained, and
+/// whether they are in base a. Shared with TopUpCollateral, which takes the
+/// same accounts.
+pub(crate) fn deposit_to_marginfi(
+    deposit_context: &DepositContext,
+    market_fixed: &MarketFixed,
+    amount: u64,
+) -> Result<(I80F48, bool), ProgramError> {
     let DepositContext {
         payer,
         market,
@@ -62,32 +122,28 @@ pub(crate) fn process_deposit_core(
         marginfi_bank,
         marginfi_account,
         marginfi_liquidity_vault,
+        transfer_hook_accounts,
     } = deposit_context;
+    // Due to transfer fees, this might not be what you expect.
+    let mut deposited_amount: u64 = amount;
 
-    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
-    let mut dynamic_account: MarketRefMut = get_mut_dynamic_account(market_data);
-
-    let is_base_a: bool = &trader_token_account.try_borrow_data()?[0..32]
-        == dynamic_account.get_base_a_mint().as_ref();
+    let is_base_a: bool =
+        &trader_token_account.try_borrow_data()?[0..32] == market_fixed.get_base_a_mint().as_ref();
 
     if *vault.owner == spl_token_2022::id() {
         let before_vault_balance: u64 = vault.get_balance();
         spl_token_2022_transfer_from_trader_to_vault(
-            &token_program,
-            &trader_token_account,
-            Some(&mint),
-            if is_base_a {
-                dynamic_account.fixed.get_base_a_mint()
-            } else {
-                dynamic_account.get_base_b_mint()
-            },
-            &vault,
-            &payer,
+            token_program,
+            trader_token_account,
+            mint,
+            vault,
+            payer,
+            transfer_hook_accounts,
             amount,
             if is_base_a {
-                dynamic_account.fixed.get_base_a_decimals()
+                market_fixed.get_base_a_decimals()
             } else {
-                dynamic_account.fixed.get_base_b_decimals()
+                market_fixed.get_base_b_decimals()
             },
         )?;
 
@@ -97,10 +153,10 @@ pub(crate) fn process_deposit_core(
             .unwrap();
     } else {
         spl_token_transfer_from_trader_to_vault(
-            &token_program,
-            &trader_token_account,
-            &vault,
-            &payer,
+            token_program,
+            trader_token_account,
+            vault,
+            payer,
             amount,
         )?;
     }
@@ -119,23 +175,24 @@ pub(crate) fn process_deposit_core(
 
     // Prepare mint option for CPI
     let mint_option = if *vault.owner == spl_token_2022::id() {
-        Some(mint)
+        Some(mint.clone())
     } else {
         None
     };
 
     // deposit CPI to marginfi
     cpi_marginfi_deposit(
-        &marginfi_group,
-        &marginfi_account,
-        &marginfi_bank,
-        &marginfi_liquidity_vault,
+        marginfi_group,
+        marginfi_account,
+        marginfi_bank,
+        marginfi_liquidity_vault,
         market_signer.clone(),
-        &vault,
-        &token_program,
+        vault,
+        token_program,
         deposited_amount,
         None,
         &mint_option,
+        transfer_hook_accounts,
         market_signer_seeds_with_bump!(market.key, market_signer.bump),
     )?;
 
@@ -157,12 +214,7 @@ pub(crate) fn process_deposit_core(
     if mfi_asset_shares_gained < I80F48::ZERO {
         return Err(NixError::InvalidMarginfiState.into());
     }
-
-    let trader_index: DataIndex =
-        get_trader_index_with_hint(trader_index_hint, &dynamic_account, &payer)?;
-
-    dynamic_account.deposit(trader_index, mfi_asset_shares_gained.into(), is_base_a)?;
-    Ok(())
+    Ok((mfi_asset_shares_gained, is_base_a))
 }
 
 /** Transfer from base (quote) trader to base (quote) vault using SPL Token **/
@@ -192,33 +244,27 @@ fn spl_token_transfer_from_trader_to_vault<'a, 'info>(
 }
 
 /** Transfer from base (quote) trader to base (quote) vault using SPL Token 2022 **/
+/// Resolves and appends the extra accounts of the mint's transfer hook, if it
+/// has one, from `transfer_hook_accounts`.
 fn spl_token_2022_transfer_from_trader_to_vault<'a, 'info>(
     token_program: &TokenProgram<'a, 'info>,
     trader_account: &TokenAccountInfo<'a, 'info>,
-    mint: Option<&MintAccountInfo<'a, 'info>>,
-    mint_pubkey: &Pubkey,
+    mint: &MintAccountInfo<'a, 'info>,
     vault: &TokenAccountInfo<'a, 'info>,
     payer: &Signer<'a, 'info>,
+    transfer_hook_accounts: &[AccountInfo<'info>],
     amount: u64,
     decimals: u8,
 ) -> ProgramResult {
-    crate::program::invoke(
-        &spl_token_2022::instruction::transfer_checked(
-            token_program.key,
-            trader_account.key,
-            mint_pubkey,
-            vault.key,
-            payer.key,
-            &[],
-            amount,
-            decimals,
-        )?,
-        &[
-            token_program.as_ref().clone(),
-            trader_account.as_ref().clone(),
-            vault.as_ref().clone(),
-            mint.unwrap().as_ref().clone(),
-            payer.as_ref().clone(),
-        ],
+    spl_token_2022::onchain::invoke_transfer_checked(
+        token_program.key,
+        trader_account.as_ref().clone(),
+        mint.as_ref().clone(),
+        vault.as_ref().clone(),
+        payer.as_ref().clone(),
+        transfer_hook_accounts,
+        amount,
+        decimals,
+        &[],
     )
 }
@@ -0,0 +1,138 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use fixed::types::I80F48;
+use hypertree::{get_mut_helper, trace, PodBool};
+use shank::ShankType;
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+use crate::{
+    logs::{emit_stack, DrawInsuranceLog},
+    marginfi_utils::{cpi_marginfi_withdraw_to, MarginfiCpiScratch},
+    market_signer_seeds_with_bump,
+    program::NixError,
+    require,
+    state::{MarketFixed, MarketInsurance},
+    validation::{loaders::DrawInsuranceContext, MintAccountInfo},
+};
+
+use super::release_collateral::transfer_from_vault_to_trader;
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct DrawInsuranceParams {
+    /// Atoms of the mint to withdraw to the admin.
+    pub amount: u64,
+}
+
+pub(crate) fn process_draw_insurance<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+) -> ProgramResult {
+    let params: DrawInsuranceParams = DrawInsuranceParams::try_from_slice(data)?;
+    process_draw_insurance_core(program_id, accounts, params)
+}
+
+/// Takes tokens out of the insurance fund of a market, for the admin to
+/// make lenders whole off chain or move the fund elsewhere. Only what the
+/// fund holds can be drawn, the shares of seats and the protocol are left
+/// alone.
+pub(crate) fn process_draw_insurance_core<'a>(
+    _program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    params: DrawInsuranceParams,
+) -> ProgramResult {
+    trace!("process_draw_insurance accts={accounts:?}");
+    let DrawInsuranceParams { amount } = params;
+    let DrawInsuranceContext {
+        admin,
+        market,
+        market_insurance,
+        market_signer,
+        mint,
+        admin_token_account,
+        token_program,
+        vault,
+        marginfi_cpi_accounts,
+        is_base_a,
+    } = DrawInsuranceContext::load(accounts)?;
+
+    let get_asset_shares = || -> Result<I80F48, ProgramError> {
+        Ok(marginfi_cpi_accounts
+            .marginfi_account
+            .get_fixed()?
+            .lending_account
+            .balances
+            .iter()
+            .find(|b| b.active != 0 && b.bank_pk == *marginfi_cpi_accounts.marginfi_bank.key)
+            .map(|b| I80F48::from(b.asset_shares))
+            .unwrap_or_default())
+    };
+    let asset_shares_before: I80F48 = get_asset_shares()?;
+    let vault_balance_before: u64 = vault.get_balance();
+    let mint_opt: Option<&MintAccountInfo> = if *vault.owner == spl_token_2022::id() {
+        Some(&mint)
+    } else {
+        None
+    };
+    cpi_marginfi_withdraw_to(
+        &marginfi_cpi_accounts,
+        &vault,
+        &token_program,
+        amount,
+        mint_opt,
+        market_signer.clone(),
+        &mut MarginfiCpiScratch::default(),
+        market_signer_seeds_with_bump!(market.key, market_signer.bump),
+        accounts,
+    )?;
+    let withdrawn_shares: I80F48 = asset_shares_before
+        .checked_sub(get_asset_shares()?)
+        .ok_or(NixError::NumericalOverflow)?;
+    // Due to transfer fees, this might not be what you expect.
+    let withdrawn_amount: u64 = vault
+        .get_balance()
+        .checked_sub(vault_balance_before)
+        .ok_or(NixError::NumericalOverflow)?;
+
+    {
+        let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+        let market_fixed: &mut MarketFixed = get_mut_helper::<MarketFixed>(market_data, 0_u32);
+        let market_insurance_data: &mut RefMut<&mut [u8]> =
+            &mut market_insurance.try_borrow_mut_data()?;
+        let market_insurance_fixed: &mut MarketInsurance =
+            get_mut_helper::<MarketInsurance>(market_insurance_data, 0_u32);
+        market_fixed.accrue_insurance_fees(market_insurance_fixed)?;
+        let drawn_shares: I80F48 = market_insurance_fixed.draw(is_base_a, withdrawn_shares)?;
+        require!(
+            drawn_shares == withdrawn_shares,
+            NixError::InvalidInsuranceDraw,
+            "Withdrew {} shares, fund only holds {}",
+            withdrawn_shares,
+            drawn_shares,
+        )?;
+    }
+
+    transfer_from_vault_to_trader(
+        &token_program,
+        &vault,
+        &mint,
+        &admin_token_account,
+        &market_signer,
+        market.key,
+        withdrawn_amount,
+        accounts,
+    )?;
+
+    emit_stack(DrawInsuranceLog {
+        market: *market.key,
+        admin: *admin.key,
+        shares: withdrawn_shares.into(),
+        amount: withdrawn_amount,
+        is_base_a: PodBool::from(is_base_a),
+        _padding: [0; 7],
+    })
+}
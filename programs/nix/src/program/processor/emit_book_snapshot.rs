@@ -0,0 +1,93 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::{trace, PodBool};
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, BookSnapshotLog},
+    state::MarketRefMut,
+    utils::{get_now_slot, get_now_unix_timestamp},
+    validation::loaders::EmitBookSnapshotContext,
+};
+
+use super::get_mut_dynamic_account;
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct EmitBookSnapshotParams {
+    pub use_a_tree: bool,
+    // Capped at MAX_BOOK_SNAPSHOT_LEVELS.
+    pub max_levels: u8,
+}
+
+impl EmitBookSnapshotParams {
+    pub fn new(use_a_tree: bool, max_levels: u8) -> Self {
+        EmitBookSnapshotParams {
+            use_a_tree,
+            max_levels,
+        }
+    }
+}
+
+pub(crate) fn process_emit_book_snapshot(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let params: EmitBookSnapshotParams = EmitBookSnapshotParams::try_from_slice(data)?;
+    process_emit_book_snapshot_core(program_id, accounts, params)
+}
+
+pub(crate) fn process_emit_book_snapshot_core(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    params: EmitBookSnapshotParams,
+) -> ProgramResult {
+    trace!("process_emit_book_snapshot accts={accounts:?}");
+    let emit_book_snapshot_context: EmitBookSnapshotContext =
+        EmitBookSnapshotContext::load(accounts)?;
+    let EmitBookSnapshotContext { market } = emit_book_snapshot_context;
+
+    let EmitBookSnapshotParams {
+        use_a_tree,
+        max_levels,
+    } = params;
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    let mut dynamic_account: MarketRefMut = get_mut_dynamic_account(market_data);
+
+    let now_slot: u32 = get_now_slot();
+    dynamic_account
+        .fixed
+        .record_book_snapshot(use_a_tree, now_slot)?;
+
+    let now_unix_timestamp: i64 = get_now_unix_timestamp();
+    let (bids, num_bid_levels) = dynamic_account.get_book_levels(
+        use_a_tree,
+        true,
+        max_levels as usize,
+        now_slot,
+        now_unix_timestamp,
+    )?;
+    let (asks, num_ask_levels) = dynamic_account.get_book_levels(
+        use_a_tree,
+        false,
+        max_levels as usize,
+        now_slot,
+        now_unix_timestamp,
+    )?;
+
+    emit_stack(BookSnapshotLog {
+        market: *market.key,
+        slot: now_slot as u64,
+        is_a_tree: PodBool::from_bool(use_a_tree),
+        num_bid_levels,
+        num_ask_levels,
+        _padding: [0; 5],
+        bids,
+        asks,
+    })?;
+
+    Ok(())
+}
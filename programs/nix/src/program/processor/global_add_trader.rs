@@ -20,6 +20,10 @@ pub(crate) fn process_global_add_trader(
 
     let GlobalAddTraderContext { payer, global, .. } = global_add_trader_context;
 
+    // Globals are shared by every market of a mint so they are not gated.
+    // Permissioned markets are still protected because a global order can
+    // only be placed by a trader with a seat on the market.
+
     // Needs a spot for this trader on the global account.
     expand_global(&payer, &global)?;
 
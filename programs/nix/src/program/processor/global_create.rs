@@ -1,8 +1,10 @@
 use std::cell::Ref;
 
-use hypertree::{get_mut_helper, trace};
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::trace;
+use shank::ShankType;
 use solana_program::{
-    account_info::AccountInfo, entrypoint::ProgramResult, program_pack::Pack, pubkey::Pubkey, rent::Rent, system_instruction, sysvar::Sysvar
+    account_info::AccountInfo, entrypoint::ProgramResult, program_pack::Pack, pubkey::Pubkey, rent::Rent, sysvar::Sysvar
 };
 use spl_token_2022::{
     extension::{BaseStateWithExtensions, ExtensionType, PodStateWithExtensions},
@@ -10,19 +12,58 @@ use spl_token_2022::{
     state::Account,
 
 };
-use std::mem::size_of;
 
 use crate::{
-    logs::{emit_stack, GlobalCreateLog}, program::invoke, state::GlobalFixed, utils::create_account, validation::{get_global_address, get_global_vault_address, loaders::GlobalCreateContext}
+    logs::{emit_stack, GlobalCreateLog},
+    program::{invoke, NixError},
+    require,
+    state::{
+        GlobalFixed, GAS_DEPOSIT_LAMPORTS, MAX_GAS_DEPOSIT_LAMPORTS, MIN_GAS_DEPOSIT_LAMPORTS,
+    },
+    utils::create_account,
+    validation::{
+        get_global_address, get_global_vault_address, loaders::GlobalCreateContext, NixAccountInfo,
+    },
 };
 
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct GlobalCreateParams {
+    /// Lamports each global order on this global prepays for its removal.
+    pub gas_deposit_lamports: u64,
+}
+
+impl Default for GlobalCreateParams {
+    fn default() -> Self {
+        GlobalCreateParams {
+            gas_deposit_lamports: GAS_DEPOSIT_LAMPORTS,
+        }
+    }
+}
+
 pub(crate) fn process_global_create(
     _program_id: &Pubkey,
     accounts: &[AccountInfo],
-    _data: &[u8],
+    data: &[u8],
 ) -> ProgramResult {
     {
         trace!("process_global_create accs={accounts:?}");
+        // Clients from before the deposit was configurable send no data.
+        let params: GlobalCreateParams = if data.is_empty() {
+            GlobalCreateParams::default()
+        } else {
+            GlobalCreateParams::try_from_slice(data)?
+        };
+        let GlobalCreateParams {
+            gas_deposit_lamports,
+        } = params;
+        require!(
+            (MIN_GAS_DEPOSIT_LAMPORTS..=MAX_GAS_DEPOSIT_LAMPORTS).contains(&gas_deposit_lamports),
+            NixError::InvalidGasDeposit,
+            "Gas deposit {} is outside of [{}, {}]",
+            gas_deposit_lamports,
+            MIN_GAS_DEPOSIT_LAMPORTS,
+            MAX_GAS_DEPOSIT_LAMPORTS,
+        )?;
         let global_create_context: GlobalCreateContext = GlobalCreateContext::load(accounts)?;
 
         let GlobalCreateContext {
@@ -43,36 +84,17 @@ pub(crate) fn process_global_create(
                 vec![global_bump],
             ];
 
-            if global.info.lamports() > 0 {
-                invoke(
-                    &system_instruction::transfer(
-                        global.info.key,
-                        payer.info.key,
-                        global.info.lamports(),
-                    ),
-                    &[
-                        payer.info.clone(),
-                        global.info.clone(),
-                        system_program.info.clone(),
-                    ],
+            let global: NixAccountInfo<GlobalFixed> =
+                NixAccountInfo::<GlobalFixed>::new_init_pda(
+                    global.info,
+                    &payer,
+                    &system_program,
+                    global_seeds,
                 )?;
-            }
-            create_account(
-                payer.as_ref(),
-                global.as_ref(),
-                system_program.as_ref(),
-                &crate::id(),
-                &Rent::get()?,
-                size_of::<GlobalFixed>() as u64,
-                global_seeds,
-            )?;
-
-            // Setup the empty market
-            let empty_global_fixed: GlobalFixed = GlobalFixed::new_empty(&global_mint.as_ref().key);
-            assert_eq!(global.info.data_len(), size_of::<GlobalFixed>());
-
-            let global_bytes: &mut [u8] = &mut global.info.try_borrow_mut_data()?[..];
-            *get_mut_helper::<GlobalFixed>(global_bytes, 0_u32) = empty_global_fixed;
+            global.init_fixed(GlobalFixed::new_empty(
+                &global_mint.as_ref().key,
+                gas_deposit_lamports,
+            ))?;
 
             // Global does not require a permanent free block for swapping.
         }
@@ -1,18 +1,22 @@
 use std::cell::RefMut;
 
 use borsh::{BorshDeserialize, BorshSerialize};
+use fixed::types::I80F48;
+use shank::ShankType;
 use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
 
 use crate::{
+    global_vault_seeds_with_bump,
     logs::{emit_stack, GlobalDepositLog},
-    program::get_mut_dynamic_account,
+    marginfi_utils::{cpi_marginfi_deposit, get_marginfi_asset_shares},
+    program::{get_mut_dynamic_account, NixError},
     state::GlobalRefMut,
-    validation::loaders::GlobalDepositContext,
+    validation::{loaders::GlobalDepositContext, MintAccountInfo},
 };
 
 use super::invoke;
 
-#[derive(BorshDeserialize, BorshSerialize)]
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
 pub struct GlobalDepositParams {
     pub amount: u64,
     // No trader index hint because global account is small so there is not much
@@ -44,33 +48,25 @@ pub(crate) fn process_global_deposit(
         global_vault,
         trader_token: trader_token_account,
         token_program,
+        marginfi_cpi_accounts_opt,
+        transfer_hook_accounts,
     } = global_deposit_context;
 
-    let global_data: &mut RefMut<&mut [u8]> = &mut global.try_borrow_mut_data()?;
-    let mut global_dynamic_account: GlobalRefMut = get_mut_dynamic_account(global_data);
-    global_dynamic_account.deposit_global(payer.key, amount)?;
-
-    // Do the token transfer
+    // Do the token transfer. Only what reaches the vault gets credited,
+    // otherwise a transfer fee would leave the global short when a global
+    // order matches.
     if *global_vault.owner == spl_token_2022::id() {
         let before_vault_balance: u64 = global_vault.get_balance();
-        invoke(
-            &spl_token_2022::instruction::transfer_checked(
-                token_program.key,
-                trader_token_account.key,
-                mint.info.key,
-                global_vault.key,
-                payer.key,
-                &[],
-                amount,
-                mint.mint.decimals,
-            )?,
-            &[
-                token_program.as_ref().clone(),
-                trader_token_account.as_ref().clone(),
-                mint.as_ref().clone(),
-                global_vault.as_ref().clone(),
-                payer.as_ref().clone(),
-            ],
+        spl_token_2022::onchain::invoke_transfer_checked(
+            token_program.key,
+            trader_token_account.as_ref().clone(),
+            mint.as_ref().clone(),
+            global_vault.as_ref().clone(),
+            payer.as_ref().clone(),
+            transfer_hook_accounts,
+            amount,
+            mint.mint.decimals,
+            &[],
         )?;
 
         let after_vault_balance: u64 = global_vault.get_balance();
@@ -96,6 +92,40 @@ pub(crate) fn process_global_deposit(
         )?;
     }
 
+    // A linked global lends what arrived and credits the shares it minted.
+    let deposited_shares: I80F48 = if let Some(marginfi_cpi_accounts) = marginfi_cpi_accounts_opt {
+        let global_vault_bump: u8 = global.get_fixed()?.get_vault_bump();
+        let mint_opt: Option<MintAccountInfo> = if *global_vault.owner == spl_token_2022::id() {
+            Some(mint.clone())
+        } else {
+            None
+        };
+        let asset_shares_before: I80F48 = get_marginfi_asset_shares(&marginfi_cpi_accounts)?;
+        cpi_marginfi_deposit(
+            &marginfi_cpi_accounts.marginfi_group,
+            &marginfi_cpi_accounts.marginfi_account,
+            &marginfi_cpi_accounts.marginfi_bank,
+            &marginfi_cpi_accounts.marginfi_liquidity_vault,
+            global_vault.clone(),
+            &global_vault,
+            &token_program,
+            deposited_amount,
+            None,
+            &mint_opt,
+            transfer_hook_accounts,
+            global_vault_seeds_with_bump!(mint.info.key, global_vault_bump),
+        )?;
+        get_marginfi_asset_shares(&marginfi_cpi_accounts)?
+            .checked_sub(asset_shares_before)
+            .ok_or(NixError::NumericalOverflow)?
+    } else {
+        I80F48::from_num(deposited_amount)
+    };
+
+    let global_data: &mut RefMut<&mut [u8]> = &mut global.try_borrow_mut_data()?;
+    let mut global_dynamic_account: GlobalRefMut = get_mut_dynamic_account(global_data);
+    global_dynamic_account.deposit_global(payer.key, deposited_shares)?;
+
     emit_stack(GlobalDepositLog {
         global: *global.key,
         trader: *payer.key,
@@ -0,0 +1,214 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use fixed::types::I80F48;
+use hypertree::trace;
+use shank::ShankType;
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, program::invoke_signed, pubkey::Pubkey,
+};
+
+use crate::{
+    global_vault_seeds_with_bump,
+    logs::{emit_stack, GlobalEvictLog},
+    marginfi_utils::{
+        cpi_marginfi_deposit, cpi_marginfi_withdraw_to, get_marginfi_asset_shares, BankShareValues,
+        MarginfiCpiScratch,
+    },
+    math::convert_asset_shares_to_tokens,
+    program::{get_mut_dynamic_account, NixError},
+    require,
+    state::GlobalRefMut,
+    validation::{loaders::GlobalEvictContext, MintAccountInfo},
+};
+
+use super::invoke;
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct GlobalEvictParams {
+    /// Amount the new trader deposits. Has to be more than the balance of the
+    /// trader being evicted.
+    pub amount: u64,
+}
+
+impl GlobalEvictParams {
+    pub fn new(amount: u64) -> Self {
+        GlobalEvictParams { amount }
+    }
+}
+
+pub(crate) fn process_global_evict<'a>(
+    _program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+) -> ProgramResult {
+    trace!("process_global_evict accs={accounts:?}");
+    let global_evict_context: GlobalEvictContext = GlobalEvictContext::load(accounts)?;
+    let GlobalEvictParams { amount } = GlobalEvictParams::try_from_slice(data)?;
+
+    let GlobalEvictContext {
+        payer,
+        global,
+        mint,
+        global_vault,
+        trader_token,
+        evictee_token,
+        token_program,
+        marginfi_cpi_accounts_opt,
+    } = global_evict_context;
+
+    let global_data: &mut RefMut<&mut [u8]> = &mut global.try_borrow_mut_data()?;
+    let mut global_dynamic_account: GlobalRefMut = get_mut_dynamic_account(global_data);
+
+    require!(
+        global_dynamic_account.fixed.is_full(),
+        NixError::GlobalNotFull,
+        "Global {} has free seats",
+        global.key,
+    )?;
+    let (evictee, evictee_balance_shares) = global_dynamic_account.get_min_deposit().unwrap();
+    require!(
+        evictee_token.get_owner() == evictee,
+        NixError::IncorrectAccount,
+        "Evictee token account must be owned by {}",
+        evictee,
+    )?;
+
+    // Due to transfer fees, this might not be what you expect.
+    let mut deposited_amount: u64 = amount;
+    let is_mint_22: bool = *global_vault.owner == spl_token_2022::id();
+    if is_mint_22 {
+        let before_vault_balance: u64 = global_vault.get_balance();
+        spl_token_2022::onchain::invoke_transfer_checked(
+            token_program.key,
+            trader_token.as_ref().clone(),
+            mint.as_ref().clone(),
+            global_vault.as_ref().clone(),
+            payer.as_ref().clone(),
+            accounts,
+            amount,
+            mint.mint.decimals,
+            &[],
+        )?;
+        let after_vault_balance: u64 = global_vault.get_balance();
+        deposited_amount = after_vault_balance
+            .checked_sub(before_vault_balance)
+            .unwrap();
+    } else {
+        invoke(
+            &spl_token::instruction::transfer(
+                token_program.key,
+                trader_token.key,
+                global_vault.key,
+                payer.key,
+                &[],
+                amount,
+            )?,
+            &[
+                token_program.as_ref().clone(),
+                trader_token.as_ref().clone(),
+                global_vault.as_ref().clone(),
+                payer.as_ref().clone(),
+            ],
+        )?;
+    }
+
+    // The evictee gets what their shares are worth, rounded down. The
+    // remainder stays lent for everyone else.
+    let evictee_atoms: u64 = match &marginfi_cpi_accounts_opt {
+        Some(marginfi_cpi_accounts) => convert_asset_shares_to_tokens(
+            evictee_balance_shares.into(),
+            &BankShareValues::from(&*marginfi_cpi_accounts.marginfi_bank.get_fixed()?),
+        )?,
+        None => u64::from(evictee_balance_shares),
+    };
+    require!(
+        deposited_amount > evictee_atoms,
+        NixError::GlobalInsufficient,
+        "Deposit of {} does not exceed the smallest deposit of {}",
+        deposited_amount,
+        evictee_atoms,
+    )?;
+
+    // Refund the evictee in full before taking the seat. Their global orders
+    // stay on the books but are unbacked and get cleaned up on match.
+    global_dynamic_account.withdraw_global(&evictee, evictee_balance_shares)?;
+    let mint_key: Pubkey = *global_dynamic_account.fixed.get_mint();
+    let global_vault_bump: u8 = global_dynamic_account.fixed.get_vault_bump();
+    let deposited_shares: I80F48 = if let Some(marginfi_cpi_accounts) = &marginfi_cpi_accounts_opt {
+        let mint_opt: Option<MintAccountInfo> = if is_mint_22 { Some(mint.clone()) } else { None };
+        cpi_marginfi_withdraw_to(
+            marginfi_cpi_accounts,
+            &evictee_token,
+            &token_program,
+            evictee_atoms,
+            mint_opt.as_ref(),
+            global_vault.clone(),
+            &mut MarginfiCpiScratch::default(),
+            global_vault_seeds_with_bump!(mint_key, global_vault_bump),
+            accounts,
+        )?;
+        let asset_shares_before: I80F48 = get_marginfi_asset_shares(marginfi_cpi_accounts)?;
+        cpi_marginfi_deposit(
+            &marginfi_cpi_accounts.marginfi_group,
+            &marginfi_cpi_accounts.marginfi_account,
+            &marginfi_cpi_accounts.marginfi_bank,
+            &marginfi_cpi_accounts.marginfi_liquidity_vault,
+            global_vault.clone(),
+            &global_vault,
+            &token_program,
+            deposited_amount,
+            None,
+            &mint_opt,
+            accounts,
+            global_vault_seeds_with_bump!(mint_key, global_vault_bump),
+        )?;
+        get_marginfi_asset_shares(marginfi_cpi_accounts)?
+            .checked_sub(asset_shares_before)
+            .ok_or(NixError::NumericalOverflow)?
+    } else {
+        if is_mint_22 {
+            spl_token_2022::onchain::invoke_transfer_checked(
+                token_program.key,
+                global_vault.as_ref().clone(),
+                mint.as_ref().clone(),
+                evictee_token.as_ref().clone(),
+                global_vault.as_ref().clone(),
+                accounts,
+                evictee_atoms,
+                mint.mint.decimals,
+                global_vault_seeds_with_bump!(mint_key, global_vault_bump),
+            )?;
+        } else {
+            invoke_signed(
+                &spl_token::instruction::transfer(
+                    token_program.key,
+                    global_vault.key,
+                    evictee_token.key,
+                    global_vault.key,
+                    &[],
+                    evictee_atoms,
+                )?,
+                &[
+                    token_program.as_ref().clone(),
+                    global_vault.as_ref().clone(),
+                    evictee_token.as_ref().clone(),
+                ],
+                global_vault_seeds_with_bump!(mint_key, global_vault_bump),
+            )?;
+        }
+        I80F48::from_num(deposited_amount)
+    };
+
+    global_dynamic_account.evict_and_take_seat(&evictee, payer.key)?;
+    global_dynamic_account.deposit_global(payer.key, deposited_shares)?;
+
+    emit_stack(GlobalEvictLog {
+        evictor: *payer.key,
+        evictee,
+        evictor_atoms: deposited_amount,
+        evictee_atoms,
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use std::cell::RefMut;
+
+use fixed::types::I80F48;
+use hypertree::trace;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    global_vault_seeds_with_bump,
+    logs::{emit_stack, GlobalLinkMarginfiLog},
+    marginfi_utils::{cpi_marginfi_deposit, initialize_marginfi_account},
+    program::{get_mut_dynamic_account, NixError},
+    state::GlobalRefMut,
+    validation::{loaders::GlobalLinkMarginfiContext, MintAccountInfo},
+};
+
+pub(crate) fn process_global_link_marginfi(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    _data: &[u8],
+) -> ProgramResult {
+    trace!("process_global_link_marginfi accs={accounts:?}");
+    let GlobalLinkMarginfiContext {
+        payer,
+        global,
+        mint,
+        global_vault,
+        marginfi_group,
+        marginfi_bank,
+        marginfi_account,
+        marginfi_liquidity_vault,
+        token_program,
+        system_program,
+    } = GlobalLinkMarginfiContext::load(accounts)?;
+
+    let global_vault_bump: u8 = global.get_fixed()?.get_vault_bump();
+    initialize_marginfi_account(
+        &marginfi_group,
+        &marginfi_account,
+        &payer,
+        &system_program,
+        global_vault.as_ref(),
+        global_vault_seeds_with_bump!(mint.info.key, global_vault_bump),
+    )?;
+
+    // Everything in the vault belongs to a balance, so all of it is lent and
+    // the balances split what it minted.
+    let vault_atoms: u64 = global_vault.get_balance();
+    let mut minted_shares: I80F48 = I80F48::ZERO;
+    if vault_atoms > 0 {
+        let mint_opt: Option<MintAccountInfo> = if *global_vault.owner == spl_token_2022::id() {
+            Some(mint.clone())
+        } else {
+            None
+        };
+        cpi_marginfi_deposit(
+            &marginfi_group,
+            &marginfi_account,
+            &marginfi_bank,
+            &marginfi_liquidity_vault,
+            global_vault.clone(),
+            &global_vault,
+            &token_program,
+            vault_atoms,
+            None,
+            &mint_opt,
+            accounts,
+            global_vault_seeds_with_bump!(mint.info.key, global_vault_bump),
+        )?;
+        minted_shares = marginfi_account
+            .get_fixed()?
+            .lending_account
+            .balances
+            .iter()
+            .find(|b| b.active != 0 && b.bank_pk == *marginfi_bank.key)
+            .map(|b| I80F48::from(b.asset_shares))
+            .ok_or(NixError::InvalidMarginfiAccount)?;
+    }
+
+    let global_data: &mut RefMut<&mut [u8]> = &mut global.try_borrow_mut_data()?;
+    let mut global_dynamic_account: GlobalRefMut = get_mut_dynamic_account(global_data);
+    global_dynamic_account.convert_balances_to_shares(vault_atoms, minted_shares)?;
+    global_dynamic_account
+        .fixed
+        .link_marginfi(marginfi_bank.key, marginfi_account.key);
+
+    emit_stack(GlobalLinkMarginfiLog {
+        global: *global.key,
+        marginfi_bank: *marginfi_bank.key,
+        minted_shares: minted_shares.into(),
+        vault_atoms,
+    })
+}
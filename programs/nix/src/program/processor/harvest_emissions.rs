@@ -0,0 +1,152 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use fixed::types::I80F48;
+use hypertree::{trace, PodBool};
+use shank::ShankType;
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+use crate::{
+    logs::{emit_stack, EmissionsHarvestedLog},
+    marginfi_utils::{cpi_marginfi_deposit, cpi_marginfi_withdraw_emissions},
+    market_signer_seeds_with_bump,
+    program::{get_mut_dynamic_account, NixError},
+    state::MarketRefMut,
+    validation::{loaders::HarvestEmissionsContext, MintAccountInfo, TokenAccountInfo},
+};
+
+use super::create_market::create_fee_receiver;
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct HarvestEmissionsParams {
+    /// Bank of the market to harvest, the one of base A or of base B.
+    pub is_base_a: bool,
+    /// Split the emissions between the seats instead of paying them to the
+    /// fee receiver. Only for emissions in the mint of the bank.
+    pub to_seats: bool,
+}
+
+pub(crate) fn process_harvest_emissions<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+) -> ProgramResult {
+    let params: HarvestEmissionsParams = HarvestEmissionsParams::try_from_slice(data)?;
+    process_harvest_emissions_core(program_id, accounts, params)
+}
+
+/// Withdraws what the marginfi account of the market earned in emissions on
+/// one bank. Paid to seats, the emissions are deposited back into the bank
+/// and the shares credited in proportion to the withdrawable balances.
+pub(crate) fn process_harvest_emissions_core<'a>(
+    _program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    params: HarvestEmissionsParams,
+) -> ProgramResult {
+    trace!("process_harvest_emissions accts={accounts:?}");
+    let HarvestEmissionsParams {
+        is_base_a,
+        to_seats,
+    } = params;
+    let HarvestEmissionsContext {
+        admin,
+        market,
+        market_signer,
+        emissions_mint,
+        emissions_auth,
+        emissions_vault,
+        destination,
+        token_program,
+        system_program,
+        marginfi_cpi_accounts,
+    } = HarvestEmissionsContext::load(accounts, is_base_a, to_seats)?;
+
+    if destination.data_is_empty() {
+        create_fee_receiver(
+            &admin,
+            &emissions_mint,
+            destination,
+            &system_program,
+            &token_program,
+            market.key,
+            market_signer.as_ref(),
+        )?;
+    }
+    let destination: TokenAccountInfo = TokenAccountInfo::new_with_owner(
+        destination,
+        emissions_mint.info.key,
+        market_signer.info.key,
+    )?;
+
+    let destination_balance_before: u64 = destination.get_balance();
+    cpi_marginfi_withdraw_emissions(
+        &marginfi_cpi_accounts,
+        market_signer.clone(),
+        &emissions_mint,
+        emissions_auth,
+        emissions_vault,
+        &destination,
+        &token_program,
+        market_signer_seeds_with_bump!(market.key, market_signer.bump),
+    )?;
+    // Due to transfer fees, this might not be what you expect.
+    let amount: u64 = destination
+        .get_balance()
+        .checked_sub(destination_balance_before)
+        .ok_or(NixError::NumericalOverflow)?;
+
+    let mut seat_shares: I80F48 = I80F48::ZERO;
+    if to_seats && amount > 0 {
+        let get_asset_shares = || -> Result<I80F48, ProgramError> {
+            Ok(marginfi_cpi_accounts
+                .marginfi_account
+                .get_fixed()?
+                .lending_account
+                .balances
+                .iter()
+                .find(|b| b.active != 0 && b.bank_pk == *marginfi_cpi_accounts.marginfi_bank.key)
+                .map(|b| I80F48::from(b.asset_shares))
+                .unwrap_or_default())
+        };
+        let asset_shares_before: I80F48 = get_asset_shares()?;
+        let mint_opt: Option<MintAccountInfo> = if *destination.owner == spl_token_2022::id() {
+            Some(emissions_mint.clone())
+        } else {
+            None
+        };
+        cpi_marginfi_deposit(
+            &marginfi_cpi_accounts.marginfi_group,
+            &marginfi_cpi_accounts.marginfi_account,
+            &marginfi_cpi_accounts.marginfi_bank,
+            &marginfi_cpi_accounts.marginfi_liquidity_vault,
+            market_signer.clone(),
+            &destination,
+            &token_program,
+            amount,
+            None,
+            &mint_opt,
+            accounts,
+            market_signer_seeds_with_bump!(market.key, market_signer.bump),
+        )?;
+        let deposited_shares: I80F48 = get_asset_shares()?
+            .checked_sub(asset_shares_before)
+            .ok_or(NixError::NumericalOverflow)?;
+
+        let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+        let mut dynamic_account: MarketRefMut = get_mut_dynamic_account(market_data);
+        seat_shares = dynamic_account.distribute_to_seats(is_base_a, deposited_shares)?;
+    }
+
+    emit_stack(EmissionsHarvestedLog {
+        market: *market.key,
+        emissions_mint: *emissions_mint.info.key,
+        seat_shares: seat_shares.into(),
+        amount,
+        is_base_a: PodBool::from(is_base_a),
+        to_seats: PodBool::from(to_seats),
+        _padding: [0; 6],
+    })
+}
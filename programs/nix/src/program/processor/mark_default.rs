@@ -0,0 +1,212 @@
+use std::cell::{Ref, RefMut};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use fixed::types::I80F48;
+use hypertree::{get_mut_helper, trace};
+use marginfi::state::{
+    marginfi_group::Bank,
+    price::{OraclePriceType, PriceBias},
+};
+use shank::ShankType;
+use solana_program::{
+    account_info::AccountInfo, clock::Clock, entrypoint::ProgramResult, pubkey::Pubkey,
+    sysvar::Sysvar,
+};
+
+use crate::{
+    logs::{emit_stack, LoanDefaultedLog},
+    marginfi_utils::{get_weighted_value_usd, BankShareValues, OraclePrices},
+    math::get_default_shortfall_usd,
+    program::NixError,
+    quantities::WrappedI80F48,
+    require,
+    state::{ActiveLoan, MarketInsurance, MarketLoansRef, MarketLoansRefMut, MarketRefMut},
+    validation::{
+        get_market_insurance_address, loaders::MarkDefaultContext, MarginfiAccountInfo,
+        MintAccountInfo, NixAccountInfo,
+    },
+};
+
+use super::{get_dynamic_account, get_mut_dynamic_account};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct MarkDefaultParams {
+    pub loan_sequence_number: u64,
+}
+
+pub fn process_mark_default<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+) -> ProgramResult {
+    let params: MarkDefaultParams = MarkDefaultParams::try_from_slice(data)?;
+    process_mark_default_core(program_id, accounts, params)
+}
+
+/// Defaults an active loan once its collateral is worth less than its
+/// liability, priced the way CheckHealth prices them. The lender takes the
+/// collateral in place of the repayment. The insurance fund of the market,
+/// when passed, pays the lender what it can of the shortfall and the rest is
+/// written off against its claim. Permissionless, nothing goes to the caller.
+pub fn process_mark_default_core<'a>(
+    _program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    params: MarkDefaultParams,
+) -> ProgramResult {
+    trace!("process_mark_default accts={accounts:?}");
+    let MarkDefaultParams {
+        loan_sequence_number,
+    } = params;
+    let MarkDefaultContext {
+        market,
+        market_loans,
+        base_a_marginfi_bank,
+        base_b_marginfi_bank,
+        base_a_mint,
+        base_b_mint,
+        ..
+    } = MarkDefaultContext::load(accounts)?;
+
+    let loan: ActiveLoan = {
+        let market_loans_data: Ref<&mut [u8]> = market_loans.try_borrow_data()?;
+        let market_loans_dynamic_account: MarketLoansRef = get_dynamic_account(&market_loans_data);
+        market_loans_dynamic_account
+            .get_loan(loan_sequence_number)
+            .ok_or(NixError::InvalidActiveLoan)?
+    };
+
+    let (liability_bank, collateral_bank, liability_mint, collateral_mint): (
+        &MarginfiAccountInfo<Bank>,
+        &MarginfiAccountInfo<Bank>,
+        &MintAccountInfo,
+        &MintAccountInfo,
+    ) = if loan.is_liability_base_a.0 == 1 {
+        (
+            &base_a_marginfi_bank,
+            &base_b_marginfi_bank,
+            &base_a_mint,
+            &base_b_mint,
+        )
+    } else {
+        (
+            &base_b_marginfi_bank,
+            &base_a_marginfi_bank,
+            &base_b_mint,
+            &base_a_mint,
+        )
+    };
+    let collateral_bank_fixed: Ref<Bank> = collateral_bank.get_fixed()?;
+    let liability_bank_fixed: Ref<Bank> = liability_bank.get_fixed()?;
+    let collateral_bank_share_values: BankShareValues =
+        BankShareValues::from(&*collateral_bank_fixed);
+    let liability_bank_share_values: BankShareValues =
+        BankShareValues::from(&*liability_bank_fixed);
+
+    // Collateral is priced low and the liability high, so a loan is not
+    // defaulted on a price that is only inside the oracle confidence.
+    let mut oracle_prices: OraclePrices = OraclePrices::new(accounts, Clock::get()?);
+    let collateral_price_usd: I80F48 = oracle_prices.get_price_of_atoms(
+        collateral_bank.key,
+        &collateral_bank_fixed.config,
+        collateral_mint,
+        Some(PriceBias::Low),
+        OraclePriceType::TimeWeighted,
+    )?;
+    let liability_price_usd: I80F48 = oracle_prices.get_price_of_atoms(
+        liability_bank.key,
+        &liability_bank_fixed.config,
+        liability_mint,
+        Some(PriceBias::High),
+        OraclePriceType::TimeWeighted,
+    )?;
+
+    let collateral_atoms: I80F48 = I80F48::from(loan.collateral_shares)
+        .checked_mul(collateral_bank_share_values.asset_share_value)
+        .ok_or(NixError::NumericalOverflow)?;
+    let liability_atoms: I80F48 = I80F48::from(loan.liability_shares)
+        .checked_mul(liability_bank_share_values.liability_share_value)
+        .ok_or(NixError::NumericalOverflow)?;
+    let collateral_value_usd: I80F48 = get_weighted_value_usd(
+        collateral_atoms,
+        I80F48::ONE,
+        collateral_bank_share_values.mint_decimals,
+        collateral_price_usd,
+    )?;
+    let liability_value_usd: I80F48 = get_weighted_value_usd(
+        liability_atoms,
+        I80F48::ONE,
+        liability_bank_share_values.mint_decimals,
+        liability_price_usd,
+    )?;
+    let shortfall_usd_opt: Option<I80F48> =
+        get_default_shortfall_usd(collateral_value_usd, liability_value_usd);
+    require!(
+        shortfall_usd_opt.is_some(),
+        NixError::LoanNotInDefault,
+        "Loan {} has {} of collateral for {} of liability",
+        loan_sequence_number,
+        collateral_value_usd,
+        liability_value_usd,
+    )?;
+    let shortfall_usd: I80F48 = shortfall_usd_opt.unwrap();
+    // Asset shares of the lent mint worth the shortfall, what the insurance
+    // fund would need to make the lender whole.
+    let shortfall_shares: I80F48 = shortfall_usd
+        .checked_div(get_weighted_value_usd(
+            I80F48::ONE,
+            I80F48::ONE,
+            liability_bank_share_values.mint_decimals,
+            liability_price_usd,
+        )?)
+        .and_then(|atoms| atoms.checked_div(liability_bank_share_values.asset_share_value))
+        .ok_or(NixError::NumericalOverflow)?;
+    drop(collateral_bank_fixed);
+    drop(liability_bank_fixed);
+
+    let loan: ActiveLoan = {
+        let market_loans_data: &mut RefMut<&mut [u8]> = &mut market_loans.try_borrow_mut_data()?;
+        let mut market_loans_dynamic_account: MarketLoansRefMut =
+            get_mut_dynamic_account(market_loans_data);
+        market_loans_dynamic_account.take_defaulted_loan(loan_sequence_number)?
+    };
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    let mut dynamic_account: MarketRefMut = get_mut_dynamic_account(market_data);
+
+    let (market_insurance_key, _bump) = get_market_insurance_address(market.key);
+    let insurance_shares: WrappedI80F48 = match accounts
+        .iter()
+        .find(|account| *account.key == market_insurance_key)
+    {
+        None => WrappedI80F48::ZERO,
+        Some(market_insurance_info) => {
+            let market_insurance: NixAccountInfo<MarketInsurance> =
+                NixAccountInfo::<MarketInsurance>::new(market_insurance_info)?;
+            let market_insurance_data: &mut RefMut<&mut [u8]> =
+                &mut market_insurance.try_borrow_mut_data()?;
+            let market_insurance_fixed: &mut MarketInsurance =
+                get_mut_helper::<MarketInsurance>(market_insurance_data, 0_u32);
+            dynamic_account
+                .fixed
+                .accrue_insurance_fees(market_insurance_fixed)?;
+            market_insurance_fixed
+                .draw(loan.is_liability_base_a.0 == 1, shortfall_shares)?
+                .into()
+        }
+    };
+    dynamic_account.write_off_defaulted_loan(&loan, insurance_shares)?;
+
+    emit_stack(LoanDefaultedLog {
+        market: *market.key,
+        market_loans: *market_loans.key,
+        lender: *dynamic_account.get_trader_key_by_index(loan.lender_index),
+        borrower: *dynamic_account.get_trader_key_by_index(loan.borrower_index),
+        loan_sequence_number,
+        collateral_shares: loan.collateral_shares,
+        liability_shares: loan.liability_shares,
+        shortfall_usd: shortfall_usd.into(),
+        insurance_shares,
+        is_liability_base_a: loan.is_liability_base_a,
+        _padding: [0; 7],
+    })
+}
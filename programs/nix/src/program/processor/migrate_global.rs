@@ -0,0 +1,65 @@
+use std::{cell::RefMut, mem::size_of};
+
+use hypertree::{get_mut_helper, trace};
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, MigrateGlobalLog},
+    program::NixError,
+    require,
+    state::{GlobalFixed, OLD_GLOBAL_HEADER_GROWTHS},
+    validation::{loaders::MigrateGlobalContext, NixAccountInfo},
+};
+
+use super::expand_dynamic;
+
+pub(crate) fn process_migrate_global(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    _data: &[u8],
+) -> ProgramResult {
+    trace!("process_migrate_global accts={accounts:?}");
+    let migrate_global_context: MigrateGlobalContext = MigrateGlobalContext::load(accounts)?;
+    let MigrateGlobalContext { payer, global, .. } = migrate_global_context;
+
+    // The header grew by gas_deposit_lamports and then by the marginfi
+    // fields. Grow the account by the most it can be missing before reading
+    // the header, a global without traders is only the old header.
+    let old_data_len: usize = global.data_len();
+    let max_header_growth: usize = OLD_GLOBAL_HEADER_GROWTHS[OLD_GLOBAL_HEADER_GROWTHS.len() - 1];
+    expand_dynamic(&payer, global, max_header_growth)?;
+    let global: NixAccountInfo<GlobalFixed> =
+        NixAccountInfo::<GlobalFixed>::new_any_version(global)?;
+
+    let missing_header_bytes: usize = {
+        let global_data: &mut RefMut<&mut [u8]> = &mut global.try_borrow_mut_data()?;
+        let missing_header_bytes: usize = get_mut_helper::<GlobalFixed>(global_data, 0_u32)
+            .get_missing_header_bytes(old_data_len);
+        require!(
+            OLD_GLOBAL_HEADER_GROWTHS.contains(&missing_header_bytes),
+            NixError::GlobalLayoutMismatch,
+            "Global {} is already migrated",
+            global.key,
+        )?;
+
+        // Move the dynamic bytes up so they start right after the new header.
+        let old_fixed_size: usize = size_of::<GlobalFixed>() - missing_header_bytes;
+        global_data.copy_within(old_fixed_size..old_data_len, size_of::<GlobalFixed>());
+        get_mut_helper::<GlobalFixed>(global_data, 0_u32).migrate(missing_header_bytes);
+        missing_header_bytes
+    };
+    // A global only missing the marginfi fields needs less than was grown.
+    if missing_header_bytes < max_header_growth {
+        global
+            .info
+            .realloc(old_data_len + missing_header_bytes, false)?;
+    }
+
+    let global_data: &mut RefMut<&mut [u8]> = &mut global.try_borrow_mut_data()?;
+    let global_fixed: &mut GlobalFixed = get_mut_helper::<GlobalFixed>(global_data, 0_u32);
+    emit_stack(MigrateGlobalLog {
+        global: *global.key,
+        gas_deposit_lamports: global_fixed.get_gas_deposit_lamports(),
+    })?;
+    Ok(())
+}
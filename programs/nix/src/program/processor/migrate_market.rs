@@ -0,0 +1,62 @@
+use std::cell::RefMut;
+
+use hypertree::{get_mut_helper, trace};
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, MigrateMarketLog},
+    state::{MarketFixed, MARKET_FIXED_SIZE, MARKET_HEADER_GROWTHS, MARKET_VERSION},
+    validation::{loaders::MigrateMarketContext, NixAccountInfo},
+};
+
+use super::expand_dynamic;
+
+/// Offset of the version byte, right after the discriminant in every layout.
+const MARKET_VERSION_OFFSET: usize = 8;
+
+/// Bytes the header of a market at this version is short of the current one.
+fn get_header_growth(version: u8) -> usize {
+    MARKET_HEADER_GROWTHS
+        .iter()
+        .filter(|(growth_version, _)| *growth_version > version)
+        .map(|(_, growth)| growth)
+        .sum()
+}
+
+pub(crate) fn process_migrate_market(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    _data: &[u8],
+) -> ProgramResult {
+    trace!("process_migrate_market accts={accounts:?}");
+    let migrate_market_context: MigrateMarketContext = MigrateMarketContext::load(accounts)?;
+    let MigrateMarketContext { payer, market, .. } = migrate_market_context;
+
+    // Older versions have a shorter header. Grow the account before reading
+    // the header, the dynamic bytes of a market without seats can be shorter
+    // than the growth.
+    let old_data_len: usize = market.data_len();
+    let header_growth: usize = get_header_growth(market.try_borrow_data()?[MARKET_VERSION_OFFSET]);
+    if header_growth > 0 {
+        expand_dynamic(&payer, market, header_growth)?;
+    }
+    let market: NixAccountInfo<MarketFixed> =
+        NixAccountInfo::<MarketFixed>::new_any_version(market)?;
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    if header_growth > 0 {
+        // Move the dynamic bytes up so they start right after the new header.
+        let old_fixed_size: usize = MARKET_FIXED_SIZE - header_growth;
+        market_data.copy_within(old_fixed_size..old_data_len, MARKET_FIXED_SIZE);
+    }
+    let market_fixed: &mut MarketFixed = get_mut_helper::<MarketFixed>(market_data, 0_u32);
+    let from_version: u8 = market_fixed.migrate()?;
+
+    emit_stack(MigrateMarketLog {
+        market: *market.key,
+        from_version,
+        to_version: MARKET_VERSION,
+        _padding: [0; 6],
+    })?;
+    Ok(())
+}
@@ -6,7 +6,46 @@ pub mod deposit;
 pub mod global_create;
 pub mod global_add_trader;
 pub mod global_deposit;
+pub mod global_evict;
 pub mod place_order;
 pub mod cancel_order;
+pub mod referrer_claim;
+pub mod claim_maker_rebate;
+pub mod close_market;
+pub mod emit_book_snapshot;
+pub mod quote_order;
+pub mod create_event_queue;
+pub mod consume_events;
+pub mod migrate_market;
+pub mod create_market_pda;
+pub mod clean_expired_orders;
+pub mod place_order_smart;
+pub mod sweep_stranded_gas;
+pub mod reduce_order;
+pub mod create_cross_margin_seat;
+pub mod place_loan_sale;
+pub mod create_market_stats;
+pub mod continue_order;
+pub mod modify_order;
+pub mod set_circuit_breaker;
+pub mod create_market_auction;
+pub mod run_auction;
+pub mod top_up_collateral;
+pub mod release_collateral;
+pub mod migrate_global;
+pub mod settle_loan_proceeds;
+pub mod check_health;
+pub mod mark_default;
+pub mod create_market_insurance;
+pub mod set_insurance_fee;
+pub mod top_up_insurance;
+pub mod draw_insurance;
+pub mod set_order_rate_limit;
+pub mod harvest_emissions;
+pub mod global_link_marginfi;
+pub mod set_allowed_caller;
+pub mod set_debug_logs;
+pub mod route_orders;
+pub mod acknowledge_bank_config_change;
 
 pub use shared::*;
\ No newline at end of file
@@ -0,0 +1,81 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::{trace, DataIndex};
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, ModifyOrderLog},
+    program::{get_mut_dynamic_account, get_trader_index_with_hint},
+    state::MarketRefMut,
+    validation::loaders::ModifyOrderContext,
+};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct ModifyOrderParams {
+    pub trader_index_hint: Option<DataIndex>,
+    pub order_sequence_number: u64,
+    pub use_a_tree: bool,
+    /// Has to be within the reverse spread bounds of the market.
+    pub reverse_spread_bps: u16,
+    /// Spread where the order flips into a bid, also within the bounds. None
+    /// for reverse_spread_bps.
+    pub reverse_bid_spread_bps: Option<u16>,
+    /// Flips left before the order rests as a plain limit order. Zero for no
+    /// limit.
+    pub reverse_cycles_left: u16,
+}
+
+pub fn process_modify_order<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+) -> ProgramResult {
+    let params: ModifyOrderParams = ModifyOrderParams::try_from_slice(data)?;
+    process_modify_order_core(program_id, accounts, params)
+}
+
+/// Lets the owner of a reverse order tune it without cancelling, which would
+/// send it to the back of the queue.
+pub fn process_modify_order_core<'a>(
+    _program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    params: ModifyOrderParams,
+) -> ProgramResult {
+    trace!("process_modify_order accts={accounts:?}");
+    let ModifyOrderParams {
+        trader_index_hint,
+        order_sequence_number,
+        use_a_tree,
+        reverse_spread_bps,
+        reverse_bid_spread_bps,
+        reverse_cycles_left,
+    } = params;
+    let reverse_bid_spread_bps: u16 = reverse_bid_spread_bps.unwrap_or(reverse_spread_bps);
+    let ModifyOrderContext { payer, market } = ModifyOrderContext::load(accounts)?;
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    let mut dynamic_account: MarketRefMut = get_mut_dynamic_account(market_data);
+    let trader_index: DataIndex =
+        get_trader_index_with_hint(trader_index_hint, &dynamic_account, &payer)?;
+
+    dynamic_account.modify_reverse_order(
+        use_a_tree,
+        trader_index,
+        order_sequence_number,
+        reverse_spread_bps,
+        reverse_bid_spread_bps,
+        reverse_cycles_left,
+    )?;
+
+    emit_stack(ModifyOrderLog {
+        market: *market.key,
+        trader: *payer.key,
+        order_sequence_number,
+        reverse_spread_bps,
+        reverse_bid_spread_bps,
+        reverse_cycles_left,
+        _padding: [0; 2],
+    })
+}
@@ -0,0 +1,99 @@
+use std::cell::{Ref, RefMut};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::{trace, DataIndex};
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, PlaceLoanSaleLog},
+    program::{
+        expand_market_if_needed, get_dynamic_account, get_mut_dynamic_account,
+        get_trader_index_with_hint, NixError,
+    },
+    require,
+    state::{ActiveLoan, MarketLoansRef, MarketRefMut},
+    utils::get_now_slot,
+    validation::loaders::PlaceLoanSaleContext,
+};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct PlaceLoanSaleParams {
+    pub trader_index_hint: Option<DataIndex>,
+    pub loan_sequence_number: u64,
+    /// Rate the buyer earns. Above the rate of the loan the buyer pays less
+    /// than what is owed, below it more.
+    pub rate_bps: u16,
+    pub last_valid_slot: u32,
+}
+
+pub fn process_place_loan_sale<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+) -> ProgramResult {
+    let params: PlaceLoanSaleParams = PlaceLoanSaleParams::try_from_slice(data)?;
+    process_place_loan_sale_core(program_id, accounts, params)
+}
+
+pub fn process_place_loan_sale_core<'a>(
+    _program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    params: PlaceLoanSaleParams,
+) -> ProgramResult {
+    trace!("process_place_loan_sale accts={accounts:?}");
+    let PlaceLoanSaleParams {
+        trader_index_hint,
+        loan_sequence_number,
+        rate_bps,
+        last_valid_slot,
+    } = params;
+    let PlaceLoanSaleContext {
+        payer,
+        market,
+        market_loans,
+        ..
+    } = PlaceLoanSaleContext::load(accounts)?;
+
+    let loan: Option<ActiveLoan> = {
+        let market_loans_data: Ref<&mut [u8]> = market_loans.try_borrow_data()?;
+        let market_loans_account: MarketLoansRef = get_dynamic_account(&market_loans_data);
+        market_loans_account.get_loan(loan_sequence_number)
+    };
+    require!(
+        loan.is_some(),
+        NixError::InvalidLoanSale,
+        "Loan {} not found on {}",
+        loan_sequence_number,
+        market_loans.key,
+    )?;
+    let loan: ActiveLoan = loan.unwrap();
+
+    let (order_sequence_number, _order_index) = {
+        let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+        let mut dynamic_account: MarketRefMut = get_mut_dynamic_account(market_data);
+        let trader_index: DataIndex =
+            get_trader_index_with_hint(trader_index_hint, &dynamic_account, &payer)?;
+        dynamic_account.place_loan_sale(
+            trader_index,
+            market_loans.key,
+            &loan,
+            rate_bps,
+            last_valid_slot,
+            get_now_slot(),
+        )?
+    };
+
+    emit_stack(PlaceLoanSaleLog {
+        market: *market.key,
+        trader: *payer.key,
+        market_loans: *market_loans.key,
+        loan_sequence_number,
+        order_sequence_number,
+        rate_bps,
+        _padding: [0; 6],
+    })?;
+
+    expand_market_if_needed(&payer, &market)?;
+    Ok(())
+}
@@ -1,29 +1,426 @@
-use std::cell::RefMut;
+use std::cell::{Ref, RefMut};
 
 use borsh::{BorshDeserialize, BorshSerialize};
-use hypertree::{DataIndex, PodBool};
-use marginfi::state::price::{OraclePriceType, PriceBias};
+use fixed::types::I80F48;
+use hypertree::{is_not_nil, DataIndex, PodBool, NIL};
+use marginfi::state::{
+    marginfi_group::Bank,
+    price::{OraclePriceType, PriceBias},
+};
+use shank::ShankType;
 use solana_program::{
     account_info::AccountInfo, clock::Clock, entrypoint::ProgramResult,
-     pubkey::Pubkey, sysvar::Sysvar,
+     program::set_return_data, program_error::ProgramError, pubkey::Pubkey, sysvar::Sysvar,
 };
 
 use crate::{
-    logs::{emit_stack, PlaceOrderLog}, marginfi_utils::get_oracle_price, program::{expand_market_if_needed, expand_market_loans}, state::{AddOrderToMarketArgs, MarketLoansFixed, MarketRefMut, OrderType}, utils::{get_now_slot, try_to_add_new_loans}, validation::loaders::PlaceOrderContext
+    logs::{emit_stack, CircuitBreakerTrippedLog, LoanSaleLog, PendingOrderLog, PlaceOrderLog}, marginfi_utils::{get_base_atoms_backed_by_quote_collateral, get_marginfi_account_health_usd, get_weighted_value_usd, BankShareValues, OraclePrices}, math::get_buffer_f, program::{expand_market_if_needed, expand_market_loans, NixError}, quantities::{BaseAtoms, QuoteAtoms, Rate}, require, state::{AddOrderToMarketArgs, AddOrderToMarketResult, AuctionPhase, CrossMarginSeat, LoanAssignment, MarketEvent, ExpiryPolicy, MarketEventType, MarketAuction, MarketLoansFixed, MarketLoansRefMut, MarketRefMut, OrderType, PendingOrder, QuoteOrderResult, MAX_MATCHED_LOANS, MAX_RATE_BPS, NO_EXPIRATION_LAST_VALID_SLOT, NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP, GTT_GAS_DEPOSIT_LAMPORTS, order_type_can_rest, order_type_can_take}, utils::{assert_allowed_caller, assert_bank_configs_unchanged, assert_market_has_required_banks, assert_valid_order_type, close_nix_account, get_now_slot, get_now_unix_timestamp, try_to_add_new_loans}, validation::{get_cross_margin_seat_address, get_market_auction_address, get_pending_order_address, loaders::PlaceOrderContext, validate_cross_margin_account, MintAccountInfo, NixAccountInfo, Program, Signer}
 };
 
-use super::{get_mut_dynamic_account, get_trader_index_with_hint};
+use super::{
+    claim_seat::claim_seat_if_needed, get_mut_dynamic_account, get_trader_index_with_hint,
+    pay_gas_deposits, push_market_events, record_market_stats, sweep_expired_orders_of_trader,
+    take_gas_deposit,
+};
 
-#[derive(BorshDeserialize, BorshSerialize)]
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
 pub struct PlaceOrderParams {
     pub trader_index_hint: Option<DataIndex>,
     pub num_base_atoms: u64,
     pub rate_bps: u16,
+    /// Spread of a reverse order where it flips into an ask, and into a bid
+    /// unless reverse_bid_spread_bps is set.
     pub reverse_spread_bps: u16,
     pub is_bid: bool,
     pub use_a_tree: bool,
     pub last_valid_slot: u32,
     pub order_type: OrderType,
+    /// What happens to a resting bid after last_valid_slot. Ignored for asks.
+    /// Bids that can rest cannot use ReturnCollateral, their borrow always
+    /// becomes a pool loan.
+    pub expiry_policy: ExpiryPolicy,
+    /// Trader with a seat on the market that receives a share of the protocol
+    /// fee on fills.
+    pub referrer: Option<Pubkey>,
+    /// Max number of maker orders to cross, including expired orders that get
+    /// cleaned up. Matching stops there and the remainder rests or is
+    /// returned depending on the order type. A remainder that would still
+    /// cross the book is dropped instead of resting. Capped at
+    /// MAX_MATCHED_LOANS, which is also the limit when None.
+    pub match_limit: Option<u32>,
+    /// Claim a seat first if the payer has none. Not allowed on permissioned
+    /// markets.
+    pub auto_claim_seat: bool,
+    /// Read num_base_atoms as quote atoms of collateral to commit. The order
+    /// is then for the most base those atoms back at the oracle prices, so
+    /// both sets of marginfi accounts are needed.
+    pub size_in_quote: bool,
+    /// When matching stops at match_limit or max_new_loans, keep the
+    /// remainder in the PendingOrder of the payer for ContinueOrder instead
+    /// of resting or dropping it. The PendingOrder PDA has to be appended,
+    /// writable, and the payer pays its rent. Not for reverse orders.
+    pub defer_remainder: bool,
+    /// Times a reverse order flips to the other side before it rests as a
+    /// plain limit order. Zero for no limit, ignored for other order types.
+    pub max_reverse_cycles: u16,
+    /// Up to this many of the payer's own expired orders are removed before
+    /// the order is placed, and their collateral returned to the seat.
+    /// Capped at MAX_SWEPT_EXPIRED_ORDERS. Zero skips the scan.
+    pub max_expired_orders_to_sweep: u8,
+    /// Max number of loans the order may open while matching. Matching
+    /// stops there like at match_limit. Also capped by the room left on the
+    /// MarketLoans page, which is the limit when None.
+    pub max_new_loans: Option<u32>,
+    /// Spread of a reverse order where it flips into a bid, so makers can
+    /// quote one side wider than the other. None for reverse_spread_bps.
+    pub reverse_bid_spread_bps: Option<u16>,
+    /// Makes the order good till this unix timestamp on top of any
+    /// last_valid_slot. The payer deposits GTT_GAS_DEPOSIT_LAMPORTS while it
+    /// rests, paid to whoever takes it off the book. Zero for none, and not
+    /// for reverse, global or deferred orders.
+    pub last_valid_unix_timestamp: u32,
+}
+
+/// Return data of PlaceOrder and of the instructions that place through it,
+/// so programs that CPI into nix do not have to parse the logs.
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct PlaceOrderReturnData {
+    pub order_sequence_number: u64,
+    /// Index of the order resting on the book, NIL when nothing rested.
+    pub order_index: DataIndex,
+    pub base_atoms_traded: u64,
+    pub quote_atoms_traded: u64,
+}
+
+/// Layout of PlaceOrderParams at version 1, before
+/// max_expired_orders_to_sweep. Untagged data is also read as this.
+#[derive(BorshDeserialize)]
+struct PlaceOrderParamsV1 {
+    trader_index_hint: Option<DataIndex>,
+    num_base_atoms: u64,
+    rate_bps: u16,
+    reverse_spread_bps: u16,
+    is_bid: bool,
+    use_a_tree: bool,
+    last_valid_slot: u32,
+    order_type: OrderType,
+    expiry_policy: ExpiryPolicy,
+    referrer: Option<Pubkey>,
+    match_limit: Option<u32>,
+    auto_claim_seat: bool,
+    size_in_quote: bool,
+    defer_remainder: bool,
+    max_reverse_cycles: u16,
+}
+
+impl From<PlaceOrderParamsV1> for PlaceOrderParams {
+    fn from(params: PlaceOrderParamsV1) -> Self {
+        PlaceOrderParams {
+            trader_index_hint: params.trader_index_hint,
+            num_base_atoms: params.num_base_atoms,
+            rate_bps: params.rate_bps,
+            reverse_spread_bps: params.reverse_spread_bps,
+            is_bid: params.is_bid,
+            use_a_tree: params.use_a_tree,
+            last_valid_slot: params.last_valid_slot,
+            order_type: params.order_type,
+            expiry_policy: params.expiry_policy,
+            referrer: params.referrer,
+            match_limit: params.match_limit,
+            auto_claim_seat: params.auto_claim_seat,
+            size_in_quote: params.size_in_quote,
+            defer_remainder: params.defer_remainder,
+            max_reverse_cycles: params.max_reverse_cycles,
+            max_expired_orders_to_sweep: 0,
+            max_new_loans: None,
+            reverse_bid_spread_bps: None,
+            last_valid_unix_timestamp: NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP,
+        }
+    }
+}
+
+/// Layout of PlaceOrderParams at version 2, before max_new_loans.
+#[derive(BorshDeserialize)]
+struct PlaceOrderParamsV2 {
+    trader_index_hint: Option<DataIndex>,
+    num_base_atoms: u64,
+    rate_bps: u16,
+    reverse_spread_bps: u16,
+    is_bid: bool,
+    use_a_tree: bool,
+    last_valid_slot: u32,
+    order_type: OrderType,
+    expiry_policy: ExpiryPolicy,
+    referrer: Option<Pubkey>,
+    match_limit: Option<u32>,
+    auto_claim_seat: bool,
+    size_in_quote: bool,
+    defer_remainder: bool,
+    max_reverse_cycles: u16,
+    max_expired_orders_to_sweep: u8,
+}
+
+impl From<PlaceOrderParamsV2> for PlaceOrderParams {
+    fn from(params: PlaceOrderParamsV2) -> Self {
+        PlaceOrderParams {
+            trader_index_hint: params.trader_index_hint,
+            num_base_atoms: params.num_base_atoms,
+            rate_bps: params.rate_bps,
+            reverse_spread_bps: params.reverse_spread_bps,
+            is_bid: params.is_bid,
+            use_a_tree: params.use_a_tree,
+            last_valid_slot: params.last_valid_slot,
+            order_type: params.order_type,
+            expiry_policy: params.expiry_policy,
+            referrer: params.referrer,
+            match_limit: params.match_limit,
+            auto_claim_seat: params.auto_claim_seat,
+            size_in_quote: params.size_in_quote,
+            defer_remainder: params.defer_remainder,
+            max_reverse_cycles: params.max_reverse_cycles,
+            max_expired_orders_to_sweep: params.max_expired_orders_to_sweep,
+            max_new_loans: None,
+            reverse_bid_spread_bps: None,
+            last_valid_unix_timestamp: NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP,
+        }
+    }
+}
+
+/// Layout of PlaceOrderParams at version 3, before reverse_bid_spread_bps.
+#[derive(BorshDeserialize)]
+struct PlaceOrderParamsV3 {
+    trader_index_hint: Option<DataIndex>,
+    num_base_atoms: u64,
+    rate_bps: u16,
+    reverse_spread_bps: u16,
+    is_bid: bool,
+    use_a_tree: bool,
+    last_valid_slot: u32,
+    order_type: OrderType,
+    expiry_policy: ExpiryPolicy,
+    referrer: Option<Pubkey>,
+    match_limit: Option<u32>,
+    auto_claim_seat: bool,
+    size_in_quote: bool,
+    defer_remainder: bool,
+    max_reverse_cycles: u16,
+    max_expired_orders_to_sweep: u8,
+    max_new_loans: Option<u32>,
+}
+
+/// Layout of PlaceOrderParams at version 4, before last_valid_unix_timestamp.
+#[derive(BorshDeserialize)]
+struct PlaceOrderParamsV4 {
+    trader_index_hint: Option<DataIndex>,
+    num_base_atoms: u64,
+    rate_bps: u16,
+    reverse_spread_bps: u16,
+    is_bid: bool,
+    use_a_tree: bool,
+    last_valid_slot: u32,
+    order_type: OrderType,
+    expiry_policy: ExpiryPolicy,
+    referrer: Option<Pubkey>,
+    match_limit: Option<u32>,
+    auto_claim_seat: bool,
+    size_in_quote: bool,
+    defer_remainder: bool,
+    max_reverse_cycles: u16,
+    max_expired_orders_to_sweep: u8,
+    max_new_loans: Option<u32>,
+    reverse_bid_spread_bps: Option<u16>,
+}
+
+impl From<PlaceOrderParamsV3> for PlaceOrderParams {
+    fn from(params: PlaceOrderParamsV3) -> Self {
+        PlaceOrderParams {
+            trader_index_hint: params.trader_index_hint,
+            num_base_atoms: params.num_base_atoms,
+            rate_bps: params.rate_bps,
+            reverse_spread_bps: params.reverse_spread_bps,
+            is_bid: params.is_bid,
+            use_a_tree: params.use_a_tree,
+            last_valid_slot: params.last_valid_slot,
+            order_type: params.order_type,
+            expiry_policy: params.expiry_policy,
+            referrer: params.referrer,
+            match_limit: params.match_limit,
+            auto_claim_seat: params.auto_claim_seat,
+            size_in_quote: params.size_in_quote,
+            defer_remainder: params.defer_remainder,
+            max_reverse_cycles: params.max_reverse_cycles,
+            max_expired_orders_to_sweep: params.max_expired_orders_to_sweep,
+            max_new_loans: params.max_new_loans,
+            reverse_bid_spread_bps: None,
+            last_valid_unix_timestamp: NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP,
+        }
+    }
+}
+
+impl From<PlaceOrderParamsV4> for PlaceOrderParams {
+    fn from(params: PlaceOrderParamsV4) -> Self {
+        PlaceOrderParams {
+            trader_index_hint: params.trader_index_hint,
+            num_base_atoms: params.num_base_atoms,
+            rate_bps: params.rate_bps,
+            reverse_spread_bps: params.reverse_spread_bps,
+            is_bid: params.is_bid,
+            use_a_tree: params.use_a_tree,
+            last_valid_slot: params.last_valid_slot,
+            order_type: params.order_type,
+            expiry_policy: params.expiry_policy,
+            referrer: params.referrer,
+            match_limit: params.match_limit,
+            auto_claim_seat: params.auto_claim_seat,
+            size_in_quote: params.size_in_quote,
+            defer_remainder: params.defer_remainder,
+            max_reverse_cycles: params.max_reverse_cycles,
+            max_expired_orders_to_sweep: params.max_expired_orders_to_sweep,
+            max_new_loans: params.max_new_loans,
+            reverse_bid_spread_bps: params.reverse_bid_spread_bps,
+            last_valid_unix_timestamp: NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP,
+        }
+    }
+}
+
+/// First byte of PlaceOrder data that carries a version. Data without it is
+/// read as v1 params, which start with the Option tag of trader_index_hint,
+/// 0 or 1, so the two cannot be mistaken for each other.
+pub const PLACE_ORDER_PARAMS_TAG: u8 = 0xff;
+/// Version of the current PlaceOrderParams layout. A new layout gets the next
+/// version, and older ones are mapped to it when decoded.
+pub const PLACE_ORDER_PARAMS_VERSION: u8 = 5;
+
+impl PlaceOrderParams {
+    /// A plain order that rests until cancelled, with everything optional
+    /// left unset. Callers override the rest with struct update syntax.
+    pub fn new(
+        num_base_atoms: BaseAtoms,
+        rate: Rate,
+        is_bid: bool,
+        use_a_tree: bool,
+        order_type: OrderType,
+    ) -> Self {
+        PlaceOrderParams {
+            trader_index_hint: None,
+            num_base_atoms: num_base_atoms.as_u64(),
+            rate_bps: rate.as_bps(),
+            reverse_spread_bps: 0,
+            is_bid,
+            use_a_tree,
+            last_valid_slot: NO_EXPIRATION_LAST_VALID_SLOT,
+            order_type,
+            expiry_policy: ExpiryPolicy::default(),
+            referrer: None,
+            match_limit: None,
+            auto_claim_seat: false,
+            size_in_quote: false,
+            defer_remainder: false,
+            max_reverse_cycles: 0,
+            max_expired_orders_to_sweep: 0,
+            max_new_loans: None,
+            reverse_bid_spread_bps: None,
+            last_valid_unix_timestamp: NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP,
+        }
+    }
+
+    /// Same as `new`, but sized by the quote collateral to commit.
+    pub fn new_sized_in_quote(
+        num_quote_atoms: QuoteAtoms,
+        rate: Rate,
+        is_bid: bool,
+        use_a_tree: bool,
+        order_type: OrderType,
+    ) -> Self {
+        PlaceOrderParams {
+            num_base_atoms: num_quote_atoms.as_u64(),
+            size_in_quote: true,
+            ..PlaceOrderParams::new(BaseAtoms::ZERO, rate, is_bid, use_a_tree, order_type)
+        }
+    }
+
+    /// Tag, version, then the params.
+    pub fn try_to_versioned_vec(&self) -> std::io::Result<Vec<u8>> {
+        Ok([
+            vec![PLACE_ORDER_PARAMS_TAG, PLACE_ORDER_PARAMS_VERSION],
+            self.try_to_vec()?,
+        ]
+        .concat())
+    }
+
+    /// Reads versioned data, and untagged v1 data from older clients.
+    pub fn try_from_versioned_slice(data: &[u8]) -> Result<Self, ProgramError> {
+        match data {
+            [PLACE_ORDER_PARAMS_TAG, PLACE_ORDER_PARAMS_VERSION, payload @ ..] => {
+                Ok(PlaceOrderParams::try_from_slice(payload)?)
+            }
+            [PLACE_ORDER_PARAMS_TAG, 4, payload @ ..] => {
+                Ok(PlaceOrderParamsV4::try_from_slice(payload)?.into())
+            }
+            [PLACE_ORDER_PARAMS_TAG, 3, payload @ ..] => {
+                Ok(PlaceOrderParamsV3::try_from_slice(payload)?.into())
+            }
+            [PLACE_ORDER_PARAMS_TAG, 2, payload @ ..] => {
+                Ok(PlaceOrderParamsV2::try_from_slice(payload)?.into())
+            }
+            [PLACE_ORDER_PARAMS_TAG, 1, payload @ ..] => {
+                Ok(PlaceOrderParamsV1::try_from_slice(payload)?.into())
+            }
+            [PLACE_ORDER_PARAMS_TAG, ..] => Err(NixError::InvalidPlaceOrderParams.into()),
+            _ => Ok(PlaceOrderParamsV1::try_from_slice(data)?.into()),
+        }
+    }
+
+    /// Checks that only need the params, so that a bad order fails before
+    /// the accounts are loaded. Bounds that depend on the market, like its
+    /// reverse spread range, are checked when the order is added.
+    pub fn validate(&self) -> ProgramResult {
+        require!(
+            Rate::from_bps(self.rate_bps).is_valid(),
+            NixError::InvalidPlaceOrderParams,
+            "Rate {} above max {}",
+            self.rate_bps,
+            MAX_RATE_BPS,
+        )?;
+        assert_valid_order_type(self.order_type, self.is_bid)?;
+        if self.order_type == OrderType::Reverse {
+            require!(
+                self.reverse_spread_bps < 10_000
+                    && self.reverse_bid_spread_bps.unwrap_or(0) < 10_000,
+                NixError::InvalidReverseSpread,
+                "Invalid reverse spreads {} {:?}",
+                self.reverse_spread_bps,
+                self.reverse_bid_spread_bps,
+            )?;
+        }
+        require!(
+            !self.defer_remainder || self.order_type != OrderType::Reverse,
+            NixError::InvalidPendingOrder,
+            "Reverse orders cannot defer their remainder",
+        )?;
+        // Reverse orders never expire, global orders already hold a gas
+        // deposit on their global, and a pending remainder has no deposit to
+        // carry over.
+        require!(
+            self.last_valid_unix_timestamp == NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP
+                || (self.order_type != OrderType::Reverse
+                    && self.order_type != OrderType::Global
+                    && !self.defer_remainder),
+            NixError::InvalidPlaceOrderParams,
+            "{:?} order cannot be good till time",
+            self.order_type,
+        )?;
+        // A resting bid borrows. Once it expires only a pool loan keeps that
+        // borrow paid for, so its collateral cannot simply go back.
+        require!(
+            !self.is_bid
+                || !order_type_can_rest(self.order_type)
+                || self.expiry_policy != ExpiryPolicy::ReturnCollateral,
+            NixError::InvalidPlaceOrderParams,
+            "Bids that can rest cannot return their collateral on expiry",
+        )?;
+        Ok(())
+    }
 }
 
 pub fn process_place_order<'a>(
@@ -31,76 +428,526 @@ pub fn process_place_order<'a>(
     accounts: &'a [AccountInfo<'a>],
     data: &[u8],
 ) -> ProgramResult {
-    let params: PlaceOrderParams = PlaceOrderParams::try_from_slice(data)?;
+    let params: PlaceOrderParams = PlaceOrderParams::try_from_versioned_slice(data)?;
     process_place_order_core(program_id, accounts, params)
 }
 
+pub fn process_place_order_base_a<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+) -> ProgramResult {
+    process_place_order_on_tree(program_id, accounts, data, true)
+}
+
+pub fn process_place_order_base_b<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+) -> ProgramResult {
+    process_place_order_on_tree(program_id, accounts, data, false)
+}
+
+/// PlaceOrder with the tree fixed by the instruction. The use_a_tree of the
+/// params is still decoded, so one that disagrees fails instead of being
+/// silently placed on the other tree.
+fn process_place_order_on_tree<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+    use_a_tree: bool,
+) -> ProgramResult {
+    let params: PlaceOrderParams = PlaceOrderParams::try_from_versioned_slice(data)?;
+    require!(
+        params.use_a_tree == use_a_tree,
+        NixError::InvalidPlaceOrderParams,
+        "Order for the base {} tree sent to the base {} tree instruction",
+        if params.use_a_tree { "A" } else { "B" },
+        if use_a_tree { "A" } else { "B" },
+    )?;
+    process_place_order_core(program_id, accounts, params)
+}
+
+/// Bids from a seat whose CrossMarginSeat is passed in also have to be
+/// covered by the initial health of the delegated marginfi account. Without
+/// it the bid is only backed by what the seat posts, as before.
+fn check_cross_margin_health<'a>(
+    place_order_context: &PlaceOrderContext<'a, 'a>,
+    accounts: &'a [AccountInfo<'a>],
+    oracle_prices: &mut OraclePrices<'a>,
+    num_base_atoms: u64,
+) -> ProgramResult {
+    let (cross_margin_seat_key, _bump) = get_cross_margin_seat_address(
+        place_order_context.market.key,
+        place_order_context.payer.key,
+    );
+    let Some(cross_margin_seat_info) = accounts
+        .iter()
+        .find(|account| *account.key == cross_margin_seat_key)
+    else {
+        return Ok(());
+    };
+    let cross_margin_seat: NixAccountInfo<CrossMarginSeat> =
+        NixAccountInfo::<CrossMarginSeat>::new(cross_margin_seat_info)?;
+    let marginfi_account_key: Pubkey = cross_margin_seat.get_fixed()?.marginfi_account;
+    let marginfi_account_opt: Option<&AccountInfo> = accounts
+        .iter()
+        .find(|account| *account.key == marginfi_account_key);
+    require!(
+        marginfi_account_opt.is_some(),
+        NixError::MissingAccounts,
+        "Missing cross margin account {}",
+        marginfi_account_key,
+    )?;
+    let marginfi_account: &AccountInfo = marginfi_account_opt.unwrap();
+    validate_cross_margin_account(
+        marginfi_account,
+        &place_order_context.market_keys.marginfi_groups,
+        place_order_context.market_signer.info.key,
+    )?;
+
+    // Bids need both sides, so the base bank is always there.
+    let Some(base_marginfi_cpi_accounts) = &place_order_context.marginfi_cpi_accounts_opts[0]
+    else {
+        return Err(NixError::MissingMarginfiAccounts.into());
+    };
+    let base_bank: Ref<Bank> = base_marginfi_cpi_accounts.marginfi_bank.get_fixed()?;
+    let base_bank_share_values: BankShareValues = BankShareValues::from(&*base_bank);
+    let base_price_usd: I80F48 = oracle_prices.get_price_of_atoms(
+        base_marginfi_cpi_accounts.marginfi_bank.key,
+        &base_bank.config,
+        &place_order_context.base_mint,
+        Some(PriceBias::High),
+        OraclePriceType::TimeWeighted,
+    )?;
+    let required_health_usd: I80F48 = get_weighted_value_usd(
+        I80F48::from_num(num_base_atoms),
+        base_bank_share_values.liability_weight_init,
+        base_bank_share_values.mint_decimals,
+        base_price_usd,
+    )?;
+    let health_usd: I80F48 =
+        get_marginfi_account_health_usd(marginfi_account, accounts, oracle_prices)?;
+    require!(
+        health_usd >= required_health_usd,
+        NixError::CrossMarginUnhealthy,
+        "Cross margin health {} does not cover {}",
+        health_usd,
+        required_health_usd,
+    )?;
+    Ok(())
+}
+
+/// The part of a bid that rests is borrowed from the base bank into the
+/// quote side marginfi account after matching. Marginfi refuses the borrow
+/// when the initial health of that account does not cover it, by which time
+/// matching already changed the book. The remainder is predicted with
+/// quote_order, stopped no later than matching stops at `match_limit`. A
+/// remainder that still crosses when matching stops is dropped rather than
+/// borrowed for, so the borrow checked here is never less than the one
+/// marginfi sees. `match_rate_bps` is the rate the bid matches at, None when
+/// it cannot match.
+fn check_borrow_health<'a>(
+    place_order_context: &PlaceOrderContext<'a, 'a>,
+    dynamic_account: &MarketRefMut,
+    accounts: &'a [AccountInfo<'a>],
+    oracle_prices: &mut OraclePrices<'a>,
+    use_a_tree: bool,
+    num_base_atoms: u64,
+    match_rate_bps: Option<u16>,
+    match_limit: u32,
+    now_slot: u32,
+    (base_oracle_price_usd, quote_oracle_price_usd): (I80F48, I80F48),
+) -> ProgramResult {
+    // Bids need both sides.
+    let [Some(base_marginfi_cpi_accounts), Some(quote_marginfi_cpi_accounts)] =
+        &place_order_context.marginfi_cpi_accounts_opts
+    else {
+        return Err(NixError::MissingMarginfiAccounts.into());
+    };
+    let base_bank: Ref<Bank> = base_marginfi_cpi_accounts.marginfi_bank.get_fixed()?;
+    let base_bank_share_values: BankShareValues = BankShareValues::from(&*base_bank);
+    let quote_bank_share_values: BankShareValues =
+        BankShareValues::from(&*quote_marginfi_cpi_accounts.marginfi_bank.get_fixed()?);
+    let matched_base_atoms: u64 = match match_rate_bps {
+        Some(match_rate_bps) => {
+            let quote: QuoteOrderResult = dynamic_account.quote_order(
+                use_a_tree,
+                true,
+                num_base_atoms,
+                match_rate_bps,
+                match_limit,
+                now_slot,
+                get_now_unix_timestamp(),
+                &base_bank_share_values,
+                &quote_bank_share_values,
+                base_oracle_price_usd,
+                quote_oracle_price_usd,
+            )?;
+            quote.base_atoms
+        }
+        None => 0,
+    };
+    let borrow_base_atoms: u64 = num_base_atoms.saturating_sub(matched_base_atoms);
+    if borrow_base_atoms == 0 {
+        return Ok(());
+    }
+
+    let base_price_usd: I80F48 = oracle_prices.get_price_of_atoms(
+        base_marginfi_cpi_accounts.marginfi_bank.key,
+        &base_bank.config,
+        &place_order_context.base_mint,
+        Some(PriceBias::High),
+        OraclePriceType::TimeWeighted,
+    )?;
+    let required_health_usd: I80F48 = get_weighted_value_usd(
+        I80F48::from_num(borrow_base_atoms),
+        base_bank_share_values.liability_weight_init,
+        base_bank_share_values.mint_decimals,
+        base_price_usd,
+    )?;
+    let health_usd: I80F48 = get_marginfi_account_health_usd(
+        &quote_marginfi_cpi_accounts.marginfi_account,
+        accounts,
+        oracle_prices,
+    )?;
+    require!(
+        health_usd >= required_health_usd,
+        NixError::InsufficientCollateral,
+        "Marginfi health {} does not cover borrowing {} base atoms worth {}",
+        health_usd,
+        borrow_base_atoms,
+        required_health_usd,
+    )?;
+    Ok(())
+}
+
+/// Clearing rate of the tree while the auction of a market in auction mode
+/// settles, None in its other phases. The MarketAuction PDA of the market
+/// has to be among the accounts.
+fn get_settling_clearing_rate_bps(
+    market_key: &Pubkey,
+    accounts: &[AccountInfo],
+    use_a_tree: bool,
+    now_slot: u32,
+) -> Result<Option<u16>, ProgramError> {
+    let (market_auction_key, _bump) = get_market_auction_address(market_key);
+    let market_auction_info_opt: Option<&AccountInfo> = accounts
+        .iter()
+        .find(|account| *account.key == market_auction_key);
+    require!(
+        market_auction_info_opt.is_some(),
+        NixError::MissingAccounts,
+        "Missing market auction {}",
+        market_auction_key,
+    )?;
+    let market_auction: NixAccountInfo<MarketAuction> =
+        NixAccountInfo::<MarketAuction>::new(market_auction_info_opt.unwrap())?;
+    let market_auction_fixed: Ref<MarketAuction> = market_auction.get_fixed()?;
+    if market_auction_fixed.get_phase(now_slot) != AuctionPhase::Settling {
+        return Ok(None);
+    }
+    Ok(market_auction_fixed.get_clearing_rate_bps(use_a_tree))
+}
+
+/// Base size of an order sized in quote atoms. Uses the same prices and
+/// buffer as the collateral taken while matching, so the order takes about
+/// num_quote_atoms of collateral if it fills.
+fn get_base_atoms_for_quote_size(
+    place_order_context: &PlaceOrderContext,
+    ltv_buffer_bps: u64,
+    base_oracle_price_usd: I80F48,
+    quote_oracle_price_usd: I80F48,
+    num_quote_atoms: u64,
+) -> Result<u64, ProgramError> {
+    let [Some(base_marginfi_cpi_accounts), Some(quote_marginfi_cpi_accounts)] =
+        &place_order_context.marginfi_cpi_accounts_opts
+    else {
+        return Err(NixError::MissingMarginfiAccounts.into());
+    };
+    let base_bank_share_values: BankShareValues =
+        BankShareValues::from(&*base_marginfi_cpi_accounts.marginfi_bank.get_fixed()?);
+    let quote_bank_share_values: BankShareValues =
+        BankShareValues::from(&*quote_marginfi_cpi_accounts.marginfi_bank.get_fixed()?);
+    let buffer_f: I80F48 = get_buffer_f(ltv_buffer_bps)?;
+    get_base_atoms_backed_by_quote_collateral(
+        &base_bank_share_values,
+        &quote_bank_share_values,
+        base_oracle_price_usd,
+        quote_oracle_price_usd,
+        buffer_f,
+        num_quote_atoms,
+    )
+}
+
 pub fn process_place_order_core<'a>(
-    _program_id: &Pubkey,
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    params: PlaceOrderParams,
+) -> ProgramResult {
+    process_place_order_resumable(program_id, accounts, params, None)
+}
+
+/// Places an order, or with resume_order_sequence_number the pending
+/// remainder of one that took that sequence number.
+pub(crate) fn process_place_order_resumable<'a>(
+    program_id: &Pubkey,
     accounts: &'a [AccountInfo<'a>],
     params: PlaceOrderParams,
+    resume_order_sequence_number: Option<u64>,
 ) -> ProgramResult {
+    let return_data_opt: Option<PlaceOrderReturnData> =
+        place_order_resumable(program_id, accounts, params, resume_order_sequence_number)?;
+    // Last, a CPI after it would replace it.
+    if let Some(return_data) = return_data_opt {
+        set_return_data(&return_data.try_to_vec()?);
+    }
+    Ok(())
+}
+
+/// Does the work of process_place_order_resumable without setting the return
+/// data, so instructions that place several orders can combine it. None when
+/// the order was dropped because it tripped the circuit breaker.
+pub(crate) fn place_order_resumable<'a>(
+    _program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    params: PlaceOrderParams,
+    resume_order_sequence_number: Option<u64>,
+) -> Result<Option<PlaceOrderReturnData>, ProgramError> {
+    params.validate()?;
     let place_order_context: PlaceOrderContext =
         PlaceOrderContext::load(accounts, params.use_a_tree)?;
     let current_slot: Option<u32> = Some(get_now_slot());
+    if params.auto_claim_seat {
+        claim_seat_if_needed(&place_order_context.market, &place_order_context.payer)?;
+    }
+    // Taken before the market data is borrowed, which the transfer CPI
+    // needs. Paid back after matching when nothing rests.
+    let gas_deposit_lamports: u64 =
+        if params.last_valid_unix_timestamp == NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP {
+            0
+        } else {
+            GTT_GAS_DEPOSIT_LAMPORTS
+        };
+    take_gas_deposit(
+        &place_order_context.payer,
+        &place_order_context.market,
+        gas_deposit_lamports,
+    )?;
 
     // Process the order directly without wrapper function
     let market_data: &mut RefMut<&mut [u8]> =
         &mut place_order_context.market.try_borrow_mut_data()?;
     let mut dynamic_account: MarketRefMut = get_mut_dynamic_account(market_data);
+    assert_allowed_caller(dynamic_account.fixed, accounts)?;
+    // Checked before the oracle reads and the health check, which would
+    // otherwise fail first on the missing bank of a one sided market.
+    assert_market_has_required_banks(
+        dynamic_account.fixed,
+        params.order_type,
+        params.is_bid,
+        params.use_a_tree,
+    )?;
+    // Before the oracle reads and collateral math, which use the bank config.
+    assert_bank_configs_unchanged(
+        dynamic_account.fixed,
+        &place_order_context.marginfi_cpi_accounts_opts,
+        params.use_a_tree,
+    )?;
     let trader_index: DataIndex = get_trader_index_with_hint(
         params.trader_index_hint,
         &dynamic_account,
         &place_order_context.payer,
     )?;
+    // Before matching, so the returned collateral can back this order.
+    sweep_expired_orders_of_trader(
+        &mut dynamic_account,
+        &place_order_context.market,
+        &place_order_context.payer,
+        trader_index,
+        params.max_expired_orders_to_sweep,
+    )?;
+    let referrer_index: DataIndex = match params.referrer {
+        None => NIL,
+        Some(referrer) => {
+            let referrer_index: DataIndex = dynamic_account.get_trader_index(&referrer);
+            require!(
+                is_not_nil!(referrer_index) && referrer_index != trader_index,
+                NixError::InvalidReferrer,
+                "Referrer {} needs a seat and cannot be the taker",
+                referrer,
+            )?;
+            referrer_index
+        }
+    };
 
-    let (base_oracle_price_usd, quote_oracle_price_usd) = {
-        let base_marginfi_bank_fixed = place_order_context.marginfi_cpi_accounts_opts[0]
-            .as_ref()
-            .unwrap()
-            .marginfi_bank
-            .get_fixed()
-            .unwrap();
-        let clock = Clock::get()?;
+    // Prices only size collateral when matching, so sides that asks without
+    // marginfi accounts leave out are never read.
+    // Shared with the health check below, which prices the same banks again.
+    let mut oracle_prices: OraclePrices = OraclePrices::new(accounts, Clock::get()?);
+    let mut oracle_prices_usd: [I80F48; 2] = [I80F48::ZERO; 2];
+    let mints: [&MintAccountInfo; 2] = [
+        &place_order_context.base_mint,
+        &place_order_context.quote_mint,
+    ];
+    for (index, marginfi_cpi_accounts_opt) in place_order_context
+        .marginfi_cpi_accounts_opts
+        .iter()
+        .enumerate()
+    {
+        if let Some(marginfi_cpi_accounts) = marginfi_cpi_accounts_opt {
+            oracle_prices_usd[index] = oracle_prices.get_price_of_atoms(
+                marginfi_cpi_accounts.marginfi_bank.key,
+                &marginfi_cpi_accounts.marginfi_bank.get_fixed()?.config,
+                mints[index],
+                Some(PriceBias::Low),
+                OraclePriceType::TimeWeighted,
+            )?;
+        }
+    }
+    let [base_oracle_price_usd, quote_oracle_price_usd] = oracle_prices_usd;
 
-        let base_oracle_price_usd = get_oracle_price(
+    // The price is only recorded by orders that read both sides. An order
+    // that trips the breaker and could take is dropped rather than failed,
+    // since failing would also undo the trip.
+    let now_slot: u32 = current_slot.unwrap();
+    if base_oracle_price_usd.is_positive() && quote_oracle_price_usd.is_positive() {
+        let price_ratio: I80F48 = base_oracle_price_usd
+            .checked_div(quote_oracle_price_usd)
+            .ok_or(NixError::NumericalOverflow)?;
+        if let Some(price_move_bps) = dynamic_account
+            .fixed
+            .update_circuit_breaker(price_ratio, now_slot)?
+        {
+            emit_stack(CircuitBreakerTrippedLog {
+                market: *place_order_context.market.key,
+                price_move_bps,
+                post_only_until_slot: dynamic_account.fixed.get_post_only_until_slot(),
+                _padding: [0; 4],
+            })?;
+            if order_type_can_take(params.order_type) {
+                pay_gas_deposits(
+                    &place_order_context.market,
+                    &place_order_context.payer,
+                    gas_deposit_lamports,
+                )?;
+                return Ok(None);
+            }
+        }
+    }
+    require!(
+        !order_type_can_take(params.order_type) || !dynamic_account.fixed.is_post_only(now_slot),
+        NixError::MarketPostOnly,
+        "Market is post only until slot {}",
+        dynamic_account.fixed.get_post_only_until_slot(),
+    )?;
+
+    // In auction mode orders only rest, except while an auction settles.
+    // Takers that reach its clearing rate then match at it.
+    let (match_limit, clearing_rate_bps) = if dynamic_account.fixed.is_auction_mode() {
+        require!(
+            !params.defer_remainder,
+            NixError::InvalidPendingOrder,
+            "Orders cannot defer their remainder in auction mode",
+        )?;
+        match get_settling_clearing_rate_bps(
+            place_order_context.market.key,
             accounts,
-            &base_marginfi_bank_fixed.config,
-            &clock,
-            Some(PriceBias::Low),
-            OraclePriceType::TimeWeighted,
+            params.use_a_tree,
+            now_slot,
+        )? {
+            Some(clearing_rate_bps)
+                if (params.is_bid && params.rate_bps >= clearing_rate_bps)
+                    || (!params.is_bid && params.rate_bps <= clearing_rate_bps) =>
+            {
+                (params.match_limit, Some(clearing_rate_bps))
+            }
+            _ => (Some(0), None),
+        }
+    } else {
+        (params.match_limit, None)
+    };
+    let num_base_atoms: u64 = if params.size_in_quote {
+        get_base_atoms_for_quote_size(
+            &place_order_context,
+            dynamic_account.fixed.get_ltv_buffer_bps(),
+            base_oracle_price_usd,
+            quote_oracle_price_usd,
+            params.num_base_atoms,
+        )?
+    } else {
+        params.num_base_atoms
+    };
+
+    // Loans are only added to the page after the marginfi CPIs of matching,
+    // so matching opens no more than the page can still take.
+    let num_free_loan_slots: u32 = place_order_context
+        .market_loans
+        .get_fixed()?
+        .get_num_free_loan_slots();
+    let max_new_loans: u32 = params
+        .max_new_loans
+        .map_or(num_free_loan_slots, |max_new_loans| {
+            max_new_loans.min(num_free_loan_slots)
+        });
+
+    if params.is_bid {
+        check_cross_margin_health(
+            &place_order_context,
+            accounts,
+            &mut oracle_prices,
+            num_base_atoms,
         )?;
-        let quote_marginfi_bank_fixed = place_order_context.marginfi_cpi_accounts_opts[1]
-            .as_ref()
-            .unwrap()
-            .marginfi_bank
-            .get_fixed()
-            .unwrap();
-        let clock = Clock::get()?;
-
-        let quote_oracle_price_usd = get_oracle_price(
+    }
+    // Only a bid that rests borrows, and a deferred remainder does not rest
+    // until ContinueOrder. Before matching, so a bid marginfi would refuse
+    // fails before it changes the book.
+    if params.is_bid
+        && order_type_can_rest(params.order_type)
+        && params.rate_bps != 0
+        && !params.defer_remainder
+    {
+        check_borrow_health(
+            &place_order_context,
+            &dynamic_account,
             accounts,
-            &quote_marginfi_bank_fixed.config,
-            &clock,
-            Some(PriceBias::Low),
-            OraclePriceType::TimeWeighted,
+            &mut oracle_prices,
+            params.use_a_tree,
+            num_base_atoms,
+            // Orders that cannot match, like those resting in auction mode,
+            // rest in full.
+            if match_limit == Some(0) {
+                None
+            } else {
+                Some(clearing_rate_bps.unwrap_or(params.rate_bps))
+            },
+            // Each maker order a bid fills opens a loan, so it stops no later
+            // than at max_new_loans.
+            match_limit.unwrap_or(u32::MAX).min(max_new_loans),
+            now_slot,
+            (base_oracle_price_usd, quote_oracle_price_usd),
         )?;
-
-        (base_oracle_price_usd, quote_oracle_price_usd)
-    };
+    }
 
     let args = AddOrderToMarketArgs {
         market: *place_order_context.market.key,
         market_signer: place_order_context.market_signer.clone(),
         market_signer_bump: place_order_context.market_signer.bump,
         trader_index,
-        num_base_atoms: params.num_base_atoms,
+        referrer_index,
+        num_base_atoms,
         rate_bps: params.rate_bps,
         reverse_spread_bps: params.reverse_spread_bps,
+        reverse_bid_spread_bps: params
+            .reverse_bid_spread_bps
+            .unwrap_or(params.reverse_spread_bps),
+        max_reverse_cycles: params.max_reverse_cycles,
         is_bid: params.is_bid,
         use_a_tree: params.use_a_tree,
         last_valid_slot: params.last_valid_slot,
+        last_valid_unix_timestamp: params.last_valid_unix_timestamp,
         order_type: params.order_type,
+        expiry_policy: params.expiry_policy,
         base_mint: place_order_context.base_mint.clone(),
         quote_mint: place_order_context.quote_mint.clone(),
         base_oracle_price_usd,
@@ -108,9 +955,21 @@ pub fn process_place_order_core<'a>(
         global_trade_accounts_opts: place_order_context.global_trade_accounts_opts,
         marginfi_cpi_accounts_opts: place_order_context.marginfi_cpi_accounts_opts,
         current_slot,
+        match_limit,
+        max_new_loans,
+        defer_remainder: params.defer_remainder,
+        resume_order_sequence_number,
+        clearing_rate_bps,
     };
 
     let res = dynamic_account.place_order(args,accounts)?;
+    // The payer gets the deposits of the good till time makers the order
+    // removed, and its own back unless the order rested.
+    pay_gas_deposits(
+        &place_order_context.market,
+        &place_order_context.payer,
+        res.gas_deposit_lamports_released + gas_deposit_lamports - res.gas_deposit_lamports_taken,
+    )?;
     emit_stack(PlaceOrderLog {
         market: *place_order_context.market.key,
         trader: *place_order_context.payer.key,
@@ -125,11 +984,190 @@ pub fn process_place_order_core<'a>(
         _padding1: [0; 6],
     })?;
 
+    if res.deferred_base_atoms > 0 || resume_order_sequence_number.is_some() {
+        update_pending_order(
+            place_order_context.market.key,
+            &place_order_context.payer,
+            &place_order_context.system_program,
+            accounts,
+            &params,
+            &res,
+            resume_order_sequence_number.is_some(),
+        )?;
+    }
+
+    let mut events: Vec<MarketEvent> = res.fill_events;
+    for loan in res.matched_loans.iter() {
+        events.push(MarketEvent::new(
+            MarketEventType::Loan,
+            current_slot.unwrap() as u64,
+            *dynamic_account.get_trader_key_by_index(loan.lender_index),
+            *dynamic_account.get_trader_key_by_index(loan.borrower_index),
+            loan.sequence_number,
+            loan.liability_shares,
+            loan.collateral_shares,
+            loan.rate_bps,
+            loan.is_liability_base_a.0 == 1,
+        ));
+    }
+    push_market_events(
+        dynamic_account.fixed,
+        place_order_context.market.key,
+        accounts,
+        &events,
+    )?;
+    record_market_stats(
+        place_order_context.market.key,
+        accounts,
+        params.use_a_tree,
+        res.base_atoms_traded,
+        current_slot.unwrap(),
+    )?;
+
     expand_market_if_needed(&place_order_context.payer, &place_order_context.market)?;
     //expand markets loans
     let matched_loans = res.matched_loans;
     expand_market_loans::<MarketLoansFixed>(&place_order_context.payer, &place_order_context.market_loans, matched_loans.len() as u32,)?;
     // insert new loans
-    try_to_add_new_loans(&place_order_context.market_loans, matched_loans)?;
-    Ok(())
+    try_to_add_new_loans(&place_order_context.market_loans, &matched_loans)?;
+    for loan_assignment in res.loan_assignments.iter() {
+        assign_sold_loan(
+            place_order_context.market.key,
+            accounts,
+            &dynamic_account,
+            loan_assignment,
+        )?;
+    }
+
+    Ok(Some(PlaceOrderReturnData {
+        order_sequence_number: res.order_sequence_number,
+        order_index: res.order_index,
+        base_atoms_traded: res.base_atoms_traded,
+        quote_atoms_traded: res.quote_atoms_traded,
+    }))
+}
+
+/// Writes the deferred remainder to the PendingOrder of the payer, creating
+/// it on the first deferral. A continued order that left nothing to defer
+/// closes it and the rent goes back to the payer.
+fn update_pending_order<'a>(
+    market_key: &Pubkey,
+    payer: &Signer<'a, 'a>,
+    system_program: &Program<'a, 'a>,
+    accounts: &'a [AccountInfo<'a>],
+    params: &PlaceOrderParams,
+    res: &AddOrderToMarketResult,
+    is_continuation: bool,
+) -> ProgramResult {
+    let AddOrderToMarketResult {
+        order_sequence_number,
+        deferred_base_atoms,
+        ..
+    } = *res;
+    let (pending_order_key, pending_order_bump) = get_pending_order_address(market_key, payer.key);
+    let pending_order_info_opt: Option<&AccountInfo> = accounts
+        .iter()
+        .find(|account| *account.key == pending_order_key);
+    require!(
+        pending_order_info_opt.is_some(),
+        NixError::MissingAccounts,
+        "Missing pending order {}",
+        pending_order_key,
+    )?;
+    let pending_order_info: &AccountInfo = pending_order_info_opt.unwrap();
+
+    if deferred_base_atoms == 0 {
+        close_nix_account(pending_order_info, payer.info)?;
+    } else {
+        let pending_order: NixAccountInfo<PendingOrder> = if is_continuation {
+            NixAccountInfo::<PendingOrder>::new(pending_order_info)?
+        } else {
+            // Deferring over a waiting remainder would drop it.
+            require!(
+                pending_order_info.data_is_empty(),
+                NixError::InvalidPendingOrder,
+                "Pending order {} is still waiting, continue it first",
+                pending_order_key,
+            )?;
+            let pending_order_seeds: Vec<Vec<u8>> = vec![
+                b"pending-order".to_vec(),
+                market_key.as_ref().to_vec(),
+                payer.key.as_ref().to_vec(),
+                vec![pending_order_bump],
+            ];
+            NixAccountInfo::<PendingOrder>::new_init_pda(
+                pending_order_info,
+                payer,
+                system_program,
+                pending_order_seeds,
+            )?
+        };
+        let mut pending_order_fixed: PendingOrder = PendingOrder::new(*market_key, *payer.key);
+        pending_order_fixed.referrer = params.referrer.unwrap_or_default();
+        pending_order_fixed.num_base_atoms = deferred_base_atoms;
+        pending_order_fixed.order_sequence_number = order_sequence_number;
+        pending_order_fixed.last_valid_slot = params.last_valid_slot;
+        pending_order_fixed.match_limit = params.match_limit.unwrap_or(MAX_MATCHED_LOANS as u32);
+        pending_order_fixed.rate_bps = params.rate_bps;
+        pending_order_fixed.is_bid = PodBool::from(params.is_bid);
+        pending_order_fixed.use_a_tree = PodBool::from(params.use_a_tree);
+        pending_order_fixed.order_type = params.order_type;
+        pending_order_fixed.expiry_policy = params.expiry_policy;
+        pending_order.init_fixed(pending_order_fixed)?;
+    }
+
+    emit_stack(PendingOrderLog {
+        market: *market_key,
+        trader: *payer.key,
+        num_base_atoms: deferred_base_atoms,
+        order_sequence_number,
+    })
+}
+
+/// Moves a loan bought while matching to its buyer. The loans page is found
+/// among the instruction accounts by the key stored on the loan sale.
+fn assign_sold_loan(
+    market_key: &Pubkey,
+    accounts: &[AccountInfo],
+    dynamic_account: &MarketRefMut,
+    loan_assignment: &LoanAssignment,
+) -> ProgramResult {
+    let market_loans_info: Option<&AccountInfo> = accounts
+        .iter()
+        .find(|account| *account.key == loan_assignment.market_loans);
+    require!(
+        market_loans_info.is_some(),
+        NixError::MissingAccounts,
+        "Missing loans page {} of a loan sale",
+        loan_assignment.market_loans,
+    )?;
+    let market_loans: NixAccountInfo<MarketLoansFixed> =
+        NixAccountInfo::<MarketLoansFixed>::new(market_loans_info.unwrap())?;
+    require!(
+        market_loans.get_fixed()?.market == *market_key,
+        NixError::IncorrectAccount,
+        "Loans page {} does not belong to market {}",
+        market_loans.key,
+        market_key,
+    )?;
+    {
+        let market_loans_data: &mut RefMut<&mut [u8]> = &mut market_loans.try_borrow_mut_data()?;
+        let mut market_loans_account: MarketLoansRefMut =
+            get_mut_dynamic_account(market_loans_data);
+        market_loans_account.assign_lender(
+            loan_assignment.loan_sequence_number,
+            loan_assignment.seller_index,
+            loan_assignment.buyer_index,
+        )?;
+    }
+    emit_stack(LoanSaleLog {
+        market: *market_key,
+        market_loans: loan_assignment.market_loans,
+        seller: *dynamic_account.get_trader_key_by_index(loan_assignment.seller_index),
+        buyer: *dynamic_account.get_trader_key_by_index(loan_assignment.buyer_index),
+        loan_sequence_number: loan_assignment.loan_sequence_number,
+        base_atoms: loan_assignment.base_atoms,
+        rate_bps: loan_assignment.rate_bps,
+        _padding: [0; 6],
+    })
 }
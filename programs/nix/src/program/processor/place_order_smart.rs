@@ -0,0 +1,183 @@
+use std::cell::Ref;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::{trace, DataIndex, PodBool};
+use shank::ShankType;
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+use crate::{
+    logs::{emit_stack, PlaceOrderSmartLog},
+    program::NixError,
+    require,
+    state::{
+        ExpiryPolicy, MarketFixed, MarketRef, OrderType, NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP,
+    },
+    utils::{get_now_slot, get_now_unix_timestamp},
+    validation::NixAccountInfo,
+};
+
+use super::{
+    get_dynamic_account,
+    place_order::{process_place_order_core, PlaceOrderParams},
+};
+
+/// A PlaceOrder that picks the tree itself. The order goes whole to the tree
+/// whose best opposing order gives the taker the better rate, it is not split
+/// across trees.
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct PlaceOrderSmartParams {
+    pub trader_index_hint: Option<DataIndex>,
+    /// Size of the order if it goes to the A tree, in base A atoms. Zero
+    /// keeps the order off the A tree.
+    pub num_base_a_atoms: u64,
+    /// Size of the order if it goes to the B tree, in base B atoms. Zero
+    /// keeps the order off the B tree.
+    pub num_base_b_atoms: u64,
+    pub rate_bps: u16,
+    pub reverse_spread_bps: u16,
+    pub is_bid: bool,
+    /// Tree to use when both trees are equally good, or when neither crosses
+    /// and the order rests.
+    pub prefer_a_tree: bool,
+    pub last_valid_slot: u32,
+    pub order_type: OrderType,
+    pub expiry_policy: ExpiryPolicy,
+    pub referrer: Option<Pubkey>,
+    pub match_limit: Option<u32>,
+    pub auto_claim_seat: bool,
+}
+
+pub(crate) fn process_place_order_smart<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+) -> ProgramResult {
+    let params: PlaceOrderSmartParams = PlaceOrderSmartParams::try_from_slice(data)?;
+    process_place_order_smart_core(program_id, accounts, params)
+}
+
+/// Takes the same accounts as PlaceOrder. Those are resolved by key, so one
+/// set works for either tree.
+pub(crate) fn process_place_order_smart_core<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    params: PlaceOrderSmartParams,
+) -> ProgramResult {
+    trace!("process_place_order_smart accts={accounts:?}");
+    require!(
+        params.num_base_a_atoms > 0 || params.num_base_b_atoms > 0,
+        NixError::OrderTooSmall,
+        "Order needs a size on at least one tree",
+    )?;
+
+    let market: NixAccountInfo<MarketFixed> = NixAccountInfo::<MarketFixed>::new(
+        accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?,
+    )?;
+    let now_slot: u32 = get_now_slot();
+    let now_unix_timestamp: i64 = get_now_unix_timestamp();
+    let (base_a_best_rate_bps, base_b_best_rate_bps): (Option<u16>, Option<u16>) = {
+        let market_data: Ref<&mut [u8]> = market.try_borrow_data()?;
+        let dynamic_account: MarketRef = get_dynamic_account(&market_data);
+        (
+            dynamic_account.get_best_opposing_rate_bps(
+                true,
+                params.is_bid,
+                now_slot,
+                now_unix_timestamp,
+            ),
+            dynamic_account.get_best_opposing_rate_bps(
+                false,
+                params.is_bid,
+                now_slot,
+                now_unix_timestamp,
+            ),
+        )
+    };
+    let use_a_tree: bool = choose_tree(&params, base_a_best_rate_bps, base_b_best_rate_bps);
+
+    emit_stack(PlaceOrderSmartLog {
+        market: *market.key,
+        trader: *accounts[0].key,
+        base_a_best_rate_bps: base_a_best_rate_bps.unwrap_or_default(),
+        base_b_best_rate_bps: base_b_best_rate_bps.unwrap_or_default(),
+        is_bid: PodBool::from_bool(params.is_bid),
+        is_a_tree: PodBool::from_bool(use_a_tree),
+        _padding: [0; 2],
+    })?;
+
+    process_place_order_core(
+        program_id,
+        accounts,
+        PlaceOrderParams {
+            trader_index_hint: params.trader_index_hint,
+            num_base_atoms: if use_a_tree {
+                params.num_base_a_atoms
+            } else {
+                params.num_base_b_atoms
+            },
+            rate_bps: params.rate_bps,
+            reverse_spread_bps: params.reverse_spread_bps,
+            is_bid: params.is_bid,
+            use_a_tree,
+            last_valid_slot: params.last_valid_slot,
+            order_type: params.order_type,
+            expiry_policy: params.expiry_policy,
+            referrer: params.referrer,
+            match_limit: params.match_limit,
+            auto_claim_seat: params.auto_claim_seat,
+            size_in_quote: false,
+            defer_remainder: false,
+            max_reverse_cycles: 0,
+            max_expired_orders_to_sweep: 0,
+            max_new_loans: None,
+            reverse_bid_spread_bps: None,
+            last_valid_unix_timestamp: NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP,
+        },
+    )
+}
+
+/// Only trees the order has a size for and whose best opposing order
+/// crosses the limit rate compete. Bids want the lower rate and asks the
+/// higher one.
+fn choose_tree(
+    params: &PlaceOrderSmartParams,
+    base_a_best_rate_bps: Option<u16>,
+    base_b_best_rate_bps: Option<u16>,
+) -> bool {
+    let crossing_rate_bps = |best_rate_bps: Option<u16>, num_base_atoms: u64| -> Option<u16> {
+        best_rate_bps.filter(|rate_bps| {
+            num_base_atoms > 0
+                && if params.is_bid {
+                    *rate_bps <= params.rate_bps
+                } else {
+                    *rate_bps >= params.rate_bps
+                }
+        })
+    };
+    match (
+        crossing_rate_bps(base_a_best_rate_bps, params.num_base_a_atoms),
+        crossing_rate_bps(base_b_best_rate_bps, params.num_base_b_atoms),
+    ) {
+        (Some(a_rate_bps), Some(b_rate_bps)) => {
+            if a_rate_bps == b_rate_bps {
+                params.prefer_a_tree
+            } else {
+                (a_rate_bps < b_rate_bps) == params.is_bid
+            }
+        }
+        (Some(_), None) => true,
+        (None, Some(_)) => false,
+        (None, None) => {
+            if params.num_base_a_atoms == 0 {
+                false
+            } else if params.num_base_b_atoms == 0 {
+                true
+            } else {
+                params.prefer_a_tree
+            }
+        }
+    }
+}
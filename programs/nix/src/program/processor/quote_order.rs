@@ -0,0 +1,171 @@
+use std::cell::Ref;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::{trace, PodBool};
+use marginfi::state::price::{OraclePriceType, PriceBias};
+use shank::ShankType;
+use solana_program::{
+    account_info::AccountInfo, clock::Clock, entrypoint::ProgramResult, program::set_return_data,
+    pubkey::Pubkey, sysvar::Sysvar,
+};
+
+use crate::{
+    logs::{emit_stack, BookLevel, QuoteOrderLog},
+    marginfi_utils::{get_oracle_price, BankShareValues},
+    quantities::{BaseAtoms, Rate},
+    state::{MarketRef, QuoteOrderResult, MAX_BOOK_SNAPSHOT_LEVELS, MAX_MATCHED_LOANS},
+    utils::{get_now_slot, get_now_unix_timestamp},
+    validation::loaders::QuoteOrderContext,
+};
+
+use super::get_dynamic_account;
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct QuoteOrderParams {
+    pub num_base_atoms: u64,
+    pub rate_bps: u16,
+    pub is_bid: bool,
+    pub use_a_tree: bool,
+    /// Rate levels of the side the order would take from to return, capped
+    /// at MAX_BOOK_SNAPSHOT_LEVELS. Zero for none.
+    pub num_levels: u8,
+}
+
+impl QuoteOrderParams {
+    pub fn new(
+        num_base_atoms: BaseAtoms,
+        rate: Rate,
+        is_bid: bool,
+        use_a_tree: bool,
+        num_levels: u8,
+    ) -> Self {
+        QuoteOrderParams {
+            num_base_atoms: num_base_atoms.as_u64(),
+            rate_bps: rate.as_bps(),
+            is_bid,
+            use_a_tree,
+            num_levels,
+        }
+    }
+}
+
+/// Return data of QuoteOrder, so routers can quote a market in a CPI or a
+/// simulation without paging through the whole account.
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct QuoteOrderReturnData {
+    pub base_atoms: u64,
+    pub quote_atoms: u64,
+    pub num_maker_orders: u32,
+    pub weighted_rate_bps: u16,
+    /// Best levels of the side the order would take from, best rate first.
+    pub levels: Vec<BookLevel>,
+}
+
+pub(crate) fn process_quote_order<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+) -> ProgramResult {
+    let params: QuoteOrderParams = QuoteOrderParams::try_from_slice(data)?;
+    process_quote_order_core(program_id, accounts, params)
+}
+
+/// Read only. Meant to be run through simulateTransaction to preview a fill.
+pub(crate) fn process_quote_order_core<'a>(
+    _program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    params: QuoteOrderParams,
+) -> ProgramResult {
+    trace!("process_quote_order accts={accounts:?}");
+    let quote_order_context: QuoteOrderContext =
+        QuoteOrderContext::load(accounts, params.use_a_tree)?;
+    let QuoteOrderContext {
+        market,
+        base_marginfi_bank,
+        quote_marginfi_bank,
+    } = quote_order_context;
+
+    let clock: Clock = Clock::get()?;
+    let (base_bank, base_oracle_price_usd) = {
+        let base_marginfi_bank_fixed = base_marginfi_bank.get_fixed()?;
+        let base_oracle_price_usd = get_oracle_price(
+            accounts,
+            &base_marginfi_bank_fixed.config,
+            &clock,
+            Some(PriceBias::Low),
+            OraclePriceType::TimeWeighted,
+        )?;
+        (
+            BankShareValues::from(&*base_marginfi_bank_fixed),
+            base_oracle_price_usd,
+        )
+    };
+    let (quote_bank, quote_oracle_price_usd) = {
+        let quote_marginfi_bank_fixed = quote_marginfi_bank.get_fixed()?;
+        let quote_oracle_price_usd = get_oracle_price(
+            accounts,
+            &quote_marginfi_bank_fixed.config,
+            &clock,
+            Some(PriceBias::Low),
+            OraclePriceType::TimeWeighted,
+        )?;
+        (
+            BankShareValues::from(&*quote_marginfi_bank_fixed),
+            quote_oracle_price_usd,
+        )
+    };
+
+    let market_data: Ref<&mut [u8]> = market.try_borrow_data()?;
+    let dynamic_account: MarketRef = get_dynamic_account(&market_data);
+    let now_slot: u32 = get_now_slot();
+    let now_unix_timestamp: i64 = get_now_unix_timestamp();
+
+    let QuoteOrderResult {
+        base_atoms,
+        quote_atoms,
+        num_maker_orders,
+        weighted_rate_bps,
+    } = dynamic_account.quote_order(
+        params.use_a_tree,
+        params.is_bid,
+        params.num_base_atoms,
+        params.rate_bps,
+        MAX_MATCHED_LOANS as u32,
+        now_slot,
+        now_unix_timestamp,
+        &base_bank,
+        &quote_bank,
+        base_oracle_price_usd,
+        quote_oracle_price_usd,
+    )?;
+    // A bid takes from the asks and an ask from the bids.
+    let levels: Vec<BookLevel> = dynamic_account.aggregate_levels(
+        params.use_a_tree,
+        !params.is_bid,
+        (params.num_levels as usize).min(MAX_BOOK_SNAPSHOT_LEVELS),
+        now_slot,
+        now_unix_timestamp,
+    )?;
+
+    emit_stack(QuoteOrderLog {
+        market: *market.key,
+        base_atoms,
+        quote_atoms,
+        num_maker_orders,
+        weighted_rate_bps,
+        is_bid: PodBool::from_bool(params.is_bid),
+        is_a_tree: PodBool::from_bool(params.use_a_tree),
+    })?;
+
+    set_return_data(
+        &QuoteOrderReturnData {
+            base_atoms,
+            quote_atoms,
+            num_maker_orders,
+            weighted_rate_bps,
+            levels,
+        }
+        .try_to_vec()?,
+    );
+    Ok(())
+}
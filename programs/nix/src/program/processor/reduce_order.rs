@@ -0,0 +1,108 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::{get_helper, trace, DataIndex, RBNode};
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, ReduceOrderLog},
+    program::{get_mut_dynamic_account, get_trader_index_with_hint, push_market_events, NixError},
+    quantities::WrappedI80F48,
+    require,
+    state::{
+        MarketDataTreeNodeType, MarketEvent, MarketEventType, MarketRefMut, RestingOrder,
+        MARKET_BLOCK_SIZE,
+    },
+    utils::get_now_slot,
+    validation::loaders::ReduceOrderContext,
+};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct ReduceOrderParams {
+    pub trader_index_hint: Option<DataIndex>,
+    pub order_sequence_number: u64,
+    pub order_index_hint: Option<DataIndex>,
+    pub use_a_tree: bool,
+    /// Asset shares to take off the order, token atoms for global asks. Has
+    /// to leave something on the order, cancel it to remove everything.
+    pub collateral_shares_to_remove: WrappedI80F48,
+}
+
+pub fn process_reduce_order<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+) -> ProgramResult {
+    let params: ReduceOrderParams = ReduceOrderParams::try_from_slice(data)?;
+    process_reduce_order_core(program_id, accounts, params)
+}
+
+/// Re-quoting the same rate with a smaller size through cancel and place
+/// sends the order to the back of the queue. This keeps it where it is.
+pub fn process_reduce_order_core<'a>(
+    _program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    params: ReduceOrderParams,
+) -> ProgramResult {
+    trace!("process_reduce_order accts={accounts:?}");
+    let ReduceOrderParams {
+        trader_index_hint,
+        order_sequence_number,
+        order_index_hint,
+        use_a_tree,
+        collateral_shares_to_remove,
+    } = params;
+    let ReduceOrderContext { payer, market } = ReduceOrderContext::load(accounts)?;
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    let mut dynamic_account: MarketRefMut = get_mut_dynamic_account(market_data);
+    let trader_index: DataIndex =
+        get_trader_index_with_hint(trader_index_hint, &dynamic_account, &payer)?;
+
+    if let Some(hinted_order_index) = order_index_hint {
+        // Owner and sequence number are checked when reducing.
+        require!(
+            hinted_order_index % (MARKET_BLOCK_SIZE as DataIndex) == 0
+                && get_helper::<RBNode<RestingOrder>>(&dynamic_account.dynamic, hinted_order_index)
+                    .get_payload_type()
+                    == MarketDataTreeNodeType::RestingOrder as u8,
+            NixError::WrongIndexHintParams,
+            "Invalid reduce hint index {}",
+            hinted_order_index,
+        )?;
+    }
+
+    let collateral_shares_remaining: WrappedI80F48 = dynamic_account.reduce_order(
+        use_a_tree,
+        trader_index,
+        order_sequence_number,
+        order_index_hint,
+        collateral_shares_to_remove,
+    )?;
+
+    push_market_events(
+        dynamic_account.fixed,
+        market.key,
+        accounts,
+        &[MarketEvent::new(
+            MarketEventType::Reduce,
+            get_now_slot() as u64,
+            *payer.key,
+            Pubkey::default(),
+            order_sequence_number,
+            collateral_shares_to_remove,
+            WrappedI80F48::default(),
+            0,
+            use_a_tree,
+        )],
+    )?;
+    emit_stack(ReduceOrderLog {
+        market: *market.key,
+        trader: *payer.key,
+        order_sequence_number,
+        collateral_shares_removed: collateral_shares_to_remove,
+        collateral_shares_remaining,
+    })?;
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::DataIndex;
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, ReferrerClaimLog},
+    state::MarketRefMut,
+    validation::loaders::ReferrerClaimContext,
+};
+
+use super::{get_mut_dynamic_account, get_trader_index_with_hint};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct ReferrerClaimParams {
+    pub trader_index_hint: Option<DataIndex>,
+}
+
+impl ReferrerClaimParams {
+    pub fn new(trader_index_hint: Option<DataIndex>) -> Self {
+        ReferrerClaimParams { trader_index_hint }
+    }
+}
+
+pub(crate) fn process_referrer_claim(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let params: ReferrerClaimParams = ReferrerClaimParams::try_from_slice(data)?;
+    process_referrer_claim_core(program_id, accounts, params)
+}
+
+pub(crate) fn process_referrer_claim_core(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    params: ReferrerClaimParams,
+) -> ProgramResult {
+    let referrer_claim_context: ReferrerClaimContext = ReferrerClaimContext::load(accounts)?;
+    let ReferrerClaimContext { referrer, market } = referrer_claim_context;
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    let mut dynamic_account: MarketRefMut = get_mut_dynamic_account(market_data);
+    let trader_index: DataIndex =
+        get_trader_index_with_hint(params.trader_index_hint, &dynamic_account, &referrer)?;
+
+    let (base_a_referral_fee_shares, base_b_referral_fee_shares) =
+        dynamic_account.claim_referral_fees(trader_index)?;
+
+    emit_stack(ReferrerClaimLog {
+        market: *market.key,
+        referrer: *referrer.key,
+        base_a_referral_fee_shares,
+        base_b_referral_fee_shares,
+    })?;
+
+    Ok(())
+}
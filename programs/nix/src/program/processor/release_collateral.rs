@@ -0,0 +1,259 @@
+use std::cell::{Ref, RefMut};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use fixed::types::I80F48;
+use hypertree::{trace, DataIndex, PodBool};
+use marginfi::state::{
+    marginfi_group::Bank,
+    price::{OraclePriceType, PriceBias},
+};
+use shank::ShankType;
+use solana_program::{
+    account_info::AccountInfo, clock::Clock, entrypoint::ProgramResult, program::invoke_signed,
+    program_error::ProgramError, pubkey::Pubkey, sysvar::Sysvar,
+};
+
+use crate::{
+    logs::{emit_stack, ReleaseCollateralLog},
+    marginfi_utils::{
+        convert_asset_shares_to_tokens, cpi_marginfi_withdraw_to,
+        get_required_quote_collateral_to_back_loan, get_token_amount_to_repay_liability_shares,
+        BankShareValues, MarginfiCpiScratch, OraclePrices,
+    },
+    market_signer_seeds_with_bump,
+    math::get_buffer_f,
+    program::NixError,
+    require,
+    state::{ActiveLoan, MarketLoansRefMut, MarketRef},
+    validation::{
+        loaders::ReleaseCollateralContext, MarketSigner, MintAccountInfo, TokenAccountInfo,
+        TokenProgram,
+    },
+};
+
+use super::{get_dynamic_account, get_mut_dynamic_account, get_trader_index_with_hint};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct ReleaseCollateralParams {
+    pub trader_index_hint: Option<DataIndex>,
+    pub loan_sequence_number: u64,
+    /// Atoms of the collateral mint to withdraw to the borrower.
+    pub amount: u64,
+}
+
+pub fn process_release_collateral<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+) -> ProgramResult {
+    let params: ReleaseCollateralParams = ReleaseCollateralParams::try_from_slice(data)?;
+    process_release_collateral_core(program_id, accounts, params)
+}
+
+/// Lets a borrower take back collateral a loan no longer needs. What is left
+/// has to back the liability at the LTV buffer of the market, priced the same
+/// conservative way as when the loan was taken.
+pub fn process_release_collateral_core<'a>(
+    _program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    params: ReleaseCollateralParams,
+) -> ProgramResult {
+    trace!("process_release_collateral accts={accounts:?}");
+    let ReleaseCollateralParams {
+        trader_index_hint,
+        loan_sequence_number,
+        amount,
+    } = params;
+    let ReleaseCollateralContext {
+        payer,
+        market,
+        market_loans,
+        market_signer,
+        mint,
+        trader_token_account,
+        token_program,
+        vault,
+        marginfi_cpi_accounts,
+        liability_marginfi_bank,
+        liability_mint,
+        is_base_a,
+    } = ReleaseCollateralContext::load(accounts)?;
+
+    let (trader_index, ltv_buffer_bps) = {
+        let market_data: Ref<&mut [u8]> = market.try_borrow_data()?;
+        let dynamic_account: MarketRef = get_dynamic_account(&market_data);
+        let trader_index: DataIndex =
+            get_trader_index_with_hint(trader_index_hint, &dynamic_account, &payer)?;
+        (trader_index, dynamic_account.fixed.get_ltv_buffer_bps())
+    };
+
+    let get_asset_shares = || -> Result<I80F48, ProgramError> {
+        Ok(marginfi_cpi_accounts
+            .marginfi_account
+            .get_fixed()?
+            .lending_account
+            .balances
+            .iter()
+            .find(|b| b.active != 0 && b.bank_pk == *marginfi_cpi_accounts.marginfi_bank.key)
+            .map(|b| I80F48::from(b.asset_shares))
+            .unwrap_or_default())
+    };
+    let asset_shares_before: I80F48 = get_asset_shares()?;
+    let vault_balance_before: u64 = vault.get_balance();
+    let mint_opt: Option<&MintAccountInfo> = if *vault.owner == spl_token_2022::id() {
+        Some(&mint)
+    } else {
+        None
+    };
+    cpi_marginfi_withdraw_to(
+        &marginfi_cpi_accounts,
+        &vault,
+        &token_program,
+        amount,
+        mint_opt,
+        market_signer.clone(),
+        &mut MarginfiCpiScratch::default(),
+        market_signer_seeds_with_bump!(market.key, market_signer.bump),
+        accounts,
+    )?;
+    let released_collateral_shares: I80F48 = asset_shares_before
+        .checked_sub(get_asset_shares()?)
+        .ok_or(NixError::NumericalOverflow)?;
+    // Due to transfer fees, this might not be what you expect.
+    let withdrawn_amount: u64 = vault
+        .get_balance()
+        .checked_sub(vault_balance_before)
+        .ok_or(NixError::NumericalOverflow)?;
+
+    let loan: ActiveLoan = {
+        let market_loans_data: &mut RefMut<&mut [u8]> = &mut market_loans.try_borrow_mut_data()?;
+        let mut market_loans_dynamic_account: MarketLoansRefMut =
+            get_mut_dynamic_account(market_loans_data);
+        market_loans_dynamic_account.release_collateral(
+            loan_sequence_number,
+            trader_index,
+            released_collateral_shares.into(),
+        )?
+    };
+    require!(
+        is_base_a != (loan.is_liability_base_a.0 == 1),
+        NixError::InvalidCollateralRelease,
+        "Collateral of loan {} is in the other mint",
+        loan_sequence_number,
+    )?;
+
+    // Banks are read after the withdraw, which accrues interest on them.
+    let collateral_bank: Ref<Bank> = marginfi_cpi_accounts.marginfi_bank.get_fixed()?;
+    let liability_bank: Ref<Bank> = liability_marginfi_bank.get_fixed()?;
+    let collateral_bank_share_values: BankShareValues = BankShareValues::from(&*collateral_bank);
+    let liability_bank_share_values: BankShareValues = BankShareValues::from(&*liability_bank);
+    let mut oracle_prices: OraclePrices = OraclePrices::new(accounts, Clock::get()?);
+    let collateral_price_usd: I80F48 = oracle_prices.get_price_of_atoms(
+        marginfi_cpi_accounts.marginfi_bank.key,
+        &collateral_bank.config,
+        &mint,
+        Some(PriceBias::Low),
+        OraclePriceType::TimeWeighted,
+    )?;
+    let liability_price_usd: I80F48 = oracle_prices.get_price_of_atoms(
+        liability_marginfi_bank.key,
+        &liability_bank.config,
+        &liability_mint,
+        Some(PriceBias::High),
+        OraclePriceType::TimeWeighted,
+    )?;
+    let required_collateral_atoms: u64 = get_required_quote_collateral_to_back_loan(
+        &liability_bank_share_values,
+        &collateral_bank_share_values,
+        liability_price_usd,
+        collateral_price_usd,
+        get_buffer_f(ltv_buffer_bps)?,
+        get_token_amount_to_repay_liability_shares(
+            loan.liability_shares.into(),
+            &liability_bank_share_values,
+        )?,
+    )?;
+    let collateral_atoms: u64 = convert_asset_shares_to_tokens(
+        loan.collateral_shares.into(),
+        &collateral_bank_share_values,
+    )?;
+    require!(
+        collateral_atoms >= required_collateral_atoms,
+        NixError::InvalidCollateralRelease,
+        "Loan {} would keep {} of collateral, needs {}",
+        loan_sequence_number,
+        collateral_atoms,
+        required_collateral_atoms,
+    )?;
+    drop(collateral_bank);
+    drop(liability_bank);
+
+    transfer_from_vault_to_trader(
+        &token_program,
+        &vault,
+        &mint,
+        &trader_token_account,
+        &market_signer,
+        market.key,
+        withdrawn_amount,
+        accounts,
+    )?;
+
+    emit_stack(ReleaseCollateralLog {
+        market: *market.key,
+        trader: *payer.key,
+        market_loans: *market_loans.key,
+        loan_sequence_number,
+        released_collateral_shares: released_collateral_shares.into(),
+        remaining_collateral_shares: loan.collateral_shares,
+        amount: withdrawn_amount,
+        is_base_a: PodBool::from(is_base_a),
+        _padding: [0; 7],
+    })
+}
+
+/// Sends what marginfi withdrew into the vault on to the trader. The vault
+/// is owned by the market signer. Transfer hook accounts, if the mint has a
+/// hook, are found by key among `accounts`.
+pub(crate) fn transfer_from_vault_to_trader<'a, 'info>(
+    token_program: &TokenProgram<'a, 'info>,
+    vault: &TokenAccountInfo<'a, 'info>,
+    mint: &MintAccountInfo<'a, 'info>,
+    trader_token_account: &TokenAccountInfo<'a, 'info>,
+    market_signer: &MarketSigner<'a, 'info>,
+    market_key: &Pubkey,
+    amount: u64,
+    accounts: &[AccountInfo<'info>],
+) -> ProgramResult {
+    if *vault.owner == spl_token_2022::id() {
+        spl_token_2022::onchain::invoke_transfer_checked(
+            token_program.key,
+            vault.as_ref().clone(),
+            mint.as_ref().clone(),
+            trader_token_account.as_ref().clone(),
+            market_signer.as_ref().clone(),
+            accounts,
+            amount,
+            mint.mint.decimals,
+            market_signer_seeds_with_bump!(market_key, market_signer.bump),
+        )
+    } else {
+        invoke_signed(
+            &spl_token::instruction::transfer(
+                token_program.key,
+                vault.key,
+                trader_token_account.key,
+                market_signer.info.key,
+                &[],
+                amount,
+            )?,
+            &[
+                token_program.as_ref().clone(),
+                vault.as_ref().clone(),
+                trader_token_account.as_ref().clone(),
+                market_signer.as_ref().clone(),
+            ],
+            market_signer_seeds_with_bump!(market_key, market_signer.bump),
+        )
+    }
+}
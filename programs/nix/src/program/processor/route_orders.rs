@@ -0,0 +1,108 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::trace;
+use shank::ShankType;
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, program::set_return_data,
+    program_error::ProgramError, pubkey::Pubkey,
+};
+
+use crate::{
+    logs::{emit_stack, RouteOrdersLog},
+    program::NixError,
+    require,
+};
+
+use super::place_order::{place_order_resumable, PlaceOrderParams, PlaceOrderReturnData};
+
+/// Two orders placed in one instruction, usually on different markets, like
+/// a bid on one and an ask on the other. Either both go through or neither
+/// does.
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct RouteOrdersParams {
+    /// Number of accounts of the first leg. The second leg takes the rest.
+    pub first_leg_num_accounts: u8,
+    pub first_leg: PlaceOrderParams,
+    pub second_leg: PlaceOrderParams,
+}
+
+/// Return data of RouteOrders, the result of each leg in order.
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct RouteOrdersReturnData {
+    pub first_leg: PlaceOrderReturnData,
+    pub second_leg: PlaceOrderReturnData,
+}
+
+pub(crate) fn process_route_orders<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+) -> ProgramResult {
+    let params: RouteOrdersParams = RouteOrdersParams::try_from_slice(data)?;
+    process_route_orders_core(program_id, accounts, params)
+}
+
+/// Each leg gets the accounts PlaceOrder would, including what it appends,
+/// and is placed like one. The payer has to be the same, globals and banks
+/// both legs use are passed to each, the runtime dedups them.
+pub(crate) fn process_route_orders_core<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    params: RouteOrdersParams,
+) -> ProgramResult {
+    trace!("process_route_orders accts={accounts:?}");
+    let RouteOrdersParams {
+        first_leg_num_accounts,
+        first_leg,
+        second_leg,
+    } = params;
+    let split_index: usize = first_leg_num_accounts as usize;
+    require!(
+        split_index > 0 && split_index < accounts.len(),
+        NixError::MissingAccounts,
+        "First leg takes {} of {} accounts, both legs need some",
+        split_index,
+        accounts.len(),
+    )?;
+    let (first_leg_accounts, second_leg_accounts) = accounts.split_at(split_index);
+    require!(
+        first_leg_accounts[0].key == second_leg_accounts[0].key,
+        NixError::InvalidPlaceOrderParams,
+        "Legs have different payers {} and {}",
+        first_leg_accounts[0].key,
+        second_leg_accounts[0].key,
+    )?;
+    let first_market: Pubkey = *first_leg_accounts
+        .get(1)
+        .ok_or(ProgramError::NotEnoughAccountKeys)?
+        .key;
+    let second_market: Pubkey = *second_leg_accounts
+        .get(1)
+        .ok_or(ProgramError::NotEnoughAccountKeys)?
+        .key;
+
+    // A leg dropped by the circuit breaker would leave the other one alone,
+    // so here it fails the route, which also undoes the trip.
+    let first_leg: PlaceOrderReturnData =
+        place_order_resumable(program_id, first_leg_accounts, first_leg, None)?
+            .ok_or(NixError::MarketPostOnly)?;
+    let second_leg: PlaceOrderReturnData =
+        place_order_resumable(program_id, second_leg_accounts, second_leg, None)?
+            .ok_or(NixError::MarketPostOnly)?;
+
+    emit_stack(RouteOrdersLog {
+        first_market,
+        second_market,
+        trader: *first_leg_accounts[0].key,
+        first_order_sequence_number: first_leg.order_sequence_number,
+        second_order_sequence_number: second_leg.order_sequence_number,
+    })?;
+
+    set_return_data(
+        &RouteOrdersReturnData {
+            first_leg,
+            second_leg,
+        }
+        .try_to_vec()?,
+    );
+    Ok(())
+}
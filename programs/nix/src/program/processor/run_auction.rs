@@ -0,0 +1,93 @@
+use std::cell::{Ref, RefMut};
+
+use hypertree::{get_mut_helper, trace};
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, RunAuctionLog},
+    marginfi_utils::BankShareValues,
+    program::NixError,
+    require,
+    state::{MarketAuction, MarketRef},
+    utils::{get_now_slot, get_now_unix_timestamp},
+    validation::loaders::RunAuctionContext,
+};
+
+use super::get_dynamic_account;
+
+pub(crate) fn process_run_auction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    process_run_auction_core(program_id, accounts, data)
+}
+
+/// Permissionless. Once the window of a market in auction mode closes, finds
+/// the rate that clears the most base on each tree over the resting orders.
+/// Nothing moves here, the market then settles at those rates through
+/// PlaceOrder: takers that reach the clearing rate match at it until the
+/// settlement ends and the next window opens.
+pub(crate) fn process_run_auction_core(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    _data: &[u8],
+) -> ProgramResult {
+    trace!("process_run_auction accts={accounts:?}");
+    let RunAuctionContext {
+        market,
+        market_auction,
+        base_a_marginfi_bank,
+        base_b_marginfi_bank,
+    } = RunAuctionContext::load(accounts)?;
+
+    let now_slot: u32 = get_now_slot();
+    let window_end_slot: u32 = market_auction.get_fixed()?.get_window_end_slot();
+    require!(
+        now_slot >= window_end_slot,
+        NixError::AuctionNotDue,
+        "Auction window closes at slot {}",
+        window_end_slot,
+    )?;
+
+    let base_a_bank: BankShareValues = BankShareValues::from(&*base_a_marginfi_bank.get_fixed()?);
+    let base_b_bank: BankShareValues = BankShareValues::from(&*base_b_marginfi_bank.get_fixed()?);
+    let now_unix_timestamp: i64 = get_now_unix_timestamp();
+    let (base_a_clearing, base_b_clearing) = {
+        let market_data: Ref<&mut [u8]> = market.try_borrow_data()?;
+        let dynamic_account: MarketRef = get_dynamic_account(&market_data);
+        (
+            dynamic_account.get_auction_clearing(
+                true,
+                &base_a_bank,
+                now_slot,
+                now_unix_timestamp,
+            )?,
+            dynamic_account.get_auction_clearing(
+                false,
+                &base_b_bank,
+                now_slot,
+                now_unix_timestamp,
+            )?,
+        )
+    };
+
+    let settle_end_slot: u32 = {
+        let mut market_auction_data: RefMut<&mut [u8]> = market_auction.try_borrow_mut_data()?;
+        let market_auction_fixed: &mut MarketAuction =
+            get_mut_helper::<MarketAuction>(&mut market_auction_data, 0_u32);
+        market_auction_fixed.settle(base_a_clearing, base_b_clearing, now_slot);
+        market_auction_fixed.get_settle_end_slot()
+    };
+
+    let (base_a_clearing_rate_bps, base_a_cleared_base_atoms) = base_a_clearing.unwrap_or_default();
+    let (base_b_clearing_rate_bps, base_b_cleared_base_atoms) = base_b_clearing.unwrap_or_default();
+    emit_stack(RunAuctionLog {
+        market: *market.key,
+        base_a_cleared_base_atoms,
+        base_b_cleared_base_atoms,
+        base_a_clearing_rate_bps,
+        base_b_clearing_rate_bps,
+        settle_end_slot,
+    })
+}
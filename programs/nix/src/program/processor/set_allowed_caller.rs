@@ -0,0 +1,47 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::{get_mut_helper, trace};
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, SetAllowedCallerLog},
+    state::MarketFixed,
+    validation::loaders::SetAllowedCallerContext,
+};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct SetAllowedCallerParams {
+    /// Program that has to CPI into PlaceOrder, like a router that charges
+    /// its own fees. Default pubkey lets anyone place orders directly again.
+    pub allowed_caller: Pubkey,
+}
+
+pub(crate) fn process_set_allowed_caller(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let params: SetAllowedCallerParams = SetAllowedCallerParams::try_from_slice(data)?;
+    process_set_allowed_caller_core(program_id, accounts, params)
+}
+
+pub(crate) fn process_set_allowed_caller_core(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    params: SetAllowedCallerParams,
+) -> ProgramResult {
+    trace!("process_set_allowed_caller accts={accounts:?}");
+    let SetAllowedCallerContext { admin, market } = SetAllowedCallerContext::load(accounts)?;
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    let market_fixed: &mut MarketFixed = get_mut_helper::<MarketFixed>(market_data, 0_u32);
+    market_fixed.set_allowed_caller(&params.allowed_caller);
+
+    emit_stack(SetAllowedCallerLog {
+        market: *market.key,
+        admin: *admin.key,
+        allowed_caller: params.allowed_caller,
+    })
+}
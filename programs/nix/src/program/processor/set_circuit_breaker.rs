@@ -0,0 +1,51 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::{get_mut_helper, trace};
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, SetCircuitBreakerLog},
+    state::MarketFixed,
+    validation::loaders::SetCircuitBreakerContext,
+};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct SetCircuitBreakerParams {
+    /// Move of the base over quote oracle price between two orders that trips
+    /// the breaker, in bps. Zero turns it off.
+    pub circuit_breaker_bps: u16,
+    /// Slots the market stays post only after a trip.
+    pub circuit_breaker_slots: u32,
+}
+
+pub(crate) fn process_set_circuit_breaker(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let params: SetCircuitBreakerParams = SetCircuitBreakerParams::try_from_slice(data)?;
+    process_set_circuit_breaker_core(program_id, accounts, params)
+}
+
+pub(crate) fn process_set_circuit_breaker_core(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    params: SetCircuitBreakerParams,
+) -> ProgramResult {
+    trace!("process_set_circuit_breaker accts={accounts:?}");
+    let SetCircuitBreakerContext { admin, market } = SetCircuitBreakerContext::load(accounts)?;
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    let market_fixed: &mut MarketFixed = get_mut_helper::<MarketFixed>(market_data, 0_u32);
+    market_fixed.set_circuit_breaker(params.circuit_breaker_bps, params.circuit_breaker_slots);
+
+    emit_stack(SetCircuitBreakerLog {
+        market: *market.key,
+        admin: *admin.key,
+        circuit_breaker_slots: params.circuit_breaker_slots,
+        circuit_breaker_bps: params.circuit_breaker_bps,
+        _padding: [0; 2],
+    })
+}
@@ -0,0 +1,58 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::{get_mut_helper, trace};
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, SetDebugLogsLog},
+    program::NixError,
+    require,
+    state::{MarketFixed, DEBUG_LOG_ALL},
+    validation::loaders::SetDebugLogsContext,
+};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct SetDebugLogsParams {
+    /// DEBUG_LOG_* bits of the diagnostic logs to emit, zero for none. They
+    /// cost compute on every order, so only turn them on while debugging.
+    pub debug_log_flags: u8,
+}
+
+pub(crate) fn process_set_debug_logs(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let params: SetDebugLogsParams = SetDebugLogsParams::try_from_slice(data)?;
+    process_set_debug_logs_core(program_id, accounts, params)
+}
+
+pub(crate) fn process_set_debug_logs_core(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    params: SetDebugLogsParams,
+) -> ProgramResult {
+    trace!("process_set_debug_logs accts={accounts:?}");
+    let SetDebugLogsContext { admin, market } = SetDebugLogsContext::load(accounts)?;
+
+    let SetDebugLogsParams { debug_log_flags } = params;
+    require!(
+        debug_log_flags & !DEBUG_LOG_ALL == 0,
+        NixError::InvalidMarketParameters,
+        "Unknown debug log flags {:#04x}",
+        debug_log_flags,
+    )?;
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    let market_fixed: &mut MarketFixed = get_mut_helper::<MarketFixed>(market_data, 0_u32);
+    market_fixed.set_debug_log_flags(debug_log_flags);
+
+    emit_stack(SetDebugLogsLog {
+        market: *market.key,
+        admin: *admin.key,
+        debug_log_flags,
+        _padding: [0; 7],
+    })
+}
@@ -0,0 +1,64 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::{get_mut_helper, trace};
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, SetInsuranceFeeLog},
+    program::NixError,
+    require,
+    state::{MarketFixed, MarketInsurance},
+    validation::loaders::SetInsuranceFeeContext,
+};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct SetInsuranceFeeParams {
+    pub insurance_fee_bps: u16,
+}
+
+pub(crate) fn process_set_insurance_fee(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let params: SetInsuranceFeeParams = SetInsuranceFeeParams::try_from_slice(data)?;
+    process_set_insurance_fee_core(program_id, accounts, params)
+}
+
+/// Fees charged before the change are split at the old rate first.
+pub(crate) fn process_set_insurance_fee_core(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    params: SetInsuranceFeeParams,
+) -> ProgramResult {
+    trace!("process_set_insurance_fee accs={accounts:?}");
+    let SetInsuranceFeeContext {
+        admin,
+        market,
+        market_insurance,
+    } = SetInsuranceFeeContext::load(accounts)?;
+    require!(
+        params.insurance_fee_bps <= 10_000,
+        NixError::InvalidMarketParameters,
+        "Insurance fee {} bps is more than the whole protocol fee",
+        params.insurance_fee_bps,
+    )?;
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    let market_fixed: &mut MarketFixed = get_mut_helper::<MarketFixed>(market_data, 0_u32);
+    let market_insurance_data: &mut RefMut<&mut [u8]> =
+        &mut market_insurance.try_borrow_mut_data()?;
+    let market_insurance_fixed: &mut MarketInsurance =
+        get_mut_helper::<MarketInsurance>(market_insurance_data, 0_u32);
+    market_fixed.accrue_insurance_fees(market_insurance_fixed)?;
+    market_insurance_fixed.set_insurance_fee_bps(params.insurance_fee_bps);
+
+    emit_stack(SetInsuranceFeeLog {
+        market: *market.key,
+        admin: *admin.key,
+        insurance_fee_bps: params.insurance_fee_bps,
+        _padding: [0; 6],
+    })
+}
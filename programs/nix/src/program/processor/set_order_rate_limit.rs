@@ -0,0 +1,60 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::{get_mut_helper, trace};
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, SetOrderRateLimitLog},
+    program::NixError,
+    require,
+    state::MarketFixed,
+    validation::loaders::SetOrderRateLimitContext,
+};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct SetOrderRateLimitParams {
+    /// Max new resting orders a seat can place per window. Zero turns the
+    /// limit off.
+    pub max_orders_per_window: u16,
+    /// Length of the window in slots.
+    pub order_window_slots: u32,
+}
+
+pub(crate) fn process_set_order_rate_limit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let params: SetOrderRateLimitParams = SetOrderRateLimitParams::try_from_slice(data)?;
+    process_set_order_rate_limit_core(program_id, accounts, params)
+}
+
+pub(crate) fn process_set_order_rate_limit_core(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    params: SetOrderRateLimitParams,
+) -> ProgramResult {
+    trace!("process_set_order_rate_limit accts={accounts:?}");
+    let SetOrderRateLimitContext { admin, market } = SetOrderRateLimitContext::load(accounts)?;
+
+    // A limit over an empty window would never count an order.
+    require!(
+        params.max_orders_per_window == 0 || params.order_window_slots > 0,
+        NixError::InvalidMarketParameters,
+        "Order window must be at least one slot",
+    )?;
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    let market_fixed: &mut MarketFixed = get_mut_helper::<MarketFixed>(market_data, 0_u32);
+    market_fixed.set_order_rate_limit(params.max_orders_per_window, params.order_window_slots);
+
+    emit_stack(SetOrderRateLimitLog {
+        market: *market.key,
+        admin: *admin.key,
+        order_window_slots: params.order_window_slots,
+        max_orders_per_window: params.max_orders_per_window,
+        _padding: [0; 2],
+    })
+}
@@ -0,0 +1,70 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hypertree::trace;
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, SettleLoanProceedsLog},
+    state::{ActiveLoan, MarketLoansRefMut, MarketRefMut},
+    validation::loaders::SettleLoanProceedsContext,
+};
+
+use super::get_mut_dynamic_account;
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct SettleLoanProceedsParams {
+    pub loan_sequence_number: u64,
+}
+
+pub(crate) fn process_settle_loan_proceeds(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let params: SettleLoanProceedsParams = SettleLoanProceedsParams::try_from_slice(data)?;
+    process_settle_loan_proceeds_core(program_id, accounts, params)
+}
+
+/// Closes out a repaid loan. What it owes each side goes to their seats, so
+/// it can be withdrawn or traded again. Permissionless, the balances only go
+/// to the lender and borrower.
+pub(crate) fn process_settle_loan_proceeds_core(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    params: SettleLoanProceedsParams,
+) -> ProgramResult {
+    trace!("process_settle_loan_proceeds accts={accounts:?}");
+    let SettleLoanProceedsParams {
+        loan_sequence_number,
+    } = params;
+    let SettleLoanProceedsContext {
+        market,
+        market_loans,
+        ..
+    } = SettleLoanProceedsContext::load(accounts)?;
+
+    let loan: ActiveLoan = {
+        let market_loans_data: &mut RefMut<&mut [u8]> = &mut market_loans.try_borrow_mut_data()?;
+        let mut market_loans_dynamic_account: MarketLoansRefMut =
+            get_mut_dynamic_account(market_loans_data);
+        market_loans_dynamic_account.take_repaid_loan(loan_sequence_number)?
+    };
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    let mut dynamic_account: MarketRefMut = get_mut_dynamic_account(market_data);
+    dynamic_account.settle_loan_proceeds(&loan)?;
+
+    emit_stack(SettleLoanProceedsLog {
+        market: *market.key,
+        market_loans: *market_loans.key,
+        lender: *dynamic_account.get_trader_key_by_index(loan.lender_index),
+        borrower: *dynamic_account.get_trader_key_by_index(loan.borrower_index),
+        loan_sequence_number,
+        lender_shares: loan.liability_shares,
+        borrower_shares: loan.collateral_shares,
+        is_liability_base_a: loan.is_liability_base_a,
+        _padding: [0; 7],
+    })
+}
@@ -1,8 +1,8 @@
 use bytemuck::Pod;
-use hypertree::{get_helper, get_mut_helper, DataIndex, Get, RBNode};
+use hypertree::{get_helper, get_mut_helper, DataIndex, Get, PodBool, RBNode};
 use solana_program::{
     account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction,
-    program_error::ProgramError, sysvar::Sysvar,
+    program_error::ProgramError, pubkey::Pubkey, sysvar::Sysvar,
 };
 use std::{
     cell::{Ref, RefMut},
@@ -10,11 +10,14 @@ use std::{
 };
 
 use crate::{
+    logs::{emit_stack, ExpiredOrdersSweptLog, MarketStatsLog},
+    program::NixError,
     require,
     state::{
-        market_loan::MarketLoansFixed, ClaimedSeat, DynamicAccount, GlobalFixed, MarketDataTreeNodeType, MarketFixed, MarketRefMut, GLOBAL_BLOCK_SIZE, MARKET_BLOCK_SIZE, MARKET_LOAN_BLOCK_SIZE
+        market_loan::MarketLoansFixed, ClaimedSeat, DynamicAccount, EventQueueFixed, EventQueueRefMut, GlobalFixed, MarketStats, MarketDataTreeNodeType, MarketEvent, MarketFixed, MarketRefMut, GLOBAL_BLOCK_SIZE, MARKET_BLOCK_SIZE, MARKET_EVENT_SIZE, MARKET_LOAN_BLOCK_SIZE
     },
-    validation::{NixAccount, NixAccountInfo, Signer},
+    utils::{get_now_slot, get_now_unix_timestamp},
+    validation::{get_market_stats_address, NixAccount, NixAccountInfo, Signer},
 };
 pub(crate) fn expand_market_loans_if_needed<'a, 'info>(
     payer: &'a AccountInfo<'info>,
@@ -44,6 +47,24 @@ pub(crate) fn expand_market_loans<'a, 'info, T: NixAccount + Pod + Clone>(
     Ok(())
 }
 
+/// Grows the event queue by room for `capacity` events. The ring buffer reads
+/// its capacity from the account length, so this is all that is needed.
+pub(crate) fn expand_event_queue<'a, 'info>(
+    payer: &'a AccountInfo<'info>,
+    event_queue: &'a AccountInfo<'info>,
+    capacity: u32,
+) -> ProgramResult {
+    expand_dynamic(payer, event_queue, capacity as usize * MARKET_EVENT_SIZE)
+}
+
+/// Makes room for one more market key on a registry.
+pub(crate) fn expand_market_registry<'a, 'info>(
+    payer: &'a AccountInfo<'info>,
+    market_registry: &'a AccountInfo<'info>,
+) -> ProgramResult {
+    expand_dynamic(payer, market_registry, size_of::<Pubkey>())
+}
+
 pub(crate) fn expand_market_if_needed<'a, 'info>(
     payer: &'a AccountInfo<'info>,
     market_account_info: &'a AccountInfo<'info>,
@@ -68,7 +89,7 @@ pub(crate) fn expand_market<'a, 'info, T: Clone>(
     Ok(())
 }
 
-fn expand_dynamic<'a, 'info>(
+pub(crate) fn expand_dynamic<'a, 'info>(
     payer: &'a AccountInfo<'info>,
     expandable_account: &'a AccountInfo<'info>,
     block_size: usize,
@@ -112,6 +133,15 @@ fn expand_market_fixed(expandable_account: &AccountInfo) -> ProgramResult {
     dynamic_account.market_expand()?;
     Ok(())
 }
+/// Generic get dynamic account from the data bytes of the account.
+pub fn get_dynamic_account<'a, T: Get>(data: &'a [u8]) -> DynamicAccount<&'a T, &'a [u8]> {
+    let (fixed_data, dynamic) = data.split_at(size_of::<T>());
+    let fixed: &T = get_helper::<T>(fixed_data, 0_u32);
+
+    let dynamic_account: DynamicAccount<&'a T, &'a [u8]> = DynamicAccount { fixed, dynamic };
+    dynamic_account
+}
+
 /// Generic get mutable dynamic account from the data bytes of the account.
 pub fn get_mut_dynamic_account<'a, T: Get>(
     data: &'a mut RefMut<'_, &mut [u8]>,
@@ -124,6 +154,152 @@ pub fn get_mut_dynamic_account<'a, T: Get>(
     dynamic_account
 }
 
+/// Pushes events onto the market's event queue. The queue is found among the
+/// instruction accounts by the key stored on the market, so callers can append
+/// it after their regular accounts. Does nothing for markets without a queue.
+pub(crate) fn push_market_events(
+    market_fixed: &MarketFixed,
+    market_key: &Pubkey,
+    accounts: &[AccountInfo],
+    events: &[MarketEvent],
+) -> ProgramResult {
+    if !market_fixed.has_event_queue() || events.is_empty() {
+        return Ok(());
+    }
+    let event_queue_key: &Pubkey = market_fixed.get_event_queue();
+    let event_queue_info: Option<&AccountInfo> =
+        accounts.iter().find(|account| account.key == event_queue_key);
+    require!(
+        event_queue_info.is_some(),
+        NixError::MissingEventQueue,
+        "Missing event queue {}",
+        event_queue_key,
+    )?;
+    let event_queue: NixAccountInfo<EventQueueFixed> =
+        NixAccountInfo::<EventQueueFixed>::new(event_queue_info.unwrap())?;
+    require!(
+        event_queue.get_fixed()?.market == *market_key,
+        NixError::IncorrectAccount,
+        "Event queue {} does not belong to market {}",
+        event_queue_key,
+        market_key,
+    )?;
+
+    let event_queue_data: &mut RefMut<&mut [u8]> = &mut event_queue.try_borrow_mut_data()?;
+    let mut dynamic_account: EventQueueRefMut = get_mut_dynamic_account(event_queue_data);
+    for event in events {
+        dynamic_account.push_event(*event)?;
+    }
+    Ok(())
+}
+
+/// Adds a match to the market stats when the stats PDA of the market is
+/// among the accounts. Markets without one, or callers that leave it out,
+/// skip this.
+pub(crate) fn record_market_stats(
+    market_key: &Pubkey,
+    accounts: &[AccountInfo],
+    use_a_tree: bool,
+    base_atoms: u64,
+    now_slot: u32,
+) -> ProgramResult {
+    if base_atoms == 0 {
+        return Ok(());
+    }
+    let (market_stats_key, _bump) = get_market_stats_address(market_key);
+    let Some(market_stats_info) = accounts
+        .iter()
+        .find(|account| *account.key == market_stats_key)
+    else {
+        return Ok(());
+    };
+    let market_stats: NixAccountInfo<MarketStats> =
+        NixAccountInfo::<MarketStats>::new(market_stats_info)?;
+
+    let (rolling_volume_atoms, open_interest_atoms) = {
+        let mut market_stats_data: RefMut<&mut [u8]> = market_stats.try_borrow_mut_data()?;
+        let market_stats_fixed: &mut MarketStats =
+            get_mut_helper::<MarketStats>(&mut market_stats_data, 0_u32);
+        market_stats_fixed.record_match(use_a_tree, base_atoms, now_slot);
+        (
+            market_stats_fixed.get_rolling_volume_atoms(use_a_tree, now_slot),
+            market_stats_fixed.get_open_interest_atoms(use_a_tree),
+        )
+    };
+    emit_stack(MarketStatsLog {
+        market: *market_key,
+        rolling_volume_atoms,
+        open_interest_atoms,
+        is_base_a_tree: PodBool::from(use_a_tree),
+        _padding: [0; 7],
+    })
+}
+
+/// Removes up to max_orders of the trader's own expired orders on the way
+/// through another instruction, so their collateral does not sit locked
+/// until a taker or cranker gets to them. Logs when anything was removed.
+pub(crate) fn sweep_expired_orders_of_trader(
+    dynamic_account: &mut MarketRefMut,
+    market: &AccountInfo,
+    trader: &AccountInfo,
+    trader_index: DataIndex,
+    max_orders: u8,
+) -> ProgramResult {
+    let (num_removed, gas_deposit_lamports): (u32, u64) = dynamic_account
+        .sweep_expired_orders_of_trader(
+            trader_index,
+            max_orders,
+            get_now_slot(),
+            get_now_unix_timestamp(),
+        )?;
+    if num_removed == 0 {
+        return Ok(());
+    }
+    pay_gas_deposits(market, trader, gas_deposit_lamports)?;
+    emit_stack(ExpiredOrdersSweptLog {
+        market: *market.key,
+        trader: *trader.key,
+        num_removed,
+        _padding: [0; 4],
+    })
+}
+
+/// Moves the gas deposit of a good till time order that rested from the
+/// payer to the market, where it stays until the order leaves the book.
+pub(crate) fn take_gas_deposit<'a, 'info>(
+    payer: &Signer<'a, 'info>,
+    market: &AccountInfo<'info>,
+    gas_deposit_lamports: u64,
+) -> ProgramResult {
+    if gas_deposit_lamports == 0 {
+        return Ok(());
+    }
+    invoke(
+        &solana_program::system_instruction::transfer(
+            payer.info.key,
+            market.key,
+            gas_deposit_lamports,
+        ),
+        &[payer.info.clone(), market.clone()],
+    )
+}
+
+/// Pays gas deposits of good till time orders that left the book from the
+/// market to whoever removed them. The market is owned by the program, so
+/// its lamports move without a CPI.
+pub(crate) fn pay_gas_deposits(
+    market: &AccountInfo,
+    receiver: &AccountInfo,
+    gas_deposit_lamports: u64,
+) -> ProgramResult {
+    if gas_deposit_lamports == 0 {
+        return Ok(());
+    }
+    **market.lamports.borrow_mut() -= gas_deposit_lamports;
+    **receiver.lamports.borrow_mut() += gas_deposit_lamports;
+    Ok(())
+}
+
 pub fn invoke(ix: &Instruction, account_infos: &[AccountInfo<'_>]) -> ProgramResult {
     #[cfg(target_os = "solana")]
     {
@@ -0,0 +1,74 @@
+use std::cell::RefMut;
+
+use hypertree::{trace, DataIndex};
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey, rent::Rent,
+    sysvar::Sysvar,
+};
+
+use crate::{
+    logs::{emit_stack, SweepStrandedGasLog},
+    program::NixError,
+    require,
+    state::{GlobalRefMut, MarketRefMut},
+    validation::loaders::SweepStrandedGasContext,
+};
+
+use super::get_mut_dynamic_account;
+
+/// Returns gas prepayments that are stuck on a global account because their
+/// orders were removed from the market without it. Only prepayments both
+/// recorded on the trader's seat and still counted on the global are paid
+/// out. Permissionless, the lamports can only go to the trader.
+pub(crate) fn process_sweep_stranded_gas(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    _data: &[u8],
+) -> ProgramResult {
+    trace!("process_sweep_stranded_gas accts={accounts:?}");
+    let sweep_stranded_gas_context: SweepStrandedGasContext =
+        SweepStrandedGasContext::load(accounts)?;
+    let SweepStrandedGasContext {
+        trader,
+        market,
+        global,
+        is_base_a,
+    } = sweep_stranded_gas_context;
+    let rent_exempt_lamports: u64 = Rent::get()?.minimum_balance(global.data_len());
+
+    let global_data: &mut RefMut<&mut [u8]> = &mut global.try_borrow_mut_data()?;
+    let mut global_dynamic_account: GlobalRefMut = get_mut_dynamic_account(global_data);
+    let num_gas_prepayments: u32 = global_dynamic_account.get_num_gas_prepayments(trader.key);
+
+    let num_swept: u32 = {
+        let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+        let mut dynamic_account: MarketRefMut = get_mut_dynamic_account(market_data);
+        let trader_index: DataIndex = dynamic_account.get_trader_index(trader.key);
+        dynamic_account.take_stranded_gas_prepayments(
+            trader_index,
+            is_base_a,
+            num_gas_prepayments,
+        )?
+    };
+    global_dynamic_account.release_gas_prepayments(trader.key, num_swept);
+
+    let lamports: u64 = num_swept as u64 * global_dynamic_account.fixed.get_gas_deposit_lamports();
+    require!(
+        global.lamports().saturating_sub(lamports) >= rent_exempt_lamports,
+        NixError::GlobalInsufficient,
+        "Global {} cannot pay out {} lamports and stay rent exempt",
+        global.key,
+        lamports,
+    )?;
+    **global.lamports.borrow_mut() -= lamports;
+    **trader.lamports.borrow_mut() += lamports;
+
+    emit_stack(SweepStrandedGasLog {
+        market: *market.key,
+        global: *global.key,
+        trader: *trader.key,
+        lamports,
+    })?;
+
+    Ok(())
+}
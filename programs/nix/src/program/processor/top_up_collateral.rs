@@ -0,0 +1,137 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use fixed::types::I80F48;
+use hypertree::{trace, DataIndex, PodBool};
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, TopUpCollateralLog},
+    program::NixError,
+    require,
+    state::{
+        should_update_base_a, ActiveLoan, MarketFixed, MarketLoansFixed, MarketLoansRefMut,
+        MarketRefMut,
+    },
+    validation::{loaders::DepositContext, NixAccountInfo},
+};
+
+use super::{deposit::deposit_to_marginfi, get_mut_dynamic_account, get_trader_index_with_hint};
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct TopUpCollateralParams {
+    pub amount: u64,
+    pub trader_index_hint: Option<DataIndex>,
+    /// Resting bid to top up, or the loan when market_loans is set.
+    pub sequence_number: u64,
+    /// Tree of the resting bid. Ignored for loans.
+    pub use_a_tree: bool,
+    /// Loans page holding the loan, appended writable after the Deposit
+    /// accounts. None to top up a resting bid.
+    pub market_loans: Option<Pubkey>,
+}
+
+pub(crate) fn process_top_up_collateral(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let params: TopUpCollateralParams = TopUpCollateralParams::try_from_slice(data)?;
+    process_top_up_collateral_core(program_id, accounts, params)
+}
+
+/// Adds margin to a borrow that is getting close to its LTV buffer. The
+/// tokens are deposited like Deposit, but the shares go into the collateral
+/// of the order or loan instead of the seat.
+pub(crate) fn process_top_up_collateral_core(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    params: TopUpCollateralParams,
+) -> ProgramResult {
+    trace!("process_top_up_collateral accts={accounts:?}");
+    let TopUpCollateralParams {
+        amount,
+        trader_index_hint,
+        sequence_number,
+        use_a_tree,
+        market_loans,
+    } = params;
+
+    let deposit_context: DepositContext = DepositContext::load(accounts)?;
+    let market: &NixAccountInfo<MarketFixed> = &deposit_context.market;
+
+    let market_loans_opt: Option<NixAccountInfo<MarketLoansFixed>> = match market_loans {
+        None => None,
+        Some(market_loans_key) => {
+            let market_loans_info_opt: Option<&AccountInfo> = accounts
+                .iter()
+                .find(|account| *account.key == market_loans_key);
+            require!(
+                market_loans_info_opt.is_some(),
+                NixError::MissingAccounts,
+                "Missing market loans {}",
+                market_loans_key,
+            )?;
+            let market_loans: NixAccountInfo<MarketLoansFixed> =
+                NixAccountInfo::<MarketLoansFixed>::new(market_loans_info_opt.unwrap())?;
+            let market_loans_market: Pubkey = market_loans.get_fixed()?.market;
+            require!(
+                market_loans_market == *market.key,
+                NixError::IncorrectAccount,
+                "Market loans belong to market {}, expected {}",
+                market_loans_market,
+                market.key,
+            )?;
+            Some(market_loans)
+        }
+    };
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    let mut dynamic_account: MarketRefMut = get_mut_dynamic_account(market_data);
+    let trader_index: DataIndex =
+        get_trader_index_with_hint(trader_index_hint, &dynamic_account, &deposit_context.payer)?;
+
+    let (collateral_shares, is_base_a): (I80F48, bool) =
+        deposit_to_marginfi(&deposit_context, dynamic_account.fixed, amount)?;
+
+    if let Some(market_loans) = &market_loans_opt {
+        let market_loans_data: &mut RefMut<&mut [u8]> = &mut market_loans.try_borrow_mut_data()?;
+        let mut market_loans_dynamic_account: MarketLoansRefMut =
+            get_mut_dynamic_account(market_loans_data);
+        let loan: ActiveLoan = market_loans_dynamic_account.top_up_collateral(
+            sequence_number,
+            trader_index,
+            collateral_shares.into(),
+        )?;
+        // Collateral of a loan is in the mint it did not lend.
+        require!(
+            is_base_a != (loan.is_liability_base_a.0 == 1),
+            NixError::InvalidTopUp,
+            "Collateral of loan {} is in the other mint",
+            sequence_number,
+        )?;
+    } else {
+        require!(
+            is_base_a == should_update_base_a(use_a_tree, true),
+            NixError::InvalidTopUp,
+            "Collateral of order {} is in the other mint",
+            sequence_number,
+        )?;
+        dynamic_account.top_up_order_collateral(
+            use_a_tree,
+            trader_index,
+            sequence_number,
+            collateral_shares.into(),
+        )?;
+    }
+
+    emit_stack(TopUpCollateralLog {
+        market: *market.key,
+        trader: *deposit_context.payer.key,
+        sequence_number,
+        collateral_shares: collateral_shares.into(),
+        is_loan: PodBool::from(market_loans_opt.is_some()),
+        _padding: [0; 7],
+    })
+}
@@ -0,0 +1,80 @@
+use std::cell::RefMut;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use fixed::types::I80F48;
+use hypertree::{get_mut_helper, trace, PodBool};
+use shank::ShankType;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+use crate::{
+    logs::{emit_stack, TopUpInsuranceLog},
+    program::NixError,
+    require,
+    state::{MarketFixed, MarketInsurance},
+    validation::{get_market_insurance_address, loaders::DepositContext, NixAccountInfo},
+};
+
+use super::deposit::deposit_to_marginfi;
+
+#[derive(BorshDeserialize, BorshSerialize, ShankType)]
+pub struct TopUpInsuranceParams {
+    pub amount: u64,
+}
+
+pub(crate) fn process_top_up_insurance(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let params: TopUpInsuranceParams = TopUpInsuranceParams::try_from_slice(data)?;
+    process_top_up_insurance_core(program_id, accounts, params)
+}
+
+/// Adds to the insurance fund of a market. The tokens are deposited like
+/// Deposit, but the shares go to the fund instead of a seat, so anyone can
+/// back the lenders of a market without a seat on it.
+pub(crate) fn process_top_up_insurance_core(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    params: TopUpInsuranceParams,
+) -> ProgramResult {
+    trace!("process_top_up_insurance accts={accounts:?}");
+    let TopUpInsuranceParams { amount } = params;
+
+    let deposit_context: DepositContext = DepositContext::load(accounts)?;
+    let market: &NixAccountInfo<MarketFixed> = &deposit_context.market;
+
+    let (market_insurance_key, _bump) = get_market_insurance_address(market.key);
+    let market_insurance_info_opt: Option<&AccountInfo> = accounts
+        .iter()
+        .find(|account| *account.key == market_insurance_key);
+    require!(
+        market_insurance_info_opt.is_some(),
+        NixError::MissingAccounts,
+        "Missing market insurance {}",
+        market_insurance_key,
+    )?;
+    let market_insurance: NixAccountInfo<MarketInsurance> =
+        NixAccountInfo::<MarketInsurance>::new(market_insurance_info_opt.unwrap())?;
+
+    let market_data: &mut RefMut<&mut [u8]> = &mut market.try_borrow_mut_data()?;
+    let market_fixed: &mut MarketFixed = get_mut_helper::<MarketFixed>(market_data, 0_u32);
+
+    let (shares, is_base_a): (I80F48, bool) =
+        deposit_to_marginfi(&deposit_context, market_fixed, amount)?;
+
+    let market_insurance_data: &mut RefMut<&mut [u8]> =
+        &mut market_insurance.try_borrow_mut_data()?;
+    let market_insurance_fixed: &mut MarketInsurance =
+        get_mut_helper::<MarketInsurance>(market_insurance_data, 0_u32);
+    market_fixed.accrue_insurance_fees(market_insurance_fixed)?;
+    market_insurance_fixed.deposit(is_base_a, shares)?;
+
+    emit_stack(TopUpInsuranceLog {
+        market: *market.key,
+        payer: *deposit_context.payer.key,
+        shares: shares.into(),
+        is_base_a: PodBool::from(is_base_a),
+        _padding: [0; 7],
+    })
+}
@@ -1,17 +1,17 @@
-use std::fmt::Display;
+use std::{cmp::Ordering, fmt::Display};
 
 use borsh::{BorshDeserialize as Deserialize, BorshSerialize as Serialize};
 use bytemuck::{Pod, Zeroable};
 use fixed::types::I80F48;
-use shank::ShankAccount;
+use shank::{ShankAccount, ShankType};
+
+use crate::{program::NixError, require, state::MAX_RATE_BPS};
 
 #[derive(
     Default,
     Debug,
     Clone,
     Copy,
-    PartialOrd,
-    Ord,
     Zeroable,
     Pod,
     Deserialize,
@@ -43,6 +43,48 @@ impl WrappedI80F48 {
         let rhs: I80F48 = rhs.into();
         lhs.checked_sub(rhs).map(WrappedI80F48::from)
     }
+
+    pub fn saturating_add<T>(&self, rhs: T) -> WrappedI80F48
+    where
+        T: Into<I80F48>,
+    {
+        let lhs: I80F48 = (*self).into();
+        WrappedI80F48::from(lhs.saturating_add(rhs.into()))
+    }
+
+    pub fn saturating_sub<T>(&self, rhs: T) -> WrappedI80F48
+    where
+        T: Into<I80F48>,
+    {
+        let lhs: I80F48 = (*self).into();
+        WrappedI80F48::from(lhs.saturating_sub(rhs.into()))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == Self::ZERO
+    }
+
+    pub fn is_negative(&self) -> bool {
+        I80F48::from(*self).is_negative()
+    }
+
+    pub fn is_positive(&self) -> bool {
+        I80F48::from(*self).is_positive()
+    }
+}
+
+// The bytes are little endian two's complement, so ordering has to go through
+// the fixed point value rather than comparing the raw arrays.
+impl Ord for WrappedI80F48 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        I80F48::from(*self).cmp(&I80F48::from(*other))
+    }
+}
+
+impl PartialOrd for WrappedI80F48 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 impl From<I80F48> for WrappedI80F48 {
@@ -85,4 +127,223 @@ impl Display for WrappedI80F48 {
         let i: I80F48 = (*self).into();
         write!(f, "{}", i)
     }
-}
\ No newline at end of file
+}
+
+/// Atoms of a mint, typed by the side of the market they are on so base and
+/// quote sizes cannot be swapped. Serialized as the bare u64, so params that
+/// switched from u64 keep their layout.
+macro_rules! atoms_type {
+    ($type_name:ident) => {
+        #[derive(
+            Default,
+            Debug,
+            Clone,
+            Copy,
+            PartialEq,
+            Eq,
+            PartialOrd,
+            Ord,
+            Deserialize,
+            Serialize,
+            ShankType,
+        )]
+        pub struct $type_name {
+            inner: u64,
+        }
+
+        impl $type_name {
+            pub const ZERO: Self = Self { inner: 0 };
+
+            pub const fn new(inner: u64) -> Self {
+                Self { inner }
+            }
+
+            pub const fn as_u64(&self) -> u64 {
+                self.inner
+            }
+
+            /// Atoms for a UI amount of a mint with `decimals`, rounded to
+            /// the nearest atom.
+            pub fn from_ui_amount(ui_amount: f64, decimals: u8) -> Result<Self, NixError> {
+                let atoms: f64 = (ui_amount * 10_f64.powi(decimals as i32)).round();
+                require!(
+                    atoms.is_finite() && atoms >= 0.0 && atoms < u64::MAX as f64,
+                    NixError::InvalidQuantity,
+                    "Invalid {} amount {} with {} decimals",
+                    stringify!($type_name),
+                    ui_amount,
+                    decimals,
+                )?;
+                Ok(Self::new(atoms as u64))
+            }
+
+            pub fn to_ui_amount(&self, decimals: u8) -> f64 {
+                self.inner as f64 / 10_f64.powi(decimals as i32)
+            }
+
+            pub fn checked_add(&self, other: Self) -> Option<Self> {
+                self.inner.checked_add(other.inner).map(Self::new)
+            }
+
+            pub fn checked_sub(&self, other: Self) -> Option<Self> {
+                self.inner.checked_sub(other.inner).map(Self::new)
+            }
+        }
+
+        impl From<$type_name> for u64 {
+            fn from(atoms: $type_name) -> Self {
+                atoms.inner
+            }
+        }
+
+        impl Display for $type_name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", self.inner)
+            }
+        }
+    };
+}
+
+atoms_type!(BaseAtoms);
+atoms_type!(QuoteAtoms);
+
+/// Interest rate of an order in basis points. Serialized as the bare u16.
+#[derive(
+    Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, ShankType,
+)]
+pub struct Rate {
+    bps: u16,
+}
+
+impl Rate {
+    pub const fn from_bps(bps: u16) -> Self {
+        Self { bps }
+    }
+
+    /// Rate for a percentage like 5.25, rounded to the nearest basis point.
+    pub fn from_percent(percent: f64) -> Result<Self, NixError> {
+        let bps: f64 = (percent * 100.0).round();
+        require!(
+            bps.is_finite() && bps >= 0.0 && bps <= MAX_RATE_BPS as f64,
+            NixError::InvalidQuantity,
+            "Invalid rate {}%",
+            percent,
+        )?;
+        Ok(Self::from_bps(bps as u16))
+    }
+
+    pub const fn as_bps(&self) -> u16 {
+        self.bps
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.bps <= MAX_RATE_BPS
+    }
+}
+
+impl From<Rate> for u16 {
+    fn from(rate: Rate) -> Self {
+        rate.bps
+    }
+}
+
+impl Display for Rate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}bps", self.bps)
+    }
+}
+
+/// The fields of a marginfi Bank used while matching. Copied out of the bank
+/// account once per instruction so the matching loop does not deserialize the
+/// full Bank or convert share values for every maker order.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct BankShareValues {
+    pub asset_share_value: I80F48,
+    pub liability_share_value: I80F48,
+    pub asset_weight_init: I80F48,
+    pub liability_weight_init: I80F48,
+    pub total_asset_shares: I80F48,
+    pub mint_decimals: u8,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_checked_ops() {
+        let one: WrappedI80F48 = WrappedI80F48::from(1_u64);
+        assert_eq!(one.checked_add(I80F48::ONE), Some(WrappedI80F48::from(2_u64)));
+        assert_eq!(one.checked_sub(I80F48::ONE), Some(WrappedI80F48::ZERO));
+        assert_eq!(WrappedI80F48::from(I80F48::MAX).checked_add(I80F48::ONE), None);
+        assert_eq!(WrappedI80F48::from(I80F48::MIN).checked_sub(I80F48::ONE), None);
+    }
+
+    #[test]
+    fn test_saturating_ops() {
+        assert_eq!(
+            WrappedI80F48::from(I80F48::MAX).saturating_add(I80F48::ONE),
+            WrappedI80F48::from(I80F48::MAX)
+        );
+        assert_eq!(
+            WrappedI80F48::from(I80F48::MIN).saturating_sub(I80F48::ONE),
+            WrappedI80F48::from(I80F48::MIN)
+        );
+    }
+
+    #[test]
+    fn test_ordering() {
+        let negative: WrappedI80F48 = WrappedI80F48::from(I80F48::from_num(-1));
+        let small: WrappedI80F48 = WrappedI80F48::from(1_u64);
+        // 256 has a zero low byte, so a byte-wise comparison would put it
+        // below 1.
+        let large: WrappedI80F48 = WrappedI80F48::from(256_u64);
+        assert!(negative < WrappedI80F48::ZERO);
+        assert!(small < large);
+        assert!(negative < large);
+        assert!(negative.is_negative());
+        assert!(large.is_positive());
+        assert!(WrappedI80F48::ZERO.is_zero());
+    }
+
+    #[test]
+    fn test_from_ui_amount() {
+        assert_eq!(
+            BaseAtoms::from_ui_amount(1.5, 9).unwrap(),
+            BaseAtoms::new(1_500_000_000)
+        );
+        // 0.29 * 100 is just under 29 as a float.
+        assert_eq!(
+            QuoteAtoms::from_ui_amount(0.29, 2).unwrap(),
+            QuoteAtoms::new(29)
+        );
+        assert_eq!(QuoteAtoms::new(1_250_000).to_ui_amount(6), 1.25);
+        assert!(BaseAtoms::from_ui_amount(-1.0, 6).is_err());
+        assert!(BaseAtoms::from_ui_amount(f64::NAN, 6).is_err());
+        assert!(BaseAtoms::from_ui_amount(1e12, 9).is_err());
+    }
+
+    #[test]
+    fn test_rate_from_percent() {
+        assert_eq!(Rate::from_percent(5.25).unwrap(), Rate::from_bps(525));
+        assert_eq!(
+            Rate::from_percent(100.0).unwrap(),
+            Rate::from_bps(MAX_RATE_BPS)
+        );
+        assert!(Rate::from_percent(100.01).is_err());
+        assert!(Rate::from_percent(-0.5).is_err());
+        assert!(!Rate::from_bps(MAX_RATE_BPS + 1).is_valid());
+    }
+
+    #[test]
+    fn test_layout_matches_bare_ints() {
+        assert_eq!(
+            BaseAtoms::new(7).try_to_vec().unwrap(),
+            7_u64.try_to_vec().unwrap()
+        );
+        assert_eq!(
+            Rate::from_bps(7).try_to_vec().unwrap(),
+            7_u16.try_to_vec().unwrap()
+        );
+    }
+}
@@ -0,0 +1,70 @@
+//! Sizes the growable accounts reach and the rent to hold them, so clients
+//! can budget for a market up front instead of guessing.
+
+use solana_program::rent::Rent;
+
+use super::{
+    EVENT_QUEUE_FIXED_SIZE, MARKET_BLOCK_SIZE, MARKET_EVENT_SIZE, MARKET_FIXED_SIZE,
+    MARKET_LOANS_FIXED_SIZE, MARKET_LOAN_BLOCK_SIZE,
+};
+
+/// Size of a market holding num_seats seats and num_orders resting orders.
+/// Each takes a block, and the program keeps one more free block ahead.
+pub fn get_market_account_size(num_seats: u32, num_orders: u32) -> usize {
+    let num_blocks: usize = num_seats as usize + num_orders as usize + 1;
+    MARKET_FIXED_SIZE + num_blocks * MARKET_BLOCK_SIZE
+}
+
+/// Size of a market loans page holding num_loans active loans, including the
+/// free block it is created with.
+pub fn get_market_loans_account_size(num_loans: u32) -> usize {
+    MARKET_LOANS_FIXED_SIZE + (num_loans as usize + 1) * MARKET_LOAN_BLOCK_SIZE
+}
+
+/// Size of an event queue with room for capacity events.
+pub fn get_event_queue_account_size(capacity: u32) -> usize {
+    EVENT_QUEUE_FIXED_SIZE + capacity as usize * MARKET_EVENT_SIZE
+}
+
+/// Lamports an account of size bytes needs to be rent exempt at the default
+/// rent. Growing an account tops it up to this, so it is also the total paid
+/// for an account by the time it reaches size.
+pub fn get_rent_exempt_lamports(size: usize) -> u64 {
+    Rent::default().minimum_balance(size)
+}
+
+/// Lamports the payers of later instructions put in to grow an account from
+/// current_size to target_size.
+pub fn get_expansion_rent_lamports(current_size: usize, target_size: usize) -> u64 {
+    get_rent_exempt_lamports(target_size).saturating_sub(get_rent_exempt_lamports(current_size))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_market_account_size() {
+        // A new market already has its spare block.
+        assert_eq!(
+            get_market_account_size(0, 0),
+            MARKET_FIXED_SIZE + MARKET_BLOCK_SIZE
+        );
+        assert_eq!(
+            get_market_account_size(2, 3) - get_market_account_size(0, 0),
+            5 * MARKET_BLOCK_SIZE
+        );
+    }
+
+    #[test]
+    fn test_expansion_rent() {
+        let empty_size: usize = get_market_account_size(0, 0);
+        let full_size: usize = get_market_account_size(10, 100);
+        assert_eq!(
+            get_rent_exempt_lamports(empty_size)
+                + get_expansion_rent_lamports(empty_size, full_size),
+            get_rent_exempt_lamports(full_size)
+        );
+        assert_eq!(get_expansion_rent_lamports(full_size, empty_size), 0);
+    }
+}
@@ -20,19 +20,65 @@ pub struct ClaimedSeat {
     // rounding.
     pub base_a_withdrawable_asset_share: WrappedI80F48,
     pub base_b_withdrawable_asset_share: WrappedI80F48,
-    /// volumes traded over lifetime, can overflow. Double counts self
-    /// trades. This is for informational and monitoring purposes only. This is
+    /// volumes traded over lifetime, saturating at the max. Double counts
+    /// self trades. This is for informational and monitoring purposes only. This is
     /// not guaranteed to be maintained. It does not secure any value in
     /// nix. Use at your own risk.
     pub base_a_volume: WrappedI80F48,
     pub base_b_volume: WrappedI80F48,
+    /// Referral fees earned on fills by referred takers, waiting to be moved
+    /// into the withdrawable balance with ReferrerClaim.
+    pub base_a_referral_fee_shares: WrappedI80F48,
+    pub base_b_referral_fee_shares: WrappedI80F48,
+    /// Maker rebates earned on resting orders that got filled, waiting to be
+    /// moved into the withdrawable balance with ClaimMakerRebate.
+    pub base_a_maker_rebate_shares: WrappedI80F48,
+    pub base_b_maker_rebate_shares: WrappedI80F48,
+    /// Liability borrowed by this seat on each base, from taker fills and
+    /// resting bids, in marginfi liability shares. A bid that leaves the
+    /// book becomes a loan that keeps it, it goes down when a loan of the
+    /// seat is settled or written off.
+    pub base_a_liability_shares: WrappedI80F48,
+    pub base_b_liability_shares: WrappedI80F48,
+    /// Number of orders this seat currently has resting on either tree.
+    pub num_open_orders: u16,
+    /// New resting orders placed since order_window_start_slot, counted
+    /// against the order rate limit of the market. Takes the high half of
+    /// what was a u32 num_open_orders, which a market account can never
+    /// hold enough blocks to reach.
+    pub orders_in_window: u16,
+    /// Gas prepayments of this seat's global orders that were removed
+    /// without the global account, so they are still held there. Returned
+    /// with SweepStrandedGas.
+    pub base_a_stranded_gas_prepayments: u32,
+    pub base_b_stranded_gas_prepayments: u32,
+    /// Slot of the first new resting order of the current order window.
+    pub order_window_start_slot: u32,
+    /// Base atoms times slots that this seat's orders rested on each tree,
+    /// saturating at the max. Accrued when an order is filled, reduced or
+    /// canceled, for off chain market maker incentives.
+    pub base_a_time_on_book: u64,
+    pub base_b_time_on_book: u64,
 }
 // 32 + // trader
-//  8 + // base_asset_share
-//  8 + // quote_asset_share
-//  8 + // quote_volume
-//  8   // padding
-// = 64
+// 16 + // base_a_withdrawable_asset_share
+// 16 + // base_b_withdrawable_asset_share
+// 16 + // base_a_volume
+// 16 + // base_b_volume
+// 16 + // base_a_referral_fee_shares
+// 16 + // base_b_referral_fee_shares
+// 16 + // base_a_maker_rebate_shares
+// 16 + // base_b_maker_rebate_shares
+// 16 + // base_a_liability_shares
+// 16 + // base_b_liability_shares
+//  2 + // num_open_orders
+//  2 + // orders_in_window
+//  4 + // base_a_stranded_gas_prepayments
+//  4 + // base_b_stranded_gas_prepayments
+//  4 + // order_window_start_slot
+//  8 + // base_a_time_on_book
+//  8   // base_b_time_on_book
+// = 224
 const_assert_eq!(size_of::<ClaimedSeat>(), CLAIMED_SEAT_SIZE);
 const_assert_eq!(size_of::<ClaimedSeat>() % 8, 0);
 
@@ -43,6 +89,106 @@ impl ClaimedSeat {
             ..Default::default()
         }
     }
+
+    pub fn get_withdrawable_asset_share(&self, is_base_a: bool) -> WrappedI80F48 {
+        if is_base_a {
+            self.base_a_withdrawable_asset_share
+        } else {
+            self.base_b_withdrawable_asset_share
+        }
+    }
+
+    pub fn get_volume(&self, is_base_a: bool) -> WrappedI80F48 {
+        if is_base_a {
+            self.base_a_volume
+        } else {
+            self.base_b_volume
+        }
+    }
+
+    pub fn get_liability_shares(&self, is_base_a: bool) -> WrappedI80F48 {
+        if is_base_a {
+            self.base_a_liability_shares
+        } else {
+            self.base_b_liability_shares
+        }
+    }
+
+    pub fn get_num_open_orders(&self) -> u32 {
+        self.num_open_orders as u32
+    }
+
+    /// Counts a new resting order against a limit of max_orders per
+    /// window_slots. The window restarts with the first order placed
+    /// window_slots or more after the one that opened it. Returns false,
+    /// without counting it, when the window is already full.
+    pub fn try_count_new_order(
+        &mut self,
+        max_orders: u16,
+        window_slots: u32,
+        now_slot: u32,
+    ) -> bool {
+        if now_slot.saturating_sub(self.order_window_start_slot) >= window_slots {
+            self.order_window_start_slot = now_slot;
+            self.orders_in_window = 0;
+        }
+        if self.orders_in_window >= max_orders {
+            return false;
+        }
+        self.orders_in_window += 1;
+        true
+    }
+
+    pub fn get_time_on_book(&self, is_base_a: bool) -> u64 {
+        if is_base_a {
+            self.base_a_time_on_book
+        } else {
+            self.base_b_time_on_book
+        }
+    }
+
+    /// Adds base atom slots to the time on book of one tree and returns the
+    /// new total.
+    pub fn record_time_on_book(&mut self, is_base_a: bool, base_atom_slots: u64) -> u64 {
+        let time_on_book: &mut u64 = if is_base_a {
+            &mut self.base_a_time_on_book
+        } else {
+            &mut self.base_b_time_on_book
+        };
+        *time_on_book = time_on_book.saturating_add(base_atom_slots);
+        *time_on_book
+    }
+
+    pub fn get_snapshot(&self) -> SeatSnapshot {
+        SeatSnapshot {
+            trader: self.trader,
+            base_a_withdrawable_asset_share: self.get_withdrawable_asset_share(true),
+            base_b_withdrawable_asset_share: self.get_withdrawable_asset_share(false),
+            base_a_liability_shares: self.get_liability_shares(true),
+            base_b_liability_shares: self.get_liability_shares(false),
+            base_a_volume: self.get_volume(true),
+            base_b_volume: self.get_volume(false),
+            base_a_time_on_book: self.get_time_on_book(true),
+            base_b_time_on_book: self.get_time_on_book(false),
+            num_open_orders: self.get_num_open_orders(),
+        }
+    }
+}
+
+/// Copy of what a client usually wants from a seat, read with
+/// get_seat_snapshot on the market.
+#[derive(Default, Debug, Copy, Clone)]
+pub struct SeatSnapshot {
+    pub trader: Pubkey,
+    pub base_a_withdrawable_asset_share: WrappedI80F48,
+    pub base_b_withdrawable_asset_share: WrappedI80F48,
+    pub base_a_liability_shares: WrappedI80F48,
+    pub base_b_liability_shares: WrappedI80F48,
+    pub base_a_volume: WrappedI80F48,
+    pub base_b_volume: WrappedI80F48,
+    pub base_a_time_on_book: u64,
+    pub base_b_time_on_book: u64,
+    pub num_open_orders: u32,
 }
 
 impl Ord for ClaimedSeat {
@@ -76,3 +222,48 @@ fn test_display() {
     let claimed_seat: ClaimedSeat = ClaimedSeat::new_empty(Pubkey::default());
     assert_eq!(claimed_seat.trader, Pubkey::default());
 }
+
+#[test]
+fn test_snapshot() {
+    use fixed::types::I80F48;
+
+    let mut claimed_seat: ClaimedSeat = ClaimedSeat::new_empty(Pubkey::new_unique());
+    claimed_seat.base_a_withdrawable_asset_share = I80F48::from_num(5).into();
+    claimed_seat.base_b_liability_shares = I80F48::from_num(3).into();
+    claimed_seat.base_b_volume = I80F48::from_num(7).into();
+    claimed_seat.num_open_orders = 2;
+
+    let snapshot: SeatSnapshot = claimed_seat.get_snapshot();
+    assert_eq!(snapshot.trader, claimed_seat.trader);
+    assert_eq!(I80F48::from(snapshot.base_a_withdrawable_asset_share), 5);
+    assert_eq!(I80F48::from(snapshot.base_b_withdrawable_asset_share), 0);
+    assert_eq!(I80F48::from(snapshot.base_a_liability_shares), 0);
+    assert_eq!(I80F48::from(snapshot.base_b_liability_shares), 3);
+    assert_eq!(I80F48::from(snapshot.base_a_volume), 0);
+    assert_eq!(I80F48::from(snapshot.base_b_volume), 7);
+    assert_eq!(snapshot.num_open_orders, 2);
+}
+
+#[test]
+fn test_record_time_on_book() {
+    let mut claimed_seat: ClaimedSeat = ClaimedSeat::new_empty(Pubkey::new_unique());
+    assert_eq!(claimed_seat.record_time_on_book(true, 10), 10);
+    assert_eq!(claimed_seat.record_time_on_book(true, 5), 15);
+    assert_eq!(claimed_seat.record_time_on_book(false, u64::MAX), u64::MAX);
+    assert_eq!(claimed_seat.record_time_on_book(false, 1), u64::MAX);
+    assert_eq!(claimed_seat.get_time_on_book(true), 15);
+}
+
+#[test]
+fn test_try_count_new_order() {
+    let mut claimed_seat: ClaimedSeat = ClaimedSeat::new_empty(Pubkey::new_unique());
+    assert!(claimed_seat.try_count_new_order(2, 10, 100));
+    assert!(claimed_seat.try_count_new_order(2, 10, 105));
+    assert!(!claimed_seat.try_count_new_order(2, 10, 109));
+    assert_eq!(claimed_seat.orders_in_window, 2);
+
+    // The window opened at 100, so it restarts at 110.
+    assert!(claimed_seat.try_count_new_order(2, 10, 110));
+    assert_eq!(claimed_seat.order_window_start_slot, 110);
+    assert_eq!(claimed_seat.orders_in_window, 1);
+}
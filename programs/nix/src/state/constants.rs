@@ -2,16 +2,39 @@ use hypertree::RBTREE_OVERHEAD_BYTES;
 
 
 pub const NO_EXPIRATION_LAST_VALID_SLOT: u32 = 0;
+// Orders can also be good till a unix timestamp, in seconds. Zero for orders
+// that only expire by slot, if at all.
+pub const NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP: u32 = 0;
 
 
-pub const MARKET_FIXED_SIZE: usize = 736;
-pub const GLOBAL_FIXED_SIZE: usize = 96;
-pub const MARKET_LOANS_FIXED_SIZE: usize = 72;
+pub const MARKET_FIXED_SIZE: usize = 816;
+// Layout version of MarketFixed. Bump it whenever a field is carved out of
+// padding and add the mapping from the previous layout to MarketFixed::migrate.
+pub const MARKET_VERSION: u8 = 10;
+// Versions whose header is longer than the one before, with the bytes each
+// added. MigrateMarket grows older markets by what they miss before migrating
+// them.
+pub const MARKET_HEADER_GROWTHS: [(u8, usize); 2] = [(7, 32), (9, 16)];
+// First version whose seats and orders are laid out in blocks of the current
+// MARKET_BLOCK_SIZE. Version 1 markets have 112 byte blocks behind a 736 byte
+// header, versions 2 and 3 have 224 byte blocks. MigrateMarket does not
+// relayout blocks, so it rejects them.
+pub const MARKET_BLOCK_LAYOUT_VERSION: u8 = 4;
+pub const GLOBAL_FIXED_SIZE: usize = 168;
+pub const MARKET_LOANS_FIXED_SIZE: usize = 104;
+pub const EVENT_QUEUE_FIXED_SIZE: usize = 56;
+pub const MARKET_EVENT_SIZE: usize = 128;
+pub const CROSS_MARGIN_SEAT_SIZE: usize = 104;
+pub const MARKET_STATS_SIZE: usize = 536;
+pub const MARKET_REGISTRY_FIXED_SIZE: usize = 80;
+pub const PENDING_ORDER_SIZE: usize = 136;
+pub const MARKET_AUCTION_SIZE: usize = 72;
+pub const MARKET_INSURANCE_SIZE: usize = 112;
 
-// Red black tree overhead is 16 bytes. If each block is 80 bytes, then we get
-// 64 bytes for a RestingOrder or ClaimedSeat.
+// Red black tree overhead is 16 bytes. If each block is 240 bytes, then we get
+// 224 bytes for a RestingOrder or ClaimedSeat.
 pub const GLOBAL_BLOCK_SIZE: usize = 64;
-pub const MARKET_BLOCK_SIZE: usize = 112;
+pub const MARKET_BLOCK_SIZE: usize = 240;
 pub const MARKET_LOAN_BLOCK_SIZE: usize = 96;
 
 const MARKET_BLOCK_PAYLOAD_SIZE: usize = MARKET_BLOCK_SIZE - RBTREE_OVERHEAD_BYTES;
@@ -43,7 +66,19 @@ pub const MARKET_LOAN_FREE_LIST_BLOCK_SIZE: usize = MARKET_LOAN_BLOCK_SIZE - FRE
 // Note that if your seat gets evicted, then all your orders are unbacked and
 // now are free to have their deposits claimed. So there is an incentive to keep
 // capital on the exchange to prevent that.
+//
+// This is the default. Each global account stores its own deposit, picked at
+// GlobalCreate within the bounds below.
 pub const GAS_DEPOSIT_LAMPORTS: u64 = 5_000;
+pub const MIN_GAS_DEPOSIT_LAMPORTS: u64 = 5_000;
+pub const MAX_GAS_DEPOSIT_LAMPORTS: u64 = 1_000_000;
+
+// Good till time orders deposit this on the market account when they rest.
+// Whoever takes the order off the book gets it: the trader on a cancel, the
+// taker on a fill, and once it expired the taker or keeper that removes it.
+// That pays keepers for cleaning up, where slot expiry relies on takers
+// removing expired orders as they reach them.
+pub const GTT_GAS_DEPOSIT_LAMPORTS: u64 = 5_000;
 
 /// Limit on the number of global seats available. Set so that this is hit
 /// before the global account starts running into account size limits, but is
@@ -59,4 +94,44 @@ pub const MAX_GLOBAL_SEATS: u16 = 3000;
 /// Limit on the number of active loans in a market. This is set to a
 /// conservative value to ensure that the market can handle a reasonable number
 /// of active loans without running into account size limits
-pub const MAX_ACTIVE_LOANS: u64 = 5000;
\ No newline at end of file
+pub const MAX_ACTIVE_LOANS: u64 = 5000;
+
+/// Max number of maker orders one order crosses, and so the number of loans
+/// it can create. Matched loans are collected on the stack, so this also caps
+/// how big that buffer gets.
+pub const MAX_MATCHED_LOANS: usize = 16;
+
+/// Max number of a trader's own expired orders Deposit, CancelOrder and
+/// PlaceOrder remove on the way. The scan for them walks the whole book, so
+/// it is opt in and kept small.
+pub const MAX_SWEPT_EXPIRED_ORDERS: u8 = 8;
+
+/// Max number of markets a registry lists for one mint pair. Keeps anyone
+/// from burying the active markets of a pair under copies.
+pub const MAX_REGISTERED_MARKETS: usize = 32;
+
+/// Max number of rate levels per side in a book snapshot log.
+pub const MAX_BOOK_SNAPSHOT_LEVELS: usize = 16;
+
+/// Highest rate an order can be placed at, 100% a year.
+pub const MAX_RATE_BPS: u16 = 10_000;
+
+/// Slots over which the time weighted average rate of a tree mostly forgets
+/// older fills, about 10 minutes.
+pub const RATE_ORACLE_WINDOW_SLOTS: u32 = 1_500;
+/// Precision the time weighted average rate is kept in, per bps.
+pub const RATE_MILLI_BPS: u32 = 1_000;
+/// Fills worth less than this in USD at the oracle price leave the rate
+/// oracle alone, otherwise a single atom could set the prevailing rate.
+pub const RATE_ORACLE_MIN_FILL_USD: u64 = 100;
+
+/// Slots per market stats bucket, about an hour.
+pub const MARKET_STATS_BUCKET_SLOTS: u32 = 9_000;
+/// Buckets in the market stats window, so the window is about 24 hours.
+pub const MARKET_STATS_NUM_BUCKETS: usize = 24;
+
+/// Debug log flag for the seat balance changes of orders, logged with msg!.
+pub const DEBUG_LOG_BALANCES: u8 = 1 << 0;
+/// Debug log flag for the book tree root and best index changes of orders.
+pub const DEBUG_LOG_TREES: u8 = 1 << 1;
+pub const DEBUG_LOG_ALL: u8 = DEBUG_LOG_BALANCES | DEBUG_LOG_TREES;
@@ -0,0 +1,70 @@
+use bytemuck::{Pod, Zeroable};
+use hypertree::Get;
+use shank::ShankType;
+use solana_program::{entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey};
+use static_assertions::const_assert_eq;
+use std::mem::size_of;
+
+use crate::{
+    require,
+    state::CROSS_MARGIN_SEAT_SIZE,
+    utils::{is_discriminant_of, StableName},
+    validation::NixAccount,
+};
+
+/// Opts a seat into cross margin. The trader's own marginfi account is
+/// registered here while the trader is still its authority. Once the
+/// authority is handed to the market signer, bids from the seat also have to
+/// be covered by the health of that account, so collateral the trader holds
+/// there counts next to what the seat posts.
+#[repr(C)]
+#[derive(Default, Copy, Clone, Zeroable, Pod, ShankType)]
+pub struct CrossMarginSeat {
+    /// Discriminant for identifying this account type.
+    pub discriminant: u64,
+    pub market: Pubkey,
+    pub trader: Pubkey,
+    pub marginfi_account: Pubkey,
+}
+
+const_assert_eq!(
+    size_of::<CrossMarginSeat>(),
+    8 +   // discriminant
+    32 +  // market
+    32 +  // trader
+    32 // marginfi_account
+);
+const_assert_eq!(size_of::<CrossMarginSeat>(), CROSS_MARGIN_SEAT_SIZE);
+const_assert_eq!(size_of::<CrossMarginSeat>() % 8, 0);
+
+impl CrossMarginSeat {
+    pub fn new(market: Pubkey, trader: Pubkey, marginfi_account: Pubkey) -> Self {
+        CrossMarginSeat {
+            discriminant: crate::utils::get_discriminant::<CrossMarginSeat>().unwrap(),
+            market,
+            trader,
+            marginfi_account,
+        }
+    }
+}
+
+impl Get for CrossMarginSeat {}
+impl StableName for CrossMarginSeat {
+    const STABLE_NAME: &'static str = "nix::CrossMarginSeat";
+    const LEGACY_NAME: &'static str = "nix::state::cross_margin_seat::CrossMarginSeat";
+}
+impl NixAccount for CrossMarginSeat {
+    fn verify_discriminant(&self) -> ProgramResult {
+        let expected_discriminant: u64 =
+            crate::utils::get_discriminant::<CrossMarginSeat>().unwrap();
+
+        require!(
+            is_discriminant_of::<CrossMarginSeat>(self.discriminant),
+            ProgramError::InvalidAccountData,
+            "Invalid cross margin seat discriminant actual: {} expected: {}",
+            self.discriminant,
+            expected_discriminant
+        )?;
+        Ok(())
+    }
+}
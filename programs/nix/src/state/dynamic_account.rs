@@ -1,3 +1,8 @@
+use bytemuck::Pod;
+use hypertree::{
+    DataIndex, FreeList, HyperTreeReadOperations, HyperTreeWriteOperations, Payload, RedBlackTree,
+};
+
 #[derive(Clone)]
 pub struct DynamicAccount<Fixed, Dynamic> {
     pub fixed: Fixed,
@@ -53,3 +58,92 @@ impl<T: Sized> DerefOrBorrowMut<[T]> for Vec<T> {
         self
     }
 }
+
+/// Fixed header of an account whose dynamic bytes are equal sized blocks
+/// handed out through a free list. Market, global and market loans all work
+/// this way, so the block bookkeeping lives here once.
+pub trait ExpandableFixed {
+    /// Filler that makes a free list node the size of one block.
+    type FreeListPadding: Pod;
+    const BLOCK_SIZE: usize;
+
+    fn get_free_list_head_index(&self) -> DataIndex;
+    fn set_free_list_head_index(&mut self, free_list_head_index: DataIndex);
+    fn get_num_bytes_allocated(&self) -> DataIndex;
+    fn set_num_bytes_allocated(&mut self, num_bytes_allocated: DataIndex);
+}
+
+/// Adds `n` blocks from the end of the allocated bytes to the free list. The
+/// account must already have been reallocated to fit them.
+pub fn expand_blocks<F: ExpandableFixed>(fixed: &mut F, dynamic: &mut [u8], n: u32) {
+    let mut free_list: FreeList<F::FreeListPadding> =
+        FreeList::new(dynamic, fixed.get_free_list_head_index());
+    for _ in 0..n {
+        let num_bytes_allocated: DataIndex = fixed.get_num_bytes_allocated();
+        free_list.add(num_bytes_allocated);
+        fixed.set_num_bytes_allocated(num_bytes_allocated + F::BLOCK_SIZE as DataIndex);
+    }
+    fixed.set_free_list_head_index(free_list.get_head());
+}
+
+/// Takes a block off the free list. Callers make sure there is one.
+pub fn allocate_block<F: ExpandableFixed>(fixed: &mut F, dynamic: &mut [u8]) -> DataIndex {
+    let mut free_list: FreeList<F::FreeListPadding> =
+        FreeList::new(dynamic, fixed.get_free_list_head_index());
+    let free_address: DataIndex = free_list.remove();
+    fixed.set_free_list_head_index(free_list.get_head());
+    free_address
+}
+
+/// Returns a block to the free list.
+pub fn release_block<F: ExpandableFixed>(fixed: &mut F, dynamic: &mut [u8], index: DataIndex) {
+    let mut free_list: FreeList<F::FreeListPadding> =
+        FreeList::new(dynamic, fixed.get_free_list_head_index());
+    free_list.add(index);
+    fixed.set_free_list_head_index(free_list.get_head());
+}
+
+/// Root and max of a tree after it was changed, to write back to the header.
+pub struct TreeNodeUpdate {
+    pub node_index: DataIndex,
+    pub root_index: DataIndex,
+    pub max_index: DataIndex,
+}
+
+/// Allocates a block and inserts `value` into the tree there.
+pub fn insert_node<F: ExpandableFixed, V: Payload>(
+    fixed: &mut F,
+    dynamic: &mut [u8],
+    root_index: DataIndex,
+    max_index: DataIndex,
+    value: V,
+) -> TreeNodeUpdate {
+    let node_index: DataIndex = allocate_block(fixed, dynamic);
+    let mut tree: RedBlackTree<V> = RedBlackTree::new(dynamic, root_index, max_index);
+    tree.insert(node_index, value);
+    TreeNodeUpdate {
+        node_index,
+        root_index: tree.get_root_index(),
+        max_index: tree.get_max_index(),
+    }
+}
+
+/// Removes the node at `node_index` from the tree and releases its block.
+pub fn remove_node<F: ExpandableFixed, V: Payload>(
+    fixed: &mut F,
+    dynamic: &mut [u8],
+    root_index: DataIndex,
+    max_index: DataIndex,
+    node_index: DataIndex,
+) -> TreeNodeUpdate {
+    let mut tree: RedBlackTree<V> = RedBlackTree::new(dynamic, root_index, max_index);
+    tree.remove_by_index(node_index);
+    let root_index: DataIndex = tree.get_root_index();
+    let max_index: DataIndex = tree.get_max_index();
+    release_block(fixed, dynamic, node_index);
+    TreeNodeUpdate {
+        node_index,
+        root_index,
+        max_index,
+    }
+}
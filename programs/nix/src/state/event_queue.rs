@@ -0,0 +1,257 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use bytemuck::{Pod, Zeroable};
+use hypertree::{get_helper, get_mut_helper, DataIndex, Get, PodBool};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use shank::ShankType;
+use solana_program::{entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey};
+use static_assertions::const_assert_eq;
+use std::mem::size_of;
+
+use crate::{
+    program::NixError,
+    quantities::WrappedI80F48,
+    require,
+    state::{
+        DerefOrBorrow, DerefOrBorrowMut, DynamicAccount, EVENT_QUEUE_FIXED_SIZE, MARKET_EVENT_SIZE,
+    },
+    utils::{is_discriminant_of, StableName},
+    validation::NixAccount,
+};
+
+#[derive(
+    Debug,
+    BorshDeserialize,
+    BorshSerialize,
+    PartialEq,
+    Clone,
+    Copy,
+    ShankType,
+    IntoPrimitive,
+    TryFromPrimitive,
+)]
+#[repr(u8)]
+pub enum MarketEventType {
+    Fill = 0,
+    Cancel = 1,
+    Loan = 2,
+    Reduce = 3,
+    LoanSale = 4,
+}
+unsafe impl bytemuck::Zeroable for MarketEventType {}
+unsafe impl bytemuck::Pod for MarketEventType {}
+impl Default for MarketEventType {
+    fn default() -> Self {
+        MarketEventType::Fill
+    }
+}
+
+/// Ring buffer of market events. The dynamic part is an array of MarketEvent
+/// sized by the account, so capacity is chosen when the account is allocated.
+#[repr(C)]
+#[derive(Default, Copy, Clone, Zeroable, Pod, ShankType)]
+pub struct EventQueueFixed {
+    /// Discriminant for identifying this account type.
+    pub discriminant: u64,
+    /// The market this queue belongs to.
+    pub market: Pubkey,
+    /// Id of the next event pushed. Ids start at 1 and increase by one per
+    /// event, so consumers can detect gaps.
+    next_event_id: u64,
+    /// Slot in the ring of the oldest unconsumed event.
+    head: u32,
+    /// Number of unconsumed events.
+    count: u32,
+}
+
+const_assert_eq!(
+    size_of::<EventQueueFixed>(),
+    8 +   // discriminant
+    32 +  // market
+    8 +   // next_event_id
+    4 +   // head
+    4 // count
+);
+const_assert_eq!(size_of::<EventQueueFixed>(), EVENT_QUEUE_FIXED_SIZE);
+const_assert_eq!(size_of::<EventQueueFixed>() % 8, 0);
+
+impl Get for EventQueueFixed {}
+impl StableName for EventQueueFixed {
+    const STABLE_NAME: &'static str = "nix::EventQueueFixed";
+    const LEGACY_NAME: &'static str = "nix::state::event_queue::EventQueueFixed";
+}
+impl NixAccount for EventQueueFixed {
+    fn verify_discriminant(&self) -> ProgramResult {
+        let expected_discriminant: u64 =
+            crate::utils::get_discriminant::<EventQueueFixed>().unwrap();
+
+        require!(
+            is_discriminant_of::<EventQueueFixed>(self.discriminant),
+            ProgramError::InvalidAccountData,
+            "Invalid event queue discriminant actual: {} expected: {}",
+            self.discriminant,
+            expected_discriminant
+        )?;
+        Ok(())
+    }
+}
+
+impl EventQueueFixed {
+    pub fn new_empty(market: Pubkey) -> Self {
+        EventQueueFixed {
+            discriminant: crate::utils::get_discriminant::<EventQueueFixed>().unwrap(),
+            market,
+            next_event_id: 1,
+            head: 0,
+            count: 0,
+        }
+    }
+    pub fn get_next_event_id(&self) -> u64 {
+        self.next_event_id
+    }
+    pub fn get_count(&self) -> u32 {
+        self.count
+    }
+}
+
+/// One entry of the event queue. Amounts are in atoms for fills and loan
+/// sales and in shares for loans and reduces.
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, Zeroable, Pod, ShankType)]
+pub struct MarketEvent {
+    pub event_id: u64,
+    pub slot: u64,
+    pub trader: Pubkey,
+    /// Taker for fills, default pubkey otherwise.
+    pub counterparty: Pubkey,
+    pub order_sequence_number: u64,
+    pub base_amount: WrappedI80F48,
+    pub quote_amount: WrappedI80F48,
+    pub rate_bps: u16,
+    pub event_type: MarketEventType,
+    pub is_a_tree: PodBool,
+    _padding: [u8; 4],
+}
+const_assert_eq!(size_of::<MarketEvent>(), MARKET_EVENT_SIZE);
+const_assert_eq!(size_of::<MarketEvent>() % 8, 0);
+impl Get for MarketEvent {}
+
+impl MarketEvent {
+    /// The event id is assigned when the event is pushed.
+    pub fn new(
+        event_type: MarketEventType,
+        slot: u64,
+        trader: Pubkey,
+        counterparty: Pubkey,
+        order_sequence_number: u64,
+        base_amount: WrappedI80F48,
+        quote_amount: WrappedI80F48,
+        rate_bps: u16,
+        is_a_tree: bool,
+    ) -> Self {
+        MarketEvent {
+            event_id: 0,
+            slot,
+            trader,
+            counterparty,
+            order_sequence_number,
+            base_amount,
+            quote_amount,
+            rate_bps,
+            event_type,
+            is_a_tree: PodBool::from(is_a_tree),
+            _padding: [0; 4],
+        }
+    }
+}
+
+/// Fully owned EventQueue, used in clients that can copy.
+pub type EventQueueValue = DynamicAccount<EventQueueFixed, Vec<u8>>;
+/// Full EventQueue reference type.
+pub type EventQueueRef<'a> = DynamicAccount<&'a EventQueueFixed, &'a [u8]>;
+/// Full EventQueue reference type.
+pub type EventQueueRefMut<'a> = DynamicAccount<&'a mut EventQueueFixed, &'a mut [u8]>;
+
+impl<Fixed: DerefOrBorrow<EventQueueFixed>, Dynamic: DerefOrBorrow<[u8]>>
+    DynamicAccount<Fixed, Dynamic>
+{
+    fn borrow_event_queue(&self) -> EventQueueRef {
+        EventQueueRef {
+            fixed: self.fixed.deref_or_borrow(),
+            dynamic: self.dynamic.deref_or_borrow(),
+        }
+    }
+
+    pub fn get_capacity(&self) -> u32 {
+        let DynamicAccount { dynamic, .. } = self.borrow_event_queue();
+        (dynamic.len() / MARKET_EVENT_SIZE) as u32
+    }
+
+    /// Unconsumed events, oldest first.
+    pub fn get_event(&self, offset: u32) -> Option<&MarketEvent> {
+        let DynamicAccount { fixed, dynamic } = self.borrow_event_queue();
+        if offset >= fixed.count {
+            return None;
+        }
+        let capacity: u32 = (dynamic.len() / MARKET_EVENT_SIZE) as u32;
+        let slot: u32 = (fixed.head + offset) % capacity;
+        Some(get_helper::<MarketEvent>(
+            dynamic,
+            (slot as usize * MARKET_EVENT_SIZE) as DataIndex,
+        ))
+    }
+}
+
+impl<Fixed: DerefOrBorrowMut<EventQueueFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
+    DynamicAccount<Fixed, Dynamic>
+{
+    fn borrow_mut_event_queue(&mut self) -> EventQueueRefMut {
+        EventQueueRefMut {
+            fixed: self.fixed.deref_or_borrow_mut(),
+            dynamic: self.dynamic.deref_or_borrow_mut(),
+        }
+    }
+
+    /// Appends an event and returns its id. Fails instead of overwriting
+    /// when the queue is full so that no event is ever lost.
+    pub fn push_event(&mut self, mut event: MarketEvent) -> Result<u64, ProgramError> {
+        let DynamicAccount { fixed, dynamic } = self.borrow_mut_event_queue();
+        let capacity: u32 = (dynamic.len() / MARKET_EVENT_SIZE) as u32;
+        require!(
+            fixed.count < capacity,
+            NixError::EventQueueFull,
+            "Event queue is full with {} events",
+            fixed.count,
+        )?;
+
+        let event_id: u64 = fixed.next_event_id;
+        event.event_id = event_id;
+        let slot: u32 = (fixed.head + fixed.count) % capacity;
+        *get_mut_helper::<MarketEvent>(dynamic, (slot as usize * MARKET_EVENT_SIZE) as DataIndex) =
+            event;
+
+        fixed.count += 1;
+        fixed.next_event_id = fixed.next_event_id.wrapping_add(1);
+        Ok(event_id)
+    }
+
+    /// Drops all events with an id up to and including `up_to_event_id`.
+    /// Returns the number of events removed.
+    pub fn consume_events(&mut self, up_to_event_id: u64) -> u32 {
+        let DynamicAccount { fixed, dynamic } = self.borrow_mut_event_queue();
+        let capacity: u32 = (dynamic.len() / MARKET_EVENT_SIZE) as u32;
+        let mut num_consumed: u32 = 0;
+        while fixed.count > 0 {
+            let oldest: &MarketEvent = get_helper::<MarketEvent>(
+                dynamic,
+                (fixed.head as usize * MARKET_EVENT_SIZE) as DataIndex,
+            );
+            if oldest.event_id > up_to_event_id {
+                break;
+            }
+            fixed.head = (fixed.head + 1) % capacity;
+            fixed.count -= 1;
+            num_consumed += 1;
+        }
+        num_consumed
+    }
+}
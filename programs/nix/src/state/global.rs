@@ -1,16 +1,23 @@
 use crate::{
-    quantities::WrappedI80F48, require, state::RestingOrder, utils::get_discriminant, validation::{get_global_address, get_global_vault_address, NixAccount}
+    quantities::WrappedI80F48,
+    require,
+    state::RestingOrder,
+    utils::{get_discriminant, is_discriminant_of, StableName},
+    validation::{get_global_address, get_global_vault_address, NixAccount},
 };
 
 use super::{
-    DerefOrBorrow, DerefOrBorrowMut, DynamicAccount, GLOBAL_BLOCK_SIZE, GLOBAL_DEPOSIT_SIZE,
+    expand_blocks, insert_node, DerefOrBorrow, DerefOrBorrowMut, DynamicAccount, ExpandableFixed,
+    TreeNodeUpdate, GAS_DEPOSIT_LAMPORTS, GLOBAL_BLOCK_SIZE, GLOBAL_DEPOSIT_SIZE,
     GLOBAL_FIXED_SIZE, GLOBAL_FREE_LIST_BLOCK_SIZE, GLOBAL_TRADER_SIZE, MAX_GLOBAL_SEATS,
 };
 use bytemuck::{Pod, Zeroable};
 use fixed::types::I80F48;
+#[cfg(feature = "program")]
+use hypertree::HyperTreeValueIteratorTrait;
 use hypertree::{
-    get_helper, get_mut_helper, DataIndex, FreeList, Get, HyperTreeReadOperations,
-    HyperTreeWriteOperations, RBNode, RedBlackTree, RedBlackTreeReadOnly, NIL,
+    get_helper, get_mut_helper, DataIndex, Get, HyperTreeReadOperations, HyperTreeWriteOperations,
+    RBNode, RedBlackTree, RedBlackTreeReadOnly, NIL,
 };
 use shank::ShankType;
 use solana_program::{entrypoint::ProgramResult, pubkey::Pubkey};
@@ -50,6 +57,19 @@ pub struct GlobalFixed {
     global_bump: u8,
 
     num_seats_claimed: u16,
+
+    /// Lamports a global order prepays for whoever removes it. Accounts
+    /// created before this was stored are migrated to GAS_DEPOSIT_LAMPORTS.
+    gas_deposit_lamports: u64,
+
+    /// Marginfi bank the deposits are lent to, default until
+    /// GlobalLinkMarginfi. Balances are asset shares of this bank, before the
+    /// link a share is one atom.
+    marginfi_bank: Pubkey,
+
+    /// Marginfi account of the global in that bank. The global vault is its
+    /// authority.
+    marginfi_account: Pubkey,
 }
 
 const_assert_eq!(
@@ -64,16 +84,41 @@ const_assert_eq!(
     4 +   // num_bytes_allocated
     1 +   // vault_bump
     1 +   // global_bump
-    2 // num_seats_claimed
+    2 +   // num_seats_claimed
+    8 +   // gas_deposit_lamports
+    32 +  // marginfi_bank
+    32 // marginfi_account
 );
 
 const_assert_eq!(size_of::<GlobalFixed>(), GLOBAL_FIXED_SIZE);
 const_assert_eq!(size_of::<GlobalFixed>() % 8, 0);
 impl Get for GlobalFixed {}
+impl StableName for GlobalFixed {
+    const STABLE_NAME: &'static str = "nix::GlobalFixed";
+    const LEGACY_NAME: &'static str = "nix::state::global::GlobalFixed";
+}
+
+impl ExpandableFixed for GlobalFixed {
+    type FreeListPadding = GlobalUnusedFreeListPadding;
+    const BLOCK_SIZE: usize = GLOBAL_BLOCK_SIZE;
+
+    fn get_free_list_head_index(&self) -> DataIndex {
+        self.free_list_head_index
+    }
+    fn set_free_list_head_index(&mut self, free_list_head_index: DataIndex) {
+        self.free_list_head_index = free_list_head_index;
+    }
+    fn get_num_bytes_allocated(&self) -> DataIndex {
+        self.num_bytes_allocated
+    }
+    fn set_num_bytes_allocated(&mut self, num_bytes_allocated: DataIndex) {
+        self.num_bytes_allocated = num_bytes_allocated;
+    }
+}
 
 #[repr(C, packed)]
 #[derive(Default, Copy, Clone, Pod, Zeroable)]
-struct GlobalUnusedFreeListPadding {
+pub struct GlobalUnusedFreeListPadding {
     _padding: [u64; 7],
     _padding2: [u8; 4],
 }
@@ -91,7 +136,9 @@ pub struct GlobalTrader {
     trader: Pubkey,
 
     deposit_index: DataIndex,
-    _padding: u32,
+    /// Number of global orders whose gas deposit this trader paid
+    /// into the global account and that have not been paid out yet.
+    num_gas_prepayments: u32,
     _padding2: u64,
 }
 const_assert_eq!(size_of::<GlobalTrader>(), GLOBAL_TRADER_SIZE);
@@ -125,9 +172,10 @@ pub struct GlobalDeposit {
     /// Trader who controls this global trader.
     trader: Pubkey,
 
-    /// Token balance in the global account for this trader. The tokens received
-    /// in trades stay in the market.
-    balance_atoms: WrappedI80F48,
+    /// Balance in the global account for this trader, in asset shares of the
+    /// marginfi bank of the global, or atoms while it is not linked. The
+    /// tokens received in trades stay in the market.
+    balance_shares: WrappedI80F48,
 }
 const_assert_eq!(size_of::<GlobalDeposit>(), GLOBAL_DEPOSIT_SIZE);
 const_assert_eq!(size_of::<GlobalDeposit>() % 8, 0);
@@ -135,7 +183,7 @@ const_assert_eq!(size_of::<GlobalDeposit>() % 8, 0);
 impl Ord for GlobalDeposit {
     fn cmp(&self, other: &Self) -> Ordering {
         // Reversed order so that the max according to the tree is actually the min.
-        other.balance_atoms.cmp(&self.balance_atoms)
+        other.balance_shares.cmp(&self.balance_shares)
     }
 }
 impl PartialOrd for GlobalDeposit {
@@ -156,7 +204,7 @@ impl std::fmt::Display for GlobalDeposit {
 }
 
 impl GlobalFixed {
-    pub fn new_empty(mint: &Pubkey) -> Self {
+    pub fn new_empty(mint: &Pubkey, gas_deposit_lamports: u64) -> Self {
         let (vault, vault_bump) = get_global_vault_address(mint);
         let (_, global_bump) = get_global_address(mint);
         GlobalFixed {
@@ -171,6 +219,9 @@ impl GlobalFixed {
             vault_bump,
             global_bump,
             num_seats_claimed: 0,
+            gas_deposit_lamports,
+            marginfi_bank: Pubkey::default(),
+            marginfi_account: Pubkey::default(),
         }
     }
     pub fn get_mint(&self) -> &Pubkey {
@@ -182,14 +233,61 @@ impl GlobalFixed {
     pub fn get_vault_bump(&self) -> u8 {
         self.vault_bump
     }
+    pub fn is_full(&self) -> bool {
+        self.num_seats_claimed >= MAX_GLOBAL_SEATS
+    }
+    pub fn get_gas_deposit_lamports(&self) -> u64 {
+        self.gas_deposit_lamports
+    }
+    pub fn get_marginfi_bank(&self) -> &Pubkey {
+        &self.marginfi_bank
+    }
+    pub fn get_marginfi_account(&self) -> &Pubkey {
+        &self.marginfi_account
+    }
+    pub fn is_marginfi_linked(&self) -> bool {
+        self.marginfi_account != Pubkey::default()
+    }
+
+    /// Bytes the header is short of the current layout. The dynamic bytes
+    /// always end right after the header, so an old layout is shorter by the
+    /// fields added since, see OLD_GLOBAL_HEADER_GROWTHS.
+    pub fn get_missing_header_bytes(&self, data_len: usize) -> usize {
+        (size_of::<GlobalFixed>() + self.num_bytes_allocated as usize).saturating_sub(data_len)
+    }
+
+    /// Fills in the fields a global moved off an old layout is missing. They
+    /// hold dynamic bytes from before the move until then.
+    pub(crate) fn migrate(&mut self, missing_header_bytes: usize) {
+        self.discriminant = get_discriminant::<GlobalFixed>().unwrap();
+        if missing_header_bytes > GLOBAL_MARGINFI_FIELDS_SIZE {
+            self.gas_deposit_lamports = GAS_DEPOSIT_LAMPORTS;
+        }
+        self.marginfi_bank = Pubkey::default();
+        self.marginfi_account = Pubkey::default();
+    }
+
+    pub(crate) fn link_marginfi(&mut self, marginfi_bank: &Pubkey, marginfi_account: &Pubkey) {
+        self.marginfi_bank = *marginfi_bank;
+        self.marginfi_account = *marginfi_account;
+    }
 }
 
+/// Size of marginfi_bank and marginfi_account, the fields added last.
+const GLOBAL_MARGINFI_FIELDS_SIZE: usize = 2 * size_of::<Pubkey>();
+/// Bytes a header from before each change to the layout is short of the
+/// current one: before the marginfi fields, and before gas_deposit_lamports.
+pub const OLD_GLOBAL_HEADER_GROWTHS: [usize; 2] = [
+    GLOBAL_MARGINFI_FIELDS_SIZE,
+    GLOBAL_MARGINFI_FIELDS_SIZE + size_of::<u64>(),
+];
+
 impl NixAccount for GlobalFixed {
     fn verify_discriminant(&self) -> ProgramResult {
         // Check the discriminant to make sure it is a global account.
         let expected_discriminant: u64 = get_discriminant::<GlobalFixed>().unwrap();
         require!(
-            self.discriminant == expected_discriminant,
+            is_discriminant_of::<GlobalFixed>(self.discriminant),
             solana_program::program_error::ProgramError::InvalidAccountData,
             "Invalid market discriminant actual: {} expected: {}",
             self.discriminant,
@@ -197,6 +295,17 @@ impl NixAccount for GlobalFixed {
         )?;
         Ok(())
     }
+
+    fn verify_data_len(&self, data_len: usize) -> ProgramResult {
+        require!(
+            data_len == size_of::<GlobalFixed>() + self.num_bytes_allocated as usize,
+            crate::program::NixError::GlobalLayoutMismatch,
+            "Global length {} does not match {} allocated bytes, run MigrateGlobal",
+            data_len,
+            self.num_bytes_allocated,
+        )?;
+        Ok(())
+    }
 }
 
 impl GlobalTrader {
@@ -204,7 +313,7 @@ impl GlobalTrader {
         GlobalTrader {
             trader: *trader,
             deposit_index,
-            _padding: 0,
+            num_gas_prepayments: 0,
             _padding2: 0,
         }
     }
@@ -214,7 +323,7 @@ impl GlobalDeposit {
     pub fn new_empty(trader: &Pubkey) -> Self {
         GlobalDeposit {
             trader: *trader,
-            balance_atoms: WrappedI80F48::default(),
+            balance_shares: WrappedI80F48::default(),
         }
     }
 }
@@ -241,17 +350,47 @@ impl<Fixed: DerefOrBorrow<GlobalFixed>, Dynamic: DerefOrBorrow<[u8]>>
         }
     }
 
-    pub fn get_balance_atoms(&self, trader: &Pubkey) -> WrappedI80F48 {
+    /// Balance of the trader, in atoms while the global is not linked to
+    /// marginfi.
+    pub fn get_balance_shares(&self, trader: &Pubkey) -> WrappedI80F48 {
         let DynamicAccount { fixed, dynamic } = self.borrow_global();
         // If the trader got evicted, then they wont be found.
         let global_balance_or: Option<&GlobalDeposit> = get_global_deposit(fixed, dynamic, trader);
         if let Some(global_deposit) = global_balance_or {
-            global_deposit.balance_atoms
+            global_deposit.balance_shares
         } else {
             WrappedI80F48::default()
         }
     }
 
+    /// Number of gas prepayments the trader has outstanding on this global.
+    /// Evicted traders keep none.
+    pub fn get_num_gas_prepayments(&self, trader: &Pubkey) -> u32 {
+        let DynamicAccount { fixed, dynamic } = self.borrow_global();
+        get_global_trader(fixed, dynamic, trader)
+            .map(|global_trader| global_trader.num_gas_prepayments)
+            .unwrap_or_default()
+    }
+
+    /// Lamports held on the global account for the trader's gas prepayments.
+    pub fn get_gas_prepayment_lamports(&self, trader: &Pubkey) -> u64 {
+        let gas_deposit_lamports: u64 = self.borrow_global().fixed.gas_deposit_lamports;
+        self.get_num_gas_prepayments(trader) as u64 * gas_deposit_lamports
+    }
+
+    /// Trader with the smallest deposit and their balance. This is who gets
+    /// evicted when the global is full.
+    pub fn get_min_deposit(&self) -> Option<(Pubkey, WrappedI80F48)> {
+        let DynamicAccount { fixed, dynamic } = self.borrow_global();
+        if fixed.global_deposits_max_index == NIL {
+            return None;
+        }
+        let global_deposit: &GlobalDeposit =
+            get_helper::<RBNode<GlobalDeposit>>(dynamic, fixed.global_deposits_max_index)
+                .get_value();
+        Some((global_deposit.trader, global_deposit.balance_shares))
+    }
+
     pub fn verify_min_balance(&self, trader: &Pubkey) -> ProgramResult {
         let DynamicAccount { fixed, dynamic } = self.borrow_global();
 
@@ -304,18 +443,12 @@ impl<Fixed: DerefOrBorrowMut<GlobalFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
             "Expected empty free list, but expand wasn't needed",
         )?;
 
-        let mut free_list: FreeList<GlobalUnusedFreeListPadding> =
-            FreeList::new(dynamic, fixed.free_list_head_index);
-
         // Expand twice since there are two trees.
-        free_list.add(fixed.num_bytes_allocated);
-        free_list.add(fixed.num_bytes_allocated + GLOBAL_BLOCK_SIZE as u32);
-        fixed.num_bytes_allocated += 2 * GLOBAL_BLOCK_SIZE as u32;
-        fixed.free_list_head_index = free_list.get_head();
+        expand_blocks(fixed, dynamic, 2);
         Ok(())
     }
 
-    pub fn reduce(&mut self, trader: &Pubkey, supply_amount: I80F48) -> ProgramResult {
+    pub fn reduce(&mut self, trader: &Pubkey, supply_shares: I80F48) -> ProgramResult {
         let DynamicAccount { fixed, dynamic } = self.borrow_mut_global();
         let global_deposit_opt: Option<&mut GlobalDeposit> =
             get_mut_global_deposit(fixed, dynamic, trader);
@@ -326,9 +459,9 @@ impl<Fixed: DerefOrBorrowMut<GlobalFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
             trader
         )?;
         let global_deposit: &mut GlobalDeposit = global_deposit_opt.unwrap();
-        global_deposit.balance_atoms = global_deposit
-            .balance_atoms
-            .checked_sub(supply_amount)
+        global_deposit.balance_shares = global_deposit
+            .balance_shares
+            .checked_sub(supply_shares)
             .ok_or(crate::program::NixError::NumericalOverflow)?;
         Ok(())
     }
@@ -336,37 +469,43 @@ impl<Fixed: DerefOrBorrowMut<GlobalFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
     pub fn add_trader(&mut self, trader: &Pubkey) -> ProgramResult {
         let DynamicAccount { fixed, dynamic } = self.borrow_mut_global();
 
-        let free_address_trader: DataIndex = get_free_address_on_global_fixed(fixed, dynamic);
-        let free_address_deposit: DataIndex = get_free_address_on_global_fixed(fixed, dynamic);
-        let mut global_trader_tree: GlobalTraderTree =
-            GlobalTraderTree::new(dynamic, fixed.global_traders_root_index, NIL);
-        let global_trader: GlobalTrader = GlobalTrader::new_empty(trader, free_address_deposit);
-
+        let global_trader_tree: GlobalTraderTreeReadOnly =
+            GlobalTraderTreeReadOnly::new(dynamic, fixed.global_traders_root_index, NIL);
         require!(
-            global_trader_tree.lookup_index(&global_trader) == NIL,
+            global_trader_tree.lookup_index(&GlobalTrader::new_empty(trader, NIL)) == NIL,
             crate::program::NixError::AlreadyClaimedSeat,
             "Already claimed global trader seat",
         )?;
-
-        global_trader_tree.insert(free_address_trader, global_trader);
-        fixed.global_traders_root_index = global_trader_tree.get_root_index();
         require!(
             fixed.num_seats_claimed < MAX_GLOBAL_SEATS,
             crate::program::NixError::TooManyGlobalSeats,
             "There is a strict limit on number of seats available in a global, use evict",
         )?;
 
-        fixed.num_seats_claimed += 1;
+        // The deposit goes in first so the trader node can point at it.
+        let global_deposits_root_index: DataIndex = fixed.global_deposits_root_index;
+        let global_deposits_max_index: DataIndex = fixed.global_deposits_max_index;
+        let deposit_update: TreeNodeUpdate = insert_node(
+            fixed,
+            dynamic,
+            global_deposits_root_index,
+            global_deposits_max_index,
+            GlobalDeposit::new_empty(trader),
+        );
+        fixed.global_deposits_root_index = deposit_update.root_index;
+        fixed.global_deposits_max_index = deposit_update.max_index;
 
-        let global_deposit: GlobalDeposit = GlobalDeposit::new_empty(trader);
-        let mut global_deposit_tree: GlobalDepositTree = GlobalDepositTree::new(
+        let global_traders_root_index: DataIndex = fixed.global_traders_root_index;
+        let trader_update: TreeNodeUpdate = insert_node(
+            fixed,
             dynamic,
-            fixed.global_deposits_root_index,
-            fixed.global_deposits_max_index,
+            global_traders_root_index,
+            NIL,
+            GlobalTrader::new_empty(trader, deposit_update.node_index),
         );
-        global_deposit_tree.insert(free_address_deposit, global_deposit);
-        fixed.global_deposits_root_index = global_deposit_tree.get_root_index();
-        fixed.global_deposits_max_index = global_deposit_tree.get_max_index();
+        fixed.global_traders_root_index = trader_update.root_index;
+
+        fixed.num_seats_claimed += 1;
 
         Ok(())
     }
@@ -399,10 +538,10 @@ impl<Fixed: DerefOrBorrowMut<GlobalFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
         )?;
         let existing_global_deposit: &mut GlobalDeposit = existing_global_deposit_opt.unwrap();
 
-        let existing_global_balance_atoms: WrappedI80F48 =
-            existing_global_deposit.balance_atoms;
+        let existing_global_balance_shares: WrappedI80F48 =
+            existing_global_deposit.balance_shares;
         require!(
-            existing_global_balance_atoms == WrappedI80F48::ZERO,
+            existing_global_balance_shares == WrappedI80F48::ZERO,
             crate::program::NixError::GlobalInsufficient,
             "Error in emptying the existing global",
         )?;
@@ -458,10 +597,12 @@ impl<Fixed: DerefOrBorrowMut<GlobalFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
         Ok(())
     }
     /// Add global order to the global account and specific market.
+    /// `asset_share_value` is what a share of the balances is worth in atoms.
     pub fn add_order(
         &mut self,
         resting_order: &RestingOrder,
         global_trade_owner: &Pubkey,
+        asset_share_value: I80F48,
     ) -> ProgramResult {
         let DynamicAccount { fixed, dynamic } = self.borrow_mut_global();
 
@@ -483,12 +624,14 @@ impl<Fixed: DerefOrBorrowMut<GlobalFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
             )?;
             let global_deposit: &mut GlobalDeposit = global_deposit_opt.unwrap();
 
-            let global_atoms_deposited: WrappedI80F48 = global_deposit.balance_atoms;
+            let global_atoms_deposited: I80F48 = I80F48::from(global_deposit.balance_shares)
+                .checked_mul(asset_share_value)
+                .ok_or(crate::program::NixError::NumericalOverflow)?;
 
             // This can be trivial to circumvent by using flash loans. This is just
             // an informational safety check.
             require!(
-                num_global_atoms <= global_atoms_deposited,
+                I80F48::from(num_global_atoms) <= global_atoms_deposited,
                 crate::program::NixError::GlobalInsufficient,
                 "Insufficient funds for global order needed {} has {}",
                 num_global_atoms,
@@ -496,15 +639,27 @@ impl<Fixed: DerefOrBorrowMut<GlobalFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
             )?;
         }
 
+        // The caller transfers the gas prepayment right after this.
+        let global_trader: &mut GlobalTrader =
+            get_mut_global_trader(fixed, dynamic, global_trade_owner).unwrap();
+        global_trader.num_gas_prepayments += 1;
+
         Ok(())
     }
 
+    /// Marks gas prepayments of the trader as paid out. Orders placed before
+    /// prepayments were counted are not in the count, so this saturates.
+    pub fn release_gas_prepayments(&mut self, trader: &Pubkey, num_gas_prepayments: u32) {
+        let DynamicAccount { fixed, dynamic } = self.borrow_mut_global();
+        if let Some(global_trader) = get_mut_global_trader(fixed, dynamic, trader) {
+            global_trader.num_gas_prepayments = global_trader
+                .num_gas_prepayments
+                .saturating_sub(num_gas_prepayments);
+        }
+    }
+
     /// Deposit to global account.
-    pub fn deposit_global(
-        &mut self,
-        trader: &Pubkey,
-        balance_atoms: u64,
-    ) -> ProgramResult {
+    pub fn deposit_global(&mut self, trader: &Pubkey, balance_shares: I80F48) -> ProgramResult {
         let DynamicAccount { fixed, dynamic } = self.borrow_mut_global();
         let global_deposit_opt: Option<&mut GlobalDeposit> =
             get_mut_global_deposit(fixed, dynamic, trader);
@@ -515,9 +670,9 @@ impl<Fixed: DerefOrBorrowMut<GlobalFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
             trader
         )?;
         let global_deposit: &mut GlobalDeposit = global_deposit_opt.unwrap();
-        global_deposit.balance_atoms = global_deposit
-            .balance_atoms
-            .checked_add(balance_atoms)
+        global_deposit.balance_shares = global_deposit
+            .balance_shares
+            .checked_add(balance_shares)
             .ok_or(crate::program::NixError::NumericalOverflow)?;
 
         Ok(())
@@ -527,7 +682,7 @@ impl<Fixed: DerefOrBorrowMut<GlobalFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
     pub fn withdraw_global(
         &mut self,
         trader: &Pubkey,
-        balance_atoms: WrappedI80F48,
+        balance_shares: WrappedI80F48,
     ) -> ProgramResult {
         let DynamicAccount { fixed, dynamic } = self.borrow_mut_global();
         let global_deposit_opt: Option<&mut GlobalDeposit> =
@@ -540,22 +695,51 @@ impl<Fixed: DerefOrBorrowMut<GlobalFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
         )?;
         let global_deposit: &mut GlobalDeposit = global_deposit_opt.unwrap();
         // Checked sub makes sure there are enough funds.
-        global_deposit.balance_atoms = global_deposit
-            .balance_atoms
-            .checked_sub(balance_atoms)
+        global_deposit.balance_shares = global_deposit
+            .balance_shares
+            .checked_sub(balance_shares)
             .ok_or(crate::program::NixError::NumericalOverflow)?;
 
         Ok(())
     }
-}
-fn get_free_address_on_global_fixed(fixed: &mut GlobalFixed, dynamic: &mut [u8]) -> DataIndex {
-    let mut free_list: FreeList<GlobalUnusedFreeListPadding> =
-        FreeList::new(dynamic, fixed.free_list_head_index);
-    let free_address: DataIndex = free_list.remove();
-    fixed.free_list_head_index = free_list.get_head();
-    free_address
-}
 
+    /// Turns the atom balances of a global into shares of the `minted_shares`
+    /// its `vault_atoms` were deposited for when linking it to marginfi.
+    /// Rounds down, so the balances never add up to more than was minted.
+    #[cfg(feature = "program")]
+    pub(crate) fn convert_balances_to_shares(
+        &mut self,
+        vault_atoms: u64,
+        minted_shares: I80F48,
+    ) -> ProgramResult {
+        let DynamicAccount { fixed, dynamic } = self.borrow_mut_global();
+        let deposit_indexes: Vec<DataIndex> = GlobalDepositTreeReadOnly::new(
+            dynamic,
+            fixed.global_deposits_root_index,
+            fixed.global_deposits_max_index,
+        )
+        .iter::<GlobalDeposit>()
+        .map(|(index, _)| index)
+        .collect();
+        for deposit_index in deposit_indexes {
+            let global_deposit: &mut GlobalDeposit =
+                get_mut_helper::<RBNode<GlobalDeposit>>(dynamic, deposit_index).get_mut_value();
+            let balance_atoms: I80F48 = global_deposit.balance_shares.into();
+            let balance_shares: I80F48 = if vault_atoms == 0 {
+                I80F48::ZERO
+            } else {
+                // A balance times the minted shares can overflow, its
+                // fraction of the vault cannot.
+                balance_atoms
+                    .checked_div(I80F48::from_num(vault_atoms))
+                    .and_then(|fraction| fraction.checked_mul(minted_shares))
+                    .ok_or(crate::program::NixError::NumericalOverflow)?
+            };
+            global_deposit.balance_shares = balance_shares.into();
+        }
+        Ok(())
+    }
+}
 fn get_global_trader<'a>(
     fixed: &'a GlobalFixed,
     dynamic: &'a [u8],
@@ -573,6 +757,21 @@ fn get_global_trader<'a>(
     Some(global_trader)
 }
 
+fn get_mut_global_trader<'a>(
+    fixed: &'a mut GlobalFixed,
+    dynamic: &'a mut [u8],
+    trader: &'a Pubkey,
+) -> Option<&'a mut GlobalTrader> {
+    let global_trader_tree: GlobalTraderTree =
+        GlobalTraderTree::new(dynamic, fixed.global_traders_root_index, NIL);
+    let global_trader_index: DataIndex =
+        global_trader_tree.lookup_index(&GlobalTrader::new_empty(trader, NIL));
+    if global_trader_index == NIL {
+        return None;
+    }
+    Some(get_mut_helper::<RBNode<GlobalTrader>>(dynamic, global_trader_index).get_mut_value())
+}
+
 fn get_mut_global_deposit<'a>(
     fixed: &'a mut GlobalFixed,
     dynamic: &'a mut [u8],
@@ -1,47 +1,73 @@
 use crate::{
-    logs::{emit_stack, FillLog},
+    logs::BookLevel,
+    matching::{plan_matches, MatchBankParams, MatchPlan},
+    math::get_required_quote_collateral_to_back_loan,
+    program::NixError,
+    quantities::{BankShareValues, WrappedI80F48},
+    require,
+    utils::{assert_already_has_seat, get_discriminant, is_discriminant_of, StableName},
+    validation::NixAccount,
+};
+#[cfg(feature = "program")]
+use crate::{
+    logs::{emit_stack, FillLog, OrderConvertedToPoolLoanLog, PoolLoanConversionReason},
     marginfi_utils::{
-        convert_tokens_to_asset_shares, convert_tokens_to_liability_shares, cpi_marginfi_borrow,
-        cpi_marginfi_deposit_place_order, cpi_marginfi_repay, cpi_marginfi_withdraw,
-        get_required_quote_collateral_to_back_loan,
+        cpi_marginfi_borrow, cpi_marginfi_deposit_place_order, cpi_marginfi_repay,
+        cpi_marginfi_withdraw, MarginfiCpiScratch,
     },
     market_signer_seeds_with_bump,
-    program::{expand_market_loans, NixError},
-    quantities::WrappedI80F48,
-    require,
-    state::{market_loan::ActiveLoan, order_type_can_rest, GlobalFixed, MarketLoansFixed},
+    matching::{get_clearing_rate, get_maker_step, get_matched_fill, MakerStep, MatchedFill},
+    math::{
+        convert_tokens_to_asset_shares, convert_tokens_to_liability_shares,
+        get_loan_sale_price_atoms, get_weighted_value_usd,
+    },
+    program::expand_market_loans,
+    state::{
+        get_next_reverse_cycles,
+        market_loan::{ActiveLoan, LoanAssignment, LoanStatus, MatchedLoans},
+        order_type_can_rest, order_type_can_take, GlobalFixed, MarketEvent, MarketEventType,
+        MarketLoansFixed, GTT_GAS_DEPOSIT_LAMPORTS, NO_EXPIRATION_LAST_VALID_SLOT,
+        NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP,
+    },
     utils::{
-        assert_already_has_seat, assert_can_take, assert_not_already_expired,
-        assert_valid_order_type, get_discriminant, get_now_slot, get_now_unix_timestamp,
-        remove_from_global, remove_from_global_core, try_to_add_new_loans, try_to_add_to_global,
+        assert_can_take, assert_has_required_marginfi_sides, assert_market_has_required_banks,
+        assert_not_already_expired, assert_not_already_expired_at_time, assert_valid_order_type,
+        assert_valid_reverse_spread, get_now_slot, get_now_unix_timestamp, remove_from_global,
+        remove_from_global_core, try_to_add_new_loans, try_to_add_to_global,
         try_to_move_global_tokens,
     },
     validation::{
-        get_market_fee_receiver_address, get_nix_marginfi_account_address, get_vault_address,
+        get_market_fee_receiver_address, get_vault_address,
         loaders::{CreateMarketContext, GlobalTradeAccounts, MarginfiCpiAccounts},
-        MarketSigner, MintAccountInfo, NixAccount, NixAccountInfo, Program, Signer,
+        MarginfiAccountInfo, MarketSigner, MintAccountInfo, NixAccountInfo, Program, Signer,
     },
 };
 use bytemuck::{Pod, Zeroable};
 
-use fixed::types::I80F48;
+use fixed::types::{I80F48, U32F32};
+#[cfg(feature = "program")]
+use hypertree::HyperTreeWriteOperations;
 use hypertree::{
-    get_helper, get_mut_helper, is_not_nil, trace, DataIndex, FreeList, FreeListNode, Get,
-    HyperTreeReadOperations, HyperTreeValueIteratorTrait, HyperTreeWriteOperations, PodBool,
-    RBNode, NIL,
+    get_helper, get_mut_helper, is_not_nil, trace, DataIndex, FreeListNode, Get,
+    HyperTreeReadOperations, HyperTreeValueIteratorTrait, PodBool, RBNode, NIL,
 };
 
 use shank::ShankType;
-use solana_program::{
-    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
-    pubkey::Pubkey,
-};
+#[cfg(feature = "program")]
+use solana_program::account_info::AccountInfo;
+use solana_program::{entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey};
 use static_assertions::const_assert_eq;
 use std::mem::size_of;
 
+#[cfg(feature = "program")]
+use super::{ExpiryPolicy, OrderType};
 use super::{
-    ClaimedSeat, DerefOrBorrow, DerefOrBorrowMut, DynamicAccount, OrderType, RestingOrder,
-    MARKET_BLOCK_SIZE, MARKET_FIXED_SIZE, MARKET_FREE_LIST_BLOCK_SIZE,
+    expand_blocks, insert_node, ClaimedSeat, DerefOrBorrow, DerefOrBorrowMut, DynamicAccount,
+    ExpandableFixed, MarketInsurance, RestingOrder, SeatSnapshot, TreeNodeUpdate,
+    DEBUG_LOG_BALANCES, DEBUG_LOG_TREES, MARKET_BLOCK_LAYOUT_VERSION, MARKET_BLOCK_SIZE,
+    MARKET_FIXED_SIZE, MARKET_FREE_LIST_BLOCK_SIZE, MARKET_VERSION, MAX_BOOK_SNAPSHOT_LEVELS,
+    MAX_MATCHED_LOANS, MAX_SWEPT_EXPIRED_ORDERS, RATE_MILLI_BPS, RATE_ORACLE_MIN_FILL_USD,
+    RATE_ORACLE_WINDOW_SLOTS,
 };
 
 #[path = "market_helpers.rs"]
@@ -93,28 +119,45 @@ mod helpers {
 
 pub use helpers::*;
 
+#[cfg(feature = "program")]
 pub struct RestRemainingOrderToMarketArgs<'a, 'info> {
     pub trader_index: DataIndex,
     pub rate_bps: u16,
     pub is_bid: bool,
     pub current_slot: Option<u32>,
     pub last_valid_slot: u32,
+    pub last_valid_unix_timestamp: u32,
     pub order_type: OrderType,
+    pub expiry_policy: ExpiryPolicy,
     pub use_a_tree: bool,
     pub global_trade_accounts_opts: [Option<GlobalTradeAccounts<'a, 'info>>; 2],
+    /// Base atoms left to rest, the size its time on book counts.
+    pub num_base_atoms: u64,
 }
+#[cfg(feature = "program")]
 pub struct AddOrderToMarketArgs<'a, 'info> {
     pub market: Pubkey,
     pub market_signer: MarketSigner<'a, 'info>,
     pub market_signer_bump: u8,
     pub trader_index: DataIndex,
+    /// Seat of the referrer of the taker, or NIL when there is none.
+    pub referrer_index: DataIndex,
     pub num_base_atoms: u64,
     pub rate_bps: u16,
     pub reverse_spread_bps: u16,
+    /// Spread of a reverse order where it flips into a bid. Same as
+    /// reverse_spread_bps unless the maker skews its quotes.
+    pub reverse_bid_spread_bps: u16,
+    /// Flips left for a reverse order, zero for no limit.
+    pub max_reverse_cycles: u16,
     pub is_bid: bool,
     pub use_a_tree: bool,
     pub last_valid_slot: u32,
+    /// Good till time orders also expire after this unix timestamp, and
+    /// hold a gas deposit while they rest. See GTT_GAS_DEPOSIT_LAMPORTS.
+    pub last_valid_unix_timestamp: u32,
     pub order_type: OrderType,
+    pub expiry_policy: ExpiryPolicy,
     pub base_mint: MintAccountInfo<'a, 'info>,
     pub quote_mint: MintAccountInfo<'a, 'info>,
     pub base_oracle_price_usd: I80F48,
@@ -122,15 +165,53 @@ pub struct AddOrderToMarketArgs<'a, 'info> {
     pub global_trade_accounts_opts: [Option<GlobalTradeAccounts<'a, 'info>>; 2],
     pub marginfi_cpi_accounts_opts: [Option<MarginfiCpiAccounts<'a, 'info>>; 2],
     pub current_slot: Option<u32>,
+    pub match_limit: Option<u32>,
+    /// Loans matching may open. Fills past it are left unmatched, the same
+    /// as past match_limit.
+    pub max_new_loans: u32,
+    /// Hand back what is left when matching stops at match_limit or
+    /// max_new_loans instead of resting or dropping it, so ContinueOrder can
+    /// pick it up.
+    pub defer_remainder: bool,
+    /// Sequence number taken when the order was first placed. A continued
+    /// order rests with it so it keeps its place among equal rates.
+    pub resume_order_sequence_number: Option<u64>,
+    /// Settles an auction: makers cross only up to this rate and every fill
+    /// is at it.
+    pub clearing_rate_bps: Option<u16>,
 }
 
+/// Expected outcome of a taker order against the current book, without
+/// mutating anything.
+#[derive(Default)]
+pub struct QuoteOrderResult {
+    pub base_atoms: u64,
+    pub quote_atoms: u64,
+    pub num_maker_orders: u32,
+    pub weighted_rate_bps: u16,
+}
+
+#[cfg(feature = "program")]
 #[derive(Default)]
 pub struct AddOrderToMarketResult {
     pub order_sequence_number: u64,
     pub order_index: DataIndex,
     pub base_atoms_traded: u64,
     pub quote_atoms_traded: u64,
-    pub matched_loans: Vec<ActiveLoan>,
+    pub matched_loans: MatchedLoans,
+    /// One Fill event per maker order crossed, for the market event queue.
+    pub fill_events: Vec<MarketEvent>,
+    /// Loans bought from LoanSale orders, to apply to their loans pages.
+    pub loan_assignments: Vec<LoanAssignment>,
+    /// Base atoms left when matching stopped at the match limit with
+    /// defer_remainder set. Nothing was rested or borrowed for them.
+    pub deferred_base_atoms: u64,
+    /// Gas deposit the rested good till time order holds, for the caller to
+    /// move from the payer to the market.
+    pub gas_deposit_lamports_taken: u64,
+    /// Gas deposits of the good till time makers removed while matching,
+    /// expired or filled, for the caller to pay the payer from the market.
+    pub gas_deposit_lamports_released: u64,
 }
 
 #[repr(u8)]
@@ -145,8 +226,8 @@ pub enum MarketDataTreeNodeType {
 #[repr(C, packed)]
 #[derive(Default, Copy, Clone, Pod, Zeroable)]
 pub struct MarketUnusedFreeListPadding {
-    _padding: [u64; 12],
-    _padding2: [u8; 12],
+    _padding: [u64; 29],
+    _padding2: [u8; 4],
 }
 // 4 bytes are for the free list, rest is payload.
 const_assert_eq!(
@@ -173,7 +254,8 @@ pub struct MarketFixed {
 
     // base_a_marginfi_account_bump: u8,
     // base_b_marginfi_account_bump: u8,
-    _padding1: [u8; 4],
+    /// Slots the market stays post only once the circuit breaker trips.
+    circuit_breaker_slots: u32,
 
     /// Base A mint
     base_a_mint: Pubkey,
@@ -213,7 +295,8 @@ pub struct MarketFixed {
     /// LinkedList representing all free blocks that could be used for ClaimedSeats or RestingOrders
     free_list_head_index: DataIndex,
 
-    _padding2: [u32; 1],
+    /// Orders that can take are turned away before this slot.
+    post_only_until_slot: u32,
 
     /// base a MarginFi group account
     base_a_marginfi_group: Pubkey,
@@ -235,16 +318,78 @@ pub struct MarketFixed {
     base_a_match_volume: WrappedI80F48,
     base_b_match_volume: WrappedI80F48,
 
+    /// Sums of the withdrawable asset shares and the liability shares over
+    /// all seats, kept in step with the seats by update_balance,
+    /// increase_seat_liability and decrease_seat_liability.
     base_a_marginfi_account_asset_shares: WrappedI80F48,
     base_a_marginfi_account_liability_shares: WrappedI80F48,
 
     base_b_marginfi_account_shares: WrappedI80F48,
     base_b_marginfi_account_liability_shares: WrappedI80F48,
 
-    // // Unused padding. Saved in case a later version wants to be backwards
-    // // compatible. Also, it is nice to have the fixed size be a round number,
-    // // 256 bytes.
-    _padding3: [u64; 16],
+    /// Protocol fees charged to takers on fills, net of referral fees.
+    base_a_protocol_fee_shares: WrappedI80F48,
+    base_b_protocol_fee_shares: WrappedI80F48,
+
+    /// Bounds on the spread a reverse order can request, in bps.
+    min_reverse_spread_bps: u16,
+    max_reverse_spread_bps: u16,
+    /// Max number of resting orders per seat. Zero means no limit.
+    max_open_orders_per_seat: u16,
+    /// When set, the protocol fee is a share of the interest on a fill
+    /// rather than of its notional.
+    fee_on_interest: PodBool,
+    /// When set, orders only match during the settlement phase of the
+    /// MarketAuction of the market, at its clearing rate.
+    auction_mode: PodBool,
+
+    /// Last slot a book snapshot was emitted for each tree.
+    base_a_last_snapshot_slot: u32,
+    base_b_last_snapshot_slot: u32,
+
+    /// When set, claiming a seat requires a co-sign from this key. Default
+    /// pubkey for permissionless markets.
+    allowlist_authority: Pubkey,
+
+    /// Event queue recording fills, cancels and loans. Default pubkey when
+    /// the market has none.
+    event_queue: Pubkey,
+
+    /// Max liability a single seat can carry on a base, in bps of the
+    /// deposits in the marginfi bank of that base. Zero means no limit.
+    max_borrow_utilization_bps: u16,
+
+    /// Rate of the last fill on each tree worth at least
+    /// RATE_ORACLE_MIN_FILL_USD, in bps.
+    base_a_last_matched_rate_bps: u16,
+    base_b_last_matched_rate_bps: u16,
+    /// Move of the oracle price between two orders that trips the circuit
+    /// breaker, in bps. Zero turns it off.
+    circuit_breaker_bps: u16,
+
+    /// Time weighted average rate of each tree in thousandths of a bps, as of
+    /// its rate update slot. See get_twar_rate_bps.
+    base_a_twar_rate_milli_bps: u32,
+    base_b_twar_rate_milli_bps: u32,
+    /// Slot of the last such fill on each tree. Zero before the first one.
+    base_a_rate_update_slot: u32,
+    base_b_rate_update_slot: u32,
+
+    /// Base over quote oracle price as of the last order that read both, as
+    /// the bits of a U32F32. Zero before the first one.
+    last_oracle_price_ratio: u64,
+
+    /// When set, orders can only be placed by a CPI from this program.
+    /// Default pubkey for markets anyone can place on directly. Added past
+    /// the old end of the header, see MARKET_HEADER_GROWTHS.
+    allowed_caller: Pubkey,
+
+    /// Hash of the config of the marginfi bank of each base as of creation
+    /// or the last AcknowledgeBankConfigChange, see get_bank_config_hash.
+    /// Orders fail once the bank config drifts from it. Zero when not
+    /// recorded yet, which the next order that reads the bank fills in.
+    base_a_bank_config_hash: u64,
+    base_b_bank_config_hash: u64,
 }
 
 #[repr(C)]
@@ -252,6 +397,21 @@ pub struct MarketFixed {
 pub struct FeeState {
     protocol_fee_rate_bps: u64,
     ltv_buffer_bps: u64,
+    /// Share of the protocol fee paid to the referrer of a taker, in bps.
+    referral_bps: u16,
+    /// DEBUG_LOG_* bits of the extra diagnostic logs the market emits. Zero
+    /// by default. This takes the high bytes of what was a u64
+    /// referral_bps, which never exceeds 10_000.
+    debug_log_flags: u8,
+    _padding_referral: [u8; 5],
+    /// Share of the protocol fee rebated to the maker of a fill, in bps.
+    maker_rebate_bps: u16,
+    /// Max new resting orders a seat can place per order window. Zero turns
+    /// the limit off. This and order_window_slots take the high bytes of
+    /// what was a u64 maker_rebate_bps, which never exceeds 10_000.
+    max_orders_per_window: u16,
+    /// Length of the order window in slots.
+    order_window_slots: u32,
     base_a_fee_receiver: Pubkey,
     base_b_fee_receiver: Pubkey,
     admin: Pubkey,
@@ -270,7 +430,7 @@ const_assert_eq!(
     1 +   // market_state
     // 1 +   // base_a_marginfi_account_bump
     // 1 +   // base_b_marginfi_account_bump
-    4 +   // _padding1
+    4 +   // circuit_breaker_slots
     32 +  // base_a_mint
     32 +  // base_b_mint
     32 +  // base_a_vault
@@ -288,7 +448,7 @@ const_assert_eq!(
     4 +   // base_b_asks_best_index
     4 +   // claimed_seats_root_index
     4 +   // free_list_head_index
-    4 +   // _padding2
+    4 +   // post_only_until_slot
     32 +  // base_a_marginfi_group
     32 +  // base_a_marginfi_bank
     32 +  // base_a_marginfi_account
@@ -302,18 +462,72 @@ const_assert_eq!(
     16 + // base_a_marginfi_account_liability_shares
     16 + // base_b_marginfi_account_shares
     16 + // base_b_marginfi_account_liability_shares
-    (16 * 8) // _padding3: [u64; 23]
+    16 + // base_a_protocol_fee_shares
+    16 + // base_b_protocol_fee_shares
+    2 +   // min_reverse_spread_bps
+    2 +   // max_reverse_spread_bps
+    2 +   // max_open_orders_per_seat
+    1 +   // fee_on_interest
+    1 +   // auction_mode
+    4 +   // base_a_last_snapshot_slot
+    4 +   // base_b_last_snapshot_slot
+    32 +  // allowlist_authority
+    32 +  // event_queue
+    2 +   // max_borrow_utilization_bps
+    2 +   // base_a_last_matched_rate_bps
+    2 +   // base_b_last_matched_rate_bps
+    2 +   // circuit_breaker_bps
+    4 +   // base_a_twar_rate_milli_bps
+    4 +   // base_b_twar_rate_milli_bps
+    4 +   // base_a_rate_update_slot
+    4 +   // base_b_rate_update_slot
+    8 +   // last_oracle_price_ratio
+    32 +  // allowed_caller
+    8 +   // base_a_bank_config_hash
+    8 // base_b_bank_config_hash
 );
 
 const_assert_eq!(size_of::<MarketFixed>(), MARKET_FIXED_SIZE);
 const_assert_eq!(size_of::<MarketFixed>() % 8, 0);
 impl Get for MarketFixed {}
+impl StableName for MarketFixed {
+    const STABLE_NAME: &'static str = "nix::MarketFixed";
+    const LEGACY_NAME: &'static str = "nix::state::market::MarketFixed";
+}
+
+impl ExpandableFixed for MarketFixed {
+    type FreeListPadding = MarketUnusedFreeListPadding;
+    const BLOCK_SIZE: usize = MARKET_BLOCK_SIZE;
+
+    fn get_free_list_head_index(&self) -> DataIndex {
+        self.free_list_head_index
+    }
+    fn set_free_list_head_index(&mut self, free_list_head_index: DataIndex) {
+        self.free_list_head_index = free_list_head_index;
+    }
+    fn get_num_bytes_allocated(&self) -> DataIndex {
+        self.num_bytes_allocated
+    }
+    fn set_num_bytes_allocated(&mut self, num_bytes_allocated: DataIndex) {
+        self.num_bytes_allocated = num_bytes_allocated;
+    }
+}
 
 impl MarketFixed {
+    #[cfg(feature = "program")]
     pub(crate) fn new_empty(
         ctx: &CreateMarketContext,
         protocol_fee_rate_bps: u64,
         ltv_buffer_bps: u64,
+        referral_bps: u64,
+        maker_rebate_bps: u64,
+        min_reverse_spread_bps: u16,
+        max_reverse_spread_bps: u16,
+        max_open_orders_per_seat: u16,
+        allowlist_authority: Pubkey,
+        fee_on_interest: bool,
+        max_borrow_utilization_bps: u16,
+        bank_config_hashes: [u64; 2],
     ) -> Self {
         let CreateMarketContext {
             base_a_mint,
@@ -323,6 +537,8 @@ impl MarketFixed {
             base_b_marginfi_group,
             base_a_marginfi_bank,
             base_b_marginfi_bank,
+            base_a_marginfi_account,
+            base_b_marginfi_account,
             admin,
             ..
         } = ctx;
@@ -332,18 +548,21 @@ impl MarketFixed {
             get_market_fee_receiver_address(market.key, base_a_mint.as_ref().key);
         let (base_b_fee_receiver, _) =
             get_market_fee_receiver_address(market.key, base_b_mint.as_ref().key);
-        let (base_a_marginfi_account, _) =
-            get_nix_marginfi_account_address(market.key, base_a_mint.as_ref().key);
-        let (base_b_marginfi_account, _) =
-            get_nix_marginfi_account_address(market.key, base_b_mint.as_ref().key);
+        // The side of a one sided market without a bank keeps default keys.
+        fn get_optional_key<T: Pod + Zeroable>(account: &Option<MarginfiAccountInfo<T>>) -> Pubkey {
+            account
+                .as_ref()
+                .map(|account| *account.info.key)
+                .unwrap_or_default()
+        }
 
         MarketFixed {
             discriminant: get_discriminant::<MarketFixed>().unwrap(),
-            version: 1,
+            version: MARKET_VERSION,
             base_a_mint_decimals: ctx.base_a_mint.mint.decimals,
             base_b_mint_decimals: ctx.base_b_mint.mint.decimals,
             market_state: 0,
-            _padding1: Default::default(),
+            circuit_breaker_slots: 0,
             base_a_mint: *base_a_mint.as_ref().key,
             base_b_mint: *base_b_mint.as_ref().key,
             base_a_vault,
@@ -361,16 +580,23 @@ impl MarketFixed {
             base_b_asks_best_index: NIL,
             claimed_seats_root_index: NIL,
             free_list_head_index: NIL,
-            _padding2: Default::default(),
-            base_a_marginfi_group: *base_a_marginfi_group.as_ref().key,
-            base_a_marginfi_bank: *base_a_marginfi_bank.as_ref().key,
-            base_a_marginfi_account,
-            base_b_marginfi_group: *base_b_marginfi_group.as_ref().key,
-            base_b_marginfi_bank: *base_b_marginfi_bank.as_ref().key,
-            base_b_marginfi_account,
+            post_only_until_slot: 0,
+            base_a_marginfi_group: get_optional_key(base_a_marginfi_group),
+            base_a_marginfi_bank: get_optional_key(base_a_marginfi_bank),
+            base_a_marginfi_account: get_optional_key(base_a_marginfi_account),
+            base_b_marginfi_group: get_optional_key(base_b_marginfi_group),
+            base_b_marginfi_bank: get_optional_key(base_b_marginfi_bank),
+            base_b_marginfi_account: get_optional_key(base_b_marginfi_account),
             fee_state: FeeState {
                 protocol_fee_rate_bps,
                 ltv_buffer_bps,
+                // Checked against 10_000 by CreateMarket.
+                referral_bps: referral_bps as u16,
+                debug_log_flags: 0,
+                _padding_referral: [0; 5],
+                maker_rebate_bps: maker_rebate_bps as u16,
+                max_orders_per_window: 0,
+                order_window_slots: 0,
                 base_a_fee_receiver,
                 base_b_fee_receiver,
                 admin: *admin.as_ref().key,
@@ -381,7 +607,29 @@ impl MarketFixed {
             base_a_marginfi_account_liability_shares: Default::default(),
             base_b_marginfi_account_shares: Default::default(),
             base_b_marginfi_account_liability_shares: Default::default(),
-            _padding3: Default::default(),
+            base_a_protocol_fee_shares: Default::default(),
+            base_b_protocol_fee_shares: Default::default(),
+            min_reverse_spread_bps,
+            max_reverse_spread_bps,
+            max_open_orders_per_seat,
+            fee_on_interest: PodBool::from(fee_on_interest),
+            auction_mode: PodBool::from(false),
+            base_a_last_snapshot_slot: 0,
+            base_b_last_snapshot_slot: 0,
+            allowlist_authority,
+            event_queue: Pubkey::default(),
+            max_borrow_utilization_bps,
+            base_a_last_matched_rate_bps: 0,
+            base_b_last_matched_rate_bps: 0,
+            circuit_breaker_bps: 0,
+            base_a_twar_rate_milli_bps: 0,
+            base_b_twar_rate_milli_bps: 0,
+            base_a_rate_update_slot: 0,
+            base_b_rate_update_slot: 0,
+            last_oracle_price_ratio: 0,
+            allowed_caller: Pubkey::default(),
+            base_a_bank_config_hash: bank_config_hashes[0],
+            base_b_bank_config_hash: bank_config_hashes[1],
         }
     }
 
@@ -429,6 +677,45 @@ impl MarketFixed {
         &self.base_b_marginfi_bank
     }
 
+    /// Whether the marginfi bank lends on base A of the market, None for a
+    /// bank of neither side.
+    pub fn is_base_a_marginfi_bank(&self, marginfi_bank: &Pubkey) -> Option<bool> {
+        if *marginfi_bank == Pubkey::default() {
+            None
+        } else if *marginfi_bank == self.base_a_marginfi_bank {
+            Some(true)
+        } else if *marginfi_bank == self.base_b_marginfi_bank {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    pub fn get_bank_config_hash(&self, is_base_a: bool) -> u64 {
+        if is_base_a {
+            self.base_a_bank_config_hash
+        } else {
+            self.base_b_bank_config_hash
+        }
+    }
+    pub(crate) fn set_bank_config_hash(&mut self, is_base_a: bool, bank_config_hash: u64) {
+        if is_base_a {
+            self.base_a_bank_config_hash = bank_config_hash;
+        } else {
+            self.base_b_bank_config_hash = bank_config_hash;
+        }
+    }
+
+    /// False for the side of a one sided market that was created without a
+    /// marginfi bank. Nothing can be lent or borrowed on that side.
+    pub fn has_marginfi_bank(&self, is_base_a: bool) -> bool {
+        if is_base_a {
+            self.base_a_marginfi_bank != Pubkey::default()
+        } else {
+            self.base_b_marginfi_bank != Pubkey::default()
+        }
+    }
+
     pub fn get_base_a_order_sequence_number(&self) -> u64 {
         self.base_a_order_sequence_number
     }
@@ -441,6 +728,345 @@ impl MarketFixed {
     pub fn get_admin(&self) -> &Pubkey {
         &self.fee_state.admin
     }
+    pub fn get_protocol_fee_rate_bps(&self) -> u64 {
+        self.fee_state.protocol_fee_rate_bps
+    }
+    pub fn get_referral_bps(&self) -> u64 {
+        self.fee_state.referral_bps as u64
+    }
+    pub fn get_maker_rebate_bps(&self) -> u64 {
+        self.fee_state.maker_rebate_bps as u64
+    }
+    pub fn get_ltv_buffer_bps(&self) -> u64 {
+        self.fee_state.ltv_buffer_bps
+    }
+    pub fn get_base_a_protocol_fee_shares(&self) -> WrappedI80F48 {
+        self.base_a_protocol_fee_shares
+    }
+    pub fn get_base_b_protocol_fee_shares(&self) -> WrappedI80F48 {
+        self.base_b_protocol_fee_shares
+    }
+    /// Moves the share of the protocol fees charged since the last accrual
+    /// into the insurance fund of the market.
+    pub(crate) fn accrue_insurance_fees(
+        &mut self,
+        market_insurance: &mut MarketInsurance,
+    ) -> ProgramResult {
+        self.base_a_protocol_fee_shares =
+            market_insurance.accrue_fees(true, self.base_a_protocol_fee_shares)?;
+        self.base_b_protocol_fee_shares =
+            market_insurance.accrue_fees(false, self.base_b_protocol_fee_shares)?;
+        Ok(())
+    }
+    pub fn get_base_a_seat_asset_shares(&self) -> WrappedI80F48 {
+        self.base_a_marginfi_account_asset_shares
+    }
+    pub fn get_base_b_seat_asset_shares(&self) -> WrappedI80F48 {
+        self.base_b_marginfi_account_shares
+    }
+    pub fn get_base_a_seat_liability_shares(&self) -> WrappedI80F48 {
+        self.base_a_marginfi_account_liability_shares
+    }
+    pub fn get_base_b_seat_liability_shares(&self) -> WrappedI80F48 {
+        self.base_b_marginfi_account_liability_shares
+    }
+    pub fn get_claimed_seats_root_index(&self) -> DataIndex {
+        self.claimed_seats_root_index
+    }
+    pub fn get_min_reverse_spread_bps(&self) -> u16 {
+        self.min_reverse_spread_bps
+    }
+    pub fn get_max_reverse_spread_bps(&self) -> u16 {
+        self.max_reverse_spread_bps
+    }
+    pub fn get_allowlist_authority(&self) -> &Pubkey {
+        &self.allowlist_authority
+    }
+    pub fn is_permissioned(&self) -> bool {
+        self.allowlist_authority != Pubkey::default()
+    }
+    pub fn get_allowed_caller(&self) -> &Pubkey {
+        &self.allowed_caller
+    }
+    pub fn has_allowed_caller(&self) -> bool {
+        self.allowed_caller != Pubkey::default()
+    }
+    pub(crate) fn set_allowed_caller(&mut self, allowed_caller: &Pubkey) {
+        self.allowed_caller = *allowed_caller;
+    }
+    pub fn get_event_queue(&self) -> &Pubkey {
+        &self.event_queue
+    }
+    pub fn has_event_queue(&self) -> bool {
+        self.event_queue != Pubkey::default()
+    }
+    pub fn set_event_queue(&mut self, event_queue: Pubkey) {
+        self.event_queue = event_queue;
+    }
+    /// Rate limits book snapshots to one per slot for each tree.
+    pub fn record_book_snapshot(&mut self, use_a_tree: bool, now_slot: u32) -> ProgramResult {
+        let last_snapshot_slot: &mut u32 = if use_a_tree {
+            &mut self.base_a_last_snapshot_slot
+        } else {
+            &mut self.base_b_last_snapshot_slot
+        };
+        require!(
+            *last_snapshot_slot == 0 || *last_snapshot_slot < now_slot,
+            NixError::BookSnapshotRateLimited,
+            "Book snapshot already emitted at slot {}",
+            last_snapshot_slot,
+        )?;
+        *last_snapshot_slot = now_slot;
+        Ok(())
+    }
+    pub fn get_max_open_orders_per_seat(&self) -> u16 {
+        self.max_open_orders_per_seat
+    }
+    pub fn is_fee_on_interest(&self) -> bool {
+        self.fee_on_interest.0 == 1
+    }
+    pub fn is_auction_mode(&self) -> bool {
+        self.auction_mode.0 == 1
+    }
+    pub(crate) fn set_auction_mode(&mut self, auction_mode: bool) {
+        self.auction_mode = PodBool::from(auction_mode);
+    }
+    pub fn get_max_borrow_utilization_bps(&self) -> u16 {
+        self.max_borrow_utilization_bps
+    }
+    pub fn get_version(&self) -> u8 {
+        self.version
+    }
+
+    /// Rate of the last fill on the tree worth at least
+    /// RATE_ORACLE_MIN_FILL_USD, or None if it never had one.
+    pub fn get_last_matched_rate_bps(&self, use_a_tree: bool) -> Option<u16> {
+        let (last_matched_rate_bps, _, rate_update_slot) = self.get_rate_oracle(use_a_tree);
+        if rate_update_slot == 0 {
+            return None;
+        }
+        Some(last_matched_rate_bps)
+    }
+
+    /// Exponentially weighted average over time of the tree's fill rate, as
+    /// of now_slot. Each rate counts for as long as it was the last fill,
+    /// with weights decaying over RATE_ORACLE_WINDOW_SLOTS. A fill in the
+    /// current slot has no weight yet, so moving the average takes holding a
+    /// rate over time. None if the tree never had a fill.
+    pub fn get_twar_rate_bps(&self, use_a_tree: bool, now_slot: u32) -> Option<u16> {
+        let (_, _, rate_update_slot) = self.get_rate_oracle(use_a_tree);
+        if rate_update_slot == 0 {
+            return None;
+        }
+        let twar_rate_milli_bps: u32 = self.get_twar_rate_milli_bps(use_a_tree, now_slot);
+        Some(((twar_rate_milli_bps + RATE_MILLI_BPS / 2) / RATE_MILLI_BPS) as u16)
+    }
+
+    fn get_rate_oracle(&self, use_a_tree: bool) -> (u16, u32, u32) {
+        if use_a_tree {
+            (
+                self.base_a_last_matched_rate_bps,
+                self.base_a_twar_rate_milli_bps,
+                self.base_a_rate_update_slot,
+            )
+        } else {
+            (
+                self.base_b_last_matched_rate_bps,
+                self.base_b_twar_rate_milli_bps,
+                self.base_b_rate_update_slot,
+            )
+        }
+    }
+
+    /// Average rolled forward from the last update to now_slot, during which
+    /// the last matched rate was the prevailing one.
+    fn get_twar_rate_milli_bps(&self, use_a_tree: bool, now_slot: u32) -> u32 {
+        let (last_matched_rate_bps, twar_rate_milli_bps, rate_update_slot) =
+            self.get_rate_oracle(use_a_tree);
+        let elapsed_slots: u64 = now_slot.saturating_sub(rate_update_slot) as u64;
+        let last_matched_rate_milli_bps: i64 = last_matched_rate_bps as i64 * RATE_MILLI_BPS as i64;
+        // Weight of the new rate is elapsed / (elapsed + window), a rational
+        // stand in for 1 - exp(-elapsed / window).
+        let delta_milli_bps: i64 = (last_matched_rate_milli_bps - twar_rate_milli_bps as i64)
+            * elapsed_slots as i64
+            / (elapsed_slots + RATE_ORACLE_WINDOW_SLOTS as u64) as i64;
+        (twar_rate_milli_bps as i64 + delta_milli_bps) as u32
+    }
+
+    /// Records a fill at rate_bps on the tree for the rate oracle. Fills
+    /// worth less than RATE_ORACLE_MIN_FILL_USD are left out.
+    pub(crate) fn record_matched_rate(
+        &mut self,
+        use_a_tree: bool,
+        rate_bps: u16,
+        fill_value_usd: I80F48,
+        now_slot: u32,
+    ) {
+        if fill_value_usd < I80F48::from_num(RATE_ORACLE_MIN_FILL_USD) {
+            return;
+        }
+        let twar_rate_milli_bps: u32 = if self.get_rate_oracle(use_a_tree).2 == 0 {
+            rate_bps as u32 * RATE_MILLI_BPS
+        } else {
+            self.get_twar_rate_milli_bps(use_a_tree, now_slot)
+        };
+        if use_a_tree {
+            self.base_a_last_matched_rate_bps = rate_bps;
+            self.base_a_twar_rate_milli_bps = twar_rate_milli_bps;
+            self.base_a_rate_update_slot = now_slot;
+        } else {
+            self.base_b_last_matched_rate_bps = rate_bps;
+            self.base_b_twar_rate_milli_bps = twar_rate_milli_bps;
+            self.base_b_rate_update_slot = now_slot;
+        }
+    }
+
+    pub fn get_circuit_breaker_bps(&self) -> u16 {
+        self.circuit_breaker_bps
+    }
+    pub fn get_circuit_breaker_slots(&self) -> u32 {
+        self.circuit_breaker_slots
+    }
+    pub fn get_post_only_until_slot(&self) -> u32 {
+        self.post_only_until_slot
+    }
+
+    /// Whether a tripped circuit breaker keeps orders that can take off the
+    /// market at now_slot.
+    pub fn is_post_only(&self, now_slot: u32) -> bool {
+        now_slot < self.post_only_until_slot
+    }
+
+    /// Sets the circuit breaker and clears its state, which also lifts a
+    /// trip early.
+    pub(crate) fn set_circuit_breaker(
+        &mut self,
+        circuit_breaker_bps: u16,
+        circuit_breaker_slots: u32,
+    ) {
+        self.circuit_breaker_bps = circuit_breaker_bps;
+        self.circuit_breaker_slots = circuit_breaker_slots;
+        self.post_only_until_slot = 0;
+        self.last_oracle_price_ratio = 0;
+    }
+
+    /// Records the base over quote oracle price. When it moved more than
+    /// circuit_breaker_bps from the last recorded one, the market goes post
+    /// only for circuit_breaker_slots and the move in bps is returned.
+    pub(crate) fn update_circuit_breaker(
+        &mut self,
+        price_ratio: I80F48,
+        now_slot: u32,
+    ) -> Result<Option<u64>, ProgramError> {
+        let last_price_ratio: I80F48 =
+            I80F48::from_num(U32F32::from_bits(self.last_oracle_price_ratio));
+        self.last_oracle_price_ratio = price_ratio.saturating_to_num::<U32F32>().to_bits();
+        if self.circuit_breaker_bps == 0 || last_price_ratio == I80F48::ZERO {
+            return Ok(None);
+        }
+        let move_bps: I80F48 = price_ratio
+            .checked_sub(last_price_ratio)
+            .and_then(|delta| delta.abs().checked_mul(I80F48::from_num(10_000)))
+            .and_then(|delta| delta.checked_div(last_price_ratio))
+            .ok_or(NixError::NumericalOverflow)?;
+        if move_bps <= I80F48::from_num(self.circuit_breaker_bps) {
+            return Ok(None);
+        }
+        self.post_only_until_slot = now_slot.saturating_add(self.circuit_breaker_slots);
+        Ok(Some(move_bps.saturating_to_num::<u64>()))
+    }
+
+    /// Max new resting orders per seat and the window they are counted over,
+    /// in slots. Zero orders means no limit.
+    pub fn get_order_rate_limit(&self) -> (u16, u32) {
+        (
+            self.fee_state.max_orders_per_window,
+            self.fee_state.order_window_slots,
+        )
+    }
+
+    pub(crate) fn set_order_rate_limit(
+        &mut self,
+        max_orders_per_window: u16,
+        order_window_slots: u32,
+    ) {
+        self.fee_state.max_orders_per_window = max_orders_per_window;
+        self.fee_state.order_window_slots = order_window_slots;
+    }
+
+    pub fn get_debug_log_flags(&self) -> u8 {
+        self.fee_state.debug_log_flags
+    }
+    pub fn is_debug_log_enabled(&self, flag: u8) -> bool {
+        self.fee_state.debug_log_flags & flag != 0
+    }
+    pub(crate) fn set_debug_log_flags(&mut self, debug_log_flags: u8) {
+        self.fee_state.debug_log_flags = debug_log_flags;
+    }
+
+    /// Brings the header up to MARKET_VERSION one version at a time and
+    /// returns the version it started from. Fields added in a version live in
+    /// what used to be padding, so each step only has to give them a value.
+    pub fn migrate(&mut self) -> Result<u8, ProgramError> {
+        let from_version: u8 = self.version;
+        require!(
+            from_version >= MARKET_BLOCK_LAYOUT_VERSION,
+            NixError::MarketNotMigratable,
+            "Market version {} has blocks of another layout than version {}",
+            from_version,
+            MARKET_BLOCK_LAYOUT_VERSION,
+        )?;
+        while self.version < MARKET_VERSION {
+            match self.version {
+                4 => {
+                    // Version 5 added auction mode. It starts out off.
+                    self.auction_mode = PodBool::from(false);
+                }
+                5 => {
+                    // Version 6 added the order rate limit. It starts out
+                    // off.
+                    self.set_order_rate_limit(0, 0);
+                }
+                6 => {
+                    // Version 7 added allowed_caller past the old end of the
+                    // header. MigrateMarket moved the dynamic bytes out of
+                    // its way, it still holds what they were.
+                    self.allowed_caller = Pubkey::default();
+                }
+                7 => {
+                    // Version 8 added the debug log flags. They start out
+                    // off.
+                    self.set_debug_log_flags(0);
+                }
+                8 => {
+                    // Version 9 added the bank config hashes past the old end
+                    // of the header. Migrating cannot read the banks, so the
+                    // next order that does records them.
+                    self.base_a_bank_config_hash = 0;
+                    self.base_b_bank_config_hash = 0;
+                }
+                9 => {
+                    // Version 10 is the first that MigrateMarket checks for
+                    // the block layout. Nothing in the header changed.
+                }
+                _ => {
+                    return Err(NixError::MarketVersionMismatch.into());
+                }
+            }
+            self.version += 1;
+        }
+        // Markets created before the stable names carry the legacy
+        // discriminant. Any migration moves them to the stable one.
+        self.discriminant = get_discriminant::<MarketFixed>()?;
+        require!(
+            self.version == MARKET_VERSION,
+            NixError::MarketVersionMismatch,
+            "Market version {} is newer than {}",
+            self.version,
+            MARKET_VERSION,
+        )?;
+        Ok(from_version)
+    }
 }
 
 impl NixAccount for MarketFixed {
@@ -448,7 +1074,7 @@ impl NixAccount for MarketFixed {
         let expected_discriminant: u64 = crate::utils::get_discriminant::<MarketFixed>().unwrap();
 
         require!(
-            self.discriminant == expected_discriminant,
+            is_discriminant_of::<MarketFixed>(self.discriminant),
             ProgramError::InvalidAccountData,
             "Invalid market discriminant actual: {} expected: {}",
             self.discriminant,
@@ -456,6 +1082,17 @@ impl NixAccount for MarketFixed {
         )?;
         Ok(())
     }
+
+    fn verify_version(&self) -> ProgramResult {
+        require!(
+            self.version == MARKET_VERSION,
+            NixError::MarketVersionMismatch,
+            "Market version {} does not match {}, run MigrateMarket",
+            self.version,
+            MARKET_VERSION,
+        )?;
+        Ok(())
+    }
 }
 
 /// Fully owned Market, used in clients that can copy.
@@ -523,6 +1160,20 @@ impl<Fixed: DerefOrBorrow<MarketFixed>, Dynamic: DerefOrBorrow<[u8]>>
             claimed_seats_tree.lookup_index(&ClaimedSeat::new_empty(*trader));
         trader_index
     }
+    /// Balances, volumes and open order count of the trader's seat. None
+    /// when the trader has no seat.
+    pub fn get_seat_snapshot(&self, trader: &Pubkey) -> Option<SeatSnapshot> {
+        let trader_index: DataIndex = self.get_trader_index(trader);
+        if trader_index == NIL {
+            return None;
+        }
+        let DynamicAccount { dynamic, .. } = self.borrow_market();
+        Some(
+            get_helper_seat(dynamic, trader_index)
+                .get_value()
+                .get_snapshot(),
+        )
+    }
     pub fn get_trader_key_by_index(&self, index: DataIndex) -> &Pubkey {
         let DynamicAccount { dynamic, .. } = self.borrow_market();
 
@@ -534,6 +1185,267 @@ impl<Fixed: DerefOrBorrow<MarketFixed>, Dynamic: DerefOrBorrow<[u8]>>
         &get_helper::<RBNode<RestingOrder>>(dynamic, index).get_value()
     }
 
+    /// Verifies there are no resting orders and no balances left on any seat,
+    /// so that closing the market cannot lose funds.
+    pub fn verify_empty_for_close(&self) -> ProgramResult {
+        let DynamicAccount { fixed, dynamic } = self.borrow_market();
+
+        require!(
+            fixed.base_a_bids_root_index == NIL
+                && fixed.base_a_asks_root_index == NIL
+                && fixed.base_b_bids_root_index == NIL
+                && fixed.base_b_asks_root_index == NIL,
+            NixError::MarketNotEmpty,
+            "Market still has resting orders",
+        )?;
+        // Protocol fees sit in the marginfi accounts of the market, which
+        // cannot be closed while they hold them.
+        require!(
+            I80F48::from(fixed.base_a_protocol_fee_shares) == 0
+                && I80F48::from(fixed.base_b_protocol_fee_shares) == 0,
+            NixError::MarketNotEmpty,
+            "Market still holds protocol fees",
+        )?;
+
+        let claimed_seats_tree: ClaimedSeatTreeReadOnly =
+            ClaimedSeatTreeReadOnly::new(dynamic, fixed.claimed_seats_root_index, NIL);
+        for (_, claimed_seat) in claimed_seats_tree.iter::<ClaimedSeat>() {
+            require!(
+                I80F48::from(claimed_seat.base_a_withdrawable_asset_share) == 0
+                    && I80F48::from(claimed_seat.base_b_withdrawable_asset_share) == 0
+                    && I80F48::from(claimed_seat.base_a_referral_fee_shares) == 0
+                    && I80F48::from(claimed_seat.base_b_referral_fee_shares) == 0
+                    && I80F48::from(claimed_seat.base_a_maker_rebate_shares) == 0
+                    && I80F48::from(claimed_seat.base_b_maker_rebate_shares) == 0
+                    && I80F48::from(claimed_seat.base_a_liability_shares) == 0
+                    && I80F48::from(claimed_seat.base_b_liability_shares) == 0,
+                NixError::MarketNotEmpty,
+                "Seat {} still has a balance",
+                claimed_seat.trader,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Rate of the first order on the opposite book side that a taker would
+    /// actually match, skipping expired and empty orders the same way
+    /// place_order does. None when the side has no live order within reach.
+    pub fn get_best_opposing_rate_bps(
+        &self,
+        use_a_tree: bool,
+        is_bid: bool,
+        now_slot: u32,
+        now_unix_timestamp: i64,
+    ) -> Option<u16> {
+        let DynamicAccount { fixed, dynamic } = self.borrow_market();
+        let (bids_best_index, asks_best_index, bids_root_index, asks_root_index) =
+            get_tree_indexes(fixed, use_a_tree);
+        let tree: BooksideReadOnly = if is_bid {
+            BooksideReadOnly::new(dynamic, asks_root_index, asks_best_index)
+        } else {
+            BooksideReadOnly::new(dynamic, bids_root_index, bids_best_index)
+        };
+        tree.iter::<RestingOrder>()
+            .take(MAX_MATCHED_LOANS)
+            .map(|(_, maker_order)| maker_order)
+            .find(|maker_order| {
+                !maker_order.is_expired(now_slot, now_unix_timestamp)
+                    && I80F48::from(maker_order.get_collateral_shares()) != 0
+            })
+            .map(|maker_order| maker_order.get_rate_bps())
+    }
+
+    /// Number of orders on one book side that CleanExpiredOrders would
+    /// remove. For keepers deciding whether a crank is worth sending.
+    pub fn count_expired_orders(
+        &self,
+        use_a_tree: bool,
+        is_bid: bool,
+        now_slot: u32,
+        now_unix_timestamp: i64,
+    ) -> u32 {
+        let DynamicAccount { fixed, dynamic } = self.borrow_market();
+        let (bids_best_index, asks_best_index, bids_root_index, asks_root_index) =
+            get_tree_indexes(fixed, use_a_tree);
+        let tree: BooksideReadOnly = if is_bid {
+            BooksideReadOnly::new(dynamic, bids_root_index, bids_best_index)
+        } else {
+            BooksideReadOnly::new(dynamic, asks_root_index, asks_best_index)
+        };
+        tree.iter::<RestingOrder>()
+            .filter(|(_, resting_order)| resting_order.is_expired(now_slot, now_unix_timestamp))
+            .count() as u32
+    }
+
+    /// Runs the same walk as place_order over the opposite book side without
+    /// mutating state. Expired and empty orders are skipped rather than
+    /// removed. Global orders are assumed to be backed. Stops after
+    /// `match_limit` maker orders, capped at MAX_MATCHED_LOANS like
+    /// place_order.
+    pub fn quote_order(
+        &self,
+        use_a_tree: bool,
+        is_bid: bool,
+        num_base_atoms: u64,
+        rate_bps: u16,
+        match_limit: u32,
+        now_slot: u32,
+        now_unix_timestamp: i64,
+        base_marginfi_bank: &BankShareValues,
+        quote_marginfi_bank: &BankShareValues,
+        base_oracle_price_usd: I80F48,
+        quote_oracle_price_usd: I80F48,
+    ) -> Result<QuoteOrderResult, ProgramError> {
+        let DynamicAccount { fixed, dynamic } = self.borrow_market();
+        let (bids_best_index, asks_best_index, bids_root_index, asks_root_index) =
+            get_tree_indexes(fixed, use_a_tree);
+        let tree: BooksideReadOnly = if is_bid {
+            BooksideReadOnly::new(dynamic, asks_root_index, asks_best_index)
+        } else {
+            BooksideReadOnly::new(dynamic, bids_root_index, bids_best_index)
+        };
+
+        let params: MatchBankParams = MatchBankParams::new(
+            *base_marginfi_bank,
+            *quote_marginfi_bank,
+            base_oracle_price_usd,
+            quote_oracle_price_usd,
+            fixed.fee_state.ltv_buffer_bps,
+        )?;
+        // place_order stops after MAX_MATCHED_LOANS maker orders, skipped
+        // ones included.
+        let plan: MatchPlan = plan_matches(
+            tree.iter::<RestingOrder>(),
+            is_bid,
+            rate_bps,
+            num_base_atoms,
+            now_slot,
+            now_unix_timestamp,
+            match_limit.min(MAX_MATCHED_LOANS as u32),
+            &params,
+        )?;
+
+        let result: QuoteOrderResult = QuoteOrderResult {
+            base_atoms: plan.base_atoms,
+            quote_atoms: plan.quote_atoms,
+            num_maker_orders: plan.get_num_fills(),
+            weighted_rate_bps: plan.get_weighted_rate_bps(),
+        };
+        Ok(result)
+    }
+
+    /// Same as aggregate_levels, in the fixed size array of a BookSnapshotLog
+    /// with the number of levels filled in.
+    pub fn get_book_levels(
+        &self,
+        use_a_tree: bool,
+        is_bid: bool,
+        max_levels: usize,
+        now_slot: u32,
+        now_unix_timestamp: i64,
+    ) -> Result<([BookLevel; MAX_BOOK_SNAPSHOT_LEVELS], u8), ProgramError> {
+        let mut levels: [BookLevel; MAX_BOOK_SNAPSHOT_LEVELS] =
+            [BookLevel::default(); MAX_BOOK_SNAPSHOT_LEVELS];
+        let aggregated_levels: Vec<BookLevel> = self.aggregate_levels(
+            use_a_tree,
+            is_bid,
+            max_levels.min(MAX_BOOK_SNAPSHOT_LEVELS),
+            now_slot,
+            now_unix_timestamp,
+        )?;
+        levels[..aggregated_levels.len()].copy_from_slice(&aggregated_levels);
+        Ok((levels, aggregated_levels.len() as u8))
+    }
+
+    /// Aggregates one side of a book into up to num_levels rate levels, best
+    /// rate first, merging the orders at each rate. For routers that quote
+    /// off the depth rather than single orders. Expired orders are skipped
+    /// since they can no longer match.
+    pub fn aggregate_levels(
+        &self,
+        use_a_tree: bool,
+        is_bid: bool,
+        num_levels: usize,
+        now_slot: u32,
+        now_unix_timestamp: i64,
+    ) -> Result<Vec<BookLevel>, ProgramError> {
+        let DynamicAccount { fixed, dynamic } = self.borrow_market();
+        let (root_index, best_index) = match (use_a_tree, is_bid) {
+            (true, true) => (fixed.base_a_bids_root_index, fixed.base_a_bids_best_index),
+            (true, false) => (fixed.base_a_asks_root_index, fixed.base_a_asks_best_index),
+            (false, true) => (fixed.base_b_bids_root_index, fixed.base_b_bids_best_index),
+            (false, false) => (fixed.base_b_asks_root_index, fixed.base_b_asks_best_index),
+        };
+        let tree: BooksideReadOnly = BooksideReadOnly::new(dynamic, root_index, best_index);
+
+        let mut levels: Vec<BookLevel> = Vec::with_capacity(num_levels);
+        for (_, resting_order) in tree.iter::<RestingOrder>() {
+            if resting_order.is_expired(now_slot, now_unix_timestamp) {
+                continue;
+            }
+            let order_shares: I80F48 = if is_bid {
+                resting_order.get_liability_shares().into()
+            } else {
+                resting_order.get_collateral_shares().into()
+            };
+            let rate_bps: u16 = resting_order.get_rate_bps();
+            if let Some(level) = levels.last_mut().filter(|level| level.rate_bps == rate_bps) {
+                level.num_orders = level.num_orders.saturating_add(1);
+                level.total_shares = level
+                    .total_shares
+                    .checked_add(order_shares)
+                    .ok_or(NixError::NumericalOverflow)?;
+                continue;
+            }
+            if levels.len() == num_levels {
+                break;
+            }
+            levels.push(BookLevel {
+                rate_bps,
+                num_orders: 1,
+                _padding: [0; 4],
+                total_shares: WrappedI80F48::from(order_shares),
+            });
+        }
+        Ok(levels)
+    }
+
+    /// Rate a batch auction over the resting orders of a tree clears at, with
+    /// the base atoms it clears. Expired orders and loan sales, which are
+    /// only bought whole, are left out.
+    #[cfg(feature = "program")]
+    pub fn get_auction_clearing(
+        &self,
+        use_a_tree: bool,
+        base_bank: &BankShareValues,
+        now_slot: u32,
+        now_unix_timestamp: i64,
+    ) -> Result<Option<(u16, u64)>, ProgramError> {
+        let DynamicAccount { fixed, dynamic } = self.borrow_market();
+        let (bids_best_index, asks_best_index, bids_root_index, asks_root_index) =
+            get_tree_indexes(fixed, use_a_tree);
+        let mut bids: Vec<(u16, u64)> = Vec::new();
+        let mut asks: Vec<(u16, u64)> = Vec::new();
+        for (side, root_index, best_index) in [
+            (&mut bids, bids_root_index, bids_best_index),
+            (&mut asks, asks_root_index, asks_best_index),
+        ] {
+            let tree: BooksideReadOnly = BooksideReadOnly::new(dynamic, root_index, best_index);
+            for (_, resting_order) in tree.iter::<RestingOrder>() {
+                if resting_order.is_expired(now_slot, now_unix_timestamp)
+                    || resting_order.is_loan_sale()
+                {
+                    continue;
+                }
+                let base_atoms: u64 = resting_order.get_num_base_atoms(base_bank)?;
+                if base_atoms > 0 {
+                    side.push((resting_order.get_rate_bps(), base_atoms));
+                }
+            }
+        }
+        Ok(get_clearing_rate(&bids, &asks))
+    }
+
 }
 
 // This generic impl covers MarketRef, MarketRefMut and other
@@ -552,49 +1464,226 @@ impl<
 
     pub fn market_expand(&mut self) -> ProgramResult {
         let DynamicAccount { fixed, dynamic } = self.borrow_mut();
-        let mut free_list: FreeList<MarketUnusedFreeListPadding> =
-            FreeList::new(dynamic, fixed.free_list_head_index);
-
-        free_list.add(fixed.num_bytes_allocated);
-        fixed.num_bytes_allocated += MARKET_BLOCK_SIZE as u32;
-        fixed.free_list_head_index = free_list.get_head();
+        expand_blocks(fixed, dynamic, 1);
         Ok(())
     }
 
     pub fn claim_seat(&mut self, trader: &Pubkey) -> ProgramResult {
         let DynamicAccount { fixed, dynamic } = self.borrow_mut();
-        let free_address: DataIndex = get_free_address_on_market_fixed_for_seat(fixed, dynamic);
-
-        let mut claimed_seats_tree: ClaimedSeatTree =
-            ClaimedSeatTree::new(dynamic, fixed.claimed_seats_root_index, NIL);
-
         let claimed_seat: ClaimedSeat = ClaimedSeat::new_empty(*trader);
 
+        let claimed_seats_tree: ClaimedSeatTreeReadOnly =
+            ClaimedSeatTreeReadOnly::new(dynamic, fixed.claimed_seats_root_index, NIL);
         require!(
             claimed_seats_tree.lookup_index(&claimed_seat) == NIL,
             NixError::AlreadyClaimedSeat,
             "Already claimed seat",
         )?;
-        claimed_seats_tree.insert(free_address, claimed_seat);
-        fixed.claimed_seats_root_index = claimed_seats_tree.get_root_index();
-        get_mut_helper::<RBNode<ClaimedSeat>>(dynamic, free_address)
+
+        let claimed_seats_root_index: DataIndex = fixed.claimed_seats_root_index;
+        let update: TreeNodeUpdate =
+            insert_node(fixed, dynamic, claimed_seats_root_index, NIL, claimed_seat);
+        fixed.claimed_seats_root_index = update.root_index;
+        get_mut_helper::<RBNode<ClaimedSeat>>(dynamic, update.node_index)
             .set_payload_type(MarketDataTreeNodeType::ClaimedSeat as u8);
         Ok(())
     }
 
-    pub fn deposit(
+    /// Moves the referral fees accrued on a seat into its withdrawable
+    /// balance. Returns the (base a, base b) asset shares moved.
+    pub fn claim_referral_fees(
         &mut self,
         trader_index: DataIndex,
-        asset_shares: WrappedI80F48,
-        update_base_a: bool,
-    ) -> ProgramResult {
-        require!(
-            is_not_nil!(trader_index),
-            NixError::InvalidDepositAccounts,
-            "No seat initialized",
-        )?;
+    ) -> Result<(WrappedI80F48, WrappedI80F48), ProgramError> {
+        assert_already_has_seat(trader_index)?;
         let DynamicAccount { fixed, dynamic } = self.borrow_mut();
-        update_balance(
+
+        let claimed_seat: &mut ClaimedSeat =
+            get_mut_helper_seat(dynamic, trader_index).get_mut_value();
+        let base_a_referral_fee_shares: WrappedI80F48 = claimed_seat.base_a_referral_fee_shares;
+        let base_b_referral_fee_shares: WrappedI80F48 = claimed_seat.base_b_referral_fee_shares;
+        claimed_seat.base_a_referral_fee_shares = WrappedI80F48::ZERO;
+        claimed_seat.base_b_referral_fee_shares = WrappedI80F48::ZERO;
+
+        update_balance(
+            fixed,
+            dynamic,
+            trader_index,
+            true,
+            true,
+            base_a_referral_fee_shares,
+        )?;
+        update_balance(
+            fixed,
+            dynamic,
+            trader_index,
+            false,
+            true,
+            base_b_referral_fee_shares,
+        )?;
+        Ok((base_a_referral_fee_shares, base_b_referral_fee_shares))
+    }
+
+    /// Moves the maker rebates accrued on a seat into its withdrawable
+    /// balance. Returns the (base a, base b) asset shares moved.
+    pub fn claim_maker_rebates(
+        &mut self,
+        trader_index: DataIndex,
+    ) -> Result<(WrappedI80F48, WrappedI80F48), ProgramError> {
+        assert_already_has_seat(trader_index)?;
+        let DynamicAccount { fixed, dynamic } = self.borrow_mut();
+
+        let claimed_seat: &mut ClaimedSeat =
+            get_mut_helper_seat(dynamic, trader_index).get_mut_value();
+        let base_a_maker_rebate_shares: WrappedI80F48 = claimed_seat.base_a_maker_rebate_shares;
+        let base_b_maker_rebate_shares: WrappedI80F48 = claimed_seat.base_b_maker_rebate_shares;
+        claimed_seat.base_a_maker_rebate_shares = WrappedI80F48::ZERO;
+        claimed_seat.base_b_maker_rebate_shares = WrappedI80F48::ZERO;
+
+        update_balance(
+            fixed,
+            dynamic,
+            trader_index,
+            true,
+            true,
+            base_a_maker_rebate_shares,
+        )?;
+        update_balance(
+            fixed,
+            dynamic,
+            trader_index,
+            false,
+            true,
+            base_b_maker_rebate_shares,
+        )?;
+        Ok((base_a_maker_rebate_shares, base_b_maker_rebate_shares))
+    }
+
+    /// Moves what a repaid loan owes into withdrawable balances. The lender
+    /// gets the liability shares in the lent mint, the borrower gets the
+    /// collateral shares back in the other one.
+    #[cfg(feature = "program")]
+    pub fn settle_loan_proceeds(&mut self, loan: &ActiveLoan) -> ProgramResult {
+        assert_already_has_seat(loan.lender_index)?;
+        assert_already_has_seat(loan.borrower_index)?;
+        require!(
+            loan.status == LoanStatus::Repaid,
+            NixError::LoanNotRepaid,
+            "Loan {} has not been repaid",
+            loan.sequence_number,
+        )?;
+        let DynamicAccount { fixed, dynamic } = self.borrow_mut();
+
+        let is_liability_base_a: bool = loan.is_liability_base_a.0 == 1;
+        update_balance(
+            fixed,
+            dynamic,
+            loan.lender_index,
+            is_liability_base_a,
+            true,
+            loan.liability_shares,
+        )?;
+        update_balance(
+            fixed,
+            dynamic,
+            loan.borrower_index,
+            !is_liability_base_a,
+            true,
+            loan.collateral_shares,
+        )?;
+        decrease_seat_liability(
+            fixed,
+            dynamic,
+            loan.borrower_index,
+            is_liability_base_a,
+            loan.liability_shares.into(),
+        )?;
+        Ok(())
+    }
+
+    /// Writes off a defaulted loan. The lender takes the collateral shares in
+    /// place of the repayment, plus insurance_shares of the lent mint the
+    /// insurance fund covered. The rest of the shortfall comes out of its
+    /// claim, the borrower gets nothing back.
+    #[cfg(feature = "program")]
+    pub fn write_off_defaulted_loan(
+        &mut self,
+        loan: &ActiveLoan,
+        insurance_shares: WrappedI80F48,
+    ) -> ProgramResult {
+        assert_already_has_seat(loan.lender_index)?;
+        require!(
+            loan.status == LoanStatus::Defaulted,
+            NixError::LoanNotInDefault,
+            "Loan {} has not defaulted",
+            loan.sequence_number,
+        )?;
+        let DynamicAccount { fixed, dynamic } = self.borrow_mut();
+
+        update_balance(
+            fixed,
+            dynamic,
+            loan.lender_index,
+            loan.is_liability_base_a.0 == 0,
+            true,
+            loan.collateral_shares,
+        )?;
+        if !insurance_shares.is_zero() {
+            update_balance(
+                fixed,
+                dynamic,
+                loan.lender_index,
+                loan.is_liability_base_a.0 == 1,
+                true,
+                insurance_shares,
+            )?;
+        }
+        decrease_seat_liability(
+            fixed,
+            dynamic,
+            loan.borrower_index,
+            loan.is_liability_base_a.0 == 1,
+            loan.liability_shares.into(),
+        )?;
+        Ok(())
+    }
+
+    /// Takes up to max_prepayments of the stranded gas prepayments the seat
+    /// has on one base. Returns the number taken.
+    pub fn take_stranded_gas_prepayments(
+        &mut self,
+        trader_index: DataIndex,
+        is_base_a: bool,
+        max_prepayments: u32,
+    ) -> Result<u32, ProgramError> {
+        assert_already_has_seat(trader_index)?;
+        let DynamicAccount { dynamic, .. } = self.borrow_mut();
+
+        let claimed_seat: &mut ClaimedSeat =
+            get_mut_helper_seat(dynamic, trader_index).get_mut_value();
+        let stranded_gas_prepayments: &mut u32 = if is_base_a {
+            &mut claimed_seat.base_a_stranded_gas_prepayments
+        } else {
+            &mut claimed_seat.base_b_stranded_gas_prepayments
+        };
+        let num_taken: u32 = (*stranded_gas_prepayments).min(max_prepayments);
+        *stranded_gas_prepayments -= num_taken;
+        Ok(num_taken)
+    }
+
+    pub fn deposit(
+        &mut self,
+        trader_index: DataIndex,
+        asset_shares: WrappedI80F48,
+        update_base_a: bool,
+    ) -> ProgramResult {
+        require!(
+            is_not_nil!(trader_index),
+            NixError::InvalidDepositAccounts,
+            "No seat initialized",
+        )?;
+        let DynamicAccount { fixed, dynamic } = self.borrow_mut();
+        update_balance(
             fixed,
             dynamic,
             trader_index,
@@ -609,6 +1698,7 @@ impl<
     ///
     /// 1. Check the order against the opposite bookside
     /// 2. Rest any amount of the order leftover on the book
+    #[cfg(feature = "program")]
     pub fn place_order<'a, 'info>(
         &mut self,
         args: AddOrderToMarketArgs<'a, 'info>,
@@ -622,13 +1712,18 @@ impl<
             market_signer,
             market_signer_bump,
             trader_index,
+            referrer_index,
             num_base_atoms,
             rate_bps,
             reverse_spread_bps,
+            reverse_bid_spread_bps,
+            max_reverse_cycles,
             is_bid,
             use_a_tree,
             last_valid_slot,
+            last_valid_unix_timestamp,
             order_type,
+            expiry_policy,
             base_mint,
             quote_mint,
             base_oracle_price_usd,
@@ -636,6 +1731,11 @@ impl<
             global_trade_accounts_opts,
             marginfi_cpi_accounts_opts,
             current_slot,
+            match_limit,
+            max_new_loans,
+            defer_remainder,
+            resume_order_sequence_number,
+            clearing_rate_bps,
         } = args;
 
         assert_already_has_seat(trader_index)?;
@@ -643,25 +1743,38 @@ impl<
         let now_unix_timestamp = get_now_unix_timestamp();
 
         assert_not_already_expired(last_valid_slot, now_slot)?;
+        assert_not_already_expired_at_time(last_valid_unix_timestamp, now_unix_timestamp)?;
         assert_valid_order_type(order_type, is_bid)?;
 
         let DynamicAccount { fixed, dynamic } = self.borrow_mut();
+        assert_market_has_required_banks(fixed, order_type, is_bid, use_a_tree)?;
+        assert_has_required_marginfi_sides(order_type, is_bid, &marginfi_cpi_accounts_opts)?;
+
+        if order_type == OrderType::Reverse {
+            for spread_bps in [reverse_spread_bps, reverse_bid_spread_bps] {
+                assert_valid_reverse_spread(
+                    spread_bps,
+                    fixed.min_reverse_spread_bps,
+                    fixed.max_reverse_spread_bps,
+                )?;
+            }
+        }
 
         let (bids_best_index, asks_best_index, bids_root_index, asks_root_index) =
             get_tree_indexes(fixed, use_a_tree);
 
-        let base_marginfi_bank = marginfi_cpi_accounts_opts[0]
-            .as_ref()
-            .unwrap()
-            .marginfi_bank
-            .get_fixed()
-            .unwrap();
-        let quote_marginfi_bank = marginfi_cpi_accounts_opts[1]
-            .as_ref()
-            .unwrap()
-            .marginfi_bank
-            .get_fixed()
-            .unwrap();
+        // Copy what matching needs out of the banks once. This also releases
+        // the borrow on the bank accounts before the marginfi CPIs below.
+        // Sides the order does not need can be left out, their defaults are
+        // never read.
+        let mut bank_share_values: [BankShareValues; 2] = [BankShareValues::default(); 2];
+        for (index, marginfi_cpi_accounts_opt) in marginfi_cpi_accounts_opts.iter().enumerate() {
+            if let Some(marginfi_cpi_accounts) = marginfi_cpi_accounts_opt {
+                bank_share_values[index] =
+                    BankShareValues::from(&*marginfi_cpi_accounts.marginfi_bank.get_fixed()?);
+            }
+        }
+        let [base_marginfi_bank, quote_marginfi_bank] = bank_share_values;
 
         let mut current_maker_order_index: DataIndex = if is_bid {
             asks_best_index
@@ -669,9 +1782,13 @@ impl<
             bids_best_index
         };
 
-        let buffer_f = I80F48::from_num(10000i64 - fixed.fee_state.ltv_buffer_bps as i64)
-            .checked_div(I80F48::from_num(10000))
-            .ok_or(NixError::NumericalOverflow)?;
+        let match_bank_params: MatchBankParams = MatchBankParams::new(
+            base_marginfi_bank,
+            quote_marginfi_bank,
+            base_oracle_price_usd,
+            quote_oracle_price_usd,
+            fixed.fee_state.ltv_buffer_bps,
+        )?;
         let mut total_base_atoms_traded: u64 = 0;
         let mut total_quote_atoms_traded: u64 = 0;
 
@@ -681,22 +1798,69 @@ impl<
         let mut remaining_base_atoms: u64 = num_base_atoms;
 
         let taker: Pubkey = get_helper_seat(dynamic, trader_index).get_value().trader;
-        let mut new_loans = Vec::new();
+        let mut new_loans: MatchedLoans = MatchedLoans::default();
+        let mut fill_events: Vec<MarketEvent> = Vec::new();
+        let mut loan_assignments: Vec<LoanAssignment> = Vec::new();
+        let mut gas_deposit_lamports_released: u64 = 0;
+        let mut num_maker_orders_crossed: u32 = 0;
+        let mut stopped_at_match_limit: bool = false;
+        // Every maker order crossed can add a loan, so the loan buffer also
+        // bounds the match limit, even without one. Stopping there is the
+        // same as stopping at a match limit, a remainder that still crosses
+        // is dropped rather than rested.
+        let match_limit: u32 = match_limit
+            .unwrap_or(MAX_MATCHED_LOANS as u32)
+            .min(MAX_MATCHED_LOANS as u32);
 
         while remaining_base_atoms > 0 && is_not_nil!(current_maker_order_index) {
+            // Bound compute on deep books. Whatever is left over rests or is
+            // returned like any other unmatched remainder.
+            if num_maker_orders_crossed >= match_limit {
+                stopped_at_match_limit = true;
+                break;
+            }
+            num_maker_orders_crossed += 1;
+
             let maker_order: &RestingOrder =
                 get_helper::<RBNode<RestingOrder>>(dynamic.as_ref(), current_maker_order_index)
                     .get_value();
 
-            if maker_order.is_expired(now_slot)
-                || I80F48::from(maker_order.get_collateral_shares()) == 0
-            {
-                if maker_order.get_is_bid() {
+            let maker_step: MakerStep = get_maker_step(
+                maker_order,
+                is_bid,
+                clearing_rate_bps.unwrap_or(rate_bps),
+                now_slot,
+                now_unix_timestamp,
+            );
+            if maker_step == MakerStep::Skip {
+                current_maker_order_index = get_next_candidate_match_index(
+                    dynamic,
+                    current_maker_order_index,
+                    asks_root_index,
+                    asks_best_index,
+                    bids_root_index,
+                    bids_best_index,
+                    is_bid,
+                );
+                continue;
+            }
+
+            if maker_step == MakerStep::Remove {
+                let maker_is_expired: bool = maker_order.is_expired(now_slot, now_unix_timestamp);
+                // A resting bid borrowed when it rested. Whatever its expiry
+                // policy, the borrow stays open as a loan to the pool, since
+                // returning the collateral would leave it unpaid. Nothing was
+                // borrowed for a loan sale, the loan stays with the seller.
+                if maker_order.get_is_bid() && !maker_order.is_loan_sale() {
+                    if new_loans.len() as u32 >= max_new_loans {
+                        stopped_at_match_limit = true;
+                        break;
+                    }
                     // convert expired order to a loan on underlying protocol
                     let active_loan = ActiveLoan::new_empty(
                         use_a_tree,
                         0, //direct underlying protocol
-                        current_maker_order_index,
+                        maker_order.get_trader_index(),
                         maker_order.is_global(),
                         maker_order.get_collateral_shares(),
                         maker_order.get_liability_shares(),
@@ -704,7 +1868,22 @@ impl<
                         now_unix_timestamp,
                         now_slot.into(),
                     );
-                    new_loans.push(active_loan);
+                    new_loans.push(active_loan)?;
+                    emit_stack(OrderConvertedToPoolLoanLog {
+                        market,
+                        trader: get_helper_seat(dynamic, maker_order.get_trader_index())
+                            .get_value()
+                            .trader,
+                        order_sequence_number: maker_order.get_sequence_number(),
+                        collateral_shares: maker_order.get_collateral_shares(),
+                        liability_shares: maker_order.get_liability_shares(),
+                        reason: if maker_is_expired {
+                            PoolLoanConversionReason::Expired
+                        } else {
+                            PoolLoanConversionReason::NoCollateral
+                        },
+                        _padding: [0; 7],
+                    })?;
                 }
                 let next_maker_order_index: DataIndex = get_next_candidate_match_index(
                     dynamic,
@@ -716,7 +1895,7 @@ impl<
                     is_bid,
                 );
 
-                remove_and_update_balances(
+                gas_deposit_lamports_released += remove_and_update_balances(
                     fixed,
                     dynamic,
                     use_a_tree,
@@ -728,9 +1907,7 @@ impl<
             }
 
             // Stop trying to match if rate no longer satisfies limit.
-            if (is_bid && maker_order.get_rate_bps() > rate_bps)
-                || (!is_bid && maker_order.get_rate_bps() < rate_bps)
-            {
+            if maker_step == MakerStep::Stop {
                 break;
             }
 
@@ -739,26 +1916,126 @@ impl<
             // because post only orders should fail, not produce a crossed book.
             assert_can_take(order_type)?;
 
+            // Every fill but a loan sale opens a loan. Stop before one the
+            // loans page has no room for, a failed insert would come after
+            // the marginfi CPIs.
+            if !maker_order.is_loan_sale() && new_loans.len() as u32 >= max_new_loans {
+                stopped_at_match_limit = true;
+                break;
+            }
+
             let maker_sequence_number = maker_order.get_sequence_number();
             let maker_trader_index: DataIndex = maker_order.get_trader_index();
 
-            let maker_base_atoms: u64 = maker_order.get_num_base_atoms(&base_marginfi_bank)?;
-            let did_fully_match_resting_order: bool = remaining_base_atoms >= maker_base_atoms;
-            let base_atoms_traded: u64 = if did_fully_match_resting_order {
-                maker_base_atoms
-            } else {
-                remaining_base_atoms
-            };
+            // A loan sale is bought whole. The taker ask pays the seller out
+            // of its seat balance and takes over the lender side of the loan,
+            // so no tokens move on marginfi.
+            if maker_order.is_loan_sale() {
+                let matched_rate: u16 = maker_order.get_rate_bps();
+                let sale_base_atoms: u64 = get_loan_sale_price_atoms(
+                    maker_order.get_num_base_atoms(&base_marginfi_bank)?,
+                    maker_order.get_loan_rate_bps(),
+                    matched_rate,
+                )?;
+                let loan_assignment: LoanAssignment = LoanAssignment {
+                    market_loans: *maker_order.get_market_loans(),
+                    loan_sequence_number: maker_order.get_loan_sequence_number(),
+                    seller_index: maker_trader_index,
+                    buyer_index: trader_index,
+                    base_atoms: sale_base_atoms,
+                    rate_bps: matched_rate,
+                };
+                let next_maker_order_index: DataIndex = get_next_candidate_match_index(
+                    dynamic,
+                    current_maker_order_index,
+                    asks_root_index,
+                    asks_best_index,
+                    bids_root_index,
+                    bids_best_index,
+                    is_bid,
+                );
+                if sale_base_atoms > remaining_base_atoms || maker_trader_index == trader_index {
+                    current_maker_order_index = next_maker_order_index;
+                    continue;
+                }
+
+                let sale_asset_shares: I80F48 =
+                    convert_tokens_to_asset_shares(sale_base_atoms, &base_marginfi_bank)?;
+                let protocol_fee_shares: I80F48 = get_protocol_fee_shares(
+                    sale_asset_shares,
+                    matched_rate,
+                    fixed.fee_state.protocol_fee_rate_bps,
+                    fixed.is_fee_on_interest(),
+                )?;
+                let update_base_a: bool = should_update_base_a(use_a_tree, !is_bid);
+                update_balance(
+                    fixed,
+                    dynamic,
+                    trader_index,
+                    update_base_a,
+                    false,
+                    sale_asset_shares
+                        .checked_add(protocol_fee_shares)
+                        .ok_or(NixError::NumericalOverflow)?
+                        .into(),
+                )?;
+                accrue_protocol_fee(
+                    fixed,
+                    dynamic,
+                    referrer_index,
+                    maker_trader_index,
+                    update_base_a,
+                    protocol_fee_shares,
+                )?;
+                update_balance(
+                    fixed,
+                    dynamic,
+                    maker_trader_index,
+                    update_base_a,
+                    true,
+                    sale_asset_shares.into(),
+                )?;
 
-            let matched_rate = maker_order.get_rate_bps();
+                let maker: Pubkey = get_helper_seat(dynamic, maker_trader_index)
+                    .get_value()
+                    .trader;
+                fill_events.push(MarketEvent::new(
+                    MarketEventType::LoanSale,
+                    now_slot as u64,
+                    maker,
+                    taker,
+                    maker_sequence_number,
+                    WrappedI80F48::from(I80F48::from_num(sale_base_atoms)),
+                    WrappedI80F48::default(),
+                    matched_rate,
+                    use_a_tree,
+                ));
+                remove_order_from_tree_and_free(
+                    fixed,
+                    dynamic,
+                    use_a_tree,
+                    current_maker_order_index,
+                    !is_bid,
+                )?;
+                remaining_base_atoms = remaining_base_atoms
+                    .checked_sub(sale_base_atoms)
+                    .ok_or(NixError::NumericalOverflow)?;
+                loan_assignments.push(loan_assignment);
+                current_maker_order_index = next_maker_order_index;
+                continue;
+            }
 
-            let quote_atoms_traded: u64 = get_required_quote_collateral_to_back_loan(
-                &base_marginfi_bank,
-                &quote_marginfi_bank,
-                base_oracle_price_usd,
-                quote_oracle_price_usd,
-                buffer_f,
-                base_atoms_traded,
+            let maker_base_atoms: u64 = maker_order.get_num_base_atoms(&base_marginfi_bank)?;
+            let MatchedFill {
+                base_atoms: base_atoms_traded,
+                quote_atoms: quote_atoms_traded,
+                rate_bps: matched_rate,
+                did_fully_match_resting_order,
+            } = get_matched_fill(
+                maker_base_atoms,
+                remaining_base_atoms,
+                clearing_rate_bps.unwrap_or(maker_order.get_rate_bps()),
+                &match_bank_params,
             )?;
 
             // If it is a global order, just in time bring the funds over, or
@@ -776,6 +2053,7 @@ impl<
                     //global orders are expected to only be asks
                     //meaning they supply base atoms only
                     base_atoms_traded,
+                    remaining_accounts,
                 )?;
 
                 if !has_enough_tokens {
@@ -789,7 +2067,7 @@ impl<
                         is_bid,
                     );
 
-                    remove_and_update_balances(
+                    gas_deposit_lamports_released += remove_and_update_balances(
                         fixed,
                         dynamic,
                         use_a_tree,
@@ -824,6 +2102,18 @@ impl<
                 convert_tokens_to_asset_shares(base_atoms_traded, &base_marginfi_bank)?;
             let quote_atom_asset_shares_traded =
                 convert_tokens_to_asset_shares(quote_atoms_traded, &quote_marginfi_bank)?;
+
+            // The protocol fee is charged to the taker on the matched base
+            // atoms, in the base asset. The collateral of a bid only backs
+            // the loan, so no fee is charged on it.
+            let protocol_fee_shares: I80F48 = get_protocol_fee_shares(
+                base_atom_asset_shares_traded,
+                matched_rate,
+                fixed.fee_state.protocol_fee_rate_bps,
+                fixed.is_fee_on_interest(),
+            )?;
+            let fee_update_base_a: bool = should_update_base_a(use_a_tree, true);
+
             // Decrease taker
             update_balance(
                 fixed,
@@ -834,28 +2124,58 @@ impl<
                 if is_bid {
                     quote_atom_asset_shares_traded.into()
                 } else {
-                    base_atom_asset_shares_traded.into()
+                    base_atom_asset_shares_traded
+                        .checked_add(protocol_fee_shares)
+                        .ok_or(NixError::NumericalOverflow)?
+                        .into()
                 },
             )?;
+            accrue_protocol_fee(
+                fixed,
+                dynamic,
+                referrer_index,
+                maker_trader_index,
+                fee_update_base_a,
+                protocol_fee_shares,
+            )?;
 
             // Increase taker
             //only a borrower (that isn't reversing) would receive their borrowed tokens
             if is_bid && order_type != OrderType::Reverse {
                 assert_not_already_expired(last_valid_slot, now_slot)?;
+                // The fee comes out of the borrowed base.
                 update_balance(
                     fixed,
                     dynamic,
                     trader_index,
-                    should_update_base_a(use_a_tree, is_bid),
+                    fee_update_base_a,
                     true,
-                    if is_bid {
-                        base_atom_asset_shares_traded.into()
-                    } else {
-                        quote_atom_asset_shares_traded.into()
-                    },
+                    base_atom_asset_shares_traded
+                        .checked_sub(protocol_fee_shares)
+                        .ok_or(NixError::NumericalOverflow)?
+                        .into(),
+                )?;
+            } else if is_bid {
+                // A reverse bid lends all of what it borrows, so it pays the
+                // fee from its base balance.
+                update_balance(
+                    fixed,
+                    dynamic,
+                    trader_index,
+                    fee_update_base_a,
+                    false,
+                    protocol_fee_shares.into(),
                 )?;
             }
 
+            let fill_value_usd: I80F48 = get_weighted_value_usd(
+                I80F48::from_num(base_atoms_traded),
+                I80F48::ONE,
+                base_marginfi_bank.mint_decimals,
+                base_oracle_price_usd,
+            )?;
+            fixed.record_matched_rate(use_a_tree, matched_rate, fill_value_usd, now_slot);
+
             // record maker & taker volume
             record_volume_by_trader_index(
                 dynamic,
@@ -869,6 +2189,14 @@ impl<
                 base_atom_asset_shares_traded,
                 use_a_tree,
             );
+            // What is left of the maker keeps resting from now on.
+            let maker_base_atom_slots: u64 =
+                get_mut_helper_order(dynamic, current_maker_order_index)
+                    .get_mut_value()
+                    .accrue_time_on_book(maker_base_atoms - base_atoms_traded, now_slot);
+            let maker_time_on_book: u64 = get_mut_helper_seat(dynamic, maker_trader_index)
+                .get_mut_value()
+                .record_time_on_book(use_a_tree, maker_base_atom_slots);
             emit_stack(FillLog {
                 market,
                 maker,
@@ -887,19 +2215,44 @@ impl<
                 taker_is_buy: PodBool::from(is_bid),
                 is_maker_global: PodBool::from(is_maker_global),
                 _padding: [0; 6],
-                _padding1: [0; 14],
+                _padding1: [0; 6],
+                maker_time_on_book,
+                protocol_fee_shares: protocol_fee_shares.into(),
             })?;
+            fill_events.push(MarketEvent::new(
+                MarketEventType::Fill,
+                now_slot as u64,
+                maker,
+                taker,
+                maker_sequence_number,
+                WrappedI80F48::from(I80F48::from_num(base_atoms_traded)),
+                WrappedI80F48::from(I80F48::from_num(quote_atoms_traded)),
+                matched_rate,
+                use_a_tree,
+            ));
 
             if did_fully_match_resting_order {
-                // Get paid for removing a global order.
-                if get_helper::<RBNode<RestingOrder>>(dynamic, current_maker_order_index)
-                    .get_value()
-                    .is_global()
-                {
-                    if is_bid {
-                        remove_from_global(&global_trade_accounts_opts[0])?;
+                // Get paid for removing a global or good till time order.
+                let maker_order: &RestingOrder =
+                    get_helper::<RBNode<RestingOrder>>(dynamic, current_maker_order_index)
+                        .get_value();
+                gas_deposit_lamports_released += maker_order.get_gas_deposit_lamports();
+                if maker_order.is_global() {
+                    let maker_trader_index: DataIndex = maker_order.get_trader_index();
+                    let global_trade_accounts_opt: &Option<GlobalTradeAccounts> = if is_bid {
+                        &global_trade_accounts_opts[0]
                     } else {
-                        remove_from_global(&global_trade_accounts_opts[1])?;
+                        &global_trade_accounts_opts[1]
+                    };
+                    if !remove_from_global(global_trade_accounts_opt, &maker)? {
+                        // Remember the prepayment so it can be swept back later.
+                        let claimed_seat: &mut ClaimedSeat =
+                            get_mut_helper_seat(dynamic, maker_trader_index).get_mut_value();
+                        if use_a_tree {
+                            claimed_seat.base_a_stranded_gas_prepayments += 1;
+                        } else {
+                            claimed_seat.base_b_stranded_gas_prepayments += 1;
+                        }
                     }
                 }
                 let next_maker_order_index: DataIndex = get_next_candidate_match_index(
@@ -948,7 +2301,7 @@ impl<
                     now_slot.into(),
                 );
 
-                new_loans.push(active_loan);
+                new_loans.push(active_loan)?;
                 current_maker_order_index = next_maker_order_index;
             } else {
                 let maker_order: &mut RestingOrder =
@@ -978,39 +2331,98 @@ impl<
         }
         // Record volume on market
         if use_a_tree {
-            fixed.base_a_match_volume = WrappedI80F48::from(
-                I80F48::from(fixed.base_a_match_volume)
-                    .wrapping_add(I80F48::from_num(total_base_atoms_traded)),
-            );
+            fixed.base_a_match_volume = fixed
+                .base_a_match_volume
+                .saturating_add(I80F48::from_num(total_base_atoms_traded));
         } else {
-            fixed.base_b_match_volume = WrappedI80F48::from(
-                I80F48::from(fixed.base_b_match_volume)
-                    .wrapping_add(I80F48::from_num(total_base_atoms_traded)),
-            );
+            fixed.base_b_match_volume = fixed
+                .base_b_match_volume
+                .saturating_add(I80F48::from_num(total_base_atoms_traded));
+        }
+
+        // The taker borrowed everything it matched on a bid.
+        if is_bid && total_base_atoms_traded > 0 {
+            increase_seat_liability(
+                fixed,
+                dynamic,
+                trader_index,
+                use_a_tree,
+                convert_tokens_to_liability_shares(total_base_atoms_traded, &base_marginfi_bank)?,
+                &base_marginfi_bank,
+            )?;
         }
 
         // Bump the order sequence number even for orders which do not end up
-        // resting.
-        let order_sequence_number: u64 = if use_a_tree {
-            fixed.base_a_order_sequence_number = fixed.base_a_order_sequence_number.wrapping_add(1);
-            fixed.base_a_order_sequence_number
+        // resting. Continued orders already took theirs.
+        let order_sequence_number: u64 = match resume_order_sequence_number {
+            Some(order_sequence_number) => order_sequence_number,
+            None if use_a_tree => {
+                fixed.base_a_order_sequence_number =
+                    fixed.base_a_order_sequence_number.wrapping_add(1);
+                fixed.base_a_order_sequence_number
+            }
+            None => {
+                fixed.base_b_order_sequence_number =
+                    fixed.base_b_order_sequence_number.wrapping_add(1);
+                fixed.base_b_order_sequence_number
+            }
+        };
+
+        let deferred_base_atoms: u64 = if defer_remainder && stopped_at_match_limit {
+            remaining_base_atoms
         } else {
-            fixed.base_b_order_sequence_number = fixed.base_b_order_sequence_number.wrapping_add(1);
-            fixed.base_b_order_sequence_number
+            0
         };
+        // Matching can stop while the book still crosses. What is left then
+        // is dropped like the rest of an immediate or cancel order, resting
+        // it would cross the book.
+        let remainder_crosses: bool = stopped_at_match_limit
+            && deferred_base_atoms == 0
+            && remainder_crosses_book(
+                fixed,
+                dynamic,
+                use_a_tree,
+                current_maker_order_index,
+                is_bid,
+                rate_bps,
+                now_slot,
+                now_unix_timestamp,
+            );
 
-        // If there is nothing left to rest, then return before resting.
-        if !order_type_can_rest(order_type) || remaining_base_atoms == 0 || rate_bps == 0 {
+        // If there is nothing left to rest, then return before resting. A
+        // deferred bid is not borrowed for until it rests.
+        if !order_type_can_rest(order_type)
+            || remaining_base_atoms == 0
+            || rate_bps == 0
+            || remainder_crosses
+            || (is_bid && deferred_base_atoms > 0)
+        {
             return Ok(AddOrderToMarketResult {
                 order_sequence_number,
                 order_index: NIL,
                 base_atoms_traded: total_base_atoms_traded,
                 quote_atoms_traded: total_quote_atoms_traded,
                 matched_loans: new_loans,
+                fill_events,
+                loan_assignments,
+                deferred_base_atoms,
+                gas_deposit_lamports_taken: 0,
+                gas_deposit_lamports_released,
             });
         }
 
         if is_bid {
+            // The rest of a bid is borrowed from marginfi up front. The borrow
+            // and the deposit after it are built in the same buffers.
+            let mut marginfi_cpi_scratch: MarginfiCpiScratch = MarginfiCpiScratch::default();
+            increase_seat_liability(
+                fixed,
+                dynamic,
+                trader_index,
+                use_a_tree,
+                convert_tokens_to_liability_shares(remaining_base_atoms, &base_marginfi_bank)?,
+                &base_marginfi_bank,
+            )?;
             cpi_marginfi_borrow(
                 &marginfi_cpi_accounts_opts,
                 &global_trade_accounts_opts,
@@ -1021,6 +2433,7 @@ impl<
                     None
                 },
                 market_signer.clone(),
+                &mut marginfi_cpi_scratch,
                 market_signer_seeds_with_bump!(market, market_signer_bump),
                 remaining_accounts,
             )?;
@@ -1045,9 +2458,12 @@ impl<
                 } else {
                     None
                 },
+                &mut marginfi_cpi_scratch,
                 market_signer_seeds_with_bump!(market, market_signer_bump),
+                remaining_accounts,
             )?;
-        } else {
+        } else if order_type_can_take(order_type) {
+            let mut marginfi_cpi_scratch: MarginfiCpiScratch = MarginfiCpiScratch::default();
             //withdraw total_base_atoms_traded from marginfi base account
             cpi_marginfi_withdraw(
                 &marginfi_cpi_accounts_opts,
@@ -1059,6 +2475,7 @@ impl<
                     None
                 },
                 market_signer.clone(),
+                &mut marginfi_cpi_scratch,
                 market_signer_seeds_with_bump!(market, market_signer_bump),
                 remaining_accounts,
             )?;
@@ -1083,16 +2500,38 @@ impl<
                 } else {
                     None
                 },
+                &mut marginfi_cpi_scratch,
                 market_signer_seeds_with_bump!(market, market_signer_bump),
+                remaining_accounts,
             )?;
         }
 
-        //use total received base_atoms to create reverse order
+        // The matched part of a deferred ask is settled above, the rest is
+        // left to ContinueOrder.
+        if deferred_base_atoms > 0 {
+            return Ok(AddOrderToMarketResult {
+                order_sequence_number,
+                order_index: NIL,
+                base_atoms_traded: total_base_atoms_traded,
+                quote_atoms_traded: total_quote_atoms_traded,
+                matched_loans: new_loans,
+                fill_events,
+                loan_assignments,
+                deferred_base_atoms,
+                gas_deposit_lamports_taken: 0,
+                gas_deposit_lamports_released,
+            });
+        }
+
+        //use total received base_atoms to create reverse order
         if is_bid && order_type == OrderType::Reverse {
             // New Ask @R --> Bid @R * (1 - spread)
-            let reverse_rate = rate_bps
-                .checked_mul(10_000u16 - reverse_spread_bps)
-                .and_then(|v| v.checked_div(10_000u16))
+            // Bids flip into asks, so the ask side spread prices the flip.
+            // Done in u32 since rate_bps * (10_000 - spread) does not fit in u16.
+            let reverse_rate: u16 = (rate_bps as u32)
+                .checked_mul(10_000u32 - reverse_spread_bps as u32)
+                .and_then(|v| v.checked_div(10_000u32))
+                .and_then(|v| u16::try_from(v).ok())
                 .ok_or(NixError::NumericalOverflow)?;
 
             let reverse_base_atoms = total_base_atoms_traded
@@ -1114,21 +2553,44 @@ impl<
                     fixed.base_a_order_sequence_number
                 };
 
+                increment_open_orders(fixed, dynamic, trader_index)?;
                 let free_address: DataIndex =
                     get_free_address_on_market_fixed_for_ask_order(fixed, dynamic);
 
-                let new_reverse_resting_order: RestingOrder = RestingOrder::new(
+                // The flipped order stays a reverse order with the same
+                // spread until its last cycle.
+                let reverse_cycles_left: Option<u16> = get_next_reverse_cycles(max_reverse_cycles);
+                let mut new_reverse_resting_order: RestingOrder = RestingOrder::new(
                     reverse_rate,
                     reverse_order_sequence_number,
                     total_reverse_base_shares.into(),
                     WrappedI80F48::from(I80F48::from(0)), // liability shares are 0 for asks
                     !use_a_tree,
                     trader_index,
-                    last_valid_slot,
-                    OrderType::Limit,
+                    // Reverse orders cannot expire.
+                    if reverse_cycles_left.is_some() {
+                        NO_EXPIRATION_LAST_VALID_SLOT
+                    } else {
+                        last_valid_slot
+                    },
+                    if reverse_cycles_left.is_some() {
+                        OrderType::Reverse
+                    } else {
+                        OrderType::Limit
+                    },
                     !is_bid,
-                    0,
+                    if reverse_cycles_left.is_some() {
+                        reverse_spread_bps
+                    } else {
+                        0
+                    },
+                    ExpiryPolicy::default(),
                 )?;
+                new_reverse_resting_order.set_reverse_cycles_left(reverse_cycles_left.unwrap_or(0));
+                if reverse_cycles_left.is_some() {
+                    new_reverse_resting_order.set_reverse_bid_spread(reverse_bid_spread_bps);
+                }
+                new_reverse_resting_order.start_time_on_book(reverse_base_atoms, now_slot);
 
                 insert_order_into_tree(
                     use_a_tree,
@@ -1146,6 +2608,11 @@ impl<
                     base_atoms_traded: total_base_atoms_traded,
                     quote_atoms_traded: total_quote_atoms_traded,
                     matched_loans: new_loans,
+                    fill_events,
+                    loan_assignments,
+                    deferred_base_atoms: 0,
+                    gas_deposit_lamports_taken: 0,
+                    gas_deposit_lamports_released,
                 });
             }
         }
@@ -1156,7 +2623,7 @@ impl<
                 &quote_marginfi_bank,
                 base_oracle_price_usd,
                 quote_oracle_price_usd,
-                buffer_f,
+                match_bank_params.buffer_f,
                 remaining_base_atoms,
             )
         } else {
@@ -1187,12 +2654,15 @@ impl<
             is_bid,
             use_a_tree,
             order_type,
+            expiry_policy,
             global_trade_accounts_opts,
             current_slot,
             last_valid_slot,
+            last_valid_unix_timestamp,
+            num_base_atoms: remaining_base_atoms,
         };
 
-        self.rest_remaining(
+        let mut result: AddOrderToMarketResult = self.rest_remaining(
             &rest_args,
             remaining_collateral_shares,
             remaining_liability_shares,
@@ -1200,9 +2670,14 @@ impl<
             total_base_atoms_traded,
             total_quote_atoms_traded,
             new_loans,
-        )
+            fill_events,
+        )?;
+        result.loan_assignments = loan_assignments;
+        result.gas_deposit_lamports_released = gas_deposit_lamports_released;
+        Ok(result)
     }
 
+    #[cfg(feature = "program")]
     fn rest_remaining<'a, 'info>(
         &mut self,
         args: &RestRemainingOrderToMarketArgs<'a, 'info>,
@@ -1211,7 +2686,8 @@ impl<
         order_sequence_number: u64,
         total_base_atoms_traded: u64,
         total_quote_atoms_traded: u64,
-        loans: Vec<ActiveLoan>,
+        loans: MatchedLoans,
+        fill_events: Vec<MarketEvent>,
     ) -> Result<AddOrderToMarketResult, ProgramError>
     where
         'a: 'info,
@@ -1221,15 +2697,25 @@ impl<
             rate_bps,
             is_bid,
             last_valid_slot,
+            last_valid_unix_timestamp,
             order_type,
+            expiry_policy,
             use_a_tree,
-
+            current_slot,
+            num_base_atoms,
             global_trade_accounts_opts,
-            ..
         } = args;
         assert_valid_order_type(*order_type, *is_bid)?;
         let DynamicAccount { fixed, dynamic } = self.borrow_mut();
 
+        increment_open_orders(fixed, dynamic, *trader_index)?;
+        count_new_resting_order(
+            fixed,
+            dynamic,
+            *trader_index,
+            current_slot.unwrap_or_else(get_now_slot),
+        )?;
+
         // Put the remaining in an order on the other bookside.
         let free_address: DataIndex = if *is_bid {
             get_free_address_on_market_fixed_for_bid_order(fixed, dynamic)
@@ -1237,7 +2723,7 @@ impl<
             get_free_address_on_market_fixed_for_ask_order(fixed, dynamic)
         };
 
-        let resting_order: RestingOrder = RestingOrder::new(
+        let mut resting_order: RestingOrder = RestingOrder::new(
             *rate_bps,
             order_sequence_number,
             remaining_collateral_shares.into(),
@@ -1248,7 +2734,17 @@ impl<
             *order_type,
             *is_bid,
             0,
+            *expiry_policy,
         )?;
+        resting_order
+            .start_time_on_book(*num_base_atoms, current_slot.unwrap_or_else(get_now_slot));
+        let gas_deposit_lamports_taken: u64 =
+            if *last_valid_unix_timestamp == NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP {
+                0
+            } else {
+                GTT_GAS_DEPOSIT_LAMPORTS
+            };
+        resting_order.set_good_till_time(*last_valid_unix_timestamp, gas_deposit_lamports_taken);
 
         if resting_order.is_global() {
             if *is_bid {
@@ -1290,102 +2786,97 @@ impl<
             base_atoms_traded: total_base_atoms_traded,
             quote_atoms_traded: total_quote_atoms_traded,
             matched_loans: loans,
+            fill_events,
+            loan_assignments: Vec::new(),
+            deferred_base_atoms: 0,
+            gas_deposit_lamports_taken,
+            gas_deposit_lamports_released: 0,
         })
     }
 
     // Does a linear scan over the orderbook to find the index to cancel.
+    /// Cancels the order with the given sequence number on the given tree and
+    /// returns its gas deposit as cancel_order_by_index does. Sequence numbers
+    /// are per tree, so orders created by reverse fills are cancelled on the
+    /// tree they rest on, never found by searching the other one.
+    #[cfg(feature = "program")]
     pub fn cancel_order<'a, 'info>(
         &mut self,
         use_a_tree: bool,
         trader_index: DataIndex,
         order_sequence_number: u64,
-        base_global: &NixAccountInfo<'a, 'info, GlobalFixed>,
+        base_global_opt: &Option<NixAccountInfo<'a, 'info, GlobalFixed>>,
         payer: Signer<'a, 'info>,
         system_program: Program<'a, 'info>,
         market_loans: &NixAccountInfo<'a, 'info, MarketLoansFixed>,
-    ) -> ProgramResult {
+    ) -> Result<u64, ProgramError> {
         let DynamicAccount { fixed, dynamic } = self.borrow_mut();
 
-        let (bids_best_index, asks_best_index, bids_root_index, asks_root_index) =
-            get_tree_indexes(fixed, use_a_tree);
-
-        let mut index_to_remove: DataIndex = NIL;
-
-        // One iteration to find the index to cancel in the ask side.
-        let tree: BooksideReadOnly =
-            BooksideReadOnly::new(dynamic, asks_root_index, asks_best_index);
-
-        for (index, resting_order) in tree.iter::<RestingOrder>() {
-            if resting_order.get_sequence_number() == order_sequence_number {
-                require!(
-                    resting_order.get_trader_index() == trader_index,
-                    NixError::InvalidCancel,
-                    "Cannot cancel for another trader",
-                )?;
-                require!(
-                    index_to_remove == NIL,
-                    NixError::InvalidCancel,
-                    "Book is broken, matched multiple orders",
-                )?;
-                index_to_remove = index;
-            }
-        }
-
-        // Second iteration to find the index to cancel in the bid side.
-        let tree: BooksideReadOnly =
-            BooksideReadOnly::new(dynamic, bids_root_index, bids_best_index);
-        for (index, resting_order) in tree.iter::<RestingOrder>() {
-            if resting_order.get_sequence_number() == order_sequence_number {
-                require!(
-                    resting_order.get_trader_index() == trader_index,
-                    NixError::InvalidCancel,
-                    "Cannot cancel for another trader",
-                )?;
-                require!(
-                    index_to_remove == NIL,
-                    NixError::InvalidCancel,
-                    "Book is broken, matched multiple orders",
-                )?;
-                index_to_remove = index;
-            }
-        }
+        let index_to_remove: DataIndex = find_order_index(
+            fixed,
+            dynamic,
+            use_a_tree,
+            trader_index,
+            order_sequence_number,
+        )?;
 
         if is_not_nil!(index_to_remove) {
             // Cancel order by index will update balances.
-            self.cancel_order_by_index(
+            return self.cancel_order_by_index(
                 use_a_tree,
                 index_to_remove,
-                base_global,
+                base_global_opt,
                 &Some(payer),
                 &Some(system_program),
                 market_loans,
-            )?;
-            return Ok(());
+            );
         }
 
         // Do not fail silently.
         Err(NixError::InvalidCancel.into())
     }
+
+    /// Returns the gas deposit of a good till time order, for the caller to
+    /// refund from the market.
+    #[cfg(feature = "program")]
     pub fn cancel_order_by_index<'a, 'info>(
         &mut self,
         use_a_tree: bool,
         order_index: DataIndex,
-        base_global: &NixAccountInfo<'a, 'info, GlobalFixed>,
+        base_global_opt: &Option<NixAccountInfo<'a, 'info, GlobalFixed>>,
         payer: &Option<Signer<'a, 'info>>,
         system_program:  &Option<Program<'a, 'info>>,
         market_loans: &NixAccountInfo<'a, 'info, MarketLoansFixed>,
-    ) -> ProgramResult {
+    ) -> Result<u64, ProgramError> {
         let DynamicAccount { fixed, dynamic } = self.borrow_mut();
+        // Canceling ends the time on book of the order.
+        let resting_order: &mut RestingOrder =
+            get_mut_helper_order(dynamic, order_index).get_mut_value();
+        let base_atom_slots: u64 = resting_order.accrue_time_on_book(0, get_now_slot());
+        let trader_index: DataIndex = resting_order.get_trader_index();
+        get_mut_helper_seat(dynamic, trader_index)
+            .get_mut_value()
+            .record_time_on_book(use_a_tree, base_atom_slots);
+
         let resting_order: &RestingOrder = get_helper_order(dynamic, order_index).get_value();
         let is_bid: bool = resting_order.get_is_bid();
+        let gas_deposit_lamports: u64 = resting_order.get_gas_deposit_lamports();
 
         // Update the accounting for the order that was just canceled.
         if resting_order.is_global() {
             if is_bid {
                 return Err(NixError::InvalidGlobalBidOrder.into());
             } else {
-                remove_from_global_core(base_global, payer, system_program)?;
+                let trader: Pubkey = get_helper_seat(dynamic, resting_order.get_trader_index())
+                    .get_value()
+                    .trader;
+                let base_global: &NixAccountInfo<GlobalFixed> =
+                    base_global_opt.as_ref().ok_or(NixError::MissingGlobal)?;
+                remove_from_global_core(base_global, &trader, payer, system_program)?;
             }
+        } else if resting_order.is_loan_sale() {
+            // Nothing was locked for a loan sale, the loan stays with the
+            // seller.
         } else {
             if is_bid {
                 let new_active_loan = ActiveLoan::new_empty(
@@ -1405,7 +2896,18 @@ impl<
                     1,
                 )?;
 
-                try_to_add_new_loans(market_loans, [new_active_loan].into())?;
+                try_to_add_new_loans(market_loans, &[new_active_loan])?;
+                emit_stack(OrderConvertedToPoolLoanLog {
+                    market: market_loans.get_fixed()?.market,
+                    trader: get_helper_seat(dynamic, resting_order.get_trader_index())
+                        .get_value()
+                        .trader,
+                    order_sequence_number: resting_order.get_sequence_number(),
+                    collateral_shares: resting_order.get_collateral_shares(),
+                    liability_shares: resting_order.get_liability_shares(),
+                    reason: PoolLoanConversionReason::Canceled,
+                    _padding: [0; 7],
+                })?;
             } else {
                 update_balance(
                     fixed,
@@ -1419,168 +2921,976 @@ impl<
         }
         remove_order_from_tree_and_free(fixed, dynamic, use_a_tree, order_index, is_bid)?;
 
-        Ok(())
+        Ok(gas_deposit_lamports)
     }
-}
 
-fn set_payload_order(dynamic: &mut [u8], free_address: DataIndex) {
-    get_mut_helper_order(dynamic, free_address)
-        .set_payload_type(MarketDataTreeNodeType::RestingOrder as u8);
-}
-fn remove_order_from_tree(
-    fixed: &mut MarketFixed,
-    dynamic: &mut [u8],
-    use_a_tree: bool,
-    order_index: DataIndex,
-    is_bid: bool,
-) -> ProgramResult {
-    if use_a_tree {
-        let mut tree: Bookside = if is_bid {
-            Bookside::new(
-                dynamic,
-                fixed.base_a_bids_root_index,
-                fixed.base_a_bids_best_index,
-            )
-        } else {
-            Bookside::new(
+    /// Shrinks a resting ask in place, so it keeps its sequence number and
+    /// its place among asks at the same rate. The removed shares go back to
+    /// the seat, a global ask just commits fewer tokens. Returns the shares
+    /// left on the order.
+    #[cfg(feature = "program")]
+    pub fn reduce_order(
+        &mut self,
+        use_a_tree: bool,
+        trader_index: DataIndex,
+        order_sequence_number: u64,
+        order_index_hint: Option<DataIndex>,
+        collateral_shares_to_remove: WrappedI80F48,
+    ) -> Result<WrappedI80F48, ProgramError> {
+        let DynamicAccount { fixed, dynamic } = self.borrow_mut();
+        let order_index: DataIndex = match order_index_hint {
+            Some(order_index) => order_index,
+            None => find_order_index(
+                fixed,
                 dynamic,
-                fixed.base_a_asks_root_index,
-                fixed.base_a_asks_best_index,
-            )
+                use_a_tree,
+                trader_index,
+                order_sequence_number,
+            )?,
         };
-        tree.remove_by_index(order_index);
+        require!(
+            is_not_nil!(order_index),
+            NixError::InvalidReduce,
+            "Order {} not found",
+            order_sequence_number,
+        )?;
 
-        // Possibly changes the root and/or best.
-        if is_bid {
-            trace!(
-                "remove order bid root:{}->{} max:{}->{}",
-                fixed.base_a_bids_root_index,
-                tree.get_root_index(),
-                fixed.base_a_bids_best_index,
-                tree.get_max_index()
-            );
-            fixed.base_a_bids_root_index = tree.get_root_index();
-            fixed.base_a_bids_best_index = tree.get_max_index();
-        } else {
-            trace!(
-                "remove order ask root:{}->{} max:{}->{}",
-                fixed.base_a_asks_root_index,
-                tree.get_root_index(),
-                fixed.base_a_asks_best_index,
-                tree.get_max_index()
-            );
-            fixed.base_a_asks_root_index = tree.get_root_index();
-            fixed.base_a_asks_best_index = tree.get_max_index();
-        }
-    } else {
-        let mut tree: Bookside = if is_bid {
-            Bookside::new(
-                dynamic,
-                fixed.base_b_bids_root_index,
-                fixed.base_b_bids_best_index,
-            )
-        } else {
-            Bookside::new(
+        let resting_order: &mut RestingOrder =
+            get_mut_helper::<RBNode<RestingOrder>>(dynamic, order_index).get_mut_value();
+        require!(
+            resting_order.get_trader_index() == trader_index
+                && resting_order.get_sequence_number() == order_sequence_number,
+            NixError::InvalidReduce,
+            "Order {} at {} does not belong to the trader",
+            order_sequence_number,
+            order_index,
+        )?;
+        require!(
+            !resting_order.get_is_bid()
+                && collateral_shares_to_remove.is_positive()
+                && collateral_shares_to_remove < resting_order.get_collateral_shares(),
+            NixError::InvalidReduce,
+            "Cannot take {} shares off order {}, cancel it instead",
+            collateral_shares_to_remove,
+            order_sequence_number,
+        )?;
+        // The size counted for time on book shrinks with the shares.
+        let collateral_shares_before: I80F48 = resting_order.get_collateral_shares().into();
+        resting_order.reduce_collateral_shares(collateral_shares_to_remove)?;
+        let collateral_shares_remaining: WrappedI80F48 = resting_order.get_collateral_shares();
+        let is_global: bool = resting_order.is_global();
+        let num_base_atoms_left: u64 =
+            I80F48::from_num(resting_order.get_time_on_book_base_atoms())
+                .checked_mul(collateral_shares_remaining.into())
+                .and_then(|atoms| atoms.checked_div(collateral_shares_before))
+                .ok_or(NixError::NumericalOverflow)?
+                .to_num::<u64>();
+        let base_atom_slots: u64 =
+            resting_order.accrue_time_on_book(num_base_atoms_left, get_now_slot());
+        get_mut_helper_seat(dynamic, trader_index)
+            .get_mut_value()
+            .record_time_on_book(use_a_tree, base_atom_slots);
+
+        if !is_global {
+            update_balance(
+                fixed,
                 dynamic,
-                fixed.base_b_asks_root_index,
-                fixed.base_b_asks_best_index,
-            )
-        };
-        tree.remove_by_index(order_index);
-
-        // Possibly changes the root and/or best.
-        if is_bid {
-            trace!(
-                "remove order bid root:{}->{} max:{}->{}",
-                fixed.base_b_bids_root_index,
-                tree.get_root_index(),
-                fixed.base_b_bids_best_index,
-                tree.get_max_index()
-            );
-            fixed.base_b_bids_root_index = tree.get_root_index();
-            fixed.base_b_bids_best_index = tree.get_max_index();
-        } else {
-            trace!(
-                "remove order ask root:{}->{} max:{}->{}",
-                fixed.base_b_asks_root_index,
-                tree.get_root_index(),
-                fixed.base_b_asks_best_index,
-                tree.get_max_index()
-            );
-            fixed.base_b_asks_root_index = tree.get_root_index();
-            fixed.base_b_asks_best_index = tree.get_max_index();
+                trader_index,
+                should_update_base_a(use_a_tree, false),
+                true,
+                collateral_shares_to_remove,
+            )?;
         }
+        Ok(collateral_shares_remaining)
     }
 
-    Ok(())
-}
-
-fn remove_order_from_tree_and_free(
-    fixed: &mut MarketFixed,
-    dynamic: &mut [u8],
-    use_a_tree: bool,
-    order_index: DataIndex,
-    is_bid: bool,
-) -> ProgramResult {
-    remove_order_from_tree(fixed, dynamic, use_a_tree, order_index, is_bid)?;
-    if is_bid {
-        release_address_on_market_fixed_for_bid_order(fixed, dynamic, order_index);
-    } else {
-        release_address_on_market_fixed_for_ask_order(fixed, dynamic, order_index);
-    }
-    Ok(())
-}
-#[allow(unused_variables)]
-pub fn update_balance(
-    fixed: &mut MarketFixed,
-    dynamic: &mut [u8],
-    trader_index: DataIndex,
-    update_base_a: bool,
-    is_increase: bool,
-    asset_shares: WrappedI80F48,
-) -> ProgramResult {
-    let claimed_seat: &mut ClaimedSeat = get_mut_helper_seat(dynamic, trader_index).get_mut_value();
-
-    trace!("update_balance_by_trader_index idx:{trader_index} base:{is_base} inc:{is_increase} amount:{asset_shares}");
-
-    let asset_shares: I80F48 = asset_shares.into();
-
-    if update_base_a {
-        if is_increase {
-            claimed_seat.base_a_withdrawable_asset_share = WrappedI80F48::from(
-                I80F48::from(claimed_seat.base_a_withdrawable_asset_share) + asset_shares,
-            );
-        } else {
-            require!(
-                I80F48::from(claimed_seat.base_a_withdrawable_asset_share) >= asset_shares,
-                ProgramError::InsufficientFunds,
-                "Not enough base a withdrawable asset shares. Has {}, needs {}",
-                I80F48::from(claimed_seat.base_a_withdrawable_asset_share),
-                asset_shares
+    /// Sets the spreads and the cycles left of a resting reverse order. The
+    /// rate, size and queue position are left alone, the spreads apply from
+    /// its next flip.
+    #[cfg(feature = "program")]
+    pub fn modify_reverse_order(
+        &mut self,
+        use_a_tree: bool,
+        trader_index: DataIndex,
+        order_sequence_number: u64,
+        reverse_spread_bps: u16,
+        reverse_bid_spread_bps: u16,
+        reverse_cycles_left: u16,
+    ) -> ProgramResult {
+        let DynamicAccount { fixed, dynamic } = self.borrow_mut();
+        for spread_bps in [reverse_spread_bps, reverse_bid_spread_bps] {
+            assert_valid_reverse_spread(
+                spread_bps,
+                fixed.min_reverse_spread_bps,
+                fixed.max_reverse_spread_bps,
             )?;
-            claimed_seat.base_a_withdrawable_asset_share = WrappedI80F48::from(
-                I80F48::from(claimed_seat.base_a_withdrawable_asset_share) - asset_shares,
-            );
         }
-    } else if is_increase {
-        claimed_seat.base_b_withdrawable_asset_share = WrappedI80F48::from(
-            I80F48::from(claimed_seat.base_b_withdrawable_asset_share) + asset_shares,
-        );
-    } else {
+        let order_index: DataIndex = find_order_index(
+            fixed,
+            dynamic,
+            use_a_tree,
+            trader_index,
+            order_sequence_number,
+        )?;
         require!(
-            I80F48::from(claimed_seat.base_b_withdrawable_asset_share) >= asset_shares,
-            ProgramError::InsufficientFunds,
-            "Not enough base b withdrawable asset shares. Has {}, needs {}",
-            I80F48::from(claimed_seat.base_b_withdrawable_asset_share),
-            asset_shares
+            is_not_nil!(order_index),
+            NixError::InvalidModifyOrder,
+            "Order {} not found",
+            order_sequence_number,
         )?;
-        claimed_seat.base_b_withdrawable_asset_share = WrappedI80F48::from(
-            I80F48::from(claimed_seat.base_b_withdrawable_asset_share) - asset_shares,
-        );
+
+        let resting_order: &mut RestingOrder =
+            get_mut_helper::<RBNode<RestingOrder>>(dynamic, order_index).get_mut_value();
+        require!(
+            resting_order.is_reverse(),
+            NixError::InvalidModifyOrder,
+            "Order {} is not a reverse order",
+            order_sequence_number,
+        )?;
+        resting_order.set_reverse_spread(reverse_spread_bps);
+        resting_order.set_reverse_bid_spread(reverse_bid_spread_bps);
+        resting_order.set_reverse_cycles_left(reverse_cycles_left);
+        Ok(())
     }
-    Ok(())
-}
 
+    /// Adds freshly deposited collateral_shares to a resting bid of the
+    /// trader. They go straight into the order, so the seat does not change.
+    #[cfg(feature = "program")]
+    pub fn top_up_order_collateral(
+        &mut self,
+        use_a_tree: bool,
+        trader_index: DataIndex,
+        order_sequence_number: u64,
+        collateral_shares: WrappedI80F48,
+    ) -> ProgramResult {
+        let DynamicAccount { fixed, dynamic } = self.borrow_mut();
+        let order_index: DataIndex = find_order_index(
+            fixed,
+            dynamic,
+            use_a_tree,
+            trader_index,
+            order_sequence_number,
+        )?;
+        require!(
+            is_not_nil!(order_index),
+            NixError::InvalidTopUp,
+            "Order {} not found",
+            order_sequence_number,
+        )?;
+
+        let resting_order: &mut RestingOrder =
+            get_mut_helper::<RBNode<RestingOrder>>(dynamic, order_index).get_mut_value();
+        require!(
+            resting_order.get_is_bid() && !resting_order.is_loan_sale(),
+            NixError::InvalidTopUp,
+            "Order {} is not a borrow order",
+            order_sequence_number,
+        )?;
+        resting_order.add_collateral_shares(collateral_shares)?;
+        Ok(())
+    }
+
+    /// Rests a LoanSale bid for the lender side of `loan`, which lives on
+    /// `market_loans`. It goes on the tree of the lent mint and is post only,
+    /// taker asks buy it through PlaceOrder. Returns the sequence number and
+    /// index of the order.
+    #[cfg(feature = "program")]
+    pub fn place_loan_sale(
+        &mut self,
+        trader_index: DataIndex,
+        market_loans: &Pubkey,
+        loan: &ActiveLoan,
+        rate_bps: u16,
+        last_valid_slot: u32,
+        now_slot: u32,
+    ) -> Result<(u64, DataIndex), ProgramError> {
+        assert_already_has_seat(trader_index)?;
+        assert_not_already_expired(last_valid_slot, now_slot)?;
+        require!(
+            loan.status == LoanStatus::Active
+                && loan.lender_index == trader_index
+                && loan.is_lender_global.0 == 0,
+            NixError::InvalidLoanSale,
+            "Loan {} is not an active loan of the seller",
+            loan.sequence_number,
+        )?;
+        require!(
+            rate_bps > 0 && (rate_bps as u32) < 10_000 + loan.rate_bps as u32,
+            NixError::InvalidLoanSale,
+            "Invalid sale rate {} for a loan at {}",
+            rate_bps,
+            loan.rate_bps,
+        )?;
+
+        let DynamicAccount { fixed, dynamic } = self.borrow_mut();
+        let use_a_tree: bool = loan.is_liability_base_a.0 == 1;
+        let (bids_best_index, asks_best_index, bids_root_index, _) =
+            get_tree_indexes(fixed, use_a_tree);
+        require!(
+            asks_best_index == NIL
+                || get_helper_order(dynamic, asks_best_index)
+                    .get_value()
+                    .get_rate_bps()
+                    > rate_bps,
+            NixError::PostOnlyCrosses,
+            "Loan sale at {} would cross the book",
+            rate_bps,
+        )?;
+        let bids: BooksideReadOnly =
+            BooksideReadOnly::new(dynamic, bids_root_index, bids_best_index);
+        for (_, resting_order) in bids.iter::<RestingOrder>() {
+            require!(
+                !resting_order.is_loan_sale()
+                    || resting_order.get_loan_sequence_number() != loan.sequence_number
+                    || resting_order.get_market_loans() != market_loans,
+                NixError::InvalidLoanSale,
+                "Loan {} is already for sale",
+                loan.sequence_number,
+            )?;
+        }
+
+        let order_sequence_number: u64 = if use_a_tree {
+            fixed.base_a_order_sequence_number = fixed.base_a_order_sequence_number.wrapping_add(1);
+            fixed.base_a_order_sequence_number
+        } else {
+            fixed.base_b_order_sequence_number = fixed.base_b_order_sequence_number.wrapping_add(1);
+            fixed.base_b_order_sequence_number
+        };
+        increment_open_orders(fixed, dynamic, trader_index)?;
+        count_new_resting_order(fixed, dynamic, trader_index, now_slot)?;
+        let free_address: DataIndex =
+            get_free_address_on_market_fixed_for_bid_order(fixed, dynamic);
+        let resting_order: RestingOrder = RestingOrder::new_loan_sale(
+            rate_bps,
+            order_sequence_number,
+            trader_index,
+            last_valid_slot,
+            *market_loans,
+            loan,
+        )?;
+        insert_order_into_tree(
+            use_a_tree,
+            true,
+            fixed,
+            dynamic,
+            free_address,
+            &resting_order,
+        );
+        set_payload_order(dynamic, free_address);
+        Ok((order_sequence_number, free_address))
+    }
+
+    /// Walks one side of a book from the best order and removes up to
+    /// `max_orders` expired orders, the same way matching does when it
+    /// crosses them. Expired bids turn into loans on the underlying protocol,
+    /// which are returned for the caller to insert. Gas deposits of expired
+    /// global orders go to the payer. Those of good till time orders are
+    /// returned for the caller to pay the payer from the market.
+    #[cfg(feature = "program")]
+    pub fn clean_expired_orders<'a, 'info>(
+        &mut self,
+        use_a_tree: bool,
+        is_bid: bool,
+        max_orders: u32,
+        now_slot: u32,
+        base_global_opt: &Option<NixAccountInfo<'a, 'info, GlobalFixed>>,
+        payer: &Signer<'a, 'info>,
+        system_program: &Program<'a, 'info>,
+    ) -> Result<(u32, MatchedLoans, u64), ProgramError> {
+        let DynamicAccount { fixed, dynamic } = self.borrow_mut();
+        let now_unix_timestamp: i64 = get_now_unix_timestamp();
+        // Every expired bid becomes a loan, so the loan buffer bounds the
+        // number of orders one call can remove.
+        let max_orders: u32 = max_orders.min(MAX_MATCHED_LOANS as u32);
+
+        let mut expired_loans: MatchedLoans = MatchedLoans::default();
+        let mut num_removed: u32 = 0;
+        let mut gas_deposit_lamports_released: u64 = 0;
+        let (bids_best_index, asks_best_index, _, _) = get_tree_indexes(fixed, use_a_tree);
+        let mut current_order_index: DataIndex = if is_bid {
+            bids_best_index
+        } else {
+            asks_best_index
+        };
+
+        while num_removed < max_orders && is_not_nil!(current_order_index) {
+            // Look up the next order before the current one leaves the tree.
+            let (bids_best_index, asks_best_index, bids_root_index, asks_root_index) =
+                get_tree_indexes(fixed, use_a_tree);
+            let next_order_index: DataIndex = get_next_candidate_match_index(
+                dynamic,
+                current_order_index,
+                asks_root_index,
+                asks_best_index,
+                bids_root_index,
+                bids_best_index,
+                !is_bid,
+            );
+
+            let resting_order: &RestingOrder =
+                get_helper_order(dynamic, current_order_index).get_value();
+            if !resting_order.is_expired(now_slot, now_unix_timestamp) {
+                current_order_index = next_order_index;
+                continue;
+            }
+            let trader_index: DataIndex = resting_order.get_trader_index();
+            let is_global: bool = resting_order.is_global();
+            let collateral_shares: WrappedI80F48 = resting_order.get_collateral_shares();
+            let liability_shares: WrappedI80F48 = resting_order.get_liability_shares();
+            let is_loan_sale: bool = resting_order.is_loan_sale();
+            gas_deposit_lamports_released += resting_order.get_gas_deposit_lamports();
+
+            if is_global {
+                if is_bid {
+                    return Err(NixError::InvalidGlobalBidOrder.into());
+                }
+                let trader: Pubkey = get_helper_seat(dynamic, trader_index).get_value().trader;
+                let base_global: &NixAccountInfo<GlobalFixed> =
+                    base_global_opt.as_ref().ok_or(NixError::MissingGlobal)?;
+                remove_from_global_core(
+                    base_global,
+                    &trader,
+                    &Some(payer.clone()),
+                    &Some(system_program.clone()),
+                )?;
+            } else if is_loan_sale {
+                // Nothing was locked for a loan sale, the loan stays with the
+                // seller.
+            } else if is_bid {
+                // Whatever its expiry policy, a bid keeps the borrow it made
+                // when resting as a loan to the pool.
+                expired_loans.push(ActiveLoan::new_empty(
+                    use_a_tree,
+                    0, //direct underlying protocol
+                    trader_index,
+                    false,
+                    collateral_shares,
+                    liability_shares,
+                    0, //underlying protocol rate
+                    now_unix_timestamp,
+                    now_slot.into(),
+                ))?;
+            } else {
+                update_balance(
+                    fixed,
+                    dynamic,
+                    trader_index,
+                    should_update_base_a(use_a_tree, false),
+                    true,
+                    collateral_shares,
+                )?;
+            }
+            remove_order_from_tree_and_free(
+                fixed,
+                dynamic,
+                use_a_tree,
+                current_order_index,
+                is_bid,
+            )?;
+
+            num_removed += 1;
+            current_order_index = next_order_index;
+        }
+        Ok((num_removed, expired_loans, gas_deposit_lamports_released))
+    }
+
+    /// Removes up to `max_orders` of the trader's own expired orders from
+    /// all four books and returns their collateral to the seat. Global
+    /// orders and bids, which convert to a pool loan, need accounts the
+    /// caller may not have, so they are left for CleanExpiredOrders. Returns the
+    /// number of orders removed and the gas deposits of the good till time
+    /// ones among them, which go back to the trader.
+    #[cfg(feature = "program")]
+    pub fn sweep_expired_orders_of_trader(
+        &mut self,
+        trader_index: DataIndex,
+        max_orders: u8,
+        now_slot: u32,
+        now_unix_timestamp: i64,
+    ) -> Result<(u32, u64), ProgramError> {
+        let DynamicAccount { fixed, dynamic } = self.borrow_mut();
+        let max_orders: usize = max_orders.min(MAX_SWEPT_EXPIRED_ORDERS) as usize;
+        if max_orders == 0 {
+            return Ok((0, 0));
+        }
+
+        // Orders cannot leave a tree while it is iterated, so find them
+        // first.
+        let mut expired_orders: [(bool, bool, DataIndex); MAX_SWEPT_EXPIRED_ORDERS as usize] =
+            [(false, false, NIL); MAX_SWEPT_EXPIRED_ORDERS as usize];
+        let mut num_expired: usize = 0;
+        'books: for use_a_tree in [true, false] {
+            let (bids_best_index, asks_best_index, bids_root_index, asks_root_index) =
+                get_tree_indexes(fixed, use_a_tree);
+            for (is_bid, root_index, best_index) in [
+                (true, bids_root_index, bids_best_index),
+                (false, asks_root_index, asks_best_index),
+            ] {
+                let tree: BooksideReadOnly = BooksideReadOnly::new(dynamic, root_index, best_index);
+                for (index, resting_order) in tree.iter::<RestingOrder>() {
+                    if num_expired == max_orders {
+                        break 'books;
+                    }
+                    if resting_order.get_trader_index() != trader_index
+                        || !resting_order.is_expired(now_slot, now_unix_timestamp)
+                        || resting_order.is_global()
+                        || (is_bid && !resting_order.is_loan_sale())
+                    {
+                        continue;
+                    }
+                    expired_orders[num_expired] = (use_a_tree, is_bid, index);
+                    num_expired += 1;
+                }
+            }
+        }
+
+        let mut gas_deposit_lamports_released: u64 = 0;
+        for &(use_a_tree, is_bid, order_index) in &expired_orders[..num_expired] {
+            let resting_order: &RestingOrder = get_helper_order(dynamic, order_index).get_value();
+            gas_deposit_lamports_released += resting_order.get_gas_deposit_lamports();
+            // Nothing was locked for a loan sale.
+            if !resting_order.is_loan_sale() {
+                let collateral_shares: WrappedI80F48 = resting_order.get_collateral_shares();
+                update_balance(
+                    fixed,
+                    dynamic,
+                    trader_index,
+                    should_update_base_a(use_a_tree, false),
+                    true,
+                    collateral_shares,
+                )?;
+            }
+            remove_order_from_tree_and_free(fixed, dynamic, use_a_tree, order_index, is_bid)?;
+        }
+        Ok((num_expired as u32, gas_deposit_lamports_released))
+    }
+
+    /// Splits asset shares of one base between the seats in proportion to
+    /// their withdrawable balance of it. Rounding dust, or everything when no
+    /// seat holds the base, goes to the protocol fees so the shares stay
+    /// claimed. Returns the shares given to seats.
+    pub fn distribute_to_seats(
+        &mut self,
+        is_base_a: bool,
+        asset_shares: I80F48,
+    ) -> Result<I80F48, ProgramError> {
+        let DynamicAccount { fixed, dynamic } = self.borrow_mut();
+        let total_seat_shares: I80F48 = if is_base_a {
+            fixed.base_a_marginfi_account_asset_shares
+        } else {
+            fixed.base_b_marginfi_account_shares
+        }
+        .into();
+
+        let mut distributed_shares: I80F48 = I80F48::ZERO;
+        if total_seat_shares > I80F48::ZERO {
+            // Balances cannot change while the seats are iterated, so find
+            // them first.
+            let seats: Vec<(DataIndex, I80F48)> =
+                ClaimedSeatTreeReadOnly::new(dynamic, fixed.claimed_seats_root_index, NIL)
+                    .iter::<ClaimedSeat>()
+                    .map(|(index, claimed_seat)| {
+                        (
+                            index,
+                            I80F48::from(claimed_seat.get_withdrawable_asset_share(is_base_a)),
+                        )
+                    })
+                    .filter(|(_, seat_shares)| *seat_shares > I80F48::ZERO)
+                    .collect();
+            for (trader_index, seat_shares) in seats {
+                // Dividing first keeps the product from overflowing.
+                let seat_asset_shares: I80F48 = seat_shares
+                    .checked_div(total_seat_shares)
+                    .and_then(|fraction| fraction.checked_mul(asset_shares))
+                    .ok_or(NixError::NumericalOverflow)?;
+                update_balance(
+                    fixed,
+                    dynamic,
+                    trader_index,
+                    is_base_a,
+                    true,
+                    seat_asset_shares.into(),
+                )?;
+                distributed_shares = distributed_shares
+                    .checked_add(seat_asset_shares)
+                    .ok_or(NixError::NumericalOverflow)?;
+            }
+        }
+
+        let undistributed_shares: I80F48 = asset_shares
+            .checked_sub(distributed_shares)
+            .ok_or(NixError::NumericalOverflow)?
+            .max(I80F48::ZERO);
+        let protocol_fee_shares: &mut WrappedI80F48 = if is_base_a {
+            &mut fixed.base_a_protocol_fee_shares
+        } else {
+            &mut fixed.base_b_protocol_fee_shares
+        };
+        *protocol_fee_shares = protocol_fee_shares
+            .checked_add(undistributed_shares)
+            .ok_or(NixError::NumericalOverflow)?;
+        Ok(distributed_shares)
+    }
+}
+
+/// Index of the order with the given sequence number on either side of one
+/// tree, NIL if there is none.
+#[cfg(feature = "program")]
+fn find_order_index(
+    fixed: &MarketFixed,
+    dynamic: &[u8],
+    use_a_tree: bool,
+    trader_index: DataIndex,
+    order_sequence_number: u64,
+) -> Result<DataIndex, ProgramError> {
+    let (bids_best_index, asks_best_index, bids_root_index, asks_root_index) =
+        get_tree_indexes(fixed, use_a_tree);
+
+    let mut order_index: DataIndex = NIL;
+    for (root_index, best_index) in [
+        (asks_root_index, asks_best_index),
+        (bids_root_index, bids_best_index),
+    ] {
+        let tree: BooksideReadOnly = BooksideReadOnly::new(dynamic, root_index, best_index);
+        for (index, resting_order) in tree.iter::<RestingOrder>() {
+            if resting_order.get_sequence_number() == order_sequence_number {
+                require!(
+                    resting_order.get_trader_index() == trader_index,
+                    NixError::InvalidCancel,
+                    "Cannot cancel for another trader",
+                )?;
+                require!(
+                    order_index == NIL,
+                    NixError::InvalidCancel,
+                    "Book is broken, matched multiple orders",
+                )?;
+                order_index = index;
+            }
+        }
+    }
+    Ok(order_index)
+}
+
+#[cfg(feature = "program")]
+fn set_payload_order(dynamic: &mut [u8], free_address: DataIndex) {
+    get_mut_helper_order(dynamic, free_address)
+        .set_payload_type(MarketDataTreeNodeType::RestingOrder as u8);
+}
+#[cfg(feature = "program")]
+fn remove_order_from_tree(
+    fixed: &mut MarketFixed,
+    dynamic: &mut [u8],
+    use_a_tree: bool,
+    order_index: DataIndex,
+    is_bid: bool,
+) -> ProgramResult {
+    let indexes_before: (DataIndex, DataIndex) = get_side_tree_indexes(fixed, use_a_tree, is_bid);
+    if use_a_tree {
+        let mut tree: Bookside = if is_bid {
+            Bookside::new(
+                dynamic,
+                fixed.base_a_bids_root_index,
+                fixed.base_a_bids_best_index,
+            )
+        } else {
+            Bookside::new(
+                dynamic,
+                fixed.base_a_asks_root_index,
+                fixed.base_a_asks_best_index,
+            )
+        };
+        tree.remove_by_index(order_index);
+
+        // Possibly changes the root and/or best.
+        if is_bid {
+            trace!(
+                "remove order bid root:{}->{} max:{}->{}",
+                fixed.base_a_bids_root_index,
+                tree.get_root_index(),
+                fixed.base_a_bids_best_index,
+                tree.get_max_index()
+            );
+            fixed.base_a_bids_root_index = tree.get_root_index();
+            fixed.base_a_bids_best_index = tree.get_max_index();
+        } else {
+            trace!(
+                "remove order ask root:{}->{} max:{}->{}",
+                fixed.base_a_asks_root_index,
+                tree.get_root_index(),
+                fixed.base_a_asks_best_index,
+                tree.get_max_index()
+            );
+            fixed.base_a_asks_root_index = tree.get_root_index();
+            fixed.base_a_asks_best_index = tree.get_max_index();
+        }
+    } else {
+        let mut tree: Bookside = if is_bid {
+            Bookside::new(
+                dynamic,
+                fixed.base_b_bids_root_index,
+                fixed.base_b_bids_best_index,
+            )
+        } else {
+            Bookside::new(
+                dynamic,
+                fixed.base_b_asks_root_index,
+                fixed.base_b_asks_best_index,
+            )
+        };
+        tree.remove_by_index(order_index);
+
+        // Possibly changes the root and/or best.
+        if is_bid {
+            trace!(
+                "remove order bid root:{}->{} max:{}->{}",
+                fixed.base_b_bids_root_index,
+                tree.get_root_index(),
+                fixed.base_b_bids_best_index,
+                tree.get_max_index()
+            );
+            fixed.base_b_bids_root_index = tree.get_root_index();
+            fixed.base_b_bids_best_index = tree.get_max_index();
+        } else {
+            trace!(
+                "remove order ask root:{}->{} max:{}->{}",
+                fixed.base_b_asks_root_index,
+                tree.get_root_index(),
+                fixed.base_b_asks_best_index,
+                tree.get_max_index()
+            );
+            fixed.base_b_asks_root_index = tree.get_root_index();
+            fixed.base_b_asks_best_index = tree.get_max_index();
+        }
+    }
+    debug_log_tree_change(fixed, use_a_tree, is_bid, indexes_before, "remove");
+
+    Ok(())
+}
+
+#[cfg(feature = "program")]
+fn remove_order_from_tree_and_free(
+    fixed: &mut MarketFixed,
+    dynamic: &mut [u8],
+    use_a_tree: bool,
+    order_index: DataIndex,
+    is_bid: bool,
+) -> ProgramResult {
+    let trader_index: DataIndex = get_helper_order(dynamic, order_index)
+        .get_value()
+        .get_trader_index();
+    remove_order_from_tree(fixed, dynamic, use_a_tree, order_index, is_bid)?;
+    decrement_open_orders(dynamic, trader_index);
+    if is_bid {
+        release_address_on_market_fixed_for_bid_order(fixed, dynamic, order_index);
+    } else {
+        release_address_on_market_fixed_for_ask_order(fixed, dynamic, order_index);
+    }
+    Ok(())
+}
+#[cfg(feature = "program")]
+fn increment_open_orders(
+    fixed: &MarketFixed,
+    dynamic: &mut [u8],
+    trader_index: DataIndex,
+) -> ProgramResult {
+    let claimed_seat: &mut ClaimedSeat = get_mut_helper_seat(dynamic, trader_index).get_mut_value();
+    let max_open_orders: u16 = fixed.max_open_orders_per_seat;
+    require!(
+        max_open_orders == 0 || claimed_seat.num_open_orders < max_open_orders,
+        NixError::TooManyOpenOrders,
+        "Seat already has {} open orders, max is {}",
+        claimed_seat.num_open_orders,
+        max_open_orders
+    )?;
+    claimed_seat.num_open_orders = claimed_seat
+        .num_open_orders
+        .checked_add(1)
+        .ok_or(NixError::NumericalOverflow)?;
+    Ok(())
+}
+
+/// Counts a new resting order of the seat against the order rate limit of
+/// the market. Orders flipped by a fill are not counted, the maker did not
+/// place them.
+#[cfg(feature = "program")]
+fn count_new_resting_order(
+    fixed: &MarketFixed,
+    dynamic: &mut [u8],
+    trader_index: DataIndex,
+    now_slot: u32,
+) -> ProgramResult {
+    let (max_orders_per_window, order_window_slots) = fixed.get_order_rate_limit();
+    if max_orders_per_window == 0 {
+        return Ok(());
+    }
+    let claimed_seat: &mut ClaimedSeat = get_mut_helper_seat(dynamic, trader_index).get_mut_value();
+    require!(
+        claimed_seat.try_count_new_order(max_orders_per_window, order_window_slots, now_slot),
+        NixError::OrderRateLimited.into(),
+        "Seat already placed {} orders in the {} slot window",
+        max_orders_per_window,
+        order_window_slots,
+    )
+}
+
+#[cfg(feature = "program")]
+fn decrement_open_orders(dynamic: &mut [u8], trader_index: DataIndex) {
+    let claimed_seat: &mut ClaimedSeat = get_mut_helper_seat(dynamic, trader_index).get_mut_value();
+    claimed_seat.num_open_orders = claimed_seat.num_open_orders.saturating_sub(1);
+}
+
+#[allow(unused_variables)]
+pub fn update_balance(
+    fixed: &mut MarketFixed,
+    dynamic: &mut [u8],
+    trader_index: DataIndex,
+    update_base_a: bool,
+    is_increase: bool,
+    asset_shares: WrappedI80F48,
+) -> ProgramResult {
+    let claimed_seat: &mut ClaimedSeat = get_mut_helper_seat(dynamic, trader_index).get_mut_value();
+
+    trace!("update_balance_by_trader_index idx:{trader_index} base:{is_base} inc:{is_increase} amount:{asset_shares}");
+
+    if update_base_a {
+        if is_increase {
+            claimed_seat.base_a_withdrawable_asset_share = claimed_seat
+                .base_a_withdrawable_asset_share
+                .checked_add(asset_shares)
+                .ok_or(NixError::NumericalOverflow)?;
+        } else {
+            require!(
+                claimed_seat.base_a_withdrawable_asset_share >= asset_shares,
+                ProgramError::InsufficientFunds,
+                "Not enough base a withdrawable asset shares. Has {}, needs {}",
+                claimed_seat.base_a_withdrawable_asset_share,
+                asset_shares
+            )?;
+            claimed_seat.base_a_withdrawable_asset_share = claimed_seat
+                .base_a_withdrawable_asset_share
+                .checked_sub(asset_shares)
+                .ok_or(NixError::NumericalOverflow)?;
+        }
+    } else if is_increase {
+        claimed_seat.base_b_withdrawable_asset_share = claimed_seat
+            .base_b_withdrawable_asset_share
+            .checked_add(asset_shares)
+            .ok_or(NixError::NumericalOverflow)?;
+    } else {
+        require!(
+            claimed_seat.base_b_withdrawable_asset_share >= asset_shares,
+            ProgramError::InsufficientFunds,
+            "Not enough base b withdrawable asset shares. Has {}, needs {}",
+            claimed_seat.base_b_withdrawable_asset_share,
+            asset_shares
+        )?;
+        claimed_seat.base_b_withdrawable_asset_share = claimed_seat
+            .base_b_withdrawable_asset_share
+            .checked_sub(asset_shares)
+            .ok_or(NixError::NumericalOverflow)?;
+    }
+
+    let total_asset_shares: &mut WrappedI80F48 = if update_base_a {
+        &mut fixed.base_a_marginfi_account_asset_shares
+    } else {
+        &mut fixed.base_b_marginfi_account_shares
+    };
+    *total_asset_shares = if is_increase {
+        total_asset_shares.checked_add(asset_shares)
+    } else {
+        total_asset_shares.checked_sub(asset_shares)
+    }
+    .ok_or(NixError::NumericalOverflow)?;
+
+    if fixed.is_debug_log_enabled(DEBUG_LOG_BALANCES) {
+        solana_program::msg!(
+            "debug balance seat:{trader_index} base_a:{update_base_a} inc:{is_increase} shares:{asset_shares} now:{}",
+            claimed_seat.get_withdrawable_asset_share(update_base_a),
+        );
+    }
+    Ok(())
+}
+
+/// Protocol fee on a fill, in shares of the matched base asset. On
+/// interest based markets the fee only applies to the interest, which is the
+/// matched rate share of the notional.
+pub fn get_protocol_fee_shares(
+    matched_base_asset_shares: I80F48,
+    rate_bps: u16,
+    protocol_fee_rate_bps: u64,
+    fee_on_interest: bool,
+) -> Result<I80F48, ProgramError> {
+    let fee_basis_shares: I80F48 = if fee_on_interest {
+        matched_base_asset_shares
+            .checked_mul(I80F48::from_num(rate_bps))
+            .and_then(|v| v.checked_div(I80F48::from_num(10_000)))
+            .ok_or(NixError::NumericalOverflow)?
+    } else {
+        matched_base_asset_shares
+    };
+    fee_basis_shares
+        .checked_mul(I80F48::from_num(protocol_fee_rate_bps))
+        .and_then(|v| v.checked_div(I80F48::from_num(10_000)))
+        .ok_or_else(|| NixError::NumericalOverflow.into())
+}
+
+/// Splits a protocol fee between the maker rebate, the referrer, if any, and
+/// the market.
+#[cfg(feature = "program")]
+fn accrue_protocol_fee(
+    fixed: &mut MarketFixed,
+    dynamic: &mut [u8],
+    referrer_index: DataIndex,
+    maker_index: DataIndex,
+    update_base_a: bool,
+    protocol_fee_shares: I80F48,
+) -> ProgramResult {
+    if protocol_fee_shares == 0 {
+        return Ok(());
+    }
+    let referral_fee_shares: I80F48 = if is_not_nil!(referrer_index) {
+        protocol_fee_shares
+            .checked_mul(I80F48::from_num(fixed.fee_state.referral_bps))
+            .and_then(|v| v.checked_div(I80F48::from_num(10_000)))
+            .ok_or(NixError::NumericalOverflow)?
+    } else {
+        I80F48::ZERO
+    };
+    let maker_rebate_shares: I80F48 = protocol_fee_shares
+        .checked_mul(I80F48::from_num(fixed.fee_state.maker_rebate_bps))
+        .and_then(|v| v.checked_div(I80F48::from_num(10_000)))
+        .ok_or(NixError::NumericalOverflow)?;
+    let market_fee_shares: I80F48 = protocol_fee_shares
+        .checked_sub(referral_fee_shares)
+        .and_then(|v| v.checked_sub(maker_rebate_shares))
+        .ok_or(NixError::NumericalOverflow)?;
+
+    let maker_seat: &mut ClaimedSeat = get_mut_helper_seat(dynamic, maker_index).get_mut_value();
+    if update_base_a {
+        maker_seat.base_a_maker_rebate_shares = maker_seat
+            .base_a_maker_rebate_shares
+            .checked_add(maker_rebate_shares)
+            .ok_or(NixError::NumericalOverflow)?;
+    } else {
+        maker_seat.base_b_maker_rebate_shares = maker_seat
+            .base_b_maker_rebate_shares
+            .checked_add(maker_rebate_shares)
+            .ok_or(NixError::NumericalOverflow)?;
+    }
+
+    if is_not_nil!(referrer_index) {
+        let referrer_seat: &mut ClaimedSeat =
+            get_mut_helper_seat(dynamic, referrer_index).get_mut_value();
+        if update_base_a {
+            referrer_seat.base_a_referral_fee_shares = referrer_seat
+                .base_a_referral_fee_shares
+                .checked_add(referral_fee_shares)
+                .ok_or(NixError::NumericalOverflow)?;
+        } else {
+            referrer_seat.base_b_referral_fee_shares = referrer_seat
+                .base_b_referral_fee_shares
+                .checked_add(referral_fee_shares)
+                .ok_or(NixError::NumericalOverflow)?;
+        }
+    }
+    if update_base_a {
+        fixed.base_a_protocol_fee_shares = fixed
+            .base_a_protocol_fee_shares
+            .checked_add(market_fee_shares)
+            .ok_or(NixError::NumericalOverflow)?;
+    } else {
+        fixed.base_b_protocol_fee_shares = fixed
+            .base_b_protocol_fee_shares
+            .checked_add(market_fee_shares)
+            .ok_or(NixError::NumericalOverflow)?;
+    }
+    Ok(())
+}
+
+/// Adds to the liability of a seat on the base of the tree and enforces the
+/// per seat borrow limit of the market.
+#[cfg(feature = "program")]
+fn increase_seat_liability(
+    fixed: &mut MarketFixed,
+    dynamic: &mut [u8],
+    trader_index: DataIndex,
+    use_a_tree: bool,
+    liability_shares: I80F48,
+    base_marginfi_bank: &BankShareValues,
+) -> ProgramResult {
+    let claimed_seat: &mut ClaimedSeat = get_mut_helper_seat(dynamic, trader_index).get_mut_value();
+    let seat_liability_shares: &mut WrappedI80F48 = if use_a_tree {
+        &mut claimed_seat.base_a_liability_shares
+    } else {
+        &mut claimed_seat.base_b_liability_shares
+    };
+    *seat_liability_shares = seat_liability_shares
+        .checked_add(liability_shares)
+        .ok_or(NixError::NumericalOverflow)?;
+    let total_liability_shares: &mut WrappedI80F48 = if use_a_tree {
+        &mut fixed.base_a_marginfi_account_liability_shares
+    } else {
+        &mut fixed.base_b_marginfi_account_liability_shares
+    };
+    *total_liability_shares = total_liability_shares
+        .checked_add(liability_shares)
+        .ok_or(NixError::NumericalOverflow)?;
+
+    if fixed.max_borrow_utilization_bps == 0 {
+        return Ok(());
+    }
+    let seat_liability_atoms: I80F48 = I80F48::from(*seat_liability_shares)
+        .checked_mul(base_marginfi_bank.liability_share_value)
+        .ok_or(NixError::NumericalOverflow)?;
+    let max_liability_atoms: I80F48 = base_marginfi_bank
+        .total_asset_shares
+        .checked_mul(base_marginfi_bank.asset_share_value)
+        .and_then(|v| v.checked_mul(I80F48::from_num(fixed.max_borrow_utilization_bps)))
+        .and_then(|v| v.checked_div(I80F48::from_num(10_000)))
+        .ok_or(NixError::NumericalOverflow)?;
+    require!(
+        seat_liability_atoms <= max_liability_atoms,
+        NixError::BorrowLimitExceeded,
+        "Seat liability of {} atoms is over the limit of {} atoms",
+        seat_liability_atoms,
+        max_liability_atoms,
+    )?;
+    Ok(())
+}
+
+/// Takes liability off a seat on the base of the tree when a loan it
+/// borrowed is closed.
+/// Shares are valued when the debt goes away, so this never takes off more
+/// than the seat has left.
+fn decrease_seat_liability(
+    fixed: &mut MarketFixed,
+    dynamic: &mut [u8],
+    trader_index: DataIndex,
+    use_a_tree: bool,
+    liability_shares: I80F48,
+) -> ProgramResult {
+    let claimed_seat: &mut ClaimedSeat = get_mut_helper_seat(dynamic, trader_index).get_mut_value();
+    let seat_liability_shares: &mut WrappedI80F48 = if use_a_tree {
+        &mut claimed_seat.base_a_liability_shares
+    } else {
+        &mut claimed_seat.base_b_liability_shares
+    };
+    let liability_shares: I80F48 = liability_shares.min(I80F48::from(*seat_liability_shares));
+    *seat_liability_shares = seat_liability_shares
+        .checked_sub(liability_shares)
+        .ok_or(NixError::NumericalOverflow)?;
+    let total_liability_shares: &mut WrappedI80F48 = if use_a_tree {
+        &mut fixed.base_a_marginfi_account_liability_shares
+    } else {
+        &mut fixed.base_b_marginfi_account_liability_shares
+    };
+    *total_liability_shares = total_liability_shares
+        .checked_sub(liability_shares)
+        .ok_or(NixError::NumericalOverflow)?;
+    Ok(())
+}
+
+#[cfg(feature = "program")]
 fn record_volume_by_trader_index(
     dynamic: &mut [u8],
     trader_index: DataIndex,
@@ -1590,15 +3900,16 @@ fn record_volume_by_trader_index(
     let claimed_seat: &mut ClaimedSeat = get_mut_helper_seat(dynamic, trader_index).get_mut_value();
     if use_a_tree {
         claimed_seat.base_a_volume = I80F48::from(claimed_seat.base_a_volume)
-            .wrapping_add(amount_atoms)
+            .saturating_add(amount_atoms)
             .into();
     } else {
         claimed_seat.base_b_volume = I80F48::from(claimed_seat.base_b_volume)
-            .wrapping_add(amount_atoms)
+            .saturating_add(amount_atoms)
             .into();
     }
 }
 #[inline(always)]
+#[cfg(feature = "program")]
 fn insert_order_into_tree(
     use_a_tree: bool,
     is_bid: bool,
@@ -1607,6 +3918,7 @@ fn insert_order_into_tree(
     free_address: DataIndex,
     resting_order: &RestingOrder,
 ) {
+    let indexes_before: (DataIndex, DataIndex) = get_side_tree_indexes(fixed, use_a_tree, is_bid);
     if use_a_tree {
         let mut tree: Bookside = if is_bid {
             Bookside::new(
@@ -1686,7 +3998,43 @@ fn insert_order_into_tree(
             fixed.base_b_asks_best_index = tree.get_max_index();
         }
     }
+    debug_log_tree_change(fixed, use_a_tree, is_bid, indexes_before, "insert");
 }
+#[cfg(feature = "program")]
+fn get_side_tree_indexes(
+    fixed: &MarketFixed,
+    use_a_tree: bool,
+    is_bid: bool,
+) -> (DataIndex, DataIndex) {
+    match (use_a_tree, is_bid) {
+        (true, true) => (fixed.base_a_bids_root_index, fixed.base_a_bids_best_index),
+        (true, false) => (fixed.base_a_asks_root_index, fixed.base_a_asks_best_index),
+        (false, true) => (fixed.base_b_bids_root_index, fixed.base_b_bids_best_index),
+        (false, false) => (fixed.base_b_asks_root_index, fixed.base_b_asks_best_index),
+    }
+}
+/// Logs the root and best index of a tree when they moved, if the market has
+/// DEBUG_LOG_TREES on. Unlike trace!, this is decided at runtime.
+#[cfg(feature = "program")]
+fn debug_log_tree_change(
+    fixed: &MarketFixed,
+    use_a_tree: bool,
+    is_bid: bool,
+    (root_before, best_before): (DataIndex, DataIndex),
+    action: &str,
+) {
+    if !fixed.is_debug_log_enabled(DEBUG_LOG_TREES) {
+        return;
+    }
+    let (root_after, best_after) = get_side_tree_indexes(fixed, use_a_tree, is_bid);
+    if root_before != root_after || best_before != best_after {
+        solana_program::msg!(
+            "debug {action} tree:{} bid:{is_bid} root:{root_before}->{root_after} best:{best_before}->{best_after}",
+            if use_a_tree { "a" } else { "b" },
+        );
+    }
+}
+#[cfg(feature = "program")]
 fn get_next_candidate_match_index(
     dynamic: &[u8],
     current_maker_order_index: DataIndex,
@@ -1711,23 +4059,79 @@ fn get_next_candidate_match_index(
     }
 }
 
+/// Whether a remainder resting at `rate_bps` would cross a maker order from
+/// `maker_order_index` on, passing over the orders matching skips or removes.
+/// Past MAX_MATCHED_LOANS of those it is taken to cross, so a remainder never
+/// rests on a book that was not checked.
+#[cfg(feature = "program")]
+fn remainder_crosses_book(
+    fixed: &MarketFixed,
+    dynamic: &[u8],
+    use_a_tree: bool,
+    mut maker_order_index: DataIndex,
+    is_bid: bool,
+    rate_bps: u16,
+    now_slot: u32,
+    now_unix_timestamp: i64,
+) -> bool {
+    let (bids_best_index, asks_best_index, bids_root_index, asks_root_index) =
+        get_tree_indexes(fixed, use_a_tree);
+    for _ in 0..MAX_MATCHED_LOANS {
+        if maker_order_index == NIL {
+            return false;
+        }
+        let maker_order: &RestingOrder = get_helper_order(dynamic, maker_order_index).get_value();
+        match get_maker_step(maker_order, is_bid, rate_bps, now_slot, now_unix_timestamp) {
+            MakerStep::Match => return true,
+            MakerStep::Stop => return false,
+            MakerStep::Skip | MakerStep::Remove => {
+                maker_order_index = get_next_candidate_match_index(
+                    dynamic,
+                    maker_order_index,
+                    asks_root_index,
+                    asks_best_index,
+                    bids_root_index,
+                    bids_best_index,
+                    is_bid,
+                );
+            }
+        }
+    }
+    true
+}
+
+/// Returns the gas deposit of a good till time order, which the caller owes
+/// whoever removed it.
+#[cfg(feature = "program")]
 fn remove_and_update_balances(
     fixed: &mut MarketFixed,
     dynamic: &mut [u8],
     use_a_tree: bool,
     order_to_remove_index: DataIndex,
     global_trade_accounts_opts: &[Option<GlobalTradeAccounts>; 2],
-) -> ProgramResult {
+) -> Result<u64, ProgramError> {
     let resting_order_to_remove: &RestingOrder =
         get_helper_order(dynamic, order_to_remove_index).get_value();
     let order_to_remove_is_bid: bool = resting_order_to_remove.get_is_bid();
+    let gas_deposit_lamports: u64 = resting_order_to_remove.get_gas_deposit_lamports();
 
     // Global order balances are accounted for on the global accounts, not on the market.
     if resting_order_to_remove.is_global() {
         if order_to_remove_is_bid {
             return Err(NixError::InvalidGlobalBidOrder.into());
         } else {
-            remove_from_global(&global_trade_accounts_opts[0])?;
+            let trader_index: DataIndex = resting_order_to_remove.get_trader_index();
+            let trader: Pubkey = get_helper_seat(dynamic, trader_index).get_value().trader;
+            if !remove_from_global(&global_trade_accounts_opts[0], &trader)? {
+                // Remember the prepayment so it can be swept back later.
+                let claimed_seat: &mut ClaimedSeat =
+                    get_mut_helper_seat(dynamic, trader_index).get_mut_value();
+                if use_a_tree {
+                    claimed_seat.base_a_stranded_gas_prepayments += 1;
+                } else {
+                    claimed_seat.base_b_stranded_gas_prepayments += 1;
+                }
+            }
         }
     } else {
         //return asset_shares only if resting_order is ask
@@ -1753,11 +4157,11 @@ fn remove_and_update_balances(
         order_to_remove_index,
         order_to_remove_is_bid,
     )?;
-    Ok(())
+    Ok(gas_deposit_lamports)
 }
 
-fn get_tree_indexes(
-    fixed: &mut MarketFixed,
+pub(crate) fn get_tree_indexes(
+    fixed: &MarketFixed,
     use_a_tree: bool,
 ) -> (DataIndex, DataIndex, DataIndex, DataIndex) {
     let (bids_best_index, asks_best_index, bids_root_index, asks_root_index) = if use_a_tree {
@@ -1783,7 +4187,8 @@ fn get_tree_indexes(
     )
 }
 
-fn should_update_base_a(use_a_tree: bool, is_bid: bool) -> bool {
+#[cfg(any(feature = "program", feature = "test"))]
+pub(crate) fn should_update_base_a(use_a_tree: bool, is_bid: bool) -> bool {
     // Determine which base asset to use based on tree type and order type
     // In A tree: bids use base B (quote), asks use base A (base)
     // In B tree: bids use base A (quote), asks use base B (base)
@@ -1795,3 +4200,820 @@ fn should_update_base_a(use_a_tree: bool, is_bid: bool) -> bool {
     };
     use_base_a
 }
+
+// Needs the test feature for state::verify.
+#[cfg(all(test, feature = "test", feature = "program"))]
+#[path = "market_tree_replay.rs"]
+mod tree_replay;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::{
+        MarketRegistryFixed, MarketRegistryValue, MarketStats, PendingOrder, DEBUG_LOG_BALANCES,
+        DEBUG_LOG_TREES, MARKET_STATS_BUCKET_SLOTS, MARKET_STATS_NUM_BUCKETS,
+    };
+
+    #[test]
+    fn test_protocol_fee_on_notional() {
+        let notional: I80F48 = I80F48::from_num(1_000_000);
+        for (protocol_fee_rate_bps, expected) in
+            [(0, 0), (1, 100), (25, 2_500), (10_000, 1_000_000)]
+        {
+            assert_eq!(
+                get_protocol_fee_shares(notional, 500, protocol_fee_rate_bps, false).unwrap(),
+                I80F48::from_num(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_protocol_fee_on_interest() {
+        // 5% of 1_000_000 is 50_000 of interest.
+        let notional: I80F48 = I80F48::from_num(1_000_000);
+        for (protocol_fee_rate_bps, expected) in
+            [(0, 0), (100, 500), (1_000, 5_000), (10_000, 50_000)]
+        {
+            assert_eq!(
+                get_protocol_fee_shares(notional, 500, protocol_fee_rate_bps, true).unwrap(),
+                I80F48::from_num(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_protocol_fee_on_interest_zero_rate() {
+        let notional: I80F48 = I80F48::from_num(1_000_000);
+        assert_eq!(
+            get_protocol_fee_shares(notional, 0, 1_000, true).unwrap(),
+            I80F48::ZERO
+        );
+    }
+
+    /// Market with a claimed seat for each trader, in order.
+    fn new_market_with_seats(fee_state: FeeState, traders: &[Pubkey]) -> MarketValue {
+        let mut market: MarketValue = MarketValue {
+            fixed: MarketFixed {
+                claimed_seats_root_index: NIL,
+                free_list_head_index: NIL,
+                fee_state,
+                ..Default::default()
+            },
+            dynamic: vec![0; MARKET_BLOCK_SIZE * traders.len()],
+        };
+        for trader in traders {
+            market.market_expand().unwrap();
+            market.claim_seat(trader).unwrap();
+        }
+        market
+    }
+
+    #[test]
+    fn test_protocol_fee_referral_split() {
+        let maker: Pubkey = Pubkey::new_unique();
+        let referrer: Pubkey = Pubkey::new_unique();
+        let mut market: MarketValue = new_market_with_seats(
+            FeeState {
+                referral_bps: 3_000,
+                ..Default::default()
+            },
+            &[maker, referrer],
+        );
+        let maker_index: DataIndex = market.get_trader_index(&maker);
+        let referrer_index: DataIndex = market.get_trader_index(&referrer);
+
+        // 30% of 7 is not a whole number of shares. Whatever the referrer
+        // gets, the market gets exactly the rest.
+        let protocol_fee_shares: I80F48 = I80F48::from_num(7);
+        let DynamicAccount { fixed, dynamic } = market.borrow_mut();
+        accrue_protocol_fee(
+            fixed,
+            dynamic,
+            referrer_index,
+            maker_index,
+            true,
+            protocol_fee_shares,
+        )
+        .unwrap();
+
+        let referral_fee_shares: I80F48 = get_helper_seat(&market.dynamic, referrer_index)
+            .get_value()
+            .base_a_referral_fee_shares
+            .into();
+        let market_fee_shares: I80F48 = market.fixed.get_base_a_protocol_fee_shares().into();
+        assert!(referral_fee_shares > 2 && referral_fee_shares < 3);
+        assert_eq!(referral_fee_shares + market_fee_shares, protocol_fee_shares);
+        assert_eq!(
+            I80F48::from(market.fixed.get_base_b_protocol_fee_shares()),
+            0
+        );
+    }
+
+    #[test]
+    fn test_protocol_fee_without_referrer() {
+        let maker: Pubkey = Pubkey::new_unique();
+        let mut market: MarketValue = new_market_with_seats(
+            FeeState {
+                referral_bps: 3_000,
+                ..Default::default()
+            },
+            &[maker],
+        );
+        let maker_index: DataIndex = market.get_trader_index(&maker);
+
+        let DynamicAccount { fixed, dynamic } = market.borrow_mut();
+        accrue_protocol_fee(fixed, dynamic, NIL, maker_index, false, I80F48::from_num(7)).unwrap();
+        assert_eq!(
+            I80F48::from(market.fixed.get_base_b_protocol_fee_shares()),
+            7
+        );
+        let maker_seat: &ClaimedSeat = get_helper_seat(&market.dynamic, maker_index).get_value();
+        assert_eq!(I80F48::from(maker_seat.base_b_referral_fee_shares), 0);
+
+        // A zero fee leaves everything as is.
+        let DynamicAccount { fixed, dynamic } = market.borrow_mut();
+        accrue_protocol_fee(fixed, dynamic, NIL, maker_index, false, I80F48::ZERO).unwrap();
+        assert_eq!(
+            I80F48::from(market.fixed.get_base_b_protocol_fee_shares()),
+            7
+        );
+    }
+
+    #[test]
+    fn test_protocol_fee_maker_rebate() {
+        let maker: Pubkey = Pubkey::new_unique();
+        let referrer: Pubkey = Pubkey::new_unique();
+        let mut market: MarketValue = new_market_with_seats(
+            FeeState {
+                referral_bps: 3_000,
+                maker_rebate_bps: 2_500,
+                ..Default::default()
+            },
+            &[maker, referrer],
+        );
+        let maker_index: DataIndex = market.get_trader_index(&maker);
+        let referrer_index: DataIndex = market.get_trader_index(&referrer);
+
+        // Both shares of 7 are fractional, the market keeps the remainder so
+        // the three add back up to the fee.
+        let protocol_fee_shares: I80F48 = I80F48::from_num(7);
+        let DynamicAccount { fixed, dynamic } = market.borrow_mut();
+        accrue_protocol_fee(
+            fixed,
+            dynamic,
+            referrer_index,
+            maker_index,
+            false,
+            protocol_fee_shares,
+        )
+        .unwrap();
+
+        let maker_rebate_shares: I80F48 = get_helper_seat(&market.dynamic, maker_index)
+            .get_value()
+            .base_b_maker_rebate_shares
+            .into();
+        let referral_fee_shares: I80F48 = get_helper_seat(&market.dynamic, referrer_index)
+            .get_value()
+            .base_b_referral_fee_shares
+            .into();
+        let market_fee_shares: I80F48 = market.fixed.get_base_b_protocol_fee_shares().into();
+        assert!(maker_rebate_shares > 1 && maker_rebate_shares < 2);
+        assert!(referral_fee_shares > 2 && referral_fee_shares < 3);
+        assert_eq!(
+            maker_rebate_shares + referral_fee_shares + market_fee_shares,
+            protocol_fee_shares
+        );
+
+        // Claiming moves the rebate into the maker's balance.
+        assert_eq!(
+            market.claim_maker_rebates(maker_index).unwrap(),
+            (WrappedI80F48::ZERO, maker_rebate_shares.into())
+        );
+        assert_eq!(
+            I80F48::from(
+                get_helper_seat(&market.dynamic, maker_index)
+                    .get_value()
+                    .base_b_maker_rebate_shares
+            ),
+            0
+        );
+        assert_eq!(
+            I80F48::from(
+                market
+                    .get_seat_snapshot(&maker)
+                    .unwrap()
+                    .base_b_withdrawable_asset_share
+            ),
+            maker_rebate_shares
+        );
+    }
+
+    #[test]
+    fn test_migrate_from_v1() {
+        // Version 1 blocks are 112 bytes, migrating cannot relayout them.
+        let mut market_fixed: MarketFixed = MarketFixed {
+            version: 1,
+            max_borrow_utilization_bps: 7,
+            ..Default::default()
+        };
+        assert_eq!(
+            market_fixed.migrate().unwrap_err(),
+            NixError::MarketNotMigratable.into()
+        );
+        assert_eq!(market_fixed.get_version(), 1);
+        assert!(market_fixed.verify_version().is_err());
+    }
+
+    #[test]
+    fn test_migrate_from_v2_and_v3() {
+        // Version 2 and 3 blocks are 224 bytes, migrating cannot relayout
+        // them.
+        for version in [2, 3] {
+            let mut market_fixed: MarketFixed = MarketFixed {
+                version,
+                circuit_breaker_bps: 7,
+                ..Default::default()
+            };
+            assert_eq!(
+                market_fixed.migrate().unwrap_err(),
+                NixError::MarketNotMigratable.into()
+            );
+            assert_eq!(market_fixed.get_version(), version);
+            assert_eq!(market_fixed.get_circuit_breaker_bps(), 7);
+            assert!(market_fixed.verify_version().is_err());
+        }
+    }
+
+    #[test]
+    fn test_migrate_from_v4() {
+        let mut market_fixed: MarketFixed = MarketFixed {
+            version: 4,
+            auction_mode: PodBool::from(true),
+            ..Default::default()
+        };
+        assert_eq!(market_fixed.migrate().unwrap(), 4);
+        assert!(!market_fixed.is_auction_mode());
+    }
+
+    #[test]
+    fn test_migrate_from_v5() {
+        let mut market_fixed: MarketFixed = MarketFixed {
+            version: 5,
+            ..Default::default()
+        };
+        market_fixed.set_order_rate_limit(7, 7);
+        assert_eq!(market_fixed.migrate().unwrap(), 5);
+        assert_eq!(market_fixed.get_order_rate_limit(), (0, 0));
+    }
+
+    #[test]
+    fn test_migrate_from_v6() {
+        let mut market_fixed: MarketFixed = MarketFixed {
+            version: 6,
+            ..Default::default()
+        };
+        market_fixed.set_allowed_caller(&Pubkey::new_unique());
+        assert_eq!(market_fixed.migrate().unwrap(), 6);
+        assert!(!market_fixed.has_allowed_caller());
+    }
+
+    #[test]
+    fn test_migrate_from_v7() {
+        let mut market_fixed: MarketFixed = MarketFixed {
+            version: 7,
+            ..Default::default()
+        };
+        market_fixed.set_debug_log_flags(DEBUG_LOG_BALANCES | DEBUG_LOG_TREES);
+        assert_eq!(market_fixed.migrate().unwrap(), 7);
+        assert_eq!(market_fixed.get_debug_log_flags(), 0);
+    }
+
+    #[test]
+    fn test_migrate_from_v8() {
+        let mut market_fixed: MarketFixed = MarketFixed {
+            version: 8,
+            ..Default::default()
+        };
+        market_fixed.set_bank_config_hash(true, 7);
+        market_fixed.set_bank_config_hash(false, 7);
+        assert_eq!(market_fixed.migrate().unwrap(), 8);
+        assert_eq!(market_fixed.get_bank_config_hash(true), 0);
+        assert_eq!(market_fixed.get_bank_config_hash(false), 0);
+    }
+
+    #[test]
+    fn test_migrate_from_v9() {
+        let mut market_fixed: MarketFixed = MarketFixed {
+            version: 9,
+            ..Default::default()
+        };
+        market_fixed.set_bank_config_hash(true, 7);
+        assert_eq!(market_fixed.migrate().unwrap(), 9);
+        assert_eq!(market_fixed.get_version(), MARKET_VERSION);
+        assert_eq!(market_fixed.get_bank_config_hash(true), 7);
+        assert!(market_fixed.verify_version().is_ok());
+    }
+
+    #[test]
+    fn test_is_base_a_marginfi_bank() {
+        let market_fixed: MarketFixed = MarketFixed {
+            base_a_marginfi_bank: Pubkey::new_unique(),
+            ..Default::default()
+        };
+        assert_eq!(
+            market_fixed.is_base_a_marginfi_bank(market_fixed.get_base_a_marginfi_bank()),
+            Some(true)
+        );
+        // Base B of this one sided market has no bank.
+        assert_eq!(
+            market_fixed.is_base_a_marginfi_bank(market_fixed.get_base_b_marginfi_bank()),
+            None
+        );
+        assert_eq!(
+            market_fixed.is_base_a_marginfi_bank(&Pubkey::new_unique()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let mut market_fixed: MarketFixed = MarketFixed::default();
+        // Off, so only the price is recorded.
+        assert_eq!(
+            market_fixed
+                .update_circuit_breaker(I80F48::from_num(100), 10)
+                .unwrap(),
+            None
+        );
+
+        market_fixed.set_circuit_breaker(500, 50);
+        // The first price after setting it has nothing to compare against.
+        assert_eq!(
+            market_fixed
+                .update_circuit_breaker(I80F48::from_num(100), 10)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            market_fixed
+                .update_circuit_breaker(I80F48::from_num(105), 11)
+                .unwrap(),
+            None
+        );
+        assert!(!market_fixed.is_post_only(11));
+
+        // 10% down from 105.
+        assert_eq!(
+            market_fixed
+                .update_circuit_breaker(I80F48::from_num(94.5), 12)
+                .unwrap(),
+            Some(1_000)
+        );
+        assert!(market_fixed.is_post_only(61));
+        assert!(!market_fixed.is_post_only(62));
+
+        // Setting it again lifts the trip.
+        market_fixed.set_circuit_breaker(0, 0);
+        assert!(!market_fixed.is_post_only(12));
+    }
+
+    #[test]
+    fn test_twar_rate() {
+        let mut market_fixed: MarketFixed = MarketFixed::default();
+        let fill_value_usd: I80F48 = I80F48::from_num(RATE_ORACLE_MIN_FILL_USD);
+        assert_eq!(market_fixed.get_twar_rate_bps(true, 100), None);
+
+        market_fixed.record_matched_rate(true, 500, fill_value_usd, 100);
+        assert_eq!(market_fixed.get_last_matched_rate_bps(true), Some(500));
+        assert_eq!(market_fixed.get_twar_rate_bps(true, 100), Some(500));
+        assert_eq!(market_fixed.get_twar_rate_bps(false, 100), None);
+
+        // A new fill has no weight until time passes.
+        let next_slot: u32 = 100 + RATE_ORACLE_WINDOW_SLOTS;
+        market_fixed.record_matched_rate(true, 700, fill_value_usd, next_slot);
+        assert_eq!(market_fixed.get_last_matched_rate_bps(true), Some(700));
+        assert_eq!(market_fixed.get_twar_rate_bps(true, next_slot), Some(500));
+
+        // After one window the new rate has half the weight.
+        assert_eq!(
+            market_fixed.get_twar_rate_bps(true, next_slot + RATE_ORACLE_WINDOW_SLOTS),
+            Some(600)
+        );
+    }
+
+    #[test]
+    fn test_twar_rate_ignores_dust_fills() {
+        let mut market_fixed: MarketFixed = MarketFixed::default();
+        market_fixed.record_matched_rate(
+            true,
+            500,
+            I80F48::from_num(RATE_ORACLE_MIN_FILL_USD),
+            100,
+        );
+
+        // A one atom fill at an outlying rate, held for many windows, does
+        // not move the oracle.
+        let dust_value_usd: I80F48 = I80F48::from_num(0.000_01);
+        market_fixed.record_matched_rate(true, 10_000, dust_value_usd, 101);
+        assert_eq!(market_fixed.get_last_matched_rate_bps(true), Some(500));
+        assert_eq!(
+            market_fixed.get_twar_rate_bps(true, 101 + 10 * RATE_ORACLE_WINDOW_SLOTS),
+            Some(500)
+        );
+
+        // Nor does it count as the first fill of a tree.
+        market_fixed.record_matched_rate(false, 10_000, dust_value_usd, 101);
+        assert_eq!(market_fixed.get_last_matched_rate_bps(false), None);
+    }
+
+    #[test]
+    fn test_market_stats_rolling_window() {
+        let mut market_stats: MarketStats = MarketStats::new(Pubkey::default());
+        let slot: u32 = 10 * MARKET_STATS_BUCKET_SLOTS;
+        market_stats.record_match(true, 100, slot);
+        market_stats.record_match(false, 40, slot + 1);
+        market_stats.record_match(true, 50, slot + MARKET_STATS_BUCKET_SLOTS);
+        assert_eq!(
+            market_stats.get_rolling_volume_atoms(true, slot + MARKET_STATS_BUCKET_SLOTS),
+            150
+        );
+        assert_eq!(market_stats.get_rolling_volume_atoms(false, slot), 40);
+
+        // The first bucket falls out of the window and is reused later.
+        let end_slot: u32 = slot + MARKET_STATS_NUM_BUCKETS as u32 * MARKET_STATS_BUCKET_SLOTS;
+        assert_eq!(market_stats.get_rolling_volume_atoms(true, end_slot), 50);
+        market_stats.record_match(true, 7, end_slot);
+        assert_eq!(market_stats.get_rolling_volume_atoms(true, end_slot), 57);
+        assert_eq!(market_stats.get_rolling_volume_atoms(false, end_slot), 0);
+
+        // Open interest does not roll off.
+        assert_eq!(market_stats.get_open_interest_atoms(true), 157);
+        assert_eq!(market_stats.get_open_interest_atoms(false), 40);
+    }
+
+    #[test]
+    fn test_market_registry() {
+        let mint: Pubkey = Pubkey::new_unique();
+        let other_mint: Pubkey = Pubkey::new_unique();
+        let fixed: MarketRegistryFixed = MarketRegistryFixed::new_empty(&mint, &other_mint);
+        let reversed: MarketRegistryFixed = MarketRegistryFixed::new_empty(&other_mint, &mint);
+        assert!(fixed.mint_low < fixed.mint_high);
+        assert_eq!(
+            (fixed.mint_low, fixed.mint_high),
+            (reversed.mint_low, reversed.mint_high)
+        );
+
+        let mut market_registry: MarketRegistryValue = MarketRegistryValue {
+            fixed,
+            dynamic: vec![0; 2 * size_of::<Pubkey>()],
+        };
+        let markets: [Pubkey; 2] = [Pubkey::new_unique(), Pubkey::new_unique()];
+        for market in markets.iter() {
+            market_registry.register_market(market).unwrap();
+        }
+        assert_eq!(market_registry.list_markets(), &markets[..]);
+        // The account has to grow before each new market.
+        assert!(market_registry
+            .register_market(&Pubkey::new_unique())
+            .is_err());
+    }
+
+    #[test]
+    fn test_pending_order() {
+        let mut pending_order: PendingOrder =
+            PendingOrder::new(Pubkey::new_unique(), Pubkey::new_unique());
+        assert_eq!(pending_order.get_referrer(), None);
+        // No expiry by default.
+        assert!(!pending_order.is_expired(u32::MAX));
+
+        let referrer: Pubkey = Pubkey::new_unique();
+        pending_order.referrer = referrer;
+        pending_order.last_valid_slot = 100;
+        assert_eq!(pending_order.get_referrer(), Some(referrer));
+        assert!(!pending_order.is_expired(100));
+        assert!(pending_order.is_expired(101));
+    }
+
+    #[test]
+    fn test_migrate_unknown_version() {
+        for version in [0, MARKET_VERSION + 1] {
+            let mut market_fixed: MarketFixed = MarketFixed {
+                version,
+                ..Default::default()
+            };
+            assert!(market_fixed.migrate().is_err());
+            assert!(market_fixed.verify_version().is_err());
+        }
+    }
+
+    #[test]
+    fn test_required_marginfi_sides() {
+        use crate::state::{get_required_marginfi_sides, OrderType};
+
+        for (order_type, is_bid, expected) in [
+            (OrderType::PostOnly, true, [true, true]),
+            (OrderType::Limit, false, [true, true]),
+            (OrderType::PostOnly, false, [true, false]),
+            (OrderType::Global, false, [false, false]),
+        ] {
+            assert_eq!(get_required_marginfi_sides(order_type, is_bid), expected);
+        }
+    }
+
+    #[test]
+    fn test_next_reverse_cycles() {
+        use crate::state::get_next_reverse_cycles;
+
+        // No limit keeps flipping, the last cycle rests as a limit order.
+        assert_eq!(get_next_reverse_cycles(0), Some(0));
+        assert_eq!(get_next_reverse_cycles(1), None);
+        assert_eq!(get_next_reverse_cycles(2), Some(1));
+    }
+
+    #[test]
+    fn test_time_on_book() {
+        use crate::state::{ExpiryPolicy, OrderType};
+
+        let mut resting_order: RestingOrder = RestingOrder::new(
+            100,
+            1,
+            WrappedI80F48::default(),
+            WrappedI80F48::default(),
+            true,
+            0,
+            50,
+            OrderType::Limit,
+            false,
+            0,
+            ExpiryPolicy::ReturnCollateral,
+        )
+        .unwrap();
+        resting_order.start_time_on_book(1_000, 10);
+        assert_eq!(resting_order.accrue_time_on_book(400, 20), 10_000);
+        assert_eq!(resting_order.accrue_time_on_book(400, 20), 0);
+        // Nothing counts past the expiry.
+        assert_eq!(resting_order.accrue_time_on_book(0, 90), 12_000);
+        assert_eq!(resting_order.get_time_on_book_base_atoms(), 0);
+    }
+
+    #[test]
+    fn test_fifo_within_rate() {
+        use crate::state::{
+            Bookside, ExpiryPolicy, OrderType, MARKET_BLOCK_SIZE, NO_EXPIRATION_LAST_VALID_SLOT,
+        };
+        use hypertree::HyperTreeWriteOperations;
+
+        for is_bid in [true, false] {
+            let new_order = |rate_bps: u16, sequence_number: u64| -> RestingOrder {
+                RestingOrder::new(
+                    rate_bps,
+                    sequence_number,
+                    WrappedI80F48::default(),
+                    WrappedI80F48::default(),
+                    true,
+                    0,
+                    NO_EXPIRATION_LAST_VALID_SLOT,
+                    OrderType::Limit,
+                    is_bid,
+                    0,
+                    ExpiryPolicy::ReturnCollateral,
+                )
+                .unwrap()
+            };
+            let better_rate_bps: u16 = if is_bid { 110 } else { 90 };
+            // Inserted out of time order, matched best rate first and then
+            // oldest first.
+            let orders: [RestingOrder; 4] = [
+                new_order(100, 3),
+                new_order(100, 1),
+                new_order(better_rate_bps, 4),
+                new_order(100, 2),
+            ];
+            let mut data: Vec<u8> = vec![0; MARKET_BLOCK_SIZE * orders.len()];
+            let mut tree: Bookside = Bookside::new(&mut data, NIL, NIL);
+            for (block, order) in orders.iter().enumerate() {
+                tree.insert((block * MARKET_BLOCK_SIZE) as DataIndex, *order);
+            }
+            let mut indexes: Vec<DataIndex> = Vec::new();
+            let mut index: DataIndex = tree.get_max_index();
+            while index != NIL {
+                indexes.push(index);
+                index = tree.get_next_lower_index::<RestingOrder>(index);
+            }
+
+            let sequence_numbers: Vec<u64> = indexes
+                .iter()
+                .map(|index| {
+                    get_helper::<RBNode<RestingOrder>>(&data, *index)
+                        .get_value()
+                        .get_sequence_number()
+                })
+                .collect();
+            assert_eq!(sequence_numbers, vec![4, 1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn test_aggregate_levels() {
+        use crate::state::{Bookside, ExpiryPolicy, OrderType, NO_EXPIRATION_LAST_VALID_SLOT};
+        use hypertree::{HyperTreeReadOperations, HyperTreeWriteOperations};
+
+        let new_ask = |rate_bps: u16, collateral_shares: u64, last_valid_slot: u32| {
+            RestingOrder::new(
+                rate_bps,
+                0,
+                I80F48::from_num(collateral_shares).into(),
+                WrappedI80F48::default(),
+                true,
+                0,
+                last_valid_slot,
+                OrderType::Limit,
+                false,
+                0,
+                ExpiryPolicy::ReturnCollateral,
+            )
+            .unwrap()
+        };
+        // The expired ask at 90 is better than all the others but cannot
+        // match any more.
+        let mut asks: [RestingOrder; 4] = [
+            new_ask(100, 2, NO_EXPIRATION_LAST_VALID_SLOT),
+            new_ask(120, 5, NO_EXPIRATION_LAST_VALID_SLOT),
+            new_ask(100, 3, NO_EXPIRATION_LAST_VALID_SLOT),
+            new_ask(90, 4, 5),
+        ];
+        asks[1].set_good_till_time(1_000, 5_000);
+        let mut dynamic: Vec<u8> = vec![0; MARKET_BLOCK_SIZE * asks.len()];
+        let mut tree: Bookside = Bookside::new(&mut dynamic, NIL, NIL);
+        for (block, ask) in asks.iter().enumerate() {
+            tree.insert((block * MARKET_BLOCK_SIZE) as DataIndex, *ask);
+        }
+        let (root_index, best_index) = (tree.get_root_index(), tree.get_max_index());
+        let market: MarketValue = MarketValue {
+            fixed: MarketFixed {
+                base_a_asks_root_index: root_index,
+                base_a_asks_best_index: best_index,
+                base_a_bids_root_index: NIL,
+                base_a_bids_best_index: NIL,
+                ..Default::default()
+            },
+            dynamic,
+        };
+
+        let levels: Vec<(u16, u16, I80F48)> = market
+            .aggregate_levels(true, false, 3, 10, 0)
+            .unwrap()
+            .iter()
+            .map(|level| (level.rate_bps, level.num_orders, level.total_shares.into()))
+            .collect();
+        assert_eq!(
+            levels,
+            vec![(100, 2, I80F48::from_num(5)), (120, 1, I80F48::from_num(5))]
+        );
+        assert_eq!(
+            market
+                .aggregate_levels(true, false, 1, 10, 0)
+                .unwrap()
+                .len(),
+            1
+        );
+        // Before it expired the ask at 90 is its own level.
+        assert_eq!(
+            market.aggregate_levels(true, false, 1, 0, 0).unwrap()[0].rate_bps,
+            90
+        );
+        assert!(market
+            .aggregate_levels(true, true, 3, 10, 0)
+            .unwrap()
+            .is_empty());
+        // The good till time ask at 120 is gone once its time passed.
+        assert_eq!(
+            market
+                .aggregate_levels(true, false, 3, 10, 1_001)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let (book_levels, num_levels) = market.get_book_levels(true, false, 3, 10, 0).unwrap();
+        assert_eq!(num_levels, 2);
+        assert_eq!(book_levels[1].rate_bps, 120);
+    }
+
+    #[test]
+    fn test_aggregate_levels_overflow() {
+        use crate::state::{Bookside, ExpiryPolicy, OrderType, NO_EXPIRATION_LAST_VALID_SLOT};
+        use hypertree::{HyperTreeReadOperations, HyperTreeWriteOperations};
+
+        let ask: RestingOrder = RestingOrder::new(
+            100,
+            0,
+            I80F48::MAX.into(),
+            WrappedI80F48::default(),
+            true,
+            0,
+            NO_EXPIRATION_LAST_VALID_SLOT,
+            OrderType::Limit,
+            false,
+            0,
+            ExpiryPolicy::ReturnCollateral,
+        )
+        .unwrap();
+        let mut dynamic: Vec<u8> = vec![0; MARKET_BLOCK_SIZE * 2];
+        let mut tree: Bookside = Bookside::new(&mut dynamic, NIL, NIL);
+        tree.insert(0, ask);
+        tree.insert(MARKET_BLOCK_SIZE as DataIndex, ask);
+        let (root_index, best_index) = (tree.get_root_index(), tree.get_max_index());
+        let market: MarketValue = MarketValue {
+            fixed: MarketFixed {
+                base_a_asks_root_index: root_index,
+                base_a_asks_best_index: best_index,
+                ..Default::default()
+            },
+            dynamic,
+        };
+
+        // Two orders at the same rate whose shares do not fit in one level.
+        assert!(market.aggregate_levels(true, false, 3, 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_has_marginfi_bank() {
+        let market_fixed: MarketFixed = MarketFixed {
+            base_a_marginfi_bank: Pubkey::new_unique(),
+            ..Default::default()
+        };
+        assert!(market_fixed.has_marginfi_bank(true));
+        assert!(!market_fixed.has_marginfi_bank(false));
+    }
+
+    #[test]
+    fn test_distribute_to_seats() {
+        let mut market: MarketValue = MarketValue {
+            fixed: MarketFixed {
+                claimed_seats_root_index: NIL,
+                free_list_head_index: NIL,
+                ..Default::default()
+            },
+            dynamic: vec![0; MARKET_BLOCK_SIZE * 3],
+        };
+        let traders: [Pubkey; 3] = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        for (trader, shares) in traders.iter().zip([1, 3, 0]) {
+            market.market_expand().unwrap();
+            market.claim_seat(trader).unwrap();
+            let trader_index: DataIndex = market.get_trader_index(trader);
+            let DynamicAccount { fixed, dynamic } = market.borrow_mut();
+            update_balance(
+                fixed,
+                dynamic,
+                trader_index,
+                true,
+                true,
+                I80F48::from_num(shares).into(),
+            )
+            .unwrap();
+        }
+
+        assert_eq!(
+            market
+                .distribute_to_seats(true, I80F48::from_num(8))
+                .unwrap(),
+            8
+        );
+        let withdrawable: Vec<I80F48> = traders
+            .iter()
+            .map(|trader| {
+                market
+                    .get_seat_snapshot(trader)
+                    .unwrap()
+                    .base_a_withdrawable_asset_share
+                    .into()
+            })
+            .collect();
+        assert_eq!(withdrawable, vec![3, 9, 0]);
+        assert_eq!(
+            I80F48::from(market.fixed.get_base_a_seat_asset_shares()),
+            12
+        );
+        assert_eq!(
+            I80F48::from(market.fixed.get_base_a_protocol_fee_shares()),
+            0
+        );
+
+        // No seat holds base b, so all of it goes to the protocol.
+        assert_eq!(
+            market
+                .distribute_to_seats(false, I80F48::from_num(5))
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            I80F48::from(market.fixed.get_base_b_protocol_fee_shares()),
+            5
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use bytemuck::{Pod, Zeroable};
+use hypertree::Get;
+use shank::ShankType;
+use solana_program::{entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey};
+use static_assertions::const_assert_eq;
+use std::mem::size_of;
+
+use crate::{
+    require,
+    state::MARKET_AUCTION_SIZE,
+    utils::{is_discriminant_of, StableName},
+    validation::NixAccount,
+};
+
+/// Where a market in auction mode is in its cycle at a slot.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum AuctionPhase {
+    /// Orders only rest, nothing matches.
+    Collecting,
+    /// The window closed and RunAuction has not cleared it yet. Orders only
+    /// rest until it does.
+    Due,
+    /// Takers on the right side of the clearing rate of the last auction
+    /// match at that rate.
+    Settling,
+}
+
+/// Auction state of a market in auction mode. Orders collect for
+/// window_slots, RunAuction then finds the rate that clears the most base on
+/// each tree and the market settles at it for another window_slots before the
+/// next window opens.
+#[repr(C)]
+#[derive(Default, Copy, Clone, Zeroable, Pod, ShankType)]
+pub struct MarketAuction {
+    /// Discriminant for identifying this account type.
+    pub discriminant: u64,
+    pub market: Pubkey,
+
+    /// Length of the collecting and settling phases.
+    window_slots: u32,
+    /// Slot the current window closes at.
+    window_end_slot: u32,
+    /// Slot the settlement of the last auction ends at. Zero before the
+    /// first one.
+    settle_end_slot: u32,
+
+    /// Clearing rate of the last auction on each tree.
+    base_a_clearing_rate_bps: u16,
+    base_b_clearing_rate_bps: u16,
+    /// Base atoms the last auction cleared on each tree. Zero when the book
+    /// did not cross.
+    base_a_cleared_base_atoms: u64,
+    base_b_cleared_base_atoms: u64,
+}
+
+const_assert_eq!(
+    size_of::<MarketAuction>(),
+    8 +   // discriminant
+    32 +  // market
+    4 +   // window_slots
+    4 +   // window_end_slot
+    4 +   // settle_end_slot
+    2 +   // base_a_clearing_rate_bps
+    2 +   // base_b_clearing_rate_bps
+    8 +   // base_a_cleared_base_atoms
+    8 // base_b_cleared_base_atoms
+);
+const_assert_eq!(size_of::<MarketAuction>(), MARKET_AUCTION_SIZE);
+const_assert_eq!(size_of::<MarketAuction>() % 8, 0);
+
+impl MarketAuction {
+    pub fn new(market: Pubkey, window_slots: u32, now_slot: u32) -> Self {
+        MarketAuction {
+            discriminant: crate::utils::get_discriminant::<MarketAuction>().unwrap(),
+            market,
+            window_slots,
+            window_end_slot: now_slot.saturating_add(window_slots),
+            ..Default::default()
+        }
+    }
+
+    pub fn get_window_slots(&self) -> u32 {
+        self.window_slots
+    }
+    pub fn get_window_end_slot(&self) -> u32 {
+        self.window_end_slot
+    }
+    pub fn get_settle_end_slot(&self) -> u32 {
+        self.settle_end_slot
+    }
+
+    pub fn get_phase(&self, now_slot: u32) -> AuctionPhase {
+        if now_slot < self.settle_end_slot {
+            AuctionPhase::Settling
+        } else if now_slot < self.window_end_slot {
+            AuctionPhase::Collecting
+        } else {
+            AuctionPhase::Due
+        }
+    }
+
+    /// Clearing rate of the last auction on the tree, None when it cleared
+    /// nothing.
+    pub fn get_clearing_rate_bps(&self, use_a_tree: bool) -> Option<u16> {
+        if self.get_cleared_base_atoms(use_a_tree) == 0 {
+            None
+        } else if use_a_tree {
+            Some(self.base_a_clearing_rate_bps)
+        } else {
+            Some(self.base_b_clearing_rate_bps)
+        }
+    }
+
+    pub fn get_cleared_base_atoms(&self, use_a_tree: bool) -> u64 {
+        if use_a_tree {
+            self.base_a_cleared_base_atoms
+        } else {
+            self.base_b_cleared_base_atoms
+        }
+    }
+
+    /// Records the clearing of each tree and settles at it from now_slot.
+    /// The next window opens once the settlement ends.
+    pub(crate) fn settle(
+        &mut self,
+        base_a_clearing: Option<(u16, u64)>,
+        base_b_clearing: Option<(u16, u64)>,
+        now_slot: u32,
+    ) {
+        let (base_a_clearing_rate_bps, base_a_cleared_base_atoms) =
+            base_a_clearing.unwrap_or_default();
+        let (base_b_clearing_rate_bps, base_b_cleared_base_atoms) =
+            base_b_clearing.unwrap_or_default();
+        self.base_a_clearing_rate_bps = base_a_clearing_rate_bps;
+        self.base_a_cleared_base_atoms = base_a_cleared_base_atoms;
+        self.base_b_clearing_rate_bps = base_b_clearing_rate_bps;
+        self.base_b_cleared_base_atoms = base_b_cleared_base_atoms;
+        self.settle_end_slot = now_slot.saturating_add(self.window_slots);
+        self.window_end_slot = self.settle_end_slot.saturating_add(self.window_slots);
+    }
+}
+
+impl Get for MarketAuction {}
+impl StableName for MarketAuction {
+    const STABLE_NAME: &'static str = "nix::MarketAuction";
+    const LEGACY_NAME: &'static str = "nix::state::market_auction::MarketAuction";
+}
+impl NixAccount for MarketAuction {
+    fn verify_discriminant(&self) -> ProgramResult {
+        let expected_discriminant: u64 = crate::utils::get_discriminant::<MarketAuction>().unwrap();
+
+        require!(
+            is_discriminant_of::<MarketAuction>(self.discriminant),
+            ProgramError::InvalidAccountData,
+            "Invalid market auction discriminant actual: {} expected: {}",
+            self.discriminant,
+            expected_discriminant
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_auction_phases() {
+        let mut market_auction: MarketAuction = MarketAuction::new(Pubkey::default(), 10, 100);
+        assert_eq!(market_auction.get_phase(100), AuctionPhase::Collecting);
+        assert_eq!(market_auction.get_phase(109), AuctionPhase::Collecting);
+        assert_eq!(market_auction.get_phase(110), AuctionPhase::Due);
+        assert_eq!(market_auction.get_clearing_rate_bps(true), None);
+
+        // Cleared late, so settlement runs from when it cleared.
+        market_auction.settle(Some((500, 1_000)), None, 115);
+        assert_eq!(market_auction.get_phase(115), AuctionPhase::Settling);
+        assert_eq!(market_auction.get_phase(124), AuctionPhase::Settling);
+        assert_eq!(market_auction.get_phase(125), AuctionPhase::Collecting);
+        assert_eq!(market_auction.get_phase(135), AuctionPhase::Due);
+        assert_eq!(market_auction.get_clearing_rate_bps(true), Some(500));
+        assert_eq!(market_auction.get_cleared_base_atoms(true), 1_000);
+        assert_eq!(market_auction.get_clearing_rate_bps(false), None);
+    }
+}
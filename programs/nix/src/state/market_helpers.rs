@@ -1,17 +1,13 @@
 mod free_addr_helpers {
 
-    use crate::state::market::{MarketFixed, MarketUnusedFreeListPadding};
-    use hypertree::{DataIndex, FreeList};
+    use crate::state::{allocate_block, market::MarketFixed, release_block};
+    use hypertree::DataIndex;
 
     pub fn get_free_address_on_market_fixed(
         fixed: &mut MarketFixed,
         dynamic: &mut [u8],
     ) -> DataIndex {
-        let mut free_list: FreeList<MarketUnusedFreeListPadding> =
-            FreeList::new(dynamic, fixed.free_list_head_index);
-        let free_address: DataIndex = free_list.remove();
-        fixed.free_list_head_index = free_list.get_head();
-        free_address
+        allocate_block(fixed, dynamic)
     }
 
     pub fn get_free_address_on_market_fixed_for_seat(
@@ -26,10 +22,7 @@ mod free_addr_helpers {
         dynamic: &mut [u8],
         index: DataIndex,
     ) {
-        let mut free_list: FreeList<MarketUnusedFreeListPadding> =
-            FreeList::new(dynamic, fixed.free_list_head_index);
-        free_list.add(index);
-        fixed.free_list_head_index = index;
+        release_block(fixed, dynamic, index);
     }
     pub fn get_free_address_on_market_fixed_for_bid_order(
         fixed: &mut MarketFixed,
@@ -0,0 +1,250 @@
+use bytemuck::{Pod, Zeroable};
+use fixed::types::I80F48;
+use hypertree::Get;
+use shank::ShankType;
+use solana_program::{entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey};
+use static_assertions::const_assert_eq;
+use std::mem::size_of;
+
+use crate::{
+    program::NixError,
+    quantities::WrappedI80F48,
+    require,
+    state::MARKET_INSURANCE_SIZE,
+    utils::{is_discriminant_of, StableName},
+    validation::NixAccount,
+};
+
+/// Insurance fund of a market. It holds asset shares in the marginfi
+/// accounts of the market, next to what the seats and the protocol are owed.
+/// insurance_fee_bps of the protocol fees move into it, and a default is
+/// covered from it before the lender takes the loss.
+#[repr(C)]
+#[derive(Default, Copy, Clone, Zeroable, Pod, ShankType)]
+pub struct MarketInsurance {
+    /// Discriminant for identifying this account type.
+    pub discriminant: u64,
+    pub market: Pubkey,
+
+    /// Share of the protocol fees of the market that goes to the fund, in
+    /// bps.
+    insurance_fee_bps: u16,
+    _padding: [u8; 6],
+
+    /// Asset shares the fund holds on each base.
+    base_a_shares: WrappedI80F48,
+    base_b_shares: WrappedI80F48,
+
+    /// Protocol fee shares of the market as of the last accrual, so only
+    /// fees charged since then are split.
+    base_a_protocol_fee_shares_seen: WrappedI80F48,
+    base_b_protocol_fee_shares_seen: WrappedI80F48,
+}
+
+const_assert_eq!(
+    size_of::<MarketInsurance>(),
+    8 +   // discriminant
+    32 +  // market
+    2 +   // insurance_fee_bps
+    6 +   // _padding
+    16 +  // base_a_shares
+    16 +  // base_b_shares
+    16 +  // base_a_protocol_fee_shares_seen
+    16 // base_b_protocol_fee_shares_seen
+);
+const_assert_eq!(size_of::<MarketInsurance>(), MARKET_INSURANCE_SIZE);
+const_assert_eq!(size_of::<MarketInsurance>() % 8, 0);
+
+impl MarketInsurance {
+    /// Fees the market charged before the fund existed stay with the
+    /// protocol, so accrual starts from its current protocol fee shares.
+    pub fn new(
+        market: Pubkey,
+        insurance_fee_bps: u16,
+        base_a_protocol_fee_shares: WrappedI80F48,
+        base_b_protocol_fee_shares: WrappedI80F48,
+    ) -> Self {
+        MarketInsurance {
+            discriminant: crate::utils::get_discriminant::<MarketInsurance>().unwrap(),
+            market,
+            insurance_fee_bps,
+            base_a_protocol_fee_shares_seen: base_a_protocol_fee_shares,
+            base_b_protocol_fee_shares_seen: base_b_protocol_fee_shares,
+            ..Default::default()
+        }
+    }
+
+    pub fn get_insurance_fee_bps(&self) -> u16 {
+        self.insurance_fee_bps
+    }
+    pub(crate) fn set_insurance_fee_bps(&mut self, insurance_fee_bps: u16) {
+        self.insurance_fee_bps = insurance_fee_bps;
+    }
+
+    pub fn get_shares(&self, is_base_a: bool) -> WrappedI80F48 {
+        if is_base_a {
+            self.base_a_shares
+        } else {
+            self.base_b_shares
+        }
+    }
+
+    /// Splits the protocol fees charged since the last accrual. Takes the
+    /// share of the fund out of `protocol_fee_shares`, the current fee
+    /// shares of the market on the base, and returns what the market keeps.
+    pub(crate) fn accrue_fees(
+        &mut self,
+        is_base_a: bool,
+        protocol_fee_shares: WrappedI80F48,
+    ) -> Result<WrappedI80F48, ProgramError> {
+        let (shares, protocol_fee_shares_seen) = if is_base_a {
+            (
+                &mut self.base_a_shares,
+                &mut self.base_a_protocol_fee_shares_seen,
+            )
+        } else {
+            (
+                &mut self.base_b_shares,
+                &mut self.base_b_protocol_fee_shares_seen,
+            )
+        };
+        let new_fee_shares: I80F48 = I80F48::from(protocol_fee_shares)
+            .checked_sub(I80F48::from(*protocol_fee_shares_seen))
+            .ok_or(NixError::NumericalOverflow)?
+            .max(I80F48::ZERO);
+        let insurance_fee_shares: I80F48 = new_fee_shares
+            .checked_mul(I80F48::from_num(self.insurance_fee_bps))
+            .and_then(|v| v.checked_div(I80F48::from_num(10_000)))
+            .ok_or(NixError::NumericalOverflow)?;
+        *shares = shares
+            .checked_add(insurance_fee_shares)
+            .ok_or(NixError::NumericalOverflow)?;
+        let remaining_protocol_fee_shares: WrappedI80F48 = protocol_fee_shares
+            .checked_sub(insurance_fee_shares)
+            .ok_or(NixError::NumericalOverflow)?;
+        *protocol_fee_shares_seen = remaining_protocol_fee_shares;
+        Ok(remaining_protocol_fee_shares)
+    }
+
+    pub(crate) fn deposit(&mut self, is_base_a: bool, shares: I80F48) -> ProgramResult {
+        let fund_shares: &mut WrappedI80F48 = if is_base_a {
+            &mut self.base_a_shares
+        } else {
+            &mut self.base_b_shares
+        };
+        *fund_shares = fund_shares
+            .checked_add(shares)
+            .ok_or(NixError::NumericalOverflow)?;
+        Ok(())
+    }
+
+    /// Takes up to max_shares out of the fund. Returns the shares taken,
+    /// less than max_shares once the fund runs dry.
+    pub(crate) fn draw(
+        &mut self,
+        is_base_a: bool,
+        max_shares: I80F48,
+    ) -> Result<I80F48, ProgramError> {
+        let fund_shares: &mut WrappedI80F48 = if is_base_a {
+            &mut self.base_a_shares
+        } else {
+            &mut self.base_b_shares
+        };
+        let drawn_shares: I80F48 = max_shares.max(I80F48::ZERO).min(I80F48::from(*fund_shares));
+        *fund_shares = fund_shares
+            .checked_sub(drawn_shares)
+            .ok_or(NixError::NumericalOverflow)?;
+        Ok(drawn_shares)
+    }
+}
+
+impl Get for MarketInsurance {}
+impl StableName for MarketInsurance {
+    const STABLE_NAME: &'static str = "nix::MarketInsurance";
+    const LEGACY_NAME: &'static str = "nix::state::market_insurance::MarketInsurance";
+}
+impl NixAccount for MarketInsurance {
+    fn verify_discriminant(&self) -> ProgramResult {
+        let expected_discriminant: u64 =
+            crate::utils::get_discriminant::<MarketInsurance>().unwrap();
+
+        require!(
+            is_discriminant_of::<MarketInsurance>(self.discriminant),
+            ProgramError::InvalidAccountData,
+            "Invalid market insurance discriminant actual: {} expected: {}",
+            self.discriminant,
+            expected_discriminant
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_accrue_fees() {
+        let mut market_insurance: MarketInsurance = MarketInsurance::new(
+            Pubkey::default(),
+            2_000,
+            I80F48::from_num(500).into(),
+            I80F48::ZERO.into(),
+        );
+        // Fees from before the fund are not split.
+        assert_eq!(
+            market_insurance
+                .accrue_fees(true, I80F48::from_num(500).into())
+                .unwrap(),
+            I80F48::from_num(500).into()
+        );
+        assert_eq!(market_insurance.get_shares(true), I80F48::ZERO.into());
+
+        // 20% of the 1_000 charged since.
+        assert_eq!(
+            market_insurance
+                .accrue_fees(true, I80F48::from_num(1_500).into())
+                .unwrap(),
+            I80F48::from_num(1_300).into()
+        );
+        assert_eq!(
+            market_insurance.get_shares(true),
+            I80F48::from_num(200).into()
+        );
+        // Nothing new, nothing moves.
+        assert_eq!(
+            market_insurance
+                .accrue_fees(true, I80F48::from_num(1_300).into())
+                .unwrap(),
+            I80F48::from_num(1_300).into()
+        );
+        assert_eq!(market_insurance.get_shares(false), I80F48::ZERO.into());
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut market_insurance: MarketInsurance = MarketInsurance::new(
+            Pubkey::default(),
+            0,
+            I80F48::ZERO.into(),
+            I80F48::ZERO.into(),
+        );
+        market_insurance
+            .deposit(false, I80F48::from_num(100))
+            .unwrap();
+        assert_eq!(
+            market_insurance.draw(false, I80F48::from_num(60)).unwrap(),
+            I80F48::from_num(60)
+        );
+        // Runs dry at what is left.
+        assert_eq!(
+            market_insurance.draw(false, I80F48::from_num(60)).unwrap(),
+            I80F48::from_num(40)
+        );
+        assert_eq!(market_insurance.get_shares(false), I80F48::ZERO.into());
+        assert_eq!(
+            market_insurance.draw(true, I80F48::ONE).unwrap(),
+            I80F48::ZERO
+        );
+    }
+}
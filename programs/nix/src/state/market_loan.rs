@@ -1,29 +1,32 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use bytemuck::{Pod, Zeroable};
 use hypertree::{
-    DataIndex, FreeList, Get, HyperTreeReadOperations, HyperTreeWriteOperations, PodBool,
-    RedBlackTree, RedBlackTreeReadOnly, NIL,
+    get_helper, get_mut_helper, DataIndex, Get, HyperTreeReadOperations,
+    HyperTreeValueIteratorTrait, PodBool, RBNode, RedBlackTree, RedBlackTreeReadOnly, NIL,
 };
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use shank::ShankType;
 use solana_program::{entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey};
 use static_assertions::const_assert_eq;
-use std::{cmp::Ordering, mem::size_of};
+use std::{cmp::Ordering, mem::size_of, ops::Deref};
 
 use crate::{
     program::NixError,
     quantities::WrappedI80F48,
     require,
     state::{
-        DerefOrBorrowMut, DynamicAccount, ACTIVE_LOAN_SIZE, MARKET_LOANS_FIXED_SIZE,
+        expand_blocks, insert_node, remove_node, DerefOrBorrow, DerefOrBorrowMut, DynamicAccount,
+        ExpandableFixed, TreeNodeUpdate, ACTIVE_LOAN_SIZE, MARKET_LOANS_FIXED_SIZE,
         MARKET_LOAN_BLOCK_SIZE, MARKET_LOAN_FREE_LIST_BLOCK_SIZE, MAX_ACTIVE_LOANS,
+        MAX_MATCHED_LOANS,
     },
+    utils::{is_discriminant_of, StableName},
     validation::NixAccount,
 };
 
 #[repr(C, packed)]
 #[derive(Default, Copy, Clone, Pod, Zeroable)]
-struct MarketLoansUnusedFreeListPadding {
+pub struct MarketLoansUnusedFreeListPadding {
     _padding: [u64; 10],
     _padding2: [u8; 12],
 }
@@ -59,7 +62,7 @@ impl Default for LoanStatus {
     }
 }
 #[repr(C)]
-#[derive(Default, Copy, Clone, Zeroable, Pod)]
+#[derive(Default, Copy, Clone, Zeroable, Pod, ShankType)]
 pub struct MarketLoansFixed {
     /// Discriminant for identifying this account type.
     pub discriminant: u64,
@@ -77,6 +80,9 @@ pub struct MarketLoansFixed {
     /// Padding to ensure 8-byte alignment.
     _padding: [u8; 4],
     pub num_active_loans: u64,
+    /// Next loans account for the same market, used once this one is full.
+    /// Default pubkey when this is the last page.
+    pub next_page: Pubkey,
 }
 
 const_assert_eq!(
@@ -88,19 +94,42 @@ const_assert_eq!(
     4 +   // free_list_head_index
     4 +   // num_bytes_allocated 
     4 +   // _padding
-    8 // num_active_loans
+    8 +  // num_active_loans
+    32 // next_page
 );
 const_assert_eq!(size_of::<MarketLoansFixed>(), MARKET_LOANS_FIXED_SIZE);
 const_assert_eq!(size_of::<MarketLoansFixed>() % 8, 0);
 
 impl Get for MarketLoansFixed {}
+impl StableName for MarketLoansFixed {
+    const STABLE_NAME: &'static str = "nix::MarketLoansFixed";
+    const LEGACY_NAME: &'static str = "nix::state::market_loan::MarketLoansFixed";
+}
+
+impl ExpandableFixed for MarketLoansFixed {
+    type FreeListPadding = MarketLoansUnusedFreeListPadding;
+    const BLOCK_SIZE: usize = MARKET_LOAN_BLOCK_SIZE;
+
+    fn get_free_list_head_index(&self) -> DataIndex {
+        self.free_list_head_index
+    }
+    fn set_free_list_head_index(&mut self, free_list_head_index: DataIndex) {
+        self.free_list_head_index = free_list_head_index;
+    }
+    fn get_num_bytes_allocated(&self) -> DataIndex {
+        self.num_bytes_allocated
+    }
+    fn set_num_bytes_allocated(&mut self, num_bytes_allocated: DataIndex) {
+        self.num_bytes_allocated = num_bytes_allocated;
+    }
+}
 impl NixAccount for MarketLoansFixed {
     fn verify_discriminant(&self) -> ProgramResult {
         let expected_discriminant: u64 =
             crate::utils::get_discriminant::<MarketLoansFixed>().unwrap();
 
         require!(
-            self.discriminant == expected_discriminant,
+            is_discriminant_of::<MarketLoansFixed>(self.discriminant),
             ProgramError::InvalidAccountData,
             "Invalid market discriminant actual: {} expected: {}",
             self.discriminant,
@@ -121,14 +150,25 @@ impl MarketLoansFixed {
             num_bytes_allocated: 0,
             _padding: [0u8; 4],
             num_active_loans: 0,
+            next_page: Pubkey::default(),
         }
     }
     pub fn has_free_block(&self) -> bool {
         self.free_list_head_index != NIL
     }
+    pub fn is_full(&self) -> bool {
+        self.num_active_loans >= MAX_ACTIVE_LOANS
+    }
+    /// Loans that can still be added before the page is full.
+    pub fn get_num_free_loan_slots(&self) -> u32 {
+        MAX_ACTIVE_LOANS.saturating_sub(self.num_active_loans) as u32
+    }
+    pub fn has_next_page(&self) -> bool {
+        self.next_page != Pubkey::default()
+    }
 }
 #[repr(C)]
-#[derive(Default, Debug, Copy, Clone, Zeroable, Pod)]
+#[derive(Default, Debug, Copy, Clone, Zeroable, Pod, ShankType)]
 pub struct ActiveLoan {
     pub sequence_number: u64,
     pub lender_index: DataIndex,
@@ -202,6 +242,55 @@ impl ActiveLoan {
         self.sequence_number = sequence_number
     }
 }
+
+/// Loans created while matching one order. Allocated once on the heap with
+/// room for MAX_MATCHED_LOANS, a full buffer is too big for the program stack.
+#[derive(Clone)]
+pub struct MatchedLoans {
+    loans: Vec<ActiveLoan>,
+}
+
+impl Default for MatchedLoans {
+    fn default() -> Self {
+        MatchedLoans {
+            loans: Vec::with_capacity(MAX_MATCHED_LOANS),
+        }
+    }
+}
+
+impl MatchedLoans {
+    pub fn push(&mut self, loan: ActiveLoan) -> ProgramResult {
+        require!(
+            self.loans.len() < MAX_MATCHED_LOANS,
+            NixError::MaxActiveLoansExceeded,
+            "Cannot match more than {} loans in one order",
+            MAX_MATCHED_LOANS
+        )?;
+        self.loans.push(loan);
+        Ok(())
+    }
+}
+
+/// Loan bought from a LoanSale order while matching. Applied to the loans
+/// page once matching is done, the market account does not hold loans.
+#[derive(Copy, Clone, Debug)]
+pub struct LoanAssignment {
+    pub market_loans: Pubkey,
+    pub loan_sequence_number: u64,
+    pub seller_index: DataIndex,
+    pub buyer_index: DataIndex,
+    pub base_atoms: u64,
+    pub rate_bps: u16,
+}
+
+impl Deref for MatchedLoans {
+    type Target = [ActiveLoan];
+
+    fn deref(&self) -> &[ActiveLoan] {
+        &self.loans
+    }
+}
+
 pub type ActiveLoanTree<'a> = RedBlackTree<'a, ActiveLoan>;
 pub type ActiveLoanTreeReadOnly<'a> = RedBlackTreeReadOnly<'a, ActiveLoan>;
 
@@ -212,6 +301,40 @@ pub type MarketLoansRef<'a> = DynamicAccount<&'a MarketLoansFixed, &'a [u8]>;
 /// Full MarketLoans reference type.
 pub type MarketLoansRefMut<'a> = DynamicAccount<&'a mut MarketLoansFixed, &'a mut [u8]>;
 
+impl<Fixed: DerefOrBorrow<MarketLoansFixed>, Dynamic: DerefOrBorrow<[u8]>>
+    DynamicAccount<Fixed, Dynamic>
+{
+    /// Looks up an active loan by sequence number.
+    pub fn get_loan(&self, sequence_number: u64) -> Option<ActiveLoan> {
+        let fixed: &MarketLoansFixed = self.fixed.deref_or_borrow();
+        let dynamic: &[u8] = self.dynamic.deref_or_borrow();
+        let loan_index: DataIndex =
+            ActiveLoanTreeReadOnly::new(dynamic, fixed.active_loans_root_index, NIL).lookup_index(
+                &ActiveLoan {
+                    sequence_number,
+                    ..Default::default()
+                },
+            );
+        if loan_index == NIL {
+            return None;
+        }
+        Some(*get_helper::<RBNode<ActiveLoan>>(dynamic, loan_index).get_value())
+    }
+
+    /// Active loans of `borrower_index` on this page.
+    pub fn get_borrowed_loans(&self, borrower_index: DataIndex) -> Vec<ActiveLoan> {
+        let fixed: &MarketLoansFixed = self.fixed.deref_or_borrow();
+        let dynamic: &[u8] = self.dynamic.deref_or_borrow();
+        ActiveLoanTreeReadOnly::new(dynamic, fixed.active_loans_root_index, NIL)
+            .iter::<ActiveLoan>()
+            .map(|(_, loan)| *loan)
+            .filter(|loan| {
+                loan.status == LoanStatus::Active && loan.borrower_index == borrower_index
+            })
+            .collect()
+    }
+}
+
 impl<Fixed: DerefOrBorrowMut<MarketLoansFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
     DynamicAccount<Fixed, Dynamic>
 {
@@ -222,29 +345,16 @@ impl<Fixed: DerefOrBorrowMut<MarketLoansFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
         }
     }
 
-    /// Expands the MarketLoans account by n block, adding the new space
-    /// to the free list for future loan records.
+    /// Expands the MarketLoans account by n blocks, adding each of them to
+    /// the free list for future loan records.
     pub fn expand_loan_account(&mut self, n: u32) -> ProgramResult {
         let DynamicAccount { fixed, dynamic } = self.borrow_mut_market_loans();
-        let mut free_list: FreeList<MarketLoansUnusedFreeListPadding> =
-            FreeList::new(dynamic, fixed.free_list_head_index);
-
-        free_list.add(fixed.num_bytes_allocated);
-        fixed.num_bytes_allocated += n * MARKET_LOAN_BLOCK_SIZE as u32;
-        fixed.free_list_head_index = free_list.get_head();
+        expand_blocks(fixed, dynamic, n);
         Ok(())
     }
 
     pub fn add_loan(&mut self, loan_record: ActiveLoan) -> ProgramResult {
         let DynamicAccount { fixed, dynamic } = self.borrow_mut_market_loans();
-        let free_address: DataIndex = get_free_address_on_market_loans_fixed(fixed, dynamic);
-
-        let mut loan_tree: ActiveLoanTree =
-            ActiveLoanTree::new(dynamic, fixed.active_loans_root_index, NIL);
-
-        loan_tree.insert(free_address, loan_record);
-        fixed.active_loans_root_index = loan_tree.get_root_index();
-
         require!(
             fixed.num_active_loans < MAX_ACTIVE_LOANS,
             NixError::MaxActiveLoansExceeded,
@@ -252,6 +362,11 @@ impl<Fixed: DerefOrBorrowMut<MarketLoansFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
             MAX_ACTIVE_LOANS
         )?;
 
+        let active_loans_root_index: DataIndex = fixed.active_loans_root_index;
+        let update: TreeNodeUpdate =
+            insert_node(fixed, dynamic, active_loans_root_index, NIL, loan_record);
+        fixed.active_loans_root_index = update.root_index;
+
         fixed.num_active_loans += 1;
 
         Ok(())
@@ -270,24 +385,135 @@ impl<Fixed: DerefOrBorrowMut<MarketLoansFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
         )?;
 
         for loan_record in loan_records {
-            let free_address: DataIndex = get_free_address_on_market_loans_fixed(fixed, dynamic);
-            let mut loan_tree: ActiveLoanTree =
-                ActiveLoanTree::new(dynamic, fixed.active_loans_root_index, NIL);
-            loan_tree.insert(free_address, *loan_record);
-            fixed.active_loans_root_index = loan_tree.get_root_index();
+            let active_loans_root_index: DataIndex = fixed.active_loans_root_index;
+            let update: TreeNodeUpdate =
+                insert_node(fixed, dynamic, active_loans_root_index, NIL, *loan_record);
+            fixed.active_loans_root_index = update.root_index;
             fixed.num_active_loans += 1;
         }
 
         Ok(())
     }
 
+    /// Hands the lender side of a loan from `seller_index` to `buyer_index`.
+    /// The borrower and the terms of the loan do not change.
+    pub fn assign_lender(
+        &mut self,
+        sequence_number: u64,
+        seller_index: DataIndex,
+        buyer_index: DataIndex,
+    ) -> ProgramResult {
+        let DynamicAccount { fixed, dynamic } = self.borrow_mut_market_loans();
+        let loan_index: DataIndex =
+            ActiveLoanTreeReadOnly::new(dynamic, fixed.active_loans_root_index, NIL).lookup_index(
+                &ActiveLoan {
+                    sequence_number,
+                    ..Default::default()
+                },
+            );
+        require!(
+            loan_index != NIL,
+            NixError::InvalidLoanSale,
+            "Loan with sequence_number {} not found",
+            sequence_number
+        )?;
+        let loan: &mut ActiveLoan =
+            get_mut_helper::<RBNode<ActiveLoan>>(dynamic, loan_index).get_mut_value();
+        require!(
+            loan.status == LoanStatus::Active
+                && loan.lender_index == seller_index
+                && loan.is_lender_global.0 == 0,
+            NixError::InvalidLoanSale,
+            "Loan {} is no longer held by the seller",
+            sequence_number
+        )?;
+        loan.lender_index = buyer_index;
+        Ok(())
+    }
+
+    /// Adds collateral_shares to an active loan of `borrower_index`. Returns
+    /// the loan as updated.
+    pub fn top_up_collateral(
+        &mut self,
+        sequence_number: u64,
+        borrower_index: DataIndex,
+        collateral_shares: WrappedI80F48,
+    ) -> Result<ActiveLoan, ProgramError> {
+        let loan: &mut ActiveLoan =
+            self.get_mut_borrowed_loan(sequence_number, borrower_index, NixError::InvalidTopUp)?;
+        loan.collateral_shares = loan
+            .collateral_shares
+            .checked_add(collateral_shares)
+            .ok_or(NixError::NumericalOverflow)?;
+        Ok(*loan)
+    }
+
+    /// Takes collateral_shares off an active loan of `borrower_index`.
+    /// Returns the loan as updated, the caller checks it is still healthy.
+    pub fn release_collateral(
+        &mut self,
+        sequence_number: u64,
+        borrower_index: DataIndex,
+        collateral_shares: WrappedI80F48,
+    ) -> Result<ActiveLoan, ProgramError> {
+        let loan: &mut ActiveLoan = self.get_mut_borrowed_loan(
+            sequence_number,
+            borrower_index,
+            NixError::InvalidCollateralRelease,
+        )?;
+        require!(
+            collateral_shares <= loan.collateral_shares,
+            NixError::InvalidCollateralRelease,
+            "Loan {} has less collateral than released",
+            sequence_number
+        )?;
+        loan.collateral_shares = loan
+            .collateral_shares
+            .checked_sub(collateral_shares)
+            .ok_or(NixError::NumericalOverflow)?;
+        Ok(*loan)
+    }
+
+    /// Active loan `sequence_number` if `borrower_index` is its borrower,
+    /// `error` otherwise.
+    fn get_mut_borrowed_loan(
+        &mut self,
+        sequence_number: u64,
+        borrower_index: DataIndex,
+        error: NixError,
+    ) -> Result<&mut ActiveLoan, ProgramError> {
+        let DynamicAccount { fixed, dynamic } = self.borrow_mut_market_loans();
+        let loan_index: DataIndex =
+            ActiveLoanTreeReadOnly::new(dynamic, fixed.active_loans_root_index, NIL).lookup_index(
+                &ActiveLoan {
+                    sequence_number,
+                    ..Default::default()
+                },
+            );
+        require!(
+            loan_index != NIL,
+            error,
+            "Loan with sequence_number {} not found",
+            sequence_number
+        )?;
+        let loan: &mut ActiveLoan =
+            get_mut_helper::<RBNode<ActiveLoan>>(dynamic, loan_index).get_mut_value();
+        require!(
+            loan.status == LoanStatus::Active && loan.borrower_index == borrower_index,
+            error,
+            "Loan {} is not an active loan of the trader",
+            sequence_number
+        )?;
+        Ok(loan)
+    }
+
     /// Remove a loan from the active loans tree and free its slot.
     pub fn remove_loan(&mut self, sequence_number: u64) -> ProgramResult {
         let DynamicAccount { fixed, dynamic } = self.borrow_mut_market_loans();
 
         // Find the loan index by sequence_number
-        let mut loan_tree: ActiveLoanTree =
-            ActiveLoanTree::new(dynamic, fixed.active_loans_root_index, NIL);
+        let loan_tree: ActiveLoanTreeReadOnly =
+            ActiveLoanTreeReadOnly::new(dynamic, fixed.active_loans_root_index, NIL);
 
         // Create a dummy loan to search by sequence_number
         let search_loan = ActiveLoan {
@@ -303,15 +529,11 @@ impl<Fixed: DerefOrBorrowMut<MarketLoansFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
             sequence_number
         )?;
 
-        // Remove from tree
-        loan_tree.remove_by_index(loan_index);
-        fixed.active_loans_root_index = loan_tree.get_root_index();
-
-        // Free the slot
-        let mut free_list: FreeList<MarketLoansUnusedFreeListPadding> =
-            FreeList::new(dynamic, fixed.free_list_head_index);
-        free_list.add(loan_index);
-        fixed.free_list_head_index = free_list.get_head();
+        // Remove from tree and free the slot
+        let active_loans_root_index: DataIndex = fixed.active_loans_root_index;
+        let update: TreeNodeUpdate =
+            remove_node::<_, ActiveLoan>(fixed, dynamic, active_loans_root_index, NIL, loan_index);
+        fixed.active_loans_root_index = update.root_index;
 
         // Decrement active loans count
         fixed.num_active_loans = fixed.num_active_loans.wrapping_sub(1);
@@ -320,13 +542,81 @@ impl<Fixed: DerefOrBorrowMut<MarketLoansFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
     }
 }
 
-fn get_free_address_on_market_loans_fixed(
-    fixed: &mut MarketLoansFixed,
-    dynamic: &mut [u8],
-) -> DataIndex {
-    let mut free_list: FreeList<MarketLoansUnusedFreeListPadding> =
-        FreeList::new(dynamic, fixed.free_list_head_index);
-    let free_address: DataIndex = free_list.remove();
-    fixed.free_list_head_index = free_list.get_head();
-    free_address
+// Taking a loan off the page reads it first, which needs both bounds.
+#[cfg(feature = "program")]
+impl<
+        Fixed: DerefOrBorrowMut<MarketLoansFixed> + DerefOrBorrow<MarketLoansFixed>,
+        Dynamic: DerefOrBorrowMut<[u8]> + DerefOrBorrow<[u8]>,
+    > DynamicAccount<Fixed, Dynamic>
+{
+    /// Takes repaid loan `sequence_number` off the page. Returns it so the
+    /// caller can pay out what it owes each side.
+    pub fn take_repaid_loan(&mut self, sequence_number: u64) -> Result<ActiveLoan, ProgramError> {
+        let loan_opt: Option<ActiveLoan> = self.get_loan(sequence_number);
+        require!(
+            loan_opt.is_some_and(|loan| loan.status == LoanStatus::Repaid),
+            NixError::LoanNotRepaid,
+            "Loan {} is not a repaid loan",
+            sequence_number
+        )?;
+        self.remove_loan(sequence_number)?;
+        Ok(loan_opt.unwrap())
+    }
+
+    /// Takes active loan `sequence_number` off the page as Defaulted. The
+    /// caller has checked that its collateral no longer covers it, and
+    /// writes it off against the claim of the lender.
+    pub fn take_defaulted_loan(
+        &mut self,
+        sequence_number: u64,
+    ) -> Result<ActiveLoan, ProgramError> {
+        let loan_opt: Option<ActiveLoan> = self.get_loan(sequence_number);
+        require!(
+            loan_opt.is_some_and(|loan| loan.status == LoanStatus::Active),
+            NixError::InvalidActiveLoan,
+            "Loan {} is not an active loan",
+            sequence_number
+        )?;
+        self.remove_loan(sequence_number)?;
+        let mut loan: ActiveLoan = loan_opt.unwrap();
+        loan.status = LoanStatus::Defaulted;
+        Ok(loan)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expand_loan_account_frees_every_block() {
+        let mut market_loans: MarketLoansValue = MarketLoansValue {
+            fixed: MarketLoansFixed::new_empty(Pubkey::new_unique()),
+            dynamic: vec![0; 3 * MARKET_LOAN_BLOCK_SIZE],
+        };
+        market_loans.expand_loan_account(3).unwrap();
+        assert_eq!(
+            market_loans.fixed.num_bytes_allocated,
+            3 * MARKET_LOAN_BLOCK_SIZE as u32
+        );
+
+        // Every new block has to be on the free list, not only the first.
+        for sequence_number in 0..3 {
+            let mut loan: ActiveLoan = ActiveLoan::new_empty(
+                true,
+                0,
+                0,
+                false,
+                WrappedI80F48::ZERO,
+                WrappedI80F48::ZERO,
+                500,
+                0,
+                0,
+            );
+            loan.set_sequence_number(sequence_number);
+            market_loans.add_loan(loan).unwrap();
+        }
+        assert_eq!(market_loans.fixed.num_active_loans, 3);
+        assert!(!market_loans.fixed.has_free_block());
+    }
 }
@@ -0,0 +1,156 @@
+use bytemuck::{Pod, Zeroable};
+use hypertree::Get;
+use shank::ShankType;
+use solana_program::{entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey};
+use static_assertions::const_assert_eq;
+use std::mem::size_of;
+
+use crate::{
+    program::NixError,
+    require,
+    state::{
+        DerefOrBorrow, DerefOrBorrowMut, DynamicAccount, MARKET_REGISTRY_FIXED_SIZE,
+        MAX_REGISTERED_MARKETS,
+    },
+    utils::{is_discriminant_of, StableName},
+    validation::NixAccount,
+};
+
+/// Markets of one mint pair, so routers can find them without scanning
+/// program accounts. There is one registry per unordered pair, the mints are
+/// stored lowest first. The dynamic part is the array of market keys, grown
+/// by one key per market.
+#[repr(C)]
+#[derive(Default, Copy, Clone, Zeroable, Pod, ShankType)]
+pub struct MarketRegistryFixed {
+    /// Discriminant for identifying this account type.
+    pub discriminant: u64,
+    pub mint_low: Pubkey,
+    pub mint_high: Pubkey,
+    num_markets: u32,
+    _padding: [u8; 4],
+}
+
+const_assert_eq!(
+    size_of::<MarketRegistryFixed>(),
+    8 +   // discriminant
+    32 +  // mint_low
+    32 +  // mint_high
+    4 +   // num_markets
+    4 // _padding
+);
+const_assert_eq!(size_of::<MarketRegistryFixed>(), MARKET_REGISTRY_FIXED_SIZE);
+const_assert_eq!(size_of::<MarketRegistryFixed>() % 8, 0);
+
+impl MarketRegistryFixed {
+    pub fn new_empty(mint: &Pubkey, other_mint: &Pubkey) -> Self {
+        let (mint_low, mint_high) = sort_mints(mint, other_mint);
+        MarketRegistryFixed {
+            discriminant: crate::utils::get_discriminant::<MarketRegistryFixed>().unwrap(),
+            mint_low,
+            mint_high,
+            num_markets: 0,
+            _padding: [0; 4],
+        }
+    }
+
+    pub fn get_num_markets(&self) -> u32 {
+        self.num_markets
+    }
+}
+
+/// The pair in the order the registry stores and derives its address from.
+pub fn sort_mints(mint: &Pubkey, other_mint: &Pubkey) -> (Pubkey, Pubkey) {
+    if mint <= other_mint {
+        (*mint, *other_mint)
+    } else {
+        (*other_mint, *mint)
+    }
+}
+
+impl Get for MarketRegistryFixed {}
+impl StableName for MarketRegistryFixed {
+    const STABLE_NAME: &'static str = "nix::MarketRegistryFixed";
+    const LEGACY_NAME: &'static str = "nix::state::market_registry::MarketRegistryFixed";
+}
+impl NixAccount for MarketRegistryFixed {
+    fn verify_discriminant(&self) -> ProgramResult {
+        let expected_discriminant: u64 =
+            crate::utils::get_discriminant::<MarketRegistryFixed>().unwrap();
+
+        require!(
+            is_discriminant_of::<MarketRegistryFixed>(self.discriminant),
+            ProgramError::InvalidAccountData,
+            "Invalid market registry discriminant actual: {} expected: {}",
+            self.discriminant,
+            expected_discriminant
+        )?;
+        Ok(())
+    }
+}
+
+/// Fully owned MarketRegistry, used in clients that can copy.
+pub type MarketRegistryValue = DynamicAccount<MarketRegistryFixed, Vec<u8>>;
+/// Full MarketRegistry reference type.
+pub type MarketRegistryRef<'a> = DynamicAccount<&'a MarketRegistryFixed, &'a [u8]>;
+/// Full MarketRegistry reference type.
+pub type MarketRegistryRefMut<'a> = DynamicAccount<&'a mut MarketRegistryFixed, &'a mut [u8]>;
+
+impl<Fixed: DerefOrBorrow<MarketRegistryFixed>, Dynamic: DerefOrBorrow<[u8]>>
+    DynamicAccount<Fixed, Dynamic>
+{
+    /// Keys of the registered markets, oldest first.
+    pub fn list_markets(&self) -> &[Pubkey] {
+        let num_markets: usize = self.fixed.deref_or_borrow().num_markets as usize;
+        bytemuck::cast_slice::<u8, Pubkey>(
+            &self.dynamic.deref_or_borrow()[..num_markets * size_of::<Pubkey>()],
+        )
+    }
+}
+
+impl<Fixed: DerefOrBorrowMut<MarketRegistryFixed>, Dynamic: DerefOrBorrowMut<[u8]>>
+    DynamicAccount<Fixed, Dynamic>
+{
+    /// Appends a market to the registry. The account has to have been grown
+    /// by a key first.
+    pub fn register_market(&mut self, market: &Pubkey) -> ProgramResult {
+        let fixed: &mut MarketRegistryFixed = self.fixed.deref_or_borrow_mut();
+        let dynamic: &mut [u8] = self.dynamic.deref_or_borrow_mut();
+        let num_markets: usize = fixed.num_markets as usize;
+        require!(
+            num_markets < MAX_REGISTERED_MARKETS,
+            NixError::MarketRegistryFull,
+            "Registry already holds {} markets for the pair",
+            num_markets,
+        )?;
+        let offset: usize = num_markets * size_of::<Pubkey>();
+        require!(
+            dynamic.len() >= offset + size_of::<Pubkey>(),
+            ProgramError::AccountDataTooSmall,
+            "Registry has no room for market {}",
+            market,
+        )?;
+        dynamic[offset..offset + size_of::<Pubkey>()].copy_from_slice(market.as_ref());
+        fixed.num_markets += 1;
+        Ok(())
+    }
+
+    /// Takes a closed market out of the registry, keeping the others oldest
+    /// first. Returns whether it was listed. The space it took stays with the
+    /// account and is reused by the next market registered.
+    pub fn unregister_market(&mut self, market: &Pubkey) -> Result<bool, ProgramError> {
+        let fixed: &mut MarketRegistryFixed = self.fixed.deref_or_borrow_mut();
+        let dynamic: &mut [u8] = self.dynamic.deref_or_borrow_mut();
+        let num_markets: usize = fixed.num_markets as usize;
+        let markets: &mut [Pubkey] = bytemuck::cast_slice_mut::<u8, Pubkey>(
+            &mut dynamic[..num_markets * size_of::<Pubkey>()],
+        );
+        let Some(position) = markets.iter().position(|listed| listed == market) else {
+            return Ok(false);
+        };
+        markets.copy_within(position + 1.., position);
+        markets[num_markets - 1] = Pubkey::default();
+        fixed.num_markets -= 1;
+        Ok(true)
+    }
+}
@@ -0,0 +1,128 @@
+use bytemuck::{Pod, Zeroable};
+use hypertree::Get;
+use shank::ShankType;
+use solana_program::{entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey};
+use static_assertions::const_assert_eq;
+use std::mem::size_of;
+
+use crate::{
+    require,
+    state::{MARKET_STATS_BUCKET_SLOTS, MARKET_STATS_NUM_BUCKETS, MARKET_STATS_SIZE},
+    utils::{is_discriminant_of, StableName},
+    validation::NixAccount,
+};
+
+/// Rolling matched volume and open interest of a market, so UIs can show
+/// the last day without an indexer. Volume is kept in buckets of
+/// MARKET_STATS_BUCKET_SLOTS that are reused once they fall out of the
+/// window. Every match opens a loan, so open interest grows with volume.
+/// Nothing repays loans yet, so it only grows.
+#[repr(C)]
+#[derive(Default, Copy, Clone, Zeroable, Pod, ShankType)]
+pub struct MarketStats {
+    /// Discriminant for identifying this account type.
+    pub discriminant: u64,
+    pub market: Pubkey,
+
+    /// Base atoms lent out through matches on each tree.
+    base_a_open_interest_atoms: u64,
+    base_b_open_interest_atoms: u64,
+
+    /// Slot divided by MARKET_STATS_BUCKET_SLOTS that each bucket holds.
+    bucket_epochs: [u32; 24],
+    /// Base atoms matched on each tree during the bucket.
+    base_a_volume_atoms: [u64; 24],
+    base_b_volume_atoms: [u64; 24],
+}
+
+const_assert_eq!(
+    size_of::<MarketStats>(),
+    8 +   // discriminant
+    32 +  // market
+    8 +   // base_a_open_interest_atoms
+    8 +   // base_b_open_interest_atoms
+    96 +  // bucket_epochs
+    192 + // base_a_volume_atoms
+    192 // base_b_volume_atoms
+);
+const_assert_eq!(size_of::<MarketStats>(), MARKET_STATS_SIZE);
+const_assert_eq!(MARKET_STATS_NUM_BUCKETS, 24);
+const_assert_eq!(size_of::<MarketStats>() % 8, 0);
+
+impl MarketStats {
+    pub fn new(market: Pubkey) -> Self {
+        MarketStats {
+            discriminant: crate::utils::get_discriminant::<MarketStats>().unwrap(),
+            market,
+            ..Default::default()
+        }
+    }
+
+    /// Base atoms matched on the tree over the window ending at now_slot.
+    pub fn get_rolling_volume_atoms(&self, use_a_tree: bool, now_slot: u32) -> u64 {
+        let now_epoch: u32 = now_slot / MARKET_STATS_BUCKET_SLOTS;
+        let volume_atoms: &[u64; MARKET_STATS_NUM_BUCKETS] = if use_a_tree {
+            &self.base_a_volume_atoms
+        } else {
+            &self.base_b_volume_atoms
+        };
+        self.bucket_epochs
+            .iter()
+            .zip(volume_atoms.iter())
+            .filter(|(bucket_epoch, _)| {
+                now_epoch.saturating_sub(**bucket_epoch) < MARKET_STATS_NUM_BUCKETS as u32
+            })
+            .fold(0_u64, |total, (_, atoms)| total.saturating_add(*atoms))
+    }
+
+    pub fn get_open_interest_atoms(&self, use_a_tree: bool) -> u64 {
+        if use_a_tree {
+            self.base_a_open_interest_atoms
+        } else {
+            self.base_b_open_interest_atoms
+        }
+    }
+
+    /// Records base_atoms matched on the tree at now_slot. The bucket of
+    /// now_slot is cleared first if it still holds an older epoch.
+    pub(crate) fn record_match(&mut self, use_a_tree: bool, base_atoms: u64, now_slot: u32) {
+        let now_epoch: u32 = now_slot / MARKET_STATS_BUCKET_SLOTS;
+        let bucket: usize = now_epoch as usize % MARKET_STATS_NUM_BUCKETS;
+        if self.bucket_epochs[bucket] != now_epoch {
+            self.bucket_epochs[bucket] = now_epoch;
+            self.base_a_volume_atoms[bucket] = 0;
+            self.base_b_volume_atoms[bucket] = 0;
+        }
+        if use_a_tree {
+            self.base_a_volume_atoms[bucket] =
+                self.base_a_volume_atoms[bucket].saturating_add(base_atoms);
+            self.base_a_open_interest_atoms =
+                self.base_a_open_interest_atoms.saturating_add(base_atoms);
+        } else {
+            self.base_b_volume_atoms[bucket] =
+                self.base_b_volume_atoms[bucket].saturating_add(base_atoms);
+            self.base_b_open_interest_atoms =
+                self.base_b_open_interest_atoms.saturating_add(base_atoms);
+        }
+    }
+}
+
+impl Get for MarketStats {}
+impl StableName for MarketStats {
+    const STABLE_NAME: &'static str = "nix::MarketStats";
+    const LEGACY_NAME: &'static str = "nix::state::market_stats::MarketStats";
+}
+impl NixAccount for MarketStats {
+    fn verify_discriminant(&self) -> ProgramResult {
+        let expected_discriminant: u64 = crate::utils::get_discriminant::<MarketStats>().unwrap();
+
+        require!(
+            is_discriminant_of::<MarketStats>(self.discriminant),
+            ProgramError::InvalidAccountData,
+            "Invalid market stats discriminant actual: {} expected: {}",
+            self.discriminant,
+            expected_discriminant
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,395 @@
+//! Replays sequences of seat and order operations on the trees of an in
+//! memory market and runs the state::verify checks after every one. Ops go
+//! through the same helpers as the processors, so a regression in the root,
+//! best index or free list bookkeeping shows up at the op that caused it.
+//! Sequences are either recorded by hand or generated from a seed, so a
+//! failing seed replays the same way every time.
+
+use super::*;
+use crate::state::verify::{verify_blocks, verify_booksides_sorted, verify_orders_have_seats};
+
+const NUM_TRADERS: usize = 6;
+const MAX_ORDERS: usize = 48;
+
+/// One step of a replay. Traders and orders are positions in the pools of
+/// the replay, not data indexes, so a sequence stays valid whichever blocks
+/// the free list hands out. Ops that do not apply to the current state, like
+/// an order for a trader without a seat, are skipped.
+#[derive(Clone, Copy, Debug)]
+enum TreeOp {
+    ClaimSeat {
+        trader: usize,
+    },
+    /// Takes the seat out of the tree and frees its block. Nothing in the
+    /// program does this yet, it is here to mix freed seat blocks into the
+    /// free list.
+    ReleaseSeat {
+        trader: usize,
+    },
+    LookupSeat {
+        trader: usize,
+    },
+    InsertOrder {
+        trader: usize,
+        use_a_tree: bool,
+        is_bid: bool,
+        rate_bps: u16,
+        sequence_number: u64,
+    },
+    RemoveOrder {
+        position: usize,
+    },
+    LookupOrder {
+        position: usize,
+    },
+}
+
+#[derive(Clone, Copy)]
+struct ReplayOrder {
+    index: DataIndex,
+    trader: usize,
+    use_a_tree: bool,
+    is_bid: bool,
+    sequence_number: u64,
+}
+
+/// xorshift64, so a seed gives the same sequence on every platform.
+struct ReplayRng(u64);
+
+impl ReplayRng {
+    fn next(&mut self) -> u64 {
+        let mut x: u64 = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Inserts outnumber removes so the trees get a few levels deep. Rates stay
+/// within a few bps to get many orders at the same rate, which are then
+/// ordered by their sequence numbers. Those are random but unique, the low
+/// bits are the step.
+fn generate_ops(seed: u64, num_ops: usize) -> Vec<TreeOp> {
+    let mut rng: ReplayRng = ReplayRng(seed);
+    (0..num_ops)
+        .map(|step| match rng.below(10) {
+            0 => TreeOp::ClaimSeat {
+                trader: rng.below(NUM_TRADERS),
+            },
+            1 => TreeOp::ReleaseSeat {
+                trader: rng.below(NUM_TRADERS),
+            },
+            2 => TreeOp::LookupSeat {
+                trader: rng.below(NUM_TRADERS),
+            },
+            3..=6 => TreeOp::InsertOrder {
+                trader: rng.below(NUM_TRADERS),
+                use_a_tree: rng.below(2) == 0,
+                is_bid: rng.below(2) == 0,
+                rate_bps: 95 + rng.below(10) as u16,
+                sequence_number: ((rng.next() >> 40) << 16) | step as u64,
+            },
+            7 => TreeOp::LookupOrder {
+                position: rng.below(MAX_ORDERS),
+            },
+            _ => TreeOp::RemoveOrder {
+                position: rng.below(MAX_ORDERS),
+            },
+        })
+        .collect()
+}
+
+struct TreeReplay {
+    market: MarketValue,
+    traders: Vec<Pubkey>,
+    /// Seat of every trader, NIL when it has none.
+    seat_indexes: Vec<DataIndex>,
+    orders: Vec<ReplayOrder>,
+}
+
+impl TreeReplay {
+    fn new() -> Self {
+        TreeReplay {
+            market: MarketValue {
+                fixed: MarketFixed {
+                    claimed_seats_root_index: NIL,
+                    free_list_head_index: NIL,
+                    base_a_bids_root_index: NIL,
+                    base_a_bids_best_index: NIL,
+                    base_a_asks_root_index: NIL,
+                    base_a_asks_best_index: NIL,
+                    base_b_bids_root_index: NIL,
+                    base_b_bids_best_index: NIL,
+                    base_b_asks_root_index: NIL,
+                    base_b_asks_best_index: NIL,
+                    ..Default::default()
+                },
+                dynamic: vec![0; MARKET_BLOCK_SIZE * (NUM_TRADERS + MAX_ORDERS)],
+            },
+            // Fixed keys rather than Pubkey::new_unique, which depends on the
+            // order tests run in and would change the shape of the seats tree.
+            traders: (0..NUM_TRADERS)
+                .map(|trader| Pubkey::new_from_array([trader as u8 + 1; 32]))
+                .collect(),
+            seat_indexes: vec![NIL; NUM_TRADERS],
+            orders: Vec::new(),
+        }
+    }
+
+    fn replay(&mut self, ops: &[TreeOp]) {
+        for (step, op) in ops.iter().enumerate() {
+            self.apply(*op);
+            self.verify(step, op);
+        }
+    }
+
+    fn expand_if_needed(&mut self) {
+        if self.market.fixed.free_list_head_index == NIL {
+            self.market.market_expand().unwrap();
+        }
+    }
+
+    fn apply(&mut self, op: TreeOp) {
+        match op {
+            TreeOp::ClaimSeat { trader } => {
+                if self.seat_indexes[trader] != NIL {
+                    assert!(self.market.claim_seat(&self.traders[trader]).is_err());
+                    return;
+                }
+                self.expand_if_needed();
+                self.market.claim_seat(&self.traders[trader]).unwrap();
+                self.seat_indexes[trader] = self.market.get_trader_index(&self.traders[trader]);
+            }
+            TreeOp::ReleaseSeat { trader } => {
+                let seat_index: DataIndex = self.seat_indexes[trader];
+                if seat_index == NIL || self.orders.iter().any(|order| order.trader == trader) {
+                    return;
+                }
+                let DynamicAccount { fixed, dynamic } = self.market.borrow_mut();
+                let mut tree: ClaimedSeatTree =
+                    ClaimedSeatTree::new(dynamic, fixed.claimed_seats_root_index, NIL);
+                tree.remove_by_index(seat_index);
+                fixed.claimed_seats_root_index = tree.get_root_index();
+                release_address_on_market_fixed_for_seat(fixed, dynamic, seat_index);
+                self.seat_indexes[trader] = NIL;
+            }
+            TreeOp::LookupSeat { trader } => {
+                assert_eq!(
+                    self.market.get_trader_index(&self.traders[trader]),
+                    self.seat_indexes[trader]
+                );
+            }
+            TreeOp::InsertOrder {
+                trader,
+                use_a_tree,
+                is_bid,
+                rate_bps,
+                sequence_number,
+            } => {
+                let trader_index: DataIndex = self.seat_indexes[trader];
+                if trader_index == NIL || self.orders.len() == MAX_ORDERS {
+                    return;
+                }
+                self.expand_if_needed();
+                let DynamicAccount { fixed, dynamic } = self.market.borrow_mut();
+                increment_open_orders(fixed, dynamic, trader_index).unwrap();
+                let free_address: DataIndex = if is_bid {
+                    get_free_address_on_market_fixed_for_bid_order(fixed, dynamic)
+                } else {
+                    get_free_address_on_market_fixed_for_ask_order(fixed, dynamic)
+                };
+                let resting_order: RestingOrder = RestingOrder::new(
+                    rate_bps,
+                    sequence_number,
+                    WrappedI80F48::default(),
+                    WrappedI80F48::default(),
+                    use_a_tree,
+                    trader_index,
+                    NO_EXPIRATION_LAST_VALID_SLOT,
+                    OrderType::Limit,
+                    is_bid,
+                    0,
+                    ExpiryPolicy::ReturnCollateral,
+                )
+                .unwrap();
+                insert_order_into_tree(
+                    use_a_tree,
+                    is_bid,
+                    fixed,
+                    dynamic,
+                    free_address,
+                    &resting_order,
+                );
+                set_payload_order(dynamic, free_address);
+                self.orders.push(ReplayOrder {
+                    index: free_address,
+                    trader,
+                    use_a_tree,
+                    is_bid,
+                    sequence_number,
+                });
+            }
+            TreeOp::RemoveOrder { position } => {
+                if self.orders.is_empty() {
+                    return;
+                }
+                let order: ReplayOrder = self.orders.swap_remove(position % self.orders.len());
+                let DynamicAccount { fixed, dynamic } = self.market.borrow_mut();
+                remove_order_from_tree_and_free(
+                    fixed,
+                    dynamic,
+                    order.use_a_tree,
+                    order.index,
+                    order.is_bid,
+                )
+                .unwrap();
+            }
+            TreeOp::LookupOrder { position } => {
+                if self.orders.is_empty() {
+                    return;
+                }
+                let order: ReplayOrder = self.orders[position % self.orders.len()];
+                let DynamicAccount { fixed, dynamic } = self.market.borrow_market();
+                assert_eq!(
+                    find_order_index(
+                        fixed,
+                        dynamic,
+                        order.use_a_tree,
+                        self.seat_indexes[order.trader],
+                        order.sequence_number,
+                    )
+                    .unwrap(),
+                    order.index
+                );
+            }
+        }
+    }
+
+    /// The verify checks cover tree shape, ordering, best indexes, seats of
+    /// orders and the free list. On top of those the trees have to hold
+    /// exactly the seats and orders the replay put there.
+    fn verify(&self, step: usize, op: &TreeOp) {
+        let market: MarketRef = self.market.borrow_market();
+        verify_booksides_sorted(&market);
+        verify_orders_have_seats(&market);
+        verify_blocks(&market);
+
+        for (trader, seat_index) in self.seat_indexes.iter().enumerate() {
+            assert_eq!(
+                self.market.get_trader_index(&self.traders[trader]),
+                *seat_index,
+                "Seat of trader {} is off after step {} {:?}",
+                trader,
+                step,
+                op
+            );
+            if *seat_index != NIL {
+                let num_orders: usize = self
+                    .orders
+                    .iter()
+                    .filter(|order| order.trader == trader)
+                    .count();
+                assert_eq!(
+                    get_helper_seat(market.dynamic, *seat_index)
+                        .get_value()
+                        .num_open_orders as usize,
+                    num_orders,
+                    "Open orders of trader {} are off after step {} {:?}",
+                    trader,
+                    step,
+                    op
+                );
+            }
+        }
+
+        for use_a_tree in [true, false] {
+            let (_, _, bids_root_index, asks_root_index) =
+                get_tree_indexes(market.fixed, use_a_tree);
+            for (is_bid, root_index) in [(true, bids_root_index), (false, asks_root_index)] {
+                let bookside: BooksideReadOnly =
+                    BooksideReadOnly::new(market.dynamic, root_index, NIL);
+                let mut tree_indexes: Vec<DataIndex> = bookside
+                    .iter::<RestingOrder>()
+                    .map(|(index, _)| index)
+                    .collect();
+                let mut replay_indexes: Vec<DataIndex> = self
+                    .orders
+                    .iter()
+                    .filter(|order| order.use_a_tree == use_a_tree && order.is_bid == is_bid)
+                    .map(|order| order.index)
+                    .collect();
+                tree_indexes.sort_unstable();
+                replay_indexes.sort_unstable();
+                assert_eq!(
+                    tree_indexes, replay_indexes,
+                    "Book side use_a_tree={} is_bid={} is off after step {} {:?}",
+                    use_a_tree, is_bid, step, op
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_replay_recorded_ops() {
+    use TreeOp::*;
+
+    let insert = |trader: usize, is_bid: bool, rate_bps: u16, sequence_number: u64| InsertOrder {
+        trader,
+        use_a_tree: true,
+        is_bid,
+        rate_bps,
+        sequence_number,
+    };
+    // Ties at 100 bps with the best bid removed and replaced at the same
+    // rate, a seat released once its orders are gone and its block reused by
+    // an order, then the last orders on a side removed so its root and best
+    // go back to NIL.
+    let ops: Vec<TreeOp> = vec![
+        ClaimSeat { trader: 0 },
+        ClaimSeat { trader: 1 },
+        ClaimSeat { trader: 0 },
+        insert(0, true, 100, 1),
+        insert(1, true, 100, 2),
+        insert(0, true, 101, 3),
+        insert(1, false, 99, 4),
+        LookupOrder { position: 2 },
+        RemoveOrder { position: 2 },
+        insert(0, true, 101, 5),
+        insert(1, true, 100, 6),
+        ReleaseSeat { trader: 1 },
+        RemoveOrder { position: 1 },
+        RemoveOrder { position: 1 },
+        RemoveOrder { position: 2 },
+        ReleaseSeat { trader: 1 },
+        LookupSeat { trader: 1 },
+        insert(0, false, 98, 7),
+        ClaimSeat { trader: 1 },
+        insert(1, true, 101, 8),
+        RemoveOrder { position: 0 },
+        RemoveOrder { position: 0 },
+        RemoveOrder { position: 0 },
+        RemoveOrder { position: 0 },
+        LookupSeat { trader: 0 },
+    ];
+    let mut replay: TreeReplay = TreeReplay::new();
+    replay.replay(&ops);
+    assert!(replay.orders.is_empty());
+    assert_eq!(replay.market.fixed.base_a_bids_root_index, NIL);
+    assert_eq!(replay.market.fixed.base_a_bids_best_index, NIL);
+    assert_eq!(replay.market.fixed.base_a_asks_best_index, NIL);
+}
+
+#[test]
+fn test_replay_generated_ops() {
+    for seed in [1, 7, 42, 0x5eed, 0xdead_beef, 0x1234_5678_9abc_def0] {
+        let mut replay: TreeReplay = TreeReplay::new();
+        replay.replay(&generate_ops(seed, 2_000));
+    }
+}
@@ -5,6 +5,17 @@ pub mod claimed_seat;
 pub mod resting_order;
 pub mod global;
 pub mod market_loan;
+pub mod event_queue;
+pub mod cross_margin_seat;
+pub mod market_stats;
+pub mod market_registry;
+pub mod pending_order;
+pub mod market_auction;
+pub mod market_insurance;
+pub mod account_sizes;
+pub mod zc;
+#[cfg(any(feature = "test", feature = "fuzz"))]
+pub mod verify;
 
 pub use market::*;
 pub use constants::*;
@@ -13,3 +24,11 @@ pub use claimed_seat::*;
 pub use resting_order::*;
 pub use market_loan::*;
 pub use global::*;
+pub use event_queue::*;
+pub use cross_margin_seat::*;
+pub use market_stats::*;
+pub use market_registry::*;
+pub use pending_order::*;
+pub use market_auction::*;
+pub use market_insurance::*;
+pub use account_sizes::*;
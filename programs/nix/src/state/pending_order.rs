@@ -0,0 +1,113 @@
+use bytemuck::{Pod, Zeroable};
+use hypertree::{Get, PodBool};
+use shank::ShankType;
+use solana_program::{entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey};
+use static_assertions::const_assert_eq;
+use std::mem::size_of;
+
+use crate::{
+    require,
+    state::{ExpiryPolicy, OrderType, NO_EXPIRATION_LAST_VALID_SLOT, PENDING_ORDER_SIZE},
+    utils::{is_discriminant_of, StableName},
+    validation::NixAccount,
+};
+
+/// Remainder of an order that stopped matching at its match limit, kept
+/// between the PlaceOrder that deferred it and the ContinueOrder calls that
+/// finish it. There is one per trader and market. Nothing is borrowed or
+/// rested for the remainder while it waits here, so collateral is only taken
+/// for what each instruction matches or rests.
+#[repr(C)]
+#[derive(Default, Copy, Clone, Zeroable, Pod, ShankType)]
+pub struct PendingOrder {
+    /// Discriminant for identifying this account type.
+    pub discriminant: u64,
+    pub market: Pubkey,
+    pub trader: Pubkey,
+    /// Default when the order has no referrer.
+    pub referrer: Pubkey,
+
+    /// Base atoms still to match or rest.
+    pub num_base_atoms: u64,
+    /// Taken when the order was placed, the remainder rests with it.
+    pub order_sequence_number: u64,
+    pub last_valid_slot: u32,
+    pub match_limit: u32,
+    pub rate_bps: u16,
+    pub is_bid: PodBool,
+    pub use_a_tree: PodBool,
+    pub order_type: OrderType,
+    pub expiry_policy: ExpiryPolicy,
+    _padding: [u8; 2],
+}
+
+const_assert_eq!(
+    size_of::<PendingOrder>(),
+    8 +   // discriminant
+    32 +  // market
+    32 +  // trader
+    32 +  // referrer
+    8 +   // num_base_atoms
+    8 +   // order_sequence_number
+    4 +   // last_valid_slot
+    4 +   // match_limit
+    2 +   // rate_bps
+    1 +   // is_bid
+    1 +   // use_a_tree
+    1 +   // order_type
+    1 +   // expiry_policy
+    2 // _padding
+);
+const_assert_eq!(size_of::<PendingOrder>(), PENDING_ORDER_SIZE);
+const_assert_eq!(size_of::<PendingOrder>() % 8, 0);
+
+impl PendingOrder {
+    pub fn new(market: Pubkey, trader: Pubkey) -> Self {
+        PendingOrder {
+            discriminant: crate::utils::get_discriminant::<PendingOrder>().unwrap(),
+            market,
+            trader,
+            ..Default::default()
+        }
+    }
+
+    pub fn get_referrer(&self) -> Option<Pubkey> {
+        if self.referrer == Pubkey::default() {
+            None
+        } else {
+            Some(self.referrer)
+        }
+    }
+
+    pub fn get_is_bid(&self) -> bool {
+        self.is_bid.0 == 1
+    }
+
+    pub fn get_use_a_tree(&self) -> bool {
+        self.use_a_tree.0 == 1
+    }
+
+    pub fn is_expired(&self, current_slot: u32) -> bool {
+        self.last_valid_slot != NO_EXPIRATION_LAST_VALID_SLOT && self.last_valid_slot < current_slot
+    }
+}
+
+impl Get for PendingOrder {}
+impl StableName for PendingOrder {
+    const STABLE_NAME: &'static str = "nix::PendingOrder";
+    const LEGACY_NAME: &'static str = "nix::state::pending_order::PendingOrder";
+}
+impl NixAccount for PendingOrder {
+    fn verify_discriminant(&self) -> ProgramResult {
+        let expected_discriminant: u64 = crate::utils::get_discriminant::<PendingOrder>().unwrap();
+
+        require!(
+            is_discriminant_of::<PendingOrder>(self.discriminant),
+            ProgramError::InvalidAccountData,
+            "Invalid pending order discriminant actual: {} expected: {}",
+            self.discriminant,
+            expected_discriminant
+        )?;
+        Ok(())
+    }
+}
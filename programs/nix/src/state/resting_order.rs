@@ -6,28 +6,57 @@ use borsh::{BorshDeserialize, BorshSerialize};
 use bytemuck::{Pod, Zeroable};
 use fixed::types::I80F48;
 use hypertree::{DataIndex, PodBool};
-use marginfi::state::marginfi_group::Bank;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use shank::ShankType;
-use solana_program::{entrypoint::ProgramResult, program_error::ProgramError};
+use solana_program::{entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey};
 use static_assertions::const_assert_eq;
 
 use crate::{
-    marginfi_utils::{
+    math::{
         convert_asset_shares_to_tokens, convert_tokens_to_asset_shares,
         convert_tokens_to_liability_shares, get_token_amount_to_repay_liability_shares,
     },
-    quantities::WrappedI80F48,
+    program::NixError,
+    quantities::{BankShareValues, WrappedI80F48},
 };
 
-use super::{constants::NO_EXPIRATION_LAST_VALID_SLOT, RESTING_ORDER_SIZE};
+use super::{
+    constants::{NO_EXPIRATION_LAST_VALID_SLOT, NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP},
+    market_loan::ActiveLoan,
+    RESTING_ORDER_SIZE,
+};
 
 pub fn order_type_can_rest(order_type: OrderType) -> bool {
     order_type != OrderType::ImmediateOrCancel
 }
 
 pub fn order_type_can_take(order_type: OrderType) -> bool {
-    order_type != OrderType::PostOnly && order_type != OrderType::Global
+    order_type != OrderType::PostOnly
+        && order_type != OrderType::Global
+        && order_type != OrderType::LoanSale
+}
+
+/// Marginfi sides, base then quote, that an order has to pass. Asks that
+/// cannot take never go through marginfi. Post only asks still read the base
+/// bank to size what rests, global asks rest in token form.
+pub fn get_required_marginfi_sides(order_type: OrderType, is_bid: bool) -> [bool; 2] {
+    if is_bid || order_type_can_take(order_type) {
+        [true, true]
+    } else if order_type == OrderType::Global {
+        [false, false]
+    } else {
+        [true, false]
+    }
+}
+
+/// Cycles left after one more flip of a reverse order with `cycles_left`,
+/// or None when that flip is the last and the order rests as a limit order.
+pub fn get_next_reverse_cycles(cycles_left: u16) -> Option<u16> {
+    match cycles_left {
+        0 => Some(0),
+        1 => None,
+        cycles_left => Some(cycles_left - 1),
+    }
 }
 #[derive(
     Debug,
@@ -60,6 +89,10 @@ pub enum OrderType {
 
     // P2P2Pool orders are like reverse orders but they are only placed when a p2p match is made.
     P2P2Pool = 5,
+
+    // Lender side of an existing loan, offered as a bid. Only placed by
+    // PlaceLoanSale and bought whole by a taker ask, which becomes the lender.
+    LoanSale = 6,
 }
 unsafe impl bytemuck::Zeroable for OrderType {}
 unsafe impl bytemuck::Pod for OrderType {}
@@ -68,6 +101,70 @@ impl Default for OrderType {
         OrderType::Limit
     }
 }
+
+/// What happens to a resting bid once it expires. Asks always get their
+/// collateral back.
+#[derive(
+    Debug,
+    BorshDeserialize,
+    BorshSerialize,
+    PartialEq,
+    Clone,
+    Copy,
+    ShankType,
+    IntoPrimitive,
+    TryFromPrimitive,
+)]
+#[repr(u8)]
+pub enum ExpiryPolicy {
+    // The first taker or cleaner to reach it turns it into a loan on the
+    // underlying protocol at rate 0.
+    ConvertToPool = 0,
+
+    // The first taker or cleaner to reach it cancels it and returns the
+    // collateral to the seat. Only for bids that cannot rest, a resting bid
+    // has borrowed and always becomes a loan on the underlying protocol.
+    ReturnCollateral = 1,
+
+    // Takers skip it. It stays on the book until CleanExpiredOrders or a
+    // cancel removes it, and it becomes a loan on the underlying protocol
+    // then.
+    KeepUntilCleaned = 2,
+}
+unsafe impl bytemuck::Zeroable for ExpiryPolicy {}
+unsafe impl bytemuck::Pod for ExpiryPolicy {}
+impl Default for ExpiryPolicy {
+    fn default() -> Self {
+        ExpiryPolicy::ConvertToPool
+    }
+}
+/// Size of the fields of a RestingOrder before padding2, which fills the rest
+/// of the block payload.
+const RESTING_ORDER_FIELDS_SIZE: usize = 16 + // collateral_shares
+    16 + // liability_shares
+    2 +  // rate_bps
+    6 +  // padding
+    8 +  // sequence_number
+    4 +  // trader_index
+    4 +  // last_valid_slot
+    1 +  // order_type
+    1 +  // is_bid
+    1 +  // is_a_tree
+    1 +  // expiry_policy
+    4 +  // padding1
+    2 +  // reverse_spread
+    2 +  // loan_rate_bps
+    2 +  // reverse_cycles_left
+    2 +  // reverse_bid_spread
+    8 +  // loan_sequence_number
+    32 + // market_loans
+    8 +  // time_on_book_base_atoms
+    4 +  // time_on_book_slot
+    4 +  // last_valid_unix_timestamp
+    8; // gas_deposit_lamports
+const RESTING_ORDER_PADDING2_SIZE: usize = RESTING_ORDER_SIZE - RESTING_ORDER_FIELDS_SIZE;
+const_assert_eq!(RESTING_ORDER_PADDING2_SIZE % 8, 0);
+
 #[repr(C)]
 #[derive(Default, Debug, Copy, Clone, Zeroable, Pod, ShankType)]
 pub struct RestingOrder {
@@ -85,10 +182,33 @@ pub struct RestingOrder {
     order_type: OrderType,
     is_bid: PodBool,
     is_a_tree: PodBool,
-    padding1: [u8; 5],
-    // // Spread for reverse orders. Defaults to zero.
+    expiry_policy: ExpiryPolicy,
+    padding1: [u8; 4],
+    // Spread for reverse orders where they flip into an ask. Defaults to
+    // zero.
     reverse_spread: u16,
-    padding2: [u8; 30],
+    // Rate the loan of a loan sale pays, zero for other orders.
+    loan_rate_bps: u16,
+    // Flips a reverse order has left, the last one rests as a limit order.
+    // Zero for no limit.
+    reverse_cycles_left: u16,
+    // Spread for reverse orders where they flip into a bid.
+    reverse_bid_spread: u16,
+    loan_sequence_number: u64,
+    // Loans page that holds the loan of a loan sale.
+    market_loans: Pubkey,
+    // Size in base atoms and slot as of resting or the last update, the time
+    // on book of the order is accrued from them. Zero for loan sales.
+    time_on_book_base_atoms: u64,
+    time_on_book_slot: u32,
+    // Unix timestamp a good till time order is valid through,
+    // NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP for other orders.
+    last_valid_unix_timestamp: u32,
+    // Lamports the order holds on the market account, paid to whoever takes
+    // it off the book. Zero but for good till time orders.
+    gas_deposit_lamports: u64,
+    // In words, Pod and Default are only implemented for some array lengths.
+    padding2: [u64; RESTING_ORDER_PADDING2_SIZE / 8],
 }
 
 // bid(borrower)  asset_shares(collateral), liability_shares amount
@@ -108,6 +228,7 @@ impl RestingOrder {
         order_type: OrderType,
         is_bid: bool,
         reverse_spread: u16,
+        expiry_policy: ExpiryPolicy,
     ) -> Result<Self, ProgramError> {
         // Reverse orders cannot have expiration.
         assert!(
@@ -125,20 +246,80 @@ impl RestingOrder {
             is_a_tree: PodBool::from_bool(is_a_tree),
             order_type,
             reverse_spread,
+            expiry_policy,
+            loan_rate_bps: 0,
+            loan_sequence_number: 0,
+            market_loans: Pubkey::default(),
             padding: Default::default(),
             padding1: Default::default(),
-            padding2: Default::default(),
+            time_on_book_base_atoms: 0,
+            time_on_book_slot: 0,
+            reverse_cycles_left: 0,
+            reverse_bid_spread: reverse_spread,
+            last_valid_unix_timestamp: NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP,
+            gas_deposit_lamports: 0,
+            padding2: [0; RESTING_ORDER_PADDING2_SIZE / 8],
         })
     }
 
+    /// Bid that offers the lender side of `loan` at `rate_bps`. The liability
+    /// shares of the loan size the order, it holds no collateral and expires
+    /// without touching the loan.
+    pub fn new_loan_sale(
+        rate_bps: u16,
+        sequence_number: u64,
+        trader_index: DataIndex,
+        last_valid_slot: u32,
+        market_loans: Pubkey,
+        loan: &ActiveLoan,
+    ) -> Result<Self, ProgramError> {
+        let mut resting_order: RestingOrder = RestingOrder::new(
+            rate_bps,
+            sequence_number,
+            WrappedI80F48::ZERO,
+            loan.liability_shares,
+            loan.is_liability_base_a.0 == 1,
+            trader_index,
+            last_valid_slot,
+            OrderType::LoanSale,
+            true,
+            0,
+            ExpiryPolicy::ReturnCollateral,
+        )?;
+        resting_order.loan_rate_bps = loan.rate_bps;
+        resting_order.loan_sequence_number = loan.sequence_number;
+        resting_order.market_loans = market_loans;
+        Ok(resting_order)
+    }
+
     pub fn get_collateral_shares(&self) -> WrappedI80F48 {
         self.collateral_shares
     }
     pub fn get_liability_shares(&self) -> WrappedI80F48 {
         self.liability_shares
     }
-    pub fn is_expired(&self, current_slot: u32) -> bool {
-        self.last_valid_slot != NO_EXPIRATION_LAST_VALID_SLOT && self.last_valid_slot < current_slot
+    pub fn is_expired(&self, current_slot: u32, current_unix_timestamp: i64) -> bool {
+        (self.last_valid_slot != NO_EXPIRATION_LAST_VALID_SLOT
+            && self.last_valid_slot < current_slot)
+            || (self.last_valid_unix_timestamp != NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP
+                && (self.last_valid_unix_timestamp as i64) < current_unix_timestamp)
+    }
+
+    /// Makes the order good till `last_valid_unix_timestamp`, holding
+    /// `gas_deposit_lamports` for whoever removes it.
+    pub fn set_good_till_time(
+        &mut self,
+        last_valid_unix_timestamp: u32,
+        gas_deposit_lamports: u64,
+    ) {
+        self.last_valid_unix_timestamp = last_valid_unix_timestamp;
+        self.gas_deposit_lamports = gas_deposit_lamports;
+    }
+    pub fn get_last_valid_unix_timestamp(&self) -> u32 {
+        self.last_valid_unix_timestamp
+    }
+    pub fn get_gas_deposit_lamports(&self) -> u64 {
+        self.gas_deposit_lamports
     }
 
     pub fn get_is_bid(&self) -> bool {
@@ -157,14 +338,71 @@ impl RestingOrder {
         self.sequence_number
     }
 
+    pub fn get_expiry_policy(&self) -> ExpiryPolicy {
+        self.expiry_policy
+    }
+
     pub fn is_reverse(&self) -> bool {
         self.order_type == OrderType::Reverse
     }
 
+    pub fn is_loan_sale(&self) -> bool {
+        self.order_type == OrderType::LoanSale
+    }
+    pub fn get_loan_rate_bps(&self) -> u16 {
+        self.loan_rate_bps
+    }
+    pub fn get_loan_sequence_number(&self) -> u64 {
+        self.loan_sequence_number
+    }
+    pub fn get_market_loans(&self) -> &Pubkey {
+        &self.market_loans
+    }
+
+    /// Starts counting time on book for `num_base_atoms` from `now_slot`.
+    pub fn start_time_on_book(&mut self, num_base_atoms: u64, now_slot: u32) {
+        self.time_on_book_base_atoms = num_base_atoms;
+        self.time_on_book_slot = now_slot;
+    }
+
+    /// Base atom slots rested since the last update, not counting past the
+    /// expiry. Restarts the count at `now_slot` with what is left.
+    pub fn accrue_time_on_book(&mut self, num_base_atoms_left: u64, now_slot: u32) -> u64 {
+        let end_slot: u32 = if self.last_valid_slot == NO_EXPIRATION_LAST_VALID_SLOT {
+            now_slot
+        } else {
+            now_slot.min(self.last_valid_slot)
+        };
+        let time_on_book: u64 = self
+            .time_on_book_base_atoms
+            .saturating_mul(end_slot.saturating_sub(self.time_on_book_slot) as u64);
+        self.start_time_on_book(num_base_atoms_left, now_slot);
+        time_on_book
+    }
+
+    pub fn get_time_on_book_base_atoms(&self) -> u64 {
+        self.time_on_book_base_atoms
+    }
+
     pub fn get_reverse_spread(self) -> u16 {
         self.reverse_spread
     }
-    pub fn get_num_base_atoms(&self, base_bank: &Bank) -> Result<u64, ProgramError> {
+    pub fn get_reverse_cycles_left(&self) -> u16 {
+        self.reverse_cycles_left
+    }
+    pub fn set_reverse_cycles_left(&mut self, reverse_cycles_left: u16) {
+        self.reverse_cycles_left = reverse_cycles_left;
+    }
+    pub fn set_reverse_spread(&mut self, reverse_spread: u16) {
+        self.reverse_spread = reverse_spread;
+    }
+    pub fn get_reverse_bid_spread(&self) -> u16 {
+        self.reverse_bid_spread
+    }
+    pub fn set_reverse_bid_spread(&mut self, reverse_bid_spread: u16) {
+        self.reverse_bid_spread = reverse_bid_spread;
+    }
+    pub fn get_num_base_atoms(&self, base_bank: &BankShareValues) -> Result<u64, ProgramError> {
         if self.get_is_bid() {
             //convert liability shares to asset tokens
             get_token_amount_to_repay_liability_shares(
@@ -185,8 +423,8 @@ impl RestingOrder {
 
     pub fn reduce_bid(
         &mut self,
-        base_bank: &Bank,
-        quote_bank: &Bank,
+        base_bank: &BankShareValues,
+        quote_bank: &BankShareValues,
         quote_atoms_traded: u64,
         base_atoms_traded: u64,
     ) -> ProgramResult {
@@ -200,24 +438,54 @@ impl RestingOrder {
             convert_tokens_to_liability_shares(base_atoms_traded, base_bank)?;
 
         //collateral amount
-        self.collateral_shares =
-            WrappedI80F48::from(I80F48::from(self.collateral_shares) - collateral_shares_delta);
+        self.collateral_shares = self
+            .collateral_shares
+            .checked_sub(collateral_shares_delta)
+            .ok_or(NixError::NumericalOverflow)?;
         //liability amount reduced
-        self.liability_shares =
-            WrappedI80F48::from(I80F48::from(self.liability_shares) - liability_shares_delta);
+        self.liability_shares = self
+            .liability_shares
+            .checked_sub(liability_shares_delta)
+            .ok_or(NixError::NumericalOverflow)?;
         Ok(())
     }
 
-    pub fn reduce_ask(&mut self, base_bank: &Bank, base_atoms_traded: u64) -> ProgramResult {
+    pub fn reduce_ask(&mut self, base_bank: &BankShareValues, base_atoms_traded: u64) -> ProgramResult {
         if self.get_is_bid() {
             return Err(ProgramError::InvalidArgument);
         }
 
         let collateral_shares_delta = convert_tokens_to_asset_shares(base_atoms_traded, base_bank)?;
 
-        self.collateral_shares =
-            WrappedI80F48::from(I80F48::from(self.collateral_shares) - collateral_shares_delta);
-        self.liability_shares = WrappedI80F48::from(I80F48::from(0));
+        self.collateral_shares = self
+            .collateral_shares
+            .checked_sub(collateral_shares_delta)
+            .ok_or(NixError::NumericalOverflow)?;
+        self.liability_shares = WrappedI80F48::ZERO;
+        Ok(())
+    }
+
+    /// Adds collateral to a bid without moving it in the tree.
+    pub fn add_collateral_shares(&mut self, collateral_shares: WrappedI80F48) -> ProgramResult {
+        if !self.get_is_bid() {
+            return Err(ProgramError::InvalidArgument);
+        }
+        self.collateral_shares = self
+            .collateral_shares
+            .checked_add(collateral_shares)
+            .ok_or(NixError::NumericalOverflow)?;
+        Ok(())
+    }
+
+    /// Takes collateral off an ask without moving it in the tree.
+    pub fn reduce_collateral_shares(&mut self, collateral_shares: WrappedI80F48) -> ProgramResult {
+        if self.get_is_bid() {
+            return Err(ProgramError::InvalidArgument);
+        }
+        self.collateral_shares = self
+            .collateral_shares
+            .checked_sub(collateral_shares)
+            .ok_or(NixError::NumericalOverflow)?;
         Ok(())
     }
 }
@@ -228,11 +496,13 @@ impl Ord for RestingOrder {
         // check if orders match, directly access their prices.
         debug_assert!(self.get_is_bid() == other.get_is_bid());
 
-        if self.get_is_bid() {
+        let cmp: Ordering = if self.get_is_bid() {
             (self.rate_bps).cmp(&other.rate_bps)
         } else {
             (other.rate_bps).cmp(&(self.rate_bps))
-        }
+        };
+        // Time priority within a rate, older orders are matched first.
+        cmp.then_with(|| other.sequence_number.cmp(&self.sequence_number))
     }
 }
 
@@ -244,17 +514,8 @@ impl PartialOrd for RestingOrder {
 
 impl PartialEq for RestingOrder {
     fn eq(&self, other: &Self) -> bool {
-        if self.trader_index != other.trader_index || self.order_type != other.order_type {
-            return false;
-        }
-        if self.order_type == OrderType::Reverse {
-            self.rate_bps == other.rate_bps
-                || self.rate_bps.wrapping_add(1) == other.rate_bps
-                || self.rate_bps.wrapping_sub(1) == other.rate_bps
-        } else {
-            // Only used in equality check of lookups, so we can ignore size, seqnum, ...
-            self.rate_bps == other.rate_bps
-        }
+        // Same as Ord, sequence numbers are unique so this is the same order.
+        self.rate_bps == other.rate_bps && self.sequence_number == other.sequence_number
     }
 }
 
@@ -0,0 +1,252 @@
+//! End to end consistency checks for a market account, for program tests,
+//! the fuzzer and tooling. Every check panics on the first broken invariant,
+//! the same way the red black tree checks in hypertree do.
+
+use fixed::types::I80F48;
+use hypertree::{
+    get_helper, DataIndex, FreeListNode, HyperTreeReadOperations, HyperTreeValueIteratorTrait,
+    RedBlackTreeTestHelpers, NIL,
+};
+use solana_program::pubkey::Pubkey;
+
+use crate::state::{
+    get_helper_seat, get_tree_indexes, should_update_base_a, BooksideReadOnly, ClaimedSeat,
+    ClaimedSeatTreeReadOnly, ExpandableFixed, MarketRef, MarketUnusedFreeListPadding, RestingOrder,
+    MARKET_BLOCK_SIZE,
+};
+
+/// Runs every check in this module.
+pub fn verify_market(market: &MarketRef) {
+    verify_booksides_sorted(market);
+    verify_orders_have_seats(market);
+    verify_blocks(market);
+    verify_cached_share_totals(market);
+}
+
+/// Every book side has to be a valid red black tree whose best index is its
+/// first order. Bids go from the highest rate down and asks from the lowest
+/// rate up.
+pub fn verify_booksides_sorted(market: &MarketRef) {
+    for use_a_tree in [true, false] {
+        let (bids_best_index, asks_best_index, bids_root_index, asks_root_index) =
+            get_tree_indexes(market.fixed, use_a_tree);
+        for (is_bid, root_index, best_index) in [
+            (true, bids_root_index, bids_best_index),
+            (false, asks_root_index, asks_best_index),
+        ] {
+            // Passing NIL as the max makes the tree look up its best order
+            // instead of trusting the cached one.
+            let bookside: BooksideReadOnly = BooksideReadOnly::new(market.dynamic, root_index, NIL);
+            bookside.verify_rb_tree::<RestingOrder>();
+
+            let mut previous_rate_bps: Option<u16> = None;
+            let mut previous_sequence_number: u64 = 0;
+            let mut first_index: DataIndex = NIL;
+            for (order_index, resting_order) in bookside.iter::<RestingOrder>() {
+                if first_index == NIL {
+                    first_index = order_index;
+                }
+                assert_eq!(
+                    resting_order.get_is_bid(),
+                    is_bid,
+                    "Order {} is on the wrong side of the book",
+                    order_index
+                );
+                let rate_bps: u16 = resting_order.get_rate_bps();
+                if let Some(previous_rate_bps) = previous_rate_bps {
+                    assert!(
+                        if is_bid {
+                            rate_bps <= previous_rate_bps
+                        } else {
+                            rate_bps >= previous_rate_bps
+                        },
+                        "Order {} at {} bps is out of order after {} bps",
+                        order_index,
+                        rate_bps,
+                        previous_rate_bps
+                    );
+                    assert!(
+                        rate_bps != previous_rate_bps
+                            || resting_order.get_sequence_number() > previous_sequence_number,
+                        "Order {} at {} bps is ahead of an older order",
+                        order_index,
+                        rate_bps
+                    );
+                }
+                previous_rate_bps = Some(rate_bps);
+                previous_sequence_number = resting_order.get_sequence_number();
+            }
+            assert_eq!(
+                best_index, first_index,
+                "Best index {} does not match the best order {}",
+                best_index, first_index
+            );
+        }
+    }
+}
+
+/// Every resting order has to point at a seat that is in the seats tree.
+pub fn verify_orders_have_seats(market: &MarketRef) {
+    let claimed_seats_tree: ClaimedSeatTreeReadOnly = ClaimedSeatTreeReadOnly::new(
+        market.dynamic,
+        market.fixed.get_claimed_seats_root_index(),
+        NIL,
+    );
+    claimed_seats_tree.verify_rb_tree::<ClaimedSeat>();
+
+    for use_a_tree in [true, false] {
+        let (_, _, bids_root_index, asks_root_index) = get_tree_indexes(market.fixed, use_a_tree);
+        for root_index in [bids_root_index, asks_root_index] {
+            let bookside: BooksideReadOnly = BooksideReadOnly::new(market.dynamic, root_index, NIL);
+            for (order_index, resting_order) in bookside.iter::<RestingOrder>() {
+                let trader_index: DataIndex = resting_order.get_trader_index();
+                let trader: Pubkey = get_helper_seat(market.dynamic, trader_index)
+                    .get_value()
+                    .trader;
+                assert_eq!(
+                    claimed_seats_tree.lookup_index(&ClaimedSeat::new_empty(trader)),
+                    trader_index,
+                    "Order {} does not belong to a claimed seat",
+                    order_index
+                );
+            }
+        }
+    }
+}
+
+/// Every allocated block has to be in exactly one of the seats tree, a book
+/// side or the free list.
+pub fn verify_blocks(market: &MarketRef) {
+    let num_blocks: usize = market.fixed.get_num_bytes_allocated() as usize / MARKET_BLOCK_SIZE;
+    let mut block_seen: Vec<bool> = vec![false; num_blocks];
+    let mut mark_block = |index: DataIndex| {
+        let block: usize = index as usize / MARKET_BLOCK_SIZE;
+        assert!(
+            index as usize % MARKET_BLOCK_SIZE == 0 && block < num_blocks,
+            "Index {} is not a block",
+            index
+        );
+        assert!(!block_seen[block], "Block {} is used twice", index);
+        block_seen[block] = true;
+    };
+
+    let claimed_seats_tree: ClaimedSeatTreeReadOnly = ClaimedSeatTreeReadOnly::new(
+        market.dynamic,
+        market.fixed.get_claimed_seats_root_index(),
+        NIL,
+    );
+    for (seat_index, _) in claimed_seats_tree.iter::<ClaimedSeat>() {
+        mark_block(seat_index);
+    }
+    for use_a_tree in [true, false] {
+        let (_, _, bids_root_index, asks_root_index) = get_tree_indexes(market.fixed, use_a_tree);
+        for root_index in [bids_root_index, asks_root_index] {
+            let bookside: BooksideReadOnly = BooksideReadOnly::new(market.dynamic, root_index, NIL);
+            for (order_index, _) in bookside.iter::<RestingOrder>() {
+                mark_block(order_index);
+            }
+        }
+    }
+
+    let mut free_index: DataIndex = market.fixed.get_free_list_head_index();
+    while free_index != NIL {
+        mark_block(free_index);
+        free_index =
+            get_helper::<FreeListNode<MarketUnusedFreeListPadding>>(market.dynamic, free_index)
+                .get_next_index();
+    }
+    assert!(
+        block_seen.iter().all(|seen| *seen),
+        "Blocks leaked out of the trees and the free list"
+    );
+}
+
+/// The share totals cached on the market header have to equal the sums over
+/// the seats.
+pub fn verify_cached_share_totals(market: &MarketRef) {
+    let mut base_a_asset_shares: I80F48 = I80F48::ZERO;
+    let mut base_b_asset_shares: I80F48 = I80F48::ZERO;
+    let mut base_a_liability_shares: I80F48 = I80F48::ZERO;
+    let mut base_b_liability_shares: I80F48 = I80F48::ZERO;
+
+    let claimed_seats_tree: ClaimedSeatTreeReadOnly = ClaimedSeatTreeReadOnly::new(
+        market.dynamic,
+        market.fixed.get_claimed_seats_root_index(),
+        NIL,
+    );
+    for (_, claimed_seat) in claimed_seats_tree.iter::<ClaimedSeat>() {
+        base_a_asset_shares += I80F48::from(claimed_seat.base_a_withdrawable_asset_share);
+        base_b_asset_shares += I80F48::from(claimed_seat.base_b_withdrawable_asset_share);
+        base_a_liability_shares += I80F48::from(claimed_seat.base_a_liability_shares);
+        base_b_liability_shares += I80F48::from(claimed_seat.base_b_liability_shares);
+    }
+
+    for (cached_shares, seat_shares, name) in [
+        (
+            market.fixed.get_base_a_seat_asset_shares(),
+            base_a_asset_shares,
+            "base a asset",
+        ),
+        (
+            market.fixed.get_base_b_seat_asset_shares(),
+            base_b_asset_shares,
+            "base b asset",
+        ),
+        (
+            market.fixed.get_base_a_seat_liability_shares(),
+            base_a_liability_shares,
+            "base a liability",
+        ),
+        (
+            market.fixed.get_base_b_seat_liability_shares(),
+            base_b_liability_shares,
+            "base b liability",
+        ),
+    ] {
+        assert_eq!(
+            I80F48::from(cached_shares),
+            seat_shares,
+            "Cached {} shares do not match the seats",
+            name
+        );
+    }
+}
+
+/// Asset shares of base A and base B that the market owes to traders and
+/// the protocol. Covers withdrawable balances, unclaimed referral fees and
+/// maker rebates, resting order collateral and protocol fees. Shares held by
+/// the insurance fund of the market come on top.
+pub fn get_total_claimed_asset_shares(market: &MarketRef) -> (I80F48, I80F48) {
+    let mut base_a_shares: I80F48 = market.fixed.get_base_a_protocol_fee_shares().into();
+    let mut base_b_shares: I80F48 = market.fixed.get_base_b_protocol_fee_shares().into();
+
+    let claimed_seats_tree: ClaimedSeatTreeReadOnly = ClaimedSeatTreeReadOnly::new(
+        market.dynamic,
+        market.fixed.get_claimed_seats_root_index(),
+        NIL,
+    );
+    for (_, claimed_seat) in claimed_seats_tree.iter::<ClaimedSeat>() {
+        base_a_shares += I80F48::from(claimed_seat.base_a_withdrawable_asset_share)
+            + I80F48::from(claimed_seat.base_a_referral_fee_shares)
+            + I80F48::from(claimed_seat.base_a_maker_rebate_shares);
+        base_b_shares += I80F48::from(claimed_seat.base_b_withdrawable_asset_share)
+            + I80F48::from(claimed_seat.base_b_referral_fee_shares)
+            + I80F48::from(claimed_seat.base_b_maker_rebate_shares);
+    }
+
+    for use_a_tree in [true, false] {
+        let (_, _, bids_root_index, asks_root_index) = get_tree_indexes(market.fixed, use_a_tree);
+        for (is_bid, root_index) in [(true, bids_root_index), (false, asks_root_index)] {
+            let bookside: BooksideReadOnly = BooksideReadOnly::new(market.dynamic, root_index, NIL);
+            for (_, resting_order) in bookside.iter::<RestingOrder>() {
+                let collateral_shares: I80F48 = resting_order.get_collateral_shares().into();
+                if should_update_base_a(use_a_tree, is_bid) {
+                    base_a_shares += collateral_shares;
+                } else {
+                    base_b_shares += collateral_shares;
+                }
+            }
+        }
+    }
+    (base_a_shares, base_b_shares)
+}
@@ -0,0 +1,144 @@
+//! Zero copy views over raw account data, such as the bytes of an RPC
+//! account subscription. The header is cast in place and orders and seats
+//! are handed out as references into the data, so a bot can read the top of
+//! a book or one seat on every update without copying the whole market.
+
+use std::marker::PhantomData;
+
+use hypertree::{
+    get_helper, DataIndex, HyperTreeReadOperations, Payload, RBNode, RedBlackTreeReadOnly, NIL,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+use crate::{require, validation::NixAccount};
+
+use super::{
+    market::get_tree_indexes, ClaimedSeat, MarketFixed, MarketRef, RestingOrder, MARKET_FIXED_SIZE,
+};
+
+/// Market account data split into its header and its blocks.
+#[derive(Clone, Copy)]
+pub struct MarketView<'a> {
+    pub fixed: &'a MarketFixed,
+    pub dynamic: &'a [u8],
+}
+
+impl<'a> MarketView<'a> {
+    /// Fails on data that is too short, misaligned or not a market, where
+    /// get_helper would panic.
+    pub fn new(data: &'a [u8]) -> Result<Self, ProgramError> {
+        require!(
+            data.len() >= MARKET_FIXED_SIZE,
+            ProgramError::InvalidAccountData,
+            "Market data is only {} bytes",
+            data.len(),
+        )?;
+        let (fixed_data, dynamic) = data.split_at(MARKET_FIXED_SIZE);
+        let fixed: &MarketFixed =
+            bytemuck::try_from_bytes(fixed_data).map_err(|_| ProgramError::InvalidAccountData)?;
+        fixed.verify_discriminant()?;
+        Ok(MarketView { fixed, dynamic })
+    }
+
+    /// For the reads MarketRef already has, like quote_order.
+    pub fn as_market_ref(&self) -> MarketRef<'a> {
+        MarketRef {
+            fixed: self.fixed,
+            dynamic: self.dynamic,
+        }
+    }
+
+    /// Best order on a book side, found through the index kept in the
+    /// header. Expired orders are not skipped.
+    pub fn get_best_order(&self, use_a_tree: bool, is_bid: bool) -> Option<&'a RestingOrder> {
+        let (bids_best_index, asks_best_index, _, _) = get_tree_indexes(self.fixed, use_a_tree);
+        let best_index: DataIndex = if is_bid {
+            bids_best_index
+        } else {
+            asks_best_index
+        };
+        if best_index == NIL {
+            return None;
+        }
+        Some(get_helper::<RBNode<RestingOrder>>(self.dynamic, best_index).get_value())
+    }
+
+    /// Orders on a book side, best rate first.
+    pub fn iter_orders(&self, use_a_tree: bool, is_bid: bool) -> TreeIter<'a, RestingOrder> {
+        let (bids_best_index, asks_best_index, bids_root_index, asks_root_index) =
+            get_tree_indexes(self.fixed, use_a_tree);
+        if is_bid {
+            TreeIter::new(self.dynamic, bids_root_index, bids_best_index)
+        } else {
+            TreeIter::new(self.dynamic, asks_root_index, asks_best_index)
+        }
+    }
+
+    /// All claimed seats, in key order from the highest.
+    pub fn iter_seats(&self) -> TreeIter<'a, ClaimedSeat> {
+        TreeIter::new(self.dynamic, self.fixed.get_claimed_seats_root_index(), NIL)
+    }
+
+    /// Seat of the trader, or None when the trader has no seat.
+    pub fn get_seat(&self, trader: &Pubkey) -> Option<&'a ClaimedSeat> {
+        let trader_index: DataIndex = self.as_market_ref().get_trader_index(trader);
+        if trader_index == NIL {
+            return None;
+        }
+        Some(get_helper::<RBNode<ClaimedSeat>>(self.dynamic, trader_index).get_value())
+    }
+}
+
+/// Walks a tree from its max node down. Unlike the hypertree iterator it
+/// only borrows the account data, so it can be returned from a view.
+pub struct TreeIter<'a, V: Payload> {
+    data: &'a [u8],
+    root_index: DataIndex,
+    index: DataIndex,
+    phantom: PhantomData<&'a V>,
+}
+
+impl<'a, V: Payload> TreeIter<'a, V> {
+    /// max_index can be NIL when the tree does not keep it, it is then
+    /// looked up from the root.
+    fn new(data: &'a [u8], root_index: DataIndex, max_index: DataIndex) -> Self {
+        let index: DataIndex = if max_index == NIL {
+            RedBlackTreeReadOnly::<V>::new(data, root_index, NIL).lookup_max_index::<V>()
+        } else {
+            max_index
+        };
+        TreeIter {
+            data,
+            root_index,
+            index,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'a, V: Payload> Iterator for TreeIter<'a, V> {
+    type Item = (DataIndex, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index == NIL {
+            return None;
+        }
+        let index: DataIndex = self.index;
+        self.index = RedBlackTreeReadOnly::<V>::new(self.data, self.root_index, NIL)
+            .get_next_lower_index::<V>(index);
+        Some((index, get_helper::<RBNode<V>>(self.data, index).get_value()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_market_view_rejects_other_data() {
+        assert!(MarketView::new(&[0; 8]).is_err());
+        // Right size, but not a market.
+        let data: Vec<u64> = vec![0; MARKET_FIXED_SIZE / 8];
+        assert!(MarketView::new(bytemuck::cast_slice(&data)).is_err());
+    }
+}
@@ -1,48 +1,110 @@
+#[cfg(feature = "program")]
 use std::cell::RefMut;
 
+#[cfg(feature = "program")]
 use fixed::types::I80F48;
 use hypertree::{DataIndex, NIL};
+#[cfg(feature = "program")]
 use solana_program::{
-    account_info::AccountInfo, entrypoint::ProgramResult, keccak, program::invoke_signed,
-    program_error::ProgramError, pubkey::Pubkey, rent::Rent, system_instruction, sysvar::Sysvar,
+    account_info::AccountInfo,
+    instruction::{get_stack_height, TRANSACTION_LEVEL_STACK_HEIGHT},
+    program::invoke_signed,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction,
+    sysvar::instructions,
 };
+use solana_program::{
+    entrypoint::ProgramResult, keccak, program_error::ProgramError, sysvar::Sysvar,
+};
+#[cfg(feature = "program")]
 use spl_token_2022::{
     extension::{
-        transfer_fee::TransferFeeConfig, transfer_hook::TransferHook, BaseStateWithExtensions,
-        StateWithExtensions,
+        interest_bearing_mint::InterestBearingConfig, transfer_fee::TransferFeeConfig,
+        BaseStateWithExtensions, StateWithExtensions,
     },
     state::Mint,
 };
 
+use crate::require;
+#[cfg(feature = "program")]
 use crate::{
     global_vault_seeds_with_bump,
     logs::{emit_stack, GlobalCleanupLog},
+    marginfi_utils::{
+        cpi_marginfi_withdraw_to, get_bank_config_hash, get_marginfi_asset_shares, BankShareValues,
+        MarginfiCpiScratch,
+    },
     program::{get_mut_dynamic_account, invoke, NixError},
-    require,
     state::{
+        get_required_marginfi_sides,
         market_loan::{ActiveLoan, MarketLoansFixed, MarketLoansRefMut},
-        order_type_can_take, GlobalFixed, GlobalRefMut, OrderType, RestingOrder,
-        GAS_DEPOSIT_LAMPORTS, NO_EXPIRATION_LAST_VALID_SLOT,
+        order_type_can_take, GlobalFixed, GlobalRefMut, MarketFixed, OrderType, RestingOrder,
+        NO_EXPIRATION_LAST_VALID_SLOT, NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP,
     },
     validation::{
-        loaders::GlobalTradeAccounts, MintAccountInfo, NixAccountInfo, Program, Signer,
-        TokenAccountInfo, TokenProgram,
+        loaders::{GlobalTradeAccounts, MarginfiCpiAccounts},
+        MintAccountInfo, NixAccountInfo, Program, Signer, TokenAccountInfo, TokenProgram,
     },
 };
 
-/// Canonical discriminant of the given struct. It is the hash of program ID and
-/// the name of the type.
-pub fn get_discriminant<T>() -> Result<u64, ProgramError> {
-    let type_name: &str = std::any::type_name::<T>();
+/// Name an account type hashes into its discriminant. Spelled out instead of
+/// std::any::type_name, which changes whenever the type moves module.
+pub trait StableName {
+    const STABLE_NAME: &'static str;
+    /// What std::any::type_name was when discriminants were hashed from it.
+    /// Accounts created back then still carry that discriminant.
+    const LEGACY_NAME: &'static str;
+}
+
+/// Hash of program ID and the name.
+pub fn get_discriminant_of_name(name: &str) -> Result<u64, ProgramError> {
     let discriminant: u64 = u64::from_le_bytes(
-        keccak::hashv(&[crate::ID.as_ref(), type_name.as_bytes()]).as_ref()[..8]
+        keccak::hashv(&[crate::ID.as_ref(), name.as_bytes()]).as_ref()[..8]
             .try_into()
             .map_err(|_| ProgramError::InvalidAccountData)?,
     );
     Ok(discriminant)
 }
 
+/// Canonical discriminant of the given struct. It is the hash of program ID and
+/// the stable name of the type.
+pub fn get_discriminant<T: StableName>() -> Result<u64, ProgramError> {
+    get_discriminant_of_name(T::STABLE_NAME)
+}
+
+pub fn get_legacy_discriminant<T: StableName>() -> Result<u64, ProgramError> {
+    get_discriminant_of_name(T::LEGACY_NAME)
+}
+
+/// Whether an account header with this discriminant is a T. Accepts the
+/// legacy discriminant so accounts created before the stable names keep
+/// loading.
+pub fn is_discriminant_of<T: StableName>(discriminant: u64) -> bool {
+    get_discriminant::<T>().is_ok_and(|expected| expected == discriminant)
+        || get_legacy_discriminant::<T>().is_ok_and(|legacy| legacy == discriminant)
+}
+
+/// Closes an account owned by this program by moving all of its lamports to
+/// the receiver and zeroing its data so it cannot be reused in the same
+/// transaction.
+#[cfg(feature = "program")]
+pub(crate) fn close_nix_account<'a, 'info>(
+    account: &'a AccountInfo<'info>,
+    receiver: &'a AccountInfo<'info>,
+) -> ProgramResult {
+    let lamports: u64 = account.lamports();
+    **receiver.try_borrow_mut_lamports()? = receiver
+        .lamports()
+        .checked_add(lamports)
+        .ok_or(NixError::NumericalOverflow)?;
+    **account.try_borrow_mut_lamports()? = 0;
+    account.try_borrow_mut_data()?.fill(0);
+    Ok(())
+}
+
 /// Send CPI for creating a new account on chain.
+#[cfg(feature = "program")]
 pub fn create_account<'a, 'info>(
     payer: &'a AccountInfo<'info>,
     new_account: &'a AccountInfo<'info>,
@@ -68,6 +130,56 @@ pub fn create_account<'a, 'info>(
             .as_slice()],
     )
 }
+
+/// Same as create_account for an address of this program. Anyone can send
+/// lamports to a PDA before it is created, which makes a plain create fail,
+/// so a funded address is topped up to rent exemption and then allocated
+/// and assigned instead.
+#[cfg(feature = "program")]
+pub fn create_pda_account<'a, 'info>(
+    payer: &'a AccountInfo<'info>,
+    new_account: &'a AccountInfo<'info>,
+    system_program: &'a AccountInfo<'info>,
+    program_owner: &Pubkey,
+    rent: &Rent,
+    space: u64,
+    seeds: Vec<Vec<u8>>,
+) -> ProgramResult {
+    let current_lamports: u64 = new_account.lamports();
+    if current_lamports == 0 {
+        return create_account(
+            payer,
+            new_account,
+            system_program,
+            program_owner,
+            rent,
+            space,
+            seeds,
+        );
+    }
+
+    let missing_lamports: u64 = rent
+        .minimum_balance(space as usize)
+        .saturating_sub(current_lamports);
+    if missing_lamports > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, new_account.key, missing_lamports),
+            &[payer.clone(), new_account.clone(), system_program.clone()],
+        )?;
+    }
+    let signer_seeds: Vec<&[u8]> = seeds.iter().map(|seed| seed.as_slice()).collect();
+    invoke_signed(
+        &system_instruction::allocate(new_account.key, space),
+        &[new_account.clone(), system_program.clone()],
+        &[signer_seeds.as_slice()],
+    )?;
+    invoke_signed(
+        &system_instruction::assign(new_account.key, program_owner),
+        &[new_account.clone(), system_program.clone()],
+        &[signer_seeds.as_slice()],
+    )
+}
+
 pub fn get_now_slot() -> u32 {
     // If we cannot get the clock (happens in tests, then only match with
     // orders without expiration). We assume that the clock cannot be
@@ -107,6 +219,7 @@ pub fn get_now_unix_timestamp() -> i64 {
         .unix_timestamp;
     now_timestamp
 }
+#[cfg(feature = "program")]
 pub(crate) fn get_now_epoch() -> u64 {
     #[cfg(feature = "no-clock")]
     let now_epoch: u64 = 0;
@@ -122,6 +235,7 @@ pub(crate) fn get_now_epoch() -> u64 {
         .slot;
     now_epoch
 }
+#[cfg(feature = "program")]
 pub(crate) fn assert_can_take(order_type: OrderType) -> ProgramResult {
     require!(
         order_type_can_take(order_type),
@@ -131,13 +245,89 @@ pub(crate) fn assert_can_take(order_type: OrderType) -> ProgramResult {
     Ok(())
 }
 
+#[cfg(feature = "program")]
+pub(crate) fn assert_has_required_marginfi_sides(
+    order_type: OrderType,
+    is_bid: bool,
+    marginfi_cpi_accounts_opts: &[Option<MarginfiCpiAccounts>; 2],
+) -> ProgramResult {
+    let required_sides: [bool; 2] = get_required_marginfi_sides(order_type, is_bid);
+    for (index, required) in required_sides.iter().enumerate() {
+        require!(
+            !required || marginfi_cpi_accounts_opts[index].is_some(),
+            crate::program::NixError::MissingMarginfiAccounts,
+            "Missing {} marginfi accounts",
+            if index == 0 { "base" } else { "quote" },
+        )?;
+    }
+    Ok(())
+}
+
+/// One sided markets have no marginfi bank for one of the mints, so orders
+/// that would lend or borrow it cannot be placed on a tree that uses it.
+#[cfg(feature = "program")]
+pub(crate) fn assert_market_has_required_banks(
+    fixed: &MarketFixed,
+    order_type: OrderType,
+    is_bid: bool,
+    use_a_tree: bool,
+) -> ProgramResult {
+    let required_sides: [bool; 2] = get_required_marginfi_sides(order_type, is_bid);
+    for (index, required) in required_sides.iter().enumerate() {
+        let is_base_a: bool = (index == 0) == use_a_tree;
+        require!(
+            !required || fixed.has_marginfi_bank(is_base_a),
+            NixError::MissingMarginfiBank,
+            "Market has no {} marginfi bank",
+            if index == 0 { "base" } else { "quote" },
+        )?;
+    }
+    Ok(())
+}
+
+/// Fails once the config of a marginfi bank the order reads no longer hashes
+/// to what the market recorded, since the collateral math would silently
+/// change with it until the admin acknowledges the change. Markets migrated
+/// without a hash record the one they see.
+#[cfg(feature = "program")]
+pub(crate) fn assert_bank_configs_unchanged(
+    fixed: &mut MarketFixed,
+    marginfi_cpi_accounts_opts: &[Option<MarginfiCpiAccounts>; 2],
+    use_a_tree: bool,
+) -> ProgramResult {
+    for (index, marginfi_cpi_accounts_opt) in marginfi_cpi_accounts_opts.iter().enumerate() {
+        let Some(marginfi_cpi_accounts) = marginfi_cpi_accounts_opt else {
+            continue;
+        };
+        let is_base_a: bool = (index == 0) == use_a_tree;
+        let bank_config_hash: u64 =
+            get_bank_config_hash(&marginfi_cpi_accounts.marginfi_bank.get_fixed()?.config);
+        let recorded_bank_config_hash: u64 = fixed.get_bank_config_hash(is_base_a);
+        if recorded_bank_config_hash == 0 {
+            fixed.set_bank_config_hash(is_base_a, bank_config_hash);
+            continue;
+        }
+        require!(
+            recorded_bank_config_hash == bank_config_hash,
+            NixError::BankConfigChanged,
+            "Config of marginfi bank {} changed, the market admin has to run AcknowledgeBankConfigChange",
+            marginfi_cpi_accounts.marginfi_bank.key,
+        )?;
+    }
+    Ok(())
+}
+
+/// Returns false when the global accounts were not given. The gas prepayment
+/// then stays stranded on the global account until SweepStrandedGas.
+#[cfg(feature = "program")]
 pub(crate) fn remove_from_global(
     global_trade_accounts_opt: &Option<GlobalTradeAccounts>,
-) -> ProgramResult {
+    trader: &Pubkey,
+) -> Result<bool, ProgramError> {
     if global_trade_accounts_opt.is_none() {
         // Payer is forfeiting the right to claim the gas prepayment. This
         // results in a stranded gas prepayment on the global account.
-        return Ok(());
+        return Ok(false);
     }
     let global_trade_accounts: &GlobalTradeAccounts = &global_trade_accounts_opt.as_ref().unwrap();
     let GlobalTradeAccounts {
@@ -148,34 +338,46 @@ pub(crate) fn remove_from_global(
 
     remove_from_global_core(
         global,
+        trader,
         gas_receiver_opt,
         &global_trade_accounts.system_program,
-    )
+    )?;
+    Ok(true)
 }
 
+#[cfg(feature = "program")]
 pub(crate) fn remove_from_global_core<'a, 'info>(
     global: &NixAccountInfo<'a, 'info, GlobalFixed>,
+    trader: &Pubkey,
     gas_receiver_opt: &Option<Signer<'a, 'info>>,
     system_program: &Option<Program<'a, 'info>>,
 ) -> ProgramResult {
     if system_program.is_some() {
-        **global.lamports.borrow_mut() -= GAS_DEPOSIT_LAMPORTS;
-        **gas_receiver_opt.as_ref().unwrap().lamports.borrow_mut() += GAS_DEPOSIT_LAMPORTS;
+        let gas_deposit_lamports: u64 = {
+            let global_data: &mut RefMut<&mut [u8]> = &mut global.try_borrow_mut_data()?;
+            let mut global_dynamic_account: GlobalRefMut = get_mut_dynamic_account(global_data);
+            global_dynamic_account.release_gas_prepayments(trader, 1);
+            global_dynamic_account.fixed.get_gas_deposit_lamports()
+        };
+        **global.lamports.borrow_mut() -= gas_deposit_lamports;
+        **gas_receiver_opt.as_ref().unwrap().lamports.borrow_mut() += gas_deposit_lamports;
     }
     Ok(())
 }
+#[cfg(feature = "program")]
 pub(crate) fn try_to_add_new_loans<'a, 'info>(
     market_loans_account: &NixAccountInfo<'a, 'info, MarketLoansFixed>,
-    matched_loans: Vec<ActiveLoan>,
+    matched_loans: &[ActiveLoan],
 ) -> ProgramResult {
     let market_loans_data: &mut RefMut<&mut [u8]> =
         &mut market_loans_account.try_borrow_mut_data()?;
     let mut market_loans_dynamic_account: MarketLoansRefMut =
         get_mut_dynamic_account(market_loans_data);
-    market_loans_dynamic_account.add_loans(&matched_loans)?;
+    market_loans_dynamic_account.add_loans(matched_loans)?;
     Ok(())
 }
 
+#[cfg(feature = "program")]
 pub(crate) fn try_to_add_to_global(
     global_trade_accounts: &GlobalTradeAccounts,
     resting_order: &RestingOrder,
@@ -186,11 +388,17 @@ pub(crate) fn try_to_add_to_global(
         ..
     } = global_trade_accounts;
 
-    {
+    let asset_share_value: I80F48 = get_global_asset_share_value(global_trade_accounts)?;
+    let gas_deposit_lamports: u64 = {
         let global_data: &mut RefMut<&mut [u8]> = &mut global.try_borrow_mut_data()?;
         let mut global_dynamic_account: GlobalRefMut = get_mut_dynamic_account(global_data);
-        global_dynamic_account.add_order(resting_order, gas_payer_opt.as_ref().unwrap().key)?;
-    }
+        global_dynamic_account.add_order(
+            resting_order,
+            gas_payer_opt.as_ref().unwrap().key,
+            asset_share_value,
+        )?;
+        global_dynamic_account.fixed.get_gas_deposit_lamports()
+    };
 
     // Need to CPI because otherwise we get:
     //
@@ -203,7 +411,7 @@ pub(crate) fn try_to_add_to_global(
         &solana_program::system_instruction::transfer(
             &gas_payer_opt.as_ref().unwrap().info.key,
             &global.key,
-            GAS_DEPOSIT_LAMPORTS,
+            gas_deposit_lamports,
         ),
         &[
             gas_payer_opt.as_ref().unwrap().info.clone(),
@@ -214,6 +422,22 @@ pub(crate) fn try_to_add_to_global(
     Ok(())
 }
 
+/// Atoms a share of the balances of the global is worth. One while the global
+/// is not linked to marginfi.
+#[cfg(feature = "program")]
+fn get_global_asset_share_value(
+    global_trade_accounts: &GlobalTradeAccounts,
+) -> Result<I80F48, ProgramError> {
+    match &global_trade_accounts.marginfi_cpi_accounts_opt {
+        Some(marginfi_cpi_accounts) => Ok(BankShareValues::from(
+            &*marginfi_cpi_accounts.marginfi_bank.get_fixed()?,
+        )
+        .asset_share_value),
+        None => Ok(I80F48::ONE),
+    }
+}
+
+#[cfg(feature = "program")]
 pub(crate) fn assert_not_already_expired(last_valid_slot: u32, now_slot: u32) -> ProgramResult {
     require!(
         last_valid_slot == NO_EXPIRATION_LAST_VALID_SLOT || last_valid_slot > now_slot,
@@ -225,6 +449,23 @@ pub(crate) fn assert_not_already_expired(last_valid_slot: u32, now_slot: u32) ->
     Ok(())
 }
 
+#[cfg(feature = "program")]
+pub(crate) fn assert_not_already_expired_at_time(
+    last_valid_unix_timestamp: u32,
+    now_unix_timestamp: i64,
+) -> ProgramResult {
+    require!(
+        last_valid_unix_timestamp == NO_EXPIRATION_LAST_VALID_UNIX_TIMESTAMP
+            || last_valid_unix_timestamp as i64 > now_unix_timestamp,
+        crate::program::NixError::AlreadyExpired,
+        "Placing an already expired order. now: {} last_valid_unix_timestamp: {}",
+        now_unix_timestamp,
+        last_valid_unix_timestamp
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "program")]
 pub(crate) fn assert_valid_order_type(order_type: OrderType, is_bid: bool) -> ProgramResult {
     if is_bid && order_type == OrderType::Global {
         return Err(NixError::InvalidGlobalBidOrder.into());
@@ -232,6 +473,64 @@ pub(crate) fn assert_valid_order_type(order_type: OrderType, is_bid: bool) -> Pr
     if !is_bid && order_type == OrderType::Reverse {
         return Err(NixError::InvalidAskReverseOrder.into());
     }
+    // Loan sales need a loan, they only come from PlaceLoanSale.
+    if order_type == OrderType::LoanSale {
+        return Err(NixError::InvalidLoanSale.into());
+    }
+    Ok(())
+}
+/// Orders on a market with an allowed caller have to come from a CPI by that
+/// program. The instructions sysvar gives the program of the top level
+/// instruction, and a stack height above it means nix was invoked from there.
+#[cfg(feature = "program")]
+pub(crate) fn assert_allowed_caller(
+    market_fixed: &MarketFixed,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    if !market_fixed.has_allowed_caller() {
+        return Ok(());
+    }
+    let instructions_sysvar_opt: Option<&AccountInfo> = accounts
+        .iter()
+        .find(|account| *account.key == instructions::ID);
+    require!(
+        instructions_sysvar_opt.is_some(),
+        NixError::MissingAccounts,
+        "Missing the instructions sysvar, orders need a CPI from {}",
+        market_fixed.get_allowed_caller(),
+    )?;
+    let instructions_sysvar: &AccountInfo = instructions_sysvar_opt.unwrap();
+    let current_index: u16 = instructions::load_current_index_checked(instructions_sysvar)?;
+    let top_level_program_id: Pubkey =
+        instructions::load_instruction_at_checked(current_index as usize, instructions_sysvar)?
+            .program_id;
+    require!(
+        get_stack_height() > TRANSACTION_LEVEL_STACK_HEIGHT
+            && top_level_program_id == *market_fixed.get_allowed_caller(),
+        NixError::CallerNotAllowed,
+        "Orders need a CPI from {}, called from {}",
+        market_fixed.get_allowed_caller(),
+        top_level_program_id,
+    )?;
+    Ok(())
+}
+
+#[cfg(feature = "program")]
+pub(crate) fn assert_valid_reverse_spread(
+    reverse_spread_bps: u16,
+    min_reverse_spread_bps: u16,
+    max_reverse_spread_bps: u16,
+) -> ProgramResult {
+    require!(
+        reverse_spread_bps < 10_000
+            && reverse_spread_bps >= min_reverse_spread_bps
+            && reverse_spread_bps <= max_reverse_spread_bps,
+        crate::program::NixError::InvalidReverseSpread,
+        "Invalid reverse spread {}. min: {} max: {}",
+        reverse_spread_bps,
+        min_reverse_spread_bps,
+        max_reverse_spread_bps
+    )?;
     Ok(())
 }
 pub(crate) fn assert_already_has_seat(trader_index: DataIndex) -> ProgramResult {
@@ -243,12 +542,17 @@ pub(crate) fn assert_already_has_seat(trader_index: DataIndex) -> ProgramResult
     Ok(())
 }
 
+#[cfg(feature = "program")]
 pub(crate) fn try_to_move_global_tokens<'a, 'info>(
     global_trade_accounts_opt: &'a Option<GlobalTradeAccounts<'a, 'info>>,
     mint: &'a MintAccountInfo<'a, 'info>,
     resting_order_trader: &Pubkey,
     desired_global_atoms: u64,
-) -> Result<bool, ProgramError> {
+    remaining_accounts: &'a [AccountInfo<'a>],
+) -> Result<bool, ProgramError>
+where
+    'a: 'info,
+{
     require!(
         global_trade_accounts_opt.is_some(),
         crate::program::NixError::MissingGlobal,
@@ -261,15 +565,18 @@ pub(crate) fn try_to_move_global_tokens<'a, 'info>(
         gas_receiver_opt,
         market_vault_opt,
         token_program_opt,
+        marginfi_cpi_accounts_opt,
         ..
     } = global_trade_accounts;
 
+    let asset_share_value: I80F48 = get_global_asset_share_value(global_trade_accounts)?;
     let global_data: &mut RefMut<&mut [u8]> = &mut global.try_borrow_mut_data()?;
     let mut global_dynamic_account: GlobalRefMut = get_mut_dynamic_account(global_data);
 
-    let num_deposited_atoms: I80F48 = global_dynamic_account
-        .get_balance_atoms(resting_order_trader)
-        .into();
+    let num_deposited_atoms: I80F48 =
+        I80F48::from(global_dynamic_account.get_balance_shares(resting_order_trader))
+            .checked_mul(asset_share_value)
+            .ok_or(NixError::NumericalOverflow)?;
     // Intentionally does not allow partial fills against a global order. The
     // reason for this is to punish global orders that are not backed. There is
     // no technical blocker for supporting partial fills against a global. It is
@@ -286,10 +593,7 @@ pub(crate) fn try_to_move_global_tokens<'a, 'info>(
         return Ok(false);
     }
 
-    // Update the GlobalTrader
-    global_dynamic_account.reduce(resting_order_trader, desired_global_atoms_i80f48)?;
-
-    let mint_key: &Pubkey = global_dynamic_account.fixed.get_mint();
+    let mint_key: Pubkey = *global_dynamic_account.fixed.get_mint();
 
     let global_vault_bump: u8 = global_dynamic_account.fixed.get_vault_bump();
 
@@ -297,7 +601,8 @@ pub(crate) fn try_to_move_global_tokens<'a, 'info>(
     let market_vault: &TokenAccountInfo<'a, 'info> = market_vault_opt.as_ref().unwrap();
     let token_program: &TokenProgram<'a, 'info> = token_program_opt.as_ref().unwrap();
 
-    if *token_program.key == spl_token_2022::id() {
+    let is_token_22: bool = *token_program.key == spl_token_2022::id();
+    if is_token_22 {
         // Prevent transfer from global to market vault if a token has a non-zero fee.
         let mint_account_info: &MintAccountInfo = &mint;
         if StateWithExtensions::<Mint>::unpack(&mint_account_info.info.data.borrow())?
@@ -307,33 +612,46 @@ pub(crate) fn try_to_move_global_tokens<'a, 'info>(
             solana_program::msg!("Treating global order as unbacked because it has a transfer fee");
             return Ok(false);
         }
-        if StateWithExtensions::<Mint>::unpack(&mint_account_info.info.data.borrow())?
-            .get_extension::<TransferHook>()
-            .is_ok_and(|f| f.program_id.0 != Pubkey::default())
-        {
-            solana_program::msg!(
-                "Treating global order as unbacked because it has a transfer hook"
-            );
-            return Ok(false);
-        }
+    }
 
-        invoke_signed(
-            &spl_token_2022::instruction::transfer_checked(
-                token_program.key,
-                global_vault.key,
-                mint_account_info.info.key,
-                market_vault.key,
-                global_vault.key,
-                &[],
-                desired_global_atoms,
-                mint_account_info.mint.decimals,
-            )?,
-            &[
-                token_program.as_ref().clone(),
-                global_vault.as_ref().clone(),
-                mint_account_info.as_ref().clone(),
-                market_vault.as_ref().clone(),
-            ],
+    if let Some(marginfi_cpi_accounts) = marginfi_cpi_accounts_opt {
+        // Marginfi rounds the shares it burns, so the balance is reduced by
+        // what the withdraw actually burned.
+        let asset_shares_before: I80F48 = get_marginfi_asset_shares(marginfi_cpi_accounts)?;
+        cpi_marginfi_withdraw_to(
+            marginfi_cpi_accounts,
+            market_vault,
+            token_program,
+            desired_global_atoms,
+            if is_token_22 { Some(mint) } else { None },
+            global_vault.clone(),
+            &mut MarginfiCpiScratch::default(),
+            global_vault_seeds_with_bump!(mint_key, global_vault_bump),
+            remaining_accounts,
+        )?;
+        let burned_shares: I80F48 = asset_shares_before
+            .checked_sub(get_marginfi_asset_shares(marginfi_cpi_accounts)?)
+            .ok_or(NixError::NumericalOverflow)?;
+        global_dynamic_account.reduce(resting_order_trader, burned_shares)?;
+        return Ok(true);
+    }
+
+    // Update the GlobalTrader
+    global_dynamic_account.reduce(resting_order_trader, desired_global_atoms_i80f48)?;
+
+    if is_token_22 {
+        // Transfer hook accounts, if the mint has a hook, are found by key
+        // among the remaining accounts.
+        let mint_account_info: &MintAccountInfo = &mint;
+        spl_token_2022::onchain::invoke_transfer_checked(
+            token_program.key,
+            global_vault.as_ref().clone(),
+            mint_account_info.as_ref().clone(),
+            market_vault.as_ref().clone(),
+            global_vault.as_ref().clone(),
+            remaining_accounts,
+            desired_global_atoms,
+            mint_account_info.mint.decimals,
             global_vault_seeds_with_bump!(mint_key, global_vault_bump),
         )?;
     } else {
@@ -358,11 +676,164 @@ pub(crate) fn try_to_move_global_tokens<'a, 'info>(
     Ok(true)
 }
 
+/// Seconds in a year, as spl-token-2022 counts them for interest-bearing mints.
+const SECONDS_PER_YEAR: f64 = 60.0 * 60.0 * 24.0 * 365.24;
+const ONE_IN_BASIS_POINTS: f64 = 10_000.0;
+
+/// ExtensionType::ScaledUiAmount. The spl-token-2022 version here predates it,
+/// so it is read from the TLV data directly.
+const SCALED_UI_AMOUNT_EXTENSION_TYPE: u16 = 25;
+/// Extensions of a mint start after the base mint padded to the length of a
+/// token account, plus the account type byte.
+const MINT_EXTENSIONS_START: usize = 166;
+
+/// How many UI tokens one token of the mint, 10^decimals atoms, is worth
+/// right now. Oracles price UI tokens, so for interest-bearing and scaled-ui
+/// mints the price of an atom is the oracle price times this. One for every
+/// other mint.
+#[cfg(feature = "program")]
+pub(crate) fn get_ui_amount_multiplier(
+    mint: &MintAccountInfo,
+    unix_timestamp: i64,
+) -> Result<I80F48, ProgramError> {
+    if *mint.info.owner != spl_token_2022::id() {
+        return Ok(I80F48::ONE);
+    }
+    let mint_data = mint.info.data.borrow();
+    let multiplier: f64 = if let Ok(config) =
+        StateWithExtensions::<Mint>::unpack(&mint_data)?.get_extension::<InterestBearingConfig>()
+    {
+        get_interest_bearing_multiplier(
+            i64::from(config.initialization_timestamp),
+            i16::from(config.pre_update_average_rate),
+            i64::from(config.last_update_timestamp),
+            i16::from(config.current_rate),
+            unix_timestamp,
+        )
+    } else if let Some(multiplier) = get_scaled_ui_amount_multiplier(&mint_data, unix_timestamp) {
+        multiplier
+    } else {
+        return Ok(I80F48::ONE);
+    };
+    require!(
+        multiplier.is_finite() && multiplier > 0.0,
+        NixError::NumericalOverflow,
+        "Invalid UI amount multiplier {} of mint {}",
+        multiplier,
+        mint.info.key,
+    )?;
+    I80F48::checked_from_num(multiplier).ok_or_else(|| NixError::NumericalOverflow.into())
+}
+
+/// Interest an interest-bearing mint has compounded continuously since it
+/// was initialized, at the average rate up to the last rate update and the
+/// current rate after.
+pub fn get_interest_bearing_multiplier(
+    initialization_timestamp: i64,
+    pre_update_average_rate_bps: i16,
+    last_update_timestamp: i64,
+    current_rate_bps: i16,
+    unix_timestamp: i64,
+) -> f64 {
+    let pre_update_seconds: i64 = last_update_timestamp.saturating_sub(initialization_timestamp);
+    let post_update_seconds: i64 = unix_timestamp.saturating_sub(last_update_timestamp);
+    let pre_update_exponent: f64 = f64::from(pre_update_average_rate_bps) / ONE_IN_BASIS_POINTS
+        * pre_update_seconds as f64
+        / SECONDS_PER_YEAR;
+    let post_update_exponent: f64 = f64::from(current_rate_bps) / ONE_IN_BASIS_POINTS
+        * post_update_seconds as f64
+        / SECONDS_PER_YEAR;
+    pre_update_exponent.exp() * post_update_exponent.exp()
+}
+
+/// Multiplier of the ScaledUiAmount extension in the data of a mint, None
+/// without one. The new multiplier applies from its effective timestamp.
+pub fn get_scaled_ui_amount_multiplier(mint_data: &[u8], unix_timestamp: i64) -> Option<f64> {
+    let mut offset: usize = MINT_EXTENSIONS_START;
+    while let Some(header) = mint_data.get(offset..offset + 4) {
+        let extension_type: u16 = u16::from_le_bytes([header[0], header[1]]);
+        let length: usize = u16::from_le_bytes([header[2], header[3]]) as usize;
+        // Uninitialized is the padding after the last extension.
+        if extension_type == 0 {
+            return None;
+        }
+        if extension_type == SCALED_UI_AMOUNT_EXTENSION_TYPE {
+            // Authority, multiplier, new multiplier effective timestamp and
+            // new multiplier.
+            let value: &[u8] = mint_data.get(offset + 4..offset + 4 + 56)?;
+            let multiplier: f64 = f64::from_le_bytes(value[32..40].try_into().ok()?);
+            let new_multiplier_effective_timestamp: i64 =
+                i64::from_le_bytes(value[40..48].try_into().ok()?);
+            let new_multiplier: f64 = f64::from_le_bytes(value[48..56].try_into().ok()?);
+            return Some(if unix_timestamp >= new_multiplier_effective_timestamp {
+                new_multiplier
+            } else {
+                multiplier
+            });
+        }
+        offset += 4 + length;
+    }
+    None
+}
+
 #[test]
 fn test_get_discriminant() {
-    //TODO: Update this when updating program id.
-    assert_eq!(
-        get_discriminant::<crate::state::MarketFixed>().unwrap(),
-        5986819525067784620
-    );
+    use crate::state::{
+        CrossMarginSeat, EventQueueFixed, GlobalFixed, MarketAuction, MarketFixed,
+        MarketInsurance, MarketLoansFixed, MarketRegistryFixed, MarketStats, PendingOrder,
+    };
+
+    // Pinned so a change to a name, or to how it is hashed, fails here instead
+    // of on every account on chain. Update when updating the program id.
+    fn assert_discriminants<T: StableName>(stable: u64, legacy: u64) {
+        assert_eq!(get_discriminant::<T>().unwrap(), stable);
+        assert_eq!(get_legacy_discriminant::<T>().unwrap(), legacy);
+        assert!(is_discriminant_of::<T>(stable));
+        assert!(is_discriminant_of::<T>(legacy));
+    }
+    assert_discriminants::<MarketFixed>(2349467196129130655, 4906530231437361602);
+    assert_discriminants::<GlobalFixed>(13948126568719662700, 4802274601682139540);
+    assert_discriminants::<MarketLoansFixed>(13099261320757664491, 11651541196072575385);
+    assert_discriminants::<EventQueueFixed>(14076671290428172254, 9036418045880108345);
+    assert_discriminants::<CrossMarginSeat>(759346222890420501, 14029059180953379835);
+    assert_discriminants::<MarketRegistryFixed>(77328603830129926, 4339101913240899755);
+    assert_discriminants::<MarketStats>(14120788287968389516, 10466993672638065514);
+    assert_discriminants::<PendingOrder>(170878407713063003, 1639711156022964609);
+    assert_discriminants::<MarketAuction>(7702695873392813168, 4813805404320215631);
+    assert_discriminants::<MarketInsurance>(2299126310702072435, 5247481911852486690);
+    assert!(!is_discriminant_of::<MarketFixed>(
+        get_discriminant::<GlobalFixed>().unwrap()
+    ));
+}
+
+#[test]
+fn test_get_interest_bearing_multiplier() {
+    let year: i64 = SECONDS_PER_YEAR as i64;
+    assert_eq!(get_interest_bearing_multiplier(0, 0, 0, 0, year), 1.0);
+    // 5% for a year then 10% for a year, compounded continuously.
+    let multiplier: f64 = get_interest_bearing_multiplier(0, 500, year, 1_000, 2 * year);
+    assert!((multiplier - 0.15_f64.exp()).abs() < 1e-9);
+    // Negative rates shrink the UI amount.
+    assert!(get_interest_bearing_multiplier(0, 0, 0, -500, year) < 1.0);
+}
+
+#[test]
+fn test_get_scaled_ui_amount_multiplier() {
+    let mut mint_data: Vec<u8> = vec![0; MINT_EXTENSIONS_START];
+    // A mint close authority before it, to check the walk over extensions.
+    mint_data.extend_from_slice(&3_u16.to_le_bytes());
+    mint_data.extend_from_slice(&32_u16.to_le_bytes());
+    mint_data.extend_from_slice(&[7; 32]);
+    assert_eq!(get_scaled_ui_amount_multiplier(&mint_data, 0), None);
+
+    mint_data.extend_from_slice(&SCALED_UI_AMOUNT_EXTENSION_TYPE.to_le_bytes());
+    mint_data.extend_from_slice(&56_u16.to_le_bytes());
+    mint_data.extend_from_slice(&[0; 32]);
+    mint_data.extend_from_slice(&1.5_f64.to_le_bytes());
+    mint_data.extend_from_slice(&100_i64.to_le_bytes());
+    mint_data.extend_from_slice(&3.0_f64.to_le_bytes());
+    assert_eq!(get_scaled_ui_amount_multiplier(&mint_data, 99), Some(1.5));
+    assert_eq!(get_scaled_ui_amount_multiplier(&mint_data, 100), Some(3.0));
+    // A plain mint has no extensions at all.
+    assert_eq!(get_scaled_ui_amount_multiplier(&[0; 82], 0), None);
 }
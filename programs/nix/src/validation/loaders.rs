@@ -11,24 +11,463 @@ use solana_program::{
 };
 
 use crate::{
-    program::NixError,
-    require,
-    state::{market_loan::MarketLoansFixed, GlobalFixed, MarketFixed},
+    logs::with_error_log,
+    program::{NixError, NixInstruction},
+    require, require_account,
+    state::{
+        market_loan::MarketLoansFixed, EventQueueFixed, GlobalFixed, MarketAuction, MarketFixed,
+        MarketInsurance, MarketRegistryFixed, MarketStats,
+    },
     validation::{
-        validate_marginfi_liquidity_vault, validate_marginfi_liquidity_vault_authority,
-        MarketSigner,
+        get_cross_margin_seat_address, get_marginfi_liquidity_vault_authority,
+        get_market_auction_address, get_market_insurance_address, get_market_stats_address,
+        validate_cross_margin_account, validate_marginfi_liquidity_vault,
+        validate_marginfi_liquidity_vault_authority, MarketSigner,
     },
 };
 
 use super::{
-    get_market_fee_receiver_address, get_vault_address, EmptyAccount, MarginfiAccountInfo,
-    MintAccountInfo, NixAccountInfo, Program, Signer, TokenAccountInfo, TokenProgram,
+    get_market_address, get_market_fee_receiver_address, get_market_registry_address,
+    get_vault_address, verify_owned_by_nix, EmptyAccount, MarginfiAccountInfo, MintAccountInfo,
+    NixAccountInfo, Program, Signer, TokenAccountInfo, TokenProgram,
 };
 use std::{cell::Ref, slice::Iter};
+
+/// Index in the instruction accounts of the account last taken from the
+/// iterator.
+fn last_account_index(accounts: &[AccountInfo], account_iter: &Iter<AccountInfo>) -> usize {
+    accounts.len() - account_iter.len() - 1
+}
+
+/// Signer and writable flags an instruction expects on one of its accounts.
+#[derive(Clone, Copy)]
+pub(crate) struct AccountSlot {
+    pub signer: bool,
+    pub writable: bool,
+}
+
+impl AccountSlot {
+    const READONLY: Self = Self {
+        signer: false,
+        writable: false,
+    };
+    const WRITABLE: Self = Self {
+        signer: false,
+        writable: true,
+    };
+    const SIGNER: Self = Self {
+        signer: true,
+        writable: false,
+    };
+    const WRITABLE_SIGNER: Self = Self {
+        signer: true,
+        writable: true,
+    };
+}
+
+/// Fixed accounts of each instruction, in the order its loader takes them.
+/// Instructions that share a loader share its slots. The IDL is checked
+/// against this table.
+pub(crate) fn get_account_slots(instruction: NixInstruction) -> &'static [AccountSlot] {
+    match instruction {
+        NixInstruction::CreateMarket | NixInstruction::CreateMarketPda => &[
+            AccountSlot::WRITABLE_SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::CreateMarketLoanAccount => &[
+            AccountSlot::SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::ClaimSeat => &[
+            AccountSlot::SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::Deposit
+        | NixInstruction::TopUpCollateral
+        | NixInstruction::TopUpInsurance => &[
+            AccountSlot::WRITABLE_SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+        ],
+        NixInstruction::GlobalCreate => &[
+            AccountSlot::WRITABLE_SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::GlobalAddTrader => &[
+            AccountSlot::WRITABLE_SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::GlobalDeposit => &[
+            AccountSlot::SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::PlaceOrder
+        | NixInstruction::PlaceOrderBaseA
+        | NixInstruction::PlaceOrderBaseB
+        | NixInstruction::PlaceOrderSmart
+        | NixInstruction::ContinueOrder => &[
+            AccountSlot::SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::CancelOrder | NixInstruction::CleanExpiredOrders => &[
+            AccountSlot::SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::ReferrerClaim => &[AccountSlot::SIGNER, AccountSlot::WRITABLE],
+        NixInstruction::ClaimMakerRebate => &[AccountSlot::SIGNER, AccountSlot::WRITABLE],
+        NixInstruction::CloseMarket => &[
+            AccountSlot::WRITABLE_SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+        ],
+        NixInstruction::EmitBookSnapshot => &[AccountSlot::WRITABLE],
+        NixInstruction::QuoteOrder => &[
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::CreateEventQueue => &[
+            AccountSlot::SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+        ],
+        NixInstruction::ConsumeEvents => &[
+            AccountSlot::SIGNER,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+        ],
+        NixInstruction::GlobalEvict => &[
+            AccountSlot::SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::MigrateMarket => &[
+            AccountSlot::WRITABLE_SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::SweepStrandedGas => &[
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+        ],
+        NixInstruction::ReduceOrder => &[AccountSlot::SIGNER, AccountSlot::WRITABLE],
+        NixInstruction::CreateCrossMarginSeat => &[
+            AccountSlot::WRITABLE_SIGNER,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::PlaceLoanSale => &[
+            AccountSlot::WRITABLE_SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::CreateMarketStats => &[
+            AccountSlot::WRITABLE_SIGNER,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::ModifyOrder => &[AccountSlot::SIGNER, AccountSlot::WRITABLE],
+        NixInstruction::SetCircuitBreaker => &[AccountSlot::SIGNER, AccountSlot::WRITABLE],
+        NixInstruction::CreateMarketAuction => &[
+            AccountSlot::WRITABLE_SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::RunAuction => &[
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::ReleaseCollateral => &[
+            AccountSlot::WRITABLE_SIGNER,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::MigrateGlobal => &[
+            AccountSlot::WRITABLE_SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::SettleLoanProceeds => &[
+            AccountSlot::SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+        ],
+        NixInstruction::CheckHealth => &[
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::MarkDefault => &[
+            AccountSlot::SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::CreateMarketInsurance => &[
+            AccountSlot::WRITABLE_SIGNER,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::SetInsuranceFee => &[
+            AccountSlot::SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+        ],
+        NixInstruction::DrawInsurance => &[
+            AccountSlot::WRITABLE_SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::SetOrderRateLimit => &[AccountSlot::SIGNER, AccountSlot::WRITABLE],
+        NixInstruction::HarvestEmissions => &[
+            AccountSlot::WRITABLE_SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::GlobalLinkMarginfi => &[
+            AccountSlot::WRITABLE_SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+            AccountSlot::READONLY,
+        ],
+        NixInstruction::SetAllowedCaller => &[AccountSlot::SIGNER, AccountSlot::WRITABLE],
+        NixInstruction::SetDebugLogs => &[AccountSlot::SIGNER, AccountSlot::WRITABLE],
+        NixInstruction::AcknowledgeBankConfigChange => &[
+            AccountSlot::SIGNER,
+            AccountSlot::WRITABLE,
+            AccountSlot::READONLY,
+        ],
+        // Each leg is loaded as a PlaceOrder on its own accounts.
+        NixInstruction::RouteOrders => &[],
+    }
+}
+
+/// Checks the fixed accounts of an instruction before any of them is
+/// deserialized, so a malformed transaction fails on the first bad slot with
+/// an error naming it. Optional trailing accounts are left to the loader.
+fn verify_account_slots(
+    accounts: &[AccountInfo],
+    instruction: NixInstruction,
+) -> Result<(), ProgramError> {
+    verify_account_slots_with_placeholders(accounts, instruction, &[])
+}
+
+/// Same as verify_account_slots, the slots at `placeholder_indexes` hold the
+/// system program in place of an account the instruction does without. The
+/// system program cannot be writable, so those slots are readonly.
+fn verify_account_slots_with_placeholders(
+    accounts: &[AccountInfo],
+    instruction: NixInstruction,
+    placeholder_indexes: &[usize],
+) -> Result<(), ProgramError> {
+    let slots: &[AccountSlot] = get_account_slots(instruction);
+    let get_slot = |index: usize| -> AccountSlot {
+        if placeholder_indexes.contains(&index) {
+            AccountSlot::READONLY
+        } else {
+            slots[index]
+        }
+    };
+    require_account!(
+        accounts.len() >= slots.len(),
+        NixError::MissingAccounts,
+        instruction,
+        accounts.len(),
+        "Expected at least {} accounts, got {}",
+        slots.len(),
+        accounts.len(),
+    )?;
+    for (index, info) in accounts.iter().take(slots.len()).enumerate() {
+        let slot: AccountSlot = get_slot(index);
+        require_account!(
+            info.is_signer || !slot.signer,
+            NixError::AccountNotSigner,
+            instruction,
+            index,
+            "Account {} at index {} has to sign",
+            info.key,
+            index,
+        )?;
+        require_account!(
+            info.is_writable || !slot.writable,
+            NixError::AccountNotWritable,
+            instruction,
+            index,
+            "Account {} at index {} has to be writable",
+            info.key,
+            index,
+        )?;
+        // Readonly accounts like programs and mints can repeat, an account
+        // that gets written to cannot stand in for a second slot.
+        for (other_index, other_info) in accounts[..index].iter().enumerate() {
+            require_account!(
+                info.key != other_info.key || !(slot.writable || get_slot(other_index).writable),
+                NixError::DuplicateAccount,
+                instruction,
+                index,
+                "Account {} at index {} is already passed in",
+                info.key,
+                index,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the accounts an instruction writes to or measures balances
+/// on are all different. verify_account_slots only sees the fixed slots, so
+/// this is needed once appended accounts are loaded, e.g. a global vault
+/// that is also passed as the market vault would make the vault deltas
+/// around a transfer meaningless.
+fn verify_distinct_roles(
+    accounts: &[AccountInfo],
+    instruction: NixInstruction,
+    roles: &[&AccountInfo],
+) -> Result<(), ProgramError> {
+    for (role_index, info) in roles.iter().enumerate() {
+        // The later position is the one standing in for a second role.
+        let index: usize = accounts
+            .iter()
+            .rposition(|account| account.key == info.key)
+            .unwrap_or(accounts.len());
+        for other_info in roles[..role_index].iter() {
+            require_account!(
+                info.key != other_info.key,
+                NixError::DuplicateAccount,
+                instruction,
+                index,
+                "Account {} at index {} is passed in for more than one role",
+                info.key,
+                index,
+            )?;
+        }
+    }
+    Ok(())
+}
+
 /// CreateMarket account infos
 pub(crate) struct CreateMarketContext<'a, 'info> {
     pub admin: Signer<'a, 'info>,
     pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub market_loans: NixAccountInfo<'a, 'info, MarketLoansFixed>,
     pub market_signer: MarketSigner<'a, 'info>,
     pub base_a_mint: MintAccountInfo<'a, 'info>,
     pub base_b_mint: MintAccountInfo<'a, 'info>,
@@ -36,24 +475,122 @@ pub(crate) struct CreateMarketContext<'a, 'info> {
     pub base_b_fee_receiver: EmptyAccount<'a, 'info>,
     pub base_a_vault: EmptyAccount<'a, 'info>,
     pub base_b_vault: EmptyAccount<'a, 'info>,
-    pub base_a_marginfi_group: MarginfiAccountInfo<'a, 'info, MarginfiGroup>,
-    pub base_a_marginfi_bank: MarginfiAccountInfo<'a, 'info, Bank>,
-    pub base_a_marginfi_account: MarginfiAccountInfo<'a, 'info, MarginfiAccount>,
-    pub base_b_marginfi_group: MarginfiAccountInfo<'a, 'info, MarginfiGroup>,
-    pub base_b_marginfi_bank: MarginfiAccountInfo<'a, 'info, Bank>,
-    pub base_b_marginfi_account: MarginfiAccountInfo<'a, 'info, MarginfiAccount>,
+    /// None on the side of a one sided market that has no marginfi bank.
+    pub base_a_marginfi_group: Option<MarginfiAccountInfo<'a, 'info, MarginfiGroup>>,
+    pub base_a_marginfi_bank: Option<MarginfiAccountInfo<'a, 'info, Bank>>,
+    pub base_a_marginfi_account: Option<MarginfiAccountInfo<'a, 'info, MarginfiAccount>>,
+    pub base_b_marginfi_group: Option<MarginfiAccountInfo<'a, 'info, MarginfiGroup>>,
+    pub base_b_marginfi_bank: Option<MarginfiAccountInfo<'a, 'info, Bank>>,
+    pub base_b_marginfi_account: Option<MarginfiAccountInfo<'a, 'info, MarginfiAccount>>,
     pub system_program: Program<'a, 'info>,
     pub token_program: TokenProgram<'a, 'info>,
     pub token_program_22: TokenProgram<'a, 'info>,
 }
 
+/// Index of the first marginfi account of each side in CreateMarket.
+const CREATE_MARKET_BASE_A_MARGINFI_INDEX: usize = 10;
+const CREATE_MARKET_BASE_B_MARGINFI_INDEX: usize = 13;
+
+/// A side of a one sided market passes the system program in place of its
+/// group, bank and marginfi account.
+fn is_marginfi_side_missing(accounts: &[AccountInfo], first_index: usize) -> bool {
+    accounts
+        .get(first_index + 1)
+        .is_some_and(|bank| *bank.key == system_program::id())
+}
+
+/// Loads the group, bank and uninitialized marginfi account of one side of
+/// CreateMarket, or checks the placeholders of a side without a bank.
+#[allow(clippy::type_complexity)]
+fn next_create_market_marginfi_side<'a, 'info>(
+    account_iter: &mut Iter<'a, AccountInfo<'info>>,
+    market: &'a AccountInfo<'info>,
+    mint: &'a AccountInfo<'info>,
+    is_missing: bool,
+) -> Result<
+    (
+        Option<MarginfiAccountInfo<'a, 'info, MarginfiGroup>>,
+        Option<MarginfiAccountInfo<'a, 'info, Bank>>,
+        Option<MarginfiAccountInfo<'a, 'info, MarginfiAccount>>,
+    ),
+    ProgramError,
+> {
+    if is_missing {
+        for _ in 0..3 {
+            let placeholder: &AccountInfo = next_account_info(account_iter)?;
+            require!(
+                *placeholder.key == system_program::id(),
+                NixError::IncorrectAccount,
+                "Expected the system program in place of the marginfi accounts of {}, got {}",
+                mint.key,
+                placeholder.key,
+            )?;
+        }
+        return Ok((None, None, None));
+    }
+    let marginfi_group: MarginfiAccountInfo<MarginfiGroup> =
+        MarginfiAccountInfo::<MarginfiGroup>::new_group(next_account_info(account_iter)?)?;
+    let marginfi_bank: MarginfiAccountInfo<Bank> =
+        MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
+    let marginfi_account: MarginfiAccountInfo<MarginfiAccount> =
+        MarginfiAccountInfo::<MarginfiAccount>::new_account_uninitialized(
+            next_account_info(account_iter)?,
+            market,
+            mint,
+        )?;
+    Ok((
+        Some(marginfi_group),
+        Some(marginfi_bank),
+        Some(marginfi_account),
+    ))
+}
+
+/// Slots of CreateMarket, which CreateMarketPda shares, that hold the
+/// system program. A side without a marginfi bank passes it in place of its
+/// marginfi account.
+fn get_create_market_placeholder_indexes(
+    accounts: &[AccountInfo],
+) -> Result<Vec<usize>, ProgramError> {
+    let is_base_a_missing: bool =
+        is_marginfi_side_missing(accounts, CREATE_MARKET_BASE_A_MARGINFI_INDEX);
+    let is_base_b_missing: bool =
+        is_marginfi_side_missing(accounts, CREATE_MARKET_BASE_B_MARGINFI_INDEX);
+    require!(
+        !(is_base_a_missing && is_base_b_missing),
+        NixError::InvalidMarketParameters,
+        "A market needs a marginfi bank on at least one side",
+    )?;
+    Ok([
+        (is_base_a_missing, CREATE_MARKET_BASE_A_MARGINFI_INDEX),
+        (is_base_b_missing, CREATE_MARKET_BASE_B_MARGINFI_INDEX),
+    ]
+    .into_iter()
+    .filter(|(is_missing, _)| *is_missing)
+    .map(|(_, first_index)| first_index + 2)
+    .collect())
+}
+
 impl<'a, 'info> CreateMarketContext<'a, 'info> {
     pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        let placeholder_indexes: Vec<usize> = get_create_market_placeholder_indexes(accounts)?;
+        verify_account_slots_with_placeholders(
+            accounts,
+            NixInstruction::CreateMarket,
+            &placeholder_indexes,
+        )?;
+        let is_base_a_missing: bool =
+            is_marginfi_side_missing(accounts, CREATE_MARKET_BASE_A_MARGINFI_INDEX);
+        let is_base_b_missing: bool =
+            is_marginfi_side_missing(accounts, CREATE_MARKET_BASE_B_MARGINFI_INDEX);
         let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
 
         let admin: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
+        // Either a keypair account allocated by the client or the canonical
+        // PDA, which CreateMarketPda allocates before getting here.
         let market: NixAccountInfo<MarketFixed> =
             NixAccountInfo::<MarketFixed>::new_init(next_account_info(account_iter)?)?;
+        let market_loans: NixAccountInfo<MarketLoansFixed> =
+            NixAccountInfo::<MarketLoansFixed>::new_init(next_account_info(account_iter)?)?;
         let market_signer = MarketSigner::new(next_account_info(account_iter)?, market.key)?;
 
         let base_a_mint: MintAccountInfo = MintAccountInfo::new(next_account_info(account_iter)?)?;
@@ -65,30 +602,19 @@ impl<'a, 'info> CreateMarketContext<'a, 'info> {
         let base_a_vault: EmptyAccount = EmptyAccount::new(next_account_info(account_iter)?)?;
         let base_b_vault: EmptyAccount = EmptyAccount::new(next_account_info(account_iter)?)?;
 
-        let base_a_marginfi_group: MarginfiAccountInfo<MarginfiGroup> =
-            MarginfiAccountInfo::<MarginfiGroup>::new_group(next_account_info(account_iter)?)?;
-
-        let base_a_marginfi_bank: MarginfiAccountInfo<Bank> =
-            MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
-
-        let base_a_marginfi_account: MarginfiAccountInfo<MarginfiAccount> =
-            MarginfiAccountInfo::<MarginfiAccount>::new_account_uninitialized(
-                next_account_info(account_iter)?,
+        let (base_a_marginfi_group, base_a_marginfi_bank, base_a_marginfi_account) =
+            next_create_market_marginfi_side(
+                account_iter,
                 market.info,
                 base_a_mint.info,
+                is_base_a_missing,
             )?;
-
-        let base_b_marginfi_group: MarginfiAccountInfo<MarginfiGroup> =
-            MarginfiAccountInfo::<MarginfiGroup>::new_group(next_account_info(account_iter)?)?;
-
-        let base_b_marginfi_bank: MarginfiAccountInfo<Bank> =
-            MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
-
-        let base_b_marginfi_account: MarginfiAccountInfo<MarginfiAccount> =
-            MarginfiAccountInfo::<MarginfiAccount>::new_account_uninitialized(
-                next_account_info(account_iter)?,
+        let (base_b_marginfi_group, base_b_marginfi_bank, base_b_marginfi_account) =
+            next_create_market_marginfi_side(
+                account_iter,
                 market.info,
-                base_a_mint.info,
+                base_b_mint.info,
+                is_base_b_missing,
             )?;
 
         let system_program: Program =
@@ -97,7 +623,7 @@ impl<'a, 'info> CreateMarketContext<'a, 'info> {
         let (expected_base_a_fee_receiver, _) =
             get_market_fee_receiver_address(market.key, base_a_mint.info.key);
         let (expected_base_b_fee_receiver, _) =
-            get_market_fee_receiver_address(market.key, base_a_mint.info.key);
+            get_market_fee_receiver_address(market.key, base_b_mint.info.key);
         require!(
             expected_base_a_fee_receiver == *base_a_fee_receiver.info.key,
             NixError::IncorrectAccount,
@@ -127,6 +653,7 @@ impl<'a, 'info> CreateMarketContext<'a, 'info> {
         Ok(Self {
             admin,
             market,
+            market_loans,
             market_signer,
             base_a_mint,
             base_b_mint,
@@ -147,6 +674,71 @@ impl<'a, 'info> CreateMarketContext<'a, 'info> {
     }
 }
 
+/// CreateMarketPda account infos. Same accounts as CreateMarket, only the
+/// ones needed to allocate the market PDA are loaded here.
+pub(crate) struct CreateMarketPdaContext<'a, 'info> {
+    pub admin: Signer<'a, 'info>,
+    pub market: EmptyAccount<'a, 'info>,
+    pub system_program: Program<'a, 'info>,
+    pub base_a_mint: MintAccountInfo<'a, 'info>,
+    pub base_b_mint: MintAccountInfo<'a, 'info>,
+}
+
+/// Indexes of the accounts CreateMarketPda needs in the CreateMarket layout.
+const CREATE_MARKET_BASE_A_MINT_INDEX: usize = 4;
+const CREATE_MARKET_BASE_B_MINT_INDEX: usize = 5;
+const CREATE_MARKET_SYSTEM_PROGRAM_INDEX: usize = 16;
+
+impl<'a, 'info> CreateMarketPdaContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>], nonce: u64) -> Result<Self, ProgramError> {
+        let placeholder_indexes: Vec<usize> = get_create_market_placeholder_indexes(accounts)?;
+        verify_account_slots_with_placeholders(
+            accounts,
+            NixInstruction::CreateMarketPda,
+            &placeholder_indexes,
+        )?;
+
+        let admin: Signer = Signer::new_payer(&accounts[0])?;
+        let market: EmptyAccount = EmptyAccount::new(&accounts[1])?;
+        let base_a_mint: MintAccountInfo =
+            MintAccountInfo::new(&accounts[CREATE_MARKET_BASE_A_MINT_INDEX])?;
+        let base_b_mint: MintAccountInfo =
+            MintAccountInfo::new(&accounts[CREATE_MARKET_BASE_B_MINT_INDEX])?;
+        let system_program: Program = Program::new(
+            &accounts[CREATE_MARKET_SYSTEM_PROGRAM_INDEX],
+            &system_program::id(),
+        )?;
+
+        // One ordering per pair so that routers derive a single address.
+        require!(
+            base_a_mint.info.key < base_b_mint.info.key,
+            NixError::InvalidMarketParameters,
+            "Canonical markets need base_a_mint {} < base_b_mint {}",
+            base_a_mint.info.key,
+            base_b_mint.info.key,
+        )?;
+        let (expected_market, _) =
+            get_market_address(base_a_mint.info.key, base_b_mint.info.key, nonce);
+        require_account!(
+            expected_market == *market.info.key,
+            NixError::IncorrectAccount,
+            NixInstruction::CreateMarketPda,
+            1,
+            "Incorrect market PDA expected: {} actual: {}",
+            expected_market,
+            market.info.key,
+        )?;
+
+        Ok(Self {
+            admin,
+            market,
+            system_program,
+            base_a_mint,
+            base_b_mint,
+        })
+    }
+}
+
 /// ClaimSeat account infos
 pub(crate) struct ClaimSeatContext<'a, 'info> {
     pub payer: Signer<'a, 'info>,
@@ -156,6 +748,7 @@ pub(crate) struct ClaimSeatContext<'a, 'info> {
 
 impl<'a, 'info> ClaimSeatContext<'a, 'info> {
     pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::ClaimSeat)?;
         let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
 
         let payer: Signer = Signer::new(next_account_info(account_iter)?)?;
@@ -163,6 +756,20 @@ impl<'a, 'info> ClaimSeatContext<'a, 'info> {
             NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
         let _system_program: Program =
             Program::new(next_account_info(account_iter)?, &system_program::id())?;
+
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        if market_fixed.is_permissioned() {
+            let allowlist_authority: Signer = Signer::new(next_account_info(account_iter)?)?;
+            require!(
+                allowlist_authority.key == market_fixed.get_allowlist_authority(),
+                NixError::NotAllowlisted,
+                "Expected allowlist authority {}, got {}",
+                market_fixed.get_allowlist_authority(),
+                allowlist_authority.key,
+            )?;
+        }
+        drop(market_fixed);
+
         Ok(Self {
             payer,
             market,
@@ -171,554 +778,2948 @@ impl<'a, 'info> ClaimSeatContext<'a, 'info> {
     }
 }
 
-/// Deposit into a market account infos
-pub(crate) struct DepositContext<'a, 'info> {
-    pub payer: Signer<'a, 'info>,
+/// ReferrerClaim account infos
+pub(crate) struct ReferrerClaimContext<'a, 'info> {
+    pub referrer: Signer<'a, 'info>,
     pub market: NixAccountInfo<'a, 'info, MarketFixed>,
-    pub market_signer: MarketSigner<'a, 'info>,
-    pub mint: MintAccountInfo<'a, 'info>,
-    pub trader_token_account: TokenAccountInfo<'a, 'info>,
-    pub token_program: TokenProgram<'a, 'info>,
-    pub vault: TokenAccountInfo<'a, 'info>,
-    pub marginfi_group: MarginfiAccountInfo<'a, 'info, MarginfiGroup>,
-    pub marginfi_bank: MarginfiAccountInfo<'a, 'info, Bank>,
-    pub marginfi_account: MarginfiAccountInfo<'a, 'info, MarginfiAccount>,
-    pub marginfi_liquidity_vault: TokenAccountInfo<'a, 'info>,
 }
 
-impl<'a, 'info> DepositContext<'a, 'info> {
+impl<'a, 'info> ReferrerClaimContext<'a, 'info> {
     pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::ReferrerClaim)?;
         let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
 
-        let payer: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
+        let referrer: Signer = Signer::new(next_account_info(account_iter)?)?;
         let market: NixAccountInfo<MarketFixed> =
             NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        Ok(Self { referrer, market })
+    }
+}
 
-        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
-        let base_a_mint: &Pubkey = market_fixed.get_base_a_mint();
-        let base_b_mint: &Pubkey = market_fixed.get_base_b_mint();
-        let market_signer = MarketSigner::new(next_account_info(account_iter)?, market.key)?;
-        let trader_token_account_info: &AccountInfo<'info> = next_account_info(account_iter)?;
+/// QuoteOrder account infos
+pub(crate) struct QuoteOrderContext<'a, 'info> {
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub base_marginfi_bank: MarginfiAccountInfo<'a, 'info, Bank>,
+    pub quote_marginfi_bank: MarginfiAccountInfo<'a, 'info, Bank>,
+}
 
-        // Infer the mint key from the token account.
-        let (
-            mint,
-            expected_vault_address,
-            expected_marginfi_group,
-            expected_marginfi_bank,
-            expected_marginfi_account,
-        ) = if &trader_token_account_info.try_borrow_data()?[0..32] == base_a_mint.as_ref() {
+impl<'a, 'info> QuoteOrderContext<'a, 'info> {
+    pub fn load(
+        accounts: &'a [AccountInfo<'info>],
+        use_a_tree: bool,
+    ) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::QuoteOrder)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        let (base_bank_key, quote_bank_key) = if use_a_tree {
             (
-                base_a_mint,
-                market_fixed.get_base_a_vault(),
-                market_fixed.get_base_a_marginfi_group(),
-                market_fixed.get_base_a_marginfi_bank(),
-                market_fixed.get_base_a_marginfi_account(),
+                *market_fixed.get_base_a_marginfi_bank(),
+                *market_fixed.get_base_b_marginfi_bank(),
             )
-        } else if &trader_token_account_info.try_borrow_data()?[0..32] == base_b_mint.as_ref() {
+        } else {
             (
-                base_b_mint,
-                market_fixed.get_base_b_vault(),
-                market_fixed.get_base_b_marginfi_group(),
-                market_fixed.get_base_b_marginfi_bank(),
-                market_fixed.get_base_b_marginfi_account(),
+                *market_fixed.get_base_b_marginfi_bank(),
+                *market_fixed.get_base_a_marginfi_bank(),
             )
-        } else {
-            return Err(NixError::InvalidDepositAccounts.into());
         };
+        drop(market_fixed);
 
-        trace!("trader token account {:?}", trader_token_account_info.key);
-        let trader_token_account: TokenAccountInfo =
-            TokenAccountInfo::new_with_owner(trader_token_account_info, mint, payer.key)?;
-
-        trace!("vault token account {:?}", expected_vault_address);
-        let vault: TokenAccountInfo = TokenAccountInfo::new_with_owner_and_key(
-            next_account_info(account_iter)?,
-            mint,
-            &expected_vault_address,
-            &expected_vault_address,
-        )?;
-
-        let token_program: TokenProgram = TokenProgram::new(next_account_info(account_iter)?)?;
-        let mint: MintAccountInfo = MintAccountInfo::new(next_account_info(account_iter)?)?;
-
-        let marginfi_group: MarginfiAccountInfo<MarginfiGroup> =
-            MarginfiAccountInfo::<MarginfiGroup>::new_group(next_account_info(account_iter)?)?;
-
-        require!(
-            expected_marginfi_group == marginfi_group.info.key,
-            NixError::InvalidMarginfiGroup,
-            "Invalid Marginfi Group >> expected: {:?}, actual: {:?}",
-            expected_marginfi_group,
-            marginfi_group.info.key
-        )?;
-        let marginfi_bank: MarginfiAccountInfo<Bank> =
+        let base_marginfi_bank: MarginfiAccountInfo<Bank> =
             MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
-
         require!(
-            expected_marginfi_bank == marginfi_bank.info.key,
+            base_bank_key == *base_marginfi_bank.info.key,
             NixError::InvalidMarginfiBank,
             "Invalid Marginfi bank >> expected: {:?}, actual: {:?}",
-            expected_marginfi_bank,
-            marginfi_bank.info.key
+            base_bank_key,
+            base_marginfi_bank.info.key
         )?;
-        let marginfi_account: MarginfiAccountInfo<MarginfiAccount> =
-            MarginfiAccountInfo::<MarginfiAccount>::new_account(
-                next_account_info(account_iter)?,
-                market.info.key,
-                mint.info.key,
-            )?;
+        let quote_marginfi_bank: MarginfiAccountInfo<Bank> =
+            MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
         require!(
-            expected_marginfi_account == marginfi_account.info.key,
-            NixError::InvalidMarginfiAccount,
-            "Invalid Marginfi account >> expected: {:?}, actual: {:?}",
-            expected_marginfi_account,
-            marginfi_account.info.key
+            quote_bank_key == *quote_marginfi_bank.info.key,
+            NixError::InvalidMarginfiBank,
+            "Invalid Marginfi bank >> expected: {:?}, actual: {:?}",
+            quote_bank_key,
+            quote_marginfi_bank.info.key
         )?;
 
-        let marginfi_liquidity_vault: TokenAccountInfo =
-            TokenAccountInfo::new(next_account_info(account_iter)?, mint.info.key)?;
-        validate_marginfi_liquidity_vault(marginfi_liquidity_vault.as_ref(), &marginfi_bank)?;
-
-        // Drop the market ref so it can be passed through the return.
-        // This is necessary to avoid borrowing issues with the market_fixed reference.
-        drop(market_fixed);
         Ok(Self {
-            payer,
             market,
-            market_signer,
-            mint,
-            trader_token_account,
-            token_program,
-            vault,
-            marginfi_group,
-            marginfi_bank,
-            marginfi_account,
-            marginfi_liquidity_vault,
+            base_marginfi_bank,
+            quote_marginfi_bank,
         })
     }
 }
 
-/// Global create
-pub(crate) struct GlobalCreateContext<'a, 'info> {
-    pub payer: Signer<'a, 'info>,
-    pub global: EmptyAccount<'a, 'info>,
-    pub system_program: Program<'a, 'info>,
-    pub global_mint: MintAccountInfo<'a, 'info>,
-    pub global_vault: EmptyAccount<'a, 'info>,
-    pub token_program: TokenProgram<'a, 'info>,
+/// EmitBookSnapshot account infos
+pub(crate) struct EmitBookSnapshotContext<'a, 'info> {
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
 }
 
-impl<'a, 'info> GlobalCreateContext<'a, 'info> {
+impl<'a, 'info> EmitBookSnapshotContext<'a, 'info> {
     pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::EmitBookSnapshot)?;
         let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
 
-        let payer: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
-        let global: EmptyAccount = EmptyAccount::new(next_account_info(account_iter)?)?;
-        let system_program: Program =
-            Program::new(next_account_info(account_iter)?, &system_program::id())?;
-        let global_mint: MintAccountInfo = MintAccountInfo::new(next_account_info(account_iter)?)?;
-        // Address of the global vault is verified in the handler because the
-        // create will only work if the signer seeds match.
-        let global_vault: EmptyAccount = EmptyAccount::new(next_account_info(account_iter)?)?;
-        let token_program: TokenProgram = TokenProgram::new(next_account_info(account_iter)?)?;
-        Ok(Self {
-            payer,
-            global,
-            system_program,
-            global_mint,
-            global_vault,
-            token_program,
-        })
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        Ok(Self { market })
     }
 }
 
-/// Global add trader
-pub(crate) struct GlobalAddTraderContext<'a, 'info> {
-    pub payer: Signer<'a, 'info>,
-    pub global: NixAccountInfo<'a, 'info, GlobalFixed>,
-    pub _system_program: Program<'a, 'info>,
+/// ClaimMakerRebate account infos
+pub(crate) struct ClaimMakerRebateContext<'a, 'info> {
+    pub trader: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
 }
 
-impl<'a, 'info> GlobalAddTraderContext<'a, 'info> {
+impl<'a, 'info> ClaimMakerRebateContext<'a, 'info> {
     pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::ClaimMakerRebate)?;
         let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
 
-        let payer: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
-        let global: NixAccountInfo<GlobalFixed> =
-            NixAccountInfo::<GlobalFixed>::new(next_account_info(account_iter)?)?;
-        let _system_program: Program =
-            Program::new(next_account_info(account_iter)?, &system_program::id())?;
-        Ok(Self {
-            payer,
-            global,
+        let trader: Signer = Signer::new(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        Ok(Self { trader, market })
+    }
+}
+
+/// ReduceOrder account infos
+pub(crate) struct ReduceOrderContext<'a, 'info> {
+    pub payer: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+}
+
+impl<'a, 'info> ReduceOrderContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::ReduceOrder)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let payer: Signer = Signer::new(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        Ok(Self { payer, market })
+    }
+}
+
+/// ModifyOrder account infos
+pub(crate) struct ModifyOrderContext<'a, 'info> {
+    pub payer: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+}
+
+impl<'a, 'info> ModifyOrderContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::ModifyOrder)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let payer: Signer = Signer::new(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        Ok(Self { payer, market })
+    }
+}
+
+/// SetCircuitBreaker account infos
+pub(crate) struct SetCircuitBreakerContext<'a, 'info> {
+    pub admin: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+}
+
+impl<'a, 'info> SetCircuitBreakerContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::SetCircuitBreaker)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let admin: Signer = Signer::new(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        require!(
+            market_fixed.get_admin() == admin.key,
+            NixError::InvalidAdminKey,
+            "Invalid admin. expected {}, got {}",
+            market_fixed.get_admin(),
+            admin.key,
+        )?;
+        drop(market_fixed);
+
+        Ok(Self { admin, market })
+    }
+}
+
+/// CreateMarketAuction account infos
+pub(crate) struct CreateMarketAuctionContext<'a, 'info> {
+    pub admin: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub market_auction: EmptyAccount<'a, 'info>,
+    pub system_program: Program<'a, 'info>,
+}
+
+impl<'a, 'info> CreateMarketAuctionContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::CreateMarketAuction)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let admin: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        require!(
+            market_fixed.get_admin() == admin.key,
+            NixError::InvalidAdminKey,
+            "Invalid admin. expected {}, got {}",
+            market_fixed.get_admin(),
+            admin.key,
+        )?;
+        drop(market_fixed);
+
+        let market_auction: EmptyAccount = EmptyAccount::new(next_account_info(account_iter)?)?;
+        let (expected_market_auction, _bump) = get_market_auction_address(market.key);
+        require_account!(
+            *market_auction.info.key == expected_market_auction,
+            NixError::IncorrectAccount,
+            NixInstruction::CreateMarketAuction,
+            2,
+            "Expected market auction {}, got {}",
+            expected_market_auction,
+            market_auction.info.key,
+        )?;
+        let system_program: Program =
+            Program::new(next_account_info(account_iter)?, &system_program::id())?;
+        Ok(Self {
+            admin,
+            market,
+            market_auction,
+            system_program,
+        })
+    }
+}
+
+/// RunAuction account infos
+pub(crate) struct RunAuctionContext<'a, 'info> {
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub market_auction: NixAccountInfo<'a, 'info, MarketAuction>,
+    pub base_a_marginfi_bank: MarginfiAccountInfo<'a, 'info, Bank>,
+    pub base_b_marginfi_bank: MarginfiAccountInfo<'a, 'info, Bank>,
+}
+
+impl<'a, 'info> RunAuctionContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::RunAuction)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        let market_auction: NixAccountInfo<MarketAuction> =
+            NixAccountInfo::<MarketAuction>::new(next_account_info(account_iter)?)?;
+        let (expected_market_auction, _bump) = get_market_auction_address(market.key);
+        require_account!(
+            *market_auction.info.key == expected_market_auction,
+            NixError::IncorrectAccount,
+            NixInstruction::RunAuction,
+            1,
+            "Expected market auction {}, got {}",
+            expected_market_auction,
+            market_auction.info.key,
+        )?;
+
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        let base_a_bank_key: Pubkey = *market_fixed.get_base_a_marginfi_bank();
+        let base_b_bank_key: Pubkey = *market_fixed.get_base_b_marginfi_bank();
+        drop(market_fixed);
+
+        let base_a_marginfi_bank: MarginfiAccountInfo<Bank> =
+            MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
+        require!(
+            base_a_bank_key == *base_a_marginfi_bank.info.key,
+            NixError::InvalidMarginfiBank,
+            "Invalid Marginfi bank >> expected: {:?}, actual: {:?}",
+            base_a_bank_key,
+            base_a_marginfi_bank.info.key
+        )?;
+        let base_b_marginfi_bank: MarginfiAccountInfo<Bank> =
+            MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
+        require!(
+            base_b_bank_key == *base_b_marginfi_bank.info.key,
+            NixError::InvalidMarginfiBank,
+            "Invalid Marginfi bank >> expected: {:?}, actual: {:?}",
+            base_b_bank_key,
+            base_b_marginfi_bank.info.key
+        )?;
+
+        Ok(Self {
+            market,
+            market_auction,
+            base_a_marginfi_bank,
+            base_b_marginfi_bank,
+        })
+    }
+}
+
+/// CreateCrossMarginSeat account infos
+pub(crate) struct CreateCrossMarginSeatContext<'a, 'info> {
+    pub trader: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub cross_margin_seat: EmptyAccount<'a, 'info>,
+    pub marginfi_account: &'a AccountInfo<'info>,
+    pub system_program: Program<'a, 'info>,
+}
+
+impl<'a, 'info> CreateCrossMarginSeatContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::CreateCrossMarginSeat)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let trader: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        let cross_margin_seat: EmptyAccount = EmptyAccount::new(next_account_info(account_iter)?)?;
+        let (expected_cross_margin_seat, _bump) =
+            get_cross_margin_seat_address(market.key, trader.key);
+        require_account!(
+            *cross_margin_seat.info.key == expected_cross_margin_seat,
+            NixError::IncorrectAccount,
+            NixInstruction::CreateCrossMarginSeat,
+            2,
+            "Expected cross margin seat {}, got {}",
+            expected_cross_margin_seat,
+            cross_margin_seat.info.key,
+        )?;
+
+        // Registering while the trader is still the authority ties the
+        // account to this trader. It only counts once delegated.
+        let marginfi_account: &AccountInfo = next_account_info(account_iter)?;
+        {
+            let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+            validate_cross_margin_account(
+                marginfi_account,
+                &[
+                    *market_fixed.get_base_a_marginfi_group(),
+                    *market_fixed.get_base_b_marginfi_group(),
+                ],
+                trader.key,
+            )?;
+        }
+        let system_program: Program =
+            Program::new(next_account_info(account_iter)?, &system_program::id())?;
+        Ok(Self {
+            trader,
+            market,
+            cross_margin_seat,
+            marginfi_account,
+            system_program,
+        })
+    }
+}
+
+/// PlaceLoanSale account infos
+pub(crate) struct PlaceLoanSaleContext<'a, 'info> {
+    pub payer: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub market_loans: NixAccountInfo<'a, 'info, MarketLoansFixed>,
+    pub _system_program: Program<'a, 'info>,
+}
+
+impl<'a, 'info> PlaceLoanSaleContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::PlaceLoanSale)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let payer: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        let market_loans: NixAccountInfo<MarketLoansFixed> =
+            NixAccountInfo::<MarketLoansFixed>::new(next_account_info(account_iter)?)?;
+        let market_loans_market: Pubkey = market_loans.get_fixed()?.market;
+        require_account!(
+            market_loans_market == *market.key,
+            NixError::IncorrectAccount,
+            NixInstruction::PlaceLoanSale,
+            2,
+            "Market loans account belongs to market {}, expected {}",
+            market_loans_market,
+            market.key,
+        )?;
+        let _system_program: Program =
+            Program::new(next_account_info(account_iter)?, &system_program::id())?;
+        Ok(Self {
+            payer,
+            market,
+            market_loans,
             _system_program,
         })
     }
 }
 
-/// Global deposit
-pub(crate) struct GlobalDepositContext<'a, 'info> {
-    pub payer: Signer<'a, 'info>,
+/// CreateMarketStats account infos
+pub(crate) struct CreateMarketStatsContext<'a, 'info> {
+    pub payer: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub market_stats: EmptyAccount<'a, 'info>,
+    pub system_program: Program<'a, 'info>,
+}
+
+impl<'a, 'info> CreateMarketStatsContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::CreateMarketStats)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let payer: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        let market_stats: EmptyAccount = EmptyAccount::new(next_account_info(account_iter)?)?;
+        let (expected_market_stats, _bump) = get_market_stats_address(market.key);
+        require_account!(
+            *market_stats.info.key == expected_market_stats,
+            NixError::IncorrectAccount,
+            NixInstruction::CreateMarketStats,
+            2,
+            "Expected market stats {}, got {}",
+            expected_market_stats,
+            market_stats.info.key,
+        )?;
+        let system_program: Program =
+            Program::new(next_account_info(account_iter)?, &system_program::id())?;
+        Ok(Self {
+            payer,
+            market,
+            market_stats,
+            system_program,
+        })
+    }
+}
+
+/// Deposit into a market account infos
+pub(crate) struct DepositContext<'a, 'info> {
+    pub payer: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub market_signer: MarketSigner<'a, 'info>,
+    pub mint: MintAccountInfo<'a, 'info>,
+    pub trader_token_account: TokenAccountInfo<'a, 'info>,
+    pub token_program: TokenProgram<'a, 'info>,
+    pub vault: TokenAccountInfo<'a, 'info>,
+    pub marginfi_group: MarginfiAccountInfo<'a, 'info, MarginfiGroup>,
+    pub marginfi_bank: MarginfiAccountInfo<'a, 'info, Bank>,
+    pub marginfi_account: MarginfiAccountInfo<'a, 'info, MarginfiAccount>,
+    pub marginfi_liquidity_vault: TokenAccountInfo<'a, 'info>,
+    // Extra accounts for token22 mints with a transfer hook: the hook
+    // program, its validation account and whatever that account lists.
+    pub transfer_hook_accounts: &'a [AccountInfo<'info>],
+}
+
+impl<'a, 'info> DepositContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::Deposit)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let payer: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        let base_a_mint: &Pubkey = market_fixed.get_base_a_mint();
+        let base_b_mint: &Pubkey = market_fixed.get_base_b_mint();
+        let market_signer = MarketSigner::new(next_account_info(account_iter)?, market.key)?;
+        let trader_token_account_info: &AccountInfo<'info> = next_account_info(account_iter)?;
+
+        // Infer the mint key from the token account.
+        let (
+            mint,
+            expected_vault_address,
+            expected_marginfi_group,
+            expected_marginfi_bank,
+            expected_marginfi_account,
+        ) = if &trader_token_account_info.try_borrow_data()?[0..32] == base_a_mint.as_ref() {
+            (
+                base_a_mint,
+                market_fixed.get_base_a_vault(),
+                market_fixed.get_base_a_marginfi_group(),
+                market_fixed.get_base_a_marginfi_bank(),
+                market_fixed.get_base_a_marginfi_account(),
+            )
+        } else if &trader_token_account_info.try_borrow_data()?[0..32] == base_b_mint.as_ref() {
+            (
+                base_b_mint,
+                market_fixed.get_base_b_vault(),
+                market_fixed.get_base_b_marginfi_group(),
+                market_fixed.get_base_b_marginfi_bank(),
+                market_fixed.get_base_b_marginfi_account(),
+            )
+        } else {
+            return Err(NixError::InvalidDepositAccounts.into());
+        };
+        // Deposits go straight to marginfi, so the side of a one sided
+        // market without a bank takes none.
+        require!(
+            *expected_marginfi_bank != Pubkey::default(),
+            NixError::MissingMarginfiBank,
+            "Market has no marginfi bank for mint {}",
+            mint,
+        )?;
+
+        trace!("trader token account {:?}", trader_token_account_info.key);
+        let trader_token_account: TokenAccountInfo =
+            TokenAccountInfo::new_with_owner(trader_token_account_info, mint, payer.key)?;
+        trader_token_account.assert_not_frozen()?;
+        trader_token_account.assert_no_cpi_guard()?;
+
+        trace!("vault token account {:?}", expected_vault_address);
+        let vault: TokenAccountInfo = TokenAccountInfo::new_with_owner_and_key(
+            next_account_info(account_iter)?,
+            mint,
+            &expected_vault_address,
+            &expected_vault_address,
+        )?;
+
+        let token_program: TokenProgram = TokenProgram::new(next_account_info(account_iter)?)?;
+        let mint: MintAccountInfo = MintAccountInfo::new(next_account_info(account_iter)?)?;
+
+        let marginfi_group: MarginfiAccountInfo<MarginfiGroup> =
+            MarginfiAccountInfo::<MarginfiGroup>::new_group(next_account_info(account_iter)?)?;
+
+        require!(
+            expected_marginfi_group == marginfi_group.info.key,
+            NixError::InvalidMarginfiGroup,
+            "Invalid Marginfi Group >> expected: {:?}, actual: {:?}",
+            expected_marginfi_group,
+            marginfi_group.info.key
+        )?;
+        let marginfi_bank: MarginfiAccountInfo<Bank> =
+            MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
+
+        require!(
+            expected_marginfi_bank == marginfi_bank.info.key,
+            NixError::InvalidMarginfiBank,
+            "Invalid Marginfi bank >> expected: {:?}, actual: {:?}",
+            expected_marginfi_bank,
+            marginfi_bank.info.key
+        )?;
+        let marginfi_account: MarginfiAccountInfo<MarginfiAccount> =
+            MarginfiAccountInfo::<MarginfiAccount>::new_account(
+                next_account_info(account_iter)?,
+                market.info.key,
+                mint.info.key,
+            )?;
+        require!(
+            expected_marginfi_account == marginfi_account.info.key,
+            NixError::InvalidMarginfiAccount,
+            "Invalid Marginfi account >> expected: {:?}, actual: {:?}",
+            expected_marginfi_account,
+            marginfi_account.info.key
+        )?;
+
+        let marginfi_liquidity_vault: TokenAccountInfo =
+            TokenAccountInfo::new(next_account_info(account_iter)?, mint.info.key)?;
+        validate_marginfi_liquidity_vault(marginfi_liquidity_vault.as_ref(), &marginfi_bank)?;
+
+        let transfer_hook_accounts: &'a [AccountInfo<'info>] = account_iter.as_slice();
+
+        // Drop the market ref so it can be passed through the return.
+        // This is necessary to avoid borrowing issues with the market_fixed reference.
+        drop(market_fixed);
+        Ok(Self {
+            payer,
+            market,
+            market_signer,
+            mint,
+            trader_token_account,
+            token_program,
+            vault,
+            marginfi_group,
+            marginfi_bank,
+            marginfi_account,
+            marginfi_liquidity_vault,
+            transfer_hook_accounts,
+        })
+    }
+}
+
+/// Release collateral of a loan account infos
+pub(crate) struct ReleaseCollateralContext<'a, 'info> {
+    pub payer: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub market_loans: NixAccountInfo<'a, 'info, MarketLoansFixed>,
+    pub market_signer: MarketSigner<'a, 'info>,
+    pub mint: MintAccountInfo<'a, 'info>,
+    pub trader_token_account: TokenAccountInfo<'a, 'info>,
+    pub token_program: TokenProgram<'a, 'info>,
+    pub vault: TokenAccountInfo<'a, 'info>,
+    // Side of the collateral, the tokens are withdrawn from it.
+    pub marginfi_cpi_accounts: MarginfiCpiAccounts<'a, 'info>,
+    // Bank of the mint the loan lent, for pricing the liability.
+    pub liability_marginfi_bank: MarginfiAccountInfo<'a, 'info, Bank>,
+    pub liability_mint: MintAccountInfo<'a, 'info>,
+    pub is_base_a: bool,
+}
+
+impl<'a, 'info> ReleaseCollateralContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::ReleaseCollateral)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let payer: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        let market_loans: NixAccountInfo<MarketLoansFixed> =
+            NixAccountInfo::<MarketLoansFixed>::new(next_account_info(account_iter)?)?;
+        let market_loans_market: Pubkey = market_loans.get_fixed()?.market;
+        require_account!(
+            market_loans_market == *market.key,
+            NixError::IncorrectAccount,
+            NixInstruction::ReleaseCollateral,
+            2,
+            "Market loans account belongs to market {}, expected {}",
+            market_loans_market,
+            market.key,
+        )?;
+
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        let market_signer = MarketSigner::new(next_account_info(account_iter)?, market.key)?;
+        let mint: MintAccountInfo = MintAccountInfo::new(next_account_info(account_iter)?)?;
+
+        // The collateral mint picks the side, the liability is on the other.
+        let is_base_a: bool = mint.info.key == market_fixed.get_base_a_mint();
+        require!(
+            is_base_a || mint.info.key == market_fixed.get_base_b_mint(),
+            NixError::InvalidWithdrawAccounts,
+            "Mint {} is not a mint of the market",
+            mint.info.key,
+        )?;
+        let (
+            expected_vault_address,
+            expected_marginfi_group,
+            expected_marginfi_bank,
+            expected_marginfi_account,
+            expected_liability_marginfi_bank,
+        ) = if is_base_a {
+            (
+                market_fixed.get_base_a_vault(),
+                market_fixed.get_base_a_marginfi_group(),
+                market_fixed.get_base_a_marginfi_bank(),
+                market_fixed.get_base_a_marginfi_account(),
+                market_fixed.get_base_b_marginfi_bank(),
+            )
+        } else {
+            (
+                market_fixed.get_base_b_vault(),
+                market_fixed.get_base_b_marginfi_group(),
+                market_fixed.get_base_b_marginfi_bank(),
+                market_fixed.get_base_b_marginfi_account(),
+                market_fixed.get_base_a_marginfi_bank(),
+            )
+        };
+
+        let trader_token_account: TokenAccountInfo = TokenAccountInfo::new_with_owner(
+            next_account_info(account_iter)?,
+            mint.info.key,
+            payer.key,
+        )?;
+        let token_program: TokenProgram = TokenProgram::new(next_account_info(account_iter)?)?;
+        let vault: TokenAccountInfo = TokenAccountInfo::new_with_owner_and_key(
+            next_account_info(account_iter)?,
+            mint.info.key,
+            &expected_vault_address,
+            &expected_vault_address,
+        )?;
+
+        let marginfi_group: MarginfiAccountInfo<MarginfiGroup> =
+            MarginfiAccountInfo::<MarginfiGroup>::new_group(next_account_info(account_iter)?)?;
+        require!(
+            expected_marginfi_group == marginfi_group.info.key,
+            NixError::InvalidMarginfiGroup,
+            "Invalid Marginfi Group >> expected: {:?}, actual: {:?}",
+            expected_marginfi_group,
+            marginfi_group.info.key
+        )?;
+        let marginfi_bank: MarginfiAccountInfo<Bank> =
+            MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
+        require!(
+            expected_marginfi_bank == marginfi_bank.info.key,
+            NixError::InvalidMarginfiBank,
+            "Invalid Marginfi bank >> expected: {:?}, actual: {:?}",
+            expected_marginfi_bank,
+            marginfi_bank.info.key
+        )?;
+        let marginfi_account: MarginfiAccountInfo<MarginfiAccount> =
+            MarginfiAccountInfo::<MarginfiAccount>::new_account(
+                next_account_info(account_iter)?,
+                market.info.key,
+                mint.info.key,
+            )?;
+        require!(
+            expected_marginfi_account == marginfi_account.info.key,
+            NixError::InvalidMarginfiAccount,
+            "Invalid Marginfi account >> expected: {:?}, actual: {:?}",
+            expected_marginfi_account,
+            marginfi_account.info.key
+        )?;
+        let marginfi_liquidity_vault: TokenAccountInfo =
+            TokenAccountInfo::new(next_account_info(account_iter)?, mint.info.key)?;
+        validate_marginfi_liquidity_vault(marginfi_liquidity_vault.as_ref(), &marginfi_bank)?;
+        let marginfi_liquidity_vault_authority: &AccountInfo = next_account_info(account_iter)?;
+        validate_marginfi_liquidity_vault_authority(
+            marginfi_liquidity_vault_authority,
+            marginfi_bank.info,
+        )?;
+
+        let liability_marginfi_bank: MarginfiAccountInfo<Bank> =
+            MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
+        require!(
+            expected_liability_marginfi_bank == liability_marginfi_bank.info.key,
+            NixError::InvalidMarginfiBank,
+            "Invalid liability Marginfi bank >> expected: {:?}, actual: {:?}",
+            expected_liability_marginfi_bank,
+            liability_marginfi_bank.info.key
+        )?;
+        let liability_mint: MintAccountInfo =
+            MintAccountInfo::new(next_account_info(account_iter)?)?;
+        let expected_liability_mint: &Pubkey = if is_base_a {
+            market_fixed.get_base_b_mint()
+        } else {
+            market_fixed.get_base_a_mint()
+        };
+        require!(
+            expected_liability_mint == liability_mint.info.key,
+            NixError::InvalidWithdrawAccounts,
+            "Invalid liability mint >> expected: {:?}, actual: {:?}",
+            expected_liability_mint,
+            liability_mint.info.key
+        )?;
+
+        drop(market_fixed);
+        Ok(Self {
+            payer,
+            market,
+            market_loans,
+            market_signer,
+            mint,
+            trader_token_account,
+            token_program,
+            vault,
+            marginfi_cpi_accounts: MarginfiCpiAccounts {
+                marginfi_group,
+                marginfi_bank,
+                marginfi_account,
+                marginfi_liquidity_vault,
+                marginfi_liquidity_vault_authority,
+            },
+            liability_marginfi_bank,
+            liability_mint,
+            is_base_a,
+        })
+    }
+}
+
+/// SettleLoanProceeds account infos
+pub(crate) struct SettleLoanProceedsContext<'a, 'info> {
+    pub payer: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub market_loans: NixAccountInfo<'a, 'info, MarketLoansFixed>,
+}
+
+impl<'a, 'info> SettleLoanProceedsContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::SettleLoanProceeds)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let payer: Signer = Signer::new(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        let market_loans: NixAccountInfo<MarketLoansFixed> =
+            NixAccountInfo::<MarketLoansFixed>::new(next_account_info(account_iter)?)?;
+        let market_loans_market: Pubkey = market_loans.get_fixed()?.market;
+        require_account!(
+            market_loans_market == *market.key,
+            NixError::IncorrectAccount,
+            NixInstruction::SettleLoanProceeds,
+            2,
+            "Market loans account belongs to market {}, expected {}",
+            market_loans_market,
+            market.key,
+        )?;
+        Ok(Self {
+            payer,
+            market,
+            market_loans,
+        })
+    }
+}
+
+/// CheckHealth account infos
+pub(crate) struct CheckHealthContext<'a, 'info> {
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub market_loans: NixAccountInfo<'a, 'info, MarketLoansFixed>,
+    pub base_a_marginfi_bank: MarginfiAccountInfo<'a, 'info, Bank>,
+    pub base_b_marginfi_bank: MarginfiAccountInfo<'a, 'info, Bank>,
+    pub base_a_mint: MintAccountInfo<'a, 'info>,
+    pub base_b_mint: MintAccountInfo<'a, 'info>,
+}
+
+impl<'a, 'info> CheckHealthContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::CheckHealth)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        let market_loans: NixAccountInfo<MarketLoansFixed> =
+            NixAccountInfo::<MarketLoansFixed>::new(next_account_info(account_iter)?)?;
+        let market_loans_market: Pubkey = market_loans.get_fixed()?.market;
+        require_account!(
+            market_loans_market == *market.key,
+            NixError::IncorrectAccount,
+            NixInstruction::CheckHealth,
+            1,
+            "Market loans account belongs to market {}, expected {}",
+            market_loans_market,
+            market.key,
+        )?;
+
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        let base_a_marginfi_bank: MarginfiAccountInfo<Bank> =
+            MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
+        require!(
+            market_fixed.get_base_a_marginfi_bank() == base_a_marginfi_bank.info.key,
+            NixError::InvalidMarginfiBank,
+            "Invalid base a Marginfi bank >> expected: {:?}, actual: {:?}",
+            market_fixed.get_base_a_marginfi_bank(),
+            base_a_marginfi_bank.info.key
+        )?;
+        let base_b_marginfi_bank: MarginfiAccountInfo<Bank> =
+            MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
+        require!(
+            market_fixed.get_base_b_marginfi_bank() == base_b_marginfi_bank.info.key,
+            NixError::InvalidMarginfiBank,
+            "Invalid base b Marginfi bank >> expected: {:?}, actual: {:?}",
+            market_fixed.get_base_b_marginfi_bank(),
+            base_b_marginfi_bank.info.key
+        )?;
+        let base_a_mint: MintAccountInfo = MintAccountInfo::new(next_account_info(account_iter)?)?;
+        require!(
+            market_fixed.get_base_a_mint() == base_a_mint.info.key,
+            NixError::IncorrectAccount,
+            "Invalid base a mint >> expected: {:?}, actual: {:?}",
+            market_fixed.get_base_a_mint(),
+            base_a_mint.info.key
+        )?;
+        let base_b_mint: MintAccountInfo = MintAccountInfo::new(next_account_info(account_iter)?)?;
+        require!(
+            market_fixed.get_base_b_mint() == base_b_mint.info.key,
+            NixError::IncorrectAccount,
+            "Invalid base b mint >> expected: {:?}, actual: {:?}",
+            market_fixed.get_base_b_mint(),
+            base_b_mint.info.key
+        )?;
+
+        drop(market_fixed);
+        Ok(Self {
+            market,
+            market_loans,
+            base_a_marginfi_bank,
+            base_b_marginfi_bank,
+            base_a_mint,
+            base_b_mint,
+        })
+    }
+}
+
+/// MarkDefault account infos
+pub(crate) struct MarkDefaultContext<'a, 'info> {
+    pub payer: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub market_loans: NixAccountInfo<'a, 'info, MarketLoansFixed>,
+    pub base_a_marginfi_bank: MarginfiAccountInfo<'a, 'info, Bank>,
+    pub base_b_marginfi_bank: MarginfiAccountInfo<'a, 'info, Bank>,
+    pub base_a_mint: MintAccountInfo<'a, 'info>,
+    pub base_b_mint: MintAccountInfo<'a, 'info>,
+}
+
+impl<'a, 'info> MarkDefaultContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::MarkDefault)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let payer: Signer = Signer::new(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        let market_loans: NixAccountInfo<MarketLoansFixed> =
+            NixAccountInfo::<MarketLoansFixed>::new(next_account_info(account_iter)?)?;
+        let market_loans_market: Pubkey = market_loans.get_fixed()?.market;
+        require_account!(
+            market_loans_market == *market.key,
+            NixError::IncorrectAccount,
+            NixInstruction::MarkDefault,
+            2,
+            "Market loans account belongs to market {}, expected {}",
+            market_loans_market,
+            market.key,
+        )?;
+
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        let base_a_marginfi_bank: MarginfiAccountInfo<Bank> =
+            MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
+        require!(
+            market_fixed.get_base_a_marginfi_bank() == base_a_marginfi_bank.info.key,
+            NixError::InvalidMarginfiBank,
+            "Invalid base a Marginfi bank >> expected: {:?}, actual: {:?}",
+            market_fixed.get_base_a_marginfi_bank(),
+            base_a_marginfi_bank.info.key
+        )?;
+        let base_b_marginfi_bank: MarginfiAccountInfo<Bank> =
+            MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
+        require!(
+            market_fixed.get_base_b_marginfi_bank() == base_b_marginfi_bank.info.key,
+            NixError::InvalidMarginfiBank,
+            "Invalid base b Marginfi bank >> expected: {:?}, actual: {:?}",
+            market_fixed.get_base_b_marginfi_bank(),
+            base_b_marginfi_bank.info.key
+        )?;
+        let base_a_mint: MintAccountInfo = MintAccountInfo::new(next_account_info(account_iter)?)?;
+        require!(
+            market_fixed.get_base_a_mint() == base_a_mint.info.key,
+            NixError::IncorrectAccount,
+            "Invalid base a mint >> expected: {:?}, actual: {:?}",
+            market_fixed.get_base_a_mint(),
+            base_a_mint.info.key
+        )?;
+        let base_b_mint: MintAccountInfo = MintAccountInfo::new(next_account_info(account_iter)?)?;
+        require!(
+            market_fixed.get_base_b_mint() == base_b_mint.info.key,
+            NixError::IncorrectAccount,
+            "Invalid base b mint >> expected: {:?}, actual: {:?}",
+            market_fixed.get_base_b_mint(),
+            base_b_mint.info.key
+        )?;
+
+        drop(market_fixed);
+        Ok(Self {
+            payer,
+            market,
+            market_loans,
+            base_a_marginfi_bank,
+            base_b_marginfi_bank,
+            base_a_mint,
+            base_b_mint,
+        })
+    }
+}
+
+/// CreateMarketInsurance account infos
+pub(crate) struct CreateMarketInsuranceContext<'a, 'info> {
+    pub admin: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub market_insurance: EmptyAccount<'a, 'info>,
+    pub system_program: Program<'a, 'info>,
+}
+
+impl<'a, 'info> CreateMarketInsuranceContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::CreateMarketInsurance)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let admin: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        require!(
+            market_fixed.get_admin() == admin.key,
+            NixError::InvalidAdminKey,
+            "Invalid admin. expected {}, got {}",
+            market_fixed.get_admin(),
+            admin.key,
+        )?;
+        drop(market_fixed);
+
+        let market_insurance: EmptyAccount = EmptyAccount::new(next_account_info(account_iter)?)?;
+        let (expected_market_insurance, _bump) = get_market_insurance_address(market.key);
+        require_account!(
+            *market_insurance.info.key == expected_market_insurance,
+            NixError::IncorrectAccount,
+            NixInstruction::CreateMarketInsurance,
+            2,
+            "Expected market insurance {}, got {}",
+            expected_market_insurance,
+            market_insurance.info.key,
+        )?;
+        let system_program: Program =
+            Program::new(next_account_info(account_iter)?, &system_program::id())?;
+        Ok(Self {
+            admin,
+            market,
+            market_insurance,
+            system_program,
+        })
+    }
+}
+
+/// SetInsuranceFee account infos
+pub(crate) struct SetInsuranceFeeContext<'a, 'info> {
+    pub admin: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub market_insurance: NixAccountInfo<'a, 'info, MarketInsurance>,
+}
+
+impl<'a, 'info> SetInsuranceFeeContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::SetInsuranceFee)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let admin: Signer = Signer::new(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        require!(
+            market_fixed.get_admin() == admin.key,
+            NixError::InvalidAdminKey,
+            "Invalid admin. expected {}, got {}",
+            market_fixed.get_admin(),
+            admin.key,
+        )?;
+        drop(market_fixed);
+
+        let market_insurance: NixAccountInfo<MarketInsurance> =
+            load_market_insurance(next_account_info(account_iter)?, market.key)?;
+        Ok(Self {
+            admin,
+            market,
+            market_insurance,
+        })
+    }
+}
+
+/// DrawInsurance account infos
+pub(crate) struct DrawInsuranceContext<'a, 'info> {
+    pub admin: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub market_insurance: NixAccountInfo<'a, 'info, MarketInsurance>,
+    pub market_signer: MarketSigner<'a, 'info>,
+    pub mint: MintAccountInfo<'a, 'info>,
+    pub admin_token_account: TokenAccountInfo<'a, 'info>,
+    pub token_program: TokenProgram<'a, 'info>,
+    pub vault: TokenAccountInfo<'a, 'info>,
+    pub marginfi_cpi_accounts: MarginfiCpiAccounts<'a, 'info>,
+    pub is_base_a: bool,
+}
+
+impl<'a, 'info> DrawInsuranceContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::DrawInsurance)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let admin: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        require!(
+            market_fixed.get_admin() == admin.key,
+            NixError::InvalidAdminKey,
+            "Invalid admin. expected {}, got {}",
+            market_fixed.get_admin(),
+            admin.key,
+        )?;
+        let market_insurance: NixAccountInfo<MarketInsurance> =
+            load_market_insurance(next_account_info(account_iter)?, market.key)?;
+        let market_signer = MarketSigner::new(next_account_info(account_iter)?, market.key)?;
+        let mint: MintAccountInfo = MintAccountInfo::new(next_account_info(account_iter)?)?;
+
+        let is_base_a: bool = mint.info.key == market_fixed.get_base_a_mint();
+        require!(
+            is_base_a || mint.info.key == market_fixed.get_base_b_mint(),
+            NixError::InvalidWithdrawAccounts,
+            "Mint {} is not a mint of the market",
+            mint.info.key,
+        )?;
+        let (
+            expected_vault_address,
+            expected_marginfi_group,
+            expected_marginfi_bank,
+            expected_marginfi_account,
+        ) = if is_base_a {
+            (
+                market_fixed.get_base_a_vault(),
+                market_fixed.get_base_a_marginfi_group(),
+                market_fixed.get_base_a_marginfi_bank(),
+                market_fixed.get_base_a_marginfi_account(),
+            )
+        } else {
+            (
+                market_fixed.get_base_b_vault(),
+                market_fixed.get_base_b_marginfi_group(),
+                market_fixed.get_base_b_marginfi_bank(),
+                market_fixed.get_base_b_marginfi_account(),
+            )
+        };
+
+        let admin_token_account: TokenAccountInfo = TokenAccountInfo::new_with_owner(
+            next_account_info(account_iter)?,
+            mint.info.key,
+            admin.key,
+        )?;
+        let token_program: TokenProgram = TokenProgram::new(next_account_info(account_iter)?)?;
+        let vault: TokenAccountInfo = TokenAccountInfo::new_with_owner_and_key(
+            next_account_info(account_iter)?,
+            mint.info.key,
+            &expected_vault_address,
+            &expected_vault_address,
+        )?;
+
+        let marginfi_group: MarginfiAccountInfo<MarginfiGroup> =
+            MarginfiAccountInfo::<MarginfiGroup>::new_group(next_account_info(account_iter)?)?;
+        require!(
+            expected_marginfi_group == marginfi_group.info.key,
+            NixError::InvalidMarginfiGroup,
+            "Invalid Marginfi Group >> expected: {:?}, actual: {:?}",
+            expected_marginfi_group,
+            marginfi_group.info.key
+        )?;
+        let marginfi_bank: MarginfiAccountInfo<Bank> =
+            MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
+        require!(
+            expected_marginfi_bank == marginfi_bank.info.key,
+            NixError::InvalidMarginfiBank,
+            "Invalid Marginfi bank >> expected: {:?}, actual: {:?}",
+            expected_marginfi_bank,
+            marginfi_bank.info.key
+        )?;
+        let marginfi_account: MarginfiAccountInfo<MarginfiAccount> =
+            MarginfiAccountInfo::<MarginfiAccount>::new_account(
+                next_account_info(account_iter)?,
+                market.info.key,
+                mint.info.key,
+            )?;
+        require!(
+            expected_marginfi_account == marginfi_account.info.key,
+            NixError::InvalidMarginfiAccount,
+            "Invalid Marginfi account >> expected: {:?}, actual: {:?}",
+            expected_marginfi_account,
+            marginfi_account.info.key
+        )?;
+        let marginfi_liquidity_vault: TokenAccountInfo =
+            TokenAccountInfo::new(next_account_info(account_iter)?, mint.info.key)?;
+        validate_marginfi_liquidity_vault(marginfi_liquidity_vault.as_ref(), &marginfi_bank)?;
+        let marginfi_liquidity_vault_authority: &AccountInfo = next_account_info(account_iter)?;
+        validate_marginfi_liquidity_vault_authority(
+            marginfi_liquidity_vault_authority,
+            marginfi_bank.info,
+        )?;
+
+        drop(market_fixed);
+        Ok(Self {
+            admin,
+            market,
+            market_insurance,
+            market_signer,
+            mint,
+            admin_token_account,
+            token_program,
+            vault,
+            marginfi_cpi_accounts: MarginfiCpiAccounts {
+                marginfi_group,
+                marginfi_bank,
+                marginfi_account,
+                marginfi_liquidity_vault,
+                marginfi_liquidity_vault_authority,
+            },
+            is_base_a,
+        })
+    }
+}
+
+/// HarvestEmissions account infos
+pub(crate) struct HarvestEmissionsContext<'a, 'info> {
+    pub admin: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub market_signer: MarketSigner<'a, 'info>,
+    pub emissions_mint: MintAccountInfo<'a, 'info>,
+    pub emissions_auth: &'a AccountInfo<'info>,
+    pub emissions_vault: &'a AccountInfo<'info>,
+    /// Fee receiver of the emissions mint, which may not exist yet, or the
+    /// market vault of the bank when the emissions go to seats.
+    pub destination: &'a AccountInfo<'info>,
+    pub token_program: TokenProgram<'a, 'info>,
+    pub system_program: Program<'a, 'info>,
+    pub marginfi_cpi_accounts: MarginfiCpiAccounts<'a, 'info>,
+}
+
+impl<'a, 'info> HarvestEmissionsContext<'a, 'info> {
+    pub fn load(
+        accounts: &'a [AccountInfo<'info>],
+        is_base_a: bool,
+        to_seats: bool,
+    ) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::HarvestEmissions)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let admin: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        require!(
+            market_fixed.get_admin() == admin.key,
+            NixError::InvalidAdminKey,
+            "Invalid admin. expected {}, got {}",
+            market_fixed.get_admin(),
+            admin.key,
+        )?;
+        let market_signer = MarketSigner::new(next_account_info(account_iter)?, market.key)?;
+        let emissions_mint: MintAccountInfo =
+            MintAccountInfo::new(next_account_info(account_iter)?)?;
+        let emissions_auth: &AccountInfo = next_account_info(account_iter)?;
+        let emissions_vault: &AccountInfo = next_account_info(account_iter)?;
+
+        let (
+            expected_mint,
+            expected_vault_address,
+            expected_marginfi_group,
+            expected_marginfi_bank,
+            expected_marginfi_account,
+        ) = if is_base_a {
+            (
+                *market_fixed.get_base_a_mint(),
+                *market_fixed.get_base_a_vault(),
+                *market_fixed.get_base_a_marginfi_group(),
+                *market_fixed.get_base_a_marginfi_bank(),
+                *market_fixed.get_base_a_marginfi_account(),
+            )
+        } else {
+            (
+                *market_fixed.get_base_b_mint(),
+                *market_fixed.get_base_b_vault(),
+                *market_fixed.get_base_b_marginfi_group(),
+                *market_fixed.get_base_b_marginfi_bank(),
+                *market_fixed.get_base_b_marginfi_account(),
+            )
+        };
+        drop(market_fixed);
+
+        // Seats only hold shares of the bank mint, so emissions in any other
+        // mint go to the fee receiver of that mint.
+        let expected_destination: Pubkey = if to_seats {
+            require!(
+                *emissions_mint.info.key == expected_mint,
+                NixError::InvalidMint,
+                "Emissions in {} cannot go to seats holding {}",
+                emissions_mint.info.key,
+                expected_mint,
+            )?;
+            expected_vault_address
+        } else {
+            get_market_fee_receiver_address(market.key, emissions_mint.info.key).0
+        };
+        let destination: &AccountInfo = next_account_info(account_iter)?;
+        require!(
+            *destination.key == expected_destination,
+            NixError::IncorrectAccount,
+            "Expected emissions destination {}, got {}",
+            expected_destination,
+            destination.key,
+        )?;
+
+        let token_program: TokenProgram = TokenProgram::new(next_account_info(account_iter)?)?;
+        let system_program: Program =
+            Program::new(next_account_info(account_iter)?, &system_program::id())?;
+
+        let marginfi_group: MarginfiAccountInfo<MarginfiGroup> =
+            MarginfiAccountInfo::<MarginfiGroup>::new_group(next_account_info(account_iter)?)?;
+        require!(
+            expected_marginfi_group == *marginfi_group.info.key,
+            NixError::InvalidMarginfiGroup,
+            "Invalid Marginfi Group >> expected: {:?}, actual: {:?}",
+            expected_marginfi_group,
+            marginfi_group.info.key
+        )?;
+        let marginfi_bank: MarginfiAccountInfo<Bank> =
+            MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
+        require!(
+            expected_marginfi_bank == *marginfi_bank.info.key,
+            NixError::InvalidMarginfiBank,
+            "Invalid Marginfi bank >> expected: {:?}, actual: {:?}",
+            expected_marginfi_bank,
+            marginfi_bank.info.key
+        )?;
+        let marginfi_account: MarginfiAccountInfo<MarginfiAccount> =
+            MarginfiAccountInfo::<MarginfiAccount>::new_account(
+                next_account_info(account_iter)?,
+                market.info.key,
+                &expected_mint,
+            )?;
+        require!(
+            expected_marginfi_account == *marginfi_account.info.key,
+            NixError::InvalidMarginfiAccount,
+            "Invalid Marginfi account >> expected: {:?}, actual: {:?}",
+            expected_marginfi_account,
+            marginfi_account.info.key
+        )?;
+        let marginfi_liquidity_vault: TokenAccountInfo =
+            TokenAccountInfo::new(next_account_info(account_iter)?, &expected_mint)?;
+        validate_marginfi_liquidity_vault(marginfi_liquidity_vault.as_ref(), &marginfi_bank)?;
+        let marginfi_liquidity_vault_authority: &AccountInfo = next_account_info(account_iter)?;
+        validate_marginfi_liquidity_vault_authority(
+            marginfi_liquidity_vault_authority,
+            marginfi_bank.info,
+        )?;
+
+        Ok(Self {
+            admin,
+            market,
+            market_signer,
+            emissions_mint,
+            emissions_auth,
+            emissions_vault,
+            destination,
+            token_program,
+            system_program,
+            marginfi_cpi_accounts: MarginfiCpiAccounts {
+                marginfi_group,
+                marginfi_bank,
+                marginfi_account,
+                marginfi_liquidity_vault,
+                marginfi_liquidity_vault_authority,
+            },
+        })
+    }
+}
+
+/// The MarketInsurance PDA of `market`.
+fn load_market_insurance<'a, 'info>(
+    info: &'a AccountInfo<'info>,
+    market: &Pubkey,
+) -> Result<NixAccountInfo<'a, 'info, MarketInsurance>, ProgramError> {
+    let (expected_market_insurance, _bump) = get_market_insurance_address(market);
+    require!(
+        *info.key == expected_market_insurance,
+        NixError::IncorrectAccount,
+        "Expected market insurance {}, got {}",
+        expected_market_insurance,
+        info.key,
+    )?;
+    NixAccountInfo::<MarketInsurance>::new(info)
+}
+
+/// Global create
+pub(crate) struct GlobalCreateContext<'a, 'info> {
+    pub payer: Signer<'a, 'info>,
+    pub global: EmptyAccount<'a, 'info>,
+    pub system_program: Program<'a, 'info>,
+    pub global_mint: MintAccountInfo<'a, 'info>,
+    pub global_vault: EmptyAccount<'a, 'info>,
+    pub token_program: TokenProgram<'a, 'info>,
+}
+
+impl<'a, 'info> GlobalCreateContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::GlobalCreate)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let payer: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
+        let global: EmptyAccount = EmptyAccount::new(next_account_info(account_iter)?)?;
+        let system_program: Program =
+            Program::new(next_account_info(account_iter)?, &system_program::id())?;
+        let global_mint: MintAccountInfo = MintAccountInfo::new(next_account_info(account_iter)?)?;
+        // Address of the global vault is verified in the handler because the
+        // create will only work if the signer seeds match.
+        let global_vault: EmptyAccount = EmptyAccount::new(next_account_info(account_iter)?)?;
+        let token_program: TokenProgram = TokenProgram::new(next_account_info(account_iter)?)?;
+        Ok(Self {
+            payer,
+            global,
+            system_program,
+            global_mint,
+            global_vault,
+            token_program,
+        })
+    }
+}
+
+/// Global add trader
+pub(crate) struct GlobalAddTraderContext<'a, 'info> {
+    pub payer: Signer<'a, 'info>,
+    pub global: NixAccountInfo<'a, 'info, GlobalFixed>,
+    pub _system_program: Program<'a, 'info>,
+}
+
+impl<'a, 'info> GlobalAddTraderContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::GlobalAddTrader)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let payer: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
+        let global: NixAccountInfo<GlobalFixed> =
+            NixAccountInfo::<GlobalFixed>::new(next_account_info(account_iter)?)?;
+        let _system_program: Program =
+            Program::new(next_account_info(account_iter)?, &system_program::id())?;
+        Ok(Self {
+            payer,
+            global,
+            _system_program,
+        })
+    }
+}
+
+/// Global deposit
+/// Group, bank, account, liquidity vault and its authority of a global that
+/// is linked to marginfi, looked up by key among `accounts` so clients can
+/// append them to whatever instruction moves the tokens of the global. None
+/// while the global is not linked.
+fn find_global_marginfi_cpi_accounts<'a, 'info>(
+    accounts: &'a [AccountInfo<'info>],
+    global: &NixAccountInfo<'a, 'info, GlobalFixed>,
+) -> Result<Option<MarginfiCpiAccounts<'a, 'info>>, ProgramError> {
+    let (mint, marginfi_bank_key, marginfi_account_key) = {
+        let global_fixed: Ref<GlobalFixed> = global.get_fixed()?;
+        if !global_fixed.is_marginfi_linked() {
+            return Ok(None);
+        }
+        (
+            *global_fixed.get_mint(),
+            *global_fixed.get_marginfi_bank(),
+            *global_fixed.get_marginfi_account(),
+        )
+    };
+    let find_account = |key: &Pubkey| -> Result<&'a AccountInfo<'info>, ProgramError> {
+        let account_opt: Option<&'a AccountInfo<'info>> =
+            accounts.iter().find(|account| account.key == key);
+        require!(
+            account_opt.is_some(),
+            NixError::MissingAccounts,
+            "Missing marginfi account {} of global {}",
+            key,
+            global.key,
+        )?;
+        Ok(account_opt.unwrap())
+    };
+
+    let marginfi_bank: MarginfiAccountInfo<Bank> =
+        MarginfiAccountInfo::<Bank>::new_bank(find_account(&marginfi_bank_key)?)?;
+    let (marginfi_group_key, marginfi_liquidity_vault_key) = {
+        let bank: Ref<Bank> = marginfi_bank.get_fixed()?;
+        (bank.group, bank.liquidity_vault)
+    };
+    let (marginfi_liquidity_vault_authority_key, _bump) =
+        get_marginfi_liquidity_vault_authority(&marginfi_bank_key);
+    Ok(Some(MarginfiCpiAccounts {
+        marginfi_group: MarginfiAccountInfo::<MarginfiGroup>::new_group(find_account(
+            &marginfi_group_key,
+        )?)?,
+        marginfi_bank,
+        marginfi_account: MarginfiAccountInfo::<MarginfiAccount>::new_account(
+            find_account(&marginfi_account_key)?,
+            global.key,
+            &mint,
+        )?,
+        marginfi_liquidity_vault: TokenAccountInfo::new(
+            find_account(&marginfi_liquidity_vault_key)?,
+            &mint,
+        )?,
+        marginfi_liquidity_vault_authority: find_account(&marginfi_liquidity_vault_authority_key)?,
+    }))
+}
+
+pub(crate) struct GlobalDepositContext<'a, 'info> {
+    pub payer: Signer<'a, 'info>,
+    pub global: NixAccountInfo<'a, 'info, GlobalFixed>,
+    pub mint: MintAccountInfo<'a, 'info>,
+    pub global_vault: TokenAccountInfo<'a, 'info>,
+    pub trader_token: TokenAccountInfo<'a, 'info>,
+    pub token_program: TokenProgram<'a, 'info>,
+    /// Some once the global is linked to marginfi.
+    pub marginfi_cpi_accounts_opt: Option<MarginfiCpiAccounts<'a, 'info>>,
+    // Extra accounts for token22 mints with a transfer hook: the hook
+    // program, its validation account and whatever that account lists.
+    pub transfer_hook_accounts: &'a [AccountInfo<'info>],
+}
+
+impl<'a, 'info> GlobalDepositContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::GlobalDeposit)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let payer: Signer = Signer::new(next_account_info(account_iter)?)?;
+        let global: NixAccountInfo<GlobalFixed> =
+            NixAccountInfo::<GlobalFixed>::new(next_account_info(account_iter)?)?;
+
+        let mint: MintAccountInfo = MintAccountInfo::new(next_account_info(account_iter)?)?;
+
+        let global_data: Ref<&mut [u8]> = global.data.borrow();
+        let global_fixed: &GlobalFixed = get_helper::<GlobalFixed>(&global_data, 0_u32);
+        let expected_global_vault_address: &Pubkey = global_fixed.get_vault();
+
+        let global_vault: TokenAccountInfo = TokenAccountInfo::new_with_owner_and_key(
+            next_account_info(account_iter)?,
+            mint.info.key,
+            &expected_global_vault_address,
+            &expected_global_vault_address,
+        )?;
+        drop(global_data);
+
+        let token_account_info: &AccountInfo<'info> = next_account_info(account_iter)?;
+        let trader_token: TokenAccountInfo =
+            TokenAccountInfo::new_with_owner(token_account_info, mint.info.key, payer.key)?;
+        trader_token.assert_not_frozen()?;
+        trader_token.assert_no_cpi_guard()?;
+        let token_program: TokenProgram = TokenProgram::new(next_account_info(account_iter)?)?;
+        let marginfi_cpi_accounts_opt: Option<MarginfiCpiAccounts> =
+            find_global_marginfi_cpi_accounts(accounts, &global)?;
+        // The hook looks its accounts up by key, so the marginfi accounts of
+        // a linked global can sit among them.
+        let transfer_hook_accounts: &'a [AccountInfo<'info>] = account_iter.as_slice();
+        Ok(Self {
+            payer,
+            global,
+            mint,
+            global_vault,
+            trader_token,
+            token_program,
+            marginfi_cpi_accounts_opt,
+            transfer_hook_accounts,
+        })
+    }
+}
+
+/// Global evict
+pub(crate) struct GlobalEvictContext<'a, 'info> {
+    pub payer: Signer<'a, 'info>,
+    pub global: NixAccountInfo<'a, 'info, GlobalFixed>,
+    pub mint: MintAccountInfo<'a, 'info>,
+    pub global_vault: TokenAccountInfo<'a, 'info>,
+    pub trader_token: TokenAccountInfo<'a, 'info>,
+    pub evictee_token: TokenAccountInfo<'a, 'info>,
+    pub token_program: TokenProgram<'a, 'info>,
+    /// Some once the global is linked to marginfi.
+    pub marginfi_cpi_accounts_opt: Option<MarginfiCpiAccounts<'a, 'info>>,
+}
+
+impl<'a, 'info> GlobalEvictContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::GlobalEvict)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let payer: Signer = Signer::new(next_account_info(account_iter)?)?;
+        let global: NixAccountInfo<GlobalFixed> =
+            NixAccountInfo::<GlobalFixed>::new(next_account_info(account_iter)?)?;
+
+        let mint: MintAccountInfo = MintAccountInfo::new(next_account_info(account_iter)?)?;
+
+        let global_data: Ref<&mut [u8]> = global.data.borrow();
+        let global_fixed: &GlobalFixed = get_helper::<GlobalFixed>(&global_data, 0_u32);
+        let expected_global_vault_address: &Pubkey = global_fixed.get_vault();
+
+        let global_vault: TokenAccountInfo = TokenAccountInfo::new_with_owner_and_key(
+            next_account_info(account_iter)?,
+            mint.info.key,
+            &expected_global_vault_address,
+            &expected_global_vault_address,
+        )?;
+        drop(global_data);
+
+        let trader_token: TokenAccountInfo = TokenAccountInfo::new_with_owner(
+            next_account_info(account_iter)?,
+            mint.info.key,
+            payer.key,
+        )?;
+        trader_token.assert_not_frozen()?;
+        trader_token.assert_no_cpi_guard()?;
+        // The evictee is whoever has the smallest deposit at execution time,
+        // so the owner is checked in the processor.
+        let evictee_token: TokenAccountInfo =
+            TokenAccountInfo::new(next_account_info(account_iter)?, mint.info.key)?;
+        let token_program: TokenProgram = TokenProgram::new(next_account_info(account_iter)?)?;
+        let marginfi_cpi_accounts_opt: Option<MarginfiCpiAccounts> =
+            find_global_marginfi_cpi_accounts(accounts, &global)?;
+        Ok(Self {
+            payer,
+            global,
+            mint,
+            global_vault,
+            trader_token,
+            evictee_token,
+            token_program,
+            marginfi_cpi_accounts_opt,
+        })
+    }
+}
+
+/// Accounts needed to make a global trade. Scope is beyond just crate so
+/// clients can place orders on markets in testing.
+#[derive(Clone)]
+pub struct GlobalTradeAccounts<'a, 'info> {
+    pub global: NixAccountInfo<'a, 'info, GlobalFixed>,
+
+    // These are required when matching a global order, not necessarily when
+    // cancelling since tokens dont move in that case.
+    pub global_vault_opt: Option<TokenAccountInfo<'a, 'info>>,
+    pub market_vault_opt: Option<TokenAccountInfo<'a, 'info>>,
+    pub token_program_opt: Option<TokenProgram<'a, 'info>>,
+
+    pub system_program: Option<Program<'a, 'info>>,
+
+    // Trader is sending or cancelling the order. They are the one who will pay
+    // or receive gas prepayments.
+    pub gas_payer_opt: Option<Signer<'a, 'info>>,
+    pub gas_receiver_opt: Option<Signer<'a, 'info>>,
+    pub market: Pubkey,
+
+    /// Marginfi accounts of the global, Some once it is linked to marginfi.
+    pub marginfi_cpi_accounts_opt: Option<MarginfiCpiAccounts<'a, 'info>>,
+}
+
+#[derive(Clone)]
+pub struct MarginfiCpiAccounts<'a, 'info> {
+    pub marginfi_group: MarginfiAccountInfo<'a, 'info, MarginfiGroup>,
+    pub marginfi_bank: MarginfiAccountInfo<'a, 'info, Bank>,
+    pub marginfi_account: MarginfiAccountInfo<'a, 'info, MarginfiAccount>,
+    pub marginfi_liquidity_vault: TokenAccountInfo<'a, 'info>,
+    pub marginfi_liquidity_vault_authority: &'a AccountInfo<'info>,
+}
+
+/// Group, bank, account, liquidity vault and its authority.
+const MARGINFI_CPI_NUM_ACCOUNTS: usize = 5;
+pub(crate) struct PlaceOrderContext<'a, 'info> {
+    pub payer: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub market_loans: NixAccountInfo<'a, 'info, MarketLoansFixed>,
+    pub market_signer: MarketSigner<'a, 'info>,
+    pub system_program: Program<'a, 'info>,
+    pub base_mint: MintAccountInfo<'a, 'info>,
+    pub quote_mint: MintAccountInfo<'a, 'info>,
+    pub market_keys: MarketKeys,
+
+    // One for each side. First is base, then is quote.
+    pub global_trade_accounts_opts: [Option<GlobalTradeAccounts<'a, 'info>>; 2],
+    pub marginfi_cpi_accounts_opts: [Option<MarginfiCpiAccounts<'a, 'info>>; 2],
+}
+
+/// Keys of the market the accounts of an order are checked against, read
+/// from the market in one borrow while loading. Like the account opts, one
+/// for each side, first is base, then is quote.
+pub(crate) struct MarketKeys {
+    pub mints: [Pubkey; 2],
+    pub vaults: [Pubkey; 2],
+    pub marginfi_groups: [Pubkey; 2],
+    pub marginfi_banks: [Pubkey; 2],
+    pub marginfi_accounts: [Pubkey; 2],
+}
+
+impl MarketKeys {
+    /// The tree the order goes on decides which base is the base side.
+    fn new(market_fixed: &MarketFixed, use_a_tree: bool) -> Self {
+        let order = |a: &Pubkey, b: &Pubkey| -> [Pubkey; 2] {
+            if use_a_tree {
+                [*a, *b]
+            } else {
+                [*b, *a]
+            }
+        };
+        Self {
+            mints: order(
+                market_fixed.get_base_a_mint(),
+                market_fixed.get_base_b_mint(),
+            ),
+            vaults: order(
+                market_fixed.get_base_a_vault(),
+                market_fixed.get_base_b_vault(),
+            ),
+            marginfi_groups: order(
+                market_fixed.get_base_a_marginfi_group(),
+                market_fixed.get_base_b_marginfi_group(),
+            ),
+            marginfi_banks: order(
+                market_fixed.get_base_a_marginfi_bank(),
+                market_fixed.get_base_b_marginfi_bank(),
+            ),
+            marginfi_accounts: order(
+                market_fixed.get_base_a_marginfi_account(),
+                market_fixed.get_base_b_marginfi_account(),
+            ),
+        }
+    }
+}
+
+impl<'a, 'info> PlaceOrderContext<'a, 'info> {
+    pub fn load(
+        accounts: &'a [AccountInfo<'info>],
+        use_a_tree: bool,
+    ) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::PlaceOrder)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        // Does not have to be writable, but this ix will fail if removing a
+        // global or requiring expanding.
+        let payer: Signer = Signer::new(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        let market_loans: NixAccountInfo<MarketLoansFixed> =
+            NixAccountInfo::<MarketLoansFixed>::new(next_account_info(account_iter)?)?;
+        verify_market_loans_page(&market_loans, market.key)?;
+        let market_signer = MarketSigner::new(next_account_info(account_iter)?, market.key)?;
+
+        let system_program: Program =
+            Program::new(next_account_info(account_iter)?, &system_program::id())?;
+
+        let mut global_trade_accounts_opts: [Option<GlobalTradeAccounts<'a, 'info>>; 2] =
+            [None, None];
+        let mut marginfi_cpi_accounts_opts: [Option<MarginfiCpiAccounts<'a, 'info>>; 2] =
+            [None, None];
+
+        {
+            // The only borrow of the market while loading, everything below
+            // checks against these.
+            let market_keys: MarketKeys = MarketKeys::new(&market.get_fixed()?, use_a_tree);
+            let [base_mint_key, quote_mint_key] = market_keys.mints;
+            let [base_vault_key, quote_vault_key] = market_keys.vaults;
+            let [base_group_key, quote_group_key] = market_keys.marginfi_groups;
+            let [base_bank_key, quote_bank_key] = market_keys.marginfi_banks;
+            let [base_account_key, quote_account_key] = market_keys.marginfi_accounts;
+
+            let mint1_ai = next_account_info(account_iter)?;
+            let mint2_ai = next_account_info(account_iter)?;
+
+            let (base_mint_ai, quote_mint_ai) =
+                if base_mint_key == *mint1_ai.key && quote_mint_key == *mint2_ai.key {
+                    (&mint1_ai, &mint2_ai)
+                } else if base_mint_key == *mint2_ai.key && quote_mint_key == *mint1_ai.key {
+                    (&mint2_ai, &mint1_ai)
+                } else {
+                    return Err(with_error_log(
+                        NixError::InvalidMint.into(),
+                        NixInstruction::PlaceOrder,
+                        last_account_index(accounts, account_iter) - 1,
+                    ));
+                };
+            let base_mint: MintAccountInfo<'a, 'info> = MintAccountInfo::new(base_mint_ai)?;
+            let quote_mint: MintAccountInfo<'a, 'info> = MintAccountInfo::new(quote_mint_ai)?;
+
+            for _ in 0..2 {
+                let next_account_info_or: Result<&AccountInfo<'info>, ProgramError> =
+                    next_account_info(account_iter);
+                if next_account_info_or.is_ok() {
+                    let global_or: Result<NixAccountInfo<'a, 'info, GlobalFixed>, ProgramError> =
+                        NixAccountInfo::<GlobalFixed>::new(next_account_info_or?);
+
+                    // If a client blindly fills in the global account and vault,
+                    // then handle that case and allow them to try to work without
+                    // the global accounts.
+                    if global_or.is_err() {
+                        let _global_vault: Result<&AccountInfo<'info>, ProgramError> =
+                            next_account_info(account_iter);
+                        let _market_vault: Result<&AccountInfo<'info>, ProgramError> =
+                            next_account_info(account_iter);
+                        let _token_program: Result<&AccountInfo<'info>, ProgramError> =
+                            next_account_info(account_iter);
+                        continue;
+                    }
+
+                    let global: NixAccountInfo<'a, 'info, GlobalFixed> = global_or.unwrap();
+                    let global_data: Ref<&mut [u8]> = global.data.borrow();
+                    let global_fixed: &GlobalFixed = get_helper::<GlobalFixed>(&global_data, 0_u32);
+                    let expected_global_vault_address: &Pubkey = global_fixed.get_vault();
+
+                    let mint: MintAccountInfo<'a, 'info> =
+                        if global_fixed.get_mint() == base_mint.info.key {
+                            base_mint.clone()
+                        } else {
+                            quote_mint.clone()
+                        };
+
+                    let (index, expected_market_vault_address) =
+                        if base_mint.info.key == mint.info.key {
+                            (0, &base_vault_key)
+                        } else {
+                            require_account!(
+                                quote_mint.info.key == mint.info.key,
+                                NixError::MissingGlobal,
+                                NixInstruction::PlaceOrder,
+                                last_account_index(accounts, account_iter),
+                                "Unexpected global mint",
+                            )?;
+                            (1, &quote_vault_key)
+                        };
+
+                    let global_vault: TokenAccountInfo<'a, 'info> =
+                        TokenAccountInfo::new_with_owner_and_key(
+                            next_account_info(account_iter)?,
+                            mint.info.key,
+                            &expected_global_vault_address,
+                            &expected_global_vault_address,
+                        )?;
+                    drop(global_data);
+
+                    let market_vault: TokenAccountInfo<'a, 'info> =
+                        TokenAccountInfo::new_with_owner_and_key(
+                            next_account_info(account_iter)?,
+                            mint.info.key,
+                            &expected_market_vault_address,
+                            &expected_market_vault_address,
+                        )?;
+                    let token_program: TokenProgram<'a, 'info> =
+                        TokenProgram::new(next_account_info(account_iter)?)?;
+                    let global_marginfi_cpi_accounts_opt: Option<MarginfiCpiAccounts> =
+                        find_global_marginfi_cpi_accounts(accounts, &global)?;
+
+                    global_trade_accounts_opts[index] = Some(GlobalTradeAccounts {
+                        global,
+                        global_vault_opt: Some(global_vault),
+                        market_vault_opt: Some(market_vault),
+                        token_program_opt: Some(token_program),
+                        system_program: Some(system_program.clone()),
+                        gas_payer_opt: Some(payer.clone()),
+                        gas_receiver_opt: Some(payer.clone()),
+                        market: *market.info.key,
+                        marginfi_cpi_accounts_opt: global_marginfi_cpi_accounts_opt,
+                    })
+                }
+            }
+
+            // Either side can be left out. Orders that need it fail later
+            // with MissingMarginfiAccounts, asks that cannot take do not.
+            // The side of a one sided market without a bank has a default
+            // group key, which a trailing system program must not match.
+            for _ in 0..2 {
+                let remaining_accounts: &[AccountInfo<'info>] = account_iter.as_slice();
+                let Some(marginfi_group_account_raw) = remaining_accounts.first() else {
+                    break;
+                };
+                if *marginfi_group_account_raw.key == Pubkey::default()
+                    || (*marginfi_group_account_raw.key != base_group_key
+                        && *marginfi_group_account_raw.key != quote_group_key)
+                {
+                    break;
+                }
+                require_account!(
+                    remaining_accounts.len() >= MARGINFI_CPI_NUM_ACCOUNTS,
+                    NixError::MissingAccounts,
+                    NixInstruction::PlaceOrder,
+                    accounts.len(),
+                    "Expected {} marginfi accounts, got {}",
+                    MARGINFI_CPI_NUM_ACCOUNTS,
+                    remaining_accounts.len(),
+                )?;
+
+                // Both banks can sit in one group, so the bank tells the
+                // sides apart.
+                let marginfi_bank_key: &Pubkey = remaining_accounts[1].key;
+                let (
+                    index,
+                    mint,
+                    expected_marginfi_group,
+                    expected_marginfi_bank,
+                    expected_marginfi_account,
+                ) = if *marginfi_bank_key == base_bank_key
+                    && marginfi_cpi_accounts_opts[0].is_none()
+                {
+                    (
+                        0,
+                        base_mint.info.key,
+                        base_group_key,
+                        base_bank_key,
+                        base_account_key,
+                    )
+                } else if *marginfi_bank_key == quote_bank_key
+                    && marginfi_cpi_accounts_opts[1].is_none()
+                {
+                    (
+                        1,
+                        quote_mint.info.key,
+                        quote_group_key,
+                        quote_bank_key,
+                        quote_account_key,
+                    )
+                } else {
+                    return Err(with_error_log(
+                        NixError::InvalidDepositAccounts.into(),
+                        NixInstruction::PlaceOrder,
+                        accounts.len() - remaining_accounts.len() + 1,
+                    ));
+                };
+                next_account_info(account_iter)?;
+
+                let marginfi_group: MarginfiAccountInfo<MarginfiGroup> =
+                    MarginfiAccountInfo::<MarginfiGroup>::new_group(marginfi_group_account_raw)?;
+
+                require_account!(
+                    expected_marginfi_group == *marginfi_group.info.key,
+                    NixError::InvalidMarginfiGroup,
+                    NixInstruction::PlaceOrder,
+                    last_account_index(accounts, account_iter),
+                    "Invalid Marginfi Group >> expected: {:?}, actual: {:?}",
+                    expected_marginfi_group,
+                    marginfi_group.info.key
+                )?;
+                let marginfi_bank: MarginfiAccountInfo<Bank> =
+                    MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
+
+                require_account!(
+                    expected_marginfi_bank == *marginfi_bank.info.key,
+                    NixError::InvalidMarginfiBank,
+                    NixInstruction::PlaceOrder,
+                    last_account_index(accounts, account_iter),
+                    "Invalid Marginfi bank >> expected: {:?}, actual: {:?}",
+                    expected_marginfi_bank,
+                    marginfi_bank.info.key
+                )?;
+                let marginfi_account: MarginfiAccountInfo<MarginfiAccount> =
+                    MarginfiAccountInfo::<MarginfiAccount>::new_account(
+                        next_account_info(account_iter)?,
+                        market.info.key,
+                        mint,
+                    )?;
+                require_account!(
+                    expected_marginfi_account == *marginfi_account.info.key,
+                    NixError::InvalidMarginfiAccount,
+                    NixInstruction::PlaceOrder,
+                    last_account_index(accounts, account_iter),
+                    "Invalid Marginfi account >> expected: {:?}, actual: {:?}",
+                    expected_marginfi_account,
+                    marginfi_account.info.key
+                )?;
+
+                let marginfi_liquidity_vault: TokenAccountInfo =
+                    TokenAccountInfo::new(next_account_info(account_iter)?, mint)?;
+                validate_marginfi_liquidity_vault(
+                    marginfi_liquidity_vault.as_ref(),
+                    &marginfi_bank,
+                )?;
+
+                let marginfi_liquidity_vault_authority = next_account_info(account_iter)?;
+                validate_marginfi_liquidity_vault_authority(
+                    marginfi_liquidity_vault_authority,
+                    marginfi_bank.info,
+                )?;
+
+                marginfi_cpi_accounts_opts[index] = Some(MarginfiCpiAccounts {
+                    marginfi_group,
+                    marginfi_bank,
+                    marginfi_account,
+                    marginfi_liquidity_vault,
+                    marginfi_liquidity_vault_authority,
+                });
+            }
+
+            // Groups, mints and programs can repeat, everything that holds
+            // balances the match or the CPIs touch cannot.
+            let mut roles: Vec<&AccountInfo<'info>> = vec![market.info, market_loans.info];
+            for global_trade_accounts in global_trade_accounts_opts.iter().flatten() {
+                roles.push(global_trade_accounts.global.info);
+                roles.extend(
+                    global_trade_accounts
+                        .global_vault_opt
+                        .iter()
+                        .map(|vault| vault.info),
+                );
+                roles.extend(
+                    global_trade_accounts
+                        .market_vault_opt
+                        .iter()
+                        .map(|vault| vault.info),
+                );
+                // The bank and its vault are usually the ones of the market.
+                roles.extend(
+                    global_trade_accounts
+                        .marginfi_cpi_accounts_opt
+                        .iter()
+                        .map(|marginfi_cpi_accounts| marginfi_cpi_accounts.marginfi_account.info),
+                );
+            }
+            for marginfi_cpi_accounts in marginfi_cpi_accounts_opts.iter().flatten() {
+                roles.push(marginfi_cpi_accounts.marginfi_bank.info);
+                roles.push(marginfi_cpi_accounts.marginfi_account.info);
+                roles.push(marginfi_cpi_accounts.marginfi_liquidity_vault.info);
+            }
+            verify_distinct_roles(accounts, NixInstruction::PlaceOrder, &roles)?;
+
+            Ok(Self {
+                payer,
+                market,
+                market_loans,
+                market_signer,
+                system_program,
+                base_mint,
+                quote_mint,
+                market_keys,
+                global_trade_accounts_opts,
+                marginfi_cpi_accounts_opts,
+            })
+        }
+    }
+}
+
+/// CreateMarketLoanAccount account infos
+pub(crate) struct CreateMarketLoanAccountContext<'a, 'info> {
+    pub admin: Signer<'a, 'info>,
+    pub market_loan_account: NixAccountInfo<'a, 'info, MarketLoansFixed>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    // Last page of the market's loans, which gets linked to the new account.
+    pub previous_market_loans_opt: Option<NixAccountInfo<'a, 'info, MarketLoansFixed>>,
+}
+
+impl<'a, 'info> CreateMarketLoanAccountContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::CreateMarketLoanAccount)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let admin: Signer = Signer::new(next_account_info(account_iter)?)?;
+        let market_loan_account: NixAccountInfo<MarketLoansFixed> =
+            NixAccountInfo::<MarketLoansFixed>::new_init(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        require!(
+            market_fixed.get_admin() == admin.key,
+            NixError::InvalidAdminKey,
+            "Invalid admin. expected {}, got {}",
+            market_fixed.get_admin(),
+            admin.key,
+        )?;
+        drop(market_fixed);
+
+        let previous_market_loans_opt: Option<NixAccountInfo<MarketLoansFixed>> =
+            match next_account_info(account_iter) {
+                Ok(previous_market_loans_info) => {
+                    let previous_market_loans: NixAccountInfo<MarketLoansFixed> =
+                        NixAccountInfo::<MarketLoansFixed>::new(previous_market_loans_info)?;
+                    let previous_market_loans_fixed: Ref<MarketLoansFixed> =
+                        previous_market_loans.get_fixed()?;
+                    require!(
+                        previous_market_loans_fixed.market == *market.key
+                            && !previous_market_loans_fixed.has_next_page(),
+                        NixError::IncorrectAccount,
+                        "Previous market loans account must be the last page of market {}",
+                        market.key,
+                    )?;
+                    drop(previous_market_loans_fixed);
+                    Some(previous_market_loans)
+                }
+                Err(_) => None,
+            };
+
+        Ok(Self {
+            admin,
+            market_loan_account,
+            market,
+            previous_market_loans_opt,
+        })
+    }
+}
+
+/// CreateEventQueue account infos
+pub(crate) struct CreateEventQueueContext<'a, 'info> {
+    pub admin: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub event_queue: NixAccountInfo<'a, 'info, EventQueueFixed>,
+}
+
+impl<'a, 'info> CreateEventQueueContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::CreateEventQueue)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let admin: Signer = Signer::new(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        let event_queue: NixAccountInfo<EventQueueFixed> =
+            NixAccountInfo::<EventQueueFixed>::new_init(next_account_info(account_iter)?)?;
+
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        require!(
+            market_fixed.get_admin() == admin.key,
+            NixError::InvalidAdminKey,
+            "Invalid admin. expected {}, got {}",
+            market_fixed.get_admin(),
+            admin.key,
+        )?;
+        // Replacing a queue would drop the events still on it.
+        require!(
+            !market_fixed.has_event_queue(),
+            NixError::IncorrectAccount,
+            "Market {} already has event queue {}",
+            market.key,
+            market_fixed.get_event_queue(),
+        )?;
+        drop(market_fixed);
+
+        Ok(Self {
+            admin,
+            market,
+            event_queue,
+        })
+    }
+}
+
+/// ConsumeEvents account infos
+pub(crate) struct ConsumeEventsContext<'a, 'info> {
+    pub admin: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub event_queue: NixAccountInfo<'a, 'info, EventQueueFixed>,
+}
+
+impl<'a, 'info> ConsumeEventsContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::ConsumeEvents)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let admin: Signer = Signer::new(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        let event_queue: NixAccountInfo<EventQueueFixed> =
+            NixAccountInfo::<EventQueueFixed>::new(next_account_info(account_iter)?)?;
+
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        require!(
+            market_fixed.get_admin() == admin.key,
+            NixError::InvalidAdminKey,
+            "Invalid admin. expected {}, got {}",
+            market_fixed.get_admin(),
+            admin.key,
+        )?;
+        require!(
+            market_fixed.get_event_queue() == event_queue.key,
+            NixError::IncorrectAccount,
+            "Incorrect event queue. expected {}, got {}",
+            market_fixed.get_event_queue(),
+            event_queue.key,
+        )?;
+        drop(market_fixed);
+
+        Ok(Self {
+            admin,
+            market,
+            event_queue,
+        })
+    }
+}
+
+/// MigrateMarket account infos
+pub(crate) struct MigrateMarketContext<'a, 'info> {
+    pub payer: Signer<'a, 'info>,
+    /// Can be shorter than MarketFixed until migrated, so the handler only
+    /// loads it once it has grown.
+    pub market: &'a AccountInfo<'info>,
+    pub system_program: Program<'a, 'info>,
+}
+
+impl<'a, 'info> MigrateMarketContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::MigrateMarket)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let payer: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
+        let market: &'a AccountInfo<'info> = next_account_info(account_iter)?;
+        verify_owned_by_nix(market.owner)?;
+        let system_program: Program =
+            Program::new(next_account_info(account_iter)?, &system_program::id())?;
+
+        Ok(Self {
+            payer,
+            market,
+            system_program,
+        })
+    }
+}
+
+/// MigrateGlobal account infos
+pub(crate) struct MigrateGlobalContext<'a, 'info> {
+    pub payer: Signer<'a, 'info>,
+    /// Can be shorter than GlobalFixed until migrated, so the handler only
+    /// loads it once it has grown.
+    pub global: &'a AccountInfo<'info>,
+    pub system_program: Program<'a, 'info>,
+}
+
+impl<'a, 'info> MigrateGlobalContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::MigrateGlobal)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let payer: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
+        let global: &'a AccountInfo<'info> = next_account_info(account_iter)?;
+        verify_owned_by_nix(global.owner)?;
+        let system_program: Program =
+            Program::new(next_account_info(account_iter)?, &system_program::id())?;
+
+        Ok(Self {
+            payer,
+            global,
+            system_program,
+        })
+    }
+}
+
+/// GlobalLinkMarginfi account infos
+pub(crate) struct GlobalLinkMarginfiContext<'a, 'info> {
+    pub payer: Signer<'a, 'info>,
+    pub global: NixAccountInfo<'a, 'info, GlobalFixed>,
+    pub mint: MintAccountInfo<'a, 'info>,
+    pub global_vault: TokenAccountInfo<'a, 'info>,
+    pub marginfi_group: MarginfiAccountInfo<'a, 'info, MarginfiGroup>,
+    pub marginfi_bank: MarginfiAccountInfo<'a, 'info, Bank>,
+    pub marginfi_account: MarginfiAccountInfo<'a, 'info, MarginfiAccount>,
+    pub marginfi_liquidity_vault: TokenAccountInfo<'a, 'info>,
+    pub token_program: TokenProgram<'a, 'info>,
+    pub system_program: Program<'a, 'info>,
+}
+
+impl<'a, 'info> GlobalLinkMarginfiContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::GlobalLinkMarginfi)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
+
+        let payer: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
+        let global: NixAccountInfo<GlobalFixed> =
+            NixAccountInfo::<GlobalFixed>::new(next_account_info(account_iter)?)?;
+        let mint: MintAccountInfo = MintAccountInfo::new(next_account_info(account_iter)?)?;
+
+        let global_data: Ref<&mut [u8]> = global.data.borrow();
+        let global_fixed: &GlobalFixed = get_helper::<GlobalFixed>(&global_data, 0_u32);
+        require!(
+            !global_fixed.is_marginfi_linked(),
+            NixError::GlobalAlreadyLinked,
+            "Global {} already lends to bank {}",
+            global.key,
+            global_fixed.get_marginfi_bank(),
+        )?;
+        let expected_global_vault_address: &Pubkey = global_fixed.get_vault();
+        let global_vault: TokenAccountInfo = TokenAccountInfo::new_with_owner_and_key(
+            next_account_info(account_iter)?,
+            mint.info.key,
+            &expected_global_vault_address,
+            &expected_global_vault_address,
+        )?;
+        drop(global_data);
+
+        // The bank has to be the one a market lends the mint to, which its
+        // admin vetted. Anyone can link a global, so it is not up to them.
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        let (expected_marginfi_group, expected_marginfi_bank) = {
+            let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+            let is_base_a: bool = market_fixed.get_base_a_mint() == mint.info.key;
+            require_account!(
+                is_base_a || market_fixed.get_base_b_mint() == mint.info.key,
+                NixError::InvalidMint,
+                NixInstruction::GlobalLinkMarginfi,
+                last_account_index(accounts, account_iter),
+                "Market {} does not trade mint {}",
+                market.key,
+                mint.info.key,
+            )?;
+            require_account!(
+                market_fixed.has_marginfi_bank(is_base_a),
+                NixError::MissingMarginfiBank,
+                NixInstruction::GlobalLinkMarginfi,
+                last_account_index(accounts, account_iter),
+                "Market {} has no marginfi bank for mint {}",
+                market.key,
+                mint.info.key,
+            )?;
+            if is_base_a {
+                (
+                    *market_fixed.get_base_a_marginfi_group(),
+                    *market_fixed.get_base_a_marginfi_bank(),
+                )
+            } else {
+                (
+                    *market_fixed.get_base_b_marginfi_group(),
+                    *market_fixed.get_base_b_marginfi_bank(),
+                )
+            }
+        };
+
+        let marginfi_group: MarginfiAccountInfo<MarginfiGroup> =
+            MarginfiAccountInfo::<MarginfiGroup>::new_group(next_account_info(account_iter)?)?;
+        require_account!(
+            expected_marginfi_group == *marginfi_group.info.key,
+            NixError::InvalidMarginfiGroup,
+            NixInstruction::GlobalLinkMarginfi,
+            last_account_index(accounts, account_iter),
+            "Invalid Marginfi Group >> expected: {:?}, actual: {:?}",
+            expected_marginfi_group,
+            marginfi_group.info.key
+        )?;
+        let marginfi_bank: MarginfiAccountInfo<Bank> =
+            MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
+        require_account!(
+            expected_marginfi_bank == *marginfi_bank.info.key,
+            NixError::InvalidMarginfiBank,
+            NixInstruction::GlobalLinkMarginfi,
+            last_account_index(accounts, account_iter),
+            "Invalid Marginfi bank >> expected: {:?}, actual: {:?}",
+            expected_marginfi_bank,
+            marginfi_bank.info.key
+        )?;
+        // Derived from the global like the account of a market is from the
+        // market.
+        let marginfi_account: MarginfiAccountInfo<MarginfiAccount> =
+            MarginfiAccountInfo::<MarginfiAccount>::new_account_uninitialized(
+                next_account_info(account_iter)?,
+                global.info,
+                mint.info,
+            )?;
+        let marginfi_liquidity_vault: TokenAccountInfo =
+            TokenAccountInfo::new(next_account_info(account_iter)?, mint.info.key)?;
+        validate_marginfi_liquidity_vault(marginfi_liquidity_vault.as_ref(), &marginfi_bank)?;
+
+        let token_program: TokenProgram = TokenProgram::new(next_account_info(account_iter)?)?;
+        let system_program: Program =
+            Program::new(next_account_info(account_iter)?, &system_program::id())?;
+
+        Ok(Self {
+            payer,
+            global,
+            mint,
+            global_vault,
+            marginfi_group,
+            marginfi_bank,
+            marginfi_account,
+            marginfi_liquidity_vault,
+            token_program,
+            system_program,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct CancelOrderGlobalTradeAccounts<'a, 'info> {
     pub global: NixAccountInfo<'a, 'info, GlobalFixed>,
-    pub mint: MintAccountInfo<'a, 'info>,
-    pub global_vault: TokenAccountInfo<'a, 'info>,
-    pub trader_token: TokenAccountInfo<'a, 'info>,
-    pub token_program: TokenProgram<'a, 'info>,
+    pub system_program: Option<Program<'a, 'info>>,
+    pub gas_receiver_opt: Option<Signer<'a, 'info>>,
+    pub market: Pubkey,
 }
 
-impl<'a, 'info> GlobalDepositContext<'a, 'info> {
-    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+pub(crate) struct CancelOrderContext<'a, 'info> {
+    pub payer: Signer<'a, 'info>,
+    pub market_loans: NixAccountInfo<'a, 'info, MarketLoansFixed>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    /// None when the client passed the system program in its place, only
+    /// global orders need it.
+    pub base_global_opt: Option<NixAccountInfo<'a, 'info, GlobalFixed>>,
+    pub system_program: Program<'a, 'info>,
+}
+
+/// Index of base_global in CancelOrder and CleanExpiredOrders.
+const CANCEL_ORDER_BASE_GLOBAL_INDEX: usize = 3;
+
+impl<'a, 'info> CancelOrderContext<'a, 'info> {
+    pub fn load(
+        accounts: &'a [AccountInfo<'info>],
+        use_a_tree: bool,
+    ) -> Result<Self, ProgramError> {
+        Self::load_for(accounts, use_a_tree, NixInstruction::CancelOrder)
+    }
+
+    /// Load for an instruction that shares the CancelOrder accounts, so
+    /// failures are logged under that instruction.
+    pub fn load_for(
+        accounts: &'a [AccountInfo<'info>],
+        use_a_tree: bool,
+        instruction: NixInstruction,
+    ) -> Result<Self, ProgramError> {
+        let is_base_global_missing: bool = accounts
+            .get(CANCEL_ORDER_BASE_GLOBAL_INDEX)
+            .is_some_and(|base_global| *base_global.key == system_program::id());
+        let placeholder_indexes: &[usize] = if is_base_global_missing {
+            &[CANCEL_ORDER_BASE_GLOBAL_INDEX]
+        } else {
+            &[]
+        };
+        verify_account_slots_with_placeholders(accounts, instruction, placeholder_indexes)?;
         let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
 
         let payer: Signer = Signer::new(next_account_info(account_iter)?)?;
-        let global: NixAccountInfo<GlobalFixed> =
-            NixAccountInfo::<GlobalFixed>::new(next_account_info(account_iter)?)?;
+        let market_loans: NixAccountInfo<MarketLoansFixed> =
+            NixAccountInfo::<MarketLoansFixed>::new(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        verify_market_loans_page(&market_loans, market.key)?;
 
-        let mint: MintAccountInfo = MintAccountInfo::new(next_account_info(account_iter)?)?;
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
 
-        let global_data: Ref<&mut [u8]> = global.data.borrow();
-        let global_fixed: &GlobalFixed = get_helper::<GlobalFixed>(&global_data, 0_u32);
-        let expected_global_vault_address: &Pubkey = global_fixed.get_vault();
+        let base_mint_key = if use_a_tree {
+            *market_fixed.get_base_a_mint()
+        } else {
+            *market_fixed.get_base_b_mint()
+        };
+        drop(market_fixed);
 
-        let global_vault: TokenAccountInfo = TokenAccountInfo::new_with_owner_and_key(
-            next_account_info(account_iter)?,
-            mint.info.key,
-            &expected_global_vault_address,
-            &expected_global_vault_address,
-        )?;
-        drop(global_data);
+        let base_global_info: &AccountInfo = next_account_info(account_iter)?;
+        let base_global_opt: Option<NixAccountInfo<GlobalFixed>> = if is_base_global_missing {
+            None
+        } else {
+            let base_global: NixAccountInfo<GlobalFixed> =
+                NixAccountInfo::<GlobalFixed>::new(base_global_info)?;
+            let base_global_fixed = base_global.get_fixed()?;
+            let base_global_mint: &Pubkey = base_global_fixed.get_mint();
+            require!(
+                base_global_mint == &base_mint_key,
+                NixError::InvalidGlobalMint,
+                "Invalid base global mint. expected {}, got {}",
+                base_mint_key,
+                base_global_mint,
+            )?;
+            drop(base_global_fixed);
+            Some(base_global)
+        };
+
+        let system_program: Program =
+            Program::new(next_account_info(account_iter)?, &system_program::id())?;
 
-        let token_account_info: &AccountInfo<'info> = next_account_info(account_iter)?;
-        let trader_token: TokenAccountInfo =
-            TokenAccountInfo::new_with_owner(token_account_info, mint.info.key, payer.key)?;
-        let token_program: TokenProgram = TokenProgram::new(next_account_info(account_iter)?)?;
         Ok(Self {
             payer,
-            global,
-            mint,
-            global_vault,
-            trader_token,
-            token_program,
+            market_loans,
+            market,
+            base_global_opt,
+            system_program,
         })
     }
 }
 
-/// Accounts needed to make a global trade. Scope is beyond just crate so
-/// clients can place orders on markets in testing.
-#[derive(Clone)]
-pub struct GlobalTradeAccounts<'a, 'info> {
+/// CleanExpiredOrders takes the same accounts as CancelOrder. The payer is
+/// the cranker and does not need a seat.
+pub(crate) type CleanExpiredOrdersContext<'a, 'info> = CancelOrderContext<'a, 'info>;
+
+/// SweepStrandedGas account infos
+pub(crate) struct SweepStrandedGasContext<'a, 'info> {
+    /// Seat owner. Gas prepayments are always paid by the trader placing the
+    /// global order, so this is where they go back to.
+    pub trader: &'a AccountInfo<'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
     pub global: NixAccountInfo<'a, 'info, GlobalFixed>,
+    pub is_base_a: bool,
+}
 
-    // These are required when matching a global order, not necessarily when
-    // cancelling since tokens dont move in that case.
-    pub global_vault_opt: Option<TokenAccountInfo<'a, 'info>>,
-    pub market_vault_opt: Option<TokenAccountInfo<'a, 'info>>,
-    pub token_program_opt: Option<TokenProgram<'a, 'info>>,
+impl<'a, 'info> SweepStrandedGasContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::SweepStrandedGas)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
 
-    pub system_program: Option<Program<'a, 'info>>,
+        let trader: &'a AccountInfo<'info> = next_account_info(account_iter)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        let global: NixAccountInfo<GlobalFixed> =
+            NixAccountInfo::<GlobalFixed>::new(next_account_info(account_iter)?)?;
 
-    // Trader is sending or cancelling the order. They are the one who will pay
-    // or receive gas prepayments.
-    pub gas_payer_opt: Option<Signer<'a, 'info>>,
-    pub gas_receiver_opt: Option<Signer<'a, 'info>>,
-    pub market: Pubkey,
-}
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        let global_fixed: Ref<GlobalFixed> = global.get_fixed()?;
+        let global_mint: &Pubkey = global_fixed.get_mint();
+        let is_base_a: bool = global_mint == market_fixed.get_base_a_mint();
+        require!(
+            is_base_a || global_mint == market_fixed.get_base_b_mint(),
+            NixError::InvalidGlobalMint,
+            "Global mint {} is not on the market",
+            global_mint,
+        )?;
+        drop(global_fixed);
+        drop(market_fixed);
 
-#[derive(Clone)]
-pub struct MarginfiCpiAccounts<'a, 'info> {
-    pub marginfi_group: MarginfiAccountInfo<'a, 'info, MarginfiGroup>,
-    pub marginfi_bank: MarginfiAccountInfo<'a, 'info, Bank>,
-    pub marginfi_account: MarginfiAccountInfo<'a, 'info, MarginfiAccount>,
-    pub marginfi_liquidity_vault: TokenAccountInfo<'a, 'info>,
-    pub marginfi_liquidity_vault_authority: &'a AccountInfo<'info>,
+        Ok(Self {
+            trader,
+            market,
+            global,
+            is_base_a,
+        })
+    }
 }
-pub(crate) struct PlaceOrderContext<'a, 'info> {
-    pub payer: Signer<'a, 'info>,
+
+/// CloseMarket account infos
+pub(crate) struct CloseMarketContext<'a, 'info> {
+    pub admin: Signer<'a, 'info>,
     pub market: NixAccountInfo<'a, 'info, MarketFixed>,
     pub market_loans: NixAccountInfo<'a, 'info, MarketLoansFixed>,
     pub market_signer: MarketSigner<'a, 'info>,
-    pub base_mint: MintAccountInfo<'a, 'info>,
-    pub quote_mint: MintAccountInfo<'a, 'info>,
+    pub base_a_vault: TokenAccountInfo<'a, 'info>,
+    pub base_b_vault: TokenAccountInfo<'a, 'info>,
+    pub base_a_fee_receiver: TokenAccountInfo<'a, 'info>,
+    pub base_b_fee_receiver: TokenAccountInfo<'a, 'info>,
+    /// None on the side of a one sided market that has no marginfi bank.
+    pub base_a_marginfi_account: Option<MarginfiAccountInfo<'a, 'info, MarginfiAccount>>,
+    pub base_b_marginfi_account: Option<MarginfiAccountInfo<'a, 'info, MarginfiAccount>>,
+    pub token_program: TokenProgram<'a, 'info>,
+    pub token_program_22: TokenProgram<'a, 'info>,
+    pub _marginfi_program: Program<'a, 'info>,
+    /// Accounts of the market that are only there once created. None when
+    /// the market never had one.
+    pub market_stats: Option<NixAccountInfo<'a, 'info, MarketStats>>,
+    pub market_auction: Option<NixAccountInfo<'a, 'info, MarketAuction>>,
+    pub market_insurance: Option<NixAccountInfo<'a, 'info, MarketInsurance>>,
+    pub event_queue: Option<NixAccountInfo<'a, 'info, EventQueueFixed>>,
+    pub market_registry: Option<NixAccountInfo<'a, 'info, MarketRegistryFixed>>,
+    /// Pages chained after market_loans, in order.
+    pub next_market_loans_pages: Vec<NixAccountInfo<'a, 'info, MarketLoansFixed>>,
+}
 
-    // One for each side. First is base, then is quote.
-    pub global_trade_accounts_opts: [Option<GlobalTradeAccounts<'a, 'info>>; 2],
-    pub marginfi_cpi_accounts_opts: [Option<MarginfiCpiAccounts<'a, 'info>>; 2],
+/// Index of the base A marginfi account in CloseMarket, base B follows it.
+const CLOSE_MARKET_BASE_A_MARGINFI_ACCOUNT_INDEX: usize = 8;
+/// Index of the event queue in CloseMarket.
+const CLOSE_MARKET_EVENT_QUEUE_INDEX: usize = 16;
+
+/// Takes the account CloseMarket expects at `expected_key`. An address that
+/// was never initialized has nothing to close.
+fn next_close_market_optional_account<'a, 'info>(
+    account_iter: &mut Iter<'a, AccountInfo<'info>>,
+    expected_key: &Pubkey,
+) -> Result<Option<&'a AccountInfo<'info>>, ProgramError> {
+    let info: &'a AccountInfo<'info> = next_account_info(account_iter)?;
+    require!(
+        info.key == expected_key,
+        NixError::IncorrectAccount,
+        "Expected {}, got {}",
+        expected_key,
+        info.key,
+    )?;
+    if info.data_is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(info))
 }
 
-impl<'a, 'info> PlaceOrderContext<'a, 'info> {
-    pub fn load(
-        accounts: &'a [AccountInfo<'info>],
-        use_a_tree: bool,
-    ) -> Result<Self, ProgramError> {
+/// Loads the marginfi account of one side of CloseMarket, or checks the
+/// system program placeholder of a side without a bank.
+fn next_close_market_marginfi_account<'a, 'info>(
+    account_iter: &mut Iter<'a, AccountInfo<'info>>,
+    market: &Pubkey,
+    mint: &Pubkey,
+    has_marginfi_bank: bool,
+) -> Result<Option<MarginfiAccountInfo<'a, 'info, MarginfiAccount>>, ProgramError> {
+    let info: &AccountInfo<'info> = next_account_info(account_iter)?;
+    if !has_marginfi_bank {
+        require!(
+            *info.key == system_program::id(),
+            NixError::IncorrectAccount,
+            "Expected the system program in place of the marginfi account of {}, got {}",
+            mint,
+            info.key,
+        )?;
+        return Ok(None);
+    }
+    Ok(Some(MarginfiAccountInfo::<MarginfiAccount>::new_account(
+        info, market, mint,
+    )?))
+}
+
+impl<'a, 'info> CloseMarketContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        // Whether the side really has no bank, or the market no event
+        // queue, is checked against the market below.
+        let placeholder_indexes: Vec<usize> = [
+            CLOSE_MARKET_BASE_A_MARGINFI_ACCOUNT_INDEX,
+            CLOSE_MARKET_BASE_A_MARGINFI_ACCOUNT_INDEX + 1,
+            CLOSE_MARKET_EVENT_QUEUE_INDEX,
+        ]
+        .into_iter()
+        .filter(|index| {
+            accounts
+                .get(*index)
+                .is_some_and(|account| *account.key == system_program::id())
+        })
+        .collect();
+        verify_account_slots_with_placeholders(
+            accounts,
+            NixInstruction::CloseMarket,
+            &placeholder_indexes,
+        )?;
         let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
 
-        // Does not have to be writable, but this ix will fail if removing a
-        // global or requiring expanding.
-        let payer: Signer = Signer::new(next_account_info(account_iter)?)?;
+        let admin: Signer = Signer::new_payer(next_account_info(account_iter)?)?;
         let market: NixAccountInfo<MarketFixed> =
             NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
         let market_loans: NixAccountInfo<MarketLoansFixed> =
             NixAccountInfo::<MarketLoansFixed>::new(next_account_info(account_iter)?)?;
         let market_signer = MarketSigner::new(next_account_info(account_iter)?, market.key)?;
 
-        let system_program: Program =
-            Program::new(next_account_info(account_iter)?, &system_program::id())?;
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        require!(
+            market_fixed.get_admin() == admin.key,
+            NixError::InvalidAdminKey,
+            "Invalid admin. expected {}, got {}",
+            market_fixed.get_admin(),
+            admin.key,
+        )?;
 
-        let mut global_trade_accounts_opts: [Option<GlobalTradeAccounts<'a, 'info>>; 2] =
-            [None, None];
-        let mut marginfi_cpi_accounts_opts: [Option<MarginfiCpiAccounts<'a, 'info>>; 2] =
-            [None, None];
+        let market_loans_fixed: Ref<MarketLoansFixed> = market_loans.get_fixed()?;
+        require!(
+            market_loans_fixed.market == *market.key,
+            NixError::IncorrectAccount,
+            "Market loans account belongs to market {}",
+            market_loans_fixed.market,
+        )?;
+        drop(market_loans_fixed);
 
-        {
-            let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        let base_a_mint: Pubkey = *market_fixed.get_base_a_mint();
+        let base_b_mint: Pubkey = *market_fixed.get_base_b_mint();
 
-            // determine primary base (this will determine which of the trees we will use)
-            let (
-                base_mint_key,
-                quote_mint_key,
-                base_vault_key,
-                quote_vault_key,
-                base_group_key,
-                quote_group_key,
-                base_bank_key,
-                quote_bank_key,
-                base_account_key,
-                quote_account_key,
-            ) = if use_a_tree {
-                (
-                    *market_fixed.get_base_a_mint(),
-                    *market_fixed.get_base_b_mint(),
-                    *market_fixed.get_base_a_vault(),
-                    *market_fixed.get_base_b_vault(),
-                    *market_fixed.get_base_a_marginfi_group(),
-                    *market_fixed.get_base_b_marginfi_group(),
-                    *market_fixed.get_base_a_marginfi_bank(),
-                    *market_fixed.get_base_b_marginfi_bank(),
-                    *market_fixed.get_base_a_marginfi_account(),
-                    *market_fixed.get_base_b_marginfi_account(),
-                )
-            } else {
-                (
-                    *market_fixed.get_base_b_mint(),
-                    *market_fixed.get_base_a_mint(),
-                    *market_fixed.get_base_b_vault(),
-                    *market_fixed.get_base_a_vault(),
-                    *market_fixed.get_base_b_marginfi_group(),
-                    *market_fixed.get_base_a_marginfi_group(),
-                    *market_fixed.get_base_b_marginfi_bank(),
-                    *market_fixed.get_base_a_marginfi_bank(),
-                    *market_fixed.get_base_b_marginfi_account(),
-                    *market_fixed.get_base_a_marginfi_account(),
-                )
-            };
-            drop(market_fixed);
+        let base_a_vault: TokenAccountInfo = TokenAccountInfo::new_with_owner_and_key(
+            next_account_info(account_iter)?,
+            &base_a_mint,
+            market_signer.info.key,
+            market_fixed.get_base_a_vault(),
+        )?;
+        let base_b_vault: TokenAccountInfo = TokenAccountInfo::new_with_owner_and_key(
+            next_account_info(account_iter)?,
+            &base_b_mint,
+            market_signer.info.key,
+            market_fixed.get_base_b_vault(),
+        )?;
+        let base_a_fee_receiver: TokenAccountInfo = TokenAccountInfo::new_with_owner_and_key(
+            next_account_info(account_iter)?,
+            &base_a_mint,
+            market_signer.info.key,
+            market_fixed.get_base_a_fee_receiver(),
+        )?;
+        let base_b_fee_receiver: TokenAccountInfo = TokenAccountInfo::new_with_owner_and_key(
+            next_account_info(account_iter)?,
+            &base_b_mint,
+            market_signer.info.key,
+            market_fixed.get_base_b_fee_receiver(),
+        )?;
+        let has_base_a_marginfi_bank: bool = market_fixed.has_marginfi_bank(true);
+        let has_base_b_marginfi_bank: bool = market_fixed.has_marginfi_bank(false);
+        let has_event_queue: bool = market_fixed.has_event_queue();
+        let event_queue_key: Pubkey = if has_event_queue {
+            *market_fixed.get_event_queue()
+        } else {
+            system_program::id()
+        };
+        drop(market_fixed);
 
-            let mint1_ai = next_account_info(account_iter)?;
-            let mint2_ai = next_account_info(account_iter)?;
+        let base_a_marginfi_account: Option<MarginfiAccountInfo<MarginfiAccount>> =
+            next_close_market_marginfi_account(
+                account_iter,
+                market.key,
+                &base_a_mint,
+                has_base_a_marginfi_bank,
+            )?;
+        let base_b_marginfi_account: Option<MarginfiAccountInfo<MarginfiAccount>> =
+            next_close_market_marginfi_account(
+                account_iter,
+                market.key,
+                &base_b_mint,
+                has_base_b_marginfi_bank,
+            )?;
 
-            let (base_mint_ai, quote_mint_ai) =
-                if base_mint_key == *mint1_ai.key && quote_mint_key == *mint2_ai.key {
-                    (&mint1_ai, &mint2_ai)
-                } else if base_mint_key == *mint2_ai.key && quote_mint_key == *mint1_ai.key {
-                    (&mint2_ai, &mint1_ai)
-                } else {
-                    return Err(NixError::InvalidMint.into());
-                };
-            let base_mint: MintAccountInfo<'a, 'info> = MintAccountInfo::new(base_mint_ai)?;
-            let quote_mint: MintAccountInfo<'a, 'info> = MintAccountInfo::new(quote_mint_ai)?;
+        let token_program: TokenProgram = TokenProgram::new(next_account_info(account_iter)?)?;
+        let token_program_22: TokenProgram = TokenProgram::new(next_account_info(account_iter)?)?;
+        let _marginfi_program: Program =
+            Program::new(next_account_info(account_iter)?, &marginfi::ID)?;
+
+        let (market_stats_key, _bump) = get_market_stats_address(market.key);
+        let market_stats: Option<NixAccountInfo<MarketStats>> =
+            next_close_market_optional_account(account_iter, &market_stats_key)?
+                .map(NixAccountInfo::<MarketStats>::new)
+                .transpose()?;
+        let (market_auction_key, _bump) = get_market_auction_address(market.key);
+        let market_auction: Option<NixAccountInfo<MarketAuction>> =
+            next_close_market_optional_account(account_iter, &market_auction_key)?
+                .map(NixAccountInfo::<MarketAuction>::new)
+                .transpose()?;
+        let (market_insurance_key, _bump) = get_market_insurance_address(market.key);
+        let market_insurance: Option<NixAccountInfo<MarketInsurance>> =
+            next_close_market_optional_account(account_iter, &market_insurance_key)?
+                .map(NixAccountInfo::<MarketInsurance>::new)
+                .transpose()?;
+        let event_queue_info: &AccountInfo = next_account_info(account_iter)?;
+        require!(
+            *event_queue_info.key == event_queue_key,
+            NixError::IncorrectAccount,
+            "Expected event queue {}, got {}",
+            event_queue_key,
+            event_queue_info.key,
+        )?;
+        let event_queue: Option<NixAccountInfo<EventQueueFixed>> = if has_event_queue {
+            Some(NixAccountInfo::<EventQueueFixed>::new(event_queue_info)?)
+        } else {
+            None
+        };
+        let (market_registry_key, _bump) = get_market_registry_address(&base_a_mint, &base_b_mint);
+        let market_registry: Option<NixAccountInfo<MarketRegistryFixed>> =
+            next_close_market_optional_account(account_iter, &market_registry_key)?
+                .map(NixAccountInfo::<MarketRegistryFixed>::new)
+                .transpose()?;
+
+        // Every page chained after market_loans follows it in order, so none
+        // is left behind holding rent or loans.
+        let mut next_market_loans_pages: Vec<NixAccountInfo<MarketLoansFixed>> = Vec::new();
+        let mut next_page: Pubkey = market_loans.get_fixed()?.next_page;
+        while next_page != Pubkey::default() {
+            let info: &AccountInfo = next_account_info(account_iter)?;
+            require_account!(
+                *info.key == next_page && info.is_writable,
+                NixError::IncorrectAccount,
+                NixInstruction::CloseMarket,
+                last_account_index(accounts, account_iter),
+                "Expected writable market loans page {}, got {}",
+                next_page,
+                info.key,
+            )?;
+            let page: NixAccountInfo<MarketLoansFixed> =
+                NixAccountInfo::<MarketLoansFixed>::new(info)?;
+            let page_fixed: Ref<MarketLoansFixed> = page.get_fixed()?;
+            require!(
+                page_fixed.market == *market.key
+                    && page_fixed.next_page != *market_loans.key
+                    && page_fixed.next_page != *info.key
+                    && next_market_loans_pages
+                        .iter()
+                        .all(|previous_page| *previous_page.key != page_fixed.next_page),
+                NixError::IncorrectAccount,
+                "Market loans page {} is not chained from market {}",
+                info.key,
+                market.key,
+            )?;
+            next_page = page_fixed.next_page;
+            drop(page_fixed);
+            next_market_loans_pages.push(page);
+        }
 
-            for _ in 0..2 {
-                let next_account_info_or: Result<&AccountInfo<'info>, ProgramError> =
-                    next_account_info(account_iter);
-                if next_account_info_or.is_ok() {
-                    let global_or: Result<NixAccountInfo<'a, 'info, GlobalFixed>, ProgramError> =
-                        NixAccountInfo::<GlobalFixed>::new(next_account_info_or?);
+        Ok(Self {
+            admin,
+            market,
+            market_loans,
+            market_signer,
+            base_a_vault,
+            base_b_vault,
+            base_a_fee_receiver,
+            base_b_fee_receiver,
+            base_a_marginfi_account,
+            base_b_marginfi_account,
+            token_program,
+            token_program_22,
+            _marginfi_program,
+            market_stats,
+            market_auction,
+            market_insurance,
+            event_queue,
+            market_registry,
+            next_market_loans_pages,
+        })
+    }
+}
 
-                    // If a client blindly fills in the global account and vault,
-                    // then handle that case and allow them to try to work without
-                    // the global accounts.
-                    if global_or.is_err() {
-                        let _global_vault: Result<&AccountInfo<'info>, ProgramError> =
-                            next_account_info(account_iter);
-                        let _market_vault: Result<&AccountInfo<'info>, ProgramError> =
-                            next_account_info(account_iter);
-                        let _token_program: Result<&AccountInfo<'info>, ProgramError> =
-                            next_account_info(account_iter);
-                        continue;
-                    }
+/// Loans accounts are chained in pages. The page passed to an instruction that
+/// can create loans has to belong to the market and still have room.
+fn verify_market_loans_page(
+    market_loans: &NixAccountInfo<MarketLoansFixed>,
+    market: &Pubkey,
+) -> Result<(), ProgramError> {
+    let market_loans_fixed: Ref<MarketLoansFixed> = market_loans.get_fixed()?;
+    require!(
+        market_loans_fixed.market == *market,
+        NixError::IncorrectAccount,
+        "Market loans account belongs to market {}, expected {}",
+        market_loans_fixed.market,
+        market,
+    )?;
+    require!(
+        !market_loans_fixed.is_full(),
+        NixError::MarketLoansPageFull,
+        "Market loans account is full, next page is {}",
+        market_loans_fixed.next_page,
+    )?;
+    Ok(())
+}
 
-                    let global: NixAccountInfo<'a, 'info, GlobalFixed> = global_or.unwrap();
-                    let global_data: Ref<&mut [u8]> = global.data.borrow();
-                    let global_fixed: &GlobalFixed = get_helper::<GlobalFixed>(&global_data, 0_u32);
-                    let expected_global_vault_address: &Pubkey = global_fixed.get_vault();
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const NUM_ROLES: usize = 8;
+    const MARKET: usize = 0;
+    const GLOBAL: usize = 1;
+    const GLOBAL_VAULT: usize = 2;
+    const MARKET_VAULT: usize = 3;
+    const MARGINFI_BANK: usize = 4;
+    const MARGINFI_ACCOUNT: usize = 5;
+    const LIQUIDITY_VAULT: usize = 6;
+    const TRADER_TOKEN: usize = 7;
+
+    /// Runs the check over one account per role where the account of
+    /// aliased_role is swapped for the one of target_role.
+    fn check_aliasing(aliased_role: usize, target_role: usize) -> Result<(), ProgramError> {
+        let mut keys: [Pubkey; NUM_ROLES] = [Pubkey::default(); NUM_ROLES];
+        for key in keys.iter_mut() {
+            *key = Pubkey::new_unique();
+        }
+        keys[aliased_role] = keys[target_role];
+        let owner: Pubkey = Pubkey::new_unique();
+        let mut lamports: [u64; NUM_ROLES] = [0; NUM_ROLES];
+        let mut data: [[u8; 0]; NUM_ROLES] = [[]; NUM_ROLES];
+        let accounts: Vec<AccountInfo> = keys
+            .iter()
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .map(|((key, lamports), data)| {
+                AccountInfo::new(key, false, true, lamports, data, &owner, false, 0)
+            })
+            .collect();
+        let roles: Vec<&AccountInfo> = accounts.iter().collect();
+        verify_distinct_roles(&accounts, NixInstruction::PlaceOrder, &roles)
+    }
 
-                    let mint: MintAccountInfo<'a, 'info> =
-                        if global_fixed.get_mint() == base_mint.info.key {
-                            base_mint.clone()
-                        } else {
-                            quote_mint.clone()
-                        };
+    #[test]
+    fn test_distinct_roles() {
+        // Aliasing a role with itself leaves every account distinct.
+        assert!(check_aliasing(MARKET, MARKET).is_ok());
+    }
 
-                    let (index, expected_market_vault_address) =
-                        if base_mint.info.key == mint.info.key {
-                            (0, &base_vault_key)
-                        } else {
-                            require!(
-                                quote_mint.info.key == mint.info.key,
-                                NixError::MissingGlobal,
-                                "Unexpected global mint",
-                            )?;
-                            (1, &quote_vault_key)
-                        };
+    #[test]
+    fn test_aliased_roles() {
+        for (aliased_role, target_role) in [
+            (MARKET_VAULT, GLOBAL_VAULT),
+            (GLOBAL, MARKET),
+            (LIQUIDITY_VAULT, MARKET_VAULT),
+            (LIQUIDITY_VAULT, GLOBAL_VAULT),
+            (TRADER_TOKEN, MARKET_VAULT),
+            (TRADER_TOKEN, GLOBAL_VAULT),
+            (MARGINFI_ACCOUNT, MARGINFI_BANK),
+        ] {
+            assert_eq!(
+                check_aliasing(aliased_role, target_role),
+                Err(NixError::DuplicateAccount.into()),
+            );
+        }
+    }
 
-                    let global_vault: TokenAccountInfo<'a, 'info> =
-                        TokenAccountInfo::new_with_owner_and_key(
-                            next_account_info(account_iter)?,
-                            mint.info.key,
-                            &expected_global_vault_address,
-                            &expected_global_vault_address,
-                        )?;
-                    drop(global_data);
+    /// A market header of scrambled bytes, so no two of its keys are equal.
+    fn new_scrambled_market_fixed() -> MarketFixed {
+        let bytes: Vec<u8> = (0..std::mem::size_of::<MarketFixed>() as u32)
+            .map(|index: u32| (index.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        bytemuck::pod_read_unaligned(&bytes)
+    }
 
-                    let market_vault: TokenAccountInfo<'a, 'info> =
-                        TokenAccountInfo::new_with_owner_and_key(
-                            next_account_info(account_iter)?,
-                            mint.info.key,
-                            &expected_market_vault_address,
-                            &expected_market_vault_address,
-                        )?;
-                    let token_program: TokenProgram<'a, 'info> =
-                        TokenProgram::new(next_account_info(account_iter)?)?;
+    #[test]
+    fn test_market_keys() {
+        let market_fixed: MarketFixed = new_scrambled_market_fixed();
+        let base_a_keys: [Pubkey; 5] = [
+            *market_fixed.get_base_a_mint(),
+            *market_fixed.get_base_a_vault(),
+            *market_fixed.get_base_a_marginfi_group(),
+            *market_fixed.get_base_a_marginfi_bank(),
+            *market_fixed.get_base_a_marginfi_account(),
+        ];
+        let base_b_keys: [Pubkey; 5] = [
+            *market_fixed.get_base_b_mint(),
+            *market_fixed.get_base_b_vault(),
+            *market_fixed.get_base_b_marginfi_group(),
+            *market_fixed.get_base_b_marginfi_bank(),
+            *market_fixed.get_base_b_marginfi_account(),
+        ];
+        for (base_a_key, base_b_key) in base_a_keys.iter().zip(base_b_keys.iter()) {
+            assert_ne!(base_a_key, base_b_key);
+        }
 
-                    global_trade_accounts_opts[index] = Some(GlobalTradeAccounts {
-                        global,
-                        global_vault_opt: Some(global_vault),
-                        market_vault_opt: Some(market_vault),
-                        token_program_opt: Some(token_program),
-                        system_program: Some(system_program.clone()),
-                        gas_payer_opt: Some(payer.clone()),
-                        gas_receiver_opt: Some(payer.clone()),
-                        market: *market.info.key,
-                    })
-                }
-            }
+        // The tree of the order picks the base side.
+        for (use_a_tree, base_keys, quote_keys) in [
+            (true, base_a_keys, base_b_keys),
+            (false, base_b_keys, base_a_keys),
+        ] {
+            let MarketKeys {
+                mints,
+                vaults,
+                marginfi_groups,
+                marginfi_banks,
+                marginfi_accounts,
+            } = MarketKeys::new(&market_fixed, use_a_tree);
+            let [base_side_keys, quote_side_keys] = [0, 1].map(|side: usize| {
+                [
+                    mints[side],
+                    vaults[side],
+                    marginfi_groups[side],
+                    marginfi_banks[side],
+                    marginfi_accounts[side],
+                ]
+            });
+            assert_eq!(base_side_keys, base_keys);
+            assert_eq!(quote_side_keys, quote_keys);
+        }
+    }
+}
 
-            for _ in 0..2 {
-                let marginfi_group_account_raw = next_account_info(account_iter)?;
+/// SetOrderRateLimit account infos
+pub(crate) struct SetOrderRateLimitContext<'a, 'info> {
+    pub admin: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+}
 
-                let (
-                    index,
-                    mint,
-                    expected_marginfi_group,
-                    expected_marginfi_bank,
-                    expected_marginfi_account,
-                ) = if *marginfi_group_account_raw.key == base_group_key {
-                    (
-                        0,
-                        base_mint.info.key,
-                        base_group_key,
-                        base_bank_key,
-                        base_account_key,
-                    )
-                } else if quote_group_key == *marginfi_group_account_raw.key {
-                    (
-                        1,
-                        quote_mint.info.key,
-                        quote_group_key,
-                        quote_bank_key,
-                        quote_account_key,
-                    )
-                } else {
-                    return Err(NixError::InvalidDepositAccounts.into());
-                };
+impl<'a, 'info> SetOrderRateLimitContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::SetOrderRateLimit)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
 
-                let marginfi_group: MarginfiAccountInfo<MarginfiGroup> =
-                    MarginfiAccountInfo::<MarginfiGroup>::new_group(marginfi_group_account_raw)?;
+        let admin: Signer = Signer::new(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
 
-                require!(
-                    expected_marginfi_group == *marginfi_group.info.key,
-                    NixError::InvalidMarginfiGroup,
-                    "Invalid Marginfi Group >> expected: {:?}, actual: {:?}",
-                    expected_marginfi_group,
-                    marginfi_group.info.key
-                )?;
-                let marginfi_bank: MarginfiAccountInfo<Bank> =
-                    MarginfiAccountInfo::<Bank>::new_bank(next_account_info(account_iter)?)?;
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        require!(
+            market_fixed.get_admin() == admin.key,
+            NixError::InvalidAdminKey,
+            "Invalid admin. expected {}, got {}",
+            market_fixed.get_admin(),
+            admin.key,
+        )?;
+        drop(market_fixed);
 
-                require!(
-                    expected_marginfi_bank == *marginfi_bank.info.key,
-                    NixError::InvalidMarginfiBank,
-                    "Invalid Marginfi bank >> expected: {:?}, actual: {:?}",
-                    expected_marginfi_bank,
-                    marginfi_bank.info.key
-                )?;
-                let marginfi_account: MarginfiAccountInfo<MarginfiAccount> =
-                    MarginfiAccountInfo::<MarginfiAccount>::new_account(
-                        next_account_info(account_iter)?,
-                        market.info.key,
-                        mint,
-                    )?;
-                require!(
-                    expected_marginfi_account == *marginfi_account.info.key,
-                    NixError::InvalidMarginfiAccount,
-                    "Invalid Marginfi account >> expected: {:?}, actual: {:?}",
-                    expected_marginfi_account,
-                    marginfi_account.info.key
-                )?;
+        Ok(Self { admin, market })
+    }
+}
 
-                let marginfi_liquidity_vault: TokenAccountInfo =
-                    TokenAccountInfo::new(next_account_info(account_iter)?, mint)?;
-                validate_marginfi_liquidity_vault(
-                    marginfi_liquidity_vault.as_ref(),
-                    &marginfi_bank,
-                )?;
+/// SetAllowedCaller account infos
+pub(crate) struct SetAllowedCallerContext<'a, 'info> {
+    pub admin: Signer<'a, 'info>,
+    pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+}
 
-                let marginfi_liquidity_vault_authority = next_account_info(account_iter)?;
-                validate_marginfi_liquidity_vault_authority(
-                    marginfi_liquidity_vault_authority,
-                    marginfi_bank.info,
-                )?;
+impl<'a, 'info> SetAllowedCallerContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::SetAllowedCaller)?;
+        let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
 
-                marginfi_cpi_accounts_opts[index] = Some(MarginfiCpiAccounts {
-                    marginfi_group,
-                    marginfi_bank,
-                    marginfi_account,
-                    marginfi_liquidity_vault,
-                    marginfi_liquidity_vault_authority,
-                });
-            }
+        let admin: Signer = Signer::new(next_account_info(account_iter)?)?;
+        let market: NixAccountInfo<MarketFixed> =
+            NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
 
-            Ok(Self {
-                payer,
-                market,
-                market_loans,
-                market_signer,
-                base_mint,
-                quote_mint,
-                global_trade_accounts_opts,
-                marginfi_cpi_accounts_opts,
-            })
-        }
+        let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
+        require!(
+            market_fixed.get_admin() == admin.key,
+            NixError::InvalidAdminKey,
+            "Invalid admin. expected {}, got {}",
+            market_fixed.get_admin(),
+            admin.key,
+        )?;
+        drop(market_fixed);
+
+        Ok(Self { admin, market })
     }
 }
 
-/// CreateMarketLoanAccount account infos
-pub(crate) struct CreateMarketLoanAccountContext<'a, 'info> {
+/// AcknowledgeBankConfigChange account infos
+pub(crate) struct AcknowledgeBankConfigChangeContext<'a, 'info> {
     pub admin: Signer<'a, 'info>,
-    pub market_loan_account: NixAccountInfo<'a, 'info, MarketLoansFixed>,
     pub market: NixAccountInfo<'a, 'info, MarketFixed>,
+    pub marginfi_bank: MarginfiAccountInfo<'a, 'info, Bank>,
+    pub is_base_a: bool,
 }
 
-impl<'a, 'info> CreateMarketLoanAccountContext<'a, 'info> {
+impl<'a, 'info> AcknowledgeBankConfigChangeContext<'a, 'info> {
     pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::AcknowledgeBankConfigChange)?;
         let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
 
         let admin: Signer = Signer::new(next_account_info(account_iter)?)?;
-        let market_loan_account: NixAccountInfo<MarketLoansFixed> =
-            NixAccountInfo::<MarketLoansFixed>::new(next_account_info(account_iter)?)?;
         let market: NixAccountInfo<MarketFixed> =
             NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
+        let marginfi_bank: MarginfiAccountInfo<Bank> =
+            MarginfiAccountInfo::new_bank(next_account_info(account_iter)?)?;
 
         let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
         require!(
@@ -728,76 +3729,49 @@ impl<'a, 'info> CreateMarketLoanAccountContext<'a, 'info> {
             market_fixed.get_admin(),
             admin.key,
         )?;
+        let is_base_a_opt: Option<bool> = market_fixed.is_base_a_marginfi_bank(marginfi_bank.key);
+        require!(
+            is_base_a_opt.is_some(),
+            NixError::InvalidMarginfiBank,
+            "Marginfi bank {} is not a bank of the market",
+            marginfi_bank.key,
+        )?;
         drop(market_fixed);
 
         Ok(Self {
             admin,
-            market_loan_account,
             market,
+            marginfi_bank,
+            is_base_a: is_base_a_opt.unwrap(),
         })
     }
 }
 
-#[derive(Clone)]
-pub struct CancelOrderGlobalTradeAccounts<'a, 'info> {
-    pub global: NixAccountInfo<'a, 'info, GlobalFixed>,
-    pub system_program: Option<Program<'a, 'info>>,
-    pub gas_receiver_opt: Option<Signer<'a, 'info>>,
-    pub market: Pubkey,
-}
-
-pub(crate) struct CancelOrderContext<'a, 'info> {
-    pub payer: Signer<'a, 'info>,
-    pub market_loans: NixAccountInfo<'a, 'info, MarketLoansFixed>,
+/// SetDebugLogs account infos
+pub(crate) struct SetDebugLogsContext<'a, 'info> {
+    pub admin: Signer<'a, 'info>,
     pub market: NixAccountInfo<'a, 'info, MarketFixed>,
-    pub base_global: NixAccountInfo<'a, 'info, GlobalFixed>,
-    pub system_program: Program<'a, 'info>,
 }
 
-impl<'a, 'info> CancelOrderContext<'a, 'info> {
-    pub fn load(
-        accounts: &'a [AccountInfo<'info>],
-        use_a_tree: bool,
-    ) -> Result<Self, ProgramError> {
+impl<'a, 'info> SetDebugLogsContext<'a, 'info> {
+    pub fn load(accounts: &'a [AccountInfo<'info>]) -> Result<Self, ProgramError> {
+        verify_account_slots(accounts, NixInstruction::SetDebugLogs)?;
         let account_iter: &mut Iter<AccountInfo<'info>> = &mut accounts.iter();
 
-        let payer: Signer = Signer::new(next_account_info(account_iter)?)?;
-        let market_loans: NixAccountInfo<MarketLoansFixed> =
-            NixAccountInfo::<MarketLoansFixed>::new(next_account_info(account_iter)?)?;
+        let admin: Signer = Signer::new(next_account_info(account_iter)?)?;
         let market: NixAccountInfo<MarketFixed> =
             NixAccountInfo::<MarketFixed>::new(next_account_info(account_iter)?)?;
 
         let market_fixed: Ref<MarketFixed> = market.get_fixed()?;
-
-        let base_mint_key = if use_a_tree {
-            *market_fixed.get_base_a_mint()
-        } else {
-            *market_fixed.get_base_b_mint()
-        };
-        drop(market_fixed);
-
-        let base_global: NixAccountInfo<GlobalFixed> =
-            NixAccountInfo::<GlobalFixed>::new(next_account_info(account_iter)?)?;
-        let base_global_fixed = base_global.get_fixed()?;
-        let base_global_mint: &Pubkey = base_global_fixed.get_mint();
         require!(
-            base_global_mint == &base_mint_key,
-            NixError::InvalidGlobalMint,
-            "Invalid base global mint. expected {}, got {}",
-            base_mint_key,
-            base_global_mint,
+            market_fixed.get_admin() == admin.key,
+            NixError::InvalidAdminKey,
+            "Invalid admin. expected {}, got {}",
+            market_fixed.get_admin(),
+            admin.key,
         )?;
-        drop(base_global_fixed);
-
-        let system_program: Program =
-            Program::new(next_account_info(account_iter)?, &system_program::id())?;
+        drop(market_fixed);
 
-        Ok(Self {
-            payer,
-            market_loans,
-            market,
-            base_global,
-            system_program,
-        })
+        Ok(Self { admin, market })
     }
 }
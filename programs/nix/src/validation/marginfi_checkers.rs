@@ -3,7 +3,8 @@ use std::{cell::Ref, ops::Deref};
 use bytemuck::{Pod, Zeroable};
 use hypertree::{get_helper, Get};
 use marginfi::{
-    constants::LIQUIDITY_VAULT_AUTHORITY_SEED, state::marginfi_group::Bank,
+    constants::LIQUIDITY_VAULT_AUTHORITY_SEED,
+    state::{marginfi_account::MarginfiAccount, marginfi_group::Bank},
     ID as MARGINFI_PROGRAM_ID,
 };
 
@@ -168,6 +169,47 @@ pub fn validate_marginfi_account(
     )
 }
 
+/// A trader's own marginfi account used for cross margin. It has to be in one
+/// of the market's groups and have the expected authority, the trader when
+/// it is registered and the market signer once it has been delegated.
+pub fn validate_cross_margin_account(
+    account: &AccountInfo,
+    marginfi_groups: &[Pubkey; 2],
+    expected_authority: &Pubkey,
+) -> ProgramResult {
+    let data = account.try_borrow_data()?;
+    require!(
+        account.owner == &MARGINFI_PROGRAM_ID,
+        NixError::InvalidMarginfiAccount,
+        "Invalid Marginfi account owner: expected: {}, actual: {}",
+        MARGINFI_PROGRAM_ID,
+        account.owner
+    )?;
+    require!(
+        &data[0..8] == MARGINFI_ACCOUNT_DISCRIMINATOR,
+        NixError::InvalidMarginfiAccount,
+        "Invalid Marginfi Account >> wrong Discriminator: expected: {:?}, actual: {:?}",
+        MARGINFI_ACCOUNT_DISCRIMINATOR,
+        &data[0..8]
+    )?;
+    let marginfi_account: &MarginfiAccount = bytemuck::from_bytes(&data[8..]);
+    require!(
+        marginfi_groups.contains(&marginfi_account.group),
+        NixError::InvalidMarginfiGroup,
+        "Marginfi account {} is in group {}, not a group of the market",
+        account.key,
+        marginfi_account.group
+    )?;
+    require!(
+        marginfi_account.authority == *expected_authority,
+        NixError::InvalidMarginfiAccount.into(),
+        "Marginfi account {} authority expected: {}, actual: {}",
+        account.key,
+        expected_authority,
+        marginfi_account.authority
+    )
+}
+
 pub fn validate_marginfi_account_pda(
     account: &AccountInfo,
     market: &AccountInfo,
@@ -1,10 +1,15 @@
 pub mod token_checkers;
 pub mod nix_checkers;
+#[cfg(feature = "program")]
 pub mod loaders;
 pub mod solana_checkers;
+#[cfg(feature = "program")]
 pub mod marginfi_checkers;
 
+// Derivations moved to crate::addresses, kept here for existing callers.
+pub use crate::addresses::*;
 pub use token_checkers::*;
 pub use nix_checkers::*;
 pub use solana_checkers::*;
+#[cfg(feature = "program")]
 pub use marginfi_checkers::*;
\ No newline at end of file
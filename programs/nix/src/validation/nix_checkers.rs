@@ -1,12 +1,23 @@
 use bytemuck::Pod;
-use hypertree::{get_helper, Get};
+use hypertree::{get_helper, get_mut_helper, Get};
 use solana_program::{
     account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
     pubkey::Pubkey,
 };
-use std::{cell::Ref, mem::size_of, ops::Deref};
+#[cfg(feature = "program")]
+use solana_program::{rent::Rent, sysvar::Sysvar};
+use std::{
+    cell::{Ref, RefMut},
+    mem::size_of,
+    ops::Deref,
+};
 
 use crate::require;
+#[cfg(feature = "program")]
+use crate::utils::create_pda_account;
+
+#[cfg(feature = "program")]
+use super::{Program, Signer};
 
 /// Validation for Nix accounts.
 #[derive(Clone)]
@@ -17,7 +28,33 @@ pub struct NixAccountInfo<'a, 'info, T: NixAccount + Pod + Clone> {
 }
 
 impl<'a, 'info, T: NixAccount + Get + Clone> NixAccountInfo<'a, 'info, T> {
-    pub fn new(
+    pub fn new(info: &'a AccountInfo<'info>) -> Result<NixAccountInfo<'a, 'info, T>, ProgramError> {
+        verify_owned_by_nix(info.owner)?;
+
+        let bytes: Ref<&mut [u8]> = info.try_borrow_data()?;
+        // Headers that grew can be longer than an account not migrated yet.
+        require!(
+            bytes.len() >= size_of::<T>(),
+            ProgramError::InvalidAccountData,
+            "Account length {} is shorter than its header {}",
+            bytes.len(),
+            size_of::<T>()
+        )?;
+        let (header_bytes, _) = bytes.split_at(size_of::<T>());
+        let header: &T = get_helper::<T>(header_bytes, 0_u32);
+        header.verify_discriminant()?;
+        header.verify_version()?;
+        header.verify_data_len(bytes.len())?;
+
+        Ok(Self {
+            info,
+            phantom: std::marker::PhantomData,
+        })
+    }
+
+    /// Same as new but also accepts accounts on an older layout. Only for
+    /// migrating them.
+    pub fn new_any_version(
         info: &'a AccountInfo<'info>,
     ) -> Result<NixAccountInfo<'a, 'info, T>, ProgramError> {
         verify_owned_by_nix(info.owner)?;
@@ -44,6 +81,48 @@ impl<'a, 'info, T: NixAccount + Get + Clone> NixAccountInfo<'a, 'info, T> {
         })
     }
 
+    /// Same as new_init for an account at an address of this program. The
+    /// account is allocated here with the size of the header, paid by the
+    /// payer, instead of by the client. Seeds have to include the bump.
+    #[cfg(feature = "program")]
+    pub fn new_init_pda(
+        info: &'a AccountInfo<'info>,
+        payer: &Signer<'a, 'info>,
+        system_program: &Program<'a, 'info>,
+        seeds: Vec<Vec<u8>>,
+    ) -> Result<NixAccountInfo<'a, 'info, T>, ProgramError> {
+        let seed_slices: Vec<&[u8]> = seeds.iter().map(|seed| seed.as_slice()).collect();
+        let expected_key: Pubkey = Pubkey::create_program_address(&seed_slices, &crate::ID)
+            .map_err(|_| ProgramError::InvalidSeeds)?;
+        require!(
+            expected_key == *info.key,
+            ProgramError::InvalidSeeds,
+            "Incorrect PDA expected: {} actual: {}",
+            expected_key,
+            info.key
+        )?;
+        create_pda_account(
+            payer.as_ref(),
+            info,
+            system_program.as_ref(),
+            &crate::ID,
+            &Rent::get()?,
+            size_of::<T>() as u64,
+            seeds,
+        )?;
+        Self::new_init(info)
+    }
+
+    /// Writes the header of an account from new_init or new_init_pda. The
+    /// discriminant is checked first so the header of another type cannot be
+    /// stamped on it.
+    pub fn init_fixed(&self, fixed: T) -> ProgramResult {
+        fixed.verify_discriminant()?;
+        let mut data: RefMut<&mut [u8]> = self.info.try_borrow_mut_data()?;
+        *get_mut_helper::<T>(&mut data[..], 0_u32) = fixed;
+        Ok(())
+    }
+
     pub fn get_fixed(&self) -> Result<Ref<'_, T>, ProgramError> {
         let data: Ref<&mut [u8]> = self.info.try_borrow_data()?;
         Ok(Ref::map(data, |data| {
@@ -70,9 +149,19 @@ impl<'a, 'info, T: NixAccount + Pod + Clone> AsRef<AccountInfo<'info>>
 
 pub trait NixAccount {
     fn verify_discriminant(&self) -> ProgramResult;
+
+    /// Accounts with a versioned layout fail here until they are migrated.
+    fn verify_version(&self) -> ProgramResult {
+        Ok(())
+    }
+
+    /// Accounts whose header grew fail here until they are migrated.
+    fn verify_data_len(&self, _data_len: usize) -> ProgramResult {
+        Ok(())
+    }
 }
 
-fn verify_owned_by_nix(owner: &Pubkey) -> ProgramResult {
+pub(crate) fn verify_owned_by_nix(owner: &Pubkey) -> ProgramResult {
     require!(
         owner == &crate::ID,
         ProgramError::IllegalOwner,
@@ -95,9 +184,8 @@ fn verify_uninitialized<T: Pod>(info: &AccountInfo) -> ProgramResult {
 
     // This can't happen because for Market, we increase the size of the account
     // with a free block when it gets init, so the first check fails. For
-    // global, we dont use new_init because the account is a PDA, so it is not
-    // at an existing account. Keep the check for thoroughness in case a new
-    // type is ever added.
+    // global, new_init_pda has just allocated the account. Keep the check for
+    // thoroughness in case a new type is ever added.
     require!(
         bytes.iter().all(|&byte| byte == 0),
         ProgramError::InvalidAccountData,
@@ -112,42 +200,3 @@ pub fn validate_init_market_account<'a, 'info, T: Pod + Clone>(
     verify_owned_by_nix(info.owner)?;
     verify_uninitialized::<T>(info)
 }
-
-macro_rules! global_seeds {
-    ( $mint:expr ) => {
-        &[b"global", $mint.as_ref()]
-    };
-}
-
-#[macro_export]
-macro_rules! global_seeds_with_bump {
-    ( $mint:expr, $bump:expr ) => {
-        &[&[b"global", $mint.as_ref(), &[$bump]]]
-    };
-}
-#[macro_export]
-macro_rules! nix_marginfi_account_seeds {
-    ($market:expr, $mint:expr) => {
-        &[b"nix_marginfi_account", $market.as_ref(), $mint.as_ref()]
-    };
-}
-
-#[macro_export]
-macro_rules! nix_marginfi_account_seeds_with_bump {
-    ( $market:expr, $mint:expr, $bump:expr ) => {
-        &[&[
-            b"nix_marginfi_account",
-            $market.as_ref(),
-            $mint.as_ref(),
-            &[$bump],
-        ]]
-    };
-}
-
-pub fn get_nix_marginfi_account_address(market: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
-    Pubkey::find_program_address(nix_marginfi_account_seeds!(market, mint), &crate::ID)
-}
-
-pub fn get_global_address(mint: &Pubkey) -> (Pubkey, u8) {
-    Pubkey::find_program_address(global_seeds!(mint), &crate::ID)
-}
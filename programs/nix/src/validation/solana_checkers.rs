@@ -1,4 +1,4 @@
-use crate::require;
+use crate::{program::NixError, require};
 use solana_program::{
     account_info::AccountInfo, program_error::ProgramError, pubkey::Pubkey, system_program,entrypoint::ProgramResult
 };
@@ -161,3 +161,141 @@ pub fn validate_solana_program_accounts(system_program: &AccountInfo, spl_token:
         spl_token_2022.key
     )
 }
+
+/// Pyth receiver program, owner of the PriceUpdateV2 accounts of push feeds.
+pub const PYTH_RECEIVER_PROGRAM_ID: Pubkey =
+    solana_program::pubkey!("rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ");
+/// Pyth Lazer program, owner of the storage account with the trusted signers
+/// Lazer updates are verified against.
+pub const PYTH_LAZER_PROGRAM_ID: Pubkey =
+    solana_program::pubkey!("pytd2yyk641x7ak7mkaasSJVXh6YYZnC7wTmtgAyxPt");
+
+/// Anchor discriminator of PriceUpdateV2.
+const PRICE_UPDATE_V2_DISCRIMINATOR: [u8; 8] = [34, 241, 35, 99, 157, 126, 244, 205];
+
+/// Feed id and exponent of a Pyth push price account.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct PythPushFeed {
+    pub feed_id: [u8; 32],
+    pub exponent: i32,
+}
+
+/// Reads the feed of PriceUpdateV2 account data. The verification level in
+/// front of the price message is a borsh enum, where Partial carries the
+/// number of signatures and Full nothing.
+pub fn get_pyth_push_feed(data: &[u8]) -> Result<PythPushFeed, ProgramError> {
+    require!(
+        data.get(..8) == Some(&PRICE_UPDATE_V2_DISCRIMINATOR[..]),
+        NixError::InvalidOracleAccount,
+        "Not a Pyth price update account",
+    )?;
+    // Discriminator, then the write authority.
+    let message_offset: usize = match data.get(40) {
+        Some(0) => 42,
+        Some(1) => 41,
+        _ => return Err(NixError::InvalidOracleAccount.into()),
+    };
+    // Feed id, price, conf, then the exponent.
+    let Some(message) = data.get(message_offset..message_offset + 52) else {
+        return Err(NixError::InvalidOracleAccount.into());
+    };
+    Ok(PythPushFeed {
+        feed_id: message[..32].try_into().unwrap(),
+        exponent: i32::from_le_bytes(message[48..52].try_into().unwrap()),
+    })
+}
+
+/// Checks a Pyth push price account is a price update of the Pyth receiver
+/// and returns its feed.
+pub fn validate_pyth_push_owner(info: &AccountInfo) -> Result<PythPushFeed, ProgramError> {
+    require!(
+        *info.owner == PYTH_RECEIVER_PROGRAM_ID,
+        NixError::InvalidOracleAccount,
+        "Pyth price account {} is owned by {}",
+        info.key,
+        info.owner,
+    )?;
+    get_pyth_push_feed(&info.try_borrow_data()?)
+}
+
+/// Same as validate_pyth_push_owner, and the account has to carry
+/// expected_feed_id, independent of the oracle keys of the bank.
+pub fn validate_pyth_push_account(
+    info: &AccountInfo,
+    expected_feed_id: &[u8; 32],
+) -> Result<PythPushFeed, ProgramError> {
+    let feed: PythPushFeed = validate_pyth_push_owner(info)?;
+    require!(
+        feed.feed_id == *expected_feed_id,
+        NixError::InvalidOracleAccount,
+        "Pyth price account {} is for feed {:?}, expected {:?}",
+        info.key,
+        feed.feed_id,
+        expected_feed_id,
+    )?;
+    Ok(feed)
+}
+
+/// Checks the Pyth Lazer storage account is the one of the Lazer program.
+pub fn validate_pyth_lazer_storage_account(info: &AccountInfo) -> ProgramResult {
+    require!(
+        *info.owner == PYTH_LAZER_PROGRAM_ID,
+        NixError::InvalidOracleAccount.into(),
+        "Pyth Lazer storage {} is owned by {}",
+        info.key,
+        info.owner,
+    )
+}
+
+/// Checks a Lazer feed id from a verified update is the one expected.
+pub fn validate_pyth_lazer_feed_id(feed_id: u32, expected_feed_id: u32) -> ProgramResult {
+    require!(
+        feed_id == expected_feed_id,
+        NixError::InvalidOracleAccount.into(),
+        "Pyth Lazer feed {}, expected {}",
+        feed_id,
+        expected_feed_id,
+    )
+}
+
+/// Checks a Pyth price, push or Lazer, has expected_decimals decimals, so a
+/// feed of a misconfigured bank is not read at the wrong scale.
+pub fn validate_pyth_feed_exponent(exponent: i32, expected_decimals: u8) -> ProgramResult {
+    require!(
+        exponent == -(expected_decimals as i32),
+        NixError::OracleFeedDecimalsMismatch.into(),
+        "Pyth feed exponent {}, expected {} decimals",
+        exponent,
+        expected_decimals,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn new_price_update(verification_level: &[u8], exponent: i32) -> Vec<u8> {
+        let mut data: Vec<u8> = PRICE_UPDATE_V2_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&[0; 32]);
+        data.extend_from_slice(verification_level);
+        data.extend_from_slice(&[7; 32]);
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(&exponent.to_le_bytes());
+        data.extend_from_slice(&[0; 48]);
+        data
+    }
+
+    #[test]
+    fn test_get_pyth_push_feed() {
+        for verification_level in [&[0, 3][..], &[1][..]] {
+            let feed: PythPushFeed =
+                get_pyth_push_feed(&new_price_update(verification_level, -8)).unwrap();
+            assert_eq!(feed.feed_id, [7; 32]);
+            assert_eq!(feed.exponent, -8);
+            assert!(validate_pyth_feed_exponent(feed.exponent, 8).is_ok());
+            assert!(validate_pyth_feed_exponent(feed.exponent, 6).is_err());
+        }
+        assert!(get_pyth_push_feed(&new_price_update(&[2], -8)).is_err());
+        assert!(get_pyth_push_feed(&[0; 8]).is_err());
+    }
+}
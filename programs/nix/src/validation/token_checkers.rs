@@ -5,7 +5,12 @@ use solana_program::{
 };
 
 use spl_token_2022::{
-    check_spl_token_program_account, extension::StateWithExtensions, state::Mint,
+    check_spl_token_program_account,
+    extension::{
+        cpi_guard::CpiGuard, default_account_state::DefaultAccountState, BaseStateWithExtensions,
+        StateWithExtensions,
+    },
+    state::{Account, AccountState, Mint},
 };
 use std::{cell::Ref, ops::Deref};
 
@@ -25,6 +30,19 @@ impl<'a, 'info> MintAccountInfo<'a, 'info> {
 
         Ok(Self { mint, info })
     }
+
+    /// Token22 mints with a frozen DefaultAccountState create every token
+    /// account frozen, vaults of the market included.
+    pub fn is_frozen_by_default(&self) -> Result<bool, ProgramError> {
+        if *self.info.owner != spl_token_2022::id() {
+            return Ok(false);
+        }
+        Ok(
+            StateWithExtensions::<Mint>::unpack(&self.info.data.borrow())?
+                .get_extension::<DefaultAccountState>()
+                .is_ok_and(|extension| extension.state == AccountState::Frozen as u8),
+        )
+    }
 }
 
 impl<'a, 'info> AsRef<AccountInfo<'info>> for MintAccountInfo<'a, 'info> {
@@ -130,6 +148,38 @@ impl<'a, 'info> TokenAccountInfo<'a, 'info> {
         )?;
         Self::new_with_owner(info, mint, owner)
     }
+
+    /// Checked up front because a transfer from or to a frozen account only
+    /// fails deep inside the token or marginfi CPI.
+    pub fn assert_not_frozen(&self) -> ProgramResult {
+        let is_frozen: bool = StateWithExtensions::<Account>::unpack(&self.info.data.borrow())?
+            .base
+            .state
+            == AccountState::Frozen;
+        require!(
+            !is_frozen,
+            NixError::TokenAccountFrozen.into(),
+            "Token account {} is frozen",
+            self.info.key,
+        )
+    }
+
+    /// The CPI guard of a token22 account rejects transfers its owner signs
+    /// in a CPI, which is how tokens are moved in from a trader.
+    pub fn assert_no_cpi_guard(&self) -> ProgramResult {
+        if *self.info.owner != spl_token_2022::id() {
+            return Ok(());
+        }
+        let is_locked: bool = StateWithExtensions::<Account>::unpack(&self.info.data.borrow())?
+            .get_extension::<CpiGuard>()
+            .is_ok_and(|extension| bool::from(extension.lock_cpi));
+        require!(
+            !is_locked,
+            NixError::CpiGuardEnabled.into(),
+            "Token account {} has the CPI guard on",
+            self.info.key,
+        )
+    }
 }
 
 impl<'a, 'info> AsRef<AccountInfo<'info>> for TokenAccountInfo<'a, 'info> {
@@ -158,72 +208,129 @@ pub fn validate_market_mint(market: &AccountInfo, mint: &AccountInfo) -> Program
     )
 }
 
-#[macro_export]
-macro_rules! market_vault_seeds {
-    ( $market:expr, $mint:expr ) => {
-        &[b"vault", $market.as_ref(), $mint.as_ref()]
-    };
-}
-
-#[macro_export]
-macro_rules! market_vault_seeds_with_bump {
-    ( $market:expr, $mint:expr, $bump:expr ) => {
-        &[&[b"vault", $market.as_ref(), $mint.as_ref(), &[$bump]]]
-    };
-}
-
-#[macro_export]
-macro_rules! global_vault_seeds {
-    ( $mint:expr ) => {
-        &[b"global-vault", $mint.as_ref()]
-    };
-}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use solana_program::program_pack::Pack;
+    use spl_token_2022::extension::{ExtensionType, StateWithExtensionsMut};
 
-#[macro_export]
-macro_rules! global_vault_seeds_with_bump {
-    ( $mint:expr, $bump:expr ) => {
-        &[&[b"global-vault", $mint.as_ref(), &[$bump]]]
-    };
-}
-#[macro_export]
-macro_rules! market_fee_receiver_seeds {
-    ( $market:expr, $mint:expr ) => {
-        &[b"fee-receiver", $market.as_ref(), $mint.as_ref()]
-    };
-}
-
-#[macro_export]
-macro_rules! market_fee_receiver_seeds_with_bump {
-    ( $market:expr, $mint:expr, $bump:expr ) => {
-        &[&[b"fee-receiver", $market.as_ref(), $mint.as_ref(), &[$bump]]]
-    };
-}
-
-#[macro_export]
-macro_rules! market_signer_seeds {
-    ( $market:expr ) => {
-        &[b"market-signer", $market.as_ref()]
-    };
-}
+    fn new_mint_data(default_account_state: Option<AccountState>) -> Vec<u8> {
+        let extensions: Vec<ExtensionType> = default_account_state
+            .iter()
+            .map(|_| ExtensionType::DefaultAccountState)
+            .collect();
+        let mut data: Vec<u8> =
+            vec![0; ExtensionType::try_calculate_account_len::<Mint>(&extensions).unwrap()];
+        let mut mint: StateWithExtensionsMut<Mint> =
+            StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut data).unwrap();
+        if let Some(state) = default_account_state {
+            mint.init_extension::<DefaultAccountState>(true)
+                .unwrap()
+                .state = state as u8;
+        }
+        mint.base = Mint {
+            is_initialized: true,
+            ..Default::default()
+        };
+        mint.pack_base();
+        mint.init_account_type().unwrap();
+        data
+    }
 
-#[macro_export]
-macro_rules! market_signer_seeds_with_bump {
-    ( $market:expr, $bump:expr ) => {
-        &[&[b"market-signer", $market.as_ref(), &[$bump]]]
-    };
-}
+    fn new_token_account_data(state: AccountState, lock_cpi: Option<bool>) -> Vec<u8> {
+        let base: Account = Account {
+            state,
+            ..Default::default()
+        };
+        let Some(lock_cpi) = lock_cpi else {
+            let mut data: Vec<u8> = vec![0; Account::LEN];
+            Account::pack(base, &mut data).unwrap();
+            return data;
+        };
+        let len: usize =
+            ExtensionType::try_calculate_account_len::<Account>(&[ExtensionType::CpiGuard])
+                .unwrap();
+        let mut data: Vec<u8> = vec![0; len];
+        let mut account: StateWithExtensionsMut<Account> =
+            StateWithExtensionsMut::<Account>::unpack_uninitialized(&mut data).unwrap();
+        account.init_extension::<CpiGuard>(true).unwrap().lock_cpi = lock_cpi.into();
+        account.base = base;
+        account.pack_base();
+        account.init_account_type().unwrap();
+        data
+    }
 
-pub fn get_market_signer_address(market: &Pubkey) -> (Pubkey, u8) {
-    Pubkey::find_program_address(market_signer_seeds!(market), &crate::ID)
-}
-pub fn get_market_fee_receiver_address(market: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
-    Pubkey::find_program_address(market_fee_receiver_seeds!(market, mint), &crate::ID)
-}
+    #[test]
+    fn test_is_frozen_by_default() {
+        let key: Pubkey = Pubkey::new_unique();
+        let mut lamports: u64 = 0;
+        for (default_account_state, expected) in [
+            (None, false),
+            (Some(AccountState::Initialized), false),
+            (Some(AccountState::Frozen), true),
+        ] {
+            let mut data: Vec<u8> = new_mint_data(default_account_state);
+            let info: AccountInfo = AccountInfo::new(
+                &key,
+                false,
+                false,
+                &mut lamports,
+                &mut data,
+                &spl_token_2022::ID,
+                false,
+                0,
+            );
+            let mint: MintAccountInfo = MintAccountInfo::new(&info).unwrap();
+            assert_eq!(mint.is_frozen_by_default().unwrap(), expected);
+        }
+    }
 
-pub fn get_vault_address(market: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
-    Pubkey::find_program_address(market_vault_seeds!(market, mint), &crate::ID)
-}
+    #[test]
+    fn test_assert_not_frozen() {
+        let key: Pubkey = Pubkey::new_unique();
+        let mut lamports: u64 = 0;
+        for owner in [spl_token::ID, spl_token_2022::ID] {
+            for (state, expected_ok) in [
+                (AccountState::Initialized, true),
+                (AccountState::Frozen, false),
+            ] {
+                let mut data: Vec<u8> = new_token_account_data(state, None);
+                let info: AccountInfo = AccountInfo::new(
+                    &key,
+                    false,
+                    true,
+                    &mut lamports,
+                    &mut data,
+                    &owner,
+                    false,
+                    0,
+                );
+                let token_account: TokenAccountInfo =
+                    TokenAccountInfo::new(&info, &Pubkey::default()).unwrap();
+                assert_eq!(token_account.assert_not_frozen().is_ok(), expected_ok);
+            }
+        }
+    }
 
-pub fn get_global_vault_address(mint: &Pubkey) -> (Pubkey, u8) {
-    Pubkey::find_program_address(global_vault_seeds!(mint), &crate::ID)
+    #[test]
+    fn test_assert_no_cpi_guard() {
+        let key: Pubkey = Pubkey::new_unique();
+        let mut lamports: u64 = 0;
+        for (lock_cpi, expected_ok) in [(None, true), (Some(false), true), (Some(true), false)] {
+            let mut data: Vec<u8> = new_token_account_data(AccountState::Initialized, lock_cpi);
+            let info: AccountInfo = AccountInfo::new(
+                &key,
+                false,
+                true,
+                &mut lamports,
+                &mut data,
+                &spl_token_2022::ID,
+                false,
+                0,
+            );
+            let token_account: TokenAccountInfo =
+                TokenAccountInfo::new(&info, &Pubkey::default()).unwrap();
+            assert_eq!(token_account.assert_no_cpi_guard().is_ok(), expected_ok);
+        }
+    }
 }
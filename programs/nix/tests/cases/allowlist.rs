@@ -0,0 +1,135 @@
+//! Permissioned markets, where every ClaimSeat needs a co-sign from the
+//! market's allowlist authority.
+
+use std::rc::Rc;
+
+use nix::{
+    program::{create_market::CreateMarketParams, get_dynamic_account, NixError, NixInstruction},
+    state::{MarketFixed, MarketRef},
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    system_program,
+};
+use solana_program_test::BanksClientError;
+use solana_sdk::{account::Account, signature::Keypair, signer::Signer};
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{
+    assert_nix_error, default_market_params, get_account, send_tx_with_retry, NixTestFixture,
+    TradingMarket,
+};
+
+/// Market whose seats `allowlist_authority` has to co-sign.
+async fn new_permissioned_market(allowlist_authority: &Keypair) -> (NixTestFixture, TradingMarket) {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = fixture
+        .create_market_with_params(CreateMarketParams {
+            allowlist_authority: Some(allowlist_authority.pubkey()),
+            ..default_market_params()
+        })
+        .await
+        .unwrap();
+    (fixture, market)
+}
+
+/// ClaimSeat for the payer, with `allowlist_authority` as the optional
+/// co-signer when given.
+async fn claim_seat(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    allowlist_authority: Option<&Keypair>,
+) -> Result<(), BanksClientError> {
+    let payer: Keypair = fixture.payer_keypair();
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(payer.pubkey(), true),
+        AccountMeta::new(market.key, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    let mut signers: Vec<&Keypair> = vec![&payer];
+    if let Some(allowlist_authority) = allowlist_authority {
+        accounts.push(AccountMeta::new_readonly(
+            allowlist_authority.pubkey(),
+            true,
+        ));
+        signers.push(allowlist_authority);
+    }
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[Instruction {
+            program_id: nix::ID,
+            accounts,
+            data: NixInstruction::ClaimSeat.to_vec(),
+        }],
+        Some(&payer.pubkey()),
+        &signers,
+    )
+    .await
+}
+
+async fn has_seat(fixture: &NixTestFixture, market: &TradingMarket) -> bool {
+    let account: Account = get_account(fixture, &market.key).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    market.get_seat_snapshot(&fixture.payer()).is_some()
+}
+
+#[tokio::test]
+async fn create_market_stores_the_allowlist_authority() -> anyhow::Result<()> {
+    let allowlist_authority: Keypair = Keypair::new();
+    let (fixture, market) = new_permissioned_market(&allowlist_authority).await;
+
+    let account: Account = get_account(&fixture, &market.key).await;
+    let market_ref: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    assert!(market_ref.fixed.is_permissioned());
+    assert_eq!(
+        *market_ref.fixed.get_allowlist_authority(),
+        allowlist_authority.pubkey()
+    );
+
+    let permissionless_market: TradingMarket = fixture
+        .create_market_with_params(default_market_params())
+        .await?;
+    let account: Account = get_account(&fixture, &permissionless_market.key).await;
+    let market_ref: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    assert!(!market_ref.fixed.is_permissioned());
+    Ok(())
+}
+
+#[tokio::test]
+async fn claim_seat_needs_the_allowlist_authority() -> anyhow::Result<()> {
+    let allowlist_authority: Keypair = Keypair::new();
+    let (fixture, market) = new_permissioned_market(&allowlist_authority).await;
+
+    assert!(claim_seat(&fixture, &market, None).await.is_err());
+    assert_nix_error(
+        claim_seat(&fixture, &market, Some(&Keypair::new())).await,
+        NixError::NotAllowlisted,
+    );
+    assert!(!has_seat(&fixture, &market).await);
+
+    claim_seat(&fixture, &market, Some(&allowlist_authority)).await?;
+    assert!(has_seat(&fixture, &market).await);
+    Ok(())
+}
+
+#[tokio::test]
+async fn claim_seat_on_a_permissionless_market_needs_no_co_sign() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = fixture
+        .create_market_with_params(default_market_params())
+        .await?;
+
+    claim_seat(&fixture, &market, None).await?;
+    assert!(has_seat(&fixture, &market).await);
+    Ok(())
+}
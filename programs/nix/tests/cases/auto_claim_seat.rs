@@ -0,0 +1,160 @@
+//! auto_claim_seat of Deposit and PlaceOrder, which claims the payer's seat
+//! when it has none, on markets without an allowlist authority only.
+
+use hypertree::{DataIndex, NIL};
+use nix::{
+    program::{
+        create_market::CreateMarketParams, deposit::DepositParams, get_dynamic_account,
+        place_order::PlaceOrderParams, NixError,
+    },
+    quantities::{BaseAtoms, Rate},
+    state::{MarketFixed, MarketRef, OrderType},
+};
+use solana_program_test::BanksClientError;
+use solana_sdk::{account::Account, pubkey::Pubkey, signature::Keypair, signer::Signer};
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{
+    assert_nix_error, default_market_params, deposit_to_seat, get_account, place_order,
+    NixTestFixture, TradingMarket,
+};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const DEPOSIT_ATOMS: u64 = 1_000_000_000;
+
+/// Market with `allowlist_authority` when given, permissionless otherwise.
+async fn new_market(allowlist_authority: Option<Pubkey>) -> (NixTestFixture, TradingMarket) {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = fixture
+        .create_market_with_params(CreateMarketParams {
+            allowlist_authority,
+            ..default_market_params()
+        })
+        .await
+        .unwrap();
+    (fixture, market)
+}
+
+/// Deposits base B for the payer.
+async fn deposit_base_b(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    auto_claim_seat: bool,
+) -> Result<(), BanksClientError> {
+    fixture
+        .base_b_mint_fixture
+        .mint_to(&fixture.payer_base_b_fixture.key, 10_000)
+        .await;
+    deposit_to_seat(
+        fixture,
+        market,
+        &fixture.payer_keypair(),
+        &fixture.base_b_bank_fixture,
+        &fixture.payer_base_b_fixture.key,
+        &fixture.base_b_token_program,
+        DepositParams::new(DEPOSIT_ATOMS, None, auto_claim_seat),
+    )
+    .await
+}
+
+async fn get_trader_index(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    trader: &Pubkey,
+) -> DataIndex {
+    let account: Account = get_account(fixture, &market.key).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    market.get_trader_index(trader)
+}
+
+fn auto_claim_ask() -> PlaceOrderParams {
+    PlaceOrderParams {
+        auto_claim_seat: true,
+        ..PlaceOrderParams::new(
+            BaseAtoms::new(ORDER_BASE_ATOMS),
+            Rate::from_bps(RATE_BPS),
+            false,
+            true,
+            OrderType::PostOnly,
+        )
+    }
+}
+
+#[tokio::test]
+async fn deposit_claims_the_missing_seat() -> anyhow::Result<()> {
+    let (fixture, market) = new_market(None).await;
+
+    assert!(deposit_base_b(&fixture, &market, false).await.is_err());
+    assert_eq!(
+        get_trader_index(&fixture, &market, &fixture.payer()).await,
+        NIL
+    );
+
+    deposit_base_b(&fixture, &market, true).await?;
+    let trader_index: DataIndex = get_trader_index(&fixture, &market, &fixture.payer()).await;
+    assert_ne!(trader_index, NIL);
+
+    // A payer with a seat keeps it.
+    deposit_base_b(&fixture, &market, true).await?;
+    assert_eq!(
+        get_trader_index(&fixture, &market, &fixture.payer()).await,
+        trader_index
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn place_order_keeps_an_existing_seat() -> anyhow::Result<()> {
+    let (fixture, market) = new_market(None).await;
+    let (lender, _borrower) = fixture
+        .seat_lender_and_borrower(&market, DEPOSIT_ATOMS, DEPOSIT_ATOMS)
+        .await?;
+    let trader_index: DataIndex = get_trader_index(&fixture, &market, &lender.pubkey()).await;
+
+    place_order(
+        &fixture,
+        &market,
+        &lender,
+        auto_claim_ask(),
+        market.ask_metas(&fixture).await,
+    )
+    .await?;
+    assert_eq!(
+        get_trader_index(&fixture, &market, &lender.pubkey()).await,
+        trader_index
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn auto_claim_seat_fails_on_a_permissioned_market() -> anyhow::Result<()> {
+    let allowlist_authority: Keypair = Keypair::new();
+    let (fixture, market) = new_market(Some(allowlist_authority.pubkey())).await;
+
+    assert_nix_error(
+        deposit_base_b(&fixture, &market, true).await,
+        NixError::NotAllowlisted,
+    );
+    assert_nix_error(
+        place_order(
+            &fixture,
+            &market,
+            &fixture.payer_keypair(),
+            auto_claim_ask(),
+            market.ask_metas(&fixture).await,
+        )
+        .await,
+        NixError::NotAllowlisted,
+    );
+    assert_eq!(
+        get_trader_index(&fixture, &market, &fixture.payer()).await,
+        NIL
+    );
+    Ok(())
+}
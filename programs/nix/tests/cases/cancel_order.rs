@@ -0,0 +1,1075 @@
+use std::rc::Rc;
+
+use borsh::BorshSerialize;
+use hypertree::get_helper;
+use marginfi::state::marginfi_group::{Bank, BankVaultType};
+use nix::{
+    program::{
+        cancel_order::CancelOrderParams, clean_expired_orders::CleanExpiredOrdersParams,
+        deposit::DepositParams, get_dynamic_account, global_deposit::GlobalDepositParams,
+        place_order::PlaceOrderParams, NixError, NixInstruction,
+    },
+    quantities::{BaseAtoms, Rate},
+    state::{
+        ExpiryPolicy, GlobalValue, MarketFixed, MarketLoansFixed, MarketRef, OrderType,
+        GAS_DEPOSIT_LAMPORTS, GTT_GAS_DEPOSIT_LAMPORTS, MARKET_LOAN_BLOCK_SIZE,
+    },
+    validation::{get_global_vault_address, get_market_signer_address, get_vault_address},
+};
+use solana_program::{
+    clock::Clock,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    system_instruction, system_program,
+};
+use solana_program_test::BanksClientError;
+use solana_sdk::{
+    account::Account, pubkey::Pubkey, signature::Keypair, signer::Signer,
+    transaction::TransactionError,
+};
+use test_case::test_case;
+use test_utilities::{
+    bank::BankFixture,
+    marginfi_account::MarginfiAccountFixture,
+    spl::TokenAccountFixture,
+    test::{BankMint, TestSettings},
+};
+
+use crate::test_utils::{send_tx_with_retry, NixTestFixture};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 500_000_000;
+const GLOBAL_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const SEAT_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const QUOTE_SEAT_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+
+async fn new_fixture() -> NixTestFixture {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    fixture
+        .claim_seat_for_keypair(&fixture.payer_keypair())
+        .await
+        .unwrap();
+    fixture
+}
+
+async fn get_account(fixture: &NixTestFixture, key: &Pubkey) -> Account {
+    fixture
+        .try_load(key)
+        .await
+        .unwrap()
+        .expect("Account not found")
+}
+
+async fn get_num_active_loans(fixture: &NixTestFixture, market_loans: &Pubkey) -> u64 {
+    let account: Account = get_account(fixture, market_loans).await;
+    get_helper::<MarketLoansFixed>(&account.data, 0_u32).num_active_loans
+}
+
+async fn get_num_ask_levels(fixture: &NixTestFixture, use_a_tree: bool) -> u8 {
+    let account: Account = get_account(fixture, &fixture.market).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    market
+        .get_book_levels(use_a_tree, false, 8, 0, 0)
+        .unwrap()
+        .1
+}
+
+/// Expired asks on the A tree as of the bank clock, the count a keeper
+/// checks before sending CleanExpiredOrders.
+async fn get_num_expired_asks(fixture: &NixTestFixture) -> u32 {
+    let account: Account = get_account(fixture, &fixture.market).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    let clock: Clock = fixture.get_clock().await;
+    market.count_expired_orders(true, false, clock.slot as u32, clock.unix_timestamp)
+}
+
+async fn get_last_order_sequence_number(fixture: &NixTestFixture) -> u64 {
+    let account: Account = get_account(fixture, &fixture.market).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    market.fixed.get_base_a_order_sequence_number()
+}
+
+fn assert_nix_error(result: Result<(), BanksClientError>, expected: NixError) {
+    match result {
+        Err(BanksClientError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        ))) => assert_eq!(code, expected as u32),
+        other => panic!("Expected {:?}, got {:?}", expected, other),
+    }
+}
+
+/// Global, global vault, market vault and token program for base A.
+fn base_a_global_metas(fixture: &NixTestFixture) -> Vec<AccountMeta> {
+    let mint: Pubkey = fixture.base_a_mint_fixture.key;
+    vec![
+        AccountMeta::new(fixture.base_a_global_fixture.key, false),
+        AccountMeta::new(get_global_vault_address(&mint).0, false),
+        AccountMeta::new(get_vault_address(&fixture.market, &mint).0, false),
+        AccountMeta::new_readonly(fixture.base_a_token_program, false),
+    ]
+}
+
+fn marginfi_cpi_metas(
+    fixture: &NixTestFixture,
+    bank: &BankFixture,
+    marginfi_account: &Pubkey,
+) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(fixture.group.key, false),
+        AccountMeta::new(bank.key, false),
+        AccountMeta::new(*marginfi_account, false),
+        AccountMeta::new(bank.get_vault(BankVaultType::Liquidity).0, false),
+        AccountMeta::new_readonly(bank.get_vault_authority(BankVaultType::Liquidity).0, false),
+    ]
+}
+
+async fn oracle_metas(bank: &BankFixture) -> Vec<AccountMeta> {
+    let bank_account: Bank = bank.load().await;
+    vec![
+        AccountMeta::new_readonly(bank.key, false),
+        AccountMeta::new_readonly(bank_account.config.oracle_keys[0], false),
+    ]
+}
+
+fn order_params(is_bid: bool, order_type: OrderType) -> PlaceOrderParams {
+    PlaceOrderParams::new(
+        BaseAtoms::new(ORDER_BASE_ATOMS),
+        Rate::from_bps(RATE_BPS),
+        is_bid,
+        true,
+        order_type,
+    )
+}
+
+async fn place_order(
+    fixture: &NixTestFixture,
+    market_loans: &Pubkey,
+    params: PlaceOrderParams,
+    optional_accounts: Vec<AccountMeta>,
+) -> Result<(), BanksClientError> {
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(fixture.payer(), true),
+        AccountMeta::new(fixture.market, false),
+        AccountMeta::new(*market_loans, false),
+        AccountMeta::new_readonly(get_market_signer_address(&fixture.market).0, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(fixture.base_a_mint_fixture.key, false),
+        AccountMeta::new_readonly(fixture.base_b_mint_fixture.key, false),
+    ];
+    accounts.extend(optional_accounts);
+    let place_order_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts,
+        data: [
+            NixInstruction::PlaceOrder.to_vec(),
+            params.try_to_versioned_vec()?,
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[place_order_ix],
+        Some(&fixture.payer()),
+        &[&fixture.payer_keypair()],
+    )
+    .await
+}
+
+async fn cancel_order(
+    fixture: &NixTestFixture,
+    trader: &Keypair,
+    market_loans: &Pubkey,
+    order_sequence_number: u64,
+    use_a_tree: bool,
+) -> Result<(), BanksClientError> {
+    let base_global: Pubkey = if use_a_tree {
+        fixture.base_a_global_fixture.key
+    } else {
+        fixture.base_b_global_fixture.key
+    };
+    cancel_order_with_base_global(
+        fixture,
+        trader,
+        market_loans,
+        AccountMeta::new(base_global, false),
+        order_sequence_number,
+        use_a_tree,
+    )
+    .await
+}
+
+async fn cancel_order_with_base_global(
+    fixture: &NixTestFixture,
+    trader: &Keypair,
+    market_loans: &Pubkey,
+    base_global_meta: AccountMeta,
+    order_sequence_number: u64,
+    use_a_tree: bool,
+) -> Result<(), BanksClientError> {
+    let cancel_order_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts: vec![
+            AccountMeta::new(trader.pubkey(), true),
+            AccountMeta::new(*market_loans, false),
+            AccountMeta::new(fixture.market, false),
+            base_global_meta,
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: [
+            NixInstruction::CancelOrder.to_vec(),
+            CancelOrderParams {
+                trader_index_hint: None,
+                order_sequence_number,
+                order_index_hint: None,
+                use_a_tree,
+                max_expired_orders_to_sweep: 0,
+            }
+            .try_to_vec()?,
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[cancel_order_ix],
+        Some(&trader.pubkey()),
+        &[trader],
+    )
+    .await
+}
+
+/// Funds a new keypair to crank with, so it can pay for loan blocks.
+async fn new_cranker(fixture: &NixTestFixture) -> Keypair {
+    let cranker: Keypair = Keypair::new();
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[system_instruction::transfer(
+            &fixture.payer(),
+            &cranker.pubkey(),
+            1_000_000_000,
+        )],
+        Some(&fixture.payer()),
+        &[&fixture.payer_keypair()],
+    )
+    .await
+    .unwrap();
+    cranker
+}
+
+/// Cranks CleanExpiredOrders on one side of the A tree. The payer pays the
+/// transaction fee so the cranker only sees the gas deposits it is paid.
+async fn clean_expired_orders(
+    fixture: &NixTestFixture,
+    cranker: &Keypair,
+    market_loans: &Pubkey,
+    is_bid: bool,
+    max_orders: u32,
+) -> Result<(), BanksClientError> {
+    let clean_expired_orders_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts: vec![
+            AccountMeta::new(cranker.pubkey(), true),
+            AccountMeta::new(*market_loans, false),
+            AccountMeta::new(fixture.market, false),
+            AccountMeta::new(fixture.base_a_global_fixture.key, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: [
+            NixInstruction::CleanExpiredOrders.to_vec(),
+            CleanExpiredOrdersParams::new(true, is_bid, max_orders).try_to_vec()?,
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[clean_expired_orders_ix],
+        Some(&fixture.payer()),
+        &[&fixture.payer_keypair(), cranker],
+    )
+    .await
+}
+
+/// Funds the payer's global base A balance and rests a global ask on the A
+/// tree. Returns its sequence number.
+async fn place_global_ask(fixture: &mut NixTestFixture, market_loans: &Pubkey) -> u64 {
+    place_global_ask_with_params(
+        fixture,
+        market_loans,
+        order_params(false, OrderType::Global),
+    )
+    .await
+}
+
+async fn place_global_ask_with_params(
+    fixture: &mut NixTestFixture,
+    market_loans: &Pubkey,
+    params: PlaceOrderParams,
+) -> u64 {
+    let global_key: Pubkey = fixture.base_a_global_fixture.key;
+    let mint_key: Pubkey = fixture.base_a_mint_fixture.key;
+    let trader_token: Pubkey = fixture.payer_base_a_fixture.key;
+
+    fixture.global_add_trader(&global_key).await.unwrap();
+    fixture.base_a_mint_fixture.mint_to(&trader_token, 10).await;
+    let global_deposit_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts: vec![
+            AccountMeta::new(fixture.payer(), true),
+            AccountMeta::new(global_key, false),
+            AccountMeta::new_readonly(mint_key, false),
+            AccountMeta::new(get_global_vault_address(&mint_key).0, false),
+            AccountMeta::new(trader_token, false),
+            AccountMeta::new_readonly(fixture.base_a_token_program, false),
+        ],
+        data: [
+            NixInstruction::GlobalDeposit.to_vec(),
+            GlobalDepositParams::new(GLOBAL_DEPOSIT_ATOMS)
+                .try_to_vec()
+                .unwrap(),
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[global_deposit_ix],
+        Some(&fixture.payer()),
+        &[&fixture.payer_keypair()],
+    )
+    .await
+    .unwrap();
+
+    place_order(fixture, market_loans, params, base_a_global_metas(fixture))
+        .await
+        .unwrap();
+    get_last_order_sequence_number(fixture).await
+}
+
+/// Deposits into the payer's seat through the market's marginfi account for
+/// the bank.
+async fn deposit_to_seat(
+    fixture: &NixTestFixture,
+    bank: &BankFixture,
+    marginfi_account: &Pubkey,
+    trader_token: &Pubkey,
+    token_program: &Pubkey,
+    amount: u64,
+) {
+    deposit_to_seat_with_params(
+        fixture,
+        bank,
+        marginfi_account,
+        trader_token,
+        token_program,
+        DepositParams::new(amount, None, false),
+    )
+    .await;
+}
+
+async fn deposit_to_seat_with_params(
+    fixture: &NixTestFixture,
+    bank: &BankFixture,
+    marginfi_account: &Pubkey,
+    trader_token: &Pubkey,
+    token_program: &Pubkey,
+    params: DepositParams,
+) {
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(fixture.payer(), true),
+        AccountMeta::new(fixture.market, false),
+        AccountMeta::new_readonly(get_market_signer_address(&fixture.market).0, false),
+        AccountMeta::new(*trader_token, false),
+        AccountMeta::new(get_vault_address(&fixture.market, &bank.mint.key).0, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(bank.mint.key, false),
+    ];
+    accounts.extend(marginfi_cpi_metas(fixture, bank, marginfi_account));
+    // Deposit takes the liquidity vault without its authority.
+    accounts.pop();
+    let deposit_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts,
+        data: [
+            NixInstruction::Deposit.to_vec(),
+            params.try_to_vec().unwrap(),
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[deposit_ix],
+        Some(&fixture.payer()),
+        &[&fixture.payer_keypair()],
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn cancel_global_ask_refunds_gas_prepayment() -> anyhow::Result<()> {
+    let mut fixture: NixTestFixture = new_fixture().await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await?;
+    let order_sequence_number: u64 = place_global_ask(&mut fixture, &market_loans).await;
+    let global_key: Pubkey = fixture.base_a_global_fixture.key;
+
+    fixture.base_a_global_fixture.reload().await;
+    let global: &GlobalValue = &fixture.base_a_global_fixture.global;
+    assert_eq!(
+        global.get_gas_prepayment_lamports(&fixture.payer()),
+        GAS_DEPOSIT_LAMPORTS
+    );
+    let before_global_lamports: u64 = get_account(&fixture, &global_key).await.lamports;
+    let before_balance_atoms: u64 = global.get_balance_shares(&fixture.payer()).into();
+    assert_eq!(get_num_ask_levels(&fixture, true).await, 1);
+
+    cancel_order(
+        &fixture,
+        &fixture.payer_keypair(),
+        &market_loans,
+        order_sequence_number,
+        true,
+    )
+    .await?;
+
+    fixture.base_a_global_fixture.reload().await;
+    let global: &GlobalValue = &fixture.base_a_global_fixture.global;
+    assert_eq!(global.get_gas_prepayment_lamports(&fixture.payer()), 0);
+    assert_eq!(
+        before_global_lamports - get_account(&fixture, &global_key).await.lamports,
+        GAS_DEPOSIT_LAMPORTS
+    );
+    // Tokens never left the global account, so the balance is untouched.
+    let after_balance_atoms: u64 = global.get_balance_shares(&fixture.payer()).into();
+    assert_eq!(after_balance_atoms, before_balance_atoms);
+    assert_eq!(get_num_ask_levels(&fixture, true).await, 0);
+    // Nothing was borrowed for an ask, so no loan is recorded.
+    assert_eq!(get_num_active_loans(&fixture, &market_loans).await, 0);
+    fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn cancel_bid_moves_borrow_to_market_loans() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await?;
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+    let quote_bank: &BankFixture = &fixture.base_b_bank_fixture;
+
+    // The bid borrows base from marginfi, so the bank needs liquidity.
+    let lender: MarginfiAccountFixture =
+        MarginfiAccountFixture::new(Rc::clone(&fixture.context), &fixture.group.key).await;
+    let lender_token: TokenAccountFixture =
+        base_bank.mint.create_token_account_and_mint_to(100).await;
+    lender
+        .try_bank_deposit(lender_token.key, base_bank, 100, None)
+        .await?;
+
+    fixture
+        .base_b_mint_fixture
+        .mint_to(&fixture.payer_base_b_fixture.key, 10_000)
+        .await;
+    deposit_to_seat(
+        &fixture,
+        quote_bank,
+        &fixture.base_b_marginfi_account,
+        &fixture.payer_base_b_fixture.key,
+        &fixture.base_b_token_program,
+        QUOTE_SEAT_DEPOSIT_ATOMS,
+    )
+    .await;
+
+    let mut optional_accounts: Vec<AccountMeta> = base_a_global_metas(&fixture);
+    optional_accounts.extend(marginfi_cpi_metas(
+        &fixture,
+        base_bank,
+        &fixture.base_a_marginfi_account,
+    ));
+    optional_accounts.extend(marginfi_cpi_metas(
+        &fixture,
+        quote_bank,
+        &fixture.base_b_marginfi_account,
+    ));
+    optional_accounts.extend(oracle_metas(quote_bank).await);
+    optional_accounts.extend(oracle_metas(base_bank).await);
+    place_order(
+        &fixture,
+        &market_loans,
+        order_params(true, OrderType::PostOnly),
+        optional_accounts,
+    )
+    .await?;
+    let order_sequence_number: u64 = get_last_order_sequence_number(&fixture).await;
+
+    let before_loans_len: usize = get_account(&fixture, &market_loans).await.data.len();
+    assert_eq!(get_num_active_loans(&fixture, &market_loans).await, 0);
+
+    cancel_order(
+        &fixture,
+        &fixture.payer_keypair(),
+        &market_loans,
+        order_sequence_number,
+        true,
+    )
+    .await?;
+
+    // The borrow made when the bid rested stays open as a loan to the pool.
+    assert_eq!(get_num_active_loans(&fixture, &market_loans).await, 1);
+    assert_eq!(
+        get_account(&fixture, &market_loans).await.data.len() - before_loans_len,
+        MARKET_LOAN_BLOCK_SIZE
+    );
+    fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn cancel_by_other_trader_fails() -> anyhow::Result<()> {
+    let mut fixture: NixTestFixture = new_fixture().await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await?;
+    let order_sequence_number: u64 = place_global_ask(&mut fixture, &market_loans).await;
+
+    let other_trader: Keypair = fixture.second_keypair.insecure_clone();
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[system_instruction::transfer(
+            &fixture.payer(),
+            &other_trader.pubkey(),
+            1_000_000_000,
+        )],
+        Some(&fixture.payer()),
+        &[&fixture.payer_keypair()],
+    )
+    .await?;
+    fixture.claim_seat_for_keypair(&other_trader).await?;
+
+    // The order is looked up among the canceller's own orders.
+    assert_nix_error(
+        cancel_order(
+            &fixture,
+            &other_trader,
+            &market_loans,
+            order_sequence_number,
+            true,
+        )
+        .await,
+        NixError::InvalidCancel,
+    );
+    assert_eq!(get_num_ask_levels(&fixture, true).await, 1);
+
+    cancel_order(
+        &fixture,
+        &fixture.payer_keypair(),
+        &market_loans,
+        order_sequence_number,
+        true,
+    )
+    .await?;
+    assert_eq!(get_num_ask_levels(&fixture, true).await, 0);
+    fixture.verify_market().await;
+    Ok(())
+}
+
+#[test_case(true ; "its own tree")]
+#[test_case(false ; "other tree")]
+#[tokio::test]
+async fn cancel_ask_only_on_its_tree(use_a_tree: bool) -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await?;
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+
+    fixture
+        .base_a_mint_fixture
+        .mint_to(&fixture.payer_base_a_fixture.key, 10)
+        .await;
+    deposit_to_seat(
+        &fixture,
+        base_bank,
+        &fixture.base_a_marginfi_account,
+        &fixture.payer_base_a_fixture.key,
+        &fixture.base_a_token_program,
+        SEAT_DEPOSIT_ATOMS,
+    )
+    .await;
+
+    let mut optional_accounts: Vec<AccountMeta> =
+        marginfi_cpi_metas(&fixture, base_bank, &fixture.base_a_marginfi_account);
+    optional_accounts.extend(oracle_metas(base_bank).await);
+    place_order(
+        &fixture,
+        &market_loans,
+        order_params(false, OrderType::PostOnly),
+        optional_accounts,
+    )
+    .await?;
+    let order_sequence_number: u64 = get_last_order_sequence_number(&fixture).await;
+
+    // The ask rests on the A tree. The B tree is never searched for it, a
+    // sequence number there belongs to another order.
+    let result: Result<(), BanksClientError> = cancel_order(
+        &fixture,
+        &fixture.payer_keypair(),
+        &market_loans,
+        order_sequence_number,
+        use_a_tree,
+    )
+    .await;
+    if use_a_tree {
+        result?;
+        assert_eq!(get_num_ask_levels(&fixture, true).await, 0);
+    } else {
+        assert_nix_error(result, NixError::InvalidCancel);
+        assert_eq!(get_num_ask_levels(&fixture, true).await, 1);
+    }
+    fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn cancel_global_ask_on_other_tree_fails() -> anyhow::Result<()> {
+    let mut fixture: NixTestFixture = new_fixture().await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await?;
+    let order_sequence_number: u64 = place_global_ask(&mut fixture, &market_loans).await;
+
+    // The base B global passed for the B tree cannot refund an A tree global
+    // order, so it has to be cancelled on its own tree.
+    assert_nix_error(
+        cancel_order(
+            &fixture,
+            &fixture.payer_keypair(),
+            &market_loans,
+            order_sequence_number,
+            false,
+        )
+        .await,
+        NixError::InvalidCancel,
+    );
+    fixture.base_a_global_fixture.reload().await;
+    assert_eq!(
+        fixture
+            .base_a_global_fixture
+            .global
+            .get_gas_prepayment_lamports(&fixture.payer()),
+        GAS_DEPOSIT_LAMPORTS
+    );
+
+    cancel_order(
+        &fixture,
+        &fixture.payer_keypair(),
+        &market_loans,
+        order_sequence_number,
+        true,
+    )
+    .await?;
+    assert_eq!(get_num_ask_levels(&fixture, true).await, 0);
+    fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn cancel_without_global_account() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await?;
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+
+    fixture
+        .base_a_mint_fixture
+        .mint_to(&fixture.payer_base_a_fixture.key, 10)
+        .await;
+    deposit_to_seat(
+        &fixture,
+        base_bank,
+        &fixture.base_a_marginfi_account,
+        &fixture.payer_base_a_fixture.key,
+        &fixture.base_a_token_program,
+        SEAT_DEPOSIT_ATOMS,
+    )
+    .await;
+
+    let mut optional_accounts: Vec<AccountMeta> =
+        marginfi_cpi_metas(&fixture, base_bank, &fixture.base_a_marginfi_account);
+    optional_accounts.extend(oracle_metas(base_bank).await);
+    place_order(
+        &fixture,
+        &market_loans,
+        order_params(false, OrderType::PostOnly),
+        optional_accounts,
+    )
+    .await?;
+    let order_sequence_number: u64 = get_last_order_sequence_number(&fixture).await;
+
+    // A plain limit order does not touch the global, so the system program
+    // stands in for it.
+    cancel_order_with_base_global(
+        &fixture,
+        &fixture.payer_keypair(),
+        &market_loans,
+        AccountMeta::new_readonly(system_program::id(), false),
+        order_sequence_number,
+        true,
+    )
+    .await?;
+    assert_eq!(get_num_ask_levels(&fixture, true).await, 0);
+    fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn cancel_global_ask_without_global_account_fails() -> anyhow::Result<()> {
+    let mut fixture: NixTestFixture = new_fixture().await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await?;
+    let order_sequence_number: u64 = place_global_ask(&mut fixture, &market_loans).await;
+
+    assert_nix_error(
+        cancel_order_with_base_global(
+            &fixture,
+            &fixture.payer_keypair(),
+            &market_loans,
+            AccountMeta::new_readonly(system_program::id(), false),
+            order_sequence_number,
+            true,
+        )
+        .await,
+        NixError::MissingGlobal,
+    );
+    assert_eq!(get_num_ask_levels(&fixture, true).await, 1);
+
+    cancel_order(
+        &fixture,
+        &fixture.payer_keypair(),
+        &market_loans,
+        order_sequence_number,
+        true,
+    )
+    .await?;
+    assert_eq!(get_num_ask_levels(&fixture, true).await, 0);
+    fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn deposit_sweeps_own_expired_orders() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await?;
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+
+    fixture
+        .base_a_mint_fixture
+        .mint_to(&fixture.payer_base_a_fixture.key, 20)
+        .await;
+    deposit_to_seat(
+        &fixture,
+        base_bank,
+        &fixture.base_a_marginfi_account,
+        &fixture.payer_base_a_fixture.key,
+        &fixture.base_a_token_program,
+        SEAT_DEPOSIT_ATOMS,
+    )
+    .await;
+
+    let mut optional_accounts: Vec<AccountMeta> =
+        marginfi_cpi_metas(&fixture, base_bank, &fixture.base_a_marginfi_account);
+    optional_accounts.extend(oracle_metas(base_bank).await);
+    let last_valid_slot: u32 = fixture.get_clock().await.slot as u32 + 1;
+    place_order(
+        &fixture,
+        &market_loans,
+        PlaceOrderParams {
+            last_valid_slot,
+            ..order_params(false, OrderType::PostOnly)
+        },
+        optional_accounts,
+    )
+    .await?;
+    assert_eq!(get_num_ask_levels(&fixture, true).await, 1);
+
+    // Warps past last_valid_slot.
+    fixture.advance_time(1).await;
+
+    // Without a sweep the expired ask stays on the book.
+    deposit_to_seat(
+        &fixture,
+        base_bank,
+        &fixture.base_a_marginfi_account,
+        &fixture.payer_base_a_fixture.key,
+        &fixture.base_a_token_program,
+        SEAT_DEPOSIT_ATOMS,
+    )
+    .await;
+    assert_eq!(get_num_ask_levels(&fixture, true).await, 1);
+
+    deposit_to_seat_with_params(
+        &fixture,
+        base_bank,
+        &fixture.base_a_marginfi_account,
+        &fixture.payer_base_a_fixture.key,
+        &fixture.base_a_token_program,
+        DepositParams {
+            max_expired_orders_to_sweep: 1,
+            ..DepositParams::new(SEAT_DEPOSIT_ATOMS, None, false)
+        },
+    )
+    .await;
+    assert_eq!(get_num_ask_levels(&fixture, true).await, 0);
+    fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn clean_expired_orders_pays_cranker_for_expired_asks() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await?;
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+
+    fixture
+        .base_a_mint_fixture
+        .mint_to(&fixture.payer_base_a_fixture.key, 20)
+        .await;
+    deposit_to_seat(
+        &fixture,
+        base_bank,
+        &fixture.base_a_marginfi_account,
+        &fixture.payer_base_a_fixture.key,
+        &fixture.base_a_token_program,
+        SEAT_DEPOSIT_ATOMS,
+    )
+    .await;
+
+    let mut optional_accounts: Vec<AccountMeta> =
+        marginfi_cpi_metas(&fixture, base_bank, &fixture.base_a_marginfi_account);
+    optional_accounts.extend(oracle_metas(base_bank).await);
+    let last_valid_unix_timestamp: u32 = fixture.get_clock().await.unix_timestamp as u32 + 1;
+    place_order(
+        &fixture,
+        &market_loans,
+        PlaceOrderParams {
+            last_valid_unix_timestamp,
+            ..order_params(false, OrderType::PostOnly)
+        },
+        optional_accounts.clone(),
+    )
+    .await?;
+    // A second ask at another rate that does not expire.
+    place_order(
+        &fixture,
+        &market_loans,
+        PlaceOrderParams {
+            rate_bps: RATE_BPS + 100,
+            ..order_params(false, OrderType::PostOnly)
+        },
+        optional_accounts,
+    )
+    .await?;
+    assert_eq!(get_num_ask_levels(&fixture, true).await, 2);
+
+    // The cranker has no seat on the market.
+    let cranker: Keypair = new_cranker(&fixture).await;
+    let cranker_lamports_before: u64 = get_account(&fixture, &cranker.pubkey()).await.lamports;
+
+    assert_eq!(get_num_expired_asks(&fixture).await, 0);
+
+    fixture.advance_time(2).await;
+    assert_eq!(get_num_expired_asks(&fixture).await, 1);
+    clean_expired_orders(&fixture, &cranker, &market_loans, false, 8).await?;
+
+    // Only the expired ask is removed and its gas deposit goes to the cranker.
+    assert_eq!(get_num_ask_levels(&fixture, true).await, 1);
+    assert_eq!(get_num_expired_asks(&fixture).await, 0);
+    assert_eq!(
+        get_account(&fixture, &cranker.pubkey()).await.lamports,
+        cranker_lamports_before + GTT_GAS_DEPOSIT_LAMPORTS
+    );
+    assert_eq!(get_num_active_loans(&fixture, &market_loans).await, 0);
+    fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn clean_expired_orders_converts_expired_bid_to_pool_loan() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await?;
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+    let quote_bank: &BankFixture = &fixture.base_b_bank_fixture;
+
+    // The bid borrows base from marginfi, so the bank needs liquidity.
+    let lender: MarginfiAccountFixture =
+        MarginfiAccountFixture::new(Rc::clone(&fixture.context), &fixture.group.key).await;
+    let lender_token: TokenAccountFixture =
+        base_bank.mint.create_token_account_and_mint_to(100).await;
+    lender
+        .try_bank_deposit(lender_token.key, base_bank, 100, None)
+        .await?;
+
+    fixture
+        .base_b_mint_fixture
+        .mint_to(&fixture.payer_base_b_fixture.key, 10_000)
+        .await;
+    deposit_to_seat(
+        &fixture,
+        quote_bank,
+        &fixture.base_b_marginfi_account,
+        &fixture.payer_base_b_fixture.key,
+        &fixture.base_b_token_program,
+        QUOTE_SEAT_DEPOSIT_ATOMS,
+    )
+    .await;
+
+    let mut optional_accounts: Vec<AccountMeta> = base_a_global_metas(&fixture);
+    optional_accounts.extend(marginfi_cpi_metas(
+        &fixture,
+        base_bank,
+        &fixture.base_a_marginfi_account,
+    ));
+    optional_accounts.extend(marginfi_cpi_metas(
+        &fixture,
+        quote_bank,
+        &fixture.base_b_marginfi_account,
+    ));
+    optional_accounts.extend(oracle_metas(quote_bank).await);
+    optional_accounts.extend(oracle_metas(base_bank).await);
+    let last_valid_unix_timestamp: u32 = fixture.get_clock().await.unix_timestamp as u32 + 1;
+    place_order(
+        &fixture,
+        &market_loans,
+        PlaceOrderParams {
+            last_valid_unix_timestamp,
+            expiry_policy: ExpiryPolicy::ConvertToPool,
+            ..order_params(true, OrderType::PostOnly)
+        },
+        optional_accounts,
+    )
+    .await?;
+    assert_eq!(get_num_active_loans(&fixture, &market_loans).await, 0);
+
+    fixture.advance_time(2).await;
+    let cranker: Keypair = new_cranker(&fixture).await;
+    clean_expired_orders(&fixture, &cranker, &market_loans, true, 8).await?;
+
+    // The borrow made when the bid rested stays open as a loan to the pool.
+    assert_eq!(get_num_active_loans(&fixture, &market_loans).await, 1);
+    fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn sweep_stranded_gas_after_expired_global_ask_is_crossed() -> anyhow::Result<()> {
+    let mut fixture: NixTestFixture = new_fixture().await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await?;
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+    let quote_bank: &BankFixture = &fixture.base_b_bank_fixture;
+    let global_key: Pubkey = fixture.base_a_global_fixture.key;
+
+    let last_valid_slot: u32 = fixture.get_clock().await.slot as u32 + 1;
+    place_global_ask_with_params(
+        &mut fixture,
+        &market_loans,
+        PlaceOrderParams {
+            last_valid_slot,
+            ..order_params(false, OrderType::Global)
+        },
+    )
+    .await;
+    fixture
+        .base_b_mint_fixture
+        .mint_to(&fixture.payer_base_b_fixture.key, 10_000)
+        .await;
+    deposit_to_seat(
+        &fixture,
+        quote_bank,
+        &fixture.base_b_marginfi_account,
+        &fixture.payer_base_b_fixture.key,
+        &fixture.base_b_token_program,
+        QUOTE_SEAT_DEPOSIT_ATOMS,
+    )
+    .await;
+
+    // Warps past last_valid_slot.
+    fixture.advance_time(1).await;
+
+    // The bid removes the expired ask without the global accounts, which
+    // strands its gas prepayment on the global.
+    let mut optional_accounts: Vec<AccountMeta> =
+        marginfi_cpi_metas(&fixture, base_bank, &fixture.base_a_marginfi_account);
+    optional_accounts.extend(marginfi_cpi_metas(
+        &fixture,
+        quote_bank,
+        &fixture.base_b_marginfi_account,
+    ));
+    optional_accounts.extend(oracle_metas(quote_bank).await);
+    optional_accounts.extend(oracle_metas(base_bank).await);
+    place_order(
+        &fixture,
+        &market_loans,
+        order_params(true, OrderType::ImmediateOrCancel),
+        optional_accounts,
+    )
+    .await?;
+    assert_eq!(get_num_ask_levels(&fixture, true).await, 0);
+    fixture.base_a_global_fixture.reload().await;
+    assert_eq!(
+        fixture
+            .base_a_global_fixture
+            .global
+            .get_gas_prepayment_lamports(&fixture.payer()),
+        GAS_DEPOSIT_LAMPORTS
+    );
+
+    // Someone else pays the fee so the trader only sees the prepayment.
+    let fee_payer: Keypair = new_cranker(&fixture).await;
+    let before_trader_lamports: u64 = get_account(&fixture, &fixture.payer()).await.lamports;
+    let sweep_stranded_gas_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts: vec![
+            AccountMeta::new(fixture.payer(), false),
+            AccountMeta::new(fixture.market, false),
+            AccountMeta::new(global_key, false),
+        ],
+        data: NixInstruction::SweepStrandedGas.to_vec(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[sweep_stranded_gas_ix],
+        Some(&fee_payer.pubkey()),
+        &[&fee_payer],
+    )
+    .await?;
+
+    assert_eq!(
+        get_account(&fixture, &fixture.payer()).await.lamports,
+        before_trader_lamports + GAS_DEPOSIT_LAMPORTS
+    );
+    fixture.base_a_global_fixture.reload().await;
+    assert_eq!(
+        fixture
+            .base_a_global_fixture
+            .global
+            .get_gas_prepayment_lamports(&fixture.payer()),
+        0
+    );
+    fixture.verify_market().await;
+    Ok(())
+}
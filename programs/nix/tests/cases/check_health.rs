@@ -0,0 +1,209 @@
+//! CheckHealth, which logs how well the loans of a seat are backed without
+//! writing anything.
+
+use fixed::types::I80F48;
+use nix::{
+    logs::CheckHealthLog,
+    program::{check_health::CheckHealthParams, get_dynamic_account, NixError, NixInstruction},
+    state::{ActiveLoan, MarketFixed, MarketLoansFixed, MarketLoansRef, MarketRef},
+};
+use solana_program::instruction::{AccountMeta, InstructionError};
+use solana_sdk::{
+    account::Account, pubkey::Pubkey, signature::Keypair, signer::Signer,
+    transaction::TransactionError,
+};
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{
+    get_account, get_logs, open_loan, oracle_metas, simulate_nix_log_data, NixTestFixture,
+    TradingMarket,
+};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+
+/// The fixture market with a loan from the lender to the borrower.
+struct Loan {
+    fixture: NixTestFixture,
+    market: TradingMarket,
+    lender: Keypair,
+    borrower: Keypair,
+    sequence_number: u64,
+}
+
+async fn new_loan() -> anyhow::Result<Loan> {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = TradingMarket {
+        key: fixture.market,
+        market_loans: fixture
+            .create_market_loan_account(&fixture.payer(), &fixture.market)
+            .await?,
+    };
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await?;
+    open_loan(
+        &fixture,
+        &market,
+        &lender,
+        &borrower,
+        ORDER_BASE_ATOMS,
+        RATE_BPS,
+    )
+    .await?;
+
+    let market_account: Account = get_account(&fixture, &market.key).await;
+    let market_ref: MarketRef = get_dynamic_account::<MarketFixed>(&market_account.data);
+    let market_loans_account: Account = get_account(&fixture, &market.market_loans).await;
+    let loans: MarketLoansRef = get_dynamic_account::<MarketLoansFixed>(&market_loans_account.data);
+    let borrowed_loans: Vec<ActiveLoan> =
+        loans.get_borrowed_loans(market_ref.get_trader_index(&borrower.pubkey()));
+    assert_eq!(borrowed_loans.len(), 1);
+    let sequence_number: u64 = borrowed_loans[0].sequence_number;
+    Ok(Loan {
+        fixture,
+        market,
+        lender,
+        borrower,
+        sequence_number,
+    })
+}
+
+async fn check_health_metas(loan: &Loan) -> Vec<AccountMeta> {
+    let fixture: &NixTestFixture = &loan.fixture;
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new_readonly(loan.market.key, false),
+        AccountMeta::new_readonly(loan.market.market_loans, false),
+        AccountMeta::new_readonly(fixture.base_a_bank_fixture.key, false),
+        AccountMeta::new_readonly(fixture.base_b_bank_fixture.key, false),
+        AccountMeta::new_readonly(fixture.base_a_mint_fixture.key, false),
+        AccountMeta::new_readonly(fixture.base_b_mint_fixture.key, false),
+    ];
+    accounts.extend(oracle_metas(&fixture.base_a_bank_fixture).await);
+    accounts.extend(oracle_metas(&fixture.base_b_bank_fixture).await);
+    accounts
+}
+
+/// Simulates CheckHealth for `trader`. Returns its log, or the error.
+async fn check_health(
+    loan: &Loan,
+    trader: &Pubkey,
+    loan_sequence_number: Option<u64>,
+) -> anyhow::Result<Result<CheckHealthLog, TransactionError>> {
+    let (result, log_data) = simulate_nix_log_data(
+        &loan.fixture,
+        &loan.fixture.payer_keypair(),
+        NixInstruction::CheckHealth,
+        check_health_metas(loan).await,
+        &CheckHealthParams {
+            trader: *trader,
+            loan_sequence_number,
+        },
+    )
+    .await?;
+    Ok(result.map(|()| {
+        let logs: Vec<CheckHealthLog> = get_logs(&log_data);
+        assert_eq!(logs.len(), 1);
+        logs[0]
+    }))
+}
+
+fn assert_custom_error(result: Result<CheckHealthLog, TransactionError>, expected: NixError) {
+    match result {
+        Err(TransactionError::InstructionError(_, InstructionError::Custom(code))) => {
+            assert_eq!(code, expected as u32)
+        }
+        Err(other) => panic!("Expected {:?}, got {:?}", expected, other),
+        Ok(_) => panic!("Expected {:?}, got a log", expected),
+    }
+}
+
+#[tokio::test]
+async fn check_health_logs_a_backed_loan() -> anyhow::Result<()> {
+    let loan: Loan = new_loan().await?;
+
+    let log: CheckHealthLog = check_health(&loan, &loan.borrower.pubkey(), None)
+        .await?
+        .unwrap();
+    assert_eq!(log.market, loan.market.key);
+    assert_eq!(log.market_loans, loan.market.market_loans);
+    assert_eq!(log.trader, loan.borrower.pubkey());
+    assert_eq!(log.num_loans, 1);
+    let collateral_value_usd: I80F48 = I80F48::from(log.collateral_value_usd);
+    let liability_value_usd: I80F48 = I80F48::from(log.liability_value_usd);
+    assert!(collateral_value_usd.is_positive());
+    assert!(liability_value_usd.is_positive());
+    assert_eq!(
+        I80F48::from(log.ltv),
+        liability_value_usd / collateral_value_usd
+    );
+    assert!(I80F48::from(log.distance_to_liquidation_usd).is_positive());
+
+    // The same loan by sequence number.
+    let single_log: CheckHealthLog =
+        check_health(&loan, &loan.borrower.pubkey(), Some(loan.sequence_number))
+            .await?
+            .unwrap();
+    assert_eq!(single_log.num_loans, 1);
+    assert_eq!(
+        I80F48::from(single_log.liability_value_usd),
+        liability_value_usd
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn check_health_goes_negative_once_the_loan_is_not_backed() -> anyhow::Result<()> {
+    let loan: Loan = new_loan().await?;
+    let fixture: &NixTestFixture = &loan.fixture;
+    let base_oracle: Pubkey = fixture.base_a_bank_fixture.load().await.config.oracle_keys[0];
+
+    // The lent base is worth more than all of the collateral.
+    fixture
+        .set_pyth_oracle_price(base_oracle, 1_000_000.0)
+        .await;
+    let log: CheckHealthLog = check_health(&loan, &loan.borrower.pubkey(), None)
+        .await?
+        .unwrap();
+    assert!(I80F48::from(log.distance_to_liquidation_usd).is_negative());
+    assert!(I80F48::from(log.ltv) > I80F48::ONE);
+    Ok(())
+}
+
+#[tokio::test]
+async fn check_health_needs_a_loan_of_the_trader() -> anyhow::Result<()> {
+    let loan: Loan = new_loan().await?;
+
+    // The lender borrowed nothing.
+    let log: CheckHealthLog = check_health(&loan, &loan.lender.pubkey(), None)
+        .await?
+        .unwrap();
+    assert_eq!(log.num_loans, 0);
+    assert_eq!(I80F48::from(log.ltv), I80F48::ZERO);
+
+    assert_custom_error(
+        check_health(&loan, &loan.lender.pubkey(), Some(loan.sequence_number)).await?,
+        NixError::InvalidActiveLoan,
+    );
+    assert_custom_error(
+        check_health(
+            &loan,
+            &loan.borrower.pubkey(),
+            Some(loan.sequence_number + 1),
+        )
+        .await?,
+        NixError::InvalidActiveLoan,
+    );
+    assert_custom_error(
+        check_health(&loan, &Pubkey::new_unique(), None).await?,
+        NixError::AlreadyClaimedSeat,
+    );
+    Ok(())
+}
@@ -0,0 +1,83 @@
+//! Reading a live market with nothing but the `client` module, the way an
+//! indexer built without the `program` feature does.
+
+use std::mem::size_of;
+
+use nix::{
+    client::{
+        get_discriminant, get_helper, BaseAtoms, DynamicAccount, MarketFixed, MarketRef, OrderType,
+        Rate, SeatSnapshot,
+    },
+    program::place_order::PlaceOrderParams,
+};
+use solana_sdk::{account::Account, signer::Signer};
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{get_account, place_order, NixTestFixture, TradingMarket};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+
+/// Splits market account data the way a client without
+/// program::get_dynamic_account has to.
+fn read_market(data: &[u8]) -> MarketRef {
+    let (fixed_data, dynamic) = data.split_at(size_of::<MarketFixed>());
+    DynamicAccount {
+        fixed: get_helper::<MarketFixed>(fixed_data, 0_u32),
+        dynamic,
+    }
+}
+
+#[tokio::test]
+async fn client_reads_the_market_header_seats_and_book() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = TradingMarket {
+        key: fixture.market,
+        market_loans: fixture
+            .create_market_loan_account(&fixture.payer(), &fixture.market)
+            .await?,
+    };
+    let (lender, _borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await?;
+    place_order(
+        &fixture,
+        &market,
+        &lender,
+        PlaceOrderParams::new(
+            BaseAtoms::new(ORDER_BASE_ATOMS),
+            Rate::from_bps(RATE_BPS),
+            false,
+            true,
+            OrderType::PostOnly,
+        ),
+        market.ask_metas(&fixture).await,
+    )
+    .await?;
+
+    let account: Account = get_account(&fixture, &market.key).await;
+    let market_ref: MarketRef = read_market(&account.data);
+    assert_eq!(
+        market_ref.fixed.discriminant,
+        get_discriminant::<MarketFixed>()?
+    );
+    assert_eq!(
+        *market_ref.fixed.get_base_a_mint(),
+        fixture.base_a_mint_fixture.key
+    );
+    let seat: SeatSnapshot = market_ref
+        .get_seat_snapshot(&lender.pubkey())
+        .expect("lender has a seat");
+    assert_eq!(seat.num_open_orders, 1);
+    let (asks, num_asks) = market_ref.get_book_levels(true, false, 8, 0, 0)?;
+    assert_eq!(num_asks, 1);
+    assert_eq!(asks[0].rate_bps, RATE_BPS);
+    Ok(())
+}
@@ -0,0 +1,233 @@
+use std::rc::Rc;
+
+use nix::{
+    program::{NixError, NixInstruction},
+    state::MARKET_LOANS_FIXED_SIZE,
+    validation::{
+        get_market_auction_address, get_market_fee_receiver_address, get_market_insurance_address,
+        get_market_registry_address, get_market_signer_address, get_market_stats_address,
+        get_vault_address,
+    },
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction, InstructionError},
+    system_instruction, system_program,
+};
+use solana_program_test::BanksClientError;
+use solana_sdk::{
+    pubkey::Pubkey, signature::Keypair, signer::Signer, transaction::TransactionError,
+};
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{send_tx_with_retry, NixTestFixture};
+
+async fn new_fixture() -> NixTestFixture {
+    NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await
+}
+
+async fn get_lamports(fixture: &NixTestFixture, key: &Pubkey) -> u64 {
+    fixture
+        .try_load(key)
+        .await
+        .unwrap()
+        .map_or(0, |account| account.lamports)
+}
+
+fn assert_nix_error(result: Result<(), BanksClientError>, expected: NixError) {
+    match result {
+        Err(BanksClientError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        ))) => assert_eq!(code, expected as u32),
+        other => panic!("Expected {:?}, got {:?}", expected, other),
+    }
+}
+
+/// Creates a loans page and links it after `previous_market_loans`.
+async fn create_chained_market_loans(
+    fixture: &NixTestFixture,
+    previous_market_loans: &Pubkey,
+) -> Pubkey {
+    let page: Keypair = Keypair::new();
+    let rent: u64 = fixture
+        .get_minimum_rent_for_size(MARKET_LOANS_FIXED_SIZE)
+        .await;
+    let create_account_ix: Instruction = system_instruction::create_account(
+        &fixture.payer(),
+        &page.pubkey(),
+        rent,
+        MARKET_LOANS_FIXED_SIZE as u64,
+        &nix::ID,
+    );
+    let create_market_loan_account_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts: vec![
+            AccountMeta::new(fixture.payer(), true),
+            AccountMeta::new(page.pubkey(), false),
+            AccountMeta::new_readonly(fixture.market, false),
+            AccountMeta::new(*previous_market_loans, false),
+        ],
+        data: NixInstruction::CreateMarketLoanAccount.to_vec(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[create_account_ix, create_market_loan_account_ix],
+        Some(&fixture.payer()),
+        &[&fixture.payer_keypair(), &page],
+    )
+    .await
+    .unwrap();
+    page.pubkey()
+}
+
+async fn create_market_stats(fixture: &NixTestFixture) -> Pubkey {
+    let market_stats: Pubkey = get_market_stats_address(&fixture.market).0;
+    let create_market_stats_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts: vec![
+            AccountMeta::new(fixture.payer(), true),
+            AccountMeta::new_readonly(fixture.market, false),
+            AccountMeta::new(market_stats, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: NixInstruction::CreateMarketStats.to_vec(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[create_market_stats_ix],
+        Some(&fixture.payer()),
+        &[&fixture.payer_keypair()],
+    )
+    .await
+    .unwrap();
+    market_stats
+}
+
+/// CloseMarket accounts of the fixture market with `market_loans_pages`
+/// passed in order after the fixed accounts.
+fn close_market_ix(fixture: &NixTestFixture, market_loans_pages: &[Pubkey]) -> Instruction {
+    let base_a_mint: Pubkey = fixture.base_a_mint_fixture.key;
+    let base_b_mint: Pubkey = fixture.base_b_mint_fixture.key;
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(fixture.payer(), true),
+        AccountMeta::new(fixture.market, false),
+        AccountMeta::new(market_loans_pages[0], false),
+        AccountMeta::new_readonly(get_market_signer_address(&fixture.market).0, false),
+        AccountMeta::new(get_vault_address(&fixture.market, &base_a_mint).0, false),
+        AccountMeta::new(get_vault_address(&fixture.market, &base_b_mint).0, false),
+        AccountMeta::new(
+            get_market_fee_receiver_address(&fixture.market, &base_a_mint).0,
+            false,
+        ),
+        AccountMeta::new(
+            get_market_fee_receiver_address(&fixture.market, &base_b_mint).0,
+            false,
+        ),
+        AccountMeta::new(fixture.base_a_marginfi_account, false),
+        AccountMeta::new(fixture.base_b_marginfi_account, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(spl_token_2022::id(), false),
+        AccountMeta::new_readonly(marginfi::ID, false),
+        AccountMeta::new(get_market_stats_address(&fixture.market).0, false),
+        AccountMeta::new(get_market_auction_address(&fixture.market).0, false),
+        AccountMeta::new(get_market_insurance_address(&fixture.market).0, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new(
+            get_market_registry_address(&base_a_mint, &base_b_mint).0,
+            false,
+        ),
+    ];
+    accounts.extend(
+        market_loans_pages[1..]
+            .iter()
+            .map(|page| AccountMeta::new(*page, false)),
+    );
+    Instruction {
+        program_id: nix::ID,
+        accounts,
+        data: NixInstruction::CloseMarket.to_vec(),
+    }
+}
+
+#[tokio::test]
+async fn close_market_closes_every_loans_page_and_side_account() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await?;
+    let next_market_loans: Pubkey = create_chained_market_loans(&fixture, &market_loans).await;
+    let market_stats: Pubkey = create_market_stats(&fixture).await;
+
+    let closed_accounts: [Pubkey; 4] = [
+        fixture.market,
+        market_loans,
+        next_market_loans,
+        market_stats,
+    ];
+    let mut closed_lamports: u64 = 0;
+    for key in closed_accounts.iter() {
+        let lamports: u64 = get_lamports(&fixture, key).await;
+        assert!(lamports > 0);
+        closed_lamports += lamports;
+    }
+    let before_payer_lamports: u64 = get_lamports(&fixture, &fixture.payer()).await;
+
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[close_market_ix(
+            &fixture,
+            &[market_loans, next_market_loans],
+        )],
+        Some(&fixture.payer()),
+        &[&fixture.payer_keypair()],
+    )
+    .await?;
+
+    for key in closed_accounts.iter() {
+        assert!(fixture.try_load(key).await?.is_none());
+    }
+    // The rent of the token and marginfi accounts comes back on top and is
+    // far more than the transaction fee.
+    assert!(
+        get_lamports(&fixture, &fixture.payer()).await > before_payer_lamports + closed_lamports
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn close_market_requires_every_chained_loans_page() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await?;
+    let next_market_loans: Pubkey = create_chained_market_loans(&fixture, &market_loans).await;
+
+    let result: Result<(), BanksClientError> = send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[close_market_ix(&fixture, &[market_loans])],
+        Some(&fixture.payer()),
+        &[&fixture.payer_keypair()],
+    )
+    .await;
+    assert!(result.is_err());
+    assert!(fixture.try_load(&next_market_loans).await?.is_some());
+    assert!(fixture.try_load(&fixture.market).await?.is_some());
+
+    let stray_page: Pubkey = Pubkey::new_unique();
+    assert_nix_error(
+        send_tx_with_retry(
+            Rc::clone(&fixture.context),
+            &[close_market_ix(&fixture, &[market_loans, stray_page])],
+            Some(&fixture.payer()),
+            &[&fixture.payer_keypair()],
+        )
+        .await,
+        NixError::IncorrectAccount,
+    );
+    Ok(())
+}
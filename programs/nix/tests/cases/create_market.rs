@@ -1,7 +1,36 @@
+use std::rc::Rc;
+
 use anyhow::Ok;
-use test_utilities::test::{BankMint, TestSettings};
+use borsh::BorshSerialize;
+use marginfi::state::marginfi_account::MarginfiAccount;
+use nix::{
+    program::{
+        create_market::CreateMarketParams,
+        create_market_pda::{CreateMarketPdaParams, MAX_CANONICAL_PROTOCOL_FEE_RATE_BPS},
+        get_dynamic_account, NixError, NixInstruction,
+    },
+    state::{MarketFixed, MarketRef, MARKET_LOANS_FIXED_SIZE},
+    validation::{
+        get_market_address, get_market_fee_receiver_address, get_market_signer_address,
+        get_nix_marginfi_account_address, get_vault_address,
+    },
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction, InstructionError},
+    system_instruction, system_program,
+};
+use solana_program_test::BanksClientError;
+use solana_sdk::{
+    account::Account, pubkey::Pubkey, signature::Keypair, signer::Signer,
+    transaction::TransactionError,
+};
+use test_utilities::{
+    bank::BankFixture,
+    spl::SupportedExtension,
+    test::{BankMint, TestSettings},
+};
 
-use crate::test_utils::NixTestFixture;
+use crate::test_utils::{send_tx_with_retry, NixTestFixture};
 use test_case::test_case; 
 
 #[test_case(&BankMint::SolSwbPull, &BankMint::Usdc)] 
@@ -11,5 +40,305 @@ async fn create_market(
     base_b_mint: &BankMint,
 ) -> anyhow::Result<()> {
     let fixture = NixTestFixture::new(Some(TestSettings::all_banks_payer_not_admin()), base_a_mint, base_b_mint).await;
+    fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn create_market_marginfi_accounts_belong_to_market_signer() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let (market_signer, _) = get_market_signer_address(&fixture.market);
+    for marginfi_account_key in [
+        fixture.base_a_marginfi_account,
+        fixture.base_b_marginfi_account,
+    ] {
+        let marginfi_account: MarginfiAccount =
+            fixture.load_and_deserialize(&marginfi_account_key).await;
+        assert_eq!(marginfi_account.group, fixture.group.key);
+        assert_eq!(marginfi_account.authority, market_signer);
+        assert_ne!(marginfi_account.authority, marginfi_account_key);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn create_market_derives_base_b_accounts_from_base_b_mint() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    for (mint, marginfi_account) in [
+        (
+            fixture.base_a_mint_fixture.key,
+            fixture.base_a_marginfi_account,
+        ),
+        (
+            fixture.base_b_mint_fixture.key,
+            fixture.base_b_marginfi_account,
+        ),
+    ] {
+        assert_eq!(
+            get_nix_marginfi_account_address(&fixture.market, &mint).0,
+            marginfi_account
+        );
+        let fee_receiver: Pubkey = get_market_fee_receiver_address(&fixture.market, &mint).0;
+        let fee_receiver_account: Account = fixture
+            .try_load(&fee_receiver)
+            .await?
+            .expect("Fee receiver not created");
+        // Mint sits at the start of token and token22 accounts.
+        assert_eq!(fee_receiver_account.data[0..32], mint.to_bytes());
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn create_market_with_interest_bearing_mint() -> anyhow::Result<()> {
+    let fixture = NixTestFixture::new_with_t22_extension(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &[SupportedExtension::InterestBearing],
+        &BankMint::UsdcT22,
+        &BankMint::SolSwbPull,
+    )
+    .await;
+    fixture.verify_market().await;
+    Ok(())
+}
+
+fn assert_nix_error(result: Result<(), BanksClientError>, expected: NixError) {
+    match result {
+        Err(BanksClientError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        ))) => assert_eq!(code, expected as u32),
+        other => panic!("Expected {:?}, got {:?}", expected, other),
+    }
+}
+
+fn market_params(
+    protocol_fee_rate_bps: u64,
+    allowlist_authority: Option<Pubkey>,
+) -> CreateMarketParams {
+    CreateMarketParams {
+        protocol_fee_rate_bps,
+        marginfi_market_buffer_bps: 0,
+        referral_bps: 0,
+        maker_rebate_bps: 0,
+        min_reverse_spread_bps: 0,
+        max_reverse_spread_bps: 0,
+        max_open_orders_per_seat: 0,
+        allowlist_authority,
+        fee_on_interest: false,
+        max_borrow_utilization_bps: 0,
+    }
+}
+
+/// Mints and banks of the fixture ordered the way canonical markets need
+/// them, base A first.
+fn get_sorted_sides(fixture: &NixTestFixture) -> [&BankFixture; 2] {
+    let mut sides: [&BankFixture; 2] = [&fixture.base_a_bank_fixture, &fixture.base_b_bank_fixture];
+    sides.sort_by_key(|bank: &&BankFixture| bank.mint.key);
+    sides
+}
+
+/// Allocates a loans account and creates the market at the PDA of the
+/// fixture's mint pair and `nonce` in the same transaction. Returns the
+/// market key.
+async fn create_market_pda(
+    fixture: &NixTestFixture,
+    nonce: u64,
+    params: CreateMarketParams,
+) -> Result<Pubkey, BanksClientError> {
+    let [base_a_bank, base_b_bank] = get_sorted_sides(fixture);
+    let market: Pubkey = get_market_address(&base_a_bank.mint.key, &base_b_bank.mint.key, nonce).0;
+    let market_loans: Keypair = Keypair::new();
+    let rent: u64 = fixture
+        .get_minimum_rent_for_size(MARKET_LOANS_FIXED_SIZE)
+        .await;
+    let create_market_loans_ix: Instruction = system_instruction::create_account(
+        &fixture.payer(),
+        &market_loans.pubkey(),
+        rent,
+        MARKET_LOANS_FIXED_SIZE as u64,
+        &nix::ID,
+    );
+
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(fixture.payer(), true),
+        AccountMeta::new(market, false),
+        AccountMeta::new(market_loans.pubkey(), false),
+        AccountMeta::new_readonly(get_market_signer_address(&market).0, false),
+        AccountMeta::new_readonly(base_a_bank.mint.key, false),
+        AccountMeta::new_readonly(base_b_bank.mint.key, false),
+    ];
+    for bank in [base_a_bank, base_b_bank] {
+        accounts.push(AccountMeta::new(
+            get_market_fee_receiver_address(&market, &bank.mint.key).0,
+            false,
+        ));
+    }
+    for bank in [base_a_bank, base_b_bank] {
+        accounts.push(AccountMeta::new(
+            get_vault_address(&market, &bank.mint.key).0,
+            false,
+        ));
+    }
+    for bank in [base_a_bank, base_b_bank] {
+        accounts.extend([
+            AccountMeta::new_readonly(fixture.group.key, false),
+            AccountMeta::new_readonly(bank.key, false),
+            AccountMeta::new(
+                get_nix_marginfi_account_address(&market, &bank.mint.key).0,
+                false,
+            ),
+        ]);
+    }
+    accounts.extend([
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(spl_token_2022::id(), false),
+    ]);
+    let create_market_pda_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts,
+        data: [
+            NixInstruction::CreateMarketPda.to_vec(),
+            CreateMarketPdaParams {
+                nonce,
+                market_params: params,
+            }
+            .try_to_vec()?,
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[create_market_loans_ix, create_market_pda_ix],
+        Some(&fixture.payer()),
+        &[&fixture.payer_keypair(), &market_loans],
+    )
+    .await?;
+    Result::Ok(market)
+}
+
+#[tokio::test]
+async fn create_market_pda_creates_the_market_at_its_pda() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let [base_a_bank, base_b_bank] = get_sorted_sides(&fixture);
+
+    let market: Pubkey = create_market_pda(&fixture, 0, market_params(0, None)).await?;
+
+    let market_account: Account = fixture.try_load(&market).await?.unwrap();
+    assert_eq!(market_account.owner, nix::ID);
+    let market_ref: MarketRef = get_dynamic_account::<MarketFixed>(&market_account.data);
+    assert_eq!(*market_ref.get_base_a_mint(), base_a_bank.mint.key);
+    assert_eq!(*market_ref.get_base_b_mint(), base_b_bank.mint.key);
+    let (market_signer, _) = get_market_signer_address(&market);
+    for bank in [base_a_bank, base_b_bank] {
+        let marginfi_account: MarginfiAccount = fixture
+            .load_and_deserialize(&get_nix_marginfi_account_address(&market, &bank.mint.key).0)
+            .await;
+        assert_eq!(marginfi_account.authority, market_signer);
+    }
+
+    // The PDA is taken now.
+    assert!(create_market_pda(&fixture, 0, market_params(0, None))
+        .await
+        .is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn create_market_pda_restricts_the_canonical_market() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+
+    assert_nix_error(
+        create_market_pda(
+            &fixture,
+            0,
+            market_params(MAX_CANONICAL_PROTOCOL_FEE_RATE_BPS + 1, None),
+        )
+        .await
+        .map(|_| ()),
+        NixError::InvalidMarketParameters,
+    );
+    assert_nix_error(
+        create_market_pda(&fixture, 0, market_params(0, Some(Pubkey::new_unique())))
+            .await
+            .map(|_| ()),
+        NixError::InvalidMarketParameters,
+    );
+    // Other nonces take any terms.
+    create_market_pda(
+        &fixture,
+        1,
+        market_params(
+            MAX_CANONICAL_PROTOCOL_FEE_RATE_BPS + 1,
+            Some(Pubkey::new_unique()),
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+// Anyone can send lamports to the market PDA before it is created, short of
+// rent exemption or past it.
+#[test_case(1_000 ; "below rent")]
+#[test_case(10_000_000_000 ; "above rent")]
+#[tokio::test]
+async fn create_market_pda_takes_a_funded_pda(funded_lamports: u64) -> anyhow::Result<()> {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let [base_a_bank, base_b_bank] = get_sorted_sides(&fixture);
+    let market: Pubkey = get_market_address(&base_a_bank.mint.key, &base_b_bank.mint.key, 0).0;
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[system_instruction::transfer(
+            &fixture.payer(),
+            &market,
+            funded_lamports,
+        )],
+        Some(&fixture.payer()),
+        &[&fixture.payer_keypair()],
+    )
+    .await?;
+
+    assert_eq!(
+        create_market_pda(&fixture, 0, market_params(0, None)).await?,
+        market
+    );
+
+    let market_account: Account = fixture.try_load(&market).await?.unwrap();
+    assert_eq!(market_account.owner, nix::ID);
+    assert!(market_account.lamports >= funded_lamports);
+    assert!(
+        market_account.lamports
+            >= fixture
+                .get_minimum_rent_for_size(market_account.data.len())
+                .await
+    );
+    let market_ref: MarketRef = get_dynamic_account::<MarketFixed>(&market_account.data);
+    assert_eq!(*market_ref.get_base_a_mint(), base_a_bank.mint.key);
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,217 @@
+//! Cross margin seats, which register a trader owned marginfi account whose
+//! health bids from the seat also have to cover once it is delegated to the
+//! market signer.
+
+use std::rc::Rc;
+
+use hypertree::get_helper;
+use nix::{
+    program::{place_order::PlaceOrderParams, NixError, NixInstruction},
+    quantities::{BaseAtoms, Rate},
+    state::{CrossMarginSeat, OrderType},
+    validation::get_cross_margin_seat_address,
+};
+use solana_program::{instruction::AccountMeta, system_program};
+use solana_program_test::BanksClientError;
+use solana_sdk::{account::Account, pubkey::Pubkey, signature::Keypair, signer::Signer};
+use test_utilities::{
+    marginfi_account::MarginfiAccountFixture,
+    test::{BankMint, TestSettings},
+};
+
+use crate::test_utils::{
+    assert_nix_error, default_market_params, get_account, place_order, send_nix_instruction,
+    NixTestFixture, TradingMarket,
+};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+
+async fn new_fixture() -> NixTestFixture {
+    NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await
+}
+
+/// Marginfi account of the payer in the fixture group.
+async fn new_marginfi_account(fixture: &NixTestFixture) -> Pubkey {
+    MarginfiAccountFixture::new(Rc::clone(&fixture.context), &fixture.group.key)
+        .await
+        .key
+}
+
+async fn create_cross_margin_seat(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    trader: &Keypair,
+    marginfi_account: &Pubkey,
+) -> Result<(), BanksClientError> {
+    send_nix_instruction(
+        fixture,
+        trader,
+        NixInstruction::CreateCrossMarginSeat,
+        vec![
+            AccountMeta::new(trader.pubkey(), true),
+            AccountMeta::new_readonly(market.key, false),
+            AccountMeta::new(
+                get_cross_margin_seat_address(&market.key, &trader.pubkey()).0,
+                false,
+            ),
+            AccountMeta::new_readonly(*marginfi_account, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        &(),
+    )
+    .await
+}
+
+/// Rests an ask from the lender and takes it with a bid from the borrower
+/// with `cross_margin_metas` appended.
+async fn open_loan_with_cross_margin(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    lender: &Keypair,
+    borrower: &Keypair,
+    cross_margin_metas: Vec<AccountMeta>,
+) -> Result<(), BanksClientError> {
+    place_order(
+        fixture,
+        market,
+        lender,
+        PlaceOrderParams::new(
+            BaseAtoms::new(ORDER_BASE_ATOMS),
+            Rate::from_bps(RATE_BPS),
+            false,
+            true,
+            OrderType::PostOnly,
+        ),
+        market.ask_metas(fixture).await,
+    )
+    .await?;
+    let mut optional_accounts: Vec<AccountMeta> = market.bid_metas(fixture).await;
+    optional_accounts.extend(cross_margin_metas);
+    place_order(
+        fixture,
+        market,
+        borrower,
+        PlaceOrderParams::new(
+            BaseAtoms::new(ORDER_BASE_ATOMS),
+            Rate::from_bps(RATE_BPS),
+            true,
+            true,
+            OrderType::ImmediateOrCancel,
+        ),
+        optional_accounts,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn create_cross_margin_seat_registers_the_account() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let market: TradingMarket = fixture
+        .create_market_with_params(default_market_params())
+        .await?;
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await?;
+    let marginfi_account: Pubkey = new_marginfi_account(&fixture).await;
+
+    // The account has to be under the authority of the trader registering it.
+    assert_nix_error(
+        create_cross_margin_seat(&fixture, &market, &lender, &marginfi_account).await,
+        NixError::InvalidMarginfiAccount,
+    );
+
+    create_cross_margin_seat(&fixture, &market, &borrower, &marginfi_account).await?;
+    let (cross_margin_seat_key, _) = get_cross_margin_seat_address(&market.key, &borrower.pubkey());
+    let account: Account = get_account(&fixture, &cross_margin_seat_key).await;
+    assert_eq!(account.owner, nix::ID);
+    let cross_margin_seat: &CrossMarginSeat = get_helper::<CrossMarginSeat>(&account.data, 0_u32);
+    assert_eq!(cross_margin_seat.market, market.key);
+    assert_eq!(cross_margin_seat.trader, borrower.pubkey());
+    assert_eq!(cross_margin_seat.marginfi_account, marginfi_account);
+
+    // One per seat.
+    assert!(
+        create_cross_margin_seat(&fixture, &market, &borrower, &marginfi_account)
+            .await
+            .is_err()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn create_cross_margin_seat_needs_a_seat() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let market: TradingMarket = fixture
+        .create_market_with_params(default_market_params())
+        .await?;
+    let marginfi_account: Pubkey = new_marginfi_account(&fixture).await;
+
+    assert_nix_error(
+        create_cross_margin_seat(
+            &fixture,
+            &market,
+            &fixture.payer_keypair(),
+            &marginfi_account,
+        )
+        .await,
+        NixError::AlreadyClaimedSeat,
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn bid_checks_the_cross_margin_account_when_passed() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let market: TradingMarket = fixture
+        .create_market_with_params(default_market_params())
+        .await?;
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await?;
+    let marginfi_account: Pubkey = new_marginfi_account(&fixture).await;
+    create_cross_margin_seat(&fixture, &market, &borrower, &marginfi_account).await?;
+    let cross_margin_seat_meta: AccountMeta = AccountMeta::new_readonly(
+        get_cross_margin_seat_address(&market.key, &borrower.pubkey()).0,
+        false,
+    );
+
+    assert_nix_error(
+        open_loan_with_cross_margin(
+            &fixture,
+            &market,
+            &lender,
+            &borrower,
+            vec![cross_margin_seat_meta.clone()],
+        )
+        .await,
+        NixError::MissingAccounts,
+    );
+    // Still under the authority of the trader, not delegated to the market.
+    assert_nix_error(
+        open_loan_with_cross_margin(
+            &fixture,
+            &market,
+            &lender,
+            &borrower,
+            vec![
+                cross_margin_seat_meta,
+                AccountMeta::new_readonly(marginfi_account, false),
+            ],
+        )
+        .await,
+        NixError::InvalidMarginfiAccount,
+    );
+
+    // Without the cross margin seat the bid is backed by the seat alone.
+    open_loan_with_cross_margin(&fixture, &market, &lender, &borrower, Vec::new()).await?;
+    fixture.verify_market().await;
+    Ok(())
+}
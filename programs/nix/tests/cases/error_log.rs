@@ -0,0 +1,162 @@
+//! ErrorLog, which names the instruction and the account a failed account
+//! check of PlaceOrder was about, and SourceErrorLog, which keeps the code
+//! marginfi failed with behind the NixError returned for it.
+
+use nix::{
+    logs::{ErrorLog, SourceErrorLog},
+    program::{place_order::PlaceOrderParams, NixError, NixInstruction},
+    quantities::{BaseAtoms, Rate},
+    state::OrderType,
+};
+use solana_program::{instruction::AccountMeta, system_program};
+use solana_sdk::{
+    instruction::InstructionError, pubkey::Pubkey, signature::Keypair,
+    transaction::TransactionError,
+};
+use test_case::test_case;
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{
+    get_logs, place_order_metas, simulate_nix_log_data, NixTestFixture, TradingMarket,
+    VersionedPlaceOrderParams,
+};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+/// The second mint of the fixed accounts.
+const BASE_B_MINT_INDEX: usize = 6;
+/// The bank of the first set of marginfi accounts, after the group.
+const MARGINFI_BANK_INDEX: usize = 8;
+/// Far past the maximum age of the fixture oracles.
+const STALE_ORACLE_SECONDS: i64 = 24 * 60 * 60;
+
+async fn new_market() -> (NixTestFixture, TradingMarket, Keypair) {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = TradingMarket {
+        key: fixture.market,
+        market_loans: fixture
+            .create_market_loan_account(&fixture.payer(), &fixture.market)
+            .await
+            .unwrap(),
+    };
+    let (lender, _borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await
+        .unwrap();
+    (fixture, market, lender)
+}
+
+/// Simulates a post only ask with the account at `replaced_index` swapped for
+/// `replacement`, or as is without one. Returns the result and the logged
+/// data.
+async fn simulate_ask(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    lender: &Keypair,
+    replacement: Option<(usize, Pubkey)>,
+) -> (Result<(), TransactionError>, Vec<Vec<u8>>) {
+    let mut accounts: Vec<AccountMeta> = place_order_metas(fixture, market, lender);
+    accounts.extend(market.ask_metas(fixture).await);
+    if let Some((replaced_index, replacement)) = replacement {
+        accounts[replaced_index].pubkey = replacement;
+    }
+    simulate_nix_log_data(
+        fixture,
+        lender,
+        NixInstruction::PlaceOrder,
+        accounts,
+        &VersionedPlaceOrderParams(&PlaceOrderParams::new(
+            BaseAtoms::new(ORDER_BASE_ATOMS),
+            Rate::from_bps(RATE_BPS),
+            false,
+            true,
+            OrderType::PostOnly,
+        )),
+    )
+    .await
+    .unwrap()
+}
+
+// Neither mint matches, which is logged at the first of the two.
+#[test_case(BASE_B_MINT_INDEX, system_program::id(), NixError::InvalidMint, BASE_B_MINT_INDEX - 1 ; "unknown mint")]
+#[test_case(MARGINFI_BANK_INDEX, Pubkey::new_unique(), NixError::InvalidDepositAccounts, MARGINFI_BANK_INDEX ; "unknown marginfi bank")]
+#[tokio::test]
+async fn place_order_logs_the_failed_account(
+    replaced_index: usize,
+    replacement: Pubkey,
+    expected_error: NixError,
+    expected_account_index: usize,
+) -> anyhow::Result<()> {
+    let (fixture, market, lender) = new_market().await;
+
+    let (result, log_data) = simulate_ask(
+        &fixture,
+        &market,
+        &lender,
+        Some((replaced_index, replacement)),
+    )
+    .await;
+    let error_logs: Vec<ErrorLog> = get_logs(&log_data);
+    assert_eq!(
+        result,
+        Err(TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(expected_error as u32)
+        ))
+    );
+    assert_eq!(error_logs.len(), 1);
+    // The code in the log is the one the transaction failed with.
+    assert_eq!(error_logs[0].error_code, expected_error as u64);
+    assert_eq!(
+        error_logs[0].instruction_tag,
+        NixInstruction::PlaceOrder as u8
+    );
+    assert_eq!(error_logs[0].account_index as usize, expected_account_index);
+    Ok(())
+}
+
+#[tokio::test]
+async fn place_order_that_passes_its_checks_logs_no_error() -> anyhow::Result<()> {
+    let (fixture, market, lender) = new_market().await;
+
+    let (result, log_data) = simulate_ask(&fixture, &market, &lender, None).await;
+    assert_eq!(result, Ok(()));
+    assert!(get_logs::<ErrorLog>(&log_data).is_empty());
+    assert!(get_logs::<SourceErrorLog>(&log_data).is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn stale_oracle_logs_the_marginfi_error() -> anyhow::Result<()> {
+    let (fixture, market, lender) = new_market().await;
+    fixture.advance_time(STALE_ORACLE_SECONDS).await;
+
+    let (result, log_data) = simulate_ask(&fixture, &market, &lender, None).await;
+    // The code stays the nix one whatever marginfi failed with.
+    assert_eq!(
+        result,
+        Err(TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(NixError::OracleFailed as u32)
+        ))
+    );
+    let source_error_logs: Vec<SourceErrorLog> = get_logs(&log_data);
+    assert_eq!(source_error_logs.len(), 1);
+    assert_eq!(
+        source_error_logs[0].error_code,
+        NixError::OracleFailed as u64
+    );
+    assert_ne!(source_error_logs[0].source_error_code, 0);
+    assert_ne!(
+        source_error_logs[0].source_error_code,
+        NixError::OracleFailed as u64
+    );
+    Ok(())
+}
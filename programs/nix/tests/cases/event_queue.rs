@@ -0,0 +1,304 @@
+//! The market event queue, which records fills, loans and cancels until the
+//! admin consumes them.
+
+use std::rc::Rc;
+
+use borsh::BorshSerialize;
+use nix::{
+    program::{
+        cancel_order::CancelOrderParams, consume_events::ConsumeEventsParams,
+        create_event_queue::CreateEventQueueParams, get_dynamic_account,
+        place_order::PlaceOrderParams, NixError, NixInstruction,
+    },
+    quantities::{BaseAtoms, Rate},
+    state::{
+        EventQueueFixed, EventQueueRef, MarketEvent, MarketEventType, MarketFixed, MarketRef,
+        OrderType, EVENT_QUEUE_FIXED_SIZE,
+    },
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    system_instruction, system_program,
+};
+use solana_program_test::BanksClientError;
+use solana_sdk::{account::Account, pubkey::Pubkey, signature::Keypair, signer::Signer};
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{
+    assert_nix_error, default_market_params, get_account, place_order, send_nix_instruction,
+    send_tx_with_retry, NixTestFixture, TradingMarket,
+};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+/// A match records a fill and a loan, so this holds one match.
+const EVENT_QUEUE_CAPACITY: u32 = 2;
+
+/// The traders of a market with an event queue.
+struct Traders {
+    fixture: NixTestFixture,
+    market: TradingMarket,
+    event_queue: Pubkey,
+    lender: Keypair,
+    borrower: Keypair,
+}
+
+async fn new_traders() -> Traders {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = fixture
+        .create_market_with_params(default_market_params())
+        .await
+        .unwrap();
+    let event_queue: Pubkey = create_event_queue(&fixture, &market).await.unwrap();
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await
+        .unwrap();
+    Traders {
+        fixture,
+        market,
+        event_queue,
+        lender,
+        borrower,
+    }
+}
+
+/// Allocates a queue and attaches it with CreateEventQueue, signed by the
+/// payer, who is the market admin.
+async fn create_event_queue(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+) -> Result<Pubkey, BanksClientError> {
+    let admin: Keypair = fixture.payer_keypair();
+    let event_queue: Keypair = Keypair::new();
+    let create_account_ix: Instruction = system_instruction::create_account(
+        &admin.pubkey(),
+        &event_queue.pubkey(),
+        fixture
+            .get_minimum_rent_for_size(EVENT_QUEUE_FIXED_SIZE)
+            .await,
+        EVENT_QUEUE_FIXED_SIZE as u64,
+        &nix::ID,
+    );
+    let create_event_queue_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts: vec![
+            AccountMeta::new(admin.pubkey(), true),
+            AccountMeta::new(market.key, false),
+            AccountMeta::new(event_queue.pubkey(), false),
+        ],
+        data: [
+            NixInstruction::CreateEventQueue.to_vec(),
+            CreateEventQueueParams::new(EVENT_QUEUE_CAPACITY).try_to_vec()?,
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[create_account_ix, create_event_queue_ix],
+        Some(&admin.pubkey()),
+        &[&admin, &event_queue],
+    )
+    .await?;
+    Ok(event_queue.pubkey())
+}
+
+async fn consume_events(traders: &Traders, up_to_event_id: u64) -> Result<(), BanksClientError> {
+    send_nix_instruction(
+        &traders.fixture,
+        &traders.fixture.payer_keypair(),
+        NixInstruction::ConsumeEvents,
+        vec![
+            AccountMeta::new_readonly(traders.fixture.payer(), true),
+            AccountMeta::new_readonly(traders.market.key, false),
+            AccountMeta::new(traders.event_queue, false),
+        ],
+        &ConsumeEventsParams::new(up_to_event_id),
+    )
+    .await
+}
+
+/// Places an order with the event queue appended when `with_event_queue`.
+async fn place_order_on_queue(
+    traders: &Traders,
+    trader: &Keypair,
+    is_bid: bool,
+    with_event_queue: bool,
+) -> Result<(), BanksClientError> {
+    let Traders {
+        fixture, market, ..
+    } = traders;
+    let (mut optional_accounts, order_type) = if is_bid {
+        (
+            market.bid_metas(fixture).await,
+            OrderType::ImmediateOrCancel,
+        )
+    } else {
+        (market.ask_metas(fixture).await, OrderType::PostOnly)
+    };
+    if with_event_queue {
+        optional_accounts.push(AccountMeta::new(traders.event_queue, false));
+    }
+    place_order(
+        fixture,
+        market,
+        trader,
+        PlaceOrderParams::new(
+            BaseAtoms::new(ORDER_BASE_ATOMS),
+            Rate::from_bps(RATE_BPS),
+            is_bid,
+            true,
+            order_type,
+        ),
+        optional_accounts,
+    )
+    .await
+}
+
+/// Unconsumed events, oldest first.
+async fn get_events(traders: &Traders) -> Vec<MarketEvent> {
+    let account: Account = get_account(&traders.fixture, &traders.event_queue).await;
+    let event_queue: EventQueueRef = get_dynamic_account::<EventQueueFixed>(&account.data);
+    (0..event_queue.fixed.get_count())
+        .map(|offset: u32| *event_queue.get_event(offset).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn create_event_queue_attaches_it_to_the_market() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+
+    let market_account: Account = get_account(&traders.fixture, &traders.market.key).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&market_account.data);
+    assert!(market.fixed.has_event_queue());
+    assert_eq!(*market.fixed.get_event_queue(), traders.event_queue);
+
+    let account: Account = get_account(&traders.fixture, &traders.event_queue).await;
+    let event_queue: EventQueueRef = get_dynamic_account::<EventQueueFixed>(&account.data);
+    assert_eq!(event_queue.fixed.market, traders.market.key);
+    assert_eq!(event_queue.get_capacity(), EVENT_QUEUE_CAPACITY);
+    assert_eq!(event_queue.fixed.get_next_event_id(), 1);
+    assert_eq!(event_queue.fixed.get_count(), 0);
+
+    // A second queue would strand the events on the first.
+    assert_nix_error(
+        create_event_queue(&traders.fixture, &traders.market)
+            .await
+            .map(|_| ()),
+        NixError::IncorrectAccount,
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn place_order_records_the_fill_and_the_loan() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+
+    // Resting an order is not an event.
+    place_order_on_queue(&traders, &traders.lender, false, true).await?;
+    assert!(get_events(&traders).await.is_empty());
+    place_order_on_queue(&traders, &traders.borrower, true, true).await?;
+
+    let events: Vec<MarketEvent> = get_events(&traders).await;
+    assert_eq!(events.len(), 2);
+    for (event, (event_id, event_type)) in events
+        .iter()
+        .zip([(1, MarketEventType::Fill), (2, MarketEventType::Loan)])
+    {
+        assert_eq!(event.event_id, event_id);
+        assert_eq!(event.event_type, event_type);
+        assert_eq!(event.trader, traders.lender.pubkey());
+        assert_eq!(event.counterparty, traders.borrower.pubkey());
+        assert_eq!(event.rate_bps, RATE_BPS);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn place_order_without_the_event_queue_fails() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+
+    place_order_on_queue(&traders, &traders.lender, false, false).await?;
+    assert_nix_error(
+        place_order_on_queue(&traders, &traders.borrower, true, false).await,
+        NixError::MissingEventQueue,
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn cancel_order_records_the_cancel() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    place_order_on_queue(&traders, &traders.lender, false, true).await?;
+    let market_account: Account = get_account(&traders.fixture, &traders.market.key).await;
+    let order_sequence_number: u64 = get_dynamic_account::<MarketFixed>(&market_account.data)
+        .fixed
+        .get_base_a_order_sequence_number();
+
+    send_nix_instruction(
+        &traders.fixture,
+        &traders.lender,
+        NixInstruction::CancelOrder,
+        vec![
+            AccountMeta::new(traders.lender.pubkey(), true),
+            AccountMeta::new(traders.market.market_loans, false),
+            AccountMeta::new(traders.market.key, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(traders.event_queue, false),
+        ],
+        &CancelOrderParams {
+            trader_index_hint: None,
+            order_sequence_number,
+            order_index_hint: None,
+            use_a_tree: true,
+            max_expired_orders_to_sweep: 0,
+        },
+    )
+    .await?;
+
+    let events: Vec<MarketEvent> = get_events(&traders).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].event_type, MarketEventType::Cancel);
+    assert_eq!(events[0].trader, traders.lender.pubkey());
+    assert_eq!(events[0].order_sequence_number, order_sequence_number);
+    Ok(())
+}
+
+#[tokio::test]
+async fn full_event_queue_blocks_matches_until_consumed() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    place_order_on_queue(&traders, &traders.lender, false, true).await?;
+    place_order_on_queue(&traders, &traders.borrower, true, true).await?;
+
+    place_order_on_queue(&traders, &traders.lender, false, true).await?;
+    assert_nix_error(
+        place_order_on_queue(&traders, &traders.borrower, true, true).await,
+        NixError::EventQueueFull,
+    );
+
+    // Consuming the fill leaves the loan, with its id.
+    consume_events(&traders, 1).await?;
+    let events: Vec<MarketEvent> = get_events(&traders).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].event_id, 2);
+
+    consume_events(&traders, 2).await?;
+    place_order_on_queue(&traders, &traders.borrower, true, true).await?;
+    let events: Vec<MarketEvent> = get_events(&traders).await;
+    assert_eq!(
+        events
+            .iter()
+            .map(|event: &MarketEvent| event.event_id)
+            .collect::<Vec<u64>>(),
+        vec![3, 4]
+    );
+    Ok(())
+}
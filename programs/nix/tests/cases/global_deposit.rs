@@ -0,0 +1,156 @@
+use std::rc::Rc;
+
+use borsh::BorshSerialize;
+use nix::{
+    program::{global_deposit::GlobalDepositParams, NixInstruction},
+    state::GlobalValue,
+    validation::get_global_vault_address,
+};
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program_test::BanksClientError;
+use solana_sdk::{pubkey::Pubkey, signer::Signer};
+use spl_transfer_hook_interface::get_extra_account_metas_address;
+use test_case::test_case;
+use test_utilities::{
+    spl::SupportedExtension,
+    test::{BankMint, TestSettings},
+    transfer_hook::TEST_HOOK_ID,
+};
+
+use crate::test_utils::{send_tx_with_retry, NixTestFixture};
+
+const DEPOSIT_ATOMS: u64 = 1_000_000;
+
+async fn get_token_balance(fixture: &NixTestFixture, token_account: &Pubkey) -> u64 {
+    let data: Vec<u8> = fixture
+        .context
+        .borrow_mut()
+        .banks_client
+        .get_account(*token_account)
+        .await
+        .unwrap()
+        .unwrap()
+        .data;
+    // Amount sits at the same offset for token and token22 accounts.
+    u64::from_le_bytes(data[64..72].try_into().unwrap())
+}
+
+/// GlobalDeposit of DEPOSIT_ATOMS from the payer into the base A global, with
+/// `extra_accounts` appended.
+fn global_deposit_ix(
+    fixture: &NixTestFixture,
+    extra_accounts: Vec<AccountMeta>,
+) -> anyhow::Result<Instruction> {
+    let mint_key: Pubkey = fixture.base_a_mint_fixture.key;
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(fixture.payer(), true),
+        AccountMeta::new(fixture.base_a_global_fixture.key, false),
+        AccountMeta::new_readonly(mint_key, false),
+        AccountMeta::new(get_global_vault_address(&mint_key).0, false),
+        AccountMeta::new(fixture.payer_base_a_fixture.key, false),
+        AccountMeta::new_readonly(fixture.base_a_token_program, false),
+    ];
+    accounts.extend(extra_accounts);
+    Ok(Instruction {
+        program_id: nix::ID,
+        accounts,
+        data: [
+            NixInstruction::GlobalDeposit.to_vec(),
+            GlobalDepositParams::new(DEPOSIT_ATOMS).try_to_vec()?,
+        ]
+        .concat(),
+    })
+}
+
+#[test_case(&BankMint::T22WithFee, true)]
+#[test_case(&BankMint::Usdc, false)]
+#[tokio::test]
+async fn global_deposit_credits_received_amount(
+    base_a_mint: &BankMint,
+    has_transfer_fee: bool,
+) -> anyhow::Result<()> {
+    let mut fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        base_a_mint,
+        &BankMint::SolSwbPull,
+    )
+    .await;
+    let global_key: Pubkey = fixture.base_a_global_fixture.key;
+    let mint_key: Pubkey = fixture.base_a_mint_fixture.key;
+    let trader_token: Pubkey = fixture.payer_base_a_fixture.key;
+    let (global_vault, _) = get_global_vault_address(&mint_key);
+
+    fixture.global_add_trader(&global_key).await?;
+    fixture.base_a_mint_fixture.mint_to(&trader_token, 10).await;
+
+    let before_vault_atoms: u64 = get_token_balance(&fixture, &global_vault).await;
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[global_deposit_ix(&fixture, vec![])?],
+        Some(&fixture.payer()),
+        &[&fixture.payer_keypair()],
+    )
+    .await?;
+    let received_atoms: u64 = get_token_balance(&fixture, &global_vault).await - before_vault_atoms;
+
+    fixture.base_a_global_fixture.reload().await;
+    let global: &GlobalValue = &fixture.base_a_global_fixture.global;
+    let credited_atoms: u64 = global.get_balance_shares(&fixture.payer()).into();
+    assert_eq!(credited_atoms, received_atoms);
+    assert_eq!(received_atoms < DEPOSIT_ATOMS, has_transfer_fee);
+    Ok(())
+}
+
+#[tokio::test]
+async fn global_deposit_runs_the_transfer_hook() -> anyhow::Result<()> {
+    let mut fixture: NixTestFixture = NixTestFixture::new_with_t22_extension(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &[SupportedExtension::TransferHook],
+        &BankMint::UsdcT22,
+        &BankMint::SolSwbPull,
+    )
+    .await;
+    let global_key: Pubkey = fixture.base_a_global_fixture.key;
+    let mint_key: Pubkey = fixture.base_a_mint_fixture.key;
+    let trader_token: Pubkey = fixture.payer_base_a_fixture.key;
+    let (global_vault, _) = get_global_vault_address(&mint_key);
+
+    fixture.global_add_trader(&global_key).await?;
+    fixture.base_a_mint_fixture.mint_to(&trader_token, 10).await;
+
+    // Token22 refuses the transfer without the accounts of the hook.
+    let result: Result<(), BanksClientError> = send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[global_deposit_ix(&fixture, vec![])?],
+        Some(&fixture.payer()),
+        &[&fixture.payer_keypair()],
+    )
+    .await;
+    assert!(result.is_err());
+
+    let before_vault_atoms: u64 = get_token_balance(&fixture, &global_vault).await;
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[global_deposit_ix(
+            &fixture,
+            vec![
+                AccountMeta::new_readonly(TEST_HOOK_ID, false),
+                AccountMeta::new_readonly(
+                    get_extra_account_metas_address(&mint_key, &TEST_HOOK_ID),
+                    false,
+                ),
+            ],
+        )?],
+        Some(&fixture.payer()),
+        &[&fixture.payer_keypair()],
+    )
+    .await?;
+    let received_atoms: u64 = get_token_balance(&fixture, &global_vault).await - before_vault_atoms;
+    assert_eq!(received_atoms, DEPOSIT_ATOMS);
+
+    fixture.base_a_global_fixture.reload().await;
+    let global: &GlobalValue = &fixture.base_a_global_fixture.global;
+    let credited_atoms: u64 = global.get_balance_shares(&fixture.payer()).into();
+    assert_eq!(credited_atoms, DEPOSIT_ATOMS);
+    Ok(())
+}
@@ -0,0 +1,250 @@
+use std::rc::Rc;
+
+use borsh::BorshSerialize;
+use nix::{
+    program::{
+        global_deposit::GlobalDepositParams, global_evict::GlobalEvictParams, NixError,
+        NixInstruction,
+    },
+    state::{GlobalValue, MAX_GLOBAL_SEATS},
+    validation::get_global_vault_address,
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction, InstructionError},
+    system_instruction,
+};
+use solana_program_test::BanksClientError;
+use solana_sdk::{
+    pubkey::Pubkey, signature::Keypair, signer::Signer, transaction::TransactionError,
+};
+use test_utilities::{
+    spl::TokenAccountFixture,
+    test::{BankMint, TestSettings},
+};
+
+use crate::test_utils::{send_tx_with_retry, NixTestFixture};
+
+const DEPOSIT_ATOMS: u64 = 1_000_000;
+
+/// Holder of a seat on the base A global and their token account.
+struct GlobalTrader {
+    keypair: Keypair,
+    token: Pubkey,
+}
+
+async fn new_fixture() -> NixTestFixture {
+    NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Usdc,
+        &BankMint::SolSwbPull,
+    )
+    .await
+}
+
+async fn get_token_balance(fixture: &NixTestFixture, token_account: &Pubkey) -> u64 {
+    let data: Vec<u8> = fixture.try_load(token_account).await.unwrap().unwrap().data;
+    // Amount sits at the same offset for token and token22 accounts.
+    u64::from_le_bytes(data[64..72].try_into().unwrap())
+}
+
+async fn get_lamports(fixture: &NixTestFixture, key: &Pubkey) -> u64 {
+    fixture.try_load(key).await.unwrap().unwrap().lamports
+}
+
+fn assert_nix_error(result: Result<(), BanksClientError>, expected: NixError) {
+    match result {
+        Err(BanksClientError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        ))) => assert_eq!(code, expected as u32),
+        other => panic!("Expected {:?}, got {:?}", expected, other),
+    }
+}
+
+/// Funds a new keypair with lamports and base A tokens.
+async fn new_funded_trader(fixture: &NixTestFixture) -> GlobalTrader {
+    let keypair: Keypair = Keypair::new();
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[system_instruction::transfer(
+            &fixture.payer(),
+            &keypair.pubkey(),
+            1_000_000_000,
+        )],
+        Some(&fixture.payer()),
+        &[&fixture.payer_keypair()],
+    )
+    .await
+    .unwrap();
+    let token: Pubkey = TokenAccountFixture::new_with_keypair(
+        Rc::clone(&fixture.context),
+        &fixture.base_a_mint_fixture.key,
+        &keypair.pubkey(),
+        &Keypair::new(),
+        &fixture.base_a_token_program,
+    )
+    .await
+    .key;
+    fixture.base_a_mint_fixture.mint_to(&token, 10).await;
+    GlobalTrader { keypair, token }
+}
+
+async fn global_deposit(
+    fixture: &NixTestFixture,
+    trader: &GlobalTrader,
+    amount: u64,
+) -> Result<(), BanksClientError> {
+    let mint_key: Pubkey = fixture.base_a_mint_fixture.key;
+    let global_deposit_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts: vec![
+            AccountMeta::new(trader.keypair.pubkey(), true),
+            AccountMeta::new(fixture.base_a_global_fixture.key, false),
+            AccountMeta::new_readonly(mint_key, false),
+            AccountMeta::new(get_global_vault_address(&mint_key).0, false),
+            AccountMeta::new(trader.token, false),
+            AccountMeta::new_readonly(fixture.base_a_token_program, false),
+        ],
+        data: [
+            NixInstruction::GlobalDeposit.to_vec(),
+            GlobalDepositParams::new(amount).try_to_vec()?,
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[global_deposit_ix],
+        Some(&trader.keypair.pubkey()),
+        &[&trader.keypair],
+    )
+    .await
+}
+
+async fn global_evict(
+    fixture: &NixTestFixture,
+    evictor: &GlobalTrader,
+    evictee_token: &Pubkey,
+    amount: u64,
+) -> Result<(), BanksClientError> {
+    let mint_key: Pubkey = fixture.base_a_mint_fixture.key;
+    let global_evict_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts: vec![
+            AccountMeta::new(evictor.keypair.pubkey(), true),
+            AccountMeta::new(fixture.base_a_global_fixture.key, false),
+            AccountMeta::new_readonly(mint_key, false),
+            AccountMeta::new(get_global_vault_address(&mint_key).0, false),
+            AccountMeta::new(evictor.token, false),
+            AccountMeta::new(*evictee_token, false),
+            AccountMeta::new_readonly(fixture.base_a_token_program, false),
+        ],
+        data: [
+            NixInstruction::GlobalEvict.to_vec(),
+            GlobalEvictParams::new(amount).try_to_vec()?,
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[global_evict_ix],
+        Some(&evictor.keypair.pubkey()),
+        &[&evictor.keypair],
+    )
+    .await
+}
+
+/// Takes `num_seats` seats on the base A global. The trader at index i
+/// deposits (i + 1) * DEPOSIT_ATOMS, so the first has the smallest deposit.
+async fn fill_global_seats(fixture: &NixTestFixture, num_seats: u16) -> Vec<GlobalTrader> {
+    let mut traders: Vec<GlobalTrader> = Vec::new();
+    for seat in 0..num_seats {
+        let trader: GlobalTrader = new_funded_trader(fixture).await;
+        fixture
+            .global_add_trader_for_keypair(&trader.keypair, &fixture.base_a_global_fixture.key)
+            .await
+            .unwrap();
+        global_deposit(fixture, &trader, (seat as u64 + 1) * DEPOSIT_ATOMS)
+            .await
+            .unwrap();
+        traders.push(trader);
+    }
+    traders
+}
+
+#[tokio::test]
+async fn global_evict_refunds_the_smallest_depositor() -> anyhow::Result<()> {
+    let mut fixture: NixTestFixture = new_fixture().await;
+    let traders: Vec<GlobalTrader> = fill_global_seats(&fixture, MAX_GLOBAL_SEATS).await;
+    let evictee: &GlobalTrader = &traders[0];
+    let evictor: GlobalTrader = new_funded_trader(&fixture).await;
+    let global_vault: Pubkey = get_global_vault_address(&fixture.base_a_mint_fixture.key).0;
+
+    let before_evictee_atoms: u64 = get_token_balance(&fixture, &evictee.token).await;
+    let before_vault_atoms: u64 = get_token_balance(&fixture, &global_vault).await;
+    let before_global_lamports: u64 =
+        get_lamports(&fixture, &fixture.base_a_global_fixture.key).await;
+
+    global_evict(&fixture, &evictor, &evictee.token, 2 * DEPOSIT_ATOMS).await?;
+
+    // The evictee gets their whole deposit back and the evictor's deposit
+    // replaces it in the vault.
+    assert_eq!(
+        get_token_balance(&fixture, &evictee.token).await,
+        before_evictee_atoms + DEPOSIT_ATOMS
+    );
+    assert_eq!(
+        get_token_balance(&fixture, &global_vault).await,
+        before_vault_atoms + DEPOSIT_ATOMS
+    );
+    // Gas prepayments stay on the global for whoever cleans up the orders of
+    // the evictee.
+    assert_eq!(
+        get_lamports(&fixture, &fixture.base_a_global_fixture.key).await,
+        before_global_lamports
+    );
+
+    fixture.base_a_global_fixture.reload().await;
+    let global: &GlobalValue = &fixture.base_a_global_fixture.global;
+    let evictor_atoms: u64 = global.get_balance_shares(&evictor.keypair.pubkey()).into();
+    assert_eq!(evictor_atoms, 2 * DEPOSIT_ATOMS);
+    assert_eq!(global.get_num_gas_prepayments(&evictor.keypair.pubkey()), 0);
+    let evictee_atoms: u64 = global.get_balance_shares(&evictee.keypair.pubkey()).into();
+    assert_eq!(evictee_atoms, 0);
+
+    // The seat is gone, so the evictee can no longer deposit.
+    assert!(global_deposit(&fixture, evictee, DEPOSIT_ATOMS)
+        .await
+        .is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn global_evict_requires_a_full_global() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let traders: Vec<GlobalTrader> = fill_global_seats(&fixture, MAX_GLOBAL_SEATS - 1).await;
+    let evictor: GlobalTrader = new_funded_trader(&fixture).await;
+
+    assert_nix_error(
+        global_evict(&fixture, &evictor, &traders[0].token, 2 * DEPOSIT_ATOMS).await,
+        NixError::GlobalNotFull,
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn global_evict_requires_more_than_the_smallest_deposit() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let traders: Vec<GlobalTrader> = fill_global_seats(&fixture, MAX_GLOBAL_SEATS).await;
+    let evictor: GlobalTrader = new_funded_trader(&fixture).await;
+
+    assert_nix_error(
+        global_evict(&fixture, &evictor, &traders[0].token, DEPOSIT_ATOMS).await,
+        NixError::GlobalInsufficient,
+    );
+    // Only the token account of the smallest depositor can take the refund.
+    assert_nix_error(
+        global_evict(&fixture, &evictor, &traders[1].token, 2 * DEPOSIT_ATOMS).await,
+        NixError::IncorrectAccount,
+    );
+    Ok(())
+}
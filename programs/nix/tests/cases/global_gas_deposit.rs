@@ -0,0 +1,214 @@
+//! The gas deposit a global stores at GlobalCreate, and MigrateGlobal, which
+//! moves globals from before it was stored to the current layout.
+
+use std::rc::Rc;
+
+use borsh::BorshSerialize;
+use nix::{
+    program::{
+        global_create::GlobalCreateParams, global_deposit::GlobalDepositParams, NixError,
+        NixInstruction,
+    },
+    state::{
+        GlobalFixed, GAS_DEPOSIT_LAMPORTS, GLOBAL_FIXED_SIZE, MAX_GAS_DEPOSIT_LAMPORTS,
+        MIN_GAS_DEPOSIT_LAMPORTS,
+    },
+    validation::{get_global_address, get_global_vault_address},
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    system_program,
+};
+use solana_program_test::BanksClientError;
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    pubkey::Pubkey,
+};
+use test_case::test_case;
+use test_utilities::{
+    spl::MintFixture,
+    test::{BankMint, TestSettings},
+};
+
+use crate::test_utils::{assert_nix_error, get_account, send_tx_with_retry, NixTestFixture};
+
+/// Header bytes of a global from before the gas deposit and the marginfi
+/// fields after it were stored.
+const OLD_GLOBAL_FIXED_SIZE: usize = 96;
+
+async fn new_fixture() -> NixTestFixture {
+    NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Usdc,
+        &BankMint::SolSwbPull,
+    )
+    .await
+}
+
+async fn send_payer_instruction(
+    fixture: &NixTestFixture,
+    accounts: Vec<AccountMeta>,
+    data: Vec<u8>,
+) -> Result<(), BanksClientError> {
+    let instruction: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts,
+        data,
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[instruction],
+        Some(&fixture.payer()),
+        &[&fixture.payer_keypair()],
+    )
+    .await
+}
+
+/// GlobalCreate for a new mint with `params` as the data, sent as is.
+async fn global_create(
+    fixture: &NixTestFixture,
+    params: Vec<u8>,
+) -> Result<Pubkey, BanksClientError> {
+    let mint: MintFixture = MintFixture::new(Rc::clone(&fixture.context), None, Some(6)).await;
+    let global: Pubkey = get_global_address(&mint.key).0;
+    send_payer_instruction(
+        fixture,
+        vec![
+            AccountMeta::new(fixture.payer(), true),
+            AccountMeta::new(global, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(mint.key, false),
+            AccountMeta::new(get_global_vault_address(&mint.key).0, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        [NixInstruction::GlobalCreate.to_vec(), params].concat(),
+    )
+    .await?;
+    Ok(global)
+}
+
+fn get_global_fixed(data: &[u8]) -> GlobalFixed {
+    bytemuck::pod_read_unaligned(&data[..GLOBAL_FIXED_SIZE])
+}
+
+async fn global_deposit(fixture: &NixTestFixture, amount: u64) -> Result<(), BanksClientError> {
+    let mint: Pubkey = fixture.base_a_mint_fixture.key;
+    send_payer_instruction(
+        fixture,
+        vec![
+            AccountMeta::new(fixture.payer(), true),
+            AccountMeta::new(fixture.base_a_global_fixture.key, false),
+            AccountMeta::new_readonly(mint, false),
+            AccountMeta::new(get_global_vault_address(&mint).0, false),
+            AccountMeta::new(fixture.payer_base_a_fixture.key, false),
+            AccountMeta::new_readonly(fixture.base_a_token_program, false),
+        ],
+        [
+            NixInstruction::GlobalDeposit.to_vec(),
+            GlobalDepositParams::new(amount).try_to_vec().unwrap(),
+        ]
+        .concat(),
+    )
+    .await
+}
+
+async fn migrate_global(fixture: &NixTestFixture, global: &Pubkey) -> Result<(), BanksClientError> {
+    send_payer_instruction(
+        fixture,
+        vec![
+            AccountMeta::new(fixture.payer(), true),
+            AccountMeta::new(*global, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        NixInstruction::MigrateGlobal.to_vec(),
+    )
+    .await
+}
+
+#[test_case(Vec::new(), GAS_DEPOSIT_LAMPORTS ; "default")]
+#[test_case(GlobalCreateParams { gas_deposit_lamports: 20_000 }.try_to_vec().unwrap(), 20_000 ; "configured")]
+#[tokio::test]
+async fn global_create_stores_the_gas_deposit(
+    params: Vec<u8>,
+    expected_gas_deposit_lamports: u64,
+) -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let global: Pubkey = global_create(&fixture, params).await?;
+
+    let account: Account = get_account(&fixture, &global).await;
+    assert_eq!(
+        get_global_fixed(&account.data).get_gas_deposit_lamports(),
+        expected_gas_deposit_lamports
+    );
+    Ok(())
+}
+
+#[test_case(MIN_GAS_DEPOSIT_LAMPORTS - 1 ; "below min")]
+#[test_case(MAX_GAS_DEPOSIT_LAMPORTS + 1 ; "above max")]
+#[tokio::test]
+async fn global_create_bounds_the_gas_deposit(gas_deposit_lamports: u64) -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let params: Vec<u8> = GlobalCreateParams {
+        gas_deposit_lamports,
+    }
+    .try_to_vec()?;
+
+    assert_nix_error(
+        global_create(&fixture, params).await.map(|_| ()),
+        NixError::InvalidGasDeposit,
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn migrate_global_moves_an_old_global() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let global: Pubkey = fixture.base_a_global_fixture.key;
+    fixture.global_add_trader(&global).await?;
+    fixture
+        .base_a_mint_fixture
+        .mint_to(&fixture.payer_base_a_fixture.key, 10)
+        .await;
+    global_deposit(&fixture, 1_000).await?;
+
+    // Drop the header fields an old global does not have.
+    let mut account: Account = get_account(&fixture, &global).await;
+    let current_data: Vec<u8> = account.data.clone();
+    account.data = [
+        &current_data[..OLD_GLOBAL_FIXED_SIZE],
+        &current_data[GLOBAL_FIXED_SIZE..],
+    ]
+    .concat();
+    fixture
+        .context
+        .borrow_mut()
+        .set_account(&global, &AccountSharedData::from(account));
+
+    assert_nix_error(
+        global_deposit(&fixture, 1_000).await,
+        NixError::GlobalLayoutMismatch,
+    );
+
+    migrate_global(&fixture, &global).await?;
+    let migrated_data: Vec<u8> = get_account(&fixture, &global).await.data;
+    assert_eq!(migrated_data.len(), current_data.len());
+    assert_eq!(
+        migrated_data[..OLD_GLOBAL_FIXED_SIZE],
+        current_data[..OLD_GLOBAL_FIXED_SIZE]
+    );
+    assert_eq!(
+        migrated_data[GLOBAL_FIXED_SIZE..],
+        current_data[GLOBAL_FIXED_SIZE..]
+    );
+    assert_eq!(
+        get_global_fixed(&migrated_data).get_gas_deposit_lamports(),
+        GAS_DEPOSIT_LAMPORTS
+    );
+    global_deposit(&fixture, 1_000).await?;
+
+    assert_nix_error(
+        migrate_global(&fixture, &global).await,
+        NixError::GlobalLayoutMismatch,
+    );
+    Ok(())
+}
@@ -0,0 +1,227 @@
+//! GlobalLinkMarginfi, which lends the deposits of a global to the marginfi
+//! bank of its mint, and the deposits into a global once it is linked.
+
+use fixed::types::I80F48;
+use marginfi::state::marginfi_group::BankVaultType;
+use nix::{
+    logs::GlobalLinkMarginfiLog,
+    program::{global_deposit::GlobalDepositParams, NixError, NixInstruction},
+    state::{GlobalFixed, GLOBAL_FIXED_SIZE},
+    validation::{get_global_vault_address, get_nix_marginfi_account_address},
+};
+use solana_program::{instruction::AccountMeta, system_program};
+use solana_program_test::BanksClientError;
+use solana_sdk::pubkey::Pubkey;
+use test_utilities::{
+    bank::BankFixture,
+    test::{BankMint, TestSettings},
+};
+
+use crate::test_utils::{
+    assert_nix_error, get_account, get_logs, send_nix_instruction, simulate_nix_log_data,
+    NixTestFixture,
+};
+
+const DEPOSIT_ATOMS: u64 = 1_000_000;
+
+/// The fixture with the payer holding DEPOSIT_ATOMS on the base A global,
+/// which is not linked yet.
+async fn new_funded_global() -> anyhow::Result<NixTestFixture> {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Usdc,
+        &BankMint::SolSwbPull,
+    )
+    .await;
+    fixture
+        .global_add_trader(&fixture.base_a_global_fixture.key)
+        .await?;
+    fixture
+        .base_a_mint_fixture
+        .mint_to(&fixture.payer_base_a_fixture.key, 10)
+        .await;
+    global_deposit(&fixture, Vec::new()).await?;
+    Ok(fixture)
+}
+
+fn global_marginfi_account(fixture: &NixTestFixture) -> Pubkey {
+    get_nix_marginfi_account_address(
+        &fixture.base_a_global_fixture.key,
+        &fixture.base_a_mint_fixture.key,
+    )
+    .0
+}
+
+/// GlobalLinkMarginfi accounts of the base A global with `bank`.
+fn link_metas(fixture: &NixTestFixture, bank: &BankFixture) -> Vec<AccountMeta> {
+    let mint: Pubkey = fixture.base_a_mint_fixture.key;
+    vec![
+        AccountMeta::new(fixture.payer(), true),
+        AccountMeta::new(fixture.base_a_global_fixture.key, false),
+        AccountMeta::new_readonly(mint, false),
+        AccountMeta::new(get_global_vault_address(&mint).0, false),
+        AccountMeta::new_readonly(fixture.market, false),
+        AccountMeta::new(fixture.group.key, false),
+        AccountMeta::new(bank.key, false),
+        AccountMeta::new(global_marginfi_account(fixture), false),
+        AccountMeta::new(bank.get_vault(BankVaultType::Liquidity).0, false),
+        AccountMeta::new_readonly(fixture.base_a_token_program, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ]
+}
+
+async fn global_link_marginfi(
+    fixture: &NixTestFixture,
+    bank: &BankFixture,
+) -> Result<(), BanksClientError> {
+    send_nix_instruction(
+        fixture,
+        &fixture.payer_keypair(),
+        NixInstruction::GlobalLinkMarginfi,
+        link_metas(fixture, bank),
+        &(),
+    )
+    .await
+}
+
+/// Group, bank, marginfi account of the global, liquidity vault and its
+/// authority, which a linked global lends deposits through.
+fn global_marginfi_metas(fixture: &NixTestFixture) -> Vec<AccountMeta> {
+    let bank: &BankFixture = &fixture.base_a_bank_fixture;
+    vec![
+        AccountMeta::new(fixture.group.key, false),
+        AccountMeta::new(bank.key, false),
+        AccountMeta::new(global_marginfi_account(fixture), false),
+        AccountMeta::new(bank.get_vault(BankVaultType::Liquidity).0, false),
+        AccountMeta::new_readonly(bank.get_vault_authority(BankVaultType::Liquidity).0, false),
+    ]
+}
+
+/// GlobalDeposit of DEPOSIT_ATOMS from the payer, with `extra_accounts`
+/// appended.
+async fn global_deposit(
+    fixture: &NixTestFixture,
+    extra_accounts: Vec<AccountMeta>,
+) -> Result<(), BanksClientError> {
+    let mint: Pubkey = fixture.base_a_mint_fixture.key;
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(fixture.payer(), true),
+        AccountMeta::new(fixture.base_a_global_fixture.key, false),
+        AccountMeta::new_readonly(mint, false),
+        AccountMeta::new(get_global_vault_address(&mint).0, false),
+        AccountMeta::new(fixture.payer_base_a_fixture.key, false),
+        AccountMeta::new_readonly(fixture.base_a_token_program, false),
+    ];
+    accounts.extend(extra_accounts);
+    send_nix_instruction(
+        fixture,
+        &fixture.payer_keypair(),
+        NixInstruction::GlobalDeposit,
+        accounts,
+        &GlobalDepositParams::new(DEPOSIT_ATOMS),
+    )
+    .await
+}
+
+async fn get_global_fixed(fixture: &NixTestFixture) -> GlobalFixed {
+    let data: Vec<u8> = get_account(fixture, &fixture.base_a_global_fixture.key)
+        .await
+        .data;
+    bytemuck::pod_read_unaligned(&data[..GLOBAL_FIXED_SIZE])
+}
+
+async fn get_payer_balance_shares(fixture: &mut NixTestFixture) -> I80F48 {
+    fixture.base_a_global_fixture.reload().await;
+    I80F48::from(
+        fixture
+            .base_a_global_fixture
+            .global
+            .get_balance_shares(&fixture.payer()),
+    )
+}
+
+async fn get_token_balance(fixture: &NixTestFixture, token_account: &Pubkey) -> u64 {
+    let data: Vec<u8> = get_account(fixture, token_account).await.data;
+    // Amount sits at the same offset for token and token22 accounts.
+    u64::from_le_bytes(data[64..72].try_into().unwrap())
+}
+
+#[tokio::test]
+async fn global_link_marginfi_lends_the_vault() -> anyhow::Result<()> {
+    let mut fixture: NixTestFixture = new_funded_global().await?;
+    let global_vault: Pubkey = get_global_vault_address(&fixture.base_a_mint_fixture.key).0;
+    // Before the link a share is one atom.
+    assert_eq!(
+        get_payer_balance_shares(&mut fixture).await,
+        I80F48::from_num(DEPOSIT_ATOMS)
+    );
+
+    let (result, log_data) = simulate_nix_log_data(
+        &fixture,
+        &fixture.payer_keypair(),
+        NixInstruction::GlobalLinkMarginfi,
+        link_metas(&fixture, &fixture.base_a_bank_fixture),
+        &(),
+    )
+    .await?;
+    assert_eq!(result, Ok(()));
+    let logs: Vec<GlobalLinkMarginfiLog> = get_logs(&log_data);
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].global, fixture.base_a_global_fixture.key);
+    assert_eq!(logs[0].marginfi_bank, fixture.base_a_bank_fixture.key);
+    assert_eq!(logs[0].vault_atoms, DEPOSIT_ATOMS);
+    let minted_shares: I80F48 = I80F48::from(logs[0].minted_shares);
+    assert!(minted_shares.is_positive());
+
+    global_link_marginfi(&fixture, &fixture.base_a_bank_fixture).await?;
+    let global_fixed: GlobalFixed = get_global_fixed(&fixture).await;
+    assert!(global_fixed.is_marginfi_linked());
+    assert_eq!(
+        *global_fixed.get_marginfi_bank(),
+        fixture.base_a_bank_fixture.key
+    );
+    assert_eq!(
+        *global_fixed.get_marginfi_account(),
+        global_marginfi_account(&fixture)
+    );
+    // All of the vault is lent, the only balance holds all of the shares.
+    assert_eq!(get_token_balance(&fixture, &global_vault).await, 0);
+    assert_eq!(get_payer_balance_shares(&mut fixture).await, minted_shares);
+    Ok(())
+}
+
+#[tokio::test]
+async fn global_deposit_lends_once_linked() -> anyhow::Result<()> {
+    let mut fixture: NixTestFixture = new_funded_global().await?;
+    global_link_marginfi(&fixture, &fixture.base_a_bank_fixture).await?;
+    let shares_before: I80F48 = get_payer_balance_shares(&mut fixture).await;
+
+    assert_nix_error(
+        global_deposit(&fixture, Vec::new()).await,
+        NixError::MissingAccounts,
+    );
+
+    global_deposit(&fixture, global_marginfi_metas(&fixture)).await?;
+    let global_vault: Pubkey = get_global_vault_address(&fixture.base_a_mint_fixture.key).0;
+    assert_eq!(get_token_balance(&fixture, &global_vault).await, 0);
+    assert!(get_payer_balance_shares(&mut fixture).await > shares_before);
+    Ok(())
+}
+
+#[tokio::test]
+async fn global_link_marginfi_links_once_to_the_bank_of_the_mint() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_funded_global().await?;
+
+    // The market lends base A to the base A bank only.
+    assert_nix_error(
+        global_link_marginfi(&fixture, &fixture.base_b_bank_fixture).await,
+        NixError::InvalidMarginfiBank,
+    );
+
+    global_link_marginfi(&fixture, &fixture.base_a_bank_fixture).await?;
+    assert_nix_error(
+        global_link_marginfi(&fixture, &fixture.base_a_bank_fixture).await,
+        NixError::GlobalAlreadyLinked,
+    );
+    Ok(())
+}
@@ -0,0 +1,209 @@
+//! Health check accounts of the marginfi borrows behind a bid, which nix
+//! assembles from the active balances of the market's marginfi account, and
+//! the oracle prices it reads for them.
+
+use marginfi::state::marginfi_account::{Balance, MarginfiAccount};
+use nix::{
+    program::{place_order::PlaceOrderParams, NixError},
+    quantities::{BaseAtoms, Rate},
+    state::OrderType,
+};
+use solana_program::instruction::AccountMeta;
+use solana_program_test::BanksClientError;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair};
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{
+    assert_nix_error, oracle_metas, place_order, NixTestFixture, TradingMarket,
+};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+
+async fn new_market() -> (NixTestFixture, TradingMarket, Keypair, Keypair) {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = TradingMarket {
+        key: fixture.market,
+        market_loans: fixture
+            .create_market_loan_account(&fixture.payer(), &fixture.market)
+            .await
+            .unwrap(),
+    };
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await
+        .unwrap();
+    (fixture, market, lender, borrower)
+}
+
+/// Rests an ask from the lender and takes it with a bid whose oracle
+/// accounts come in `bid_oracle_metas` order after the CPI accounts.
+async fn open_loan_with_oracles(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    lender: &Keypair,
+    borrower: &Keypair,
+    bid_oracle_metas: Vec<AccountMeta>,
+) -> Result<(), BanksClientError> {
+    place_order(
+        fixture,
+        market,
+        lender,
+        PlaceOrderParams::new(
+            BaseAtoms::new(ORDER_BASE_ATOMS),
+            Rate::from_bps(RATE_BPS),
+            false,
+            true,
+            OrderType::PostOnly,
+        ),
+        market.ask_metas(fixture).await,
+    )
+    .await?;
+    let mut optional_accounts: Vec<AccountMeta> =
+        market.marginfi_cpi_metas(fixture, &fixture.base_a_bank_fixture);
+    optional_accounts.extend(market.marginfi_cpi_metas(fixture, &fixture.base_b_bank_fixture));
+    optional_accounts.extend(bid_oracle_metas);
+    place_order(
+        fixture,
+        market,
+        borrower,
+        PlaceOrderParams::new(
+            BaseAtoms::new(ORDER_BASE_ATOMS),
+            Rate::from_bps(RATE_BPS),
+            true,
+            true,
+            OrderType::ImmediateOrCancel,
+        ),
+        optional_accounts,
+    )
+    .await
+}
+
+/// Rests a bid from the borrower, which borrows the whole of it.
+async fn rest_bid(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    borrower: &Keypair,
+) -> Result<(), BanksClientError> {
+    place_order(
+        fixture,
+        market,
+        borrower,
+        PlaceOrderParams::new(
+            BaseAtoms::new(ORDER_BASE_ATOMS),
+            Rate::from_bps(RATE_BPS),
+            true,
+            true,
+            OrderType::PostOnly,
+        ),
+        market.bid_metas(fixture).await,
+    )
+    .await
+}
+
+/// Banks of the active balances of the market's base B marginfi account,
+/// which holds the collateral and the borrows of A tree bids.
+async fn get_active_banks(fixture: &NixTestFixture, market: &TradingMarket) -> Vec<Pubkey> {
+    let marginfi_account: MarginfiAccount = fixture
+        .load_and_deserialize(&market.marginfi_account(&fixture.base_b_bank_fixture))
+        .await;
+    marginfi_account
+        .lending_account
+        .balances
+        .iter()
+        .filter(|balance: &&Balance| balance.active != 0)
+        .map(|balance: &Balance| balance.bank_pk)
+        .collect()
+}
+
+#[tokio::test]
+async fn bid_finds_health_check_accounts_by_key() -> anyhow::Result<()> {
+    let (fixture, market, lender, borrower) = new_market().await;
+    assert_eq!(
+        get_active_banks(&fixture, &market).await,
+        vec![fixture.base_b_bank_fixture.key]
+    );
+
+    // The borrowed bank is not on the account yet, and its oracle comes
+    // first here, the opposite of bid_metas.
+    let bid_oracle_metas: Vec<AccountMeta> = [
+        oracle_metas(&fixture.base_a_bank_fixture).await,
+        oracle_metas(&fixture.base_b_bank_fixture).await,
+    ]
+    .concat();
+    open_loan_with_oracles(&fixture, &market, &lender, &borrower, bid_oracle_metas).await?;
+    assert_eq!(
+        get_active_banks(&fixture, &market).await,
+        vec![
+            fixture.base_b_bank_fixture.key,
+            fixture.base_a_bank_fixture.key
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn bid_borrows_again_from_an_active_balance() -> anyhow::Result<()> {
+    let (fixture, market, lender, borrower) = new_market().await;
+
+    for _ in 0..2 {
+        let bid_oracle_metas: Vec<AccountMeta> = [
+            oracle_metas(&fixture.base_b_bank_fixture).await,
+            oracle_metas(&fixture.base_a_bank_fixture).await,
+        ]
+        .concat();
+        open_loan_with_oracles(&fixture, &market, &lender, &borrower, bid_oracle_metas).await?;
+    }
+    // The second borrow adds to the balance the first one opened.
+    assert_eq!(get_active_banks(&fixture, &market).await.len(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn resting_bid_prices_each_bank_from_its_own_oracle() -> anyhow::Result<()> {
+    let (fixture, market, _lender, borrower) = new_market().await;
+    let base_oracle: Pubkey = fixture.base_a_bank_fixture.load().await.config.oracle_keys[0];
+
+    // The quote collateral is priced by the quote oracle even after the base
+    // bank was priced in the same instruction, so it no longer covers the
+    // borrow once base is this expensive.
+    fixture
+        .set_pyth_oracle_price(base_oracle, 1_000_000.0)
+        .await;
+    assert_nix_error(
+        rest_bid(&fixture, &market, &borrower).await,
+        NixError::InsufficientCollateral,
+    );
+
+    // Prices are not kept across instructions.
+    fixture.set_pyth_oracle_price(base_oracle, 10.0).await;
+    rest_bid(&fixture, &market, &borrower).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn resting_bid_prices_a_borrowed_bank_again() -> anyhow::Result<()> {
+    let (fixture, market, _lender, borrower) = new_market().await;
+
+    // Once the base bank is borrowed from, the health check prices it a
+    // second time as a liability, with the other bias.
+    for _ in 0..2 {
+        rest_bid(&fixture, &market, &borrower).await?;
+    }
+    assert_eq!(
+        get_active_banks(&fixture, &market).await,
+        vec![
+            fixture.base_b_bank_fixture.key,
+            fixture.base_a_bank_fixture.key
+        ]
+    );
+    fixture.verify_market().await;
+    Ok(())
+}
@@ -0,0 +1,758 @@
+//! Walks one loan through the market: the lender deposits and asks, the
+//! borrower deposits collateral and bids into the ask, and the match opens
+//! the loan, which then accrues interest on marginfi. Every step checks that
+//! no atoms appear or vanish between the traders, the market vaults and the
+//! marginfi liquidity vaults.
+//!
+//! Repaying and withdrawing are not instructions of the program yet, so the
+//! lifecycle stops at the accrued loan. Extend it once they exist.
+
+use std::rc::Rc;
+
+use borsh::BorshSerialize;
+use fixed::types::I80F48;
+use marginfi::state::{
+    marginfi_account::{Balance, MarginfiAccount},
+    marginfi_group::{Bank, BankVaultType},
+};
+use nix::{
+    program::{
+        clean_expired_orders::CleanExpiredOrdersParams, deposit::DepositParams,
+        get_dynamic_account, mark_default::MarkDefaultParams, place_order::PlaceOrderParams,
+        NixError, NixInstruction,
+    },
+    quantities::{BaseAtoms, Rate},
+    state::{
+        ActiveLoan, ExpiryPolicy, LoanStatus, MarketFixed, MarketLoansFixed, MarketLoansRef,
+        MarketRef, OrderType, SeatSnapshot,
+    },
+    validation::{get_market_signer_address, get_vault_address},
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction, InstructionError},
+    system_instruction, system_program,
+};
+use solana_program_test::BanksClientError;
+use solana_sdk::{
+    account::Account, pubkey::Pubkey, signature::Keypair, signer::Signer,
+    transaction::TransactionError,
+};
+use test_utilities::{
+    bank::BankFixture,
+    test::{BankMint, TestSettings},
+};
+
+use crate::test_utils::{send_tx_with_retry, FixtureSnapshot, NixTestFixture};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 500_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+const ONE_DAY_SECONDS: i64 = 24 * 60 * 60;
+
+async fn get_account(fixture: &NixTestFixture, key: &Pubkey) -> Account {
+    fixture
+        .try_load(key)
+        .await
+        .unwrap()
+        .expect("Account not found")
+}
+
+async fn get_token_balance(fixture: &NixTestFixture, token_account: &Pubkey) -> u64 {
+    let data: Vec<u8> = get_account(fixture, token_account).await.data;
+    // Amount sits at the same offset for token and token22 accounts.
+    u64::from_le_bytes(data[64..72].try_into().unwrap())
+}
+
+/// Atoms of the bank's mint held by the trader token accounts, the market
+/// vault and the marginfi liquidity vault. Nothing else moves them in this
+/// case, so the sum only changes when the test mints.
+async fn get_tracked_atoms(
+    fixture: &NixTestFixture,
+    bank: &BankFixture,
+    trader_tokens: &[Pubkey],
+) -> u64 {
+    let mut atoms: u64 = 0;
+    for token_account in trader_tokens.iter().chain(
+        [
+            get_vault_address(&fixture.market, &bank.mint.key).0,
+            bank.get_vault(BankVaultType::Liquidity).0,
+        ]
+        .iter(),
+    ) {
+        atoms += get_token_balance(fixture, token_account).await;
+    }
+    atoms
+}
+
+/// Asset and liability shares the market's marginfi account holds in the
+/// bank.
+async fn get_marginfi_shares(
+    fixture: &NixTestFixture,
+    marginfi_account: &Pubkey,
+    bank: &BankFixture,
+) -> (I80F48, I80F48) {
+    let account: MarginfiAccount = fixture.load_and_deserialize(marginfi_account).await;
+    account
+        .lending_account
+        .balances
+        .iter()
+        .find(|balance: &&Balance| balance.active != 0 && balance.bank_pk == bank.key)
+        .map(|balance: &Balance| {
+            (
+                I80F48::from(balance.asset_shares),
+                I80F48::from(balance.liability_shares),
+            )
+        })
+        .unwrap_or((I80F48::ZERO, I80F48::ZERO))
+}
+
+async fn get_borrowed_loans(
+    fixture: &NixTestFixture,
+    market_loans: &Pubkey,
+    borrower: &Pubkey,
+) -> Vec<ActiveLoan> {
+    let market_account: Account = get_account(fixture, &fixture.market).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&market_account.data);
+    let market_loans_account: Account = get_account(fixture, market_loans).await;
+    let loans: MarketLoansRef = get_dynamic_account::<MarketLoansFixed>(&market_loans_account.data);
+    loans.get_borrowed_loans(market.get_trader_index(borrower))
+}
+
+/// Base A liability the trader's seat has borrowed, in marginfi liability
+/// shares.
+async fn get_seat_liability_shares(fixture: &NixTestFixture, trader: &Pubkey) -> I80F48 {
+    let market_account: Account = get_account(fixture, &fixture.market).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&market_account.data);
+    let seat: SeatSnapshot = market.get_seat_snapshot(trader).unwrap();
+    I80F48::from(seat.base_a_liability_shares)
+}
+
+fn marginfi_cpi_metas(
+    fixture: &NixTestFixture,
+    bank: &BankFixture,
+    marginfi_account: &Pubkey,
+) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(fixture.group.key, false),
+        AccountMeta::new(bank.key, false),
+        AccountMeta::new(*marginfi_account, false),
+        AccountMeta::new(bank.get_vault(BankVaultType::Liquidity).0, false),
+        AccountMeta::new_readonly(bank.get_vault_authority(BankVaultType::Liquidity).0, false),
+    ]
+}
+
+async fn oracle_metas(bank: &BankFixture) -> Vec<AccountMeta> {
+    let bank_account: Bank = bank.load().await;
+    vec![
+        AccountMeta::new_readonly(bank.key, false),
+        AccountMeta::new_readonly(bank_account.config.oracle_keys[0], false),
+    ]
+}
+
+fn order_params(is_bid: bool, order_type: OrderType) -> PlaceOrderParams {
+    PlaceOrderParams {
+        expiry_policy: ExpiryPolicy::ReturnCollateral,
+        ..PlaceOrderParams::new(
+            BaseAtoms::new(ORDER_BASE_ATOMS),
+            Rate::from_bps(RATE_BPS),
+            is_bid,
+            true,
+            order_type,
+        )
+    }
+}
+
+async fn place_order(
+    fixture: &NixTestFixture,
+    trader: &Keypair,
+    market_loans: &Pubkey,
+    params: PlaceOrderParams,
+    optional_accounts: Vec<AccountMeta>,
+) -> Result<(), BanksClientError> {
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(trader.pubkey(), true),
+        AccountMeta::new(fixture.market, false),
+        AccountMeta::new(*market_loans, false),
+        AccountMeta::new_readonly(get_market_signer_address(&fixture.market).0, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(fixture.base_a_mint_fixture.key, false),
+        AccountMeta::new_readonly(fixture.base_b_mint_fixture.key, false),
+    ];
+    accounts.extend(optional_accounts);
+    let place_order_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts,
+        data: [
+            NixInstruction::PlaceOrder.to_vec(),
+            params.try_to_versioned_vec()?,
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[place_order_ix],
+        Some(&trader.pubkey()),
+        &[trader],
+    )
+    .await
+}
+
+/// Deposits into the trader's seat through the market's marginfi account for
+/// the bank.
+async fn deposit_to_seat(
+    fixture: &NixTestFixture,
+    trader: &Keypair,
+    bank: &BankFixture,
+    marginfi_account: &Pubkey,
+    trader_token: &Pubkey,
+    token_program: &Pubkey,
+    amount: u64,
+) -> Result<(), BanksClientError> {
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(trader.pubkey(), true),
+        AccountMeta::new(fixture.market, false),
+        AccountMeta::new_readonly(get_market_signer_address(&fixture.market).0, false),
+        AccountMeta::new(*trader_token, false),
+        AccountMeta::new(get_vault_address(&fixture.market, &bank.mint.key).0, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(bank.mint.key, false),
+    ];
+    accounts.extend(marginfi_cpi_metas(fixture, bank, marginfi_account));
+    // Deposit takes the liquidity vault without its authority.
+    accounts.pop();
+    let deposit_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts,
+        data: [
+            NixInstruction::Deposit.to_vec(),
+            DepositParams::new(amount, None, false).try_to_vec()?,
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[deposit_ix],
+        Some(&trader.pubkey()),
+        &[trader],
+    )
+    .await
+}
+
+/// Seats the lender (the second keypair) and the borrower (the payer), with
+/// LENDER_DEPOSIT_ATOMS of base and BORROWER_COLLATERAL_ATOMS of quote in
+/// their seats.
+async fn seat_lender_and_borrower(fixture: &NixTestFixture) -> anyhow::Result<(Keypair, Keypair)> {
+    let borrower: Keypair = fixture.payer_keypair();
+    let lender: Keypair = fixture.second_keypair.insecure_clone();
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[system_instruction::transfer(
+            &borrower.pubkey(),
+            &lender.pubkey(),
+            1_000_000_000,
+        )],
+        Some(&borrower.pubkey()),
+        &[&borrower],
+    )
+    .await?;
+    fixture.claim_seat_for_keypair(&borrower).await?;
+    fixture.claim_seat_for_keypair(&lender).await?;
+    fixture
+        .base_a_mint_fixture
+        .mint_to(&fixture.second_keypair_base_a_fixture.key, 10)
+        .await;
+    fixture
+        .base_b_mint_fixture
+        .mint_to(&fixture.payer_base_b_fixture.key, 10_000)
+        .await;
+    deposit_to_seat(
+        fixture,
+        &lender,
+        &fixture.base_a_bank_fixture,
+        &fixture.base_a_marginfi_account,
+        &fixture.second_keypair_base_a_fixture.key,
+        &fixture.base_a_token_program,
+        LENDER_DEPOSIT_ATOMS,
+    )
+    .await?;
+    deposit_to_seat(
+        fixture,
+        &borrower,
+        &fixture.base_b_bank_fixture,
+        &fixture.base_b_marginfi_account,
+        &fixture.payer_base_b_fixture.key,
+        &fixture.base_b_token_program,
+        BORROWER_COLLATERAL_ATOMS,
+    )
+    .await?;
+    Ok((lender, borrower))
+}
+
+/// Accounts of a bid from the borrower, which borrows base against quote.
+async fn bid_metas(fixture: &NixTestFixture) -> Vec<AccountMeta> {
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+    let quote_bank: &BankFixture = &fixture.base_b_bank_fixture;
+    let mut bid_accounts: Vec<AccountMeta> =
+        marginfi_cpi_metas(fixture, base_bank, &fixture.base_a_marginfi_account);
+    bid_accounts.extend(marginfi_cpi_metas(
+        fixture,
+        quote_bank,
+        &fixture.base_b_marginfi_account,
+    ));
+    bid_accounts.extend(oracle_metas(quote_bank).await);
+    bid_accounts.extend(oracle_metas(base_bank).await);
+    bid_accounts
+}
+
+/// Opens one loan of ORDER_BASE_ATOMS from the lender to the borrower. Both
+/// have deposited into their seats already.
+async fn open_loan(
+    fixture: &NixTestFixture,
+    lender: &Keypair,
+    borrower: &Keypair,
+    market_loans: &Pubkey,
+) -> Result<(), BanksClientError> {
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+    let mut ask_accounts: Vec<AccountMeta> =
+        marginfi_cpi_metas(fixture, base_bank, &fixture.base_a_marginfi_account);
+    ask_accounts.extend(oracle_metas(base_bank).await);
+    place_order(
+        fixture,
+        lender,
+        market_loans,
+        order_params(false, OrderType::PostOnly),
+        ask_accounts,
+    )
+    .await?;
+
+    place_order(
+        fixture,
+        borrower,
+        market_loans,
+        order_params(true, OrderType::ImmediateOrCancel),
+        bid_metas(fixture).await,
+    )
+    .await
+}
+
+async fn mark_default(
+    fixture: &NixTestFixture,
+    market_loans: &Pubkey,
+    loan_sequence_number: u64,
+) -> Result<(), BanksClientError> {
+    let payer: Keypair = fixture.payer_keypair();
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new_readonly(payer.pubkey(), true),
+        AccountMeta::new(fixture.market, false),
+        AccountMeta::new(*market_loans, false),
+        AccountMeta::new_readonly(fixture.base_a_bank_fixture.key, false),
+        AccountMeta::new_readonly(fixture.base_b_bank_fixture.key, false),
+        AccountMeta::new_readonly(fixture.base_a_mint_fixture.key, false),
+        AccountMeta::new_readonly(fixture.base_b_mint_fixture.key, false),
+    ];
+    accounts.extend(oracle_metas(&fixture.base_a_bank_fixture).await);
+    accounts.extend(oracle_metas(&fixture.base_b_bank_fixture).await);
+    let mark_default_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts,
+        data: [
+            NixInstruction::MarkDefault.to_vec(),
+            MarkDefaultParams {
+                loan_sequence_number,
+            }
+            .try_to_vec()?,
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[mark_default_ix],
+        Some(&payer.pubkey()),
+        &[&payer],
+    )
+    .await
+}
+
+/// Cranks CleanExpiredOrders on the bids of the A tree.
+async fn clean_expired_bids(
+    fixture: &NixTestFixture,
+    cranker: &Keypair,
+    market_loans: &Pubkey,
+) -> Result<(), BanksClientError> {
+    let clean_expired_orders_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts: vec![
+            AccountMeta::new(cranker.pubkey(), true),
+            AccountMeta::new(*market_loans, false),
+            AccountMeta::new(fixture.market, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: [
+            NixInstruction::CleanExpiredOrders.to_vec(),
+            CleanExpiredOrdersParams::new(true, true, 1).try_to_vec()?,
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[clean_expired_orders_ix],
+        Some(&cranker.pubkey()),
+        &[cranker],
+    )
+    .await
+}
+
+fn assert_nix_error(result: Result<(), BanksClientError>, expected: NixError) {
+    match result {
+        Err(BanksClientError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        ))) => assert_eq!(code, expected as u32),
+        other => panic!("Expected {:?}, got {:?}", expected, other),
+    }
+}
+
+/// Value in atoms of the asset shares the market holds in the bank.
+async fn get_asset_atoms(
+    fixture: &NixTestFixture,
+    bank: &BankFixture,
+    marginfi_account: &Pubkey,
+) -> anyhow::Result<I80F48> {
+    let (asset_shares, _) = get_marginfi_shares(fixture, marginfi_account, bank).await;
+    Ok(bank.load().await.get_asset_amount(asset_shares)?)
+}
+
+/// Same as get_asset_atoms, after marginfi accrues the bank `seconds` from
+/// now.
+async fn accrue_and_get_asset_atoms(
+    fixture: &NixTestFixture,
+    bank: &BankFixture,
+    marginfi_account: &Pubkey,
+    seconds: i64,
+) -> anyhow::Result<I80F48> {
+    fixture.advance_time(seconds).await;
+    fixture.group.try_accrue_interest(bank).await?;
+    get_asset_atoms(fixture, bank, marginfi_account).await
+}
+
+#[tokio::test]
+async fn loan_lifecycle_conserves_tokens() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await?;
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+    let quote_bank: &BankFixture = &fixture.base_b_bank_fixture;
+    let borrower: Keypair = fixture.payer_keypair();
+    let lender: Keypair = fixture.second_keypair.insecure_clone();
+    let base_tokens: [Pubkey; 2] = [
+        fixture.payer_base_a_fixture.key,
+        fixture.second_keypair_base_a_fixture.key,
+    ];
+    let quote_tokens: [Pubkey; 2] = [
+        fixture.payer_base_b_fixture.key,
+        fixture.second_keypair_base_b_fixture.key,
+    ];
+
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[system_instruction::transfer(
+            &borrower.pubkey(),
+            &lender.pubkey(),
+            1_000_000_000,
+        )],
+        Some(&borrower.pubkey()),
+        &[&borrower],
+    )
+    .await?;
+    fixture.claim_seat_for_keypair(&borrower).await?;
+    fixture.claim_seat_for_keypair(&lender).await?;
+    fixture
+        .base_a_mint_fixture
+        .mint_to(&fixture.second_keypair_base_a_fixture.key, 10)
+        .await;
+    fixture
+        .base_b_mint_fixture
+        .mint_to(&fixture.payer_base_b_fixture.key, 10_000)
+        .await;
+    let base_atoms: u64 = get_tracked_atoms(&fixture, base_bank, &base_tokens).await;
+    let quote_atoms: u64 = get_tracked_atoms(&fixture, quote_bank, &quote_tokens).await;
+
+    // Lender deposits and asks.
+    let lender_token_before: u64 =
+        get_token_balance(&fixture, &fixture.second_keypair_base_a_fixture.key).await;
+    deposit_to_seat(
+        &fixture,
+        &lender,
+        base_bank,
+        &fixture.base_a_marginfi_account,
+        &fixture.second_keypair_base_a_fixture.key,
+        &fixture.base_a_token_program,
+        LENDER_DEPOSIT_ATOMS,
+    )
+    .await?;
+    assert_eq!(
+        lender_token_before
+            - get_token_balance(&fixture, &fixture.second_keypair_base_a_fixture.key).await,
+        LENDER_DEPOSIT_ATOMS
+    );
+    let mut ask_accounts: Vec<AccountMeta> =
+        marginfi_cpi_metas(&fixture, base_bank, &fixture.base_a_marginfi_account);
+    ask_accounts.extend(oracle_metas(base_bank).await);
+    place_order(
+        &fixture,
+        &lender,
+        &market_loans,
+        order_params(false, OrderType::PostOnly),
+        ask_accounts,
+    )
+    .await?;
+    assert_eq!(
+        get_tracked_atoms(&fixture, base_bank, &base_tokens).await,
+        base_atoms
+    );
+    let (base_asset_shares, _) =
+        get_marginfi_shares(&fixture, &fixture.base_a_marginfi_account, base_bank).await;
+    assert!(base_asset_shares > I80F48::ZERO);
+
+    // Borrower deposits collateral and bids into the ask.
+    deposit_to_seat(
+        &fixture,
+        &borrower,
+        quote_bank,
+        &fixture.base_b_marginfi_account,
+        &fixture.payer_base_b_fixture.key,
+        &fixture.base_b_token_program,
+        BORROWER_COLLATERAL_ATOMS,
+    )
+    .await?;
+    assert_eq!(
+        get_tracked_atoms(&fixture, quote_bank, &quote_tokens).await,
+        quote_atoms
+    );
+    let (quote_asset_shares, _) =
+        get_marginfi_shares(&fixture, &fixture.base_b_marginfi_account, quote_bank).await;
+    assert!(quote_asset_shares > I80F48::ZERO);
+
+    let mut bid_accounts: Vec<AccountMeta> =
+        marginfi_cpi_metas(&fixture, base_bank, &fixture.base_a_marginfi_account);
+    bid_accounts.extend(marginfi_cpi_metas(
+        &fixture,
+        quote_bank,
+        &fixture.base_b_marginfi_account,
+    ));
+    bid_accounts.extend(oracle_metas(quote_bank).await);
+    bid_accounts.extend(oracle_metas(base_bank).await);
+    place_order(
+        &fixture,
+        &borrower,
+        &market_loans,
+        order_params(true, OrderType::ImmediateOrCancel),
+        bid_accounts,
+    )
+    .await?;
+
+    // The match opens the loan at the ask's rate without moving tokens.
+    let loans: Vec<ActiveLoan> =
+        get_borrowed_loans(&fixture, &market_loans, &borrower.pubkey()).await;
+    assert_eq!(loans.len(), 1);
+    assert_eq!(loans[0].status, LoanStatus::Active);
+    assert_eq!(loans[0].rate_bps, RATE_BPS);
+    assert!(loans[0].is_liability_base_a.0 == 1);
+    assert_eq!(
+        get_tracked_atoms(&fixture, base_bank, &base_tokens).await,
+        base_atoms
+    );
+    assert_eq!(
+        get_tracked_atoms(&fixture, quote_bank, &quote_tokens).await,
+        quote_atoms
+    );
+    // Collateral stays lent on marginfi for the life of the loan.
+    assert_eq!(
+        get_marginfi_shares(&fixture, &fixture.base_b_marginfi_account, quote_bank)
+            .await
+            .0,
+        quote_asset_shares
+    );
+    fixture.verify_market().await;
+
+    // Interest accrues on marginfi. Each horizon starts from the loan as
+    // matched.
+    let snapshot: FixtureSnapshot = fixture.snapshot(&[market_loans]).await;
+    let (_, base_liability_shares) =
+        get_marginfi_shares(&fixture, &fixture.base_a_marginfi_account, base_bank).await;
+    let matched_atoms: I80F48 =
+        get_asset_atoms(&fixture, base_bank, &fixture.base_a_marginfi_account).await?;
+    let one_day_atoms: I80F48 = accrue_and_get_asset_atoms(
+        &fixture,
+        base_bank,
+        &fixture.base_a_marginfi_account,
+        ONE_DAY_SECONDS,
+    )
+    .await?;
+    fixture.restore(&snapshot).await;
+    let thirty_day_atoms: I80F48 = accrue_and_get_asset_atoms(
+        &fixture,
+        base_bank,
+        &fixture.base_a_marginfi_account,
+        30 * ONE_DAY_SECONDS,
+    )
+    .await?;
+    assert!(one_day_atoms >= matched_atoms);
+    assert!(thirty_day_atoms >= one_day_atoms);
+
+    // Accrual changes what shares are worth, never how many there are or
+    // where the atoms sit.
+    let (final_base_asset_shares, final_base_liability_shares) =
+        get_marginfi_shares(&fixture, &fixture.base_a_marginfi_account, base_bank).await;
+    let (final_quote_asset_shares, _) =
+        get_marginfi_shares(&fixture, &fixture.base_b_marginfi_account, quote_bank).await;
+    assert_eq!(final_base_liability_shares, base_liability_shares);
+    assert_eq!(final_quote_asset_shares, quote_asset_shares);
+    assert!(final_base_asset_shares > I80F48::ZERO);
+    assert_eq!(
+        get_tracked_atoms(&fixture, base_bank, &base_tokens).await,
+        base_atoms
+    );
+    assert_eq!(
+        get_tracked_atoms(&fixture, quote_bank, &quote_tokens).await,
+        quote_atoms
+    );
+    assert_eq!(
+        get_borrowed_loans(&fixture, &market_loans, &borrower.pubkey())
+            .await
+            .len(),
+        1
+    );
+    fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn loan_default_clears_seat_liability() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await?;
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+    let (lender, borrower): (Keypair, Keypair) = seat_lender_and_borrower(&fixture).await?;
+
+    // Borrow.
+    open_loan(&fixture, &lender, &borrower, &market_loans).await?;
+    let borrowed_liability_shares: I80F48 =
+        get_seat_liability_shares(&fixture, &borrower.pubkey()).await;
+    assert!(borrowed_liability_shares > I80F48::ZERO);
+    let loans: Vec<ActiveLoan> =
+        get_borrowed_loans(&fixture, &market_loans, &borrower.pubkey()).await;
+    assert_eq!(loans.len(), 1);
+
+    // The lent base goes up until the collateral no longer covers the loan,
+    // which closes it.
+    let base_oracle: Pubkey = base_bank.load().await.config.oracle_keys[0];
+    fixture
+        .set_pyth_oracle_price(base_oracle, 1_000_000.0)
+        .await;
+    mark_default(&fixture, &market_loans, loans[0].sequence_number).await?;
+    assert!(
+        get_borrowed_loans(&fixture, &market_loans, &borrower.pubkey())
+            .await
+            .is_empty()
+    );
+    assert_eq!(
+        get_seat_liability_shares(&fixture, &borrower.pubkey()).await,
+        I80F48::ZERO
+    );
+    fixture.verify_market().await;
+
+    // Borrow again, the closed loan no longer counts against the seat.
+    fixture.set_pyth_oracle_price(base_oracle, 10.0).await;
+    open_loan(&fixture, &lender, &borrower, &market_loans).await?;
+    assert_eq!(
+        get_seat_liability_shares(&fixture, &borrower.pubkey()).await,
+        borrowed_liability_shares
+    );
+    fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn expired_bid_keeps_its_borrow_as_a_pool_loan() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await?;
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+    let (lender, borrower): (Keypair, Keypair) = seat_lender_and_borrower(&fixture).await?;
+
+    // Returning the collateral of a resting bid would leave its borrow
+    // unpaid, so that policy is turned away.
+    assert_nix_error(
+        place_order(
+            &fixture,
+            &borrower,
+            &market_loans,
+            order_params(true, OrderType::PostOnly),
+            bid_metas(&fixture).await,
+        )
+        .await,
+        NixError::InvalidPlaceOrderParams,
+    );
+
+    let last_valid_slot: u32 = fixture.get_clock().await.slot as u32 + 1;
+    place_order(
+        &fixture,
+        &borrower,
+        &market_loans,
+        PlaceOrderParams {
+            last_valid_slot,
+            expiry_policy: ExpiryPolicy::KeepUntilCleaned,
+            ..order_params(true, OrderType::PostOnly)
+        },
+        bid_metas(&fixture).await,
+    )
+    .await?;
+    let (_, rested_liability_shares) =
+        get_marginfi_shares(&fixture, &fixture.base_a_marginfi_account, base_bank).await;
+    assert!(rested_liability_shares > I80F48::ZERO);
+    let seat_liability_shares: I80F48 =
+        get_seat_liability_shares(&fixture, &borrower.pubkey()).await;
+
+    // Warps past last_valid_slot.
+    fixture.advance_time(1).await;
+    clean_expired_bids(&fixture, &lender, &market_loans).await?;
+
+    // The borrow on marginfi is still open and now held by a pool loan of
+    // the borrower, who still carries it.
+    let (_, cleaned_liability_shares) =
+        get_marginfi_shares(&fixture, &fixture.base_a_marginfi_account, base_bank).await;
+    assert_eq!(cleaned_liability_shares, rested_liability_shares);
+    assert_eq!(
+        get_borrowed_loans(&fixture, &market_loans, &borrower.pubkey())
+            .await
+            .len(),
+        1
+    );
+    assert_eq!(
+        get_seat_liability_shares(&fixture, &borrower.pubkey()).await,
+        seat_liability_shares
+    );
+    fixture.verify_market().await;
+    Ok(())
+}
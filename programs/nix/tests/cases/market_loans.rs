@@ -0,0 +1,247 @@
+//! MarketLoans pages: the first one comes with the market, later ones are
+//! chained after it.
+
+use nix::{
+    program::{get_dynamic_account, place_order::PlaceOrderParams, NixError},
+    quantities::{BaseAtoms, Rate},
+    state::{
+        MarketFixed, MarketLoansFixed, MarketLoansRef, MarketRef, OrderType,
+        MARKET_LOANS_FIXED_SIZE, MAX_ACTIVE_LOANS,
+    },
+};
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    pubkey::Pubkey,
+    signature::Keypair,
+    signer::Signer,
+};
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{
+    assert_nix_error, default_market_params, get_account, open_loan, place_order, NixTestFixture,
+    TradingMarket,
+};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+
+async fn new_market() -> (NixTestFixture, TradingMarket) {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = fixture
+        .create_market_with_params(default_market_params())
+        .await
+        .unwrap();
+    (fixture, market)
+}
+
+/// Loans on `market_loans` that `trader` borrowed.
+async fn get_num_borrowed_loans(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    market_loans: &Pubkey,
+    trader: &Keypair,
+) -> usize {
+    let market_account: Account = get_account(fixture, &market.key).await;
+    let market_ref: MarketRef = get_dynamic_account::<MarketFixed>(&market_account.data);
+    let market_loans_account: Account = get_account(fixture, market_loans).await;
+    let market_loans: MarketLoansRef =
+        get_dynamic_account::<MarketLoansFixed>(&market_loans_account.data);
+    market_loans
+        .get_borrowed_loans(market_ref.get_trader_index(&trader.pubkey()))
+        .len()
+}
+
+async fn get_market_loans_fixed(
+    fixture: &NixTestFixture,
+    market_loans: &Pubkey,
+) -> MarketLoansFixed {
+    let account: Account = get_account(fixture, market_loans).await;
+    bytemuck::pod_read_unaligned(&account.data[..MARKET_LOANS_FIXED_SIZE])
+}
+
+/// Writes MAX_ACTIVE_LOANS into the page header, as if it had filled up.
+async fn fill_market_loans_page(fixture: &NixTestFixture, market_loans: &Pubkey) {
+    let mut account: Account = get_account(fixture, market_loans).await;
+    let mut market_loans_fixed: MarketLoansFixed =
+        get_market_loans_fixed(fixture, market_loans).await;
+    market_loans_fixed.num_active_loans = MAX_ACTIVE_LOANS;
+    account.data[..MARKET_LOANS_FIXED_SIZE]
+        .copy_from_slice(bytemuck::bytes_of(&market_loans_fixed));
+    fixture
+        .context
+        .borrow_mut()
+        .set_account(market_loans, &AccountSharedData::from(account));
+}
+
+fn bid_params() -> PlaceOrderParams {
+    PlaceOrderParams::new(
+        BaseAtoms::new(ORDER_BASE_ATOMS),
+        Rate::from_bps(RATE_BPS),
+        true,
+        true,
+        OrderType::ImmediateOrCancel,
+    )
+}
+
+#[tokio::test]
+async fn create_market_initializes_its_loans_account() -> anyhow::Result<()> {
+    let (fixture, market) = new_market().await;
+
+    let market_loans_account: Account = get_account(&fixture, &market.market_loans).await;
+    assert_eq!(market_loans_account.owner, nix::ID);
+    let market_loans: MarketLoansRef =
+        get_dynamic_account::<MarketLoansFixed>(&market_loans_account.data);
+    assert_eq!(market_loans.fixed.market, market.key);
+    assert_eq!(market_loans.fixed.num_active_loans, 0);
+    assert!(market_loans.fixed.has_free_block());
+    assert!(!market_loans.fixed.has_next_page());
+
+    // Loans can be recorded on it without a CreateMarketLoanAccount.
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await?;
+    open_loan(
+        &fixture,
+        &market,
+        &lender,
+        &borrower,
+        ORDER_BASE_ATOMS,
+        RATE_BPS,
+    )
+    .await?;
+    assert_eq!(
+        get_num_borrowed_loans(&fixture, &market, &market.market_loans, &borrower).await,
+        1
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn create_market_loan_account_links_the_last_page() -> anyhow::Result<()> {
+    let (fixture, market) = new_market().await;
+
+    let second_page: Pubkey = fixture
+        .create_market_loans_page(&market.key, &market.market_loans)
+        .await?;
+    let first_page_fixed: MarketLoansFixed =
+        get_market_loans_fixed(&fixture, &market.market_loans).await;
+    assert_eq!(first_page_fixed.next_page, second_page);
+    let second_page_fixed: MarketLoansFixed = get_market_loans_fixed(&fixture, &second_page).await;
+    assert_eq!(second_page_fixed.market, market.key);
+    assert!(!second_page_fixed.has_next_page());
+
+    // Only the last page can take a new link.
+    assert_nix_error(
+        fixture
+            .create_market_loans_page(&market.key, &market.market_loans)
+            .await
+            .map(|_| ()),
+        NixError::IncorrectAccount,
+    );
+    let third_page: Pubkey = fixture
+        .create_market_loans_page(&market.key, &second_page)
+        .await?;
+    assert_eq!(
+        get_market_loans_fixed(&fixture, &second_page)
+            .await
+            .next_page,
+        third_page
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn place_order_moves_on_from_a_full_loans_page() -> anyhow::Result<()> {
+    let (fixture, market) = new_market().await;
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await?;
+    let second_page: Pubkey = fixture
+        .create_market_loans_page(&market.key, &market.market_loans)
+        .await?;
+    fill_market_loans_page(&fixture, &market.market_loans).await;
+    let next_page_market: TradingMarket = TradingMarket {
+        key: market.key,
+        market_loans: second_page,
+    };
+
+    place_order(
+        &fixture,
+        &next_page_market,
+        &lender,
+        PlaceOrderParams::new(
+            BaseAtoms::new(ORDER_BASE_ATOMS),
+            Rate::from_bps(RATE_BPS),
+            false,
+            true,
+            OrderType::PostOnly,
+        ),
+        market.ask_metas(&fixture).await,
+    )
+    .await?;
+    assert_nix_error(
+        place_order(
+            &fixture,
+            &market,
+            &borrower,
+            bid_params(),
+            market.bid_metas(&fixture).await,
+        )
+        .await,
+        NixError::MarketLoansPageFull,
+    );
+
+    place_order(
+        &fixture,
+        &next_page_market,
+        &borrower,
+        bid_params(),
+        market.bid_metas(&fixture).await,
+    )
+    .await?;
+    assert_eq!(
+        get_num_borrowed_loans(&fixture, &market, &second_page, &borrower).await,
+        1
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn place_order_rejects_the_loans_page_of_another_market() -> anyhow::Result<()> {
+    let (fixture, market) = new_market().await;
+    let other_market: TradingMarket = fixture
+        .create_market_with_params(default_market_params())
+        .await?;
+    let (lender, _borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await?;
+
+    assert_nix_error(
+        place_order(
+            &fixture,
+            &TradingMarket {
+                key: market.key,
+                market_loans: other_market.market_loans,
+            },
+            &lender,
+            PlaceOrderParams::new(
+                BaseAtoms::new(ORDER_BASE_ATOMS),
+                Rate::from_bps(RATE_BPS),
+                false,
+                true,
+                OrderType::PostOnly,
+            ),
+            market.ask_metas(&fixture).await,
+        )
+        .await,
+        NixError::IncorrectAccount,
+    );
+    Ok(())
+}
@@ -0,0 +1,455 @@
+//! Matching that stops before the book is done crossing: at match_limit, at
+//! max_new_loans and at the MAX_MATCHED_LOANS cap. Whatever is left of the
+//! taker must never rest on the other side of a maker it could still match,
+//! and the borrow health check before matching stops where matching does.
+
+use std::rc::Rc;
+
+use borsh::BorshSerialize;
+use marginfi::state::marginfi_group::{Bank, BankVaultType};
+use nix::{
+    program::{
+        deposit::DepositParams, get_dynamic_account, place_order::PlaceOrderParams, NixError,
+        NixInstruction,
+    },
+    quantities::{BaseAtoms, Rate},
+    state::{
+        MarketFixed, MarketLoansFixed, MarketLoansRef, MarketRef, OrderType, MAX_MATCHED_LOANS,
+    },
+    validation::{get_market_signer_address, get_vault_address},
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction, InstructionError},
+    system_instruction, system_program,
+};
+use solana_program_test::BanksClientError;
+use solana_sdk::{
+    account::Account, pubkey::Pubkey, signature::Keypair, signer::Signer,
+    transaction::TransactionError,
+};
+use test_case::test_case;
+use test_utilities::{
+    bank::BankFixture,
+    test::{BankMint, TestSettings},
+};
+
+use crate::test_utils::{send_tx_with_retry, NixTestFixture};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 2_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+
+/// Fixture with a lender (the second keypair) and a borrower (the payer),
+/// both seated, the lender funded in base A and the borrower in quote.
+struct Traders {
+    fixture: NixTestFixture,
+    market_loans: Pubkey,
+    lender: Keypair,
+    borrower: Keypair,
+}
+
+async fn new_traders() -> Traders {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await
+        .unwrap();
+    let borrower: Keypair = fixture.payer_keypair();
+    let lender: Keypair = fixture.second_keypair.insecure_clone();
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[system_instruction::transfer(
+            &borrower.pubkey(),
+            &lender.pubkey(),
+            1_000_000_000,
+        )],
+        Some(&borrower.pubkey()),
+        &[&borrower],
+    )
+    .await
+    .unwrap();
+    fixture.claim_seat_for_keypair(&borrower).await.unwrap();
+    fixture.claim_seat_for_keypair(&lender).await.unwrap();
+    fixture
+        .base_a_mint_fixture
+        .mint_to(&fixture.second_keypair_base_a_fixture.key, 10)
+        .await;
+    fixture
+        .base_b_mint_fixture
+        .mint_to(&fixture.payer_base_b_fixture.key, 10_000)
+        .await;
+
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+    let quote_bank: &BankFixture = &fixture.base_b_bank_fixture;
+    deposit_to_seat(
+        &fixture,
+        &lender,
+        base_bank,
+        &fixture.base_a_marginfi_account,
+        &fixture.second_keypair_base_a_fixture.key,
+        &fixture.base_a_token_program,
+        LENDER_DEPOSIT_ATOMS,
+    )
+    .await
+    .unwrap();
+    deposit_to_seat(
+        &fixture,
+        &borrower,
+        quote_bank,
+        &fixture.base_b_marginfi_account,
+        &fixture.payer_base_b_fixture.key,
+        &fixture.base_b_token_program,
+        BORROWER_COLLATERAL_ATOMS,
+    )
+    .await
+    .unwrap();
+    Traders {
+        fixture,
+        market_loans,
+        lender,
+        borrower,
+    }
+}
+
+async fn get_account(fixture: &NixTestFixture, key: &Pubkey) -> Account {
+    fixture
+        .try_load(key)
+        .await
+        .unwrap()
+        .expect("Account not found")
+}
+
+/// Levels resting on one side of the A tree.
+async fn get_num_levels(fixture: &NixTestFixture, is_bid: bool) -> u8 {
+    let account: Account = get_account(fixture, &fixture.market).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    market.get_book_levels(true, is_bid, 8, 0, 0).unwrap().1
+}
+
+async fn get_num_borrowed_loans(traders: &Traders) -> usize {
+    let fixture: &NixTestFixture = &traders.fixture;
+    let market_account: Account = get_account(fixture, &fixture.market).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&market_account.data);
+    let market_loans_account: Account = get_account(fixture, &traders.market_loans).await;
+    let loans: MarketLoansRef = get_dynamic_account::<MarketLoansFixed>(&market_loans_account.data);
+    loans
+        .get_borrowed_loans(market.get_trader_index(&traders.borrower.pubkey()))
+        .len()
+}
+
+fn marginfi_cpi_metas(
+    fixture: &NixTestFixture,
+    bank: &BankFixture,
+    marginfi_account: &Pubkey,
+) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(fixture.group.key, false),
+        AccountMeta::new(bank.key, false),
+        AccountMeta::new(*marginfi_account, false),
+        AccountMeta::new(bank.get_vault(BankVaultType::Liquidity).0, false),
+        AccountMeta::new_readonly(bank.get_vault_authority(BankVaultType::Liquidity).0, false),
+    ]
+}
+
+async fn oracle_metas(bank: &BankFixture) -> Vec<AccountMeta> {
+    let bank_account: Bank = bank.load().await;
+    vec![
+        AccountMeta::new_readonly(bank.key, false),
+        AccountMeta::new_readonly(bank_account.config.oracle_keys[0], false),
+    ]
+}
+
+fn order_params(num_base_atoms: u64, is_bid: bool, order_type: OrderType) -> PlaceOrderParams {
+    PlaceOrderParams::new(
+        BaseAtoms::new(num_base_atoms),
+        Rate::from_bps(RATE_BPS),
+        is_bid,
+        true,
+        order_type,
+    )
+}
+
+async fn place_order(
+    fixture: &NixTestFixture,
+    trader: &Keypair,
+    market_loans: &Pubkey,
+    params: PlaceOrderParams,
+    optional_accounts: Vec<AccountMeta>,
+) -> Result<(), BanksClientError> {
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(trader.pubkey(), true),
+        AccountMeta::new(fixture.market, false),
+        AccountMeta::new(*market_loans, false),
+        AccountMeta::new_readonly(get_market_signer_address(&fixture.market).0, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(fixture.base_a_mint_fixture.key, false),
+        AccountMeta::new_readonly(fixture.base_b_mint_fixture.key, false),
+    ];
+    accounts.extend(optional_accounts);
+    let place_order_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts,
+        data: [
+            NixInstruction::PlaceOrder.to_vec(),
+            params.try_to_versioned_vec()?,
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[place_order_ix],
+        Some(&trader.pubkey()),
+        &[trader],
+    )
+    .await
+}
+
+/// Deposits into the trader's seat through the market's marginfi account for
+/// the bank.
+async fn deposit_to_seat(
+    fixture: &NixTestFixture,
+    trader: &Keypair,
+    bank: &BankFixture,
+    marginfi_account: &Pubkey,
+    trader_token: &Pubkey,
+    token_program: &Pubkey,
+    amount: u64,
+) -> Result<(), BanksClientError> {
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(trader.pubkey(), true),
+        AccountMeta::new(fixture.market, false),
+        AccountMeta::new_readonly(get_market_signer_address(&fixture.market).0, false),
+        AccountMeta::new(*trader_token, false),
+        AccountMeta::new(get_vault_address(&fixture.market, &bank.mint.key).0, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(bank.mint.key, false),
+    ];
+    accounts.extend(marginfi_cpi_metas(fixture, bank, marginfi_account));
+    // Deposit takes the liquidity vault without its authority.
+    accounts.pop();
+    let deposit_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts,
+        data: [
+            NixInstruction::Deposit.to_vec(),
+            DepositParams::new(amount, None, false).try_to_vec()?,
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[deposit_ix],
+        Some(&trader.pubkey()),
+        &[trader],
+    )
+    .await
+}
+
+/// Rests `num_asks` post only asks of ORDER_BASE_ATOMS from the lender.
+async fn place_lender_asks(traders: &Traders, num_asks: usize) {
+    place_sized_lender_asks(traders, num_asks, ORDER_BASE_ATOMS).await;
+}
+
+/// Rests `num_asks` post only asks of `num_base_atoms` from the lender.
+async fn place_sized_lender_asks(traders: &Traders, num_asks: usize, num_base_atoms: u64) {
+    let fixture: &NixTestFixture = &traders.fixture;
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+    for _ in 0..num_asks {
+        let mut ask_accounts: Vec<AccountMeta> =
+            marginfi_cpi_metas(fixture, base_bank, &fixture.base_a_marginfi_account);
+        ask_accounts.extend(oracle_metas(base_bank).await);
+        place_order(
+            fixture,
+            &traders.lender,
+            &traders.market_loans,
+            order_params(num_base_atoms, false, OrderType::PostOnly),
+            ask_accounts,
+        )
+        .await
+        .unwrap();
+    }
+}
+
+/// Places a limit bid from the borrower at the rate of the lender asks.
+async fn place_borrower_bid(
+    traders: &Traders,
+    params: PlaceOrderParams,
+) -> Result<(), BanksClientError> {
+    let fixture: &NixTestFixture = &traders.fixture;
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+    let quote_bank: &BankFixture = &fixture.base_b_bank_fixture;
+    let mut bid_accounts: Vec<AccountMeta> =
+        marginfi_cpi_metas(fixture, base_bank, &fixture.base_a_marginfi_account);
+    bid_accounts.extend(marginfi_cpi_metas(
+        fixture,
+        quote_bank,
+        &fixture.base_b_marginfi_account,
+    ));
+    bid_accounts.extend(oracle_metas(quote_bank).await);
+    bid_accounts.extend(oracle_metas(base_bank).await);
+    place_order(
+        fixture,
+        &traders.borrower,
+        &traders.market_loans,
+        params,
+        bid_accounts,
+    )
+    .await
+}
+
+fn assert_nix_error(result: Result<(), BanksClientError>, expected: NixError) {
+    match result {
+        Err(BanksClientError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        ))) => assert_eq!(code, expected as u32),
+        other => panic!("Expected {:?}, got {:?}", expected, other),
+    }
+}
+
+/// Limit bid of `num_base_atoms` that stops matching after one maker order,
+/// at match_limit or at max_new_loans.
+fn limited_bid_params(num_base_atoms: u64, is_max_new_loans: bool) -> PlaceOrderParams {
+    let (match_limit, max_new_loans) = if is_max_new_loans {
+        (None, Some(1))
+    } else {
+        (Some(1), None)
+    };
+    PlaceOrderParams {
+        match_limit,
+        max_new_loans,
+        ..order_params(num_base_atoms, true, OrderType::Limit)
+    }
+}
+
+#[tokio::test]
+async fn match_limit_stop_does_not_rest_a_crossing_remainder() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    place_lender_asks(&traders, 2).await;
+
+    place_borrower_bid(
+        &traders,
+        PlaceOrderParams {
+            match_limit: Some(1),
+            ..order_params(2 * ORDER_BASE_ATOMS, true, OrderType::Limit)
+        },
+    )
+    .await?;
+
+    // One ask matched, the rest of the bid would have crossed the other.
+    assert_eq!(get_num_borrowed_loans(&traders).await, 1);
+    assert_eq!(get_num_levels(&traders.fixture, false).await, 1);
+    assert_eq!(get_num_levels(&traders.fixture, true).await, 0);
+    traders.fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn match_limit_stop_rests_a_remainder_past_the_book() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    place_lender_asks(&traders, 1).await;
+
+    place_borrower_bid(
+        &traders,
+        PlaceOrderParams {
+            match_limit: Some(1),
+            ..order_params(2 * ORDER_BASE_ATOMS, true, OrderType::Limit)
+        },
+    )
+    .await?;
+
+    // Nothing is left to cross, so the rest of the bid rests as usual.
+    assert_eq!(get_num_borrowed_loans(&traders).await, 1);
+    assert_eq!(get_num_levels(&traders.fixture, false).await, 0);
+    assert_eq!(get_num_levels(&traders.fixture, true).await, 1);
+    traders.fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn max_new_loans_stop_does_not_rest_a_crossing_remainder() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    place_lender_asks(&traders, 2).await;
+
+    place_borrower_bid(
+        &traders,
+        PlaceOrderParams {
+            max_new_loans: Some(1),
+            ..order_params(2 * ORDER_BASE_ATOMS, true, OrderType::Limit)
+        },
+    )
+    .await?;
+
+    // One loan opened, the rest of the bid would have crossed the other ask.
+    assert_eq!(get_num_borrowed_loans(&traders).await, 1);
+    assert_eq!(get_num_levels(&traders.fixture, false).await, 1);
+    assert_eq!(get_num_levels(&traders.fixture, true).await, 0);
+    traders.fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn loan_cap_stop_does_not_rest_a_crossing_remainder() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    let num_asks: usize = MAX_MATCHED_LOANS + 1;
+    place_lender_asks(&traders, num_asks).await;
+
+    place_borrower_bid(
+        &traders,
+        order_params(num_asks as u64 * ORDER_BASE_ATOMS, true, OrderType::Limit),
+    )
+    .await?;
+
+    // Matching stops at the loan cap with one ask left to cross, so the rest
+    // of the bid is dropped instead of resting against it.
+    assert_eq!(get_num_borrowed_loans(&traders).await, MAX_MATCHED_LOANS);
+    assert_eq!(get_num_levels(&traders.fixture, false).await, 1);
+    assert_eq!(get_num_levels(&traders.fixture, true).await, 0);
+    traders.fixture.verify_market().await;
+    Ok(())
+}
+
+#[test_case(false ; "match limit")]
+#[test_case(true ; "max new loans")]
+#[tokio::test]
+async fn borrow_health_counts_what_is_left_past_the_limit(
+    is_max_new_loans: bool,
+) -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    // Two asks of half a SOL. The borrower's 1000 USDC back a borrow of
+    // about 6.4 SOL at the fixture price.
+    place_sized_lender_asks(&traders, 2, 5 * ORDER_BASE_ATOMS).await;
+
+    // Matching stops after one ask, which leaves 6.7 SOL to borrow, more
+    // than the collateral covers even though the book could fill 1 SOL.
+    assert_nix_error(
+        place_borrower_bid(
+            &traders,
+            limited_bid_params(72 * ORDER_BASE_ATOMS, is_max_new_loans),
+        )
+        .await,
+        NixError::InsufficientCollateral,
+    );
+    assert_eq!(get_num_borrowed_loans(&traders).await, 0);
+    assert_eq!(get_num_levels(&traders.fixture, false).await, 1);
+    assert_eq!(get_num_levels(&traders.fixture, true).await, 0);
+
+    // What is left past the limit is covered, the bid matches one ask and
+    // drops the remainder that still crosses the other.
+    place_borrower_bid(
+        &traders,
+        limited_bid_params(15 * ORDER_BASE_ATOMS, is_max_new_loans),
+    )
+    .await?;
+    assert_eq!(get_num_borrowed_loans(&traders).await, 1);
+    assert_eq!(get_num_levels(&traders.fixture, false).await, 1);
+    assert_eq!(get_num_levels(&traders.fixture, true).await, 0);
+    traders.fixture.verify_market().await;
+    Ok(())
+}
@@ -0,0 +1,136 @@
+//! Resting orders counted on the seat against the market's
+//! max_open_orders_per_seat.
+
+use nix::{
+    program::{
+        create_market::CreateMarketParams, get_dynamic_account, place_order::PlaceOrderParams,
+        NixError,
+    },
+    quantities::{BaseAtoms, Rate},
+    state::{MarketFixed, MarketRef, OrderType},
+};
+use solana_program_test::BanksClientError;
+use solana_sdk::{account::Account, signature::Keypair, signer::Signer};
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{
+    assert_nix_error, cancel_order, default_market_params, get_account, place_order,
+    NixTestFixture, TradingMarket,
+};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+const MAX_OPEN_ORDERS_PER_SEAT: u16 = 2;
+
+/// Market with `max_open_orders_per_seat` and a funded lender.
+async fn new_market(max_open_orders_per_seat: u16) -> (NixTestFixture, TradingMarket, Keypair) {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = fixture
+        .create_market_with_params(CreateMarketParams {
+            max_open_orders_per_seat,
+            ..default_market_params()
+        })
+        .await
+        .unwrap();
+    let (lender, _borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await
+        .unwrap();
+    (fixture, market, lender)
+}
+
+async fn get_num_open_orders(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    trader: &Keypair,
+) -> u32 {
+    let account: Account = get_account(fixture, &market.key).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    market
+        .get_seat_snapshot(&trader.pubkey())
+        .unwrap()
+        .num_open_orders
+}
+
+async fn get_last_order_sequence_number(fixture: &NixTestFixture, market: &TradingMarket) -> u64 {
+    let account: Account = get_account(fixture, &market.key).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    market.fixed.get_base_a_order_sequence_number()
+}
+
+/// Rests a post only ask of ORDER_BASE_ATOMS `rate_offset_bps` above
+/// RATE_BPS, so every ask gets its own level.
+async fn place_ask(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    lender: &Keypair,
+    rate_offset_bps: u16,
+) -> Result<(), BanksClientError> {
+    place_order(
+        fixture,
+        market,
+        lender,
+        PlaceOrderParams::new(
+            BaseAtoms::new(ORDER_BASE_ATOMS),
+            Rate::from_bps(RATE_BPS + rate_offset_bps),
+            false,
+            true,
+            OrderType::PostOnly,
+        ),
+        market.ask_metas(fixture).await,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn open_orders_stop_at_the_market_max() -> anyhow::Result<()> {
+    let (fixture, market, lender) = new_market(MAX_OPEN_ORDERS_PER_SEAT).await;
+
+    place_ask(&fixture, &market, &lender, 0).await?;
+    let first_order_sequence_number: u64 = get_last_order_sequence_number(&fixture, &market).await;
+    place_ask(&fixture, &market, &lender, 100).await?;
+    assert_eq!(
+        get_num_open_orders(&fixture, &market, &lender).await,
+        MAX_OPEN_ORDERS_PER_SEAT as u32
+    );
+
+    assert_nix_error(
+        place_ask(&fixture, &market, &lender, 200).await,
+        NixError::TooManyOpenOrders,
+    );
+
+    // Cancelling frees the slot for another order.
+    cancel_order(
+        &fixture,
+        &market,
+        &lender,
+        first_order_sequence_number,
+        true,
+    )
+    .await?;
+    assert_eq!(get_num_open_orders(&fixture, &market, &lender).await, 1);
+    place_ask(&fixture, &market, &lender, 200).await?;
+    assert_eq!(
+        get_num_open_orders(&fixture, &market, &lender).await,
+        MAX_OPEN_ORDERS_PER_SEAT as u32
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn open_orders_without_a_market_max() -> anyhow::Result<()> {
+    let (fixture, market, lender) = new_market(0).await;
+
+    for rate_offset_bps in [0, 100, 200] {
+        place_ask(&fixture, &market, &lender, rate_offset_bps).await?;
+    }
+    assert_eq!(get_num_open_orders(&fixture, &market, &lender).await, 3);
+    Ok(())
+}
@@ -0,0 +1,296 @@
+//! Decoding of versioned PlaceOrder data, the checks on the params that run
+//! before any account is loaded, and PlaceOrderBaseA and PlaceOrderBaseB,
+//! which name the tree in the instruction.
+
+use std::rc::Rc;
+
+use borsh::BorshSerialize;
+use nix::{
+    program::{
+        get_dynamic_account,
+        place_order::{PlaceOrderParams, PLACE_ORDER_PARAMS_TAG, PLACE_ORDER_PARAMS_VERSION},
+        NixError, NixInstruction,
+    },
+    quantities::{BaseAtoms, Rate},
+    state::{ExpiryPolicy, MarketFixed, MarketRef, OrderType, MAX_RATE_BPS},
+};
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program_test::BanksClientError;
+use solana_sdk::{account::Account, signature::Keypair, signer::Signer};
+use test_case::test_case;
+use test_utilities::{
+    bank::BankFixture,
+    test::{BankMint, TestSettings},
+};
+
+use crate::test_utils::{
+    assert_nix_error, get_account, oracle_metas, place_order_metas, send_nix_instruction,
+    send_tx_with_retry, NixTestFixture, TradingMarket, VersionedPlaceOrderParams,
+};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+/// max_expired_orders_to_sweep, then the None tags of max_new_loans and
+/// reverse_bid_spread_bps, then last_valid_unix_timestamp.
+const FIELDS_SINCE_V1_SIZE: usize = 1 + 1 + 1 + 4;
+
+async fn new_market() -> (NixTestFixture, TradingMarket, Keypair) {
+    let (fixture, market, lender, _borrower) = new_traders().await;
+    (fixture, market, lender)
+}
+
+/// The fixture market with the lender and the borrower, who holds base B.
+async fn new_traders() -> (NixTestFixture, TradingMarket, Keypair, Keypair) {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = TradingMarket {
+        key: fixture.market,
+        market_loans: fixture
+            .create_market_loan_account(&fixture.payer(), &fixture.market)
+            .await
+            .unwrap(),
+    };
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await
+        .unwrap();
+    (fixture, market, lender, borrower)
+}
+
+fn order_params(is_bid: bool, order_type: OrderType) -> PlaceOrderParams {
+    PlaceOrderParams::new(
+        BaseAtoms::new(ORDER_BASE_ATOMS),
+        Rate::from_bps(RATE_BPS),
+        is_bid,
+        true,
+        order_type,
+    )
+}
+
+fn rate_above_max() -> PlaceOrderParams {
+    PlaceOrderParams {
+        rate_bps: MAX_RATE_BPS + 1,
+        ..order_params(false, OrderType::Limit)
+    }
+}
+
+fn reverse_spread_of_a_whole_rate() -> PlaceOrderParams {
+    PlaceOrderParams {
+        reverse_spread_bps: 10_000,
+        ..order_params(true, OrderType::Reverse)
+    }
+}
+
+fn deferred_reverse() -> PlaceOrderParams {
+    PlaceOrderParams {
+        defer_remainder: true,
+        ..order_params(true, OrderType::Reverse)
+    }
+}
+
+fn good_till_time_reverse() -> PlaceOrderParams {
+    PlaceOrderParams {
+        last_valid_unix_timestamp: 1,
+        ..order_params(true, OrderType::Reverse)
+    }
+}
+
+fn resting_bid_returning_collateral() -> PlaceOrderParams {
+    PlaceOrderParams {
+        expiry_policy: ExpiryPolicy::ReturnCollateral,
+        ..order_params(true, OrderType::Limit)
+    }
+}
+
+/// PlaceOrder with `data` as is, after the fixed accounts and the accounts
+/// of an ask.
+async fn send_place_order_data(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    lender: &Keypair,
+    data: Vec<u8>,
+) -> Result<(), BanksClientError> {
+    let mut accounts: Vec<AccountMeta> = place_order_metas(fixture, market, lender);
+    accounts.extend(market.ask_metas(fixture).await);
+    let place_order_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts,
+        data: [NixInstruction::PlaceOrder.to_vec(), data].concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[place_order_ix],
+        Some(&lender.pubkey()),
+        &[lender],
+    )
+    .await
+}
+
+async fn get_num_asks(fixture: &NixTestFixture, market: &TradingMarket, use_a_tree: bool) -> u16 {
+    let account: Account = get_account(fixture, &market.key).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    let (asks, num_asks) = market.get_book_levels(use_a_tree, false, 8, 0, 0).unwrap();
+    if num_asks == 0 {
+        return 0;
+    }
+    asks[0].num_orders
+}
+
+#[test_case(rate_above_max(), NixError::InvalidPlaceOrderParams ; "rate above max")]
+#[test_case(reverse_spread_of_a_whole_rate(), NixError::InvalidReverseSpread ; "reverse spread")]
+#[test_case(deferred_reverse(), NixError::InvalidPendingOrder ; "deferred reverse")]
+#[test_case(good_till_time_reverse(), NixError::InvalidPlaceOrderParams ; "good till time reverse")]
+#[test_case(resting_bid_returning_collateral(), NixError::InvalidPlaceOrderParams ; "bid returning collateral")]
+#[tokio::test]
+async fn place_order_checks_params_before_accounts(
+    params: PlaceOrderParams,
+    expected_error: NixError,
+) -> anyhow::Result<()> {
+    let (fixture, _market, lender) = new_market().await;
+
+    // Only the payer, which would fail on the missing accounts otherwise.
+    assert_nix_error(
+        send_nix_instruction(
+            &fixture,
+            &lender,
+            NixInstruction::PlaceOrder,
+            vec![AccountMeta::new(lender.pubkey(), true)],
+            &VersionedPlaceOrderParams(&params),
+        )
+        .await,
+        expected_error,
+    );
+    Ok(())
+}
+
+#[test_case(Vec::new() ; "untagged")]
+#[test_case(vec![PLACE_ORDER_PARAMS_TAG, 1] ; "tagged")]
+#[tokio::test]
+async fn place_order_reads_v1_data(prefix: Vec<u8>) -> anyhow::Result<()> {
+    let (fixture, market, lender) = new_market().await;
+    let data: Vec<u8> = order_params(false, OrderType::PostOnly).try_to_vec()?;
+    let v1_data: &[u8] = &data[..data.len() - FIELDS_SINCE_V1_SIZE];
+
+    send_place_order_data(
+        &fixture,
+        &market,
+        &lender,
+        [prefix, v1_data.to_vec()].concat(),
+    )
+    .await?;
+    assert_eq!(get_num_asks(&fixture, &market, true).await, 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn place_order_rejects_an_unknown_version() -> anyhow::Result<()> {
+    let (fixture, market, lender) = new_market().await;
+    let data: Vec<u8> = order_params(false, OrderType::PostOnly).try_to_vec()?;
+
+    assert_nix_error(
+        send_place_order_data(
+            &fixture,
+            &market,
+            &lender,
+            [
+                vec![PLACE_ORDER_PARAMS_TAG, PLACE_ORDER_PARAMS_VERSION + 1],
+                data.clone(),
+            ]
+            .concat(),
+        )
+        .await,
+        NixError::InvalidPlaceOrderParams,
+    );
+    assert_eq!(get_num_asks(&fixture, &market, true).await, 0);
+
+    send_place_order_data(
+        &fixture,
+        &market,
+        &lender,
+        [
+            vec![PLACE_ORDER_PARAMS_TAG, PLACE_ORDER_PARAMS_VERSION],
+            data,
+        ]
+        .concat(),
+    )
+    .await?;
+    assert_eq!(get_num_asks(&fixture, &market, true).await, 1);
+    Ok(())
+}
+
+/// Optional accounts of an ask on the B tree, which lends base B.
+async fn base_b_ask_metas(fixture: &NixTestFixture, market: &TradingMarket) -> Vec<AccountMeta> {
+    let base_bank: &BankFixture = &fixture.base_b_bank_fixture;
+    let mut accounts: Vec<AccountMeta> = market.marginfi_cpi_metas(fixture, base_bank);
+    accounts.extend(oracle_metas(base_bank).await);
+    accounts
+}
+
+#[tokio::test]
+async fn place_order_on_tree_places_on_its_tree() -> anyhow::Result<()> {
+    let (fixture, market, lender, borrower) = new_traders().await;
+
+    let mut accounts: Vec<AccountMeta> = place_order_metas(&fixture, &market, &lender);
+    accounts.extend(market.ask_metas(&fixture).await);
+    send_nix_instruction(
+        &fixture,
+        &lender,
+        NixInstruction::PlaceOrderBaseA,
+        accounts,
+        &VersionedPlaceOrderParams(&order_params(false, OrderType::PostOnly)),
+    )
+    .await?;
+    assert_eq!(get_num_asks(&fixture, &market, true).await, 1);
+
+    // The borrower holds base B, which it can lend on the B tree.
+    let mut accounts: Vec<AccountMeta> = place_order_metas(&fixture, &market, &borrower);
+    accounts.extend(base_b_ask_metas(&fixture, &market).await);
+    send_nix_instruction(
+        &fixture,
+        &borrower,
+        NixInstruction::PlaceOrderBaseB,
+        accounts,
+        &VersionedPlaceOrderParams(&PlaceOrderParams {
+            use_a_tree: false,
+            ..order_params(false, OrderType::PostOnly)
+        }),
+    )
+    .await?;
+    assert_eq!(get_num_asks(&fixture, &market, false).await, 1);
+    assert_eq!(get_num_asks(&fixture, &market, true).await, 1);
+    Ok(())
+}
+
+#[test_case(NixInstruction::PlaceOrderBaseA, false ; "a tree instruction")]
+#[test_case(NixInstruction::PlaceOrderBaseB, true ; "b tree instruction")]
+#[tokio::test]
+async fn place_order_on_tree_rejects_the_other_tree(
+    instruction: NixInstruction,
+    use_a_tree: bool,
+) -> anyhow::Result<()> {
+    let (fixture, _market, lender) = new_market().await;
+    let params: PlaceOrderParams = PlaceOrderParams {
+        use_a_tree,
+        ..order_params(false, OrderType::PostOnly)
+    };
+
+    // Only the payer, the tree is checked before any account.
+    assert_nix_error(
+        send_nix_instruction(
+            &fixture,
+            &lender,
+            instruction,
+            vec![AccountMeta::new(lender.pubkey(), true)],
+            &VersionedPlaceOrderParams(&params),
+        )
+        .await,
+        NixError::InvalidPlaceOrderParams,
+    );
+    Ok(())
+}
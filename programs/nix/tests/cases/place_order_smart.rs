@@ -0,0 +1,346 @@
+//! PlaceOrderSmart picks the tree for the order from the best opposing rate
+//! of each tree.
+
+use std::rc::Rc;
+
+use borsh::BorshSerialize;
+use marginfi::state::marginfi_group::{Bank, BankVaultType};
+use nix::{
+    program::{
+        deposit::DepositParams, get_dynamic_account, place_order::PlaceOrderParams,
+        place_order_smart::PlaceOrderSmartParams, NixInstruction,
+    },
+    quantities::{BaseAtoms, Rate},
+    state::{ExpiryPolicy, MarketFixed, MarketLoansFixed, MarketLoansRef, MarketRef, OrderType},
+    validation::{get_market_signer_address, get_vault_address},
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    system_instruction, system_program,
+};
+use solana_program_test::BanksClientError;
+use solana_sdk::{account::Account, pubkey::Pubkey, signature::Keypair, signer::Signer};
+use test_case::test_case;
+use test_utilities::{
+    bank::BankFixture,
+    test::{BankMint, TestSettings},
+};
+
+use crate::test_utils::{send_tx_with_retry, NixTestFixture};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 2_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+
+/// Fixture with a lender (the second keypair) and a borrower (the payer),
+/// both seated, the lender funded in base A and the borrower in quote.
+struct Traders {
+    fixture: NixTestFixture,
+    market_loans: Pubkey,
+    lender: Keypair,
+    borrower: Keypair,
+}
+
+async fn new_traders() -> Traders {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market_loans: Pubkey = fixture
+        .create_market_loan_account(&fixture.payer(), &fixture.market)
+        .await
+        .unwrap();
+    let borrower: Keypair = fixture.payer_keypair();
+    let lender: Keypair = fixture.second_keypair.insecure_clone();
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[system_instruction::transfer(
+            &borrower.pubkey(),
+            &lender.pubkey(),
+            1_000_000_000,
+        )],
+        Some(&borrower.pubkey()),
+        &[&borrower],
+    )
+    .await
+    .unwrap();
+    fixture.claim_seat_for_keypair(&borrower).await.unwrap();
+    fixture.claim_seat_for_keypair(&lender).await.unwrap();
+    fixture
+        .base_a_mint_fixture
+        .mint_to(&fixture.second_keypair_base_a_fixture.key, 10)
+        .await;
+    fixture
+        .base_b_mint_fixture
+        .mint_to(&fixture.payer_base_b_fixture.key, 10_000)
+        .await;
+
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+    let quote_bank: &BankFixture = &fixture.base_b_bank_fixture;
+    deposit_to_seat(
+        &fixture,
+        &lender,
+        base_bank,
+        &fixture.base_a_marginfi_account,
+        &fixture.second_keypair_base_a_fixture.key,
+        &fixture.base_a_token_program,
+        LENDER_DEPOSIT_ATOMS,
+    )
+    .await
+    .unwrap();
+    deposit_to_seat(
+        &fixture,
+        &borrower,
+        quote_bank,
+        &fixture.base_b_marginfi_account,
+        &fixture.payer_base_b_fixture.key,
+        &fixture.base_b_token_program,
+        BORROWER_COLLATERAL_ATOMS,
+    )
+    .await
+    .unwrap();
+    Traders {
+        fixture,
+        market_loans,
+        lender,
+        borrower,
+    }
+}
+
+async fn get_account(fixture: &NixTestFixture, key: &Pubkey) -> Account {
+    fixture
+        .try_load(key)
+        .await
+        .unwrap()
+        .expect("Account not found")
+}
+
+/// Levels resting on one side of the A tree.
+async fn get_num_levels(fixture: &NixTestFixture, is_bid: bool) -> u8 {
+    let account: Account = get_account(fixture, &fixture.market).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    market.get_book_levels(true, is_bid, 8, 0, 0).unwrap().1
+}
+
+async fn get_num_borrowed_loans(traders: &Traders) -> usize {
+    let fixture: &NixTestFixture = &traders.fixture;
+    let market_account: Account = get_account(fixture, &fixture.market).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&market_account.data);
+    let market_loans_account: Account = get_account(fixture, &traders.market_loans).await;
+    let loans: MarketLoansRef = get_dynamic_account::<MarketLoansFixed>(&market_loans_account.data);
+    loans
+        .get_borrowed_loans(market.get_trader_index(&traders.borrower.pubkey()))
+        .len()
+}
+
+fn marginfi_cpi_metas(
+    fixture: &NixTestFixture,
+    bank: &BankFixture,
+    marginfi_account: &Pubkey,
+) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(fixture.group.key, false),
+        AccountMeta::new(bank.key, false),
+        AccountMeta::new(*marginfi_account, false),
+        AccountMeta::new(bank.get_vault(BankVaultType::Liquidity).0, false),
+        AccountMeta::new_readonly(bank.get_vault_authority(BankVaultType::Liquidity).0, false),
+    ]
+}
+
+async fn oracle_metas(bank: &BankFixture) -> Vec<AccountMeta> {
+    let bank_account: Bank = bank.load().await;
+    vec![
+        AccountMeta::new_readonly(bank.key, false),
+        AccountMeta::new_readonly(bank_account.config.oracle_keys[0], false),
+    ]
+}
+
+fn order_params(num_base_atoms: u64, is_bid: bool, order_type: OrderType) -> PlaceOrderParams {
+    PlaceOrderParams {
+        expiry_policy: ExpiryPolicy::ReturnCollateral,
+        ..PlaceOrderParams::new(
+            BaseAtoms::new(num_base_atoms),
+            Rate::from_bps(RATE_BPS),
+            is_bid,
+            true,
+            order_type,
+        )
+    }
+}
+
+async fn place_order(
+    fixture: &NixTestFixture,
+    trader: &Keypair,
+    market_loans: &Pubkey,
+    params: PlaceOrderParams,
+    optional_accounts: Vec<AccountMeta>,
+) -> Result<(), BanksClientError> {
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(trader.pubkey(), true),
+        AccountMeta::new(fixture.market, false),
+        AccountMeta::new(*market_loans, false),
+        AccountMeta::new_readonly(get_market_signer_address(&fixture.market).0, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(fixture.base_a_mint_fixture.key, false),
+        AccountMeta::new_readonly(fixture.base_b_mint_fixture.key, false),
+    ];
+    accounts.extend(optional_accounts);
+    let place_order_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts,
+        data: [
+            NixInstruction::PlaceOrder.to_vec(),
+            params.try_to_versioned_vec()?,
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[place_order_ix],
+        Some(&trader.pubkey()),
+        &[trader],
+    )
+    .await
+}
+
+/// Deposits into the trader's seat through the market's marginfi account for
+/// the bank.
+async fn deposit_to_seat(
+    fixture: &NixTestFixture,
+    trader: &Keypair,
+    bank: &BankFixture,
+    marginfi_account: &Pubkey,
+    trader_token: &Pubkey,
+    token_program: &Pubkey,
+    amount: u64,
+) -> Result<(), BanksClientError> {
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(trader.pubkey(), true),
+        AccountMeta::new(fixture.market, false),
+        AccountMeta::new_readonly(get_market_signer_address(&fixture.market).0, false),
+        AccountMeta::new(*trader_token, false),
+        AccountMeta::new(get_vault_address(&fixture.market, &bank.mint.key).0, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(bank.mint.key, false),
+    ];
+    accounts.extend(marginfi_cpi_metas(fixture, bank, marginfi_account));
+    // Deposit takes the liquidity vault without its authority.
+    accounts.pop();
+    let deposit_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts,
+        data: [
+            NixInstruction::Deposit.to_vec(),
+            DepositParams::new(amount, None, false).try_to_vec()?,
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[deposit_ix],
+        Some(&trader.pubkey()),
+        &[trader],
+    )
+    .await
+}
+
+/// Rests `num_asks` post only asks of ORDER_BASE_ATOMS from the lender.
+async fn place_lender_asks(traders: &Traders, num_asks: usize) {
+    let fixture: &NixTestFixture = &traders.fixture;
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+    for _ in 0..num_asks {
+        let mut ask_accounts: Vec<AccountMeta> =
+            marginfi_cpi_metas(fixture, base_bank, &fixture.base_a_marginfi_account);
+        ask_accounts.extend(oracle_metas(base_bank).await);
+        place_order(
+            fixture,
+            &traders.lender,
+            &traders.market_loans,
+            order_params(ORDER_BASE_ATOMS, false, OrderType::PostOnly),
+            ask_accounts,
+        )
+        .await
+        .unwrap();
+    }
+}
+
+/// Places a smart bid from the borrower for ORDER_BASE_ATOMS on either tree.
+async fn place_borrower_smart_bid(
+    traders: &Traders,
+    prefer_a_tree: bool,
+) -> Result<(), BanksClientError> {
+    let fixture: &NixTestFixture = &traders.fixture;
+    let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+    let quote_bank: &BankFixture = &fixture.base_b_bank_fixture;
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(traders.borrower.pubkey(), true),
+        AccountMeta::new(fixture.market, false),
+        AccountMeta::new(traders.market_loans, false),
+        AccountMeta::new_readonly(get_market_signer_address(&fixture.market).0, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(fixture.base_a_mint_fixture.key, false),
+        AccountMeta::new_readonly(fixture.base_b_mint_fixture.key, false),
+    ];
+    accounts.extend(marginfi_cpi_metas(
+        fixture,
+        base_bank,
+        &fixture.base_a_marginfi_account,
+    ));
+    accounts.extend(marginfi_cpi_metas(
+        fixture,
+        quote_bank,
+        &fixture.base_b_marginfi_account,
+    ));
+    accounts.extend(oracle_metas(quote_bank).await);
+    accounts.extend(oracle_metas(base_bank).await);
+    let params: PlaceOrderSmartParams = PlaceOrderSmartParams {
+        trader_index_hint: None,
+        num_base_a_atoms: ORDER_BASE_ATOMS,
+        num_base_b_atoms: ORDER_BASE_ATOMS,
+        rate_bps: RATE_BPS,
+        reverse_spread_bps: 0,
+        is_bid: true,
+        prefer_a_tree,
+        last_valid_slot: 0,
+        order_type: OrderType::ImmediateOrCancel,
+        expiry_policy: ExpiryPolicy::ReturnCollateral,
+        referrer: None,
+        match_limit: None,
+        auto_claim_seat: false,
+    };
+    let place_order_smart_ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts,
+        data: [
+            NixInstruction::PlaceOrderSmart.to_vec(),
+            params.try_to_vec()?,
+        ]
+        .concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[place_order_smart_ix],
+        Some(&traders.borrower.pubkey()),
+        &[&traders.borrower],
+    )
+    .await
+}
+
+#[test_case(true ; "a tree preferred")]
+#[test_case(false ; "b tree preferred")]
+#[tokio::test]
+async fn place_order_smart_routes_to_the_crossing_tree(prefer_a_tree: bool) -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    place_lender_asks(&traders, 1).await;
+
+    place_borrower_smart_bid(&traders, prefer_a_tree).await?;
+
+    // Only the A tree has an ask to cross, so the bid goes there either way.
+    assert_eq!(get_num_borrowed_loans(&traders).await, 1);
+    assert_eq!(get_num_levels(&traders.fixture, false).await, 0);
+    traders.fixture.verify_market().await;
+    Ok(())
+}
@@ -0,0 +1,182 @@
+//! OrderConvertedToPoolLoanLog, emitted when a resting bid leaves the book
+//! and what it borrowed stays open as a loan to the pool.
+
+use fixed::types::I80F48;
+use nix::{
+    logs::{OrderConvertedToPoolLoanLog, PoolLoanConversionReason},
+    program::{
+        cancel_order::CancelOrderParams, get_dynamic_account, place_order::PlaceOrderParams,
+        NixInstruction,
+    },
+    quantities::{BaseAtoms, Rate},
+    state::{ExpiryPolicy, MarketFixed, OrderType},
+};
+use solana_program::{instruction::AccountMeta, system_program};
+use solana_sdk::{account::Account, signature::Keypair, signer::Signer};
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{
+    get_account, get_logs, place_order, place_order_metas, simulate_nix_log_data, NixTestFixture,
+    TradingMarket, VersionedPlaceOrderParams,
+};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+
+/// The fixture market with a funded lender and borrower.
+struct Traders {
+    fixture: NixTestFixture,
+    market: TradingMarket,
+    lender: Keypair,
+    borrower: Keypair,
+}
+
+async fn new_traders() -> Traders {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = TradingMarket {
+        key: fixture.market,
+        market_loans: fixture
+            .create_market_loan_account(&fixture.payer(), &fixture.market)
+            .await
+            .unwrap(),
+    };
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await
+        .unwrap();
+    Traders {
+        fixture,
+        market,
+        lender,
+        borrower,
+    }
+}
+
+fn order_params(is_bid: bool, order_type: OrderType) -> PlaceOrderParams {
+    PlaceOrderParams::new(
+        BaseAtoms::new(ORDER_BASE_ATOMS),
+        Rate::from_bps(RATE_BPS),
+        is_bid,
+        true,
+        order_type,
+    )
+}
+
+/// Rests a bid from the borrower, which borrows when it rests, and returns
+/// its sequence number.
+async fn rest_bid(traders: &Traders, params: PlaceOrderParams) -> anyhow::Result<u64> {
+    let Traders {
+        fixture,
+        market,
+        borrower,
+        ..
+    } = traders;
+    place_order(
+        fixture,
+        market,
+        borrower,
+        params,
+        market.bid_metas(fixture).await,
+    )
+    .await?;
+    let account: Account = get_account(fixture, &market.key).await;
+    Ok(get_dynamic_account::<MarketFixed>(&account.data)
+        .fixed
+        .get_base_a_order_sequence_number())
+}
+
+/// Checks the single OrderConvertedToPoolLoanLog among `log_data`.
+fn assert_converted_bid(
+    traders: &Traders,
+    log_data: &[Vec<u8>],
+    order_sequence_number: u64,
+    reason: PoolLoanConversionReason,
+) {
+    let logs: Vec<OrderConvertedToPoolLoanLog> = get_logs(log_data);
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].market, traders.market.key);
+    assert_eq!(logs[0].trader, traders.borrower.pubkey());
+    assert_eq!(logs[0].order_sequence_number, order_sequence_number);
+    assert_eq!(logs[0].reason, reason);
+    assert!(I80F48::from(logs[0].liability_shares).is_positive());
+}
+
+#[tokio::test]
+async fn cancel_bid_logs_the_pool_loan() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    let order_sequence_number: u64 =
+        rest_bid(&traders, order_params(true, OrderType::PostOnly)).await?;
+
+    let (result, log_data) = simulate_nix_log_data(
+        &traders.fixture,
+        &traders.borrower,
+        NixInstruction::CancelOrder,
+        vec![
+            AccountMeta::new(traders.borrower.pubkey(), true),
+            AccountMeta::new(traders.market.market_loans, false),
+            AccountMeta::new(traders.market.key, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        &CancelOrderParams {
+            trader_index_hint: None,
+            order_sequence_number,
+            order_index_hint: None,
+            use_a_tree: true,
+            max_expired_orders_to_sweep: 0,
+        },
+    )
+    .await?;
+    assert_eq!(result, Ok(()));
+    assert_converted_bid(
+        &traders,
+        &log_data,
+        order_sequence_number,
+        PoolLoanConversionReason::Canceled,
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn taker_reaching_an_expired_bid_logs_the_pool_loan() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    let last_valid_unix_timestamp: u32 =
+        traders.fixture.get_clock().await.unix_timestamp as u32 + 1;
+    let order_sequence_number: u64 = rest_bid(
+        &traders,
+        PlaceOrderParams {
+            last_valid_unix_timestamp,
+            expiry_policy: ExpiryPolicy::ConvertToPool,
+            ..order_params(true, OrderType::PostOnly)
+        },
+    )
+    .await?;
+    traders.fixture.advance_time(2).await;
+
+    let mut accounts: Vec<AccountMeta> =
+        place_order_metas(&traders.fixture, &traders.market, &traders.lender);
+    accounts.extend(traders.market.ask_metas(&traders.fixture).await);
+    let (result, log_data) = simulate_nix_log_data(
+        &traders.fixture,
+        &traders.lender,
+        NixInstruction::PlaceOrder,
+        accounts,
+        &VersionedPlaceOrderParams(&order_params(false, OrderType::ImmediateOrCancel)),
+    )
+    .await?;
+    assert_eq!(result, Ok(()));
+    assert_converted_bid(
+        &traders,
+        &log_data,
+        order_sequence_number,
+        PoolLoanConversionReason::Expired,
+    );
+    Ok(())
+}
@@ -0,0 +1,194 @@
+//! QuoteOrder previews what a taker would fill without touching the book.
+
+use borsh::BorshDeserialize;
+use nix::{
+    program::{
+        place_order::PlaceOrderParams,
+        quote_order::{QuoteOrderParams, QuoteOrderReturnData},
+        NixError, NixInstruction,
+    },
+    quantities::{BaseAtoms, Rate},
+    state::OrderType,
+};
+use solana_program::instruction::AccountMeta;
+use solana_program_test::BanksClientError;
+use solana_sdk::{account::Account, signature::Keypair};
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{
+    assert_nix_error, get_account, oracle_metas, place_order, send_nix_instruction,
+    simulate_nix_instruction, NixTestFixture, TradingMarket,
+};
+
+const RATE_BPS: u16 = 500;
+const RATE_STEP_BPS: u16 = 100;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+
+/// The fixture market with two lender asks of ORDER_BASE_ATOMS, at RATE_BPS
+/// and RATE_STEP_BPS above it.
+async fn new_book() -> (NixTestFixture, TradingMarket, Keypair) {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = TradingMarket {
+        key: fixture.market,
+        market_loans: fixture
+            .create_market_loan_account(&fixture.payer(), &fixture.market)
+            .await
+            .unwrap(),
+    };
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await
+        .unwrap();
+    for rate_bps in [RATE_BPS, RATE_BPS + RATE_STEP_BPS] {
+        place_order(
+            &fixture,
+            &market,
+            &lender,
+            PlaceOrderParams::new(
+                BaseAtoms::new(ORDER_BASE_ATOMS),
+                Rate::from_bps(rate_bps),
+                false,
+                true,
+                OrderType::PostOnly,
+            ),
+            market.ask_metas(&fixture).await,
+        )
+        .await
+        .unwrap();
+    }
+    (fixture, market, borrower)
+}
+
+/// Market, the banks of the A tree and their oracles.
+async fn quote_order_metas(fixture: &NixTestFixture, market: &TradingMarket) -> Vec<AccountMeta> {
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new_readonly(market.key, false),
+        AccountMeta::new_readonly(fixture.base_a_bank_fixture.key, false),
+        AccountMeta::new_readonly(fixture.base_b_bank_fixture.key, false),
+    ];
+    accounts.extend(oracle_metas(&fixture.base_a_bank_fixture).await);
+    accounts.extend(oracle_metas(&fixture.base_b_bank_fixture).await);
+    accounts
+}
+
+async fn quote_bid(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    trader: &Keypair,
+    num_base_atoms: u64,
+    rate_bps: u16,
+) -> Result<QuoteOrderReturnData, BanksClientError> {
+    let return_data: Vec<u8> = simulate_nix_instruction(
+        fixture,
+        trader,
+        NixInstruction::QuoteOrder,
+        quote_order_metas(fixture, market).await,
+        &QuoteOrderParams::new(
+            BaseAtoms::new(num_base_atoms),
+            Rate::from_bps(rate_bps),
+            true,
+            true,
+            8,
+        ),
+    )
+    .await?;
+    Ok(QuoteOrderReturnData::try_from_slice(&return_data)?)
+}
+
+#[tokio::test]
+async fn quote_order_previews_a_fill_across_levels() -> anyhow::Result<()> {
+    let (fixture, market, borrower) = new_book().await;
+
+    let quote: QuoteOrderReturnData = quote_bid(
+        &fixture,
+        &market,
+        &borrower,
+        2 * ORDER_BASE_ATOMS,
+        RATE_BPS + RATE_STEP_BPS,
+    )
+    .await?;
+    assert_eq!(quote.num_maker_orders, 2);
+    assert!(quote.base_atoms.abs_diff(2 * ORDER_BASE_ATOMS) <= 2);
+    assert!(quote.quote_atoms > 0);
+    assert!(
+        quote
+            .weighted_rate_bps
+            .abs_diff(RATE_BPS + RATE_STEP_BPS / 2)
+            <= 1
+    );
+    // Both ask levels, best rate first.
+    assert_eq!(quote.levels.len(), 2);
+    assert_eq!(quote.levels[0].rate_bps, RATE_BPS);
+    assert_eq!(quote.levels[1].rate_bps, RATE_BPS + RATE_STEP_BPS);
+    Ok(())
+}
+
+#[tokio::test]
+async fn quote_order_stops_at_the_limit_rate() -> anyhow::Result<()> {
+    let (fixture, market, borrower) = new_book().await;
+
+    let quote: QuoteOrderReturnData =
+        quote_bid(&fixture, &market, &borrower, 2 * ORDER_BASE_ATOMS, RATE_BPS).await?;
+    assert_eq!(quote.num_maker_orders, 1);
+    assert!(quote.base_atoms.abs_diff(ORDER_BASE_ATOMS) <= 1);
+    assert_eq!(quote.weighted_rate_bps, RATE_BPS);
+    Ok(())
+}
+
+#[tokio::test]
+async fn quote_order_leaves_the_market_unchanged() -> anyhow::Result<()> {
+    let (fixture, market, borrower) = new_book().await;
+    let market_before: Account = get_account(&fixture, &market.key).await;
+
+    // Sent for real rather than simulated, it still changes nothing.
+    send_nix_instruction(
+        &fixture,
+        &borrower,
+        NixInstruction::QuoteOrder,
+        quote_order_metas(&fixture, &market).await,
+        &QuoteOrderParams::new(
+            BaseAtoms::new(2 * ORDER_BASE_ATOMS),
+            Rate::from_bps(RATE_BPS + RATE_STEP_BPS),
+            true,
+            true,
+            0,
+        ),
+    )
+    .await?;
+    assert_eq!(get_account(&fixture, &market.key).await, market_before);
+    fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn quote_order_rejects_banks_of_the_other_tree() -> anyhow::Result<()> {
+    let (fixture, market, borrower) = new_book().await;
+    let mut accounts: Vec<AccountMeta> = quote_order_metas(&fixture, &market).await;
+    accounts.swap(1, 2);
+
+    assert_nix_error(
+        send_nix_instruction(
+            &fixture,
+            &borrower,
+            NixInstruction::QuoteOrder,
+            accounts,
+            &QuoteOrderParams::new(
+                BaseAtoms::new(ORDER_BASE_ATOMS),
+                Rate::from_bps(RATE_BPS),
+                true,
+                true,
+                0,
+            ),
+        )
+        .await,
+        NixError::InvalidMarginfiBank,
+    );
+    Ok(())
+}
@@ -0,0 +1,236 @@
+//! ReduceOrder, which takes collateral off a resting ask and leaves it where
+//! it is in the queue.
+
+use fixed::types::I80F48;
+use nix::{
+    program::{
+        get_dynamic_account, place_order::PlaceOrderParams, reduce_order::ReduceOrderParams,
+        NixError, NixInstruction,
+    },
+    quantities::{BaseAtoms, Rate, WrappedI80F48},
+    state::{MarketFixed, MarketRef, OrderType},
+};
+use solana_program::instruction::AccountMeta;
+use solana_program_test::BanksClientError;
+use solana_sdk::{account::Account, signature::Keypair, signer::Signer};
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{
+    assert_nix_error, get_account, place_order, send_nix_instruction, NixTestFixture, TradingMarket,
+};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+
+/// The fixture market with a funded lender and borrower.
+struct Traders {
+    fixture: NixTestFixture,
+    market: TradingMarket,
+    lender: Keypair,
+    borrower: Keypair,
+}
+
+async fn new_traders() -> Traders {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = TradingMarket {
+        key: fixture.market,
+        market_loans: fixture
+            .create_market_loan_account(&fixture.payer(), &fixture.market)
+            .await
+            .unwrap(),
+    };
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await
+        .unwrap();
+    Traders {
+        fixture,
+        market,
+        lender,
+        borrower,
+    }
+}
+
+async fn get_market_account(traders: &Traders) -> Account {
+    get_account(&traders.fixture, &traders.market.key).await
+}
+
+/// Rests an ask of ORDER_BASE_ATOMS from the lender and returns its
+/// sequence number.
+async fn place_ask(traders: &Traders) -> Result<u64, BanksClientError> {
+    let Traders {
+        fixture,
+        market,
+        lender,
+        ..
+    } = traders;
+    place_order(
+        fixture,
+        market,
+        lender,
+        PlaceOrderParams::new(
+            BaseAtoms::new(ORDER_BASE_ATOMS),
+            Rate::from_bps(RATE_BPS),
+            false,
+            true,
+            OrderType::PostOnly,
+        ),
+        market.ask_metas(fixture).await,
+    )
+    .await?;
+    let account: Account = get_market_account(traders).await;
+    Ok(get_dynamic_account::<MarketFixed>(&account.data)
+        .fixed
+        .get_base_a_order_sequence_number())
+}
+
+async fn reduce_order(
+    traders: &Traders,
+    trader: &Keypair,
+    order_sequence_number: u64,
+    collateral_shares_to_remove: I80F48,
+) -> Result<(), BanksClientError> {
+    send_nix_instruction(
+        &traders.fixture,
+        trader,
+        NixInstruction::ReduceOrder,
+        vec![
+            AccountMeta::new(trader.pubkey(), true),
+            AccountMeta::new(traders.market.key, false),
+        ],
+        &ReduceOrderParams {
+            trader_index_hint: None,
+            order_sequence_number,
+            order_index_hint: None,
+            use_a_tree: true,
+            collateral_shares_to_remove: WrappedI80F48::from(collateral_shares_to_remove),
+        },
+    )
+    .await
+}
+
+/// Collateral shares of the single ask level, and its number of orders.
+async fn get_ask_level(traders: &Traders) -> (I80F48, u16) {
+    let account: Account = get_market_account(traders).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    let (asks, num_asks) = market.get_book_levels(true, false, 8, 0, 0).unwrap();
+    assert_eq!(num_asks, 1);
+    (I80F48::from(asks[0].total_shares), asks[0].num_orders)
+}
+
+async fn get_lender_withdrawable_shares(traders: &Traders) -> I80F48 {
+    let account: Account = get_market_account(traders).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    I80F48::from(
+        market
+            .get_seat_snapshot(&traders.lender.pubkey())
+            .unwrap()
+            .base_a_withdrawable_asset_share,
+    )
+}
+
+#[tokio::test]
+async fn reduce_order_keeps_the_ask_in_front() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    let first_ask: u64 = place_ask(&traders).await?;
+    let second_ask: u64 = place_ask(&traders).await?;
+    let (level_shares, _) = get_ask_level(&traders).await;
+    let withdrawable_shares_before: I80F48 = get_lender_withdrawable_shares(&traders).await;
+
+    // Half of the first ask.
+    let shares_to_remove: I80F48 = level_shares / I80F48::from_num(4);
+    reduce_order(&traders, &traders.lender, first_ask, shares_to_remove).await?;
+    assert_eq!(
+        get_ask_level(&traders).await,
+        (level_shares - shares_to_remove, 2)
+    );
+    // The shares go back to the seat.
+    assert_eq!(
+        get_lender_withdrawable_shares(&traders).await,
+        withdrawable_shares_before + shares_to_remove
+    );
+
+    // A bid the size of one ask still fills the reduced one first and takes
+    // the rest from the second.
+    place_order(
+        &traders.fixture,
+        &traders.market,
+        &traders.borrower,
+        PlaceOrderParams::new(
+            BaseAtoms::new(ORDER_BASE_ATOMS),
+            Rate::from_bps(RATE_BPS),
+            true,
+            true,
+            OrderType::ImmediateOrCancel,
+        ),
+        traders.market.bid_metas(&traders.fixture).await,
+    )
+    .await?;
+    assert_eq!(get_ask_level(&traders).await.1, 1);
+    assert_nix_error(
+        reduce_order(&traders, &traders.lender, first_ask, I80F48::ONE).await,
+        NixError::InvalidReduce,
+    );
+    reduce_order(&traders, &traders.lender, second_ask, I80F48::ONE).await?;
+    traders.fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn reduce_order_leaves_something_on_the_ask() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    let ask: u64 = place_ask(&traders).await?;
+    let (order_shares, _) = get_ask_level(&traders).await;
+
+    // Removing everything is a cancel, and removing nothing is no reduce.
+    for shares_to_remove in [order_shares, I80F48::ZERO] {
+        assert_nix_error(
+            reduce_order(&traders, &traders.lender, ask, shares_to_remove).await,
+            NixError::InvalidReduce,
+        );
+    }
+    assert_eq!(get_ask_level(&traders).await, (order_shares, 1));
+    Ok(())
+}
+
+#[tokio::test]
+async fn reduce_order_only_takes_own_asks() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    let ask: u64 = place_ask(&traders).await?;
+
+    assert_nix_error(
+        reduce_order(&traders, &traders.borrower, ask, I80F48::ONE).await,
+        NixError::InvalidReduce,
+    );
+
+    place_order(
+        &traders.fixture,
+        &traders.market,
+        &traders.borrower,
+        PlaceOrderParams::new(
+            BaseAtoms::new(ORDER_BASE_ATOMS),
+            Rate::from_bps(RATE_BPS - 100),
+            true,
+            true,
+            OrderType::PostOnly,
+        ),
+        traders.market.bid_metas(&traders.fixture).await,
+    )
+    .await?;
+    let account: Account = get_market_account(&traders).await;
+    let bid: u64 = get_dynamic_account::<MarketFixed>(&account.data)
+        .fixed
+        .get_base_a_order_sequence_number();
+    assert_nix_error(
+        reduce_order(&traders, &traders.borrower, bid, I80F48::ONE).await,
+        NixError::InvalidReduce,
+    );
+    Ok(())
+}
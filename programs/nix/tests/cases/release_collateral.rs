@@ -0,0 +1,319 @@
+//! ReleaseCollateral, which lets a borrower withdraw the collateral of a loan
+//! that its liability at the LTV buffer of the market does not need.
+
+use fixed::types::I80F48;
+use nix::{
+    logs::ReleaseCollateralLog,
+    program::{
+        get_dynamic_account, place_order::PlaceOrderParams,
+        release_collateral::ReleaseCollateralParams, top_up_collateral::TopUpCollateralParams,
+        NixError, NixInstruction,
+    },
+    quantities::{BaseAtoms, Rate},
+    state::{ActiveLoan, MarketFixed, MarketLoansFixed, MarketLoansRef, MarketRef, OrderType},
+    validation::{get_market_signer_address, get_vault_address},
+};
+use solana_program::instruction::AccountMeta;
+use solana_program_test::BanksClientError;
+use solana_sdk::{account::Account, pubkey::Pubkey, signature::Keypair, signer::Signer};
+use test_utilities::{
+    bank::BankFixture,
+    test::{BankMint, TestSettings},
+};
+
+use crate::test_utils::{
+    assert_nix_error, deposit_metas, get_account, get_logs, oracle_metas, place_order,
+    send_nix_instruction, simulate_nix_log_data, NixTestFixture, TradingMarket,
+};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+const TOP_UP_ATOMS: u64 = 100_000_000;
+
+/// The fixture market with a loan from the lender to the borrower.
+struct Loan {
+    fixture: NixTestFixture,
+    market: TradingMarket,
+    lender: Keypair,
+    borrower: Keypair,
+    sequence_number: u64,
+}
+
+async fn open_loan() -> anyhow::Result<Loan> {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = TradingMarket {
+        key: fixture.market,
+        market_loans: fixture
+            .create_market_loan_account(&fixture.payer(), &fixture.market)
+            .await?,
+    };
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await?;
+    for (trader, is_bid, order_type, optional_accounts) in [
+        (
+            &lender,
+            false,
+            OrderType::PostOnly,
+            market.ask_metas(&fixture).await,
+        ),
+        (
+            &borrower,
+            true,
+            OrderType::ImmediateOrCancel,
+            market.bid_metas(&fixture).await,
+        ),
+    ] {
+        place_order(
+            &fixture,
+            &market,
+            trader,
+            PlaceOrderParams::new(
+                BaseAtoms::new(ORDER_BASE_ATOMS),
+                Rate::from_bps(RATE_BPS),
+                is_bid,
+                true,
+                order_type,
+            ),
+            optional_accounts,
+        )
+        .await?;
+    }
+    let mut loan: Loan = Loan {
+        fixture,
+        market,
+        lender,
+        borrower,
+        sequence_number: 0,
+    };
+    loan.sequence_number = get_loan(&loan).await.sequence_number;
+    Ok(loan)
+}
+
+/// The single loan of the borrower.
+async fn get_loan(loan: &Loan) -> ActiveLoan {
+    let market_account: Account = get_account(&loan.fixture, &loan.market.key).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&market_account.data);
+    let market_loans_account: Account = get_account(&loan.fixture, &loan.market.market_loans).await;
+    let loans: MarketLoansRef = get_dynamic_account::<MarketLoansFixed>(&market_loans_account.data);
+    let borrowed_loans: Vec<ActiveLoan> =
+        loans.get_borrowed_loans(market.get_trader_index(&loan.borrower.pubkey()));
+    assert_eq!(borrowed_loans.len(), 1);
+    borrowed_loans[0]
+}
+
+async fn get_token_balance(fixture: &NixTestFixture, token_account: &Pubkey) -> u64 {
+    let data: Vec<u8> = get_account(fixture, token_account).await.data;
+    // Amount sits at the same offset for token and token22 accounts.
+    u64::from_le_bytes(data[64..72].try_into().unwrap())
+}
+
+/// Deposits TOP_UP_ATOMS of base B into the collateral of the loan.
+async fn top_up_loan(loan: &Loan) -> Result<(), BanksClientError> {
+    let fixture: &NixTestFixture = &loan.fixture;
+    let mut accounts: Vec<AccountMeta> = deposit_metas(
+        fixture,
+        &loan.market,
+        &loan.borrower,
+        &fixture.base_b_bank_fixture,
+        &fixture.payer_base_b_fixture.key,
+        &fixture.base_b_token_program,
+    );
+    accounts.push(AccountMeta::new(loan.market.market_loans, false));
+    send_nix_instruction(
+        fixture,
+        &loan.borrower,
+        NixInstruction::TopUpCollateral,
+        accounts,
+        &TopUpCollateralParams {
+            amount: TOP_UP_ATOMS,
+            trader_index_hint: None,
+            sequence_number: loan.sequence_number,
+            use_a_tree: true,
+            market_loans: Some(loan.market.market_loans),
+        },
+    )
+    .await
+}
+
+/// ReleaseCollateral accounts of `trader` withdrawing from
+/// `collateral_bank` to `trader_token`.
+async fn release_metas(
+    loan: &Loan,
+    trader: &Keypair,
+    collateral_bank: &BankFixture,
+    trader_token: &Pubkey,
+    token_program: &Pubkey,
+    liability_bank: &BankFixture,
+) -> Vec<AccountMeta> {
+    let mint: Pubkey = collateral_bank.mint.key;
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(trader.pubkey(), true),
+        AccountMeta::new_readonly(loan.market.key, false),
+        AccountMeta::new(loan.market.market_loans, false),
+        AccountMeta::new_readonly(get_market_signer_address(&loan.market.key).0, false),
+        AccountMeta::new_readonly(mint, false),
+        AccountMeta::new(*trader_token, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new(get_vault_address(&loan.market.key, &mint).0, false),
+    ];
+    accounts.extend(
+        loan.market
+            .marginfi_cpi_metas(&loan.fixture, collateral_bank),
+    );
+    accounts.push(AccountMeta::new_readonly(liability_bank.key, false));
+    accounts.extend(oracle_metas(collateral_bank).await);
+    accounts.extend(oracle_metas(liability_bank).await);
+    accounts
+}
+
+/// The borrower's release of base B collateral, which backs loans of base A.
+async fn borrower_release_metas(loan: &Loan) -> Vec<AccountMeta> {
+    let fixture: &NixTestFixture = &loan.fixture;
+    release_metas(
+        loan,
+        &loan.borrower,
+        &fixture.base_b_bank_fixture,
+        &fixture.payer_base_b_fixture.key,
+        &fixture.base_b_token_program,
+        &fixture.base_a_bank_fixture,
+    )
+    .await
+}
+
+fn release_params(loan: &Loan, amount: u64) -> ReleaseCollateralParams {
+    ReleaseCollateralParams {
+        trader_index_hint: None,
+        loan_sequence_number: loan.sequence_number,
+        amount,
+    }
+}
+
+async fn release_collateral(
+    loan: &Loan,
+    trader: &Keypair,
+    accounts: Vec<AccountMeta>,
+    amount: u64,
+) -> Result<(), BanksClientError> {
+    send_nix_instruction(
+        &loan.fixture,
+        trader,
+        NixInstruction::ReleaseCollateral,
+        accounts,
+        &release_params(loan, amount),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn release_collateral_pays_out_the_excess() -> anyhow::Result<()> {
+    let loan: Loan = open_loan().await?;
+    let fixture: &NixTestFixture = &loan.fixture;
+    top_up_loan(&loan).await?;
+    let collateral_shares_before: I80F48 = I80F48::from(get_loan(&loan).await.collateral_shares);
+    let token_atoms_before: u64 =
+        get_token_balance(fixture, &fixture.payer_base_b_fixture.key).await;
+
+    let (result, log_data) = simulate_nix_log_data(
+        fixture,
+        &loan.borrower,
+        NixInstruction::ReleaseCollateral,
+        borrower_release_metas(&loan).await,
+        &release_params(&loan, TOP_UP_ATOMS / 2),
+    )
+    .await?;
+    assert_eq!(result, Ok(()));
+    let logs: Vec<ReleaseCollateralLog> = get_logs(&log_data);
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].loan_sequence_number, loan.sequence_number);
+    assert_eq!(logs[0].amount, TOP_UP_ATOMS / 2);
+    assert_eq!(logs[0].is_base_a.0, 0);
+    let released_shares: I80F48 = I80F48::from(logs[0].released_collateral_shares);
+    assert!(released_shares.is_positive());
+
+    release_collateral(
+        &loan,
+        &loan.borrower,
+        borrower_release_metas(&loan).await,
+        TOP_UP_ATOMS / 2,
+    )
+    .await?;
+    assert_eq!(
+        get_token_balance(fixture, &fixture.payer_base_b_fixture.key).await,
+        token_atoms_before + TOP_UP_ATOMS / 2
+    );
+    assert_eq!(
+        I80F48::from(get_loan(&loan).await.collateral_shares),
+        collateral_shares_before - released_shares
+    );
+    fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn release_collateral_keeps_the_loan_backed() -> anyhow::Result<()> {
+    let loan: Loan = open_loan().await?;
+    top_up_loan(&loan).await?;
+    let collateral_shares: I80F48 = I80F48::from(get_loan(&loan).await.collateral_shares);
+
+    // The top up is excess, the collateral the loan opened with is not.
+    assert_nix_error(
+        release_collateral(
+            &loan,
+            &loan.borrower,
+            borrower_release_metas(&loan).await,
+            TOP_UP_ATOMS * 2,
+        )
+        .await,
+        NixError::InvalidCollateralRelease,
+    );
+    assert_eq!(
+        I80F48::from(get_loan(&loan).await.collateral_shares),
+        collateral_shares
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn release_collateral_is_for_the_borrower_in_the_collateral_mint() -> anyhow::Result<()> {
+    let loan: Loan = open_loan().await?;
+    let fixture: &NixTestFixture = &loan.fixture;
+    top_up_loan(&loan).await?;
+
+    let lender_accounts: Vec<AccountMeta> = release_metas(
+        &loan,
+        &loan.lender,
+        &fixture.base_b_bank_fixture,
+        &fixture.second_keypair_base_b_fixture.key,
+        &fixture.base_b_token_program,
+        &fixture.base_a_bank_fixture,
+    )
+    .await;
+    assert_nix_error(
+        release_collateral(&loan, &loan.lender, lender_accounts, 1).await,
+        NixError::InvalidCollateralRelease,
+    );
+
+    // The loan lent base A, so base A is not its collateral.
+    let base_a_accounts: Vec<AccountMeta> = release_metas(
+        &loan,
+        &loan.borrower,
+        &fixture.base_a_bank_fixture,
+        &fixture.payer_base_a_fixture.key,
+        &fixture.base_a_token_program,
+        &fixture.base_b_bank_fixture,
+    )
+    .await;
+    assert_nix_error(
+        release_collateral(&loan, &loan.borrower, base_a_accounts, 1).await,
+        NixError::InvalidCollateralRelease,
+    );
+    Ok(())
+}
@@ -0,0 +1,192 @@
+//! Return data of PlaceOrder and CancelOrder, which programs that CPI into
+//! nix read instead of parsing the logs.
+
+use borsh::BorshDeserialize;
+use hypertree::NIL;
+use nix::{
+    program::{
+        cancel_order::{CancelOrderParams, CancelOrderReturnData},
+        get_dynamic_account,
+        place_order::{PlaceOrderParams, PlaceOrderReturnData},
+        NixInstruction,
+    },
+    quantities::{BaseAtoms, Rate},
+    state::{MarketFixed, MarketRef, OrderType},
+};
+use solana_program::{instruction::AccountMeta, system_program};
+use solana_sdk::{account::Account, signature::Keypair, signer::Signer};
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{
+    get_account, place_order_metas, send_nix_instruction, simulate_nix_instruction, NixTestFixture,
+    TradingMarket, VersionedPlaceOrderParams,
+};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+
+/// The fixture market with a funded lender and borrower.
+struct Traders {
+    fixture: NixTestFixture,
+    market: TradingMarket,
+    lender: Keypair,
+    borrower: Keypair,
+}
+
+async fn new_traders() -> Traders {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = TradingMarket {
+        key: fixture.market,
+        market_loans: fixture
+            .create_market_loan_account(&fixture.payer(), &fixture.market)
+            .await
+            .unwrap(),
+    };
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await
+        .unwrap();
+    Traders {
+        fixture,
+        market,
+        lender,
+        borrower,
+    }
+}
+
+fn order_params(is_bid: bool, order_type: OrderType) -> PlaceOrderParams {
+    PlaceOrderParams::new(
+        BaseAtoms::new(ORDER_BASE_ATOMS),
+        Rate::from_bps(RATE_BPS),
+        is_bid,
+        true,
+        order_type,
+    )
+}
+
+/// Places an order of `trader`, simulated first for its return data, which a
+/// sent transaction does not give back.
+async fn place_order(
+    traders: &Traders,
+    trader: &Keypair,
+    params: PlaceOrderParams,
+) -> anyhow::Result<PlaceOrderReturnData> {
+    let Traders {
+        fixture, market, ..
+    } = traders;
+    let mut accounts: Vec<AccountMeta> = place_order_metas(fixture, market, trader);
+    if params.is_bid {
+        accounts.extend(market.bid_metas(fixture).await);
+    } else {
+        accounts.extend(market.ask_metas(fixture).await);
+    }
+    let return_data: Vec<u8> = simulate_nix_instruction(
+        fixture,
+        trader,
+        NixInstruction::PlaceOrder,
+        accounts.clone(),
+        &VersionedPlaceOrderParams(&params),
+    )
+    .await?;
+    send_nix_instruction(
+        fixture,
+        trader,
+        NixInstruction::PlaceOrder,
+        accounts,
+        &VersionedPlaceOrderParams(&params),
+    )
+    .await?;
+    Ok(PlaceOrderReturnData::try_from_slice(&return_data)?)
+}
+
+#[tokio::test]
+async fn place_order_returns_the_resting_order() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+
+    let ask: PlaceOrderReturnData = place_order(
+        &traders,
+        &traders.lender,
+        order_params(false, OrderType::PostOnly),
+    )
+    .await?;
+    assert_ne!(ask.order_index, NIL);
+    assert_eq!(ask.base_atoms_traded, 0);
+    assert_eq!(ask.quote_atoms_traded, 0);
+
+    let account: Account = get_account(&traders.fixture, &traders.market.key).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    assert_eq!(
+        market
+            .get_order_by_index(ask.order_index)
+            .get_sequence_number(),
+        ask.order_sequence_number
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn place_order_returns_what_it_traded() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    let ask: PlaceOrderReturnData = place_order(
+        &traders,
+        &traders.lender,
+        order_params(false, OrderType::PostOnly),
+    )
+    .await?;
+
+    let bid: PlaceOrderReturnData = place_order(
+        &traders,
+        &traders.borrower,
+        order_params(true, OrderType::ImmediateOrCancel),
+    )
+    .await?;
+    // The whole ask was taken and nothing of the bid rests.
+    assert_eq!(bid.order_index, NIL);
+    assert_ne!(bid.order_sequence_number, ask.order_sequence_number);
+    assert_eq!(bid.base_atoms_traded, ORDER_BASE_ATOMS);
+    assert!(bid.quote_atoms_traded > 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn cancel_order_returns_the_cancelled_order() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    let ask: PlaceOrderReturnData = place_order(
+        &traders,
+        &traders.lender,
+        order_params(false, OrderType::PostOnly),
+    )
+    .await?;
+
+    let return_data: Vec<u8> = simulate_nix_instruction(
+        &traders.fixture,
+        &traders.lender,
+        NixInstruction::CancelOrder,
+        vec![
+            AccountMeta::new(traders.lender.pubkey(), true),
+            AccountMeta::new(traders.market.market_loans, false),
+            AccountMeta::new(traders.market.key, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        &CancelOrderParams {
+            trader_index_hint: None,
+            order_sequence_number: ask.order_sequence_number,
+            order_index_hint: None,
+            use_a_tree: true,
+            max_expired_orders_to_sweep: 0,
+        },
+    )
+    .await?;
+    let cancelled: CancelOrderReturnData = CancelOrderReturnData::try_from_slice(&return_data)?;
+    assert_eq!(cancelled.order_sequence_number, ask.order_sequence_number);
+    assert!(cancelled.cancelled_on_a_tree);
+    Ok(())
+}
@@ -0,0 +1,362 @@
+//! Reverse bids, which lend out what they borrow on the other tree at a
+//! spread the market bounds.
+
+use borsh::BorshDeserialize;
+use nix::{
+    program::{
+        create_market::CreateMarketParams,
+        get_dynamic_account,
+        modify_order::ModifyOrderParams,
+        place_order::{PlaceOrderParams, PlaceOrderReturnData},
+        NixError, NixInstruction,
+    },
+    quantities::{BaseAtoms, Rate},
+    state::{MarketFixed, MarketRef, OrderType, RestingOrder},
+};
+use solana_program::instruction::AccountMeta;
+use solana_program_test::BanksClientError;
+use solana_sdk::{account::Account, signature::Keypair, signer::Signer};
+use test_case::test_case;
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{
+    assert_nix_error, default_market_params, place_order, place_order_metas, send_nix_instruction,
+    simulate_nix_instruction, NixTestFixture, TradingMarket, VersionedPlaceOrderParams,
+};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+const MIN_REVERSE_SPREAD_BPS: u16 = 100;
+const MAX_REVERSE_SPREAD_BPS: u16 = 1_000;
+
+async fn new_fixture() -> NixTestFixture {
+    NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await
+}
+
+/// Market that takes reverse spreads from MIN_REVERSE_SPREAD_BPS to
+/// MAX_REVERSE_SPREAD_BPS, with the borrower seated and funded.
+async fn new_bounded_market(fixture: &NixTestFixture) -> (TradingMarket, Keypair) {
+    let market: TradingMarket = fixture
+        .create_market_with_params(CreateMarketParams {
+            min_reverse_spread_bps: MIN_REVERSE_SPREAD_BPS,
+            max_reverse_spread_bps: MAX_REVERSE_SPREAD_BPS,
+            ..default_market_params()
+        })
+        .await
+        .unwrap();
+    let (_lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await
+        .unwrap();
+    (market, borrower)
+}
+
+/// Orders resting on either side of either tree.
+async fn get_num_resting_levels(fixture: &NixTestFixture, market: &TradingMarket) -> u8 {
+    let account: Account = fixture.try_load(&market.key).await.unwrap().unwrap();
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    let mut num_levels: u8 = 0;
+    for use_a_tree in [true, false] {
+        for is_bid in [true, false] {
+            num_levels += market
+                .get_book_levels(use_a_tree, is_bid, 8, 0, 0)
+                .unwrap()
+                .1;
+        }
+    }
+    num_levels
+}
+
+fn reverse_bid_params(reverse_spread_bps: u16) -> PlaceOrderParams {
+    skewed_reverse_bid_params(reverse_spread_bps, None)
+}
+
+fn skewed_reverse_bid_params(
+    reverse_spread_bps: u16,
+    reverse_bid_spread_bps: Option<u16>,
+) -> PlaceOrderParams {
+    PlaceOrderParams {
+        reverse_spread_bps,
+        reverse_bid_spread_bps,
+        ..PlaceOrderParams::new(
+            BaseAtoms::new(ORDER_BASE_ATOMS),
+            Rate::from_bps(RATE_BPS),
+            true,
+            true,
+            OrderType::Reverse,
+        )
+    }
+}
+
+async fn place_reverse_bid(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    borrower: &Keypair,
+    params: PlaceOrderParams,
+) -> Result<(), BanksClientError> {
+    place_order(
+        fixture,
+        market,
+        borrower,
+        params,
+        market.bid_metas(fixture).await,
+    )
+    .await
+}
+
+/// Places a reverse bid like place_reverse_bid, simulated first for its
+/// return data, which points at the ask the bid flipped into.
+async fn place_returning_reverse_bid(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    borrower: &Keypair,
+    params: PlaceOrderParams,
+) -> anyhow::Result<PlaceOrderReturnData> {
+    let mut accounts: Vec<AccountMeta> = place_order_metas(fixture, market, borrower);
+    accounts.extend(market.bid_metas(fixture).await);
+    let return_data: Vec<u8> = simulate_nix_instruction(
+        fixture,
+        borrower,
+        NixInstruction::PlaceOrder,
+        accounts,
+        &VersionedPlaceOrderParams(&params),
+    )
+    .await?;
+    place_reverse_bid(fixture, market, borrower, params).await?;
+    Ok(PlaceOrderReturnData::try_from_slice(&return_data)?)
+}
+
+async fn get_resting_order(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    placed: &PlaceOrderReturnData,
+) -> RestingOrder {
+    let account: Account = fixture.try_load(&market.key).await.unwrap().unwrap();
+    *get_dynamic_account::<MarketFixed>(&account.data).get_order_by_index(placed.order_index)
+}
+
+async fn modify_order(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    borrower: &Keypair,
+    order_sequence_number: u64,
+    reverse_spread_bps: u16,
+    reverse_bid_spread_bps: Option<u16>,
+) -> Result<(), BanksClientError> {
+    send_nix_instruction(
+        fixture,
+        borrower,
+        NixInstruction::ModifyOrder,
+        vec![
+            AccountMeta::new_readonly(borrower.pubkey(), true),
+            AccountMeta::new(market.key, false),
+        ],
+        &ModifyOrderParams {
+            trader_index_hint: None,
+            order_sequence_number,
+            // The flipped order rests in the tree the bid was placed on.
+            use_a_tree: true,
+            reverse_spread_bps,
+            reverse_bid_spread_bps,
+            reverse_cycles_left: 0,
+        },
+    )
+    .await
+}
+
+#[test_case(200, 100 ; "min above max")]
+#[test_case(0, 10_000 ; "max of the whole rate")]
+#[tokio::test]
+async fn create_market_rejects_bad_reverse_spread_bounds(
+    min_reverse_spread_bps: u16,
+    max_reverse_spread_bps: u16,
+) -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    assert_nix_error(
+        fixture
+            .create_market_with_params(CreateMarketParams {
+                min_reverse_spread_bps,
+                max_reverse_spread_bps,
+                ..default_market_params()
+            })
+            .await
+            .map(|_| ()),
+        NixError::InvalidMarketParameters,
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn create_market_stores_reverse_spread_bounds() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let (market, _borrower) = new_bounded_market(&fixture).await;
+
+    let account: Account = fixture.try_load(&market.key).await?.unwrap();
+    let market_ref: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    assert_eq!(
+        market_ref.fixed.get_min_reverse_spread_bps(),
+        MIN_REVERSE_SPREAD_BPS
+    );
+    assert_eq!(
+        market_ref.fixed.get_max_reverse_spread_bps(),
+        MAX_REVERSE_SPREAD_BPS
+    );
+    Ok(())
+}
+
+#[test_case(MIN_REVERSE_SPREAD_BPS - 1 ; "below min")]
+#[test_case(MAX_REVERSE_SPREAD_BPS + 1 ; "above max")]
+#[tokio::test]
+async fn reverse_spread_outside_market_bounds_fails(reverse_spread_bps: u16) -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let (market, borrower) = new_bounded_market(&fixture).await;
+
+    assert_nix_error(
+        place_reverse_bid(
+            &fixture,
+            &market,
+            &borrower,
+            reverse_bid_params(reverse_spread_bps),
+        )
+        .await,
+        NixError::InvalidReverseSpread,
+    );
+    assert_eq!(get_num_resting_levels(&fixture, &market).await, 0);
+    Ok(())
+}
+
+#[test_case(MIN_REVERSE_SPREAD_BPS ; "at min")]
+#[test_case(MAX_REVERSE_SPREAD_BPS ; "at max")]
+#[tokio::test]
+async fn reverse_spread_within_market_bounds_rests(reverse_spread_bps: u16) -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let (market, borrower) = new_bounded_market(&fixture).await;
+
+    place_reverse_bid(
+        &fixture,
+        &market,
+        &borrower,
+        reverse_bid_params(reverse_spread_bps),
+    )
+    .await?;
+    assert_eq!(get_num_resting_levels(&fixture, &market).await, 1);
+    Ok(())
+}
+
+#[test_case(Some(MAX_REVERSE_SPREAD_BPS), MAX_REVERSE_SPREAD_BPS ; "skewed")]
+#[test_case(None, MIN_REVERSE_SPREAD_BPS ; "same as the ask side")]
+#[tokio::test]
+async fn reverse_bid_spread_rides_on_the_flipped_ask(
+    reverse_bid_spread_bps: Option<u16>,
+    expected_bid_spread_bps: u16,
+) -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let (market, borrower) = new_bounded_market(&fixture).await;
+
+    let placed: PlaceOrderReturnData = place_returning_reverse_bid(
+        &fixture,
+        &market,
+        &borrower,
+        skewed_reverse_bid_params(MIN_REVERSE_SPREAD_BPS, reverse_bid_spread_bps),
+    )
+    .await?;
+    let ask: RestingOrder = get_resting_order(&fixture, &market, &placed).await;
+    assert!(!ask.get_is_bid());
+    assert!(ask.is_reverse());
+    // The ask side spread priced the flip, the bid side one waits for the
+    // next.
+    assert_eq!(
+        ask.get_rate_bps() as u32,
+        RATE_BPS as u32 * (10_000 - MIN_REVERSE_SPREAD_BPS as u32) / 10_000
+    );
+    assert_eq!(ask.get_reverse_spread(), MIN_REVERSE_SPREAD_BPS);
+    assert_eq!(ask.get_reverse_bid_spread(), expected_bid_spread_bps);
+    Ok(())
+}
+
+#[test_case(MIN_REVERSE_SPREAD_BPS - 1 ; "below min")]
+#[test_case(MAX_REVERSE_SPREAD_BPS + 1 ; "above max")]
+#[tokio::test]
+async fn reverse_bid_spread_outside_market_bounds_fails(
+    reverse_bid_spread_bps: u16,
+) -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let (market, borrower) = new_bounded_market(&fixture).await;
+
+    assert_nix_error(
+        place_reverse_bid(
+            &fixture,
+            &market,
+            &borrower,
+            skewed_reverse_bid_params(MIN_REVERSE_SPREAD_BPS, Some(reverse_bid_spread_bps)),
+        )
+        .await,
+        NixError::InvalidReverseSpread,
+    );
+    assert_eq!(get_num_resting_levels(&fixture, &market).await, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn modify_order_sets_both_spreads() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = new_fixture().await;
+    let (market, borrower) = new_bounded_market(&fixture).await;
+    let placed: PlaceOrderReturnData = place_returning_reverse_bid(
+        &fixture,
+        &market,
+        &borrower,
+        reverse_bid_params(MIN_REVERSE_SPREAD_BPS),
+    )
+    .await?;
+    // The flipped order has a sequence number of its own.
+    let order_sequence_number: u64 = get_resting_order(&fixture, &market, &placed)
+        .await
+        .get_sequence_number();
+
+    modify_order(
+        &fixture,
+        &market,
+        &borrower,
+        order_sequence_number,
+        MAX_REVERSE_SPREAD_BPS,
+        Some(MIN_REVERSE_SPREAD_BPS),
+    )
+    .await?;
+    let ask: RestingOrder = get_resting_order(&fixture, &market, &placed).await;
+    assert_eq!(ask.get_reverse_spread(), MAX_REVERSE_SPREAD_BPS);
+    assert_eq!(ask.get_reverse_bid_spread(), MIN_REVERSE_SPREAD_BPS);
+
+    // Without a bid side spread both sides take the ask side one.
+    modify_order(
+        &fixture,
+        &market,
+        &borrower,
+        order_sequence_number,
+        MAX_REVERSE_SPREAD_BPS,
+        None,
+    )
+    .await?;
+    let ask: RestingOrder = get_resting_order(&fixture, &market, &placed).await;
+    assert_eq!(ask.get_reverse_bid_spread(), MAX_REVERSE_SPREAD_BPS);
+
+    assert_nix_error(
+        modify_order(
+            &fixture,
+            &market,
+            &borrower,
+            order_sequence_number,
+            MAX_REVERSE_SPREAD_BPS,
+            Some(MAX_REVERSE_SPREAD_BPS + 1),
+        )
+        .await,
+        NixError::InvalidReverseSpread,
+    );
+    Ok(())
+}
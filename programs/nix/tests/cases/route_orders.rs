@@ -0,0 +1,274 @@
+//! RouteOrders, which places two orders, usually on different markets, that
+//! both go through or both fail.
+
+use borsh::BorshDeserialize;
+use hypertree::NIL;
+use nix::{
+    logs::RouteOrdersLog,
+    program::{
+        get_dynamic_account,
+        place_order::{PlaceOrderParams, PlaceOrderReturnData},
+        route_orders::{RouteOrdersParams, RouteOrdersReturnData},
+        NixError, NixInstruction,
+    },
+    quantities::{BaseAtoms, Rate},
+    state::{MarketFixed, MarketRef, OrderType},
+};
+use solana_program::instruction::AccountMeta;
+use solana_program_test::BanksClientError;
+use solana_sdk::{account::Account, signature::Keypair, signer::Signer};
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{
+    assert_nix_error, default_market_params, get_account, get_logs, place_order, place_order_metas,
+    send_nix_instruction, simulate_nix_instruction, simulate_nix_log_data, NixTestFixture,
+    TradingMarket,
+};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+
+/// The fixture market and a second market of the same mints, with the lender
+/// and the borrower funded on both.
+struct Traders {
+    fixture: NixTestFixture,
+    first_market: TradingMarket,
+    second_market: TradingMarket,
+    lender: Keypair,
+    borrower: Keypair,
+}
+
+async fn new_traders() -> anyhow::Result<Traders> {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let first_market: TradingMarket = TradingMarket {
+        key: fixture.market,
+        market_loans: fixture
+            .create_market_loan_account(&fixture.payer(), &fixture.market)
+            .await?,
+    };
+    let second_market: TradingMarket = fixture
+        .create_market_with_params(default_market_params())
+        .await?;
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(
+            &first_market,
+            LENDER_DEPOSIT_ATOMS,
+            BORROWER_COLLATERAL_ATOMS,
+        )
+        .await?;
+    fixture
+        .seat_lender_and_borrower(
+            &second_market,
+            LENDER_DEPOSIT_ATOMS,
+            BORROWER_COLLATERAL_ATOMS,
+        )
+        .await?;
+    Ok(Traders {
+        fixture,
+        first_market,
+        second_market,
+        lender,
+        borrower,
+    })
+}
+
+fn order_params(is_bid: bool) -> PlaceOrderParams {
+    PlaceOrderParams::new(
+        BaseAtoms::new(ORDER_BASE_ATOMS),
+        Rate::from_bps(RATE_BPS),
+        is_bid,
+        true,
+        OrderType::PostOnly,
+    )
+}
+
+/// PlaceOrder accounts of a post only ask of `trader` on `market`.
+async fn ask_leg_metas(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    trader: &Keypair,
+) -> Vec<AccountMeta> {
+    let mut accounts: Vec<AccountMeta> = place_order_metas(fixture, market, trader);
+    accounts.extend(market.ask_metas(fixture).await);
+    accounts
+}
+
+/// Post only asks of the lender on both markets, the accounts and the params
+/// of RouteOrders.
+async fn route_asks(traders: &Traders) -> (Vec<AccountMeta>, RouteOrdersParams) {
+    let Traders {
+        fixture,
+        first_market,
+        second_market,
+        lender,
+        ..
+    } = traders;
+    let mut accounts: Vec<AccountMeta> = ask_leg_metas(fixture, first_market, lender).await;
+    let first_leg_num_accounts: u8 = accounts.len() as u8;
+    accounts.extend(ask_leg_metas(fixture, second_market, lender).await);
+    (
+        accounts,
+        RouteOrdersParams {
+            first_leg_num_accounts,
+            first_leg: order_params(false),
+            second_leg: order_params(false),
+        },
+    )
+}
+
+async fn route_orders(
+    traders: &Traders,
+    accounts: Vec<AccountMeta>,
+    params: &RouteOrdersParams,
+) -> Result<(), BanksClientError> {
+    send_nix_instruction(
+        &traders.fixture,
+        &traders.lender,
+        NixInstruction::RouteOrders,
+        accounts,
+        params,
+    )
+    .await
+}
+
+async fn get_num_asks(fixture: &NixTestFixture, market: &TradingMarket) -> u16 {
+    let account: Account = get_account(fixture, &market.key).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    let (asks, num_asks) = market.get_book_levels(true, false, 8, 0, 0).unwrap();
+    if num_asks == 0 {
+        return 0;
+    }
+    asks[0].num_orders
+}
+
+async fn get_order_sequence_number(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    leg: &PlaceOrderReturnData,
+) -> u64 {
+    let account: Account = get_account(fixture, &market.key).await;
+    get_dynamic_account::<MarketFixed>(&account.data)
+        .get_order_by_index(leg.order_index)
+        .get_sequence_number()
+}
+
+#[tokio::test]
+async fn route_orders_places_both_legs() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await?;
+    let fixture: &NixTestFixture = &traders.fixture;
+    let (accounts, params) = route_asks(&traders).await;
+
+    let return_data: Vec<u8> = simulate_nix_instruction(
+        fixture,
+        &traders.lender,
+        NixInstruction::RouteOrders,
+        accounts.clone(),
+        &params,
+    )
+    .await?;
+    let legs: RouteOrdersReturnData = RouteOrdersReturnData::try_from_slice(&return_data)?;
+    let (result, log_data) = simulate_nix_log_data(
+        fixture,
+        &traders.lender,
+        NixInstruction::RouteOrders,
+        accounts.clone(),
+        &params,
+    )
+    .await?;
+    assert_eq!(result, Ok(()));
+    let logs: Vec<RouteOrdersLog> = get_logs(&log_data);
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].first_market, traders.first_market.key);
+    assert_eq!(logs[0].second_market, traders.second_market.key);
+    assert_eq!(logs[0].trader, traders.lender.pubkey());
+    assert_eq!(
+        logs[0].first_order_sequence_number,
+        legs.first_leg.order_sequence_number
+    );
+    assert_eq!(
+        logs[0].second_order_sequence_number,
+        legs.second_leg.order_sequence_number
+    );
+
+    route_orders(&traders, accounts, &params).await?;
+    // Both asks rest, one on each market.
+    for (market, leg) in [
+        (&traders.first_market, &legs.first_leg),
+        (&traders.second_market, &legs.second_leg),
+    ] {
+        assert_ne!(leg.order_index, NIL);
+        assert_eq!(leg.base_atoms_traded, 0);
+        assert_eq!(get_num_asks(fixture, market).await, 1);
+        assert_eq!(
+            get_order_sequence_number(fixture, market, leg).await,
+            leg.order_sequence_number
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn route_orders_fails_both_legs_with_either() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await?;
+    let fixture: &NixTestFixture = &traders.fixture;
+    // A resting bid on the second market that its post only ask would cross.
+    place_order(
+        fixture,
+        &traders.second_market,
+        &traders.borrower,
+        order_params(true),
+        traders.second_market.bid_metas(fixture).await,
+    )
+    .await?;
+
+    let (accounts, params) = route_asks(&traders).await;
+    assert_nix_error(
+        route_orders(&traders, accounts, &params).await,
+        NixError::PostOnlyCrosses,
+    );
+    // The first leg went through on its own but is undone with the second.
+    assert_eq!(get_num_asks(fixture, &traders.first_market).await, 0);
+    assert_eq!(get_num_asks(fixture, &traders.second_market).await, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn route_orders_needs_two_legs_of_one_payer() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await?;
+    let fixture: &NixTestFixture = &traders.fixture;
+    let (accounts, params) = route_asks(&traders).await;
+
+    for first_leg_num_accounts in [0, accounts.len() as u8] {
+        assert_nix_error(
+            route_orders(
+                &traders,
+                accounts.clone(),
+                &RouteOrdersParams {
+                    first_leg_num_accounts,
+                    first_leg: order_params(false),
+                    second_leg: order_params(false),
+                },
+            )
+            .await,
+            NixError::MissingAccounts,
+        );
+    }
+
+    // The borrower starts the second leg, without signing.
+    let mut other_payer_accounts: Vec<AccountMeta> = accounts;
+    other_payer_accounts[params.first_leg_num_accounts as usize] =
+        AccountMeta::new(traders.borrower.pubkey(), false);
+    assert_nix_error(
+        route_orders(&traders, other_payer_accounts, &params).await,
+        NixError::InvalidPlaceOrderParams,
+    );
+    assert_eq!(get_num_asks(fixture, &traders.first_market).await, 0);
+    Ok(())
+}
@@ -0,0 +1,230 @@
+//! SettleLoanProceeds, which closes a repaid loan and credits the lender and
+//! the borrower with what it owes each of them.
+//!
+//! Repaying is not an instruction of the program yet, so the tests mark the
+//! loan repaid by rewriting it on the loans page.
+
+use fixed::types::I80F48;
+use nix::{
+    logs::SettleLoanProceedsLog,
+    program::{
+        get_dynamic_account, settle_loan_proceeds::SettleLoanProceedsParams, NixError,
+        NixInstruction,
+    },
+    state::{
+        ActiveLoan, LoanStatus, MarketFixed, MarketLoansFixed, MarketLoansRef, MarketRef,
+        SeatSnapshot, MARKET_LOANS_FIXED_SIZE,
+    },
+};
+use solana_program::instruction::AccountMeta;
+use solana_program_test::BanksClientError;
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    signature::Keypair,
+    signer::Signer,
+};
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{
+    assert_nix_error, get_account, get_logs, open_loan, send_nix_instruction,
+    simulate_nix_log_data, NixTestFixture, TradingMarket,
+};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+
+/// The fixture market with a loan from the lender to the borrower.
+struct Loan {
+    fixture: NixTestFixture,
+    market: TradingMarket,
+    lender: Keypair,
+    borrower: Keypair,
+    loan: ActiveLoan,
+}
+
+async fn new_loan() -> anyhow::Result<Loan> {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = TradingMarket {
+        key: fixture.market,
+        market_loans: fixture
+            .create_market_loan_account(&fixture.payer(), &fixture.market)
+            .await?,
+    };
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await?;
+    open_loan(
+        &fixture,
+        &market,
+        &lender,
+        &borrower,
+        ORDER_BASE_ATOMS,
+        RATE_BPS,
+    )
+    .await?;
+    let loans: Vec<ActiveLoan> = get_borrowed_loans(&fixture, &market, &borrower).await;
+    assert_eq!(loans.len(), 1);
+    Ok(Loan {
+        fixture,
+        market,
+        lender,
+        borrower,
+        loan: loans[0],
+    })
+}
+
+async fn get_borrowed_loans(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    borrower: &Keypair,
+) -> Vec<ActiveLoan> {
+    let market_account: Account = get_account(fixture, &market.key).await;
+    let market_ref: MarketRef = get_dynamic_account::<MarketFixed>(&market_account.data);
+    let market_loans_account: Account = get_account(fixture, &market.market_loans).await;
+    let loans: MarketLoansRef = get_dynamic_account::<MarketLoansFixed>(&market_loans_account.data);
+    loans.get_borrowed_loans(market_ref.get_trader_index(&borrower.pubkey()))
+}
+
+/// The loan as it is on its page, whatever its status.
+async fn get_page_loan(loan: &Loan) -> Option<ActiveLoan> {
+    let account: Account = get_account(&loan.fixture, &loan.market.market_loans).await;
+    get_dynamic_account::<MarketLoansFixed>(&account.data).get_loan(loan.loan.sequence_number)
+}
+
+async fn get_seat(loan: &Loan, trader: &Keypair) -> SeatSnapshot {
+    let account: Account = get_account(&loan.fixture, &loan.market.key).await;
+    get_dynamic_account::<MarketFixed>(&account.data)
+        .get_seat_snapshot(&trader.pubkey())
+        .unwrap()
+}
+
+/// Rewrites the loan on its page as repaid, as if the borrower had paid it
+/// back.
+async fn mark_repaid(loan: &Loan) {
+    let mut account: Account = get_account(&loan.fixture, &loan.market.market_loans).await;
+    let loan_bytes: &[u8] = bytemuck::bytes_of(&loan.loan);
+    let loan_offset: usize = MARKET_LOANS_FIXED_SIZE
+        + account.data[MARKET_LOANS_FIXED_SIZE..]
+            .windows(loan_bytes.len())
+            .position(|window: &[u8]| window == loan_bytes)
+            .expect("Loan not found on its page");
+    let mut repaid_loan: ActiveLoan = loan.loan;
+    repaid_loan.status = LoanStatus::Repaid;
+    account.data[loan_offset..loan_offset + loan_bytes.len()]
+        .copy_from_slice(bytemuck::bytes_of(&repaid_loan));
+    loan.fixture
+        .context
+        .borrow_mut()
+        .set_account(&loan.market.market_loans, &AccountSharedData::from(account));
+}
+
+fn settle_metas(loan: &Loan) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(loan.lender.pubkey(), true),
+        AccountMeta::new(loan.market.key, false),
+        AccountMeta::new(loan.market.market_loans, false),
+    ]
+}
+
+async fn settle_loan_proceeds(
+    loan: &Loan,
+    loan_sequence_number: u64,
+) -> Result<(), BanksClientError> {
+    send_nix_instruction(
+        &loan.fixture,
+        &loan.lender,
+        NixInstruction::SettleLoanProceeds,
+        settle_metas(loan),
+        &SettleLoanProceedsParams {
+            loan_sequence_number,
+        },
+    )
+    .await
+}
+
+#[tokio::test]
+async fn settle_loan_proceeds_credits_both_sides() -> anyhow::Result<()> {
+    let loan: Loan = new_loan().await?;
+    mark_repaid(&loan).await;
+    let lender_before: SeatSnapshot = get_seat(&loan, &loan.lender).await;
+    let borrower_before: SeatSnapshot = get_seat(&loan, &loan.borrower).await;
+
+    // Anyone can settle, the lender does here.
+    let (result, log_data) = simulate_nix_log_data(
+        &loan.fixture,
+        &loan.lender,
+        NixInstruction::SettleLoanProceeds,
+        settle_metas(&loan),
+        &SettleLoanProceedsParams {
+            loan_sequence_number: loan.loan.sequence_number,
+        },
+    )
+    .await?;
+    assert_eq!(result, Ok(()));
+    let logs: Vec<SettleLoanProceedsLog> = get_logs(&log_data);
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].market, loan.market.key);
+    assert_eq!(logs[0].market_loans, loan.market.market_loans);
+    assert_eq!(logs[0].lender, loan.lender.pubkey());
+    assert_eq!(logs[0].borrower, loan.borrower.pubkey());
+    assert_eq!(logs[0].loan_sequence_number, loan.loan.sequence_number);
+
+    settle_loan_proceeds(&loan, loan.loan.sequence_number).await?;
+    assert!(get_page_loan(&loan).await.is_none());
+
+    let liability_shares: I80F48 = I80F48::from(loan.loan.liability_shares);
+    let collateral_shares: I80F48 = I80F48::from(loan.loan.collateral_shares);
+    let lender_after: SeatSnapshot = get_seat(&loan, &loan.lender).await;
+    let borrower_after: SeatSnapshot = get_seat(&loan, &loan.borrower).await;
+    // The loan lent base A against base B collateral.
+    assert_eq!(loan.loan.is_liability_base_a.0, 1);
+    assert_eq!(
+        I80F48::from(lender_after.base_a_withdrawable_asset_share),
+        I80F48::from(lender_before.base_a_withdrawable_asset_share) + liability_shares
+    );
+    assert_eq!(
+        I80F48::from(borrower_after.base_b_withdrawable_asset_share),
+        I80F48::from(borrower_before.base_b_withdrawable_asset_share) + collateral_shares
+    );
+    assert_eq!(
+        I80F48::from(borrower_after.base_a_liability_shares),
+        I80F48::from(borrower_before.base_a_liability_shares) - liability_shares
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn settle_loan_proceeds_needs_a_repaid_loan() -> anyhow::Result<()> {
+    let loan: Loan = new_loan().await?;
+
+    assert_nix_error(
+        settle_loan_proceeds(&loan, loan.loan.sequence_number).await,
+        NixError::LoanNotRepaid,
+    );
+    assert_eq!(
+        get_page_loan(&loan)
+            .await
+            .map(|page_loan: ActiveLoan| page_loan.status),
+        Some(LoanStatus::Active)
+    );
+
+    mark_repaid(&loan).await;
+    assert_nix_error(
+        settle_loan_proceeds(&loan, loan.loan.sequence_number + 1).await,
+        NixError::LoanNotRepaid,
+    );
+    settle_loan_proceeds(&loan, loan.loan.sequence_number).await?;
+    // Settled once, the loan is gone.
+    assert_nix_error(
+        settle_loan_proceeds(&loan, loan.loan.sequence_number).await,
+        NixError::LoanNotRepaid,
+    );
+    Ok(())
+}
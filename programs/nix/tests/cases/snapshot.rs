@@ -0,0 +1,29 @@
+use solana_sdk::signature::Keypair;
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{FixtureSnapshot, NixTestFixture};
+
+#[tokio::test]
+async fn restore_undoes_changes_after_snapshot() -> anyhow::Result<()> {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let payer_keypair: Keypair = fixture.payer_keypair();
+    let snapshot: FixtureSnapshot = fixture.snapshot(&[]).await;
+
+    fixture.claim_seat_for_keypair(&payer_keypair).await?;
+    // Claiming the same seat twice fails.
+    assert!(fixture
+        .claim_seat_for_keypair(&payer_keypair)
+        .await
+        .is_err());
+
+    // Back to before the seat was claimed, so it can be claimed again.
+    fixture.restore(&snapshot).await;
+    fixture.claim_seat_for_keypair(&payer_keypair).await?;
+    fixture.verify_market().await;
+    Ok(())
+}
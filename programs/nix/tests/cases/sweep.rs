@@ -0,0 +1,160 @@
+//! A bid that fills several maker asks in one instruction, opening a loan
+//! per maker.
+
+use fixed::types::I80F48;
+use marginfi::state::marginfi_group::Bank;
+use nix::{
+    program::{get_dynamic_account, place_order::PlaceOrderParams},
+    quantities::{BaseAtoms, Rate},
+    state::{
+        ActiveLoan, MarketFixed, MarketLoansFixed, MarketLoansRef, MarketRef, OrderType,
+        SeatSnapshot,
+    },
+};
+use solana_sdk::{account::Account, signature::Keypair, signer::Signer};
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{get_account, place_order, NixTestFixture, TradingMarket};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const NUM_ASKS: u64 = 3;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+
+/// The fixture market with a funded lender and borrower.
+struct Traders {
+    fixture: NixTestFixture,
+    market: TradingMarket,
+    lender: Keypair,
+    borrower: Keypair,
+}
+
+async fn new_traders() -> Traders {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = TradingMarket {
+        key: fixture.market,
+        market_loans: fixture
+            .create_market_loan_account(&fixture.payer(), &fixture.market)
+            .await
+            .unwrap(),
+    };
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await
+        .unwrap();
+    Traders {
+        fixture,
+        market,
+        lender,
+        borrower,
+    }
+}
+
+fn order_params(num_base_atoms: u64, is_bid: bool, order_type: OrderType) -> PlaceOrderParams {
+    PlaceOrderParams::new(
+        BaseAtoms::new(num_base_atoms),
+        Rate::from_bps(RATE_BPS),
+        is_bid,
+        true,
+        order_type,
+    )
+}
+
+/// Rests NUM_ASKS asks of ORDER_BASE_ATOMS from the lender and takes them
+/// all with one bid from the borrower.
+async fn sweep_asks(traders: &Traders) -> anyhow::Result<()> {
+    let Traders {
+        fixture,
+        market,
+        lender,
+        borrower,
+    } = traders;
+    for _ in 0..NUM_ASKS {
+        place_order(
+            fixture,
+            market,
+            lender,
+            order_params(ORDER_BASE_ATOMS, false, OrderType::PostOnly),
+            market.ask_metas(fixture).await,
+        )
+        .await?;
+    }
+    place_order(
+        fixture,
+        market,
+        borrower,
+        order_params(
+            NUM_ASKS * ORDER_BASE_ATOMS,
+            true,
+            OrderType::ImmediateOrCancel,
+        ),
+        market.bid_metas(fixture).await,
+    )
+    .await?;
+    Ok(())
+}
+
+async fn get_borrowed_loans(traders: &Traders) -> Vec<ActiveLoan> {
+    let fixture: &NixTestFixture = &traders.fixture;
+    let market_account: Account = get_account(fixture, &traders.market.key).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&market_account.data);
+    let market_loans_account: Account = get_account(fixture, &traders.market.market_loans).await;
+    let loans: MarketLoansRef = get_dynamic_account::<MarketLoansFixed>(&market_loans_account.data);
+    loans.get_borrowed_loans(market.get_trader_index(&traders.borrower.pubkey()))
+}
+
+#[tokio::test]
+async fn sweep_prices_every_maker_at_the_same_share_values() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    sweep_asks(&traders).await?;
+
+    let loans: Vec<ActiveLoan> = get_borrowed_loans(&traders).await;
+    assert_eq!(loans.len(), NUM_ASKS as usize);
+    // Equal makers filled within one instruction see the same bank, so
+    // every loan carries the same shares.
+    for loan in loans.iter() {
+        assert_eq!(
+            I80F48::from(loan.liability_shares),
+            I80F48::from(loans[0].liability_shares)
+        );
+        assert_eq!(
+            I80F48::from(loan.collateral_shares),
+            I80F48::from(loans[0].collateral_shares)
+        );
+    }
+
+    // And those shares are the borrowed atoms at the bank's share value.
+    let base_bank: Bank = traders.fixture.base_a_bank_fixture.load().await;
+    let liability_atoms: I80F48 = I80F48::from(loans[0].liability_shares)
+        .checked_mul(I80F48::from(base_bank.liability_share_value))
+        .unwrap();
+    assert!(
+        (liability_atoms - I80F48::from_num(ORDER_BASE_ATOMS)).abs() <= I80F48::ONE,
+        "loan of {} atoms, expected {}",
+        liability_atoms,
+        ORDER_BASE_ATOMS
+    );
+
+    // The seat owes what the loans add up to.
+    let market_account: Account = get_account(&traders.fixture, &traders.market.key).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&market_account.data);
+    let seat: SeatSnapshot = market
+        .get_seat_snapshot(&traders.borrower.pubkey())
+        .unwrap();
+    let total_liability_shares: I80F48 = loans
+        .iter()
+        .map(|loan: &ActiveLoan| I80F48::from(loan.liability_shares))
+        .sum();
+    assert_eq!(
+        I80F48::from(seat.base_a_liability_shares),
+        total_liability_shares
+    );
+    traders.fixture.verify_market().await;
+    Ok(())
+}
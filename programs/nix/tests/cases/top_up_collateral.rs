@@ -0,0 +1,365 @@
+//! TopUpCollateral, which deposits straight into the collateral of a resting
+//! bid or an active loan of the payer instead of into the seat.
+
+use borsh::BorshDeserialize;
+use fixed::types::I80F48;
+use nix::{
+    logs::TopUpCollateralLog,
+    program::{
+        get_dynamic_account,
+        place_order::{PlaceOrderParams, PlaceOrderReturnData},
+        top_up_collateral::TopUpCollateralParams,
+        NixError, NixInstruction,
+    },
+    quantities::{BaseAtoms, Rate},
+    state::{
+        ActiveLoan, MarketFixed, MarketLoansFixed, MarketLoansRef, MarketRef, OrderType,
+        SeatSnapshot,
+    },
+};
+use solana_program::instruction::AccountMeta;
+use solana_program_test::BanksClientError;
+use solana_sdk::{account::Account, pubkey::Pubkey, signature::Keypair, signer::Signer};
+use test_utilities::{
+    bank::BankFixture,
+    test::{BankMint, TestSettings},
+};
+
+use crate::test_utils::{
+    assert_nix_error, deposit_metas, get_account, get_logs, place_order, place_order_metas,
+    send_nix_instruction, simulate_nix_instruction, simulate_nix_log_data, NixTestFixture,
+    TradingMarket, VersionedPlaceOrderParams,
+};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+const TOP_UP_ATOMS: u64 = 100_000_000;
+
+/// The fixture market with a funded lender and borrower.
+struct Traders {
+    fixture: NixTestFixture,
+    market: TradingMarket,
+    lender: Keypair,
+    borrower: Keypair,
+}
+
+async fn new_traders() -> Traders {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = TradingMarket {
+        key: fixture.market,
+        market_loans: fixture
+            .create_market_loan_account(&fixture.payer(), &fixture.market)
+            .await
+            .unwrap(),
+    };
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await
+        .unwrap();
+    Traders {
+        fixture,
+        market,
+        lender,
+        borrower,
+    }
+}
+
+fn order_params(is_bid: bool, order_type: OrderType) -> PlaceOrderParams {
+    PlaceOrderParams::new(
+        BaseAtoms::new(ORDER_BASE_ATOMS),
+        Rate::from_bps(RATE_BPS),
+        is_bid,
+        true,
+        order_type,
+    )
+}
+
+/// Rests a bid from the borrower and returns where it rests. Simulated
+/// first for its return data, which a sent transaction does not give back.
+async fn rest_bid(traders: &Traders) -> anyhow::Result<PlaceOrderReturnData> {
+    let Traders {
+        fixture,
+        market,
+        borrower,
+        ..
+    } = traders;
+    let params: PlaceOrderParams = order_params(true, OrderType::PostOnly);
+    let mut accounts: Vec<AccountMeta> = place_order_metas(fixture, market, borrower);
+    accounts.extend(market.bid_metas(fixture).await);
+    let return_data: Vec<u8> = simulate_nix_instruction(
+        fixture,
+        borrower,
+        NixInstruction::PlaceOrder,
+        accounts,
+        &VersionedPlaceOrderParams(&params),
+    )
+    .await?;
+    place_order(
+        fixture,
+        market,
+        borrower,
+        params,
+        market.bid_metas(fixture).await,
+    )
+    .await?;
+    Ok(PlaceOrderReturnData::try_from_slice(&return_data)?)
+}
+
+/// Rests an ask from the lender and takes it with a bid from the borrower,
+/// and returns the loan that opens.
+async fn open_loan(traders: &Traders) -> anyhow::Result<ActiveLoan> {
+    let Traders {
+        fixture,
+        market,
+        lender,
+        borrower,
+    } = traders;
+    place_order(
+        fixture,
+        market,
+        lender,
+        order_params(false, OrderType::PostOnly),
+        market.ask_metas(fixture).await,
+    )
+    .await?;
+    place_order(
+        fixture,
+        market,
+        borrower,
+        order_params(true, OrderType::ImmediateOrCancel),
+        market.bid_metas(fixture).await,
+    )
+    .await?;
+    let loans: Vec<ActiveLoan> = get_borrowed_loans(traders).await;
+    assert_eq!(loans.len(), 1);
+    Ok(loans[0])
+}
+
+async fn get_borrowed_loans(traders: &Traders) -> Vec<ActiveLoan> {
+    let market_account: Account = get_account(&traders.fixture, &traders.market.key).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&market_account.data);
+    let market_loans_account: Account =
+        get_account(&traders.fixture, &traders.market.market_loans).await;
+    let loans: MarketLoansRef = get_dynamic_account::<MarketLoansFixed>(&market_loans_account.data);
+    loans.get_borrowed_loans(market.get_trader_index(&traders.borrower.pubkey()))
+}
+
+async fn get_order_collateral_shares(traders: &Traders, order_index: u32) -> I80F48 {
+    let account: Account = get_account(&traders.fixture, &traders.market.key).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    I80F48::from(
+        market
+            .get_order_by_index(order_index)
+            .get_collateral_shares(),
+    )
+}
+
+async fn get_borrower_seat(traders: &Traders) -> SeatSnapshot {
+    let account: Account = get_account(&traders.fixture, &traders.market.key).await;
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    market
+        .get_seat_snapshot(&traders.borrower.pubkey())
+        .unwrap()
+}
+
+/// TopUpCollateral accounts of the borrower depositing into `bank`, with the
+/// loans page appended when the params name one.
+fn top_up_metas(
+    traders: &Traders,
+    bank: &BankFixture,
+    params: &TopUpCollateralParams,
+) -> Vec<AccountMeta> {
+    let fixture: &NixTestFixture = &traders.fixture;
+    let (trader_token, token_program) = if bank.key == fixture.base_a_bank_fixture.key {
+        (
+            fixture.payer_base_a_fixture.key,
+            fixture.base_a_token_program,
+        )
+    } else {
+        (
+            fixture.payer_base_b_fixture.key,
+            fixture.base_b_token_program,
+        )
+    };
+    let mut accounts: Vec<AccountMeta> = deposit_metas(
+        fixture,
+        &traders.market,
+        &traders.borrower,
+        bank,
+        &trader_token,
+        &token_program,
+    );
+    if let Some(market_loans) = params.market_loans {
+        accounts.push(AccountMeta::new(market_loans, false));
+    }
+    accounts
+}
+
+async fn top_up_collateral(
+    traders: &Traders,
+    bank: &BankFixture,
+    params: TopUpCollateralParams,
+) -> Result<(), BanksClientError> {
+    send_nix_instruction(
+        &traders.fixture,
+        &traders.borrower,
+        NixInstruction::TopUpCollateral,
+        top_up_metas(traders, bank, &params),
+        &params,
+    )
+    .await
+}
+
+/// Simulates a top up that has to succeed and returns its log.
+async fn simulate_top_up_collateral(
+    traders: &Traders,
+    bank: &BankFixture,
+    params: TopUpCollateralParams,
+) -> anyhow::Result<TopUpCollateralLog> {
+    let (result, log_data) = simulate_nix_log_data(
+        &traders.fixture,
+        &traders.borrower,
+        NixInstruction::TopUpCollateral,
+        top_up_metas(traders, bank, &params),
+        &params,
+    )
+    .await?;
+    assert_eq!(result, Ok(()));
+    let logs: Vec<TopUpCollateralLog> = get_logs(&log_data);
+    assert_eq!(logs.len(), 1);
+    Ok(logs[0])
+}
+
+fn top_up_order_params(sequence_number: u64) -> TopUpCollateralParams {
+    TopUpCollateralParams {
+        amount: TOP_UP_ATOMS,
+        trader_index_hint: None,
+        sequence_number,
+        use_a_tree: true,
+        market_loans: None,
+    }
+}
+
+#[tokio::test]
+async fn top_up_collateral_adds_to_a_resting_bid() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    let bid: PlaceOrderReturnData = rest_bid(&traders).await?;
+    let collateral_shares_before: I80F48 =
+        get_order_collateral_shares(&traders, bid.order_index).await;
+    let seat_before: SeatSnapshot = get_borrower_seat(&traders).await;
+
+    // Bids on the A tree are backed by base B.
+    let bank: &BankFixture = &traders.fixture.base_b_bank_fixture;
+    let log: TopUpCollateralLog = simulate_top_up_collateral(
+        &traders,
+        bank,
+        top_up_order_params(bid.order_sequence_number),
+    )
+    .await?;
+    assert_eq!(log.trader, traders.borrower.pubkey());
+    assert_eq!(log.sequence_number, bid.order_sequence_number);
+    assert_eq!(log.is_loan.0, 0);
+    let topped_up_shares: I80F48 = I80F48::from(log.collateral_shares);
+    assert!(topped_up_shares.is_positive());
+
+    top_up_collateral(
+        &traders,
+        bank,
+        top_up_order_params(bid.order_sequence_number),
+    )
+    .await?;
+    assert_eq!(
+        get_order_collateral_shares(&traders, bid.order_index).await,
+        collateral_shares_before + topped_up_shares
+    );
+    // Nothing went through the seat.
+    assert_eq!(
+        I80F48::from(
+            get_borrower_seat(&traders)
+                .await
+                .base_b_withdrawable_asset_share
+        ),
+        I80F48::from(seat_before.base_b_withdrawable_asset_share)
+    );
+    traders.fixture.verify_market().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn top_up_collateral_needs_a_bid_of_the_payer_in_its_mint() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    let bid: PlaceOrderReturnData = rest_bid(&traders).await?;
+    let fixture: &NixTestFixture = &traders.fixture;
+    fixture
+        .base_a_mint_fixture
+        .mint_to(&fixture.payer_base_a_fixture.key, 10)
+        .await;
+
+    assert_nix_error(
+        top_up_collateral(
+            &traders,
+            &fixture.base_a_bank_fixture,
+            top_up_order_params(bid.order_sequence_number),
+        )
+        .await,
+        NixError::InvalidTopUp,
+    );
+    assert_nix_error(
+        top_up_collateral(
+            &traders,
+            &fixture.base_b_bank_fixture,
+            top_up_order_params(bid.order_sequence_number + 1),
+        )
+        .await,
+        NixError::InvalidTopUp,
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn top_up_collateral_adds_to_a_loan() -> anyhow::Result<()> {
+    let traders: Traders = new_traders().await;
+    let loan: ActiveLoan = open_loan(&traders).await?;
+    let fixture: &NixTestFixture = &traders.fixture;
+    let params = || TopUpCollateralParams {
+        market_loans: Some(traders.market.market_loans),
+        ..top_up_order_params(loan.sequence_number)
+    };
+
+    // The loans page has to be among the accounts.
+    let market_loans: Pubkey = traders.market.market_loans;
+    let mut accounts: Vec<AccountMeta> =
+        top_up_metas(&traders, &fixture.base_b_bank_fixture, &params());
+    accounts.retain(|account: &AccountMeta| account.pubkey != market_loans);
+    assert_nix_error(
+        send_nix_instruction(
+            fixture,
+            &traders.borrower,
+            NixInstruction::TopUpCollateral,
+            accounts,
+            &params(),
+        )
+        .await,
+        NixError::MissingAccounts,
+    );
+
+    let log: TopUpCollateralLog =
+        simulate_top_up_collateral(&traders, &fixture.base_b_bank_fixture, params()).await?;
+    assert_eq!(log.sequence_number, loan.sequence_number);
+    assert_eq!(log.is_loan.0, 1);
+    top_up_collateral(&traders, &fixture.base_b_bank_fixture, params()).await?;
+    let loans: Vec<ActiveLoan> = get_borrowed_loans(&traders).await;
+    assert_eq!(
+        I80F48::from(loans[0].collateral_shares),
+        I80F48::from(loan.collateral_shares) + I80F48::from(log.collateral_shares)
+    );
+    fixture.verify_market().await;
+    Ok(())
+}
@@ -0,0 +1,104 @@
+//! state::verify, the consistency checks every scenario ends with. A market
+//! that went through matching passes them, and a corrupted one does not.
+
+use fixed::types::I80F48;
+use hypertree::DataIndex;
+use nix::{
+    program::get_dynamic_account,
+    quantities::WrappedI80F48,
+    state::{
+        get_mut_helper_seat, verify::verify_market, ClaimedSeat, MarketFixed, MarketRef,
+        MARKET_FIXED_SIZE,
+    },
+};
+use solana_sdk::{account::Account, signature::Keypair, signer::Signer};
+use test_utilities::test::{BankMint, TestSettings};
+
+use crate::test_utils::{get_account, open_loan, NixTestFixture, TradingMarket};
+
+const RATE_BPS: u16 = 500;
+const ORDER_BASE_ATOMS: u64 = 100_000_000;
+const LENDER_DEPOSIT_ATOMS: u64 = 1_000_000_000;
+const BORROWER_COLLATERAL_ATOMS: u64 = 1_000_000_000;
+
+/// The fixture market after a loan, and the borrower.
+async fn new_market_with_loan() -> (NixTestFixture, Keypair) {
+    let fixture: NixTestFixture = NixTestFixture::new(
+        Some(TestSettings::all_banks_payer_not_admin()),
+        &BankMint::Sol,
+        &BankMint::Usdc,
+    )
+    .await;
+    let market: TradingMarket = TradingMarket {
+        key: fixture.market,
+        market_loans: fixture
+            .create_market_loan_account(&fixture.payer(), &fixture.market)
+            .await
+            .unwrap(),
+    };
+    let (lender, borrower) = fixture
+        .seat_lender_and_borrower(&market, LENDER_DEPOSIT_ATOMS, BORROWER_COLLATERAL_ATOMS)
+        .await
+        .unwrap();
+    open_loan(
+        &fixture,
+        &market,
+        &lender,
+        &borrower,
+        ORDER_BASE_ATOMS,
+        RATE_BPS,
+    )
+    .await
+    .unwrap();
+    (fixture, borrower)
+}
+
+/// Runs state::verify on a copy of the market with `corrupt` applied to the
+/// seat of `trader`.
+async fn verify_with_corrupted_seat(
+    fixture: &NixTestFixture,
+    trader: &Keypair,
+    corrupt: impl FnOnce(&mut ClaimedSeat),
+) {
+    let mut account: Account = get_account(fixture, &fixture.market).await;
+    let trader_index: DataIndex =
+        get_dynamic_account::<MarketFixed>(&account.data).get_trader_index(&trader.pubkey());
+    corrupt(
+        get_mut_helper_seat(&mut account.data[MARKET_FIXED_SIZE..], trader_index).get_mut_value(),
+    );
+    let market: MarketRef = get_dynamic_account::<MarketFixed>(&account.data);
+    verify_market(&market);
+}
+
+#[tokio::test]
+async fn verify_passes_after_a_loan() -> anyhow::Result<()> {
+    let (fixture, borrower) = new_market_with_loan().await;
+
+    fixture.verify_market().await;
+    // Leaving the seat as it is changes nothing.
+    verify_with_corrupted_seat(&fixture, &borrower, |_| {}).await;
+    Ok(())
+}
+
+#[tokio::test]
+#[should_panic(expected = "Cached base b asset shares do not match the seats")]
+async fn verify_catches_seat_asset_shares_off_the_cached_total() {
+    let (fixture, borrower) = new_market_with_loan().await;
+
+    verify_with_corrupted_seat(&fixture, &borrower, |seat: &mut ClaimedSeat| {
+        seat.base_b_withdrawable_asset_share =
+            WrappedI80F48::from(I80F48::from(seat.base_b_withdrawable_asset_share) + I80F48::ONE);
+    })
+    .await;
+}
+
+#[tokio::test]
+#[should_panic(expected = "Cached base a liability shares do not match the seats")]
+async fn verify_catches_seat_liability_shares_off_the_cached_total() {
+    let (fixture, borrower) = new_market_with_loan().await;
+
+    verify_with_corrupted_seat(&fixture, &borrower, |seat: &mut ClaimedSeat| {
+        seat.base_a_liability_shares = WrappedI80F48::ZERO;
+    })
+    .await;
+}
@@ -1,5 +1,37 @@
 pub mod test_utils;
 
 pub mod cases {
+    pub mod allowlist;
+    pub mod auto_claim_seat;
+    pub mod cancel_order;
+    pub mod check_health;
+    pub mod client;
+    pub mod close_market;
     pub mod create_market;
+    pub mod cross_margin_seat;
+    pub mod error_log;
+    pub mod event_queue;
+    pub mod global_deposit;
+    pub mod global_evict;
+    pub mod global_gas_deposit;
+    pub mod global_link_marginfi;
+    pub mod health_check;
+    pub mod loan_lifecycle;
+    pub mod market_loans;
+    pub mod match_limit;
+    pub mod open_orders;
+    pub mod place_order_params;
+    pub mod place_order_smart;
+    pub mod pool_loan_log;
+    pub mod quote_order;
+    pub mod reduce_order;
+    pub mod return_data;
+    pub mod release_collateral;
+    pub mod reverse_order;
+    pub mod route_orders;
+    pub mod settle_loan_proceeds;
+    pub mod snapshot;
+    pub mod sweep;
+    pub mod top_up_collateral;
+    pub mod verify;
 }
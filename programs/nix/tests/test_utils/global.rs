@@ -13,7 +13,7 @@ use nix::{
     program::{
         get_dynamic_value, global_create_instruction::global_create_instruction
     },
-    state::{GlobalFixed, GlobalValue},
+    state::{GlobalFixed, GlobalValue, GAS_DEPOSIT_LAMPORTS},
     validation::{
         get_global_address,
         
@@ -59,7 +59,7 @@ impl GlobalFixture {
             key: global_key,
             mint_key: *mint,
             global: GlobalValue {
-                fixed: GlobalFixed::new_empty(mint),
+                fixed: GlobalFixed::new_empty(mint, GAS_DEPOSIT_LAMPORTS),
                 dynamic: Vec::new(),
             },
         }
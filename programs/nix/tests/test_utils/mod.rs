@@ -1,5 +1,9 @@
 pub mod test_fixture;
 pub mod global;
+pub mod snapshot;
+pub mod trading;
 
 pub use test_fixture::*;
-pub use global::*;
\ No newline at end of file
+pub use global::*;
+pub use snapshot::*;
+pub use trading::*;
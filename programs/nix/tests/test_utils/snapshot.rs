@@ -0,0 +1,88 @@
+use std::cell::RefMut;
+
+use marginfi::state::marginfi_group::BankVaultType;
+use nix::validation::{get_global_vault_address, get_vault_address};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    clock::Clock,
+    pubkey::Pubkey,
+    signer::Signer,
+};
+
+use super::NixTestFixture;
+
+/// Accounts of a NixTestFixture at one point, see NixTestFixture::snapshot.
+pub struct FixtureSnapshot {
+    accounts: Vec<(Pubkey, Option<Account>)>,
+    clock: Clock,
+}
+
+impl NixTestFixture {
+    /// Accounts the fixture sets up that instructions on the market can
+    /// write to: the market and its vaults and marginfi accounts, the
+    /// globals, the group and banks, and the token accounts of both traders.
+    pub fn tracked_accounts(&self) -> Vec<Pubkey> {
+        let mut accounts: Vec<Pubkey> = vec![
+            self.market,
+            self.base_a_marginfi_account,
+            self.base_b_marginfi_account,
+            self.group.key,
+            self.payer(),
+            self.second_keypair.pubkey(),
+            self.payer_base_a_fixture.key,
+            self.payer_base_b_fixture.key,
+            self.second_keypair_base_a_fixture.key,
+            self.second_keypair_base_b_fixture.key,
+            self.base_a_global_fixture.key,
+            self.base_b_global_fixture.key,
+        ];
+        for mint in [self.base_a_mint_fixture.key, self.base_b_mint_fixture.key] {
+            accounts.push(mint);
+            accounts.push(get_vault_address(&self.market, &mint).0);
+            accounts.push(get_global_vault_address(&mint).0);
+        }
+        for bank in self.banks.values() {
+            accounts.push(bank.key);
+            accounts.push(bank.get_vault(BankVaultType::Liquidity).0);
+            accounts.push(bank.get_vault(BankVaultType::Insurance).0);
+            accounts.push(bank.get_vault(BankVaultType::Fee).0);
+        }
+        accounts.sort();
+        accounts.dedup();
+        accounts
+    }
+
+    /// Captures the tracked accounts, `extra_accounts` and the clock. Cases
+    /// that share a setup build the fixture once and restore the snapshot
+    /// between them instead of rebuilding the group, banks and market.
+    /// Accounts a case creates, like a MarketLoans page, go in
+    /// `extra_accounts`.
+    pub async fn snapshot(&self, extra_accounts: &[Pubkey]) -> FixtureSnapshot {
+        let mut keys: Vec<Pubkey> = self.tracked_accounts();
+        keys.extend_from_slice(extra_accounts);
+
+        let mut accounts: Vec<(Pubkey, Option<Account>)> = Vec::with_capacity(keys.len());
+        for key in keys {
+            accounts.push((key, self.try_load(&key).await.unwrap()));
+        }
+        FixtureSnapshot {
+            accounts,
+            clock: self.get_clock().await,
+        }
+    }
+
+    /// Puts every account of the snapshot back. Accounts that did not exist
+    /// when it was taken are emptied, which closes them.
+    pub async fn restore(&self, snapshot: &FixtureSnapshot) {
+        let mut context: RefMut<ProgramTestContext> = self.context.borrow_mut();
+        for (key, account_opt) in snapshot.accounts.iter() {
+            let account: AccountSharedData = account_opt
+                .clone()
+                .map(AccountSharedData::from)
+                .unwrap_or_default();
+            context.set_account(key, &account);
+        }
+        context.set_sysvar(&snapshot.clock);
+    }
+}
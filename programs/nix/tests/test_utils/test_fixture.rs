@@ -7,8 +7,10 @@ use nix::{
         claim_seat_instruction::claim_seat_instruction,
         create_market_instruction::create_market_instructions,
         create_market_loan_account_instruction::create_market_loan_account_instruction,
+        get_dynamic_account,
         global_add_trader_instruction::global_add_trader_instruction,
     },
+    state::{verify::verify_market, MarketFixed, MarketRef},
     validation::get_nix_marginfi_account_address,
 };
 use solana_program::{hash::Hash, sysvar};
@@ -522,6 +524,41 @@ impl NixTestFixture {
         ctx.set_account(&address, &aso);
     }
 
+    /// Sets the spot and ema price of a pyth push oracle to `price` in
+    /// whole units of its mint.
+    pub async fn set_pyth_oracle_price(&self, address: Pubkey, price: f64) {
+        let mut ctx = self.context.borrow_mut();
+
+        let mut account = ctx
+            .banks_client
+            .get_account(address)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let data = account.data.as_mut_slice();
+        let mut price_update = PriceUpdateV2::deserialize(&mut &data[8..]).unwrap();
+
+        let scaled_price: i64 = (price * 10f64.powi(-price_update.price_message.exponent)) as i64;
+        price_update.price_message.price = scaled_price;
+        price_update.price_message.ema_price = scaled_price;
+
+        let mut data = vec![];
+        let mut account_data = vec![];
+
+        data.extend_from_slice(PriceUpdateV2::DISCRIMINATOR);
+
+        price_update.serialize(&mut account_data).unwrap();
+
+        data.extend_from_slice(&account_data);
+
+        let mut aso = AccountSharedData::from(account);
+
+        aso.set_data_from_slice(data.as_slice());
+
+        ctx.set_account(&address, &aso);
+    }
+
     pub async fn advance_time(&self, seconds: i64) {
         let mut clock: Clock = self
             .context
@@ -728,6 +765,18 @@ impl NixTestFixture {
             .await
     }
 
+    /// Runs the state::verify checks on the market. Scenarios call this at
+    /// the end so every test also checks the market account is consistent.
+    pub async fn verify_market(&self) {
+        let market_account: Account = self
+            .try_load(&self.market)
+            .await
+            .unwrap()
+            .expect("Market not found");
+        let market: MarketRef = get_dynamic_account::<MarketFixed>(&market_account.data);
+        verify_market(&market);
+    }
+
     pub fn get_bank_mut(&mut self, bank_mint: &BankMint) -> &mut BankFixture {
         self.banks.get_mut(bank_mint).unwrap()
     }
@@ -0,0 +1,553 @@
+use std::{cell::RefMut, io::Write, mem::size_of, rc::Rc};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use borsh::BorshSerialize;
+use bytemuck::Pod;
+use marginfi::state::marginfi_group::{Bank, BankVaultType};
+use nix::{
+    logs::Discriminant,
+    program::{
+        cancel_order::CancelOrderParams, create_market::CreateMarketParams, deposit::DepositParams,
+        place_order::PlaceOrderParams, NixError, NixInstruction,
+    },
+    quantities::{BaseAtoms, Rate},
+    state::{OrderType, MARKET_FIXED_SIZE, MARKET_LOANS_FIXED_SIZE},
+    validation::{
+        get_market_fee_receiver_address, get_market_signer_address,
+        get_nix_marginfi_account_address, get_vault_address,
+    },
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction, InstructionError},
+    system_instruction, system_program,
+};
+use solana_program_test::{
+    BanksClientError, BanksTransactionResultWithSimulation, ProgramTestContext,
+};
+use solana_sdk::{
+    account::Account,
+    hash::Hash,
+    pubkey::Pubkey,
+    signature::Keypair,
+    signer::Signer,
+    transaction::{Transaction, TransactionError},
+};
+use test_utilities::bank::BankFixture;
+
+use super::{send_tx_with_retry, NixTestFixture};
+
+/// A market of the fixture's mints and the first page of its loans. Either
+/// the fixture market or one a case creates with its own params, see
+/// NixTestFixture::create_market_with_params.
+#[derive(Clone, Copy)]
+pub struct TradingMarket {
+    pub key: Pubkey,
+    pub market_loans: Pubkey,
+}
+
+impl TradingMarket {
+    pub fn marginfi_account(&self, bank: &BankFixture) -> Pubkey {
+        get_nix_marginfi_account_address(&self.key, &bank.mint.key).0
+    }
+
+    /// Group, bank, the market's marginfi account for the bank, the
+    /// liquidity vault and its authority.
+    pub fn marginfi_cpi_metas(
+        &self,
+        fixture: &NixTestFixture,
+        bank: &BankFixture,
+    ) -> Vec<AccountMeta> {
+        vec![
+            AccountMeta::new_readonly(fixture.group.key, false),
+            AccountMeta::new(bank.key, false),
+            AccountMeta::new(self.marginfi_account(bank), false),
+            AccountMeta::new(bank.get_vault(BankVaultType::Liquidity).0, false),
+            AccountMeta::new_readonly(bank.get_vault_authority(BankVaultType::Liquidity).0, false),
+        ]
+    }
+
+    /// Optional accounts of an ask on the A tree, which lends base A.
+    pub async fn ask_metas(&self, fixture: &NixTestFixture) -> Vec<AccountMeta> {
+        let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+        let mut accounts: Vec<AccountMeta> = self.marginfi_cpi_metas(fixture, base_bank);
+        accounts.extend(oracle_metas(base_bank).await);
+        accounts
+    }
+
+    /// Optional accounts of a bid on the A tree, which borrows base A against
+    /// base B.
+    pub async fn bid_metas(&self, fixture: &NixTestFixture) -> Vec<AccountMeta> {
+        let base_bank: &BankFixture = &fixture.base_a_bank_fixture;
+        let quote_bank: &BankFixture = &fixture.base_b_bank_fixture;
+        let mut accounts: Vec<AccountMeta> = self.marginfi_cpi_metas(fixture, base_bank);
+        accounts.extend(self.marginfi_cpi_metas(fixture, quote_bank));
+        accounts.extend(oracle_metas(quote_bank).await);
+        accounts.extend(oracle_metas(base_bank).await);
+        accounts
+    }
+}
+
+/// Permissionless market without fees or reverse spread bounds.
+pub fn default_market_params() -> CreateMarketParams {
+    CreateMarketParams {
+        protocol_fee_rate_bps: 0,
+        marginfi_market_buffer_bps: 0,
+        referral_bps: 0,
+        maker_rebate_bps: 0,
+        min_reverse_spread_bps: 0,
+        max_reverse_spread_bps: 0,
+        max_open_orders_per_seat: 0,
+        allowlist_authority: None,
+        fee_on_interest: false,
+        max_borrow_utilization_bps: 0,
+    }
+}
+
+pub fn assert_nix_error(result: Result<(), BanksClientError>, expected: NixError) {
+    match result {
+        Err(BanksClientError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        ))) => assert_eq!(code, expected as u32),
+        other => panic!("Expected {:?}, got {:?}", expected, other),
+    }
+}
+
+pub async fn get_account(fixture: &NixTestFixture, key: &Pubkey) -> Account {
+    fixture
+        .try_load(key)
+        .await
+        .unwrap()
+        .expect("Account not found")
+}
+
+/// The bank and its oracle, as marginfi reads them for health checks.
+pub async fn oracle_metas(bank: &BankFixture) -> Vec<AccountMeta> {
+    let bank_account: Bank = bank.load().await;
+    vec![
+        AccountMeta::new_readonly(bank.key, false),
+        AccountMeta::new_readonly(bank_account.config.oracle_keys[0], false),
+    ]
+}
+
+/// Sends one nix instruction signed and paid for by `signer`.
+pub async fn send_nix_instruction(
+    fixture: &NixTestFixture,
+    signer: &Keypair,
+    instruction: NixInstruction,
+    accounts: Vec<AccountMeta>,
+    params: &impl BorshSerialize,
+) -> Result<(), BanksClientError> {
+    let ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts,
+        data: [instruction.to_vec(), params.try_to_vec()?].concat(),
+    };
+    send_tx_with_retry(
+        Rc::clone(&fixture.context),
+        &[ix],
+        Some(&signer.pubkey()),
+        &[signer],
+    )
+    .await
+}
+
+async fn simulate_nix_transaction(
+    fixture: &NixTestFixture,
+    signer: &Keypair,
+    instruction: NixInstruction,
+    accounts: Vec<AccountMeta>,
+    params: &impl BorshSerialize,
+) -> Result<BanksTransactionResultWithSimulation, BanksClientError> {
+    let ix: Instruction = Instruction {
+        program_id: nix::ID,
+        accounts,
+        data: [instruction.to_vec(), params.try_to_vec()?].concat(),
+    };
+    let mut context: RefMut<ProgramTestContext> = fixture.context.borrow_mut();
+    let blockhash: Hash = context.get_new_latest_blockhash().await?;
+    let tx: Transaction =
+        Transaction::new_signed_with_payer(&[ix], Some(&signer.pubkey()), &[signer], blockhash);
+    context.banks_client.simulate_transaction(tx).await
+}
+
+/// Simulates one nix instruction signed and paid for by `signer` and
+/// returns the return data it set, empty when it set none.
+pub async fn simulate_nix_instruction(
+    fixture: &NixTestFixture,
+    signer: &Keypair,
+    instruction: NixInstruction,
+    accounts: Vec<AccountMeta>,
+    params: &impl BorshSerialize,
+) -> Result<Vec<u8>, BanksClientError> {
+    let BanksTransactionResultWithSimulation {
+        result,
+        simulation_details,
+    } = simulate_nix_transaction(fixture, signer, instruction, accounts, params).await?;
+    if let Some(Err(error)) = result {
+        return Err(BanksClientError::TransactionError(error));
+    }
+    Ok(simulation_details
+        .and_then(|details| details.return_data)
+        .map(|return_data| return_data.data)
+        .unwrap_or_default())
+}
+
+/// Simulates one nix instruction and returns the logs it emitted with
+/// sol_log_data, discriminant first, along with its result. Logs of a failed
+/// instruction are kept, so the logs that explain a failure can be checked.
+pub async fn simulate_nix_log_data(
+    fixture: &NixTestFixture,
+    signer: &Keypair,
+    instruction: NixInstruction,
+    accounts: Vec<AccountMeta>,
+    params: &impl BorshSerialize,
+) -> Result<(Result<(), TransactionError>, Vec<Vec<u8>>), BanksClientError> {
+    let BanksTransactionResultWithSimulation {
+        result,
+        simulation_details,
+    } = simulate_nix_transaction(fixture, signer, instruction, accounts, params).await?;
+    let log_data: Vec<Vec<u8>> = simulation_details
+        .map(|details| details.logs)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|log: &String| log.strip_prefix("Program data: "))
+        .map(|data: &str| BASE64_STANDARD.decode(data).unwrap())
+        .collect();
+    Ok((result.unwrap_or(Ok(())), log_data))
+}
+
+/// The logs of type T among data from simulate_nix_log_data.
+pub fn get_logs<T: Discriminant + Pod>(log_data: &[Vec<u8>]) -> Vec<T> {
+    log_data
+        .iter()
+        .filter(|data: &&Vec<u8>| data[..8] == T::discriminant())
+        .map(|data: &Vec<u8>| bytemuck::pod_read_unaligned(&data[8..8 + size_of::<T>()]))
+        .collect()
+}
+
+/// Accounts of a Deposit of the trader's tokens into the market's marginfi
+/// account for the bank. TopUpCollateral takes the same.
+pub fn deposit_metas(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    trader: &Keypair,
+    bank: &BankFixture,
+    trader_token: &Pubkey,
+    token_program: &Pubkey,
+) -> Vec<AccountMeta> {
+    let mut accounts: Vec<AccountMeta> = vec![
+        AccountMeta::new(trader.pubkey(), true),
+        AccountMeta::new(market.key, false),
+        AccountMeta::new_readonly(get_market_signer_address(&market.key).0, false),
+        AccountMeta::new(*trader_token, false),
+        AccountMeta::new(get_vault_address(&market.key, &bank.mint.key).0, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(bank.mint.key, false),
+    ];
+    accounts.extend(market.marginfi_cpi_metas(fixture, bank));
+    // Deposit takes the liquidity vault without its authority.
+    accounts.pop();
+    accounts
+}
+
+/// Deposits into the trader's seat through the market's marginfi account for
+/// the bank.
+pub async fn deposit_to_seat(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    trader: &Keypair,
+    bank: &BankFixture,
+    trader_token: &Pubkey,
+    token_program: &Pubkey,
+    params: DepositParams,
+) -> Result<(), BanksClientError> {
+    let accounts: Vec<AccountMeta> =
+        deposit_metas(fixture, market, trader, bank, trader_token, token_program);
+    send_nix_instruction(fixture, trader, NixInstruction::Deposit, accounts, &params).await
+}
+
+/// PlaceOrderParams behind the tag and version byte PlaceOrder expects.
+/// Borsh of the params alone would be read as the untagged v1 layout.
+pub struct VersionedPlaceOrderParams<'a>(pub &'a PlaceOrderParams);
+
+impl BorshSerialize for VersionedPlaceOrderParams<'_> {
+    fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&self.0.try_to_versioned_vec()?)
+    }
+}
+
+/// PlaceOrder with the fixed accounts of `market` followed by
+/// `optional_accounts`.
+pub async fn place_order(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    trader: &Keypair,
+    params: PlaceOrderParams,
+    optional_accounts: Vec<AccountMeta>,
+) -> Result<(), BanksClientError> {
+    let mut accounts: Vec<AccountMeta> = place_order_metas(fixture, market, trader);
+    accounts.extend(optional_accounts);
+    send_nix_instruction(
+        fixture,
+        trader,
+        NixInstruction::PlaceOrder,
+        accounts,
+        &VersionedPlaceOrderParams(&params),
+    )
+    .await
+}
+
+/// Cancels an order that is not global, so the system program stands in for
+/// the global account.
+pub async fn cancel_order(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    trader: &Keypair,
+    order_sequence_number: u64,
+    use_a_tree: bool,
+) -> Result<(), BanksClientError> {
+    send_nix_instruction(
+        fixture,
+        trader,
+        NixInstruction::CancelOrder,
+        vec![
+            AccountMeta::new(trader.pubkey(), true),
+            AccountMeta::new(market.market_loans, false),
+            AccountMeta::new(market.key, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        &CancelOrderParams {
+            trader_index_hint: None,
+            order_sequence_number,
+            order_index_hint: None,
+            use_a_tree,
+            max_expired_orders_to_sweep: 0,
+        },
+    )
+    .await
+}
+
+/// Rests a post only ask of `num_base_atoms` from the lender on the A tree
+/// and takes it with an immediate or cancel bid from the borrower, which
+/// opens one loan on the market's loans page.
+pub async fn open_loan(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    lender: &Keypair,
+    borrower: &Keypair,
+    num_base_atoms: u64,
+    rate_bps: u16,
+) -> Result<(), BanksClientError> {
+    for (trader, is_bid, order_type) in [
+        (lender, false, OrderType::PostOnly),
+        (borrower, true, OrderType::ImmediateOrCancel),
+    ] {
+        let optional_accounts: Vec<AccountMeta> = if is_bid {
+            market.bid_metas(fixture).await
+        } else {
+            market.ask_metas(fixture).await
+        };
+        place_order(
+            fixture,
+            market,
+            trader,
+            PlaceOrderParams::new(
+                BaseAtoms::new(num_base_atoms),
+                Rate::from_bps(rate_bps),
+                is_bid,
+                true,
+                order_type,
+            ),
+            optional_accounts,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Fixed accounts of PlaceOrder and the instructions that share its layout.
+pub fn place_order_metas(
+    fixture: &NixTestFixture,
+    market: &TradingMarket,
+    trader: &Keypair,
+) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(trader.pubkey(), true),
+        AccountMeta::new(market.key, false),
+        AccountMeta::new(market.market_loans, false),
+        AccountMeta::new_readonly(get_market_signer_address(&market.key).0, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(fixture.base_a_mint_fixture.key, false),
+        AccountMeta::new_readonly(fixture.base_b_mint_fixture.key, false),
+    ]
+}
+
+impl NixTestFixture {
+    /// Creates a market of the fixture's mints and banks at a new keypair,
+    /// with its first loans page, and the payer as admin.
+    pub async fn create_market_with_params(
+        &self,
+        params: CreateMarketParams,
+    ) -> Result<TradingMarket, BanksClientError> {
+        let payer: Keypair = self.payer_keypair();
+        let market: Keypair = Keypair::new();
+        let market_loans: Keypair = Keypair::new();
+        let mut ixs: Vec<Instruction> = Vec::new();
+        for (key, size) in [
+            (market.pubkey(), MARKET_FIXED_SIZE),
+            (market_loans.pubkey(), MARKET_LOANS_FIXED_SIZE),
+        ] {
+            ixs.push(system_instruction::create_account(
+                &payer.pubkey(),
+                &key,
+                self.get_minimum_rent_for_size(size).await,
+                size as u64,
+                &nix::ID,
+            ));
+        }
+
+        let banks: [&BankFixture; 2] = [&self.base_a_bank_fixture, &self.base_b_bank_fixture];
+        let mut accounts: Vec<AccountMeta> = vec![
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new(market.pubkey(), false),
+            AccountMeta::new(market_loans.pubkey(), false),
+            AccountMeta::new_readonly(get_market_signer_address(&market.pubkey()).0, false),
+            AccountMeta::new_readonly(self.base_a_mint_fixture.key, false),
+            AccountMeta::new_readonly(self.base_b_mint_fixture.key, false),
+        ];
+        for bank in banks {
+            accounts.push(AccountMeta::new(
+                get_market_fee_receiver_address(&market.pubkey(), &bank.mint.key).0,
+                false,
+            ));
+        }
+        for bank in banks {
+            accounts.push(AccountMeta::new(
+                get_vault_address(&market.pubkey(), &bank.mint.key).0,
+                false,
+            ));
+        }
+        for bank in banks {
+            accounts.extend([
+                AccountMeta::new_readonly(self.group.key, false),
+                AccountMeta::new_readonly(bank.key, false),
+                AccountMeta::new(
+                    get_nix_marginfi_account_address(&market.pubkey(), &bank.mint.key).0,
+                    false,
+                ),
+            ]);
+        }
+        accounts.extend([
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(spl_token_2022::id(), false),
+        ]);
+        ixs.push(Instruction {
+            program_id: nix::ID,
+            accounts,
+            data: [NixInstruction::CreateMarket.to_vec(), params.try_to_vec()?].concat(),
+        });
+        send_tx_with_retry(
+            Rc::clone(&self.context),
+            &ixs,
+            Some(&payer.pubkey()),
+            &[&payer, &market, &market_loans],
+        )
+        .await?;
+        Ok(TradingMarket {
+            key: market.pubkey(),
+            market_loans: market_loans.pubkey(),
+        })
+    }
+
+    /// Allocates a loans page for `market` and initializes it with
+    /// CreateMarketLoanAccount, linked after `previous_market_loans`.
+    pub async fn create_market_loans_page(
+        &self,
+        market: &Pubkey,
+        previous_market_loans: &Pubkey,
+    ) -> Result<Pubkey, BanksClientError> {
+        let payer: Keypair = self.payer_keypair();
+        let page: Keypair = Keypair::new();
+        let create_account_ix: Instruction = system_instruction::create_account(
+            &payer.pubkey(),
+            &page.pubkey(),
+            self.get_minimum_rent_for_size(MARKET_LOANS_FIXED_SIZE)
+                .await,
+            MARKET_LOANS_FIXED_SIZE as u64,
+            &nix::ID,
+        );
+        let create_market_loan_account_ix: Instruction = Instruction {
+            program_id: nix::ID,
+            accounts: vec![
+                AccountMeta::new(payer.pubkey(), true),
+                AccountMeta::new(page.pubkey(), false),
+                AccountMeta::new_readonly(*market, false),
+                AccountMeta::new(*previous_market_loans, false),
+            ],
+            data: NixInstruction::CreateMarketLoanAccount.to_vec(),
+        };
+        send_tx_with_retry(
+            Rc::clone(&self.context),
+            &[create_account_ix, create_market_loan_account_ix],
+            Some(&payer.pubkey()),
+            &[&payer, &page],
+        )
+        .await?;
+        Ok(page.pubkey())
+    }
+
+    /// Seats a lender (the second keypair) and a borrower (the payer) on
+    /// `market` through deposits that claim their seats, the lender with
+    /// `lender_atoms` of base A and the borrower with `borrower_atoms` of
+    /// base B. Permissioned markets need the seats claimed first.
+    pub async fn seat_lender_and_borrower(
+        &self,
+        market: &TradingMarket,
+        lender_atoms: u64,
+        borrower_atoms: u64,
+    ) -> Result<(Keypair, Keypair), BanksClientError> {
+        let borrower: Keypair = self.payer_keypair();
+        let lender: Keypair = self.second_keypair.insecure_clone();
+        send_tx_with_retry(
+            Rc::clone(&self.context),
+            &[system_instruction::transfer(
+                &borrower.pubkey(),
+                &lender.pubkey(),
+                1_000_000_000,
+            )],
+            Some(&borrower.pubkey()),
+            &[&borrower],
+        )
+        .await?;
+        self.base_a_mint_fixture
+            .mint_to(&self.second_keypair_base_a_fixture.key, 10)
+            .await;
+        self.base_b_mint_fixture
+            .mint_to(&self.payer_base_b_fixture.key, 10_000)
+            .await;
+        deposit_to_seat(
+            self,
+            market,
+            &lender,
+            &self.base_a_bank_fixture,
+            &self.second_keypair_base_a_fixture.key,
+            &self.base_a_token_program,
+            DepositParams::new(lender_atoms, None, true),
+        )
+        .await?;
+        deposit_to_seat(
+            self,
+            market,
+            &borrower,
+            &self.base_b_bank_fixture,
+            &self.payer_base_b_fixture.key,
+            &self.base_b_token_program,
+            DepositParams::new(borrower_atoms, None, true),
+        )
+        .await?;
+        Ok((lender, borrower))
+    }
+}